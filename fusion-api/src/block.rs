@@ -0,0 +1,435 @@
+use ethers_core::types::{H256, U256};
+use fusion_types::{ToFr, ToU256};
+use poseidon_rs::Poseidon;
+use serde::{Deserialize, Serialize};
+
+use crate::SignedTx;
+
+fn poseidon(elements: &[U256]) -> U256 {
+    Poseidon::new()
+        .hash(elements.iter().map(|e| e.to_fr()).collect())
+        .unwrap()
+        .to_u256()
+}
+
+/// Maximum number of headers returned by a single `get_headers` call.
+pub const MAX_HEADERS_PER_REQUEST: u64 = 1000;
+
+/// Maximum number of blocks returned by a single `get_blocks` call.
+pub const MAX_BLOCKS_PER_REQUEST: u64 = 100;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockHeader {
+    pub number: u64,
+    /// Hash of the previous header, zero for the first block.
+    pub parent_hash: U256,
+    /// L2 state root after the block's transactions.
+    pub state_root: U256,
+    /// Merkle root of the block's transaction hashes, in block order.
+    pub tx_root: U256,
+    pub timestamp: u64,
+    /// Root of the L1 contract when the block was built, `None` in
+    /// dev mode.
+    pub l1_root: Option<U256>,
+    /// L1 transactions that submitted the block's proofs.
+    /// Filled in after the header is created, so not part of `hash`:
+    /// clients check them against L1 instead.
+    pub l1_tx_hashes: Vec<H256>,
+}
+
+/// Size of a header without its L1 transaction hashes, see `to_compact`.
+const COMPACT_HEADER_SIZE: usize = 8 + 32 * 3 + 8 + 33 + 4;
+
+impl BlockHeader {
+    pub fn hash(&self) -> U256 {
+        poseidon(&[
+            self.number.into(),
+            self.parent_hash,
+            self.state_root,
+            self.tx_root,
+            self.timestamp.into(),
+            self.l1_root.unwrap_or_default(),
+        ])
+    }
+
+    /// Fixed-width binary encoding, for bandwidth-sensitive clients.
+    /// Integers are big-endian, `l1_root` is a presence byte followed
+    /// by 32 bytes, and the L1 transaction hashes are prefixed by their
+    /// 4-byte count.
+    pub fn to_compact(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(COMPACT_HEADER_SIZE + 32 * self.l1_tx_hashes.len());
+        let mut word = [0u8; 32];
+        bytes.extend(self.number.to_be_bytes());
+        for value in [self.parent_hash, self.state_root, self.tx_root] {
+            value.to_big_endian(&mut word);
+            bytes.extend(word);
+        }
+        bytes.extend(self.timestamp.to_be_bytes());
+        bytes.push(self.l1_root.is_some() as u8);
+        self.l1_root.unwrap_or_default().to_big_endian(&mut word);
+        bytes.extend(word);
+        bytes.extend((self.l1_tx_hashes.len() as u32).to_be_bytes());
+        for hash in &self.l1_tx_hashes {
+            bytes.extend(hash.as_bytes());
+        }
+        bytes
+    }
+
+    /// Decodes a header from the start of `bytes`, returning it with the
+    /// number of bytes read.
+    pub fn from_compact(bytes: &[u8]) -> Result<(Self, usize), String> {
+        if bytes.len() < COMPACT_HEADER_SIZE {
+            return Err("Truncated header".to_string());
+        }
+        let u64_at = |i: usize| u64::from_be_bytes(bytes[i..i + 8].try_into().unwrap());
+        let u256_at = |i: usize| U256::from_big_endian(&bytes[i..i + 32]);
+        let l1_tx_count =
+            u32::from_be_bytes(bytes[145..COMPACT_HEADER_SIZE].try_into().unwrap()) as usize;
+        let size = COMPACT_HEADER_SIZE + 32 * l1_tx_count;
+        if bytes.len() < size {
+            return Err("Truncated header".to_string());
+        }
+        let header = BlockHeader {
+            number: u64_at(0),
+            parent_hash: u256_at(8),
+            state_root: u256_at(40),
+            tx_root: u256_at(72),
+            timestamp: u64_at(104),
+            l1_root: match bytes[112] {
+                0 => None,
+                1 => Some(u256_at(113)),
+                _ => return Err("Invalid L1 root flag".to_string()),
+            },
+            l1_tx_hashes: bytes[COMPACT_HEADER_SIZE..size]
+                .chunks(32)
+                .map(H256::from_slice)
+                .collect(),
+        };
+        Ok((header, size))
+    }
+}
+
+pub fn encode_headers(headers: &[BlockHeader]) -> Vec<u8> {
+    headers.iter().flat_map(BlockHeader::to_compact).collect()
+}
+
+pub fn decode_headers(mut bytes: &[u8]) -> Result<Vec<BlockHeader>, String> {
+    let mut headers = vec![];
+    while !bytes.is_empty() {
+        let (header, size) = BlockHeader::from_compact(bytes)?;
+        headers.push(header);
+        bytes = &bytes[size..];
+    }
+    Ok(headers)
+}
+
+/// Checks that `headers` are consecutive blocks, each one pointing to
+/// the hash of the previous one.
+pub fn verify_header_chain(headers: &[BlockHeader]) -> bool {
+    headers
+        .windows(2)
+        .all(|pair| pair[1].number == pair[0].number + 1 && pair[1].parent_hash == pair[0].hash())
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Block {
+    pub header: BlockHeader,
+    pub txs: Vec<SignedTx>,
+    /// `hash_tx` of each transaction, the leaves of `header.tx_root`.
+    pub tx_hashes: Vec<U256>,
+}
+
+impl Block {
+    /// Whether maintenance dropped the transactions of the block, which
+    /// only keeps their hashes.
+    pub fn is_pruned(&self) -> bool {
+        self.txs.len() != self.tx_hashes.len()
+    }
+
+    pub fn inclusion_proof(&self, tx_hash: &U256) -> Option<InclusionProof> {
+        let index = self.tx_hashes.iter().position(|h| h == tx_hash)?;
+        Some(InclusionProof {
+            header: self.header.clone(),
+            tx_hash: *tx_hash,
+            index: index as u64,
+            siblings: tx_siblings(&self.tx_hashes, index),
+        })
+    }
+}
+
+/// Maximum number of attestations returned by a single
+/// `get_attestations` call.
+pub const MAX_ATTESTATIONS_PER_REQUEST: usize = 100;
+
+/// Statement signed by the sequencer once a block is confirmed on L1.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attestation {
+    pub block_number: u64,
+    pub block_hash: U256,
+    pub state_root: U256,
+    /// The L1 transaction that moved the L1 root to `state_root`.
+    pub l1_tx_hash: H256,
+    /// Signature of `message` by the key in `SequencerInfo::attestation_key`.
+    pub signature: String,
+}
+
+impl Attestation {
+    /// The signed message. The L1 transaction hash is split in two
+    /// halves since it does not fit in a field element.
+    pub fn message(&self) -> U256 {
+        let l1_tx_hash = self.l1_tx_hash.as_bytes();
+        poseidon(&[
+            self.block_number.into(),
+            self.block_hash,
+            self.state_root,
+            U256::from_big_endian(&l1_tx_hash[..16]),
+            U256::from_big_endian(&l1_tx_hash[16..]),
+        ])
+    }
+}
+
+/// Promise signed by the sequencer when it queues a transaction, see
+/// `QueuePosition::receipt`: the transaction is in block
+/// `promised_block` at the latest, unless it is rejected or dropped, or
+/// its validity window keeps it out. Along with the batches, a receipt
+/// shows a promise that was not kept.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Receipt {
+    pub tx_hash: U256,
+    /// When it was queued, in seconds since the Unix epoch.
+    pub accepted_at: u64,
+    pub promised_block: u64,
+    /// Signature of `message` by the key in `SequencerInfo::attestation_key`.
+    pub signature: String,
+}
+
+impl Receipt {
+    /// The signed message.
+    pub fn message(&self) -> U256 {
+        poseidon(&[
+            self.tx_hash,
+            self.accepted_at.into(),
+            self.promised_block.into(),
+        ])
+    }
+}
+
+/// A block whose proofs were all accepted by L1, see `get_batch`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Batch {
+    /// The number of its block.
+    pub number: u64,
+    /// State root before the block.
+    pub prior_root: U256,
+    pub state_root: U256,
+    pub tx_hashes: Vec<U256>,
+    pub txs: Vec<SignedTx>,
+    /// The L1 transaction of its last proof and the L1 block it was
+    /// mined in.
+    pub l1_tx_hash: H256,
+    pub l1_block_number: u64,
+    /// When L1 accepted it, in seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// The receipts issued for its transactions since the sequencer
+    /// started.
+    #[serde(default)]
+    pub receipts: Vec<Receipt>,
+}
+
+/// An account of the state tree with the siblings of its leaf, from
+/// the leaf up to the root.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LeafWitness {
+    /// Key of the leaf.
+    pub address: U256,
+    pub id: U256,
+    pub balance: U256,
+    pub nonce: U256,
+    pub path: Vec<U256>,
+}
+
+/// What the circuit needs to prove a transaction: its sender and
+/// recipient leaves before it, with their paths in the tree before it,
+/// and after it, with their paths in the tree after it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxWitness {
+    pub tx: SignedTx,
+    pub pre_root: U256,
+    pub post_root: U256,
+    pub pre_sender: LeafWitness,
+    pub pre_to: LeafWitness,
+    pub post_sender: LeafWitness,
+    pub post_to: LeafWitness,
+}
+
+/// The witnesses of the transactions of a block, see `get_batch_witness`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchWitness {
+    /// The number of the block.
+    pub number: u64,
+    pub pre_root: U256,
+    pub post_root: U256,
+    pub txs: Vec<TxWitness>,
+}
+
+/// Proof that a transaction is part of a block.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct InclusionProof {
+    pub header: BlockHeader,
+    pub tx_hash: U256,
+    /// Position of the transaction in the block.
+    pub index: u64,
+    /// Sibling hashes from the leaf up to the root.
+    pub siblings: Vec<U256>,
+}
+
+/// The leaves of one level of the transactions tree, padded with zeros
+/// to the next power of two.
+fn padded(leaves: &[U256]) -> Vec<U256> {
+    let mut level = leaves.to_vec();
+    level.resize(leaves.len().next_power_of_two(), U256::zero());
+    level
+}
+
+fn parent_level(level: &[U256]) -> Vec<U256> {
+    level
+        .chunks(2)
+        .map(|pair| poseidon(&[pair[0], pair[1]]))
+        .collect()
+}
+
+/// Merkle root of `tx_hashes`. A single transaction is its own root,
+/// and an empty block has a zero root.
+pub fn tx_root(tx_hashes: &[U256]) -> U256 {
+    if tx_hashes.is_empty() {
+        return U256::zero();
+    }
+    let mut level = padded(tx_hashes);
+    while level.len() > 1 {
+        level = parent_level(&level);
+    }
+    level[0]
+}
+
+fn tx_siblings(tx_hashes: &[U256], mut index: usize) -> Vec<U256> {
+    let mut siblings = vec![];
+    let mut level = padded(tx_hashes);
+    while level.len() > 1 {
+        siblings.push(level[index ^ 1]);
+        level = parent_level(&level);
+        index /= 2;
+    }
+    siblings
+}
+
+/// Checks that `proof.tx_hash` is at `proof.index` in the block whose
+/// header hashes to `header_hash`, which the caller must already trust.
+pub fn verify_inclusion(proof: &InclusionProof, header_hash: &U256) -> bool {
+    if proof.header.hash() != *header_hash {
+        return false;
+    }
+    if proof.siblings.len() < 64 && proof.index >> proof.siblings.len() != 0 {
+        return false;
+    }
+
+    let root =
+        proof.siblings.iter().enumerate().fold(
+            proof.tx_hash,
+            |node, (level, sibling)| match (proof.index >> level) & 1 {
+                0 => poseidon(&[node, *sibling]),
+                _ => poseidon(&[*sibling, node]),
+            },
+        );
+    root == proof.header.tx_root
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn block(tx_count: u64) -> Block {
+        let tx_hashes: Vec<U256> = (1..=tx_count).map(|i| U256::from(i * 1000)).collect();
+        Block {
+            header: BlockHeader {
+                number: 1,
+                parent_hash: U256::zero(),
+                state_root: 42.into(),
+                tx_root: tx_root(&tx_hashes),
+                timestamp: 1_700_000_000,
+                l1_root: None,
+                l1_tx_hashes: vec![],
+            },
+            txs: vec![],
+            tx_hashes,
+        }
+    }
+
+    #[test]
+    fn inclusion_proofs_verify() {
+        for tx_count in [1, 2, 3, 5, 8] {
+            let block = block(tx_count);
+            let header_hash = block.header.hash();
+            for tx_hash in &block.tx_hashes {
+                let proof = block.inclusion_proof(tx_hash).unwrap();
+                assert!(verify_inclusion(&proof, &header_hash));
+            }
+        }
+    }
+
+    #[test]
+    fn shifted_index_fails() {
+        let block = block(5);
+        let header_hash = block.header.hash();
+        let mut proof = block.inclusion_proof(&3000.into()).unwrap();
+        assert_eq!(proof.index, 2);
+        proof.index = 3;
+        assert!(!verify_inclusion(&proof, &header_hash));
+        proof.index = 2 + 8;
+        assert!(!verify_inclusion(&proof, &header_hash));
+    }
+
+    #[test]
+    fn proof_is_bound_to_header() {
+        let block = block(4);
+        let header_hash = block.header.hash();
+        let mut proof = block.inclusion_proof(&1000.into()).unwrap();
+        proof.header.timestamp += 1;
+        assert!(!verify_inclusion(&proof, &header_hash));
+
+        assert!(block.inclusion_proof(&7.into()).is_none());
+    }
+
+    #[test]
+    fn compact_headers() {
+        let first = block(2).header;
+        let second = BlockHeader {
+            number: 2,
+            parent_hash: first.hash(),
+            l1_root: Some(12345.into()),
+            l1_tx_hashes: vec![H256::repeat_byte(1), H256::repeat_byte(2)],
+            ..first.clone()
+        };
+        assert_eq!(first.to_compact().len(), COMPACT_HEADER_SIZE);
+        let headers = vec![first, second];
+        let bytes = encode_headers(&headers);
+        assert_eq!(decode_headers(&bytes).unwrap(), headers);
+        assert!(decode_headers(&bytes[..bytes.len() - 1]).is_err());
+        assert!(verify_header_chain(&headers));
+    }
+
+    #[test]
+    fn broken_header_chain() {
+        let first = block(1).header;
+        let mut second = BlockHeader {
+            number: 2,
+            parent_hash: first.hash(),
+            ..first.clone()
+        };
+        assert!(verify_header_chain(&[first.clone(), second.clone()]));
+        second.parent_hash = U256::zero();
+        assert!(!verify_header_chain(&[first.clone(), second.clone()]));
+        second.parent_hash = first.hash();
+        second.number = 3;
+        assert!(!verify_header_chain(&[first, second]));
+    }
+}
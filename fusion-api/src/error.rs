@@ -0,0 +1,152 @@
+use std::fmt;
+
+use ethers_core::types::U256;
+use serde::{Deserialize, Serialize};
+
+/// Why `submit_transaction`, `submit_transaction_raw`,
+/// `submit_commitment` or `reveal_transaction` refused a transaction. Each kind has the
+/// JSON-RPC error `code` a gateway answers with, and its fields as the
+/// error data.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SubmitError {
+    /// -32001: the signature is malformed or was not made by the sender.
+    InvalidSignature(String),
+    /// -32003: `nonce` was already used or skips ahead of `expected`.
+    BadNonce { nonce: U256, expected: U256 },
+    /// -32004: the mempool holds `max` transactions already.
+    MempoolFull { max: usize },
+    /// -32005: the sender has another transaction pending with `nonce`.
+    Duplicate { nonce: U256 },
+    /// -32006: more than `rate` submissions per second from this client.
+    RateLimited { rate: u32, retry_in_ms: u64 },
+    /// -32007: the sender has `max` transactions pending already.
+    SenderLimit { max: usize },
+    /// -32008: `max` commitments are waiting to be revealed already.
+    CommitmentsFull { max: usize },
+    /// -32602: a malformed transaction, e.g. a recipient that is not a
+    /// public key or the wrong chain id.
+    Invalid(String),
+    /// -32603: the sequencer could not handle the transaction.
+    Internal(String),
+}
+
+impl SubmitError {
+    pub fn code(&self) -> i32 {
+        match self {
+            SubmitError::InvalidSignature(_) => -32001,
+            SubmitError::BadNonce { .. } => -32003,
+            SubmitError::MempoolFull { .. } => -32004,
+            SubmitError::Duplicate { .. } => -32005,
+            SubmitError::RateLimited { .. } => -32006,
+            SubmitError::SenderLimit { .. } => -32007,
+            SubmitError::CommitmentsFull { .. } => -32008,
+            SubmitError::Invalid(_) => -32602,
+            SubmitError::Internal(_) => -32603,
+        }
+    }
+}
+
+impl fmt::Display for SubmitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SubmitError::InvalidSignature(e)
+            | SubmitError::Invalid(e)
+            | SubmitError::Internal(e) => {
+                write!(f, "{e}")
+            }
+            SubmitError::BadNonce { nonce, expected } if nonce < expected => write!(
+                f,
+                "Nonce {nonce} was already used, the next one is {expected}"
+            ),
+            SubmitError::BadNonce { nonce, expected } => {
+                write!(f, "Nonce {nonce} skips ahead, the next one is {expected}")
+            }
+            SubmitError::MempoolFull { max } => {
+                write!(f, "The mempool is full, with {max} transactions")
+            }
+            SubmitError::Duplicate { nonce } => {
+                write!(f, "A transaction with nonce {nonce} is already pending")
+            }
+            SubmitError::RateLimited { rate, retry_in_ms } => write!(
+                f,
+                "Rate limited: more than {rate} submissions per second, retry in {retry_in_ms}ms"
+            ),
+            SubmitError::SenderLimit { max } => {
+                write!(f, "The sender has {max} transactions pending already")
+            }
+            SubmitError::CommitmentsFull { max } => {
+                write!(f, "{max} commitments are waiting to be revealed already")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SubmitError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn codes_and_messages() {
+        for (error, code, message) in [
+            (
+                SubmitError::InvalidSignature("Invalid signature".to_string()),
+                -32001,
+                "Invalid signature",
+            ),
+            (
+                SubmitError::BadNonce {
+                    nonce: 2.into(),
+                    expected: 5.into(),
+                },
+                -32003,
+                "Nonce 2 was already used, the next one is 5",
+            ),
+            (
+                SubmitError::BadNonce {
+                    nonce: 7.into(),
+                    expected: 5.into(),
+                },
+                -32003,
+                "Nonce 7 skips ahead, the next one is 5",
+            ),
+            (
+                SubmitError::MempoolFull { max: 10 },
+                -32004,
+                "The mempool is full, with 10 transactions",
+            ),
+            (
+                SubmitError::Duplicate { nonce: 3.into() },
+                -32005,
+                "A transaction with nonce 3 is already pending",
+            ),
+            (
+                SubmitError::RateLimited {
+                    rate: 10,
+                    retry_in_ms: 100,
+                },
+                -32006,
+                "Rate limited: more than 10 submissions per second, retry in 100ms",
+            ),
+            (
+                SubmitError::SenderLimit { max: 16 },
+                -32007,
+                "The sender has 16 transactions pending already",
+            ),
+            (
+                SubmitError::CommitmentsFull { max: 1000 },
+                -32008,
+                "1000 commitments are waiting to be revealed already",
+            ),
+            (
+                SubmitError::Invalid("Signed for chain 2".to_string()),
+                -32602,
+                "Signed for chain 2",
+            ),
+        ] {
+            assert_eq!(error.code(), code);
+            assert_eq!(error.to_string(), message);
+        }
+    }
+}
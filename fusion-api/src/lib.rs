@@ -1,8 +1,16 @@
-use ethers_core::types::U256;
+use ethers_core::types::{Address, Bytes, H256, U256};
+use ethers_core::utils::keccak256;
 use fusion_types::{FromBabyJubjubPoint, PublicKey, ToBabyJubjubPoint, ToFr, ToU256};
 use poseidon_rs::*;
 use serde::{Deserialize, Serialize};
 
+mod block;
+mod error;
+mod wire;
+pub use block::*;
+pub use error::*;
+pub use wire::*;
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Tx {
     pub kind: TxKind,
@@ -10,9 +18,96 @@ pub struct Tx {
     pub to: U256,
     pub nonce: U256,
     pub value: U256,
+    /// Unix timestamp (in seconds) before which the transaction must not
+    /// be included in a block.
+    #[serde(default)]
+    pub not_before: Option<u64>,
+    /// Unix timestamp (in seconds) after which the transaction expires.
+    #[serde(default)]
+    pub not_after: Option<u64>,
+    /// The deployment the transaction is signed for, see `chain_info`.
+    /// `None` in the first message format, which deployments accept
+    /// while their compatibility window is open.
+    #[serde(default)]
+    pub chain_id: Option<u64>,
+    /// The L1 address of the ERC-20 token moved, `NATIVE_TOKEN` for the
+    /// native asset.
+    #[serde(default)]
+    pub token: Address,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// The `token` of the transactions of the native asset.
+pub const NATIVE_TOKEN: Address = Address::zero();
+
+/// The key of the leaf holding the balance of `token` of the account at
+/// `address`: the address itself for the native asset, so that native
+/// balances keep their leaves.
+pub fn account_key(address: U256, token: Address) -> U256 {
+    if token == NATIVE_TOKEN {
+        return address;
+    }
+    Poseidon::new()
+        .hash(vec![address.to_fr(), token_input(token).to_fr()])
+        .unwrap()
+        .to_u256()
+}
+
+/// `token` as the circuit reads it.
+pub fn token_input(token: Address) -> U256 {
+    U256::from_big_endian(token.as_bytes())
+}
+
+/// Version of the message signed for the transactions built now, the
+/// first one having no chain id.
+pub const TX_FORMAT: u8 = 2;
+
+/// Where a transaction stands relative to its validity window
+/// at a given block timestamp.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Validity {
+    /// `not_before` has not been reached yet.
+    Premature,
+    Valid,
+    /// `not_after` has passed.
+    Expired,
+}
+
+impl Tx {
+    /// Version of the message signed for the transaction.
+    pub fn format(&self) -> u8 {
+        match self.chain_id {
+            None => 1,
+            Some(_) => TX_FORMAT,
+        }
+    }
+
+    /// The leaf the transaction takes from, its sender's account of
+    /// `token`. Each has its own nonces.
+    pub fn sender_account(&self) -> U256 {
+        account_key(PublicKey::from(self.sender).address(), self.token)
+    }
+
+    /// The leaf a transfer credits, its recipient's account of `token`.
+    pub fn to_account(&self) -> U256 {
+        account_key(PublicKey::from(self.to).address(), self.token)
+    }
+
+    pub fn has_validity_window(&self) -> bool {
+        self.not_before.is_some() || self.not_after.is_some()
+    }
+
+    /// Both bounds are inclusive: a transaction is valid at `not_before`
+    /// and at `not_after`.
+    pub fn validity_at(&self, timestamp: u64) -> Validity {
+        match (self.not_before, self.not_after) {
+            (Some(not_before), _) if timestamp < not_before => Validity::Premature,
+            (_, Some(not_after)) if timestamp > not_after => Validity::Expired,
+            _ => Validity::Valid,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TxKind {
     Transfer,
     Deposit,
@@ -51,20 +146,63 @@ impl From<U256> for TxKind {
     }
 }
 
+/// Hashes a transaction the same way the circuit does.
+/// Transactions without a validity window keep the original
+/// 5-element preimage, so their signatures remain valid.
+/// Otherwise the window is hashed and appended as a 6th element. In the
+/// second format, the chain id is hashed with the window, which is then
+/// always appended, and so is the token unless it is the native one.
 pub fn hash_tx(tx: &Tx) -> U256 {
     let sender_pk = PublicKey::from_babyjubjub_point(&tx.sender.to_babyjubjub_point());
     let to_pk = PublicKey::from_babyjubjub_point(&tx.to.to_babyjubjub_point());
+    let mut preimage = vec![
+        tx.kind.to_u256().to_fr(),
+        sender_pk.to_fr(),
+        to_pk.to_fr(),
+        tx.nonce.to_fr(),
+        tx.value.to_fr(),
+    ];
+    if tx.chain_id.is_some() || tx.token != NATIVE_TOKEN {
+        let mut extension = vec![
+            U256::from(tx.not_before.unwrap_or(0)).to_fr(),
+            U256::from(tx.not_after.unwrap_or(0)).to_fr(),
+            U256::from(tx.chain_id.unwrap_or(0)).to_fr(),
+        ];
+        if tx.token != NATIVE_TOKEN {
+            extension.push(token_input(tx.token).to_fr());
+        }
+        preimage.push(Poseidon::new().hash(extension).unwrap());
+    } else if tx.has_validity_window() {
+        preimage.push(
+            Poseidon::new()
+                .hash(vec![
+                    U256::from(tx.not_before.unwrap_or(0)).to_fr(),
+                    U256::from(tx.not_after.unwrap_or(0)).to_fr(),
+                ])
+                .unwrap(),
+        );
+    }
+    Poseidon::new().hash(preimage).unwrap().to_u256()
+}
+
+/// Commitment to a transaction revealed later with `salt`,
+/// see `FusionRPC::submit_commitment`.
+pub fn tx_commitment(tx: &Tx, salt: U256) -> U256 {
+    let mut preimage = [0u8; 64];
+    hash_tx(tx).to_big_endian(&mut preimage[..32]);
+    salt.to_big_endian(&mut preimage[32..]);
+    U256::from(keccak256(preimage))
+}
+
+/// The message the sender of a pending transaction signs to drop it,
+/// see `FusionRPC::drop_transaction`. It differs from the transaction
+/// hash, which the transaction's own signature already signs.
+pub fn drop_message(tx_hash: U256) -> U256 {
     Poseidon::new()
-        .hash(
-            [
-                tx.kind.to_u256().to_fr(),
-                sender_pk.to_fr(),
-                to_pk.to_fr(),
-                tx.nonce.to_fr(),
-                tx.value.to_fr(),
-            ]
-            .to_vec(),
-        )
+        .hash(vec![
+            tx_hash.to_fr(),
+            U256::from_big_endian(b"drop").to_fr(),
+        ])
         .unwrap()
         .to_u256()
 }
@@ -75,9 +213,564 @@ pub struct SignedTx {
     pub signature: String,
 }
 
+/// Outcome of an `admin_flush_batch` request.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FlushResult {
+    /// The number of the block that was built.
+    Block(u64),
+    NothingToBatch,
+}
+
+/// How the sequencer orders the transactions of a block.
+/// Whatever the policy, each sender's transactions stay in nonce order.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OrderingPolicy {
+    /// In the order the sequencer received them.
+    #[default]
+    Fifo,
+    /// Shuffled with a seed derived from the parent block hash and the
+    /// block number, so anyone can check the order afterwards.
+    Random,
+}
+
+/// What transactions are signed for on a deployment, see `chain_info`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChainInfo {
+    /// To sign into `Tx::chain_id`.
+    pub chain_id: u64,
+    /// The L1 chain the blocks are proven to, any when 0, and the
+    /// contract there.
+    pub l1_chain_id: u64,
+    pub l1_contract: Address,
+    /// The latest message format the sequencer accepts, `TX_FORMAT`.
+    pub tx_format: u8,
+    /// Whether transactions without a chain id are still accepted.
+    pub accepts_legacy: bool,
+}
+
+/// Static information about the sequencer.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SequencerInfo {
+    /// Dev mode blocks are not proven, and dev account keys are public.
+    pub dev: bool,
+    pub ordering: OrderingPolicy,
+    /// Public key that signs attestations.
+    pub attestation_key: U256,
+    /// The config profile the sequencer runs with, if any.
+    pub profile: Option<String>,
+    /// Version of the L1 contract, `None` in dev mode.
+    pub contract_version: Option<u64>,
+    /// Identifier of the circuit the sequencer proves, `None` in dev mode.
+    pub circuit_params: Option<H256>,
+    /// Transactions that trigger a block, `min_tx_block` unless the
+    /// batch size is tuned.
+    #[serde(default)]
+    pub batch_size: usize,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HealthStatus {
+    #[default]
+    Ok,
+    /// Still building blocks, but the last one could not be fully
+    /// proven or submitted.
+    Degraded,
+    /// Not building blocks anymore.
+    Halted,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Health {
+    pub status: HealthStatus,
+    /// Why the sequencer is not `Ok`.
+    pub reason: Option<String>,
+    /// Number of the last block built.
+    pub block_number: u64,
+    pub mempool_size: usize,
+    /// Proofs waiting to be submitted to L1.
+    #[serde(default)]
+    pub submission_queue: usize,
+    /// Seconds the oldest of them has been waiting.
+    #[serde(default)]
+    pub oldest_submission_age: u64,
+    /// Seconds the system clock is ahead of the latest L1 block,
+    /// negative when behind, as measured for the last block.
+    #[serde(default)]
+    pub clock_drift: Option<i64>,
+    /// As of the latest maintenance run.
+    #[serde(default)]
+    pub storage: StorageStats,
+    /// L1 transaction of the proof being submitted, until it is mined.
+    #[serde(default)]
+    pub pending_l1_tx: Option<H256>,
+}
+
+/// One check of `SequencerStatus`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatusCheck {
+    pub ok: bool,
+    pub detail: String,
+}
+
+/// Whether the sequencer is ready to serve, as on `/ready`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SequencerStatus {
+    /// Whether every check is ok.
+    pub ready: bool,
+    /// `l1`: the L1 node answers. `sync`: L1 is at most
+    /// `ready_max_block_lag` blocks behind the sequencer.
+    /// `submission`: the last proof submission did not fail.
+    pub checks: std::collections::BTreeMap<String, StatusCheck>,
+    /// Number of the last block built.
+    pub block_number: u64,
+    /// The latest batch confirmed on L1 and when.
+    pub last_batch: Option<u64>,
+    pub last_batch_time: Option<u64>,
+    /// Times in a row the proofs to submit built on a root L1 no longer
+    /// held, so their blocks were built again. More than one suggests a
+    /// second sequencer submitting to the same contract.
+    #[serde(default)]
+    pub stale_root_conflicts: u64,
+}
+
+/// Outcome of the latest maintenance run.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StorageStats {
+    /// Blocks 1 to `pruned_through` only keep their header and
+    /// transaction hashes.
+    pub pruned_through: u64,
+    /// Size of the transactions the run dropped, in their JSON encoding.
+    pub reclaimed_bytes: u64,
+    /// Size of the transactions still kept, in their JSON encoding.
+    pub blocks_bytes: u64,
+    /// Size of `database_path` on disk.
+    pub database_bytes: u64,
+}
+
+/// Breakdown of the transactions waiting in the mempool.
+/// Statuses are relative to the current L2 state and clock.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MempoolStats {
+    pub pending: usize,
+    /// Would be included in the next block.
+    pub ready: usize,
+    /// Their validity window has not started yet.
+    pub premature: usize,
+    /// Their validity window is over, dropped at the next block.
+    pub expired: usize,
+    /// Their nonce was already used, dropped at the next block.
+    pub stale_nonce: usize,
+    /// Their sender cannot afford them, dropped at the next block.
+    pub unfundable: usize,
+    pub distinct_senders: usize,
+    /// Senders with the most pending transactions, with their count.
+    pub top_senders: Vec<(U256, usize)>,
+    pub total_value: U256,
+    /// Ages in seconds since the sequencer received the transactions.
+    pub age_p50: u64,
+    pub age_p90: u64,
+    pub oldest_age: u64,
+}
+
+/// Why a queued transaction is left out of the next block.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Exclusion {
+    /// Its validity window has not started yet, it stays queued.
+    Premature,
+    /// Its commitment appears in the next block itself, it stays queued.
+    Committed,
+    /// The block already has `max_block_txs` transactions before it, it
+    /// stays queued.
+    Full,
+    /// Its validity window is over, it is dropped.
+    Expired,
+    /// Its signature or the state before the block rejects it, it is
+    /// dropped.
+    Invalid(String),
+    /// An earlier transaction of the block spent the funds or used the
+    /// nonce, or it claims more than is left of its L1 deposits, it is
+    /// dropped.
+    Revalidation(String),
+}
+
+/// What the sequencer would put in the next block if it built it now.
+/// Only advisory: transactions keep arriving, and the block timestamp
+/// may differ.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchPreview {
+    pub block: u64,
+    pub timestamp: u64,
+    /// Hashes of the included transactions, in block order. No block
+    /// would be built when empty.
+    pub tx_hashes: Vec<U256>,
+    /// State root after the block.
+    pub state_root: U256,
+    /// Hashes of the queued transactions left out, with the reason.
+    pub excluded: Vec<(U256, Exclusion)>,
+}
+
+/// Milliseconds spent on each stage of a proven block.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockTimings {
+    pub block: u64,
+    pub txs: usize,
+    /// Building the circuit inputs of all transactions.
+    pub inputs_ms: u64,
+    /// Building the witnesses, summed over the transactions.
+    pub witness_ms: u64,
+    /// Generating the proofs, summed over the transactions.
+    pub prove_ms: u64,
+    /// From requesting the proofs to receiving all of them. Transactions
+    /// are proven in parallel, so this is what delays the block.
+    pub proving_ms: u64,
+    /// Submitting the proofs to L1, where they are verified.
+    pub submit_ms: u64,
+}
+
+/// Distribution of the time spent on a stage, in milliseconds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StageStats {
+    pub p50: u64,
+    pub p90: u64,
+    pub max: u64,
+}
+
+/// Timings of the latest proven blocks.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProvingStats {
+    /// At most `MAX_TIMED_BLOCKS` of them, oldest first.
+    pub blocks: Vec<BlockTimings>,
+    pub inputs: StageStats,
+    pub witness: StageStats,
+    pub prove: StageStats,
+    pub proving: StageStats,
+    pub submit: StageStats,
+}
+
+/// Number of blocks `ProvingStats` covers.
+pub const MAX_TIMED_BLOCKS: usize = 100;
+
+/// A failure the sequencer can inject to exercise its recovery paths.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Fault {
+    /// Sending a proof to L1 fails.
+    Submission,
+    /// Proving a transaction fails.
+    Prover,
+    /// Persisting the submission queue fails.
+    Storage,
+}
+
+impl std::fmt::Display for Fault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Fault::Submission => write!(f, "L1 submission"),
+            Fault::Prover => write!(f, "prover"),
+            Fault::Storage => write!(f, "storage"),
+        }
+    }
+}
+
+/// Faults injected on purpose, only on a dev sequencer or one running
+/// against a local chain. The defaults are the standard chaos profile.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChaosProfile {
+    pub enabled: bool,
+    /// Seed of the random faults, so a run can be replayed.
+    pub seed: u64,
+    /// Probabilities, between 0 and 1, of each random fault.
+    pub submission_failure_rate: f64,
+    pub prover_failure_rate: f64,
+    pub storage_failure_rate: f64,
+    /// Milliseconds the receipt of every L1 submission is delayed by.
+    pub receipt_delay_ms: u64,
+    /// Faults injected in this order, each at the next occasion of its
+    /// kind, before any random one.
+    pub script: Vec<Fault>,
+}
+
+impl Default for ChaosProfile {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            seed: 0,
+            submission_failure_rate: 0.2,
+            prover_failure_rate: 0.1,
+            storage_failure_rate: 0.1,
+            receipt_delay_ms: 500,
+            script: vec![],
+        }
+    }
+}
+
+/// Activity of an account in the blocks built so far. Deposits count as
+/// received and withdrawals as sent.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountStats {
+    pub sent_count: u64,
+    pub received_count: u64,
+    pub total_sent: U256,
+    pub total_received: U256,
+    pub first_active_block: Option<u64>,
+    pub last_active_block: Option<u64>,
+}
+
+/// Maximum number of records returned by a single
+/// `get_account_history` call.
+pub const MAX_HISTORY_PER_REQUEST: usize = 1000;
+
+/// A transaction applied in a block, with the balances it changed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub batch: u64,
+    /// Position of the transaction in the block.
+    pub index: usize,
+    pub tx_hash: U256,
+    pub kind: TxKind,
+    pub token: Address,
+    /// Public key of the sender.
+    pub from: U256,
+    /// Public key of the recipient.
+    pub to: U256,
+    pub value: U256,
+    /// Always zero: transactions have no fees yet.
+    pub fee: U256,
+    pub nonce: U256,
+    pub from_balance_before: U256,
+    pub from_balance_after: U256,
+    pub to_balance_before: U256,
+    pub to_balance_after: U256,
+}
+
+/// Balance of an account. Unknown accounts have a zero balance.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Balance {
+    /// As of the latest block.
+    pub committed: U256,
+    /// Once the transactions in the mempool that are valid in the order
+    /// they arrived are included, regardless of their validity windows.
+    pub pending: U256,
+}
+
+/// Nonces of a sender. Unknown accounts have a zero nonce.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Nonces {
+    /// Last nonce used as of the latest block.
+    pub committed: U256,
+    /// Last nonce used once the mempool is included. Above `committed`
+    /// while transactions of the sender wait for a block.
+    pub pending: U256,
+    /// Nonce of the sender's next transaction.
+    pub next: U256,
+}
+
+/// Where a pending transaction stands, returned when it is submitted and
+/// by `get_queue_position`. Blocks take the mempool in order, so the
+/// estimate only moves as the transactions ahead leave it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueuePosition {
+    pub tx_hash: U256,
+    /// Transactions ahead of it in the mempool.
+    pub position: usize,
+    /// Transactions in the mempool, this one included.
+    pub queued: usize,
+    /// Queued transactions that start a block, see `min_tx_block`.
+    pub batch_size: usize,
+    /// Most transactions in a block, no limit when 0.
+    pub max_block_txs: usize,
+    /// Blocks built before the one expected to include it. Transactions
+    /// outside their validity window are skipped, so it is an estimate.
+    pub blocks_ahead: usize,
+    /// The promise of inclusion signed when it was queued. Only
+    /// `submit_transaction` and `submit_transaction_raw` return it.
+    pub receipt: Option<Receipt>,
+}
+
+/// Whether the sequencer would include a transaction, see
+/// `estimate_transaction`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Estimate {
+    /// Leaves the sender with `post_balance` once it and the mempool
+    /// before it are included. `fee` is 0 until transactions carry one.
+    Success { post_balance: U256, fee: U256 },
+    /// The sender's pending balance is `shortfall` short of the value.
+    InsufficientBalance { balance: U256, shortfall: U256 },
+    /// The nonce is not the sender's next one, `expected`.
+    BadNonce { expected: U256 },
+    /// Refused for another reason.
+    Invalid(String),
+}
+
+/// Where a transaction is on its way to L1.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TxStatus {
+    /// Waiting in the mempool.
+    Pending,
+    /// In block `block`, whose proofs were sent to L1 in `l1_tx_hashes`.
+    /// Empty until the first proof is sent, and in dev mode.
+    Included { block: u64, l1_tx_hashes: Vec<H256> },
+    /// Left out of a block and dropped.
+    Rejected { reason: String },
+}
+
+/// A block built or confirmed, see `wait_for_block`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockEvent {
+    pub block: u64,
+    pub state_root: U256,
+    /// The L1 transaction of the last proof of the block once all of them
+    /// are confirmed, `None` when the block was just built.
+    pub l1_tx_hash: Option<H256>,
+}
+
+/// Seconds a `wait_for_*` request waits at most.
+pub const MAX_WAIT: u64 = 60;
+
+/// Number of rejected transactions `get_transaction` remembers.
+pub const MAX_REJECTED_TXS: usize = 1000;
+
+/// A transaction the sequencer accepted, see `get_transaction`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TxInfo {
+    pub hash: U256,
+    /// `None` once its block is pruned.
+    pub tx: Option<SignedTx>,
+    pub status: TxStatus,
+}
+
 #[tarpc::service]
 pub trait FusionRPC {
-    async fn submit_transaction(tx: SignedTx) -> Result<(), String>;
+    /// Queues a transaction and returns its hash, to follow it with
+    /// `get_transaction`, and where it stands in the mempool. See
+    /// `SubmitError` for the error codes.
+    async fn submit_transaction(tx: SignedTx) -> Result<QueuePosition, SubmitError>;
+    /// Same as `submit_transaction`, with the transaction in the binary
+    /// encoding of `encode_signed_tx`, which is smaller and faster to
+    /// decode.
+    async fn submit_transaction_raw(tx: Bytes) -> Result<QueuePosition, SubmitError>;
+    /// Where the pending transaction with hash `tx_hash` stands now.
+    async fn get_queue_position(tx_hash: U256) -> Result<QueuePosition, String>;
+    /// First step of a commit-reveal submission: records `tx_commitment`
+    /// of a transaction without disclosing it. Returns the number of the
+    /// block the commitment appears in.
+    async fn submit_commitment(commitment: U256) -> Result<u64, SubmitError>;
+    /// Second step of a commit-reveal submission, within `reveal_window`
+    /// blocks of the commitment. The transaction is included in a later
+    /// block than its commitment, before the ones submitted directly.
+    async fn reveal_transaction(tx: SignedTx, salt: U256) -> Result<(), SubmitError>;
+    /// Drops the pending transaction with hash `tx_hash` from the mempool.
+    /// `signature` is its sender's signature of `drop_message(tx_hash)`.
+    async fn drop_transaction(tx_hash: U256, signature: String) -> Result<(), String>;
+    /// Dev mode only: sends `amount` from the faucet account to the
+    /// public key `to` in the next block. Returns the transaction hash.
+    async fn dev_faucet(to: U256, amount: U256) -> Result<U256, String>;
+    async fn sequencer_info() -> SequencerInfo;
+    /// The chain id to sign transactions with.
+    async fn chain_info() -> Result<ChainInfo, String>;
+    async fn sequencer_health() -> Health;
+    /// The readiness checks served on `/ready`.
+    async fn sequencer_status() -> SequencerStatus;
+    async fn get_mempool_stats() -> Result<MempoolStats, String>;
+    /// Selects and orders the next block from the mempool without
+    /// touching it. Refused unless `batch_preview` is set.
+    async fn get_next_batch_preview() -> Result<BatchPreview, String>;
+    /// Proof that the transaction with hash `tx_hash` was included
+    /// in a block, to be checked with `verify_inclusion`.
+    async fn get_inclusion_proof(tx_hash: U256) -> Result<InclusionProof, String>;
+    /// Headers of blocks `from` to `to`, both included, at most
+    /// `MAX_HEADERS_PER_REQUEST` of them.
+    async fn get_headers(from: u64, to: u64) -> Result<Vec<BlockHeader>, String>;
+    /// Same as `get_headers`, in the compact encoding of `encode_headers`.
+    async fn get_compact_headers(from: u64, to: u64) -> Result<Vec<u8>, String>;
+    /// Blocks `from` to `to` with their transactions, both included, at
+    /// most `MAX_BLOCKS_PER_REQUEST` of them. Pruned blocks only have
+    /// their transaction hashes.
+    async fn get_blocks(from: u64, to: u64) -> Result<Vec<Block>, String>;
+    /// The block `number` once L1 accepted all its proofs, `None` before.
+    async fn get_batch(number: u64) -> Option<Batch>;
+    /// The latest block L1 accepted all the proofs of.
+    async fn get_latest_batch() -> Option<Batch>;
+    /// The witness the proofs of block `number` were generated from,
+    /// `None` for a block that was not proven.
+    async fn get_batch_witness(number: u64) -> Result<Option<BatchWitness>, String>;
+    /// Attestations of the blocks confirmed on L1, oldest first, at most
+    /// `MAX_ATTESTATIONS_PER_REQUEST` of them starting at `offset`.
+    async fn get_attestations(offset: usize, limit: usize) -> Result<Vec<Attestation>, String>;
+    /// Statistics of the account with public key `address`.
+    async fn get_account_stats(address: U256) -> Result<AccountStats, String>;
+    /// The transactions of blocks `from_batch` to `to_batch`, both
+    /// included, sent or received by the account with public key
+    /// `address`, oldest first, at most `MAX_HISTORY_PER_REQUEST` of them
+    /// starting at `offset`. Only the blocks still in the audit log are
+    /// searched.
+    async fn get_account_history(
+        address: U256,
+        from_batch: u64,
+        to_batch: u64,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<AuditRecord>, String>;
+    /// Balance of `token` of the account with public key `address`, of
+    /// the native asset when `None`.
+    async fn get_balance(address: U256, token: Option<Address>) -> Result<Balance, String>;
+    /// Nonces of the sender with public key `address` for `token`, the
+    /// native asset when `None`, counting its transactions in the
+    /// mempool. Each token has its own nonces.
+    async fn get_nonce(address: U256, token: Option<Address>) -> Result<Nonces, String>;
+    /// Checks the unsigned `tx` against the state with the mempool
+    /// included, without queuing it nor reserving its nonce.
+    async fn estimate_transaction(tx: Tx) -> Result<Estimate, String>;
+    /// The transaction with hash `tx_hash` and its status. Only the
+    /// latest `MAX_REJECTED_TXS` rejected transactions are kept.
+    async fn get_transaction(tx_hash: U256) -> Result<TxInfo, String>;
+    /// The withdrawals of the sender with public key `sender` that L1 has
+    /// not paid out yet, in the mempool or in blocks whose proofs are
+    /// still queued, oldest first.
+    async fn get_pending_withdrawals(sender: U256) -> Result<Vec<TxInfo>, String>;
+    /// Waits for the next block to be built or confirmed on L1, at most
+    /// `MAX_WAIT` seconds. Call it again for the one after.
+    async fn wait_for_block() -> Result<BlockEvent, String>;
+    /// Waits until the status of the transaction with hash `tx_hash` is
+    /// no longer `known`, at most `MAX_WAIT` seconds, and returns it like
+    /// `get_transaction`. Returns at once when `known` is `None`.
+    async fn wait_for_transaction(tx_hash: U256, known: Option<TxStatus>)
+        -> Result<TxInfo, String>;
+}
+
+/// Operator methods, served apart from `FusionRPC` on the loopback
+/// interface only, at `admin_port`.
+#[tarpc::service]
+pub trait AdminRPC {
+    /// Stops building blocks. Transactions are still accepted and wait
+    /// in the mempool, and the proofs of the blocks already built are
+    /// still submitted to L1.
+    async fn admin_pause_batching() -> Result<(), String>;
+    /// Builds blocks again, at once if enough transactions are queued.
+    async fn admin_resume_batching() -> Result<(), String>;
+    /// Every transaction in the mempool, in the order they were queued.
+    async fn admin_mempool_content() -> Result<Vec<TxInfo>, String>;
+    /// Drops every transaction in the mempool and returns how many.
+    async fn admin_drop_all() -> Result<usize, String>;
+    /// Catches up with L1, snapshots the state replayed from it and
+    /// returns the L1 block of the snapshot.
+    async fn admin_snapshot() -> Result<u64, String>;
+    /// Builds a block right away with the queued transactions,
+    /// instead of waiting for `min_tx_block` of them.
+    async fn admin_flush_batch() -> Result<FlushResult, String>;
+    /// Dev mode only: drops the mempool and all blocks and goes back
+    /// to the genesis state.
+    async fn admin_dev_reset() -> Result<(), String>;
+    /// Time spent proving and submitting the latest blocks.
+    async fn admin_proving_stats() -> ProvingStats;
+    /// Checks that the L1 contract still matches the sequencer's bindings
+    /// and returns its version. Blocks are not built while it does not.
+    async fn admin_check_contract() -> Result<u64, String>;
+    /// Dev mode or local chain only: replaces the faults the sequencer
+    /// injects, see `ChaosProfile`.
+    async fn admin_set_chaos(profile: ChaosProfile) -> Result<(), String>;
 }
 
 #[cfg(test)]
@@ -98,6 +791,10 @@ mod test {
             nonce: 0.into(),
             value: 0.into(),
             kind: TxKind::Transfer,
+            not_before: None,
+            not_after: None,
+            chain_id: None,
+            token: NATIVE_TOKEN,
         };
         assert_eq!(
             hash_tx(&tx),
@@ -107,4 +804,128 @@ mod test {
             .unwrap()
         );
     }
+
+    #[test]
+    fn hash_commits_to_validity_window() {
+        let mut tx = Tx {
+            sender: U256::from_dec_str(
+                "11693830015789570214896451416834991706586932551962432904221523856506008194081",
+            )
+            .unwrap(),
+            to: U256::from_dec_str(
+                "11693830015789570214896451416834991706586932551962432904221523856506008194081",
+            )
+            .unwrap(),
+            nonce: 0.into(),
+            value: 0.into(),
+            kind: TxKind::Transfer,
+            not_before: None,
+            not_after: None,
+            chain_id: None,
+            token: NATIVE_TOKEN,
+        };
+        let legacy = hash_tx(&tx);
+        tx.not_before = Some(1_700_000_000);
+        let with_not_before = hash_tx(&tx);
+        tx.not_after = Some(1_700_003_600);
+        let with_window = hash_tx(&tx);
+        assert_ne!(legacy, with_not_before);
+        assert_ne!(with_not_before, with_window);
+    }
+
+    #[test]
+    fn hash_commits_to_chain_id() {
+        let mut tx = Tx {
+            sender: U256::from_dec_str(
+                "11693830015789570214896451416834991706586932551962432904221523856506008194081",
+            )
+            .unwrap(),
+            to: U256::from_dec_str(
+                "11693830015789570214896451416834991706586932551962432904221523856506008194081",
+            )
+            .unwrap(),
+            nonce: 0.into(),
+            value: 0.into(),
+            kind: TxKind::Transfer,
+            not_before: None,
+            not_after: None,
+            chain_id: None,
+            token: NATIVE_TOKEN,
+        };
+        let legacy = hash_tx(&tx);
+        assert_eq!(tx.format(), 1);
+        tx.chain_id = Some(1);
+        let chain_1 = hash_tx(&tx);
+        assert_eq!(tx.format(), TX_FORMAT);
+        tx.chain_id = Some(2);
+        assert_ne!(legacy, chain_1);
+        assert_ne!(chain_1, hash_tx(&tx));
+
+        tx.chain_id = Some(1);
+        tx.token = Address::from_low_u64_be(0xdead);
+        let token = hash_tx(&tx);
+        assert_ne!(token, chain_1);
+        tx.token = Address::from_low_u64_be(0xbeef);
+        assert_ne!(token, hash_tx(&tx));
+    }
+
+    #[test]
+    fn token_accounts() {
+        let address = U256::from(42);
+        assert_eq!(account_key(address, NATIVE_TOKEN), address);
+        let [a, b] = [1, 2].map(|token| account_key(address, Address::from_low_u64_be(token)));
+        assert_ne!(a, address);
+        assert_ne!(a, b);
+        assert_ne!(a, account_key(43.into(), Address::from_low_u64_be(1)));
+    }
+
+    #[test]
+    fn commitment_depends_on_salt() {
+        let tx = Tx {
+            sender: U256::from_dec_str(
+                "11693830015789570214896451416834991706586932551962432904221523856506008194081",
+            )
+            .unwrap(),
+            to: U256::from_dec_str(
+                "11693830015789570214896451416834991706586932551962432904221523856506008194081",
+            )
+            .unwrap(),
+            nonce: 0.into(),
+            value: 0.into(),
+            kind: TxKind::Transfer,
+            not_before: None,
+            not_after: None,
+            chain_id: None,
+            token: NATIVE_TOKEN,
+        };
+        assert_eq!(tx_commitment(&tx, 1.into()), tx_commitment(&tx, 1.into()));
+        assert_ne!(tx_commitment(&tx, 1.into()), tx_commitment(&tx, 2.into()));
+    }
+
+    #[test]
+    fn validity_window_boundaries() {
+        let tx = Tx {
+            sender: 0.into(),
+            to: 0.into(),
+            nonce: 0.into(),
+            value: 0.into(),
+            kind: TxKind::Transfer,
+            not_before: Some(100),
+            not_after: Some(200),
+            chain_id: None,
+            token: NATIVE_TOKEN,
+        };
+        assert_eq!(tx.validity_at(99), Validity::Premature);
+        assert_eq!(tx.validity_at(100), Validity::Valid);
+        assert_eq!(tx.validity_at(200), Validity::Valid);
+        assert_eq!(tx.validity_at(201), Validity::Expired);
+
+        let unbounded = Tx {
+            not_before: None,
+            not_after: None,
+            ..tx
+        };
+        assert_eq!(unbounded.validity_at(0), Validity::Valid);
+        assert_eq!(unbounded.validity_at(u64::MAX), Validity::Valid);
+    }
 }
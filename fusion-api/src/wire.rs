@@ -0,0 +1,179 @@
+//! Binary encoding of signed transactions, for `submit_transaction_raw`.
+//!
+//! The layout is fixed: the kind byte, then `sender`, `to`, `nonce` and
+//! `value` as 32-byte big-endian integers, then `not_before`, `not_after`
+//! and `chain_id` each as a presence byte followed by 8 bytes, zero when
+//! absent, then the 20 bytes of `token`, and finally the signature as a
+//! 64-byte big-endian integer. Encodings from before `token`,
+//! `PRE_TOKEN_RAW_TX_SIZE` bytes long, and from before `chain_id`,
+//! `LEGACY_RAW_TX_SIZE` bytes long, are still decoded.
+
+use ethers_core::types::{Address, U256, U512};
+use fusion_types::ToU256;
+
+use crate::{SignedTx, Tx, TxKind, NATIVE_TOKEN};
+
+/// Size of every encoded transaction.
+pub const RAW_TX_SIZE: usize = 1 + 32 * 4 + 9 * 3 + 20 + 64;
+
+/// Size of the encoded transactions without `token`, of the native token.
+pub const PRE_TOKEN_RAW_TX_SIZE: usize = RAW_TX_SIZE - 20;
+
+/// Size of the encoded transactions without `chain_id` nor `token`.
+pub const LEGACY_RAW_TX_SIZE: usize = PRE_TOKEN_RAW_TX_SIZE - 9;
+
+/// Parses a signature, which must be a decimal number written without
+/// leading zeros so that it has a single encoding.
+pub fn parse_signature(signature: &str) -> Result<U512, String> {
+    let invalid = || format!("Invalid signature `{signature}`, expected a decimal number");
+    let value = U512::from_dec_str(signature).map_err(|_| invalid())?;
+    match value.to_string() == signature {
+        true => Ok(value),
+        false => Err(invalid()),
+    }
+}
+
+pub fn encode_signed_tx(signed_tx: &SignedTx) -> Result<Vec<u8>, String> {
+    let signature = parse_signature(&signed_tx.signature)?;
+    let tx = &signed_tx.tx;
+    let mut bytes = Vec::with_capacity(RAW_TX_SIZE);
+    bytes.push(tx.kind.to_u256().low_u32() as u8);
+    let mut word = [0u8; 32];
+    for value in [tx.sender, tx.to, tx.nonce, tx.value] {
+        value.to_big_endian(&mut word);
+        bytes.extend(word);
+    }
+    for option in [tx.not_before, tx.not_after, tx.chain_id] {
+        bytes.push(option.is_some() as u8);
+        bytes.extend(option.unwrap_or(0).to_be_bytes());
+    }
+    bytes.extend(tx.token.as_bytes());
+    let mut signature_bytes = [0u8; 64];
+    signature.to_big_endian(&mut signature_bytes);
+    bytes.extend(signature_bytes);
+    Ok(bytes)
+}
+
+pub fn decode_signed_tx(bytes: &[u8]) -> Result<SignedTx, String> {
+    if ![RAW_TX_SIZE, PRE_TOKEN_RAW_TX_SIZE, LEGACY_RAW_TX_SIZE].contains(&bytes.len()) {
+        return Err(format!(
+            "Raw transactions are {RAW_TX_SIZE} bytes, got {}",
+            bytes.len()
+        ));
+    }
+    let legacy = bytes.len() == LEGACY_RAW_TX_SIZE;
+    let kind = match bytes[0] {
+        0 => TxKind::Transfer,
+        1 => TxKind::Deposit,
+        2 => TxKind::Withdraw,
+        kind => return Err(format!("Invalid transaction kind {kind}")),
+    };
+    let u256_at = |i: usize| U256::from_big_endian(&bytes[i..i + 32]);
+    let option_at = |i: usize, name: &str| -> Result<Option<u64>, String> {
+        let value = u64::from_be_bytes(bytes[i + 1..i + 9].try_into().unwrap());
+        match (bytes[i], value) {
+            (0, 0) => Ok(None),
+            (1, value) => Ok(Some(value)),
+            _ => Err(format!("Invalid encoding of {name}")),
+        }
+    };
+    Ok(SignedTx {
+        tx: Tx {
+            kind,
+            sender: u256_at(1),
+            to: u256_at(33),
+            nonce: u256_at(65),
+            value: u256_at(97),
+            not_before: option_at(129, "not_before")?,
+            not_after: option_at(138, "not_after")?,
+            chain_id: match legacy {
+                true => None,
+                false => option_at(147, "chain_id")?,
+            },
+            token: match bytes.len() == RAW_TX_SIZE {
+                true => Address::from_slice(&bytes[156..176]),
+                false => NATIVE_TOKEN,
+            },
+        },
+        signature: U512::from_big_endian(&bytes[bytes.len() - 64..]).to_string(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn signed_tx() -> SignedTx {
+        SignedTx {
+            tx: Tx {
+                kind: TxKind::Withdraw,
+                sender: U256::MAX,
+                to: 42.into(),
+                nonce: 7.into(),
+                value: U256::exp10(18),
+                not_before: None,
+                not_after: Some(1_700_000_000),
+                chain_id: Some(3),
+                token: NATIVE_TOKEN,
+            },
+            signature: U512::MAX.to_string(),
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let signed_tx = signed_tx();
+        let bytes = encode_signed_tx(&signed_tx).unwrap();
+        assert_eq!(bytes.len(), RAW_TX_SIZE);
+        let decoded = decode_signed_tx(&bytes).unwrap();
+        assert_eq!(format!("{decoded:?}"), format!("{signed_tx:?}"));
+        assert_eq!(encode_signed_tx(&decoded).unwrap(), bytes);
+
+        // The encodings before tokens and before chain ids.
+        let pre_token = [&bytes[..156], &bytes[176..]].concat();
+        let decoded = decode_signed_tx(&pre_token).unwrap();
+        assert_eq!(decoded.tx.chain_id, Some(3));
+        assert_eq!(decoded.tx.token, NATIVE_TOKEN);
+        let legacy = [&bytes[..147], &bytes[176..]].concat();
+        let decoded = decode_signed_tx(&legacy).unwrap();
+        assert_eq!(decoded.tx.chain_id, None);
+        assert_eq!(decoded.signature, signed_tx.signature);
+
+        let mut token_tx = signed_tx;
+        token_tx.tx.token = Address::repeat_byte(0xab);
+        let bytes = encode_signed_tx(&token_tx).unwrap();
+        assert_eq!(
+            decode_signed_tx(&bytes).unwrap().tx.token,
+            token_tx.tx.token
+        );
+    }
+
+    #[test]
+    fn rejected() {
+        let bytes = encode_signed_tx(&signed_tx()).unwrap();
+        assert!(decode_signed_tx(&bytes[1..]).is_err());
+        assert!(decode_signed_tx(&[bytes.clone(), vec![0]].concat()).is_err());
+
+        let mut invalid_kind = bytes.clone();
+        invalid_kind[0] = 3;
+        assert_eq!(
+            decode_signed_tx(&invalid_kind).unwrap_err(),
+            "Invalid transaction kind 3"
+        );
+        // An absent bound must be zero, so it has a single encoding.
+        let mut absent_bound = bytes.clone();
+        absent_bound[136] = 1;
+        assert!(decode_signed_tx(&absent_bound).is_err());
+        let mut invalid_flag = bytes;
+        invalid_flag[138] = 2;
+        assert!(decode_signed_tx(&invalid_flag).is_err());
+    }
+
+    #[test]
+    fn canonical_signatures() {
+        assert!(parse_signature("12345").is_ok());
+        for signature in ["", "012345", "+12345", "0x12", "1e3"] {
+            assert!(parse_signature(signature).is_err(), "{signature}");
+        }
+    }
+}
@@ -1,27 +1,278 @@
 use ethers_core::types;
+use fusion_api::{ChaosProfile, OrderingPolicy};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::default::Default;
-use std::path::PathBuf;
+use std::net::{IpAddr, SocketAddr};
+
+mod resolve;
+pub use resolve::*;
+
+/// Environment variable selecting the profile when `--profile` is not given.
+pub const PROFILE_ENV: &str = "FUSION_PROFILE";
+
+/// How the sequencer writes its logs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// One human readable line per event.
+    #[default]
+    Text,
+    /// One JSON object per event, with its fields.
+    Json,
+}
+
+/// Where the sequencer's L1 key comes from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeySource {
+    /// The hex key in `eth_private_key`.
+    #[default]
+    Hex,
+    /// The JSON keystore at `eth_keystore_path`, unlocked with
+    /// `ETH_KEYSTORE_PASSWORD` or a prompt.
+    Keystore,
+    /// Derived along `eth_derivation_path` from the mnemonic in the file
+    /// at `eth_mnemonic_path`.
+    Mnemonic,
+    /// A Ledger at `eth_derivation_path`, which asks to confirm every
+    /// transaction. Needs the `ledger` feature.
+    Ledger,
+    /// A Trezor at `eth_derivation_path`, likewise. Needs the `trezor`
+    /// feature.
+    Trezor,
+}
+
+impl KeySource {
+    /// Whether the key stays on a device, which signs on its own.
+    pub fn is_hardware(&self) -> bool {
+        matches!(self, KeySource::Ledger | KeySource::Trezor)
+    }
+}
+
+/// Where the sequencer keeps the transactions of its mempool.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MempoolBackend {
+    /// Indexed by hash and by sender and nonce, and logged to
+    /// `mempool.jsonl` in `database_path` so that a restart keeps them.
+    #[default]
+    Log,
+    /// A list in memory, lost on restart.
+    Memory,
+}
+
+/// Where the proofs of the blocks come from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProverBackend {
+    /// The circuit in `circuit_path`, run by the sequencer.
+    #[default]
+    Local,
+    /// The proving service at `prover_url`.
+    Remote,
+    /// Proofs of zeros that no verifier accepts, to run the sequencer
+    /// without a circuit.
+    Mock,
+}
+
+/// Connection settings of a network, overriding the top-level ones
+/// when the profile is selected.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    pub eth_rpc_url: Option<String>,
+    pub eth_private_key: Option<String>,
+    pub fusion_l1_contract: Option<types::Address>,
+    pub socket_address: Option<String>,
+    pub socket_port: Option<u16>,
+}
 
 #[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
     pub circuit_path: String,
     pub circuit_abi_path: String,
     pub proving_key_path: String,
+    /// Who proves the blocks, `local`, `remote` or `mock`.
+    pub prover: ProverBackend,
+    /// Where the proving service takes circuit inputs when `prover` is
+    /// `remote`.
+    pub prover_url: String,
+    /// Seconds the proving service may take per proof before the attempt
+    /// fails.
+    pub prover_timeout: u64,
     pub database_path: String,
     pub eth_rpc_url: String,
+    /// L1 endpoints requests move on to, in order, when `eth_rpc_url`
+    /// cannot be reached.
+    pub eth_rpc_fallback_urls: Vec<String>,
     pub eth_private_key: String,
+    /// Where the L1 key signing block submissions comes from.
+    pub key_source: KeySource,
+    pub eth_keystore_path: String,
+    pub eth_mnemonic_path: String,
+    pub eth_derivation_path: String,
     pub min_tx_block: usize,
+    /// Adjust the number of transactions per block, between
+    /// `min_batch_size` and `max_batch_size`, to keep proving a block
+    /// under `target_proving_time` seconds.
+    pub auto_tune_batch: bool,
+    pub min_batch_size: usize,
+    pub max_batch_size: usize,
+    pub target_proving_time: u64,
+    /// Most transactions in a block, the others stay queued in order for
+    /// the next ones. No limit when 0.
+    pub max_block_txs: usize,
+    /// How the transactions of a block are ordered, `fifo` or `random`.
+    pub ordering: OrderingPolicy,
+    /// Whether a transaction with the nonce of a pending one of its
+    /// sender replaces it, rather than being refused.
+    pub replace_pending_txs: bool,
+    /// Signed into the transactions of this deployment, so that they are
+    /// refused by any other. Never 0.
+    pub chain_id: u64,
+    /// Whether transactions signed without a chain id, in the first
+    /// message format, are accepted.
+    pub accept_legacy_txs: bool,
+    /// Unix time after which they are refused anyway, never when 0.
+    pub legacy_txs_until: u64,
+    /// Seconds a transaction may wait in the mempool before it is
+    /// dropped, forever when 0.
+    pub mempool_ttl: u64,
+    /// Most transactions in the mempool, more are refused; no limit
+    /// when 0.
+    pub max_mempool_size: usize,
+    /// Most transactions pending from one sender, per token, more are
+    /// refused; no limit when 0.
+    pub max_pending_per_sender: usize,
+    /// Where the mempool is kept, `log` or `memory`. Dev mode always
+    /// keeps it in memory.
+    pub mempool_backend: MempoolBackend,
     pub socket_address: String,
+    /// Port of the RPC server, picked by the system when 0.
     pub socket_port: u16,
+    /// Port of the Prometheus `/metrics` endpoint on `socket_address`,
+    /// not served when 0.
+    pub metrics_port: u16,
+    /// Port of the admin server, only ever bound to 127.0.0.1, not
+    /// served when 0.
+    pub admin_port: u16,
+    /// Blocks the L1 root may lag behind the latest block before
+    /// `/ready` fails.
+    pub ready_max_block_lag: u64,
     pub fusion_l1_contract: types::Address,
-}
-
-impl Config {
-    pub fn from_file(path: String) -> Self {
-        let config_file_str = std::fs::read_to_string(PathBuf::from(path)).unwrap();
-        toml::from_str(&config_file_str).unwrap()
-    }
+    /// Chain id the L1 node must report, any when 0.
+    pub l1_chain_id: u64,
+    /// Milliseconds between two polls of the L1 node, e.g. for pending transactions.
+    pub l1_poll_interval_ms: u64,
+    /// Seconds before a request to the L1 node is abandoned.
+    pub l1_request_timeout: u64,
+    /// Seconds before connecting to the L1 node is abandoned.
+    pub l1_connect_timeout: u64,
+    /// Seconds an L1 endpoint that could not be reached is skipped,
+    /// doubled each time in a row it fails again.
+    pub l1_failover_backoff: u64,
+    /// L1 block the contract was deployed at, where the state is
+    /// replayed from.
+    pub l1_sync_start_block: u64,
+    /// Most L1 blocks asked for in one `eth_getLogs` call.
+    pub l1_log_range: u64,
+    /// L1 blocks between snapshots of the state replayed from L1, taken
+    /// at startup and on maintenance. 0 disables them.
+    pub snapshot_interval: u64,
+    /// Most bytes of audit log kept, the oldest records are deleted past
+    /// it. 0 disables the audit log.
+    pub audit_log_max_bytes: u64,
+    /// Bytes of audit log written to a file before the next one starts.
+    pub audit_log_segment_bytes: u64,
+    /// Blocks mined on top of a proof submission before it counts as
+    /// accepted.
+    pub l1_confirmations: usize,
+    /// Seconds a proof submission may stay unmined before it is
+    /// replaced with a gas price `gas_bump_percent` higher, at most
+    /// `max_gas_bumps` times.
+    pub l1_tx_timeout: u64,
+    /// Seconds a proof submission may take to be signed, e.g. confirmed
+    /// on a hardware wallet, before it is retried later.
+    pub l1_signing_timeout: u64,
+    /// Gas limit of a proof submission, estimated by the L1 node when 0.
+    pub l1_gas_limit: u64,
+    pub gas_bump_percent: u64,
+    pub max_gas_bumps: u32,
+    /// Sends the proofs in a compact encoding to
+    /// `submitCompressedBlock(bytes)`, which the contract must have.
+    pub compressed_calldata: bool,
+    /// L1 blocks on top of a confirmed block after which it is no
+    /// longer checked for a reorg, never checked when 0.
+    pub reorg_depth: u64,
+    /// Submissions per second accepted from each IP, no limit when 0.
+    pub rpc_rate_limit: u32,
+    /// Submissions an IP may send at once before `rpc_rate_limit` applies.
+    pub rpc_burst: u32,
+    /// Largest RPC request in bytes, a larger one closes its connection.
+    pub rpc_max_request_size: usize,
+    /// Seconds before a wallet request to the sequencer is abandoned.
+    pub rpc_request_timeout: u64,
+    /// Seconds before the wallet gives up connecting to the sequencer.
+    pub rpc_connect_timeout: u64,
+    /// Address book of the wallet, mapping contact names to public keys.
+    pub contacts_path: String,
+    /// Baby Jubjub private key signing block attestations. A random one
+    /// is used when empty.
+    pub attestation_private_key: String,
+    /// Blocks within which a commitment must be revealed.
+    pub reveal_window: u64,
+    /// Most commitments waiting to be revealed, more are refused; no
+    /// limit when 0.
+    pub max_pending_commitments: usize,
+    /// Seconds between two checks of a random block against L1, never
+    /// when 0.
+    pub consistency_check_interval: u64,
+    /// Answer `get_next_batch_preview`, which reveals the content and
+    /// order of the next block before it is built.
+    pub batch_preview: bool,
+    /// Seconds a block timestamp may be ahead of the latest L1 block,
+    /// unbounded when 0.
+    pub max_l1_drift: u64,
+    /// Seconds between the system clock and the latest L1 block above
+    /// which the sequencer reports itself degraded, never when 0.
+    pub clock_drift_warning: u64,
+    /// Blocks whose transactions are kept, all of them when 0. Older
+    /// blocks only keep their header and transaction hashes.
+    pub block_retention: u64,
+    /// Seconds between two maintenance runs, never when 0.
+    pub maintenance_interval: u64,
+    /// Build a last block with the mempool when stopped by a signal.
+    pub flush_on_shutdown: bool,
+    /// Seconds a stopping sequencer keeps submitting queued proofs
+    /// before it exits anyway.
+    pub shutdown_timeout: u64,
+    pub log_format: LogFormat,
+    /// Which events are logged, e.g. `info` or `fusion_sequencer=debug`.
+    /// `RUST_LOG` overrides it.
+    pub log_filter: String,
+    /// Commit blocks locally without proving them nor talking to L1.
+    pub dev: bool,
+    /// Deploy the L1 contract on a local chain when `fusion_l1_contract`
+    /// is zero.
+    pub dev_l1: bool,
+    /// Faults injected on purpose, refused unless `dev` or `dev_l1`.
+    pub chaos: ChaosProfile,
+    /// Named networks, e.g. `[profiles.testnet]`.
+    pub profiles: BTreeMap<String, Profile>,
+    /// The selected profile, set by `resolve`.
+    #[serde(skip)]
+    pub profile: Option<String>,
+    /// Seconds before the dev faucet sends funds to the same recipient again.
+    pub faucet_cooldown: u64,
+    /// Number of pre-funded accounts derived from the dev mnemonic.
+    pub dev_accounts: u32,
+    /// Genesis balance of each dev account.
+    pub dev_account_balance: u64,
+    /// JSON file of accounts funded at genesis in dev mode, besides the
+    /// dev accounts. Empty for none, and refused outside of dev mode.
+    pub genesis_path: String,
 }
 
 impl Default for Config {
@@ -30,13 +281,160 @@ impl Default for Config {
             circuit_path: "../circuits/out".to_string(),
             circuit_abi_path: "../circuits/abi.json".to_string(),
             proving_key_path: "../circuits/proving.key".to_string(),
+            prover: ProverBackend::Local,
+            prover_url: String::default(),
+            prover_timeout: 600,
             database_path: "./db".to_string(),
             eth_rpc_url: "http://localhost:8545".to_string(),
+            eth_rpc_fallback_urls: vec![],
             eth_private_key: String::default(),
+            key_source: KeySource::Hex,
+            eth_keystore_path: String::default(),
+            eth_mnemonic_path: String::default(),
+            eth_derivation_path: "m/44'/60'/0'/0/0".to_string(),
             min_tx_block: 1,
+            auto_tune_batch: false,
+            min_batch_size: 1,
+            max_batch_size: 64,
+            target_proving_time: 60,
+            max_block_txs: 256,
+            ordering: OrderingPolicy::Fifo,
+            replace_pending_txs: false,
+            chain_id: 1,
+            accept_legacy_txs: true,
+            legacy_txs_until: 0,
+            mempool_ttl: 3600,
+            max_mempool_size: 10000,
+            max_pending_per_sender: 16,
+            mempool_backend: MempoolBackend::Log,
             socket_address: "127.0.0.1".to_string(),
             socket_port: 38171,
+            metrics_port: 38172,
+            admin_port: 38173,
+            ready_max_block_lag: 8,
             fusion_l1_contract: types::Address::default(),
+            l1_chain_id: 0,
+            l1_poll_interval_ms: 1000,
+            l1_request_timeout: 30,
+            l1_connect_timeout: 10,
+            l1_failover_backoff: 5,
+            l1_sync_start_block: 0,
+            l1_log_range: 1000,
+            snapshot_interval: 1000,
+            audit_log_max_bytes: 100_000_000,
+            audit_log_segment_bytes: 10_000_000,
+            l1_confirmations: 1,
+            l1_tx_timeout: 120,
+            l1_signing_timeout: 300,
+            l1_gas_limit: 1_000_000,
+            gas_bump_percent: 20,
+            max_gas_bumps: 3,
+            compressed_calldata: false,
+            reorg_depth: 64,
+            rpc_rate_limit: 20,
+            rpc_burst: 50,
+            rpc_max_request_size: 16 << 20,
+            rpc_request_timeout: 10,
+            rpc_connect_timeout: 5,
+            contacts_path: "./contacts.json".to_string(),
+            attestation_private_key: String::default(),
+            reveal_window: 10,
+            max_pending_commitments: 1000,
+            consistency_check_interval: 600,
+            batch_preview: false,
+            max_l1_drift: 0,
+            clock_drift_warning: 60,
+            block_retention: 0,
+            maintenance_interval: 3600,
+            flush_on_shutdown: false,
+            shutdown_timeout: 30,
+            log_format: LogFormat::Text,
+            log_filter: "info".to_string(),
+            dev: false,
+            dev_l1: false,
+            chaos: ChaosProfile::default(),
+            profiles: BTreeMap::new(),
+            profile: None,
+            faucet_cooldown: 60,
+            dev_accounts: 10,
+            dev_account_balance: 1_000_000_000_000_000_000,
+            genesis_path: String::new(),
         }
     }
 }
+
+impl Config {
+    /// The address the sequencer listens on and the wallet connects to.
+    pub fn socket_addr(&self) -> Result<SocketAddr, String> {
+        let ip: IpAddr = self.socket_address.parse().map_err(|e| {
+            format!(
+                "Invalid socket_address `{}`: {e}, expected an IP address",
+                self.socket_address
+            )
+        })?;
+        Ok((ip, self.socket_port).into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CONFIG: &str = r#"
+eth_rpc_url = "http://localhost:8545"
+socket_port = 38171
+
+[profiles.testnet]
+eth_rpc_url = "https://testnet.example.org"
+fusion_l1_contract = "0x5fbdb2315678afecb367f032d93f642f64180aa3"
+
+[profiles.local]
+socket_port = 4000
+"#;
+
+    fn with_profile(name: &str) -> Result<Config, String> {
+        resolve_from(CONFIG, Some(name), vec![], vec![]).map(|resolved| resolved.config)
+    }
+
+    #[test]
+    fn profile_overrides_top_level() {
+        let config = with_profile("testnet").unwrap();
+        assert_eq!(config.eth_rpc_url, "https://testnet.example.org");
+        assert_eq!(
+            config.fusion_l1_contract,
+            "0x5fbdb2315678afecb367f032d93f642f64180aa3"
+                .parse()
+                .unwrap()
+        );
+        // Not in the profile, kept from the top level.
+        assert_eq!(config.socket_port, 38171);
+        assert_eq!(config.profile.as_deref(), Some("testnet"));
+
+        let config = with_profile("local").unwrap();
+        assert_eq!(config.eth_rpc_url, "http://localhost:8545");
+        assert_eq!(config.socket_port, 4000);
+    }
+
+    #[test]
+    fn socket_addr() {
+        let mut config = Config::default();
+        assert_eq!(config.socket_addr(), Ok("127.0.0.1:38171".parse().unwrap()));
+        config.socket_address = "localhost".to_string();
+        assert!(config
+            .socket_addr()
+            .unwrap_err()
+            .starts_with("Invalid socket_address `localhost`"));
+    }
+
+    #[test]
+    fn unknown_profile() {
+        assert_eq!(
+            with_profile("mainnet").err(),
+            Some("Unknown profile `mainnet`, available profiles: local, testnet".to_string())
+        );
+        assert!(resolve_from("", Some("local"), vec![], vec![])
+            .err()
+            .unwrap()
+            .ends_with("none"));
+    }
+}
@@ -0,0 +1,396 @@
+//! Resolution of the config from its sources. From lowest to highest
+//! precedence:
+//!
+//! 1. the defaults of `Config`,
+//! 2. the config file,
+//! 3. the selected profile, see `Profile`,
+//! 4. `FUSION_*` environment variables,
+//! 5. command line flags.
+//!
+//! Every key can be set from the environment: `FUSION_SOCKET_PORT` sets
+//! `socket_port`, and `__` separates nested keys, so
+//! `FUSION_PROFILES__LOCAL__ETH_RPC_URL` sets `profiles.local.eth_rpc_url`.
+//! Values are read as TOML, e.g. `[1, 2]` for a list, except for string
+//! keys which take the variable as is.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+use toml::{Table, Value};
+
+use crate::{Config, PROFILE_ENV};
+
+const ENV_PREFIX: &str = "FUSION_";
+
+/// Where a config value comes from, by increasing precedence.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Source {
+    Default,
+    File,
+    Profile,
+    Env,
+    Flag,
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let source = match self {
+            Source::Default => "default",
+            Source::File => "file",
+            Source::Profile => "profile",
+            Source::Env => "env",
+            Source::Flag => "flag",
+        };
+        write!(f, "{source}")
+    }
+}
+
+pub struct Resolved {
+    pub config: Config,
+    values: Table,
+    /// Source of each leaf value, by dotted key.
+    sources: BTreeMap<String, Source>,
+}
+
+impl Resolved {
+    pub fn source(&self, key: &str) -> Option<Source> {
+        self.sources.get(key).copied()
+    }
+
+    /// One `key = value (source)` line per value, with private keys
+    /// redacted.
+    pub fn dump(&self) -> String {
+        let mut dump = String::new();
+        for (key, source) in &self.sources {
+            let path: Vec<&str> = key.split('.').collect();
+            let Some(value) = get(&self.values, &path) else {
+                continue;
+            };
+            let is_secret = key.ends_with("private_key") && value.as_str() != Some("");
+            let value = match is_secret {
+                true => "<redacted>".to_string(),
+                false => value.to_string(),
+            };
+            writeln!(dump, "{key} = {value} ({source})").unwrap();
+        }
+        dump
+    }
+}
+
+impl Config {
+    /// Resolves the config from the file at `path`, the environment and
+    /// `flags`, which map dotted keys to their value. The profile is
+    /// `explicit` if given, else the one named by `FUSION_PROFILE`.
+    pub fn resolve(
+        path: impl AsRef<Path>,
+        explicit: Option<&str>,
+        flags: Vec<(&str, Value)>,
+    ) -> Result<Resolved, String> {
+        let path = path.as_ref();
+        let file = std::fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read config {}: {e}", path.display()))?;
+        resolve_from(&file, explicit, std::env::vars(), flags)
+    }
+}
+
+pub fn resolve_from(
+    file: &str,
+    explicit: Option<&str>,
+    env: impl IntoIterator<Item = (String, String)>,
+    flags: Vec<(&str, Value)>,
+) -> Result<Resolved, String> {
+    let env: Vec<(String, String)> = env.into_iter().collect();
+    let mut resolved = Resolved {
+        config: Config::default(),
+        values: Table::new(),
+        sources: BTreeMap::new(),
+    };
+
+    let Ok(Value::Table(defaults)) = Value::try_from(Config::default()) else {
+        unreachable!("the config serializes to a table");
+    };
+    resolved.merge(defaults, Source::Default);
+    let file: Table = toml::from_str(file).map_err(|e| format!("Invalid config file: {e}"))?;
+    resolved.merge(file, Source::File);
+
+    let mut overrides = vec![];
+    for (name, raw) in &env {
+        let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        if name == PROFILE_ENV {
+            continue;
+        }
+        let path: Vec<String> = key.to_lowercase().split("__").map(str::to_string).collect();
+        let value = resolved.parse_env(&path, raw, name)?;
+        overrides.push((path, value));
+    }
+    // Profiles defined from the environment must be complete before
+    // one of them is applied.
+    let (profiles, overrides): (Vec<_>, Vec<_>) = overrides
+        .into_iter()
+        .partition(|(path, _)| path[0] == "profiles");
+    for (path, value) in profiles {
+        resolved.set(&path, value, Source::Env);
+    }
+
+    let env_profile = env
+        .iter()
+        .find(|(name, _)| name == PROFILE_ENV)
+        .map(|(_, value)| value.clone());
+    let profile = profile_name(explicit, env_profile);
+    if let Some(name) = &profile {
+        resolved.apply_profile(name)?;
+    }
+
+    for (path, value) in overrides {
+        resolved.set(&path, value, Source::Env);
+    }
+    for (key, value) in flags {
+        let path: Vec<String> = key.split('.').map(str::to_string).collect();
+        resolved.set(&path, value, Source::Flag);
+    }
+
+    resolved.config = Value::Table(resolved.values.clone())
+        .try_into()
+        .map_err(|e| format!("Invalid config: {e}"))?;
+    resolved.config.profile = profile;
+    Ok(resolved)
+}
+
+/// The `--profile` flag wins over the environment.
+fn profile_name(explicit: Option<&str>, env: Option<String>) -> Option<String> {
+    explicit
+        .map(str::to_string)
+        .or(env.filter(|name| !name.is_empty()))
+}
+
+fn get<'a, S: AsRef<str>>(table: &'a Table, path: &[S]) -> Option<&'a Value> {
+    let (last, parents) = path.split_last()?;
+    let mut table = table;
+    for key in parents {
+        table = table.get(key.as_ref())?.as_table()?;
+    }
+    table.get(last.as_ref())
+}
+
+impl Resolved {
+    fn merge(&mut self, table: Table, source: Source) {
+        for (key, value) in table {
+            self.merge_value(vec![key], value, source);
+        }
+    }
+
+    fn merge_value(&mut self, path: Vec<String>, value: Value, source: Source) {
+        match value {
+            Value::Table(table) => {
+                for (key, value) in table {
+                    let mut path = path.clone();
+                    path.push(key);
+                    self.merge_value(path, value, source);
+                }
+            }
+            value => self.set(&path, value, source),
+        }
+    }
+
+    fn set(&mut self, path: &[String], value: Value, source: Source) {
+        let (last, parents) = path.split_last().expect("keys are not empty");
+        let mut table = &mut self.values;
+        for key in parents {
+            let entry = table
+                .entry(key.clone())
+                .or_insert_with(|| Value::Table(Table::new()));
+            if !entry.is_table() {
+                *entry = Value::Table(Table::new());
+            }
+            table = entry.as_table_mut().unwrap();
+        }
+        table.insert(last.clone(), value);
+        self.sources.insert(path.join("."), source);
+    }
+
+    fn apply_profile(&mut self, name: &str) -> Result<(), String> {
+        let profiles = self.values.get("profiles").and_then(Value::as_table);
+        let Some(profile) = profiles.and_then(|p| p.get(name)).and_then(Value::as_table) else {
+            let available: Vec<_> = profiles
+                .map(|p| p.keys().map(String::as_str).collect())
+                .unwrap_or_default();
+            return Err(format!(
+                "Unknown profile `{name}`, available profiles: {}",
+                match available.is_empty() {
+                    true => "none".to_string(),
+                    false => available.join(", "),
+                }
+            ));
+        };
+        for (key, value) in profile.clone() {
+            self.set(&[key], value, Source::Profile);
+        }
+        Ok(())
+    }
+
+    /// Parses the value of the environment variable `name` for the key at
+    /// `path`, following the type of the key. Profile keys have the type
+    /// of the top-level key they override.
+    fn parse_env(&self, path: &[String], raw: &str, name: &str) -> Result<Value, String> {
+        let template = match path {
+            [profiles, _, key] if profiles == "profiles" => get(&self.values, &[key]),
+            _ => get(&self.values, path),
+        };
+        match template {
+            None => Err(format!(
+                "Unknown config key `{}` set by {name}",
+                path.join(".")
+            )),
+            Some(Value::String(_)) => Ok(Value::String(raw.to_string())),
+            Some(_) => toml::from_str::<Table>(&format!("value = {raw}"))
+                .ok()
+                .and_then(|mut table| table.remove("value"))
+                .ok_or_else(|| format!("Invalid value for {name}: `{raw}`")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CONFIG: &str = r#"
+eth_rpc_url = "http://localhost:8545"
+eth_private_key = "0x0123"
+socket_port = 38171
+
+[profiles.local]
+socket_port = 4000
+"#;
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn precedence() {
+        let resolved = resolve_from(CONFIG, None, vec![], vec![]).unwrap();
+        assert_eq!(resolved.config.socket_port, 38171);
+        assert_eq!(resolved.source("socket_port"), Some(Source::File));
+        assert_eq!(resolved.source("reveal_window"), Some(Source::Default));
+
+        let resolved = resolve_from(CONFIG, Some("local"), vec![], vec![]).unwrap();
+        assert_eq!(resolved.config.socket_port, 4000);
+        assert_eq!(resolved.source("socket_port"), Some(Source::Profile));
+
+        let vars = env(&[("FUSION_SOCKET_PORT", "5000")]);
+        let resolved = resolve_from(CONFIG, Some("local"), vars.clone(), vec![]).unwrap();
+        assert_eq!(resolved.config.socket_port, 5000);
+        assert_eq!(resolved.source("socket_port"), Some(Source::Env));
+
+        let flags = vec![("socket_port", Value::Integer(6000))];
+        let resolved = resolve_from(CONFIG, Some("local"), vars, flags).unwrap();
+        assert_eq!(resolved.config.socket_port, 6000);
+        assert_eq!(resolved.source("socket_port"), Some(Source::Flag));
+    }
+
+    #[test]
+    fn nested_keys() {
+        let vars = env(&[
+            ("FUSION_PROFILES__LOCAL__ETH_RPC_URL", "http://node:8545"),
+            ("FUSION_PROFILES__TESTNET__SOCKET_PORT", "4001"),
+            ("FUSION_PROFILE", "local"),
+        ]);
+        let resolved = resolve_from(CONFIG, None, vars, vec![]).unwrap();
+        assert_eq!(resolved.config.profile.as_deref(), Some("local"));
+        assert_eq!(resolved.config.eth_rpc_url, "http://node:8545");
+        assert_eq!(resolved.config.socket_port, 4000);
+        assert_eq!(resolved.config.profiles["testnet"].socket_port, Some(4001));
+        assert_eq!(
+            resolved.source("profiles.local.eth_rpc_url"),
+            Some(Source::Env)
+        );
+
+        let vars = env(&[("FUSION_PROFILES__LOCAL__BLOCK_SIZE", "2")]);
+        assert!(resolve_from(CONFIG, None, vars, vec![])
+            .err()
+            .unwrap()
+            .contains("Unknown config key `profiles.local.block_size`"));
+    }
+
+    #[test]
+    fn values_follow_key_types() {
+        let vars = env(&[
+            ("FUSION_DEV", "true"),
+            ("FUSION_ORDERING", "random"),
+            // A string key is never parsed, even if it looks like a number.
+            ("FUSION_ETH_PRIVATE_KEY", "0x42"),
+            (
+                "FUSION_FUSION_L1_CONTRACT",
+                "0x5fbdb2315678afecb367f032d93f642f64180aa3",
+            ),
+        ]);
+        let resolved = resolve_from(CONFIG, None, vars, vec![]).unwrap();
+        assert!(resolved.config.dev);
+        assert_eq!(resolved.config.ordering, fusion_api::OrderingPolicy::Random);
+        assert_eq!(resolved.config.eth_private_key, "0x42");
+        assert_eq!(
+            resolved.config.fusion_l1_contract,
+            "0x5fbdb2315678afecb367f032d93f642f64180aa3"
+                .parse()
+                .unwrap()
+        );
+
+        for (name, value) in [
+            ("FUSION_SOCKET_PORT", "high"),
+            ("FUSION_DEV_ACCOUNTS", "-1"),
+        ] {
+            assert!(resolve_from(CONFIG, None, env(&[(name, value)]), vec![]).is_err());
+        }
+        assert!(resolve_from(CONFIG, None, env(&[("FUSION_PORT", "1")]), vec![]).is_err());
+    }
+
+    #[test]
+    fn lists() {
+        let file = "[profiles.local]\nsocket_port = 4000\n";
+        let mut resolved = resolve_from(file, None, vec![], vec![]).unwrap();
+        resolved.set(&["peers".to_string()], Value::Array(vec![]), Source::File);
+        let value = resolved
+            .parse_env(&["peers".to_string()], "[\"a:1\", \"b:2\"]", "FUSION_PEERS")
+            .unwrap();
+        assert_eq!(
+            value,
+            Value::Array(vec![Value::from("a:1"), Value::from("b:2")])
+        );
+        assert!(resolved
+            .parse_env(&["peers".to_string()], "[\"a:1\"", "FUSION_PEERS")
+            .is_err());
+    }
+
+    #[test]
+    fn dump_redacts_secrets() {
+        let vars = env(&[("FUSION_ATTESTATION_PRIVATE_KEY", "0x99")]);
+        let dump = resolve_from(CONFIG, None, vars, vec![("dev", true.into())])
+            .unwrap()
+            .dump();
+        assert!(dump.contains("eth_private_key = <redacted> (file)\n"));
+        assert!(dump.contains("attestation_private_key = <redacted> (env)\n"));
+        assert!(dump.contains("dev = true (flag)\n"));
+        assert!(dump.contains("socket_port = 38171 (file)\n"));
+        assert!(dump.contains("reveal_window = 10 (default)\n"));
+        assert!(!dump.contains("0x0123") && !dump.contains("0x99"));
+    }
+
+    #[test]
+    fn flag_wins_over_env() {
+        assert_eq!(
+            profile_name(Some("local"), Some("testnet".to_string())),
+            Some("local".to_string())
+        );
+        assert_eq!(
+            profile_name(None, Some("testnet".to_string())),
+            Some("testnet".to_string())
+        );
+        assert_eq!(profile_name(None, Some(String::new())), None);
+        assert_eq!(profile_name(None, None), None);
+    }
+}
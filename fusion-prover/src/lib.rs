@@ -1,6 +1,9 @@
 pub mod merkle_tree;
+pub mod offline;
+pub mod params;
 mod poseidon_hasher;
 pub mod state;
+pub mod test_vectors;
 
 use crate::merkle_tree::ToBitmap;
 use crate::state::{Account, State};
@@ -21,6 +24,8 @@ use serde_tuple::*;
 use rand_0_8::rngs::StdRng;
 use rand_0_8::SeedableRng;
 
+use rayon::prelude::*;
+
 use zokrates_abi::{parse_strict, Encode, Inputs};
 use zokrates_ark::Ark;
 use zokrates_ast::ir::{self, Parameter, ProgEnum, Solver, Witness};
@@ -32,24 +37,29 @@ use std::borrow::Borrow;
 use std::fs::File;
 use std::io::BufReader;
 use std::path::Path;
+use std::time::{Duration, Instant};
 
-#[derive(Serialize_tuple, Deserialize_tuple, Debug)]
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
 pub struct CircuitInput {
     pre_root: U256,
     post_root: U256,
     tx: CircuitTx,
     pre_accounts: Vec<Account>,
+    /// 0 for a transaction signed without one.
+    chain_id: U256,
+    /// 0 for the native token.
+    token: U256,
     direction_selector: Vec<Vec<bool>>,
     pre_path: Vec<Vec<U256>>,
     post_path: Vec<Vec<U256>>,
 }
 
 impl CircuitInput {
-    pub fn new(tx: &SignedTx, pre_state: &State, post_state: &State) -> Self {
-        let circuit_tx = tx.to_circuit_tx();
+    pub fn new(tx: &SignedTx, pre_state: &State, post_state: &State) -> Result<Self, String> {
+        let circuit_tx = tx.to_circuit_tx()?;
 
-        let sender_addr = PublicKey(circuit_tx.sender.clone()).address();
-        let to_addr = PublicKey(circuit_tx.to.clone()).address();
+        let sender_addr = tx.tx.sender_account();
+        let to_addr = tx.tx.to_account();
 
         let pre_account_from = pre_state.get(&sender_addr);
         let pre_account_to = match tx.tx.kind {
@@ -61,10 +71,12 @@ impl CircuitInput {
             _ => pre_state.get(&to_addr),
         };
 
-        Self {
+        Ok(Self {
             pre_root: pre_state.root(),
             tx: circuit_tx,
             pre_accounts: vec![pre_account_from, pre_account_to],
+            chain_id: tx.tx.chain_id.unwrap_or(0).into(),
+            token: token_input(tx.tx.token),
             post_root: post_state.root(),
             direction_selector: vec![
                 sender_addr.to_bitmap().to_vec_bool(),
@@ -72,7 +84,51 @@ impl CircuitInput {
             ],
             pre_path: vec![pre_state.proof(&sender_addr), pre_state.proof(&to_addr)],
             post_path: vec![post_state.proof(&sender_addr), post_state.proof(&to_addr)],
-        }
+        })
+    }
+
+    /// The inputs of every transaction of a block, where `states` are
+    /// the state before the block followed by the state after each
+    /// transaction. Applying the transactions is serial, but once the
+    /// states are known each input only reads the two around its
+    /// transaction, so they are built in parallel. A transaction whose
+    /// inputs cannot be built does not stop the others.
+    pub fn batch(txs: &[SignedTx], states: &[State]) -> Vec<Result<Self, String>> {
+        assert_eq!(states.len(), txs.len() + 1);
+        txs.par_iter()
+            .zip(states.par_windows(2))
+            .map(|(tx, states)| Self::new(tx, &states[0], &states[1]))
+            .collect()
+    }
+
+    /// The public inputs of the proof, see `Fusion.sol` for the layout.
+    pub fn public_inputs(&self) -> [U256; PUBLIC_INPUTS] {
+        let tx = &self.tx;
+        let [sender, to] = [&self.pre_accounts[0], &self.pre_accounts[1]];
+        [
+            self.pre_root,
+            self.post_root,
+            tx.kind,
+            tx.sender.x,
+            tx.sender.y,
+            tx.to.x,
+            tx.to.y,
+            tx.nonce,
+            tx.value,
+            tx.not_before,
+            tx.not_after,
+            tx.sig.r.x,
+            tx.sig.r.y,
+            tx.sig.s,
+            sender.id,
+            sender.balance,
+            sender.nonce,
+            to.id,
+            to.balance,
+            to.nonce,
+            self.chain_id,
+            self.token,
+        ]
     }
 }
 
@@ -97,25 +153,29 @@ pub struct CircuitTx {
     to: Point,
     nonce: U256,
     value: U256,
+    not_before: U256,
+    not_after: U256,
     sig: CircuitTxSignature,
 }
 
 trait ToCircuitTx {
-    fn to_circuit_tx(&self) -> CircuitTx;
+    fn to_circuit_tx(&self) -> Result<CircuitTx, String>;
 }
 
 impl ToCircuitTx for fusion_api::SignedTx {
-    fn to_circuit_tx(&self) -> CircuitTx {
+    fn to_circuit_tx(&self) -> Result<CircuitTx, String> {
         let sender_pk: PublicKey = self.tx.sender.into();
         let to_pk: PublicKey = self.tx.to.into();
-        CircuitTx {
+        Ok(CircuitTx {
             kind: self.tx.kind.to_u256(),
             sender: sender_pk.0,
             to: to_pk.0,
             nonce: self.tx.nonce,
             value: self.tx.value,
-            sig: self.clone().into(),
-        }
+            not_before: self.tx.not_before.unwrap_or(0).into(),
+            not_after: self.tx.not_after.unwrap_or(0).into(),
+            sig: self.try_into()?,
+        })
     }
 }
 
@@ -125,16 +185,25 @@ struct CircuitTxSignature {
     s: U256,
 }
 
-impl From<fusion_api::SignedTx> for CircuitTxSignature {
-    fn from(tx: fusion_api::SignedTx) -> CircuitTxSignature {
-        let sig = tx.signature.to_babyjubjub_signature();
-        CircuitTxSignature {
+impl TryFrom<&fusion_api::SignedTx> for CircuitTxSignature {
+    type Error = String;
+
+    fn try_from(tx: &fusion_api::SignedTx) -> Result<CircuitTxSignature, String> {
+        let sig = tx.signature.to_babyjubjub_signature()?;
+        Ok(CircuitTxSignature {
             r: Point::from_babyjubjub_point(&sig.r_b8),
             s: sig.s.to_u256(),
-        }
+        })
     }
 }
 
+/// Time spent in each stage of a proof.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ProvingTimings {
+    pub witness: Duration,
+    pub proof: Duration,
+}
+
 pub struct Prover;
 
 impl Prover {
@@ -143,7 +212,26 @@ impl Prover {
         tx: &fusion_api::SignedTx,
         pre_state: &State,
         post_state: &State,
-    ) -> Result<fusion::TxProof, String> {
+    ) -> Result<(fusion::TxProof, ProvingTimings), String> {
+        Self::prove_for_l1(config, &CircuitInput::new(tx, pre_state, post_state)?)
+    }
+
+    /// Proves `inputs` in the encoding the contract receives.
+    pub fn prove_for_l1(
+        config: &Config,
+        inputs: &CircuitInput,
+    ) -> Result<(fusion::TxProof, ProvingTimings), String> {
+        let (proof, timings) = Self::prove_input(config, inputs, &config.proving_key_path)?;
+        Ok((proof.to_fusion_l1_tx(), timings))
+    }
+
+    /// Proves `inputs` with the proving key at `proving_key_path`.
+    pub fn prove_input(
+        config: &Config,
+        inputs: &CircuitInput,
+        proving_key_path: &str,
+    ) -> Result<(Proof<Bn128Field, G16>, ProvingTimings), String> {
+        let start = Instant::now();
         let path = Path::new(&config.circuit_path);
         let file = File::open(path)
             .map_err(|why| format!("Could not open {}: {}", path.display(), why))?;
@@ -161,12 +249,12 @@ impl Prover {
             prog.statements.iter(),
             &prog.arguments,
             &prog.solvers,
-            tx,
-            pre_state,
-            post_state,
+            inputs,
         )?;
+        let witness_time = start.elapsed();
 
-        let pk_path = Path::new(&config.proving_key_path);
+        let start = Instant::now();
+        let pk_path = Path::new(proving_key_path);
         let pk_file = File::open(pk_path)
             .map_err(|why| format!("Could not open {}: {}", pk_path.display(), why))?;
 
@@ -174,7 +262,6 @@ impl Prover {
 
         let mut rng = StdRng::from_entropy();
         let proof: Proof<Bn128Field, G16> = Ark::generate_proof(prog, witness, pk_reader, &mut rng);
-        let ret = proof.to_fusion_l1_tx();
 
         /*
         let proof = serde_json::to_string_pretty(&TaggedProof::<Bn128Field, G16>::new(
@@ -185,7 +272,39 @@ impl Prover {
         println!("Proof:\n{proof}");
         */
 
-        Ok(ret)
+        let timings = ProvingTimings {
+            witness: witness_time,
+            proof: start.elapsed(),
+        };
+        Ok((proof, timings))
+    }
+
+    /// Builds the witness of each of `inputs` without proving them.
+    /// Returns the time it took, loading the circuit aside.
+    pub fn compute_witnesses(config: &Config, inputs: &[CircuitInput]) -> Result<Duration, String> {
+        let path = Path::new(&config.circuit_path);
+        let file = File::open(path)
+            .map_err(|why| format!("Could not open {}: {}", path.display(), why))?;
+
+        let mut reader = BufReader::new(file);
+
+        let prog = match ProgEnum::deserialize(&mut reader).unwrap() {
+            ProgEnum::Bn128Program(p) => p,
+            _ => panic!(),
+        };
+        let prog = prog.collect();
+
+        let start = Instant::now();
+        for inputs in inputs {
+            Self::compute_witness(
+                config,
+                prog.statements.iter(),
+                &prog.arguments,
+                &prog.solvers,
+                inputs,
+            )?;
+        }
+        Ok(start.elapsed())
     }
 
     fn compute_witness<'a, S: Borrow<ir::Statement<'a, Bn128Field>>>(
@@ -193,9 +312,7 @@ impl Prover {
         statements: impl Iterator<Item = S>,
         arguments: &[Parameter],
         solvers: &[Solver<'a, Bn128Field>],
-        tx: &SignedTx,
-        pre_state: &State,
-        post_state: &State,
+        inputs: &CircuitInput,
     ) -> Result<Witness<Bn128Field>, String> {
         let signature = {
             let path = Path::new(&config.circuit_abi_path);
@@ -208,11 +325,10 @@ impl Prover {
             abi.signature()
         };
 
-        let inputs = CircuitInput::new(tx, pre_state, post_state);
         //println!("\n\n{}\n\n", serde_json::to_string(&inputs).unwrap());
 
         let witness = parse_strict(
-            serde_json::to_string(&inputs).unwrap().as_str(),
+            serde_json::to_string(inputs).unwrap().as_str(),
             signature.inputs,
         )
         .map(Inputs::Abi)
@@ -247,10 +363,13 @@ impl Prover {
     }
 }
 
+/// Number of public inputs of the circuit, see `Fusion.sol` for the layout.
+pub const PUBLIC_INPUTS: usize = 22;
+
 trait ToFusionL1 {
     fn to_fusion_l1_tx(&self) -> fusion::TxProof;
     fn to_fusion_l1_proof(&self) -> fusion::Proof;
-    fn to_fusion_l1_input(&self) -> [U256; 18usize];
+    fn to_fusion_l1_input(&self) -> [U256; PUBLIC_INPUTS];
 }
 
 impl ToFusionL1 for Proof<Bn128Field, G16> {
@@ -287,8 +406,8 @@ impl ToFusionL1 for Proof<Bn128Field, G16> {
         }
     }
 
-    fn to_fusion_l1_input(&self) -> [U256; 18usize] {
-        assert_eq!(self.inputs.len(), 18);
+    fn to_fusion_l1_input(&self) -> [U256; PUBLIC_INPUTS] {
+        assert_eq!(self.inputs.len(), PUBLIC_INPUTS);
         self.inputs
             .iter()
             .map(|x| U256::from_str_radix(&x[2..], 16).unwrap())
@@ -297,3 +416,89 @@ impl ToFusionL1 for Proof<Bn128Field, G16> {
             .unwrap()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand_0_8::Rng;
+
+    /// A random block of transfers between `accounts` funded ones, with
+    /// the states they go through.
+    fn random_block(accounts: usize, txs: usize) -> (Vec<SignedTx>, Vec<State>) {
+        let mut rng = rand_0_8::thread_rng();
+        let keys: Vec<_> = (0..accounts)
+            .map(|_| fusion_wallet::new_key_pair())
+            .collect();
+        let mut state = State::default();
+        for (_, pk) in &keys {
+            state.update(
+                &pk.address(),
+                Account::new(pk.address(), 1000.into(), 0.into()),
+            );
+        }
+
+        let mut states = vec![state.clone()];
+        let mut signed_txs = vec![];
+        for _ in 0..txs {
+            let (sk, sender) = &keys[rng.gen_range(0..accounts)];
+            let (_, to) = &keys[rng.gen_range(0..accounts)];
+            let sender_account = state.get(&sender.address());
+            let tx = Tx {
+                kind: TxKind::Transfer,
+                sender: sender.to_u256(),
+                to: to.to_u256(),
+                nonce: sender_account.nonce + 1,
+                value: rng.gen_range(0..10u64).into(),
+                not_before: None,
+                not_after: None,
+                chain_id: None,
+                token: NATIVE_TOKEN,
+            };
+            state.update(
+                &sender.address(),
+                Account::new(
+                    sender.address(),
+                    sender_account.balance - tx.value,
+                    tx.nonce,
+                ),
+            );
+            let to_account = state.get(&to.address());
+            state.update(
+                &to.address(),
+                Account::new(
+                    to.address(),
+                    to_account.balance + tx.value,
+                    to_account.nonce,
+                ),
+            );
+            let signature = fusion_wallet::sign(&tx, sk.to_string())
+                .unwrap()
+                .to_string();
+            signed_txs.push(SignedTx { tx, signature });
+            states.push(state.clone());
+        }
+        (signed_txs, states)
+    }
+
+    #[test]
+    fn batch_matches_serial() {
+        for (accounts, txs) in [(1, 1), (2, 5), (5, 20), (20, 16)] {
+            let (txs, states) = random_block(accounts, txs);
+            let serial: Vec<_> = txs
+                .iter()
+                .zip(states.windows(2))
+                .map(|(tx, states)| CircuitInput::new(tx, &states[0], &states[1]).unwrap())
+                .collect();
+            assert_eq!(
+                serde_json::to_vec(
+                    &CircuitInput::batch(&txs, &states)
+                        .into_iter()
+                        .collect::<Result<Vec<_>, _>>()
+                        .unwrap()
+                )
+                .unwrap(),
+                serde_json::to_vec(&serial).unwrap()
+            );
+        }
+    }
+}
@@ -111,6 +111,11 @@ impl<H: Hasher + Default, T: Value + Clone + Default> MerkleTree<H, T> {
         self.leaves.get(key)
     }
 
+    /// The leaves set, by key.
+    pub fn leaves(&self) -> impl Iterator<Item = (&U256, &T)> {
+        self.leaves.iter()
+    }
+
     pub fn proof(&self, key: &U256) -> Vec<U256> {
         BranchKey::for_leaf(key)
             .path_to_root()
@@ -138,8 +143,13 @@ impl<H: Hasher + Default, T: Value + Clone + Default> MerkleTree<H, T> {
     }
 
     pub fn verify_proof(root_hash: &U256, key: &U256, value: &T, proof: &[U256]) -> bool {
+        Self::root_from_proof(key, value, proof).as_ref() == Some(root_hash)
+    }
+
+    /// The root of the tree holding `value` at `key` with siblings `proof`.
+    pub fn root_from_proof(key: &U256, value: &T, proof: &[U256]) -> Option<U256> {
         if proof.len() != 256 {
-            return false;
+            return None;
         }
         let mut hash = Self::leaf_hash(key, value);
         for (proof_item, path_item) in proof.iter().zip(BranchKey::for_leaf(key).path_to_root()) {
@@ -149,7 +159,7 @@ impl<H: Hasher + Default, T: Value + Clone + Default> MerkleTree<H, T> {
                 Self::merge_hashes(proof_item, &hash)
             };
         }
-        hash == *root_hash
+        Some(hash)
     }
 
     fn update_parents(&mut self, branch_key: &BranchKey) {
@@ -190,7 +200,7 @@ impl<H: Hasher + Default, T: Value + Clone + Default> MerkleTree<H, T> {
 
     /// Hashes the key and the value together. Returns zero if the value is zero
     /// (but hashes even if the key is zero).
-    fn leaf_hash(key: &U256, value: &T) -> U256 {
+    pub(crate) fn leaf_hash(key: &U256, value: &T) -> U256 {
         let value = value.to_u256();
         if value.is_zero() {
             0.into()
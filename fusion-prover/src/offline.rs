@@ -0,0 +1,348 @@
+//! Proving outside the sequencer, to debug the circuit and the prover.
+//!
+//! The input is the JSON the sequencer passes to the circuit for one
+//! transaction, so a proof made from it has the same public inputs as
+//! the one the sequencer would submit.
+//!
+//! Proofs are verified locally either in the ZoKrates format or in the
+//! ABI encoding the contract receives.
+
+use ethers_core::abi::{AbiDecode, AbiEncode};
+use ethers_core::types::{Bytes, U256};
+use ethers_core::utils::hex;
+use std::path::Path;
+
+use fusion_config::Config;
+use fusion_l1::fusion;
+use zokrates_ark::Ark;
+use zokrates_field::Bn128Field;
+use zokrates_proof_systems::{Backend, Proof, Scheme, TaggedProof, G16};
+
+use crate::{CircuitInput, Prover, ProvingTimings, ToFusionL1, PUBLIC_INPUTS};
+
+/// Names of the public inputs, see `Fusion.sol` for the layout.
+pub const PUBLIC_INPUT_NAMES: [&str; PUBLIC_INPUTS] = [
+    "preRoot",
+    "postRoot",
+    "tx.kind",
+    "tx.sender.x",
+    "tx.sender.y",
+    "tx.to.x",
+    "tx.to.y",
+    "tx.nonce",
+    "tx.value",
+    "tx.not_before",
+    "tx.not_after",
+    "tx.sig.r.x",
+    "tx.sig.r.y",
+    "tx.sig.s",
+    "sender.id",
+    "sender.balance",
+    "sender.nonce",
+    "to.id",
+    "to.balance",
+    "to.nonce",
+    "chainId",
+    "token",
+];
+
+pub struct ProveStats {
+    pub timings: ProvingTimings,
+    /// Peak resident memory of the process in kB, where available.
+    pub peak_memory: Option<u64>,
+}
+
+pub fn read_input(path: &Path) -> Result<CircuitInput, String> {
+    let json = std::fs::read_to_string(path)
+        .map_err(|why| format!("Could not read {}: {why}", path.display()))?;
+    serde_json::from_str(&json).map_err(|why| format!("Invalid input {}: {why}", path.display()))
+}
+
+/// Proves the input at `input_path` and writes the proof to `output`:
+/// `proof.json` in the ZoKrates format, `inputs.json` with the public
+/// inputs, and `tx_proof.hex` with the ABI encoding the contract receives.
+pub fn prove_file(
+    config: &Config,
+    input_path: &Path,
+    proving_key_path: &str,
+    output: &Path,
+) -> Result<ProveStats, String> {
+    let inputs = read_input(input_path)?;
+    let (proof, timings) = Prover::prove_input(config, &inputs, proving_key_path)?;
+    write_proof(proof, output)?;
+    Ok(ProveStats {
+        timings,
+        peak_memory: peak_memory(),
+    })
+}
+
+fn write_proof(proof: Proof<Bn128Field, G16>, output: &Path) -> Result<(), String> {
+    let write = |name: &str, contents: String| {
+        let path = output.join(name);
+        std::fs::write(&path, contents + "\n")
+            .map_err(|why| format!("Could not write {}: {why}", path.display()))
+    };
+    std::fs::create_dir_all(output)
+        .map_err(|why| format!("Could not create {}: {why}", output.display()))?;
+
+    write(
+        "tx_proof.hex",
+        Bytes::from(proof.to_fusion_l1_tx().encode()).to_string(),
+    )?;
+    write(
+        "inputs.json",
+        serde_json::to_string_pretty(&proof.inputs).unwrap(),
+    )?;
+    let tagged = TaggedProof::<Bn128Field, G16>::new(proof.proof, proof.inputs);
+    write("proof.json", serde_json::to_string_pretty(&tagged).unwrap())
+}
+
+fn read(path: &Path) -> Result<String, String> {
+    std::fs::read_to_string(path).map_err(|why| format!("Could not read {}: {why}", path.display()))
+}
+
+fn to_hex(value: &U256) -> String {
+    let mut bytes = [0u8; 32];
+    value.to_big_endian(&mut bytes);
+    format!("0x{}", hex::encode(bytes))
+}
+
+fn parse_input(input: &str) -> Result<U256, String> {
+    match input.strip_prefix("0x") {
+        Some(hex) => U256::from_str_radix(hex, 16),
+        None => U256::from_dec_str(input),
+    }
+    .map_err(|_| format!("Invalid public input `{input}`"))
+}
+
+/// Reads a proof, either `proof.json` as written by ZoKrates or the hex
+/// ABI encoding of a `TxProof`, as submitted to the contract.
+pub fn read_proof(path: &Path) -> Result<Proof<Bn128Field, G16>, String> {
+    let contents = read(path)?;
+    let contents = contents.trim();
+    let invalid = |why: String| format!("Invalid proof {}: {why}", path.display());
+    let Some(encoded) = contents.strip_prefix("0x") else {
+        return serde_json::from_str(contents).map_err(|why| invalid(why.to_string()));
+    };
+
+    let bytes = hex::decode(encoded).map_err(|why| invalid(why.to_string()))?;
+    let tx = fusion::TxProof::decode(bytes).map_err(|why| invalid(why.to_string()))?;
+    let (a, b, c) = (&tx.proof.a, &tx.proof.b, &tx.proof.c);
+    let json = serde_json::json!({
+        "proof": {
+            "a": [to_hex(&a.x), to_hex(&a.y)],
+            "b": [
+                [to_hex(&b.x[0]), to_hex(&b.x[1])],
+                [to_hex(&b.y[0]), to_hex(&b.y[1])],
+            ],
+            "c": [to_hex(&c.x), to_hex(&c.y)],
+        },
+        "inputs": tx.input.iter().map(to_hex).collect::<Vec<_>>(),
+    });
+    serde_json::from_value(json).map_err(|why| invalid(why.to_string()))
+}
+
+/// Reads public inputs written as a JSON array of hex or decimal strings.
+pub fn read_inputs(path: &Path) -> Result<Vec<U256>, String> {
+    let inputs: Vec<String> = serde_json::from_str(&read(path)?)
+        .map_err(|why| format!("Invalid inputs {}: {why}", path.display()))?;
+    inputs.iter().map(|input| parse_input(input)).collect()
+}
+
+/// Verifies the proof at `proof_path` with the verification key at
+/// `vk_path`. If `inputs_path` is given, the proof must also be for
+/// these public inputs, and the ones that differ are reported.
+pub fn verify_file(
+    proof_path: &Path,
+    vk_path: &Path,
+    inputs_path: Option<&Path>,
+) -> Result<(), String> {
+    let proof = read_proof(proof_path)?;
+    let vk: <G16 as Scheme<Bn128Field>>::VerificationKey = serde_json::from_str(&read(vk_path)?)
+        .map_err(|why| format!("Invalid verification key {}: {why}", vk_path.display()))?;
+
+    if let Some(inputs_path) = inputs_path {
+        let expected = read_inputs(inputs_path)?;
+        let actual = proof
+            .inputs
+            .iter()
+            .map(|input| parse_input(input))
+            .collect::<Result<Vec<_>, _>>()?;
+        if expected.len() != actual.len() {
+            return Err(format!(
+                "The proof has {} public inputs, {} has {}",
+                actual.len(),
+                inputs_path.display(),
+                expected.len()
+            ));
+        }
+        let mismatches: Vec<String> = actual
+            .iter()
+            .zip(&expected)
+            .enumerate()
+            .filter(|(_, (actual, expected))| actual != expected)
+            .map(|(i, (actual, expected))| {
+                format!(
+                    "public input {i} ({}) is {actual} in the proof, {expected} in {}",
+                    PUBLIC_INPUT_NAMES.get(i).unwrap_or(&"unknown"),
+                    inputs_path.display()
+                )
+            })
+            .collect();
+        if !mismatches.is_empty() {
+            return Err(mismatches.join("\n"));
+        }
+    }
+
+    match <Ark as Backend<Bn128Field, G16>>::verify(vk, proof) {
+        true => Ok(()),
+        false => Err(format!(
+            "{} is not a valid proof for its public inputs",
+            proof_path.display()
+        )),
+    }
+}
+
+/// Reads `VmHWM` from `/proc/self/status`.
+pub fn peak_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::state::{Account, State};
+    use ethers_core::types::U256;
+    use fusion_api::{SignedTx, Tx, TxKind, NATIVE_TOKEN};
+    use fusion_types::PublicKey;
+
+    const FIXTURE: &str = "fixtures/transfer.json";
+
+    /// The transaction of the fixture: a transfer of 10 from an account
+    /// holding 100, to an empty account.
+    fn fixture_tx() -> SignedTx {
+        let dec = |s: &str| U256::from_dec_str(s).unwrap();
+        SignedTx {
+            tx: Tx {
+                kind: TxKind::Transfer,
+                sender: dec(
+                    "45305891956831302981909026450015177370389082830806760773597630281115559973890",
+                ),
+                to: dec(
+                    "45176059214598992327895356460447437953435929389677919346120968312420788809516",
+                ),
+                nonce: 1.into(),
+                value: 10.into(),
+                not_before: None,
+                not_after: None,
+                chain_id: None,
+                token: NATIVE_TOKEN,
+            },
+            signature: "153945208018340088305849589795296139931290207829856866629741309280811712897816207558075679608632238468594323215026517125481374541735295515027245331253564".to_string(),
+        }
+    }
+
+    fn fixture_inputs() -> CircuitInput {
+        let tx = fixture_tx();
+        let sender = PublicKey::from(tx.tx.sender).address();
+        let to = PublicKey::from(tx.tx.to).address();
+
+        let mut pre_state = State::default();
+        pre_state.update(&sender, Account::new(sender, 100.into(), 0.into()));
+        let mut post_state = pre_state.clone();
+        post_state.update(&sender, Account::new(sender, 90.into(), 1.into()));
+        post_state.update(&to, Account::new(to, 10.into(), 0.into()));
+
+        CircuitInput::new(&tx, &pre_state, &post_state).unwrap()
+    }
+
+    #[test]
+    fn fixture_matches_the_sequencer() {
+        let fixture = read_input(Path::new(FIXTURE)).unwrap();
+        assert_eq!(
+            serde_json::to_value(&fixture).unwrap(),
+            serde_json::to_value(fixture_inputs()).unwrap()
+        );
+    }
+
+    #[test]
+    fn fixture_signature_is_valid() {
+        fusion_wallet::verify_tx_signature(&fixture_tx()).unwrap();
+    }
+
+    #[test]
+    fn prove_and_verify_fixture() {
+        let config = Config::default();
+        let output = std::env::temp_dir().join(format!("fusion-prove-{}", std::process::id()));
+        prove_file(
+            &config,
+            Path::new(FIXTURE),
+            &config.proving_key_path,
+            &output,
+        )
+        .unwrap();
+
+        let inputs: Vec<String> =
+            serde_json::from_str(&std::fs::read_to_string(output.join("inputs.json")).unwrap())
+                .unwrap();
+        let inputs: Vec<U256> = inputs
+            .iter()
+            .map(|input| U256::from_str_radix(&input[2..], 16).unwrap())
+            .collect();
+        assert_eq!(inputs, fixture_inputs().public_inputs().to_vec());
+
+        // Both formats verify, with and without the expected inputs.
+        let vk = Path::new("../circuits/verification.key");
+        let inputs_path = output.join("inputs.json");
+        for proof in ["proof.json", "tx_proof.hex"] {
+            verify_file(&output.join(proof), vk, None).unwrap();
+            verify_file(&output.join(proof), vk, Some(&inputs_path)).unwrap();
+        }
+
+        // A corrupted proof does not verify.
+        let mut proof: serde_json::Value =
+            serde_json::from_str(&read(&output.join("proof.json")).unwrap()).unwrap();
+        let a = proof["proof"]["a"].take();
+        proof["proof"]["a"] = proof["proof"]["c"].take();
+        proof["proof"]["c"] = a;
+        let corrupted = output.join("corrupted.json");
+        std::fs::write(&corrupted, proof.to_string()).unwrap();
+        assert!(verify_file(&corrupted, vk, None)
+            .unwrap_err()
+            .contains("not a valid proof"));
+
+        // Inputs that disagree with the proof are named.
+        let mut other_inputs = inputs.clone();
+        other_inputs[1] += U256::one();
+        let other_inputs: Vec<String> = other_inputs.iter().map(to_hex).collect();
+        let other_inputs_path = output.join("other_inputs.json");
+        std::fs::write(
+            &other_inputs_path,
+            serde_json::to_string(&other_inputs).unwrap(),
+        )
+        .unwrap();
+        let error =
+            verify_file(&output.join("proof.json"), vk, Some(&other_inputs_path)).unwrap_err();
+        assert!(error.contains("public input 1 (postRoot)"), "{error}");
+        assert_eq!(error.lines().count(), 1);
+
+        std::fs::remove_dir_all(output).unwrap();
+    }
+
+    #[test]
+    fn public_inputs() {
+        for input in ["0x0a", "10"] {
+            assert_eq!(parse_input(input), Ok(10.into()));
+        }
+        assert!(parse_input("ten").is_err());
+        assert_eq!(to_hex(&10.into()), format!("0x{}0a", "0".repeat(62)));
+    }
+}
@@ -0,0 +1,91 @@
+//! The parameters a proof depends on. The verifier deployed with the
+//! contract only accepts proofs made with the same ones, so they are
+//! summarized in a single identifier the contract can expose.
+
+use ethers_core::abi::{encode, Token};
+use ethers_core::types::{H256, U256};
+use ethers_core::utils::keccak256;
+
+use crate::PUBLIC_INPUTS;
+
+/// Depth of the state tree.
+pub const TREE_DEPTH: u64 = 256;
+
+/// Transactions per block, `BLOCK_SIZE` in `Fusion.sol`.
+pub const BLOCK_SIZE: u64 = 1;
+
+/// The hash used by the state tree and the transaction hash.
+pub const HASHER: &str = "poseidon";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CircuitParams {
+    pub tree_depth: u64,
+    pub block_size: u64,
+    pub public_inputs: u64,
+    pub hasher: String,
+    /// Hash of the proving key, which identifies the verifying key.
+    pub key_hash: H256,
+}
+
+impl CircuitParams {
+    /// The parameters of the circuit whose proving key is at `proving_key_path`.
+    pub fn from_proving_key(proving_key_path: &str) -> Result<Self, String> {
+        let key = std::fs::read(proving_key_path)
+            .map_err(|why| format!("Could not read {proving_key_path}: {why}"))?;
+        Ok(Self::with_key_hash(keccak256(key).into()))
+    }
+
+    pub fn with_key_hash(key_hash: H256) -> Self {
+        Self {
+            tree_depth: TREE_DEPTH,
+            block_size: BLOCK_SIZE,
+            public_inputs: PUBLIC_INPUTS as u64,
+            hasher: HASHER.to_string(),
+            key_hash,
+        }
+    }
+
+    /// `keccak256(abi.encode(treeDepth, blockSize, publicInputs, hasher, keyHash))`.
+    pub fn id(&self) -> H256 {
+        keccak256(encode(&[
+            Token::Uint(U256::from(self.tree_depth)),
+            Token::Uint(U256::from(self.block_size)),
+            Token::Uint(U256::from(self.public_inputs)),
+            Token::String(self.hasher.clone()),
+            Token::FixedBytes(self.key_hash.as_bytes().to_vec()),
+        ]))
+        .into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn id_depends_on_every_param() {
+        let params = CircuitParams::with_key_hash(H256::repeat_byte(1));
+        let changed = [
+            CircuitParams {
+                tree_depth: 32,
+                ..params.clone()
+            },
+            CircuitParams {
+                block_size: 2,
+                ..params.clone()
+            },
+            CircuitParams {
+                hasher: "mimc".to_string(),
+                ..params.clone()
+            },
+            CircuitParams::with_key_hash(H256::repeat_byte(2)),
+        ];
+        for other in changed {
+            assert_ne!(params.id(), other.id(), "{other:?}");
+        }
+        assert_eq!(
+            params.id(),
+            CircuitParams::with_key_hash(H256::repeat_byte(1)).id()
+        );
+    }
+}
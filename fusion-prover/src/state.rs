@@ -4,7 +4,7 @@ use crate::poseidon_hasher::{poseidon, PoseidonHasher};
 use ethers_core::types::U256;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq, Eq)]
 pub struct Account {
     pub id: U256,
     pub balance: U256,
@@ -38,9 +38,31 @@ impl State {
         self.inner.proof(key)
     }
 
+    /// The accounts set, by key.
+    pub fn accounts(&self) -> impl Iterator<Item = (&U256, &Account)> {
+        self.inner.leaves()
+    }
+
     pub fn update(&mut self, key: &U256, value: Account) {
         self.inner.update(key, value)
     }
+
+    /// Checks a `proof` that `value` is the account at `key` in the state
+    /// with root `root`.
+    pub fn verify_proof(root: &U256, key: &U256, value: &Account, proof: &[U256]) -> bool {
+        MerkleTree::<PoseidonHasher, Account>::verify_proof(root, key, value, proof)
+    }
+
+    /// The root of the state holding `value` at `key`, given the
+    /// siblings `proof` of its leaf.
+    pub fn root_from_proof(key: &U256, value: &Account, proof: &[U256]) -> Option<U256> {
+        MerkleTree::<PoseidonHasher, Account>::root_from_proof(key, value, proof)
+    }
+
+    /// The hash of the leaf holding `value` at `key`.
+    pub fn leaf_hash(key: &U256, value: &Account) -> U256 {
+        MerkleTree::<PoseidonHasher, Account>::leaf_hash(key, value)
+    }
 }
 
 impl Value for Account {
@@ -127,5 +149,69 @@ mod tests {
             )
             .unwrap()
         );
+
+        let root = s.root();
+        for (key, acc) in [(0, &acc0), (1, &acc1), (2, &acc2)] {
+            assert!(State::verify_proof(
+                &root,
+                &key.into(),
+                acc,
+                &s.proof(&key.into())
+            ));
+        }
+        // Not for another balance, nor for another account.
+        let stale = Account::new(0.into(), 41.into(), 1.into());
+        assert!(!State::verify_proof(&root, &0.into(), &stale, &proof));
+        assert!(!State::verify_proof(&root, &1.into(), &acc0, &proof));
+        // Missing accounts are proven empty.
+        assert!(State::verify_proof(
+            &root,
+            &3.into(),
+            &Account::default(),
+            &s.proof(&3.into())
+        ));
+    }
+
+    #[test]
+    fn update_account() {
+        let mut s = State::default();
+        s.update(&5.into(), Account::new(5.into(), 10.into(), 1.into()));
+        s.update(&9.into(), Account::new(9.into(), 20.into(), 0.into()));
+        let before = s.root();
+        s.update(&5.into(), Account::new(5.into(), 7.into(), 2.into()));
+        assert_ne!(s.root(), before);
+
+        // Same as if the account always had its new value.
+        let mut fresh = State::default();
+        fresh.update(&9.into(), Account::new(9.into(), 20.into(), 0.into()));
+        fresh.update(&5.into(), Account::new(5.into(), 7.into(), 2.into()));
+        assert_eq!(s.root(), fresh.root());
+        assert_eq!(s.get(&5.into()).balance, 7.into());
+
+        // Emptying an account removes it from the root.
+        s.update(&5.into(), Account::new(5.into(), 0.into(), 0.into()));
+        let mut only_nine = State::default();
+        only_nine.update(&9.into(), Account::new(9.into(), 20.into(), 0.into()));
+        assert_eq!(s.root(), only_nine.root());
+    }
+
+    #[test]
+    fn insertion_order() {
+        let accounts: Vec<_> = [3u64, 1 << 40, 7, u64::MAX, 8]
+            .iter()
+            .enumerate()
+            .map(|(i, &key)| Account::new(key.into(), (i as u64 + 1).into(), (i as u64).into()))
+            .collect();
+        let root = |order: &[usize]| {
+            let mut s = State::default();
+            for &i in order {
+                s.update(&accounts[i].id, accounts[i].clone());
+            }
+            s.root()
+        };
+        let expected = root(&[0, 1, 2, 3, 4]);
+        for order in [[4, 3, 2, 1, 0], [2, 0, 4, 1, 3], [1, 3, 0, 4, 2]] {
+            assert_eq!(root(&order), expected);
+        }
     }
 }
@@ -0,0 +1,271 @@
+//! Conformance vectors for the encodings that the contract and the circuit
+//! implement independently: the transaction hash, the leaf of an account
+//! and the state root.
+//!
+//! The vectors are committed in `test-vectors.json` and the tests fail as
+//! soon as an encoding changes. Regenerate the file with
+//! `fusion-sequencer gen-vectors` only when the change is intended.
+//!
+//! Values are field elements, so the largest value used is `p - 1`.
+
+use ethers_core::types::U256;
+use serde::{Deserialize, Serialize};
+
+use fusion_api::{hash_tx, Tx, TxKind, NATIVE_TOKEN};
+use fusion_types::PublicKey;
+
+use crate::merkle_tree::Value;
+use crate::state::{Account, State};
+
+/// Where the vectors are committed, relative to the sequencer.
+pub const DEFAULT_PATH: &str = "../fusion-prover/test-vectors.json";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TestVectors {
+    pub txs: Vec<TxVector>,
+    pub leaves: Vec<LeafVector>,
+    pub states: Vec<StateVector>,
+}
+
+/// A transaction with the addresses of its public keys and its hash.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TxVector {
+    pub name: String,
+    pub tx: Tx,
+    pub sender_address: U256,
+    pub to_address: U256,
+    pub hash: U256,
+}
+
+/// An account stored at `account.id`, with the value and the hash of its
+/// leaf.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LeafVector {
+    pub name: String,
+    pub account: Account,
+    pub value: U256,
+    pub hash: U256,
+}
+
+/// Accounts stored at their `id`, and the resulting root.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StateVector {
+    pub name: String,
+    pub accounts: Vec<Account>,
+    pub root: U256,
+}
+
+/// The BN254 scalar field modulus minus one.
+fn max_field_element() -> U256 {
+    U256::from_dec_str(
+        "21888242871839275222246405745257275088548364400416034343698204186575808495616",
+    )
+    .unwrap()
+}
+
+/// Compressed public keys: an arbitrary one, the generator of the circuit
+/// and its negation, which has the sign bit set.
+fn public_keys() -> [U256; 3] {
+    [
+        "0x19da79263254492407a166d5c67b15a2587353c186d16a83ffb935a060394c21",
+        "0x8b7d2d877a253c4b7733e1b91f05e0fcedf96bd11c2e572549b2a0f703727925",
+        "0x8b7d2d877a253c4b7733e1b91f05e0fcedf96bd11c2e572549b2a0f7037279a5",
+    ]
+    .map(|key| U256::from_str_radix(&key[2..], 16).unwrap())
+}
+
+fn tx_vector(name: &str, tx: Tx) -> TxVector {
+    TxVector {
+        name: name.to_string(),
+        sender_address: PublicKey::from(tx.sender).address(),
+        to_address: PublicKey::from(tx.to).address(),
+        hash: hash_tx(&tx),
+        tx,
+    }
+}
+
+fn account(id: U256, balance: U256, nonce: U256) -> Account {
+    Account::new(id, balance, nonce)
+}
+
+fn leaf_vector(name: &str, account: Account) -> LeafVector {
+    LeafVector {
+        name: name.to_string(),
+        value: account.to_u256(),
+        hash: State::leaf_hash(&account.id, &account),
+        account,
+    }
+}
+
+fn state_vector(name: &str, accounts: Vec<Account>) -> StateVector {
+    let mut state = State::default();
+    for account in &accounts {
+        state.update(&account.id, account.clone());
+    }
+    StateVector {
+        name: name.to_string(),
+        accounts,
+        root: state.root(),
+    }
+}
+
+/// Computes every vector with the current implementation.
+pub fn generate() -> TestVectors {
+    let [k1, k2, k3] = public_keys();
+    let max = max_field_element();
+    let ether = U256::exp10(18);
+    let tx = |kind, sender, to, nonce: u64, value| Tx {
+        kind,
+        sender,
+        to,
+        nonce: nonce.into(),
+        value,
+        not_before: None,
+        not_after: None,
+        chain_id: None,
+        token: NATIVE_TOKEN,
+    };
+
+    let txs = vec![
+        tx_vector("zero_values", tx(TxKind::Transfer, k1, k2, 0, 0.into())),
+        tx_vector(
+            "same_sender_and_recipient",
+            tx(TxKind::Transfer, k1, k1, 0, 0.into()),
+        ),
+        tx_vector(
+            "max_field_values",
+            Tx {
+                nonce: max,
+                ..tx(TxKind::Transfer, k2, k3, 0, max)
+            },
+        ),
+        tx_vector("deposit", tx(TxKind::Deposit, k2, k2, 1, ether)),
+        tx_vector("withdraw", tx(TxKind::Withdraw, k3, k1, 7, ether)),
+        tx_vector(
+            "validity_window",
+            Tx {
+                not_before: Some(1_700_000_000),
+                not_after: Some(1_700_003_600),
+                ..tx(TxKind::Transfer, k1, k2, 1, 1.into())
+            },
+        ),
+        tx_vector(
+            "not_before_only",
+            Tx {
+                not_before: Some(1),
+                ..tx(TxKind::Transfer, k1, k2, 1, 1.into())
+            },
+        ),
+        tx_vector(
+            "zero_not_after",
+            Tx {
+                not_after: Some(0),
+                ..tx(TxKind::Transfer, k1, k2, 1, 1.into())
+            },
+        ),
+    ];
+
+    let a1 = PublicKey::from(k1).address();
+    let a2 = PublicKey::from(k2).address();
+    let leaves = vec![
+        leaf_vector("empty_account", account(0.into(), 0.into(), 0.into())),
+        leaf_vector("zero_key", account(0.into(), 42.into(), 1.into())),
+        leaf_vector("zero_balance", account(1.into(), 0.into(), 1.into())),
+        leaf_vector("zero_nonce", account(1.into(), 1.into(), 0.into())),
+        leaf_vector("max_field_balance", account(a1, max, max)),
+        leaf_vector("public_key_address", account(a2, ether, 3.into())),
+    ];
+
+    let states = vec![
+        state_vector("empty", vec![]),
+        state_vector(
+            "single_account",
+            vec![account(0.into(), 42.into(), 1.into())],
+        ),
+        state_vector(
+            "three_accounts",
+            vec![
+                account(0.into(), 42.into(), 1.into()),
+                account(1.into(), 43.into(), 2.into()),
+                account(2.into(), 44.into(), 3.into()),
+            ],
+        ),
+        state_vector(
+            "empty_account_is_ignored",
+            vec![
+                account(0.into(), 42.into(), 1.into()),
+                account(5.into(), 0.into(), 0.into()),
+            ],
+        ),
+        state_vector(
+            "keys_differing_in_top_bit",
+            vec![
+                account(1.into(), 1.into(), 0.into()),
+                account((U256::one() << 253) + U256::one(), 2.into(), 0.into()),
+            ],
+        ),
+        state_vector(
+            "public_key_addresses",
+            vec![
+                account(a1, ether, 1.into()),
+                account(a2, 5.into(), 0.into()),
+            ],
+        ),
+    ];
+
+    TestVectors {
+        txs,
+        leaves,
+        states,
+    }
+}
+
+/// Writes freshly generated vectors to `path`.
+pub fn write(path: &str) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(&generate()).map_err(|e| e.to_string())?;
+    std::fs::write(path, json + "\n").map_err(|e| format!("Could not write {path}: {e}"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const COMMITTED: &str = include_str!("../test-vectors.json");
+
+    fn committed() -> TestVectors {
+        serde_json::from_str(COMMITTED).unwrap()
+    }
+
+    /// Compares one kind of vector at a time so a failure names the case.
+    fn assert_matches<T: Serialize>(name: &str, generated: &[T], committed: &[T]) {
+        assert_eq!(generated.len(), committed.len(), "number of {name}");
+        for (generated, committed) in generated.iter().zip(committed) {
+            assert_eq!(
+                serde_json::to_value(generated).unwrap(),
+                serde_json::to_value(committed).unwrap(),
+                "{name} changed, regenerate test-vectors.json with `fusion-sequencer gen-vectors` if this is intended"
+            );
+        }
+    }
+
+    #[test]
+    fn txs() {
+        assert_matches("txs", &generate().txs, &committed().txs);
+    }
+
+    #[test]
+    fn leaves() {
+        assert_matches("leaves", &generate().leaves, &committed().leaves);
+    }
+
+    #[test]
+    fn states() {
+        assert_matches("states", &generate().states, &committed().states);
+    }
+
+    #[test]
+    fn committed_file_is_canonical() {
+        let json = serde_json::to_string_pretty(&committed()).unwrap();
+        assert_eq!(json + "\n", COMMITTED);
+    }
+}
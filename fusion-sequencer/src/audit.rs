@@ -0,0 +1,395 @@
+//! The audit log: a record of every transaction applied in a block, with
+//! the balances of its sender and recipient before and after it, so that
+//! operators can replay what happened to an account.
+//!
+//! Records are appended as lines of JSON to `audit/<n>.jsonl` in
+//! `database_path`. A new file is started once the last one holds
+//! `audit_log_segment_bytes`, and the oldest files are deleted once they
+//! hold more than `audit_log_max_bytes` together. Dev mode keeps them in
+//! memory, within the same bounds. Blocks an L1 reorg drops are taken out
+//! of the log, since they are built again.
+
+use std::{
+    collections::VecDeque,
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use ethers::types::U256;
+use tracing::warn;
+
+use fusion_api::{hash_tx, AuditRecord, SignedTx};
+use fusion_prover::state::State;
+
+/// The records of the transactions of block `batch`, where `states` are
+/// the state before the block followed by the state after each of `txs`.
+pub fn records(batch: u64, txs: &[SignedTx], states: &[State]) -> Vec<AuditRecord> {
+    assert_eq!(states.len(), txs.len() + 1);
+    txs.iter()
+        .zip(states.windows(2))
+        .enumerate()
+        .map(|(index, (signed_tx, states))| {
+            let tx = &signed_tx.tx;
+            let (from, to) = (tx.sender_account(), tx.to_account());
+            AuditRecord {
+                batch,
+                index,
+                tx_hash: hash_tx(tx),
+                kind: tx.kind.clone(),
+                token: tx.token,
+                from: tx.sender,
+                to: tx.to,
+                value: tx.value,
+                fee: U256::zero(),
+                nonce: tx.nonce,
+                from_balance_before: states[0].get(&from).balance,
+                from_balance_after: states[1].get(&from).balance,
+                to_balance_before: states[0].get(&to).balance,
+                to_balance_after: states[1].get(&to).balance,
+            }
+        })
+        .collect()
+}
+
+#[derive(Default)]
+struct Segment {
+    number: u64,
+    bytes: u64,
+    /// The lines of the segment when they are kept in memory.
+    contents: String,
+}
+
+/// Keeps nothing until opened or created in memory.
+#[derive(Default)]
+pub struct AuditLog {
+    /// `None` keeps the records in memory only.
+    dir: Option<PathBuf>,
+    /// Oldest first.
+    segments: VecDeque<Segment>,
+    segment_bytes: u64,
+    max_bytes: u64,
+}
+
+impl AuditLog {
+    /// An audit log of at most `max_bytes` in memory, off when 0.
+    pub fn in_memory(segment_bytes: u64, max_bytes: u64) -> Self {
+        Self {
+            dir: None,
+            segments: VecDeque::new(),
+            segment_bytes,
+            max_bytes,
+        }
+    }
+
+    /// Opens the audit log in `dir`. A last line cut short by a crash is
+    /// dropped.
+    pub fn open(dir: &Path, segment_bytes: u64, max_bytes: u64) -> Result<Self, String> {
+        let dir = dir.join("audit");
+        let mut log = Self {
+            dir: Some(dir.clone()),
+            segments: VecDeque::new(),
+            segment_bytes,
+            max_bytes,
+        };
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(log),
+            Err(e) => return Err(format!("Could not read {}: {e}", dir.display())),
+        };
+        let mut numbers: Vec<u64> = entries
+            .filter_map(|entry| {
+                let name = entry.ok()?.file_name();
+                name.to_str()?.strip_suffix(".jsonl")?.parse().ok()
+            })
+            .collect();
+        numbers.sort_unstable();
+        for number in numbers {
+            let path = log.path(number);
+            let bytes = std::fs::metadata(&path)
+                .map_err(|e| format!("Could not read {}: {e}", path.display()))?
+                .len();
+            log.segments.push_back(Segment {
+                number,
+                bytes,
+                contents: String::new(),
+            });
+        }
+        if let Some(number) = log.segments.back().map(|segment| segment.number) {
+            let contents = log.read(number)?;
+            let complete = contents.rfind('\n').map_or(0, |end| end + 1);
+            if complete < contents.len() {
+                warn!(path = %log.path(number).display(), "Dropping a torn audit record");
+                log.rewrite(number, &contents[..complete])?;
+            }
+        }
+        Ok(log)
+    }
+
+    fn path(&self, number: u64) -> PathBuf {
+        let dir = self.dir.as_ref().expect("Only files have a path");
+        dir.join(format!("{number}.jsonl"))
+    }
+
+    fn segment(&mut self, number: u64) -> &mut Segment {
+        self.segments
+            .iter_mut()
+            .find(|segment| segment.number == number)
+            .expect("The segment exists")
+    }
+
+    fn read(&self, number: u64) -> Result<String, String> {
+        if self.dir.is_none() {
+            let segment = self.segments.iter().find(|s| s.number == number);
+            return Ok(segment.map_or_else(String::new, |s| s.contents.clone()));
+        }
+        let path = self.path(number);
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => Ok(contents),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+            Err(e) => Err(format!("Could not read {}: {e}", path.display())),
+        }
+    }
+
+    fn rewrite(&mut self, number: u64, contents: &str) -> Result<(), String> {
+        if self.dir.is_some() {
+            let path = self.path(number);
+            let tmp = path.with_extension("jsonl.tmp");
+            std::fs::write(&tmp, contents)
+                .and_then(|()| std::fs::rename(&tmp, &path))
+                .map_err(|e| format!("Could not write {}: {e}", path.display()))?;
+        }
+        let in_memory = self.dir.is_none();
+        let segment = self.segment(number);
+        segment.bytes = contents.len() as u64;
+        if in_memory {
+            segment.contents = contents.to_string();
+        }
+        Ok(())
+    }
+
+    fn remove(&self, number: u64) -> Result<(), String> {
+        if self.dir.is_none() {
+            return Ok(());
+        }
+        let path = self.path(number);
+        match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(format!("Could not remove {}: {e}", path.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Appends `records`, starting a new segment and deleting the oldest
+    /// ones as needed.
+    pub fn append(&mut self, records: &[AuditRecord]) -> Result<(), String> {
+        if self.max_bytes == 0 || records.is_empty() {
+            return Ok(());
+        }
+        let mut lines = String::new();
+        for record in records {
+            lines += &serde_json::to_string(record).map_err(|e| e.to_string())?;
+            lines.push('\n');
+        }
+        let full = self
+            .segments
+            .back()
+            .map_or(true, |segment| segment.bytes >= self.segment_bytes);
+        if full {
+            let number = self.segments.back().map_or(0, |segment| segment.number + 1);
+            self.segments.push_back(Segment {
+                number,
+                ..Default::default()
+            });
+        }
+        let number = self.segments.back().unwrap().number;
+        if self.dir.is_some() {
+            let path = self.path(number);
+            std::fs::create_dir_all(path.parent().unwrap())
+                .and_then(|()| OpenOptions::new().create(true).append(true).open(&path))
+                .and_then(|mut file| file.write_all(lines.as_bytes()))
+                .map_err(|e| format!("Could not write {}: {e}", path.display()))?;
+        } else {
+            self.segment(number).contents += &lines;
+        }
+        self.segment(number).bytes += lines.len() as u64;
+
+        while self.segments.len() > 1
+            && self.segments.iter().map(|s| s.bytes).sum::<u64>() > self.max_bytes
+        {
+            let oldest = self.segments.pop_front().unwrap();
+            self.remove(oldest.number)?;
+        }
+        Ok(())
+    }
+
+    /// Takes out the records of block `batch` and after, once an L1
+    /// reorg dropped them.
+    pub fn truncate_from(&mut self, batch: u64) -> Result<(), String> {
+        while let Some(number) = self.segments.back().map(|segment| segment.number) {
+            let contents = self.read(number)?;
+            let mut kept = String::new();
+            for line in contents.lines() {
+                match serde_json::from_str::<AuditRecord>(line) {
+                    Ok(record) if record.batch >= batch => {}
+                    _ => {
+                        kept += line;
+                        kept.push('\n');
+                    }
+                }
+            }
+            if kept.len() == contents.len() {
+                return Ok(());
+            }
+            if !kept.is_empty() {
+                return self.rewrite(number, &kept);
+            }
+            self.segments.pop_back();
+            self.remove(number)?;
+        }
+        Ok(())
+    }
+
+    /// The records of blocks `from_batch` to `to_batch` where the account
+    /// with public key `address` sent or received, oldest first, skipping
+    /// the first `offset` and at most `limit` of them. Reads the whole
+    /// log, which is at most `audit_log_max_bytes`.
+    pub fn account_history(
+        &self,
+        address: U256,
+        (from_batch, to_batch): (u64, u64),
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<AuditRecord>, String> {
+        let mut found = vec![];
+        let mut skipped = 0;
+        for segment in &self.segments {
+            for line in self.read(segment.number)?.lines() {
+                let Ok(record) = serde_json::from_str::<AuditRecord>(line) else {
+                    continue;
+                };
+                if !(from_batch..=to_batch).contains(&record.batch)
+                    || (record.from != address && record.to != address)
+                {
+                    continue;
+                }
+                if skipped < offset {
+                    skipped += 1;
+                    continue;
+                }
+                if found.len() == limit {
+                    return Ok(found);
+                }
+                found.push(record);
+            }
+        }
+        Ok(found)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fusion_config::Config;
+
+    use crate::dev::{self, dev_transfer};
+    use crate::transition::apply_tx;
+
+    /// The records of `blocks` of transfers, built one after the other.
+    fn blocks(blocks: &[Vec<SignedTx>]) -> Vec<Vec<AuditRecord>> {
+        let config = Config {
+            dev: true,
+            dev_accounts: 3,
+            ..Default::default()
+        };
+        let mut state = dev::genesis_state(&config);
+        let mut built = vec![];
+        for (i, txs) in blocks.iter().enumerate() {
+            let mut states = vec![state.clone()];
+            for tx in txs {
+                state = apply_tx(state, &tx.tx).unwrap();
+                states.push(state.clone());
+            }
+            built.push(records(i as u64 + 1, txs, &states));
+        }
+        built
+    }
+
+    #[test]
+    fn balances_before_and_after() {
+        let built = blocks(&[vec![dev_transfer(0, 1, 1, 10), dev_transfer(1, 0, 1, 4)]]);
+        let [first, second] = &built[0][..] else {
+            panic!("Two records expected");
+        };
+        assert_eq!((first.batch, first.index), (1, 0));
+        assert_eq!(first.tx_hash, hash_tx(&dev_transfer(0, 1, 1, 10).tx));
+        assert_eq!(
+            first.from_balance_before - first.from_balance_after,
+            10.into()
+        );
+        assert_eq!(first.to_balance_after - first.to_balance_before, 10.into());
+        assert_eq!(second.index, 1);
+        assert_eq!(second.from_balance_before, first.to_balance_after);
+        assert_eq!(
+            second.to_balance_after,
+            first.from_balance_after + U256::from(4)
+        );
+    }
+
+    #[test]
+    fn rotates_and_pages() {
+        let built = blocks(&[
+            vec![dev_transfer(0, 1, 1, 1)],
+            vec![dev_transfer(1, 2, 1, 1)],
+            vec![dev_transfer(0, 2, 2, 1), dev_transfer(2, 0, 1, 1)],
+            vec![dev_transfer(0, 1, 3, 1)],
+        ]);
+        let size = |records: &[AuditRecord]| -> u64 {
+            let lines = records
+                .iter()
+                .map(|r| serde_json::to_string(r).unwrap().len() + 1);
+            lines.sum::<usize>() as u64
+        };
+        // One block per segment, two blocks at most.
+        let max_bytes = size(&built[1]) + size(&built[2]);
+        let account = built[0][0].from;
+        let dir = std::env::temp_dir().join(format!("fusion-audit-{}", rand::random::<u64>()));
+        let logs = [
+            AuditLog::in_memory(1, max_bytes),
+            AuditLog::open(&dir, 1, max_bytes).unwrap(),
+        ];
+        for mut log in logs {
+            for records in &built[..3] {
+                log.append(records).unwrap();
+            }
+            assert_eq!(log.segments.len(), 2);
+            let history = log.account_history(account, (0, 10), 0, 10).unwrap();
+            let batches: Vec<_> = history.iter().map(|r| (r.batch, r.index)).collect();
+            assert_eq!(batches, [(3, 0), (3, 1)]);
+            let page = log.account_history(account, (0, 10), 1, 10).unwrap();
+            assert_eq!(page, history[1..]);
+            assert!(log
+                .account_history(account, (0, 10), 0, 0)
+                .unwrap()
+                .is_empty());
+            assert!(log
+                .account_history(account, (4, 10), 0, 10)
+                .unwrap()
+                .is_empty());
+
+            // Block 3 was dropped by a reorg and built again.
+            log.truncate_from(3).unwrap();
+            assert_eq!(log.segments.len(), 1);
+            log.append(&built[3]).unwrap();
+            let history = log.account_history(account, (0, 10), 0, 10).unwrap();
+            assert_eq!(history, built[3]);
+        }
+
+        let log = AuditLog::open(&dir, 1, max_bytes).unwrap();
+        assert_eq!(log.segments.len(), 2);
+        let history = log.account_history(account, (0, 10), 0, 10).unwrap();
+        assert_eq!(history, built[3]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
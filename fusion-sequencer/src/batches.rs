@@ -0,0 +1,130 @@
+//! The blocks L1 accepted, kept in `batches.jsonl` next to the mempool
+//! log so that `get_batch` still answers after a restart. A line of JSON
+//! is appended per confirmed block, and the file is rewritten when an L1
+//! reorg drops the latest ones.
+
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use tracing::warn;
+
+use fusion_api::Batch;
+
+pub struct BatchLog {
+    /// `None` keeps the batches in memory only.
+    path: Option<PathBuf>,
+}
+
+impl BatchLog {
+    pub fn in_memory() -> Self {
+        Self { path: None }
+    }
+
+    /// Opens the log in `dir` and returns it with the batches it holds,
+    /// oldest first. A last line cut short by a crash is dropped.
+    pub fn open(dir: &Path) -> Result<(Self, Vec<Batch>), String> {
+        let path = dir.join("batches.jsonl");
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok((Self { path: Some(path) }, vec![]))
+            }
+            Err(e) => return Err(format!("Could not read {}: {e}", path.display())),
+        };
+        let (complete, torn) = match contents.rfind('\n') {
+            Some(end) => contents.split_at(end + 1),
+            None => ("", contents.as_str()),
+        };
+        let batches = complete
+            .lines()
+            .enumerate()
+            .map(|(i, line)| {
+                serde_json::from_str(line).map_err(|e| {
+                    format!("Could not parse line {} of {}: {e}", i + 1, path.display())
+                })
+            })
+            .collect::<Result<Vec<Batch>, _>>()?;
+        let log = Self { path: Some(path) };
+        if !torn.is_empty() {
+            warn!("Dropping an incomplete batch at the end of the batch log");
+            log.rewrite(&batches)?;
+        }
+        Ok((log, batches))
+    }
+
+    pub fn append(&self, batch: &Batch) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut line = serde_json::to_vec(batch).map_err(|e| e.to_string())?;
+        line.push(b'\n');
+        std::fs::create_dir_all(path.parent().unwrap())
+            .and_then(|()| OpenOptions::new().create(true).append(true).open(path))
+            .and_then(|mut file| file.write_all(&line))
+            .map_err(|e| format!("Could not write {}: {e}", path.display()))
+    }
+
+    /// Replaces the log with `batches`.
+    pub fn rewrite(&self, batches: &[Batch]) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut lines = vec![];
+        for batch in batches {
+            lines.extend(serde_json::to_vec(batch).map_err(|e| e.to_string())?);
+            lines.push(b'\n');
+        }
+        let tmp = path.with_extension("jsonl.tmp");
+        std::fs::create_dir_all(path.parent().unwrap())
+            .and_then(|()| std::fs::write(&tmp, &lines))
+            .and_then(|()| std::fs::rename(&tmp, path))
+            .map_err(|e| format!("Could not write {}: {e}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ethers::types::H256;
+
+    fn batch(number: u64) -> Batch {
+        Batch {
+            number,
+            prior_root: (number - 1).into(),
+            state_root: number.into(),
+            tx_hashes: vec![],
+            txs: vec![],
+            l1_tx_hash: H256::from_low_u64_be(number),
+            l1_block_number: 10 * number,
+            timestamp: 1000 + number,
+            receipts: vec![],
+        }
+    }
+
+    #[test]
+    fn survives_restarts() {
+        let dir = std::env::temp_dir().join(format!("fusion-batches-{}", rand::random::<u64>()));
+        let numbers = |batches: Vec<Batch>| batches.iter().map(|b| b.number).collect::<Vec<_>>();
+
+        let (log, restored) = BatchLog::open(&dir).unwrap();
+        assert!(restored.is_empty());
+        for number in 1..=3 {
+            log.append(&batch(number)).unwrap();
+        }
+        let (log, restored) = BatchLog::open(&dir).unwrap();
+        assert_eq!(numbers(restored.clone()), [1, 2, 3]);
+        assert_eq!(restored[1].l1_block_number, 20);
+
+        // Reorged out.
+        log.rewrite(&restored[..1]).unwrap();
+        let path = dir.join("batches.jsonl");
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"number\":").unwrap();
+        let (_, restored) = BatchLog::open(&dir).unwrap();
+        assert_eq!(numbers(restored), [1]);
+        assert!(std::fs::read_to_string(&path).unwrap().ends_with("}\n"));
+    }
+}
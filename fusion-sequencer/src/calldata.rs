@@ -0,0 +1,277 @@
+//! A compact encoding of the proofs of `submitBlock`, sent instead of
+//! the ABI encoding when `compressed_calldata` is set.
+//!
+//! The ABI encoding spends a 32-byte word on each public input, when most
+//! are small numbers or zero: kinds, nonces, values, chain ids, tokens.
+//! The compact encoding writes a public input as its length in bytes
+//! followed by its minimal big-endian bytes. A public key already written
+//! in the same call is written as its index among them, and a pre-state
+//! root that is the post-state root of the previous proof is left out.
+//! The points of the proofs are field elements and are written whole.
+//!
+//! The encoding starts with the number of proofs. Fusion.sol does not
+//! decode it yet: it is only sent to `submitCompressedBlock(bytes)`, which
+//! `compatibility::check_compressed_calldata` looks for in the contract.
+
+use ethers::abi::{self, AbiEncode, Token};
+use ethers::types::{Selector, U256};
+
+use fusion_l1::fusion;
+use fusion_prover::PUBLIC_INPUTS;
+
+/// Public inputs of the x coordinate of a key, followed by its y
+/// coordinate: the sender and the recipient.
+const KEYS: [usize; 2] = [3, 5];
+
+/// Tag of a public key written earlier in the call, followed by its
+/// index. Tags up to 32 are lengths.
+const KEY_TAG: u8 = 0x80;
+/// Tag of a pre-state root that is the post-state root of the previous
+/// proof.
+const CHAINED_TAG: u8 = 0x81;
+
+/// Selector of `submitCompressedBlock(bytes)`.
+pub fn compressed_selector() -> Selector {
+    ethers::utils::id("submitCompressedBlock(bytes)")
+}
+
+fn put_word(out: &mut Vec<u8>, word: U256) {
+    let mut bytes = [0u8; 32];
+    word.to_big_endian(&mut bytes);
+    let len = word.bits().div_ceil(8);
+    out.push(len as u8);
+    out.extend(&bytes[32 - len..]);
+}
+
+fn put_point(out: &mut Vec<u8>, words: &[U256]) {
+    for word in words {
+        let mut bytes = [0u8; 32];
+        word.to_big_endian(&mut bytes);
+        out.extend(bytes);
+    }
+}
+
+/// The compact encoding of `proofs`.
+pub fn encode(proofs: &[fusion::TxProof]) -> Vec<u8> {
+    let count = u8::try_from(proofs.len()).expect("At most 255 proofs per call");
+    let mut out = vec![count];
+    let mut keys: Vec<(U256, U256)> = vec![];
+    let mut previous_root = None;
+    for fusion::TxProof { proof, input } in proofs {
+        assert_eq!(input.len(), PUBLIC_INPUTS);
+        put_point(&mut out, &[proof.a.x, proof.a.y]);
+        put_point(&mut out, &proof.b.x);
+        put_point(&mut out, &proof.b.y);
+        put_point(&mut out, &[proof.c.x, proof.c.y]);
+
+        let mut i = 0;
+        while i < PUBLIC_INPUTS {
+            if i == 0 && previous_root == Some(input[0]) {
+                out.push(CHAINED_TAG);
+            } else if KEYS.contains(&i) {
+                let key = (input[i], input[i + 1]);
+                let index = keys.iter().position(|seen| *seen == key);
+                match index.and_then(|index| u8::try_from(index).ok()) {
+                    Some(index) => out.extend([KEY_TAG, index]),
+                    None => {
+                        put_word(&mut out, key.0);
+                        put_word(&mut out, key.1);
+                        keys.push(key);
+                    }
+                }
+                i += 1;
+            } else {
+                put_word(&mut out, input[i]);
+            }
+            i += 1;
+        }
+        previous_root = Some(input[1]);
+    }
+    out
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.data.len() < len {
+            return Err("The compressed calldata is truncated".to_string());
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn point(&mut self) -> Result<U256, String> {
+        Ok(U256::from_big_endian(self.take(32)?))
+    }
+
+    /// A word whose length tag was read already.
+    fn word(&mut self, len: u8) -> Result<U256, String> {
+        match len {
+            0..=32 => Ok(U256::from_big_endian(self.take(len as usize)?)),
+            tag => Err(format!(
+                "Unexpected tag {tag:#x} in the compressed calldata"
+            )),
+        }
+    }
+}
+
+/// The proofs of the compact encoding `data`.
+pub fn decode(data: &[u8]) -> Result<Vec<fusion::TxProof>, String> {
+    let mut reader = Reader { data };
+    let count = reader.byte()?;
+    let mut keys: Vec<(U256, U256)> = vec![];
+    let mut proofs: Vec<fusion::TxProof> = vec![];
+    for _ in 0..count {
+        let proof = fusion::Proof {
+            a: fusion::G1Point {
+                x: reader.point()?,
+                y: reader.point()?,
+            },
+            b: fusion::G2Point {
+                x: [reader.point()?, reader.point()?],
+                y: [reader.point()?, reader.point()?],
+            },
+            c: fusion::G1Point {
+                x: reader.point()?,
+                y: reader.point()?,
+            },
+        };
+        let mut input = Vec::with_capacity(PUBLIC_INPUTS);
+        while input.len() < PUBLIC_INPUTS {
+            let i = input.len();
+            let tag = reader.byte()?;
+            if i == 0 && tag == CHAINED_TAG {
+                let previous = proofs
+                    .last()
+                    .ok_or("The first proof has no previous root to chain to")?;
+                input.push(previous.input[1]);
+            } else if KEYS.contains(&i) && tag == KEY_TAG {
+                let index = reader.byte()? as usize;
+                let key = keys
+                    .get(index)
+                    .ok_or_else(|| format!("Unknown key {index} in the compressed calldata"))?;
+                input.extend([key.0, key.1]);
+            } else if KEYS.contains(&i) {
+                let x = reader.word(tag)?;
+                let tag = reader.byte()?;
+                let key = (x, reader.word(tag)?);
+                keys.push(key);
+                input.extend([key.0, key.1]);
+            } else {
+                input.push(reader.word(tag)?);
+            }
+        }
+        proofs.push(fusion::TxProof { proof, input });
+    }
+    if !reader.data.is_empty() {
+        return Err("Trailing bytes after the compressed calldata".to_string());
+    }
+    Ok(proofs)
+}
+
+/// The calldata of `submitCompressedBlock(encode(proofs))`.
+pub fn compressed_call(proofs: &[fusion::TxProof]) -> Vec<u8> {
+    let mut calldata = compressed_selector().to_vec();
+    calldata.extend(abi::encode(&[Token::Bytes(encode(proofs))]));
+    calldata
+}
+
+/// The proofs of a `submitCompressedBlock` call, `None` if `calldata`
+/// calls another function.
+pub fn decode_call(calldata: &[u8]) -> Option<Result<Vec<fusion::TxProof>, String>> {
+    let args = calldata.strip_prefix(&compressed_selector())?;
+    Some(
+        match abi::decode(&[abi::ParamType::Bytes], args).map(|mut tokens| tokens.pop()) {
+            Ok(Some(Token::Bytes(data))) => decode(&data),
+            _ => Err("Invalid submitCompressedBlock calldata".to_string()),
+        },
+    )
+}
+
+/// Bytes of calldata the compact encoding saves on `call`.
+pub fn saved_bytes(call: &fusion::SubmitBlockCall) -> usize {
+    let compressed = compressed_call(&call.l_2_block).len();
+    call.clone().encode().len().saturating_sub(compressed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn proof(pre_root: u64, post_root: u64, sender: u64, to: u64) -> fusion::TxProof {
+        let big = U256::MAX - 1;
+        let mut input = vec![U256::zero(); PUBLIC_INPUTS];
+        input[0] = big - pre_root;
+        input[1] = big - post_root;
+        input[2] = 1.into();
+        input[3] = big - sender;
+        input[4] = sender.into();
+        input[5] = big - to;
+        input[6] = to.into();
+        input[7] = 3.into();
+        input[8] = 1_000_000_000_000_000_000u64.into();
+        input[14] = big - 7;
+        input[20] = 31337.into();
+        fusion::TxProof {
+            proof: fusion::Proof {
+                a: fusion::G1Point {
+                    x: big,
+                    y: 1.into(),
+                },
+                b: fusion::G2Point {
+                    x: [2.into(), big],
+                    y: [U256::zero(), 4.into()],
+                },
+                c: fusion::G1Point {
+                    x: 5.into(),
+                    y: big - 1,
+                },
+            },
+            input,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let proofs = [proof(0, 1, 10, 11)];
+        let encoded = encode(&proofs);
+        assert_eq!(decode(&encoded).unwrap(), proofs);
+        assert_eq!(
+            decode_call(&compressed_call(&proofs)),
+            Some(Ok(proofs.to_vec()))
+        );
+        let call = fusion::SubmitBlockCall {
+            l_2_block: proofs.clone(),
+        };
+        assert!(saved_bytes(&call) > 0);
+        assert_eq!(decode_call(&call.encode()), None);
+
+        // Chained roots and repeated keys.
+        let proofs = [
+            proof(0, 1, 10, 11),
+            proof(1, 2, 11, 10),
+            proof(5, 6, 10, 12),
+        ];
+        let encoded = encode(&proofs);
+        assert_eq!(decode(&encoded).unwrap(), proofs);
+        // Two count bytes, a root of 32 bytes written as a tag and three
+        // keys of 35 bytes written in 2.
+        let separately: usize = proofs.iter().map(|p| encode(&[p.clone()]).len()).sum();
+        assert_eq!(separately - encoded.len(), 2 + 32 + 3 * 33);
+
+        assert!(decode(&encoded[..encoded.len() - 1]).is_err());
+        let mut trailing = encoded.clone();
+        trailing.push(0);
+        assert!(decode(&trailing).is_err());
+        assert!(decode(&[1, 0]).is_err());
+        assert!(decode(&[0]).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,202 @@
+//! Fault injection, to exercise retries and recovery without waiting
+//! for real failures. Faults are only injected on a dev sequencer or
+//! one running against a local chain, and every injected failure is
+//! tagged with `INJECTED` so the logs tell it apart from a real one.
+//!
+//! The L1 contract has no mock to reorganize, so reorgs are not among
+//! the faults.
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tracing::warn;
+
+use fusion_api::{ChaosProfile, Fault};
+use fusion_config::Config;
+
+/// Prefix of the logs and errors of injected faults.
+pub const INJECTED: &str = "[chaos]";
+
+struct Injector {
+    profile: ChaosProfile,
+    rng: ChaCha8Rng,
+    script: VecDeque<Fault>,
+}
+
+/// Decides which faults to inject. Clones share their decisions, so a
+/// scripted fault is only injected once.
+#[derive(Clone, Default)]
+pub struct Chaos(Arc<Mutex<Option<Injector>>>);
+
+impl Chaos {
+    /// Injects the faults of `profile` from now on, or none once it is
+    /// disabled.
+    pub fn set(&self, config: &Config, profile: ChaosProfile) -> Result<(), String> {
+        if !profile.enabled {
+            *self.0.lock().unwrap() = None;
+            return Ok(());
+        }
+        if !config.dev && !config.dev_l1 {
+            return Err("Chaos is only available in dev mode or with dev_l1".to_string());
+        }
+        for (name, rate) in [
+            ("submission_failure_rate", profile.submission_failure_rate),
+            ("prover_failure_rate", profile.prover_failure_rate),
+            ("storage_failure_rate", profile.storage_failure_rate),
+        ] {
+            if !(0.0..=1.0).contains(&rate) {
+                return Err(format!("{name} must be between 0 and 1, got {rate}"));
+            }
+        }
+        warn!(?profile, "{INJECTED} injecting faults");
+        *self.0.lock().unwrap() = Some(Injector {
+            rng: ChaCha8Rng::seed_from_u64(profile.seed),
+            script: profile.script.iter().copied().collect(),
+            profile,
+        });
+        Ok(())
+    }
+
+    /// Whether `fault` happens now. Returns the error to fail with.
+    pub fn inject(&self, fault: Fault) -> Option<String> {
+        let mut guard = self.0.lock().unwrap();
+        let injector = guard.as_mut()?;
+        let scripted = injector.script.iter().position(|next| *next == fault);
+        let injected = match scripted {
+            Some(position) => {
+                injector.script.remove(position);
+                true
+            }
+            None => {
+                let rate = match fault {
+                    Fault::Submission => injector.profile.submission_failure_rate,
+                    Fault::Prover => injector.profile.prover_failure_rate,
+                    Fault::Storage => injector.profile.storage_failure_rate,
+                };
+                injector.rng.gen_bool(rate)
+            }
+        };
+        injected.then(|| {
+            warn!(%fault, "{INJECTED} injecting a failure");
+            format!("{INJECTED} injected {fault} failure")
+        })
+    }
+
+    /// How long to wait before the receipt of an L1 submission.
+    pub fn receipt_delay(&self) -> Option<Duration> {
+        self.0
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|injector| Duration::from_millis(injector.profile.receipt_delay_ms))
+            .filter(|delay| !delay.is_zero())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn dev_config() -> Config {
+        Config {
+            dev: true,
+            ..Default::default()
+        }
+    }
+
+    fn quiet() -> ChaosProfile {
+        ChaosProfile {
+            enabled: true,
+            submission_failure_rate: 0.0,
+            prover_failure_rate: 0.0,
+            storage_failure_rate: 0.0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn only_in_dev() {
+        let chaos = Chaos::default();
+        let enabled = ChaosProfile {
+            enabled: true,
+            ..Default::default()
+        };
+        assert!(chaos.set(&Config::default(), enabled.clone()).is_err());
+        assert!(chaos.inject(Fault::Prover).is_none());
+        // Disabling it is always allowed.
+        assert!(chaos
+            .set(&Config::default(), ChaosProfile::default())
+            .is_ok());
+
+        let invalid = ChaosProfile {
+            prover_failure_rate: 1.5,
+            ..enabled.clone()
+        };
+        assert!(chaos.set(&dev_config(), invalid).is_err());
+        let dev_l1 = Config {
+            dev_l1: true,
+            ..Default::default()
+        };
+        assert!(chaos.set(&dev_l1, enabled).is_ok());
+    }
+
+    #[test]
+    fn scripted_faults_come_first() {
+        let chaos = Chaos::default();
+        let profile = ChaosProfile {
+            script: vec![Fault::Submission, Fault::Prover, Fault::Submission],
+            ..quiet()
+        };
+        chaos.set(&dev_config(), profile).unwrap();
+
+        let clone = chaos.clone();
+        let error = clone.inject(Fault::Prover).unwrap();
+        assert!(error.starts_with(INJECTED), "{error}");
+        assert!(chaos.inject(Fault::Prover).is_none());
+        assert!(chaos.inject(Fault::Storage).is_none());
+        assert!(chaos.inject(Fault::Submission).is_some());
+        assert!(chaos.inject(Fault::Submission).is_some());
+        assert!(chaos.inject(Fault::Submission).is_none());
+    }
+
+    #[test]
+    fn random_faults_replay_from_the_seed() {
+        let draws = |seed| {
+            let chaos = Chaos::default();
+            let profile = ChaosProfile {
+                seed,
+                submission_failure_rate: 0.5,
+                ..quiet()
+            };
+            chaos.set(&dev_config(), profile).unwrap();
+            (0..64)
+                .map(|_| chaos.inject(Fault::Submission).is_some())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(draws(7), draws(7));
+        assert_ne!(draws(7), draws(8));
+        assert!(draws(7).contains(&true) && draws(7).contains(&false));
+    }
+
+    #[test]
+    fn receipt_delay() {
+        let chaos = Chaos::default();
+        assert_eq!(chaos.receipt_delay(), None);
+        chaos
+            .set(
+                &dev_config(),
+                ChaosProfile {
+                    receipt_delay_ms: 250,
+                    ..quiet()
+                },
+            )
+            .unwrap();
+        assert_eq!(chaos.receipt_delay(), Some(Duration::from_millis(250)));
+        chaos.set(&dev_config(), ChaosProfile::default()).unwrap();
+        assert_eq!(chaos.receipt_delay(), None);
+    }
+}
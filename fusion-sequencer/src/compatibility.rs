@@ -0,0 +1,370 @@
+//! Checks that the deployed contract matches the bindings the sequencer
+//! is built with, so a mismatch is caught before submitting blocks.
+//!
+//! Fusion.sol has no version function, so the dispatcher of the deployed
+//! code is searched for the selectors the sequencer calls. Contracts that
+//! expose `version()` must also report a supported version, and
+//! unversioned contracts count as version 1.
+//!
+//! Contracts that expose `circuitParams()` must also verify proofs of the
+//! circuit the prover uses, see `fusion_prover::params`. During a
+//! migration, `nextCircuitParams()` names a second circuit accepted from
+//! an L1 block on.
+
+use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use std::ops::RangeInclusive;
+
+use fusion_l1::fusion;
+
+use crate::calldata;
+
+/// Contract versions this sequencer can submit blocks to.
+pub const SUPPORTED_VERSIONS: RangeInclusive<u64> = 1..=1;
+
+/// Selector of `version()`.
+const VERSION_SELECTOR: Selector = [0x54, 0xfd, 0x4d, 0x50];
+
+fn circuit_params_selector() -> Selector {
+    ethers::utils::id("circuitParams()")
+}
+
+fn next_circuit_params_selector() -> Selector {
+    ethers::utils::id("nextCircuitParams()")
+}
+
+/// `PUSH4`, which the dispatcher uses to compare the called selector.
+const PUSH4: u8 = 0x63;
+
+/// The functions the sequencer calls, with their signature.
+fn required_selectors() -> Vec<(String, Selector)> {
+    vec![
+        (
+            fusion::RootCall::abi_signature().into_owned(),
+            fusion::RootCall::selector(),
+        ),
+        (
+            fusion::SubmitBlockCall::abi_signature().into_owned(),
+            fusion::SubmitBlockCall::selector(),
+        ),
+    ]
+}
+
+fn has_selector(code: &[u8], selector: Selector) -> bool {
+    code.windows(5)
+        .any(|window| window[0] == PUSH4 && window[1..] == selector)
+}
+
+async fn deployed_code<M: Middleware>(client: &M, address: Address) -> Result<Bytes, String> {
+    let code = client
+        .get_code(address, None)
+        .await
+        .map_err(|e| format!("Cannot fetch the code of {address:?}: {e}"))?;
+    match code.is_empty() {
+        true => Err(format!("No contract deployed at {address:?}")),
+        false => Ok(code),
+    }
+}
+
+/// Calls the function without arguments `name` and checks that it
+/// returns `words` words.
+async fn call_getter<M: Middleware>(
+    client: &M,
+    address: Address,
+    name: &str,
+    selector: Selector,
+    words: usize,
+) -> Result<Bytes, String> {
+    let tx: TypedTransaction = TransactionRequest::new()
+        .to(address)
+        .data(selector.to_vec())
+        .into();
+    let output = client
+        .call(&tx, None)
+        .await
+        .map_err(|e| format!("Calling {name} on {address:?} failed: {e}"))?;
+    if output.len() != 32 * words {
+        return Err(format!("Invalid {name} output from {address:?}: {output}"));
+    }
+    Ok(output)
+}
+
+/// Returns the version of the contract at `address`, or why the
+/// sequencer cannot use it.
+pub async fn contract_version<M: Middleware>(client: &M, address: Address) -> Result<u64, String> {
+    let code = deployed_code(client, address).await?;
+
+    let missing: Vec<String> = required_selectors()
+        .into_iter()
+        .filter(|(_, selector)| !has_selector(&code, *selector))
+        .map(|(signature, _)| signature)
+        .collect();
+    if !missing.is_empty() {
+        return Err(format!(
+            "Contract at {address:?} does not implement {}, it does not match the sequencer's bindings",
+            missing.join(", ")
+        ));
+    }
+
+    let version = match has_selector(&code, VERSION_SELECTOR) {
+        false => 1,
+        true => {
+            let output = call_getter(client, address, "version()", VERSION_SELECTOR, 1).await?;
+            let version = U256::from_big_endian(&output);
+            match version > U256::from(u64::MAX) {
+                true => u64::MAX,
+                false => version.as_u64(),
+            }
+        }
+    };
+    if !SUPPORTED_VERSIONS.contains(&version) {
+        return Err(format!(
+            "Contract at {address:?} has version {version}, this sequencer supports versions {} to {}",
+            SUPPORTED_VERSIONS.start(),
+            SUPPORTED_VERSIONS.end()
+        ));
+    }
+    Ok(version)
+}
+
+/// Checks that the contract at `address` verifies proofs of the circuit
+/// identified by `params`.
+pub async fn check_circuit_params<M: Middleware>(
+    client: &M,
+    address: Address,
+    params: H256,
+) -> Result<(), String> {
+    let code = deployed_code(client, address).await?;
+    if !has_selector(&code, circuit_params_selector()) {
+        return Ok(());
+    }
+    let output = call_getter(
+        client,
+        address,
+        "circuitParams()",
+        circuit_params_selector(),
+        1,
+    )
+    .await?;
+    let current = H256::from_slice(&output);
+    if current == params {
+        return Ok(());
+    }
+
+    if has_selector(&code, next_circuit_params_selector()) {
+        let output = call_getter(
+            client,
+            address,
+            "nextCircuitParams()",
+            next_circuit_params_selector(),
+            2,
+        )
+        .await?;
+        let next = H256::from_slice(&output[..32]);
+        let activation = U256::from_big_endian(&output[32..]);
+        if next == params {
+            let block = client
+                .get_block_number()
+                .await
+                .map_err(|e| format!("Cannot fetch the L1 block number: {e}"))?;
+            if U256::from(block.as_u64()) >= activation {
+                return Ok(());
+            }
+            return Err(format!(
+                "Contract at {address:?} accepts circuit params {params:?} from L1 block {activation}, current block is {block}"
+            ));
+        }
+    }
+    Err(format!(
+        "Contract at {address:?} verifies circuit params {current:?}, the prover has {params:?}"
+    ))
+}
+
+/// Checks that the contract at `address` takes proofs in the compact
+/// encoding of `calldata`, for `compressed_calldata`.
+pub async fn check_compressed_calldata<M: Middleware>(
+    client: &M,
+    address: Address,
+) -> Result<(), String> {
+    let code = deployed_code(client, address).await?;
+    match has_selector(&code, calldata::compressed_selector()) {
+        true => Ok(()),
+        false => Err(format!(
+            "Contract at {address:?} does not implement submitCompressedBlock(bytes), which compressed_calldata requires"
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Bytecode comparing the called selector with `selectors`.
+    fn dispatcher(selectors: &[Selector]) -> Bytes {
+        let mut code = vec![0x60, 0x80, 0x60, 0x40, 0x52];
+        for selector in selectors {
+            code.extend([0x80, PUSH4]);
+            code.extend(selector);
+            code.extend([0x14, 0x61, 0x00, 0x42, 0x57]);
+        }
+        code.into()
+    }
+
+    fn all_selectors() -> Vec<Selector> {
+        required_selectors()
+            .into_iter()
+            .map(|(_, selector)| selector)
+            .collect()
+    }
+
+    /// Responses are popped from the back, so `version()` output is
+    /// pushed before the code.
+    fn mock_l1(code: Bytes, version: Option<u64>) -> Provider<MockProvider> {
+        let (provider, mock) = Provider::mocked();
+        if let Some(version) = version {
+            let mut output = [0u8; 32];
+            U256::from(version).to_big_endian(&mut output);
+            mock.push::<Bytes, _>(Bytes::from(output.to_vec())).unwrap();
+        }
+        mock.push::<Bytes, _>(code).unwrap();
+        provider
+    }
+
+    #[tokio::test]
+    async fn compatible() {
+        let address = Address::repeat_byte(1);
+        let provider = mock_l1(dispatcher(&all_selectors()), None);
+        assert_eq!(contract_version(&provider, address).await, Ok(1));
+
+        let mut selectors = all_selectors();
+        selectors.push(VERSION_SELECTOR);
+        let provider = mock_l1(dispatcher(&selectors), Some(1));
+        assert_eq!(contract_version(&provider, address).await, Ok(1));
+    }
+
+    #[tokio::test]
+    async fn missing_selector() {
+        let address = Address::repeat_byte(1);
+        let provider = mock_l1(dispatcher(&[fusion::RootCall::selector()]), None);
+        let error = contract_version(&provider, address).await.unwrap_err();
+        assert!(error.contains("submitBlock"), "{error}");
+        assert!(!error.contains("root()"), "{error}");
+
+        let provider = mock_l1(Bytes::default(), None);
+        assert!(contract_version(&provider, address)
+            .await
+            .unwrap_err()
+            .contains("No contract deployed"));
+    }
+
+    #[tokio::test]
+    async fn compressed_calldata() {
+        let address = Address::repeat_byte(1);
+        let provider = mock_l1(dispatcher(&all_selectors()), None);
+        let error = check_compressed_calldata(&provider, address)
+            .await
+            .unwrap_err();
+        assert!(error.contains("submitCompressedBlock(bytes)"), "{error}");
+
+        let mut selectors = all_selectors();
+        selectors.push(calldata::compressed_selector());
+        let provider = mock_l1(dispatcher(&selectors), None);
+        assert_eq!(check_compressed_calldata(&provider, address).await, Ok(()));
+    }
+
+    /// Mocks the calls of `check_circuit_params`, pushed in reverse.
+    fn mock_params(
+        selectors: &[Selector],
+        current: H256,
+        next: Option<(H256, u64)>,
+        block: u64,
+    ) -> Provider<MockProvider> {
+        let (provider, mock) = Provider::mocked();
+        if let Some((next, activation)) = next {
+            mock.push::<U64, _>(U64::from(block)).unwrap();
+            let mut output = next.as_bytes().to_vec();
+            let mut word = [0u8; 32];
+            U256::from(activation).to_big_endian(&mut word);
+            output.extend(word);
+            mock.push::<Bytes, _>(Bytes::from(output)).unwrap();
+        }
+        mock.push::<Bytes, _>(Bytes::from(current.as_bytes().to_vec()))
+            .unwrap();
+        mock.push::<Bytes, _>(dispatcher(selectors)).unwrap();
+        provider
+    }
+
+    #[tokio::test]
+    async fn circuit_params() {
+        let address = Address::repeat_byte(1);
+        let params = H256::repeat_byte(2);
+        let other = H256::repeat_byte(3);
+
+        // Contracts without circuitParams() are not checked.
+        let (provider, mock) = Provider::mocked();
+        mock.push::<Bytes, _>(dispatcher(&all_selectors())).unwrap();
+        assert_eq!(
+            check_circuit_params(&provider, address, params).await,
+            Ok(())
+        );
+
+        let mut selectors = all_selectors();
+        selectors.push(circuit_params_selector());
+        let provider = mock_params(&selectors, params, None, 0);
+        assert_eq!(
+            check_circuit_params(&provider, address, params).await,
+            Ok(())
+        );
+
+        let provider = mock_params(&selectors, other, None, 0);
+        let error = check_circuit_params(&provider, address, params)
+            .await
+            .unwrap_err();
+        assert!(error.contains("verifies circuit params"), "{error}");
+    }
+
+    #[tokio::test]
+    async fn circuit_params_migration() {
+        let address = Address::repeat_byte(1);
+        let params = H256::repeat_byte(2);
+        let old = H256::repeat_byte(3);
+        let mut selectors = all_selectors();
+        selectors.extend([circuit_params_selector(), next_circuit_params_selector()]);
+
+        // The new circuit is refused until its activation block.
+        let provider = mock_params(&selectors, old, Some((params, 100)), 99);
+        let error = check_circuit_params(&provider, address, params)
+            .await
+            .unwrap_err();
+        assert!(error.contains("from L1 block 100"), "{error}");
+
+        let provider = mock_params(&selectors, old, Some((params, 100)), 100);
+        assert_eq!(
+            check_circuit_params(&provider, address, params).await,
+            Ok(())
+        );
+
+        // The old circuit is still accepted during the migration.
+        let provider = mock_params(&selectors, params, None, 0);
+        assert_eq!(
+            check_circuit_params(&provider, address, params).await,
+            Ok(())
+        );
+
+        // Neither circuit matches.
+        let provider = mock_params(&selectors, old, Some((H256::repeat_byte(4), 0)), 0);
+        assert!(check_circuit_params(&provider, address, params)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn version_too_new() {
+        let address = Address::repeat_byte(1);
+        let mut selectors = all_selectors();
+        selectors.push(VERSION_SELECTOR);
+        let provider = mock_l1(dispatcher(&selectors), Some(2));
+        let error = contract_version(&provider, address).await.unwrap_err();
+        assert!(error.contains("has version 2"), "{error}");
+    }
+}
@@ -0,0 +1,542 @@
+//! Checks that the roots posted to L1 agree with the ones the sequencer
+//! computes.
+//!
+//! Fusion.sol only stores the root the last proof moved it to, so the
+//! blocks are replayed from the public inputs of the proofs in the L1
+//! transactions: each proof must start from the root the previous ones
+//! led to, show its accounts as they are in the replayed state, and end
+//! at the root the replay reaches. Block headers must carry that same
+//! root.
+
+use ethers::abi::AbiDecode;
+use ethers::providers::Middleware;
+use ethers::types::{H256, U256};
+use rand::Rng;
+use std::{fmt, sync::Arc, time::Duration};
+use tracing::{error, info, warn};
+
+use fusion_api::{BlockHeader, HealthStatus, TxKind, MAX_HEADERS_PER_REQUEST};
+use fusion_config::Config;
+use fusion_l1::fusion;
+use fusion_prover::state::{Account, State};
+use fusion_prover::PUBLIC_INPUTS;
+
+use crate::calldata;
+use crate::metrics::Metrics;
+use crate::node::{failover_provider, l1_endpoints, ProviderSettings};
+use crate::sequencer::{genesis_state, Shared};
+use crate::server::{connect, request_context};
+use crate::transition::apply_transition;
+
+/// The public inputs of a proof L1 accepted.
+#[derive(Clone, Debug)]
+pub struct PostedProof {
+    pub block: u64,
+    pub l1_tx_hash: H256,
+    pub input: Vec<U256>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Inconsistency {
+    /// The proof does not start from the expected root.
+    PreRoot {
+        block: u64,
+        expected: U256,
+        posted: U256,
+    },
+    /// The proof does not end at the expected root.
+    PostRoot {
+        block: u64,
+        expected: U256,
+        posted: U256,
+    },
+    /// An account of the proof differs from the replayed state.
+    Leaf {
+        block: u64,
+        account: U256,
+        field: &'static str,
+        expected: U256,
+        posted: U256,
+    },
+    /// The header of the block has another state root than the replay.
+    HeaderRoot {
+        block: u64,
+        expected: U256,
+        header: U256,
+    },
+    /// The proof cannot be replayed.
+    Invalid { block: u64, reason: String },
+}
+
+impl Inconsistency {
+    pub fn block(&self) -> u64 {
+        match self {
+            Inconsistency::PreRoot { block, .. }
+            | Inconsistency::PostRoot { block, .. }
+            | Inconsistency::Leaf { block, .. }
+            | Inconsistency::HeaderRoot { block, .. }
+            | Inconsistency::Invalid { block, .. } => *block,
+        }
+    }
+}
+
+impl fmt::Display for Inconsistency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Inconsistency::PreRoot {
+                block,
+                expected,
+                posted,
+            } => write!(
+                f,
+                "block {block}: proof starts from root {posted}, expected {expected}"
+            ),
+            Inconsistency::PostRoot {
+                block,
+                expected,
+                posted,
+            } => write!(
+                f,
+                "block {block}: proof ends at root {posted}, expected {expected}"
+            ),
+            Inconsistency::Leaf {
+                block,
+                account,
+                field,
+                expected,
+                posted,
+            } => write!(
+                f,
+                "block {block}: account {account} has {field} {posted} in the proof, {expected} in the replay"
+            ),
+            Inconsistency::HeaderRoot {
+                block,
+                expected,
+                header,
+            } => write!(
+                f,
+                "block {block}: header has state root {header}, the replay reached {expected}"
+            ),
+            Inconsistency::Invalid { block, reason } => write!(f, "block {block}: {reason}"),
+        }
+    }
+}
+
+/// Replays `posted`, in the order L1 accepted it, from `genesis`, and
+/// compares every step with the proofs and with `headers`.
+pub fn replay(
+    genesis: State,
+    posted: &[PostedProof],
+    headers: &[BlockHeader],
+) -> Vec<Inconsistency> {
+    let mut state = genesis;
+    let mut found = vec![];
+    for (i, proof) in posted.iter().enumerate() {
+        let block = proof.block;
+        let input = &proof.input;
+        if input.len() != PUBLIC_INPUTS {
+            found.push(Inconsistency::Invalid {
+                block,
+                reason: format!("{} public inputs, expected {PUBLIC_INPUTS}", input.len()),
+            });
+            continue;
+        }
+        if input[2] > U256::from(2) {
+            found.push(Inconsistency::Invalid {
+                block,
+                reason: format!("invalid transaction kind {}", input[2]),
+            });
+            continue;
+        }
+        let kind = TxKind::from(input[2]);
+
+        if state.root() != input[0] {
+            found.push(Inconsistency::PreRoot {
+                block,
+                expected: state.root(),
+                posted: input[0],
+            });
+        }
+
+        let (sender, to) = (input[14], input[17]);
+        let replayed_to = match kind {
+            // The recipient of a withdrawal is an L1 address.
+            TxKind::Withdraw => Account::new(to, 0.into(), 0.into()),
+            _ => state.get(&to),
+        };
+        let accounts = [
+            (state.get(&sender), &input[14..17]),
+            (replayed_to, &input[17..20]),
+        ];
+        for (replayed, leaf) in accounts {
+            for (field, expected, posted) in [
+                ("balance", replayed.balance, leaf[1]),
+                ("nonce", replayed.nonce, leaf[2]),
+            ] {
+                if expected != posted {
+                    found.push(Inconsistency::Leaf {
+                        block,
+                        account: replayed.id,
+                        field,
+                        expected,
+                        posted,
+                    });
+                }
+            }
+        }
+
+        let (nonce, value) = (input[7], input[8]);
+        if !matches!(kind, TxKind::Deposit) && state.get(&sender).balance < value {
+            found.push(Inconsistency::Invalid {
+                block,
+                reason: format!("sender {sender} cannot afford {value} in the replay"),
+            });
+            continue;
+        }
+        state = match apply_transition(state.clone(), &kind, sender, to, nonce, value) {
+            Ok(state) => state,
+            Err(e) => {
+                found.push(Inconsistency::Invalid {
+                    block,
+                    reason: format!("{e} in the replay"),
+                });
+                continue;
+            }
+        };
+        if state.root() != input[1] {
+            found.push(Inconsistency::PostRoot {
+                block,
+                expected: state.root(),
+                posted: input[1],
+            });
+        }
+
+        let last_of_block = posted.get(i + 1).map_or(true, |next| next.block != block);
+        match headers.iter().find(|header| header.number == block) {
+            Some(header) if last_of_block && header.state_root != state.root() => {
+                found.push(Inconsistency::HeaderRoot {
+                    block,
+                    expected: state.root(),
+                    header: header.state_root,
+                })
+            }
+            _ => (),
+        }
+    }
+    found
+}
+
+/// Checks that the proofs of the block of `header` lead from
+/// `parent_root` to its state root, without replaying the state.
+pub fn check_roots(
+    header: &BlockHeader,
+    parent_root: U256,
+    posted: &[PostedProof],
+) -> Vec<Inconsistency> {
+    let block = header.number;
+    let mut expected = parent_root;
+    let mut found = vec![];
+    for proof in posted {
+        let [pre_root, post_root] = match proof.input[..] {
+            [pre_root, post_root, ..] => [pre_root, post_root],
+            _ => {
+                found.push(Inconsistency::Invalid {
+                    block,
+                    reason: format!("{} public inputs", proof.input.len()),
+                });
+                return found;
+            }
+        };
+        if pre_root != expected {
+            found.push(Inconsistency::PreRoot {
+                block,
+                expected,
+                posted: pre_root,
+            });
+        }
+        expected = post_root;
+    }
+    if expected != header.state_root {
+        found.push(Inconsistency::PostRoot {
+            block,
+            expected: header.state_root,
+            posted: expected,
+        });
+    }
+    found
+}
+
+/// The public inputs of every proof in a call to `submitBlock`, or to
+/// `submitCompressedBlock`, see `calldata`.
+pub fn decode_submission(calldata: &[u8]) -> Result<Vec<Vec<U256>>, String> {
+    if let Some(proofs) = calldata::decode_call(calldata) {
+        return proofs.map(|proofs| proofs.into_iter().map(|proof| proof.input).collect());
+    }
+    match fusion::FusionCalls::decode(calldata) {
+        Ok(fusion::FusionCalls::SubmitBlock(fusion::SubmitBlockCall { l_2_block })) => {
+            Ok(l_2_block.into_iter().map(|proof| proof.input).collect())
+        }
+        Ok(_) => Err("Not a call to submitBlock".to_string()),
+        Err(e) => Err(format!("Invalid calldata: {e}")),
+    }
+}
+
+/// The proofs of the blocks of `headers` that L1 accepted, in order.
+pub async fn fetch_posted<M: Middleware>(
+    client: &M,
+    headers: &[BlockHeader],
+) -> Result<Vec<PostedProof>, String> {
+    let mut posted = vec![];
+    for header in headers {
+        for l1_tx_hash in &header.l1_tx_hashes {
+            // Failed attempts are recorded too.
+            let receipt = client
+                .get_transaction_receipt(*l1_tx_hash)
+                .await
+                .map_err(|e| e.to_string())?;
+            if receipt.and_then(|receipt| receipt.status) != Some(1u64.into()) {
+                continue;
+            }
+            let l1_tx = client
+                .get_transaction(*l1_tx_hash)
+                .await
+                .map_err(|e| e.to_string())?
+                .ok_or_else(|| format!("L1 transaction {l1_tx_hash:?} not found"))?;
+            for input in decode_submission(&l1_tx.input)
+                .map_err(|e| format!("L1 transaction {l1_tx_hash:?}: {e}"))?
+            {
+                posted.push(PostedProof {
+                    block: header.number,
+                    l1_tx_hash: *l1_tx_hash,
+                    input,
+                });
+            }
+        }
+    }
+    Ok(posted)
+}
+
+/// Headers of blocks 1 to `to` from the running sequencer.
+async fn fetch_headers(config: &Config, to: u64) -> Result<Vec<BlockHeader>, String> {
+    let sequencer = connect(config).await?;
+
+    let mut headers = vec![];
+    let mut from = 1;
+    while from <= to {
+        let page_end = to.min(from + MAX_HEADERS_PER_REQUEST - 1);
+        headers.extend(
+            sequencer
+                .get_headers(request_context(config), from, page_end)
+                .await
+                .map_err(|e| e.to_string())??,
+        );
+        from = page_end + 1;
+    }
+    Ok(headers)
+}
+
+/// Replays blocks 1 to `to` of the running sequencer from the proofs
+/// L1 accepted, and returns the inconsistencies from block `from` on.
+pub async fn check_range(
+    config: &Config,
+    from: u64,
+    to: u64,
+) -> Result<Vec<Inconsistency>, String> {
+    if from == 0 || from > to {
+        return Err(format!("Invalid block range {from}..={to}"));
+    }
+    let headers = fetch_headers(config, to).await?;
+    let settings = ProviderSettings::from_config(config);
+    let provider = failover_provider(&l1_endpoints(config), &settings, Metrics::default())
+        .map_err(|e| e.to_string())?;
+    let posted = fetch_posted(&provider, &headers).await?;
+    info!(proofs = posted.len(), to, "Replaying blocks from L1");
+    Ok(replay(genesis_state(config), &posted, &headers)
+        .into_iter()
+        .filter(|inconsistency| inconsistency.block() >= from)
+        .collect())
+}
+
+/// Every `interval`, checks the roots of a random block whose proofs were
+/// all accepted, and raises an alert if they do not match.
+pub async fn run_sampler<M: Middleware>(
+    shared: Shared,
+    client: Arc<M>,
+    genesis_root: U256,
+    interval: Duration,
+) {
+    loop {
+        tokio::time::sleep(interval).await;
+        // Proofs are submitted in order, so every block up to the last
+        // attested one was fully accepted.
+        let Some(attested) = shared
+            .attestations
+            .lock()
+            .unwrap()
+            .last()
+            .map(|attestation| attestation.block_number)
+        else {
+            continue;
+        };
+        let (header, parent_root) = {
+            let blocks = shared.blocks.lock().unwrap();
+            let attested = (attested as usize).min(blocks.len());
+            if attested == 0 {
+                continue;
+            }
+            let i = rand::thread_rng().gen_range(0..attested);
+            let parent_root = match i {
+                0 => genesis_root,
+                _ => blocks[i - 1].header.state_root,
+            };
+            (blocks[i].header.clone(), parent_root)
+        };
+
+        let found = match fetch_posted(client.as_ref(), &[header.clone()]).await {
+            Ok(posted) => check_roots(&header, parent_root, &posted),
+            Err(e) => {
+                warn!(block = header.number, error = %e, "Could not check the roots");
+                continue;
+            }
+        };
+        for inconsistency in &found {
+            error!(
+                block = inconsistency.block(),
+                "ALERT: L1 and the sequencer disagree, {inconsistency}"
+            );
+        }
+        if let Some(first) = found.first() {
+            let mut health = shared.health.lock().unwrap();
+            health.status = HealthStatus::Degraded;
+            health.reason = Some(format!("L1 and the sequencer disagree, {first}"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ethers::abi::AbiEncode;
+    use fusion_config::Config;
+
+    use crate::dev;
+
+    /// Three blocks of one transfer between two dev accounts, with the
+    /// proofs L1 would have received for them.
+    fn recorded() -> (State, Vec<BlockHeader>, Vec<PostedProof>) {
+        let config = Config {
+            dev: true,
+            dev_accounts: 2,
+            ..Default::default()
+        };
+        let genesis = dev::genesis_state(&config);
+        let mut state = genesis.clone();
+        let mut headers = vec![];
+        let mut posted = vec![];
+        for block in 1..=3u64 {
+            let from = block as u32 % 2;
+            let input = dev::apply_dev_transfer(&mut state, from, 1 - from, 10 * block);
+            let l1_tx_hash = H256::from_low_u64_be(block);
+            posted.push(PostedProof {
+                block,
+                l1_tx_hash,
+                input,
+            });
+            headers.push(BlockHeader {
+                number: block,
+                parent_hash: U256::zero(),
+                state_root: state.root(),
+                tx_root: U256::zero(),
+                timestamp: 0,
+                l1_root: None,
+                l1_tx_hashes: vec![l1_tx_hash],
+            });
+        }
+        (genesis, headers, posted)
+    }
+
+    #[test]
+    fn consistent() {
+        let (genesis, headers, posted) = recorded();
+        assert_eq!(replay(genesis.clone(), &posted, &headers), vec![]);
+        let mut parent_root = genesis.root();
+        for (header, proof) in headers.iter().zip(&posted) {
+            assert_eq!(
+                check_roots(header, parent_root, std::slice::from_ref(proof)),
+                vec![]
+            );
+            parent_root = header.state_root;
+        }
+    }
+
+    #[test]
+    fn corrupted_leaf() {
+        let (genesis, headers, mut posted) = recorded();
+        let sender = posted[1].input[14];
+        let balance = posted[1].input[15];
+        posted[1].input[15] = balance + 1;
+        assert_eq!(
+            replay(genesis, &posted, &headers),
+            vec![Inconsistency::Leaf {
+                block: 2,
+                account: sender,
+                field: "balance",
+                expected: balance,
+                posted: balance + 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn corrupted_root() {
+        let (genesis, mut headers, posted) = recorded();
+        let correct = headers[1].state_root;
+        headers[1].state_root = correct + 1;
+        assert_eq!(
+            replay(genesis, &posted, &headers),
+            vec![Inconsistency::HeaderRoot {
+                block: 2,
+                expected: correct,
+                header: correct + 1,
+            }]
+        );
+        assert_eq!(
+            check_roots(&headers[1], headers[0].state_root, &posted[1..2]),
+            vec![Inconsistency::PostRoot {
+                block: 2,
+                expected: correct + 1,
+                posted: correct,
+            }]
+        );
+        // The next block starts from the header, not from what L1 has.
+        assert_eq!(
+            check_roots(&headers[2], headers[1].state_root, &posted[2..3]),
+            vec![Inconsistency::PreRoot {
+                block: 3,
+                expected: correct + 1,
+                posted: correct,
+            }]
+        );
+    }
+
+    #[test]
+    fn submission_calldata() {
+        let (_, _, posted) = recorded();
+        let proof = fusion::TxProof {
+            input: posted[0].input.clone(),
+            ..Default::default()
+        };
+        let compressed = calldata::compressed_call(&[proof.clone()]);
+        let calldata = fusion::SubmitBlockCall { l_2_block: [proof] }.encode();
+        assert_eq!(
+            decode_submission(&calldata),
+            Ok(vec![posted[0].input.clone()])
+        );
+        assert_eq!(
+            decode_submission(&compressed),
+            Ok(vec![posted[0].input.clone()])
+        );
+        assert!(decode_submission(&fusion::RootCall.encode()).is_err());
+        assert!(decode_submission(&calldata[..4]).is_err());
+    }
+}
@@ -0,0 +1,367 @@
+//! Inspection of the transaction encodings people paste around while
+//! debugging.
+//!
+//! The input is recognized as a `SignedTx` in JSON, a transaction in the
+//! binary encoding of `submit_transaction_raw`, or the calldata of a
+//! `submitBlock` or `submitCompressedBlock` call to L1, the latter two in
+//! hex or base64.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ethers::contract::EthCall;
+use ethers::types::{Address, U256};
+use ethers::utils::hex;
+use std::fmt;
+
+use fusion_api::{
+    decode_signed_tx, hash_tx, SignedTx, Tx, TxKind, LEGACY_RAW_TX_SIZE, NATIVE_TOKEN,
+    PRE_TOKEN_RAW_TX_SIZE, RAW_TX_SIZE,
+};
+use fusion_l1::fusion;
+use fusion_prover::PUBLIC_INPUTS;
+use fusion_types::{PublicKey, ToU256};
+use fusion_wallet::units::display_value;
+
+use crate::calldata;
+use crate::consistency::decode_submission;
+
+/// Size of the arguments of `submitBlock` in 32-byte words: the offsets
+/// of the block and of its proof, the points `a`, `b` and `c`, the offset
+/// and length of the public inputs, then the inputs.
+const SUBMISSION_WORDS: usize = 2 + 8 + 2 + PUBLIC_INPUTS;
+
+#[derive(Clone, Debug)]
+pub struct DecodedTx {
+    pub signed_tx: SignedTx,
+    pub hash: U256,
+    /// Whether the signature is the sender's.
+    pub signature: Result<(), String>,
+}
+
+impl DecodedTx {
+    fn new(signed_tx: SignedTx) -> Result<Self, String> {
+        let tx = &signed_tx.tx;
+        for (name, key) in [("sender", tx.sender), ("to", tx.to)] {
+            if !fusion_wallet::is_public_key(&key) {
+                return Err(format!(
+                    "The {name} field {key:#x} is not a Fusion public key"
+                ));
+            }
+        }
+        Ok(DecodedTx {
+            hash: hash_tx(tx),
+            signature: fusion_wallet::verify_tx_signature(&signed_tx).map_err(|e| e.to_string()),
+            signed_tx,
+        })
+    }
+}
+
+/// A transaction of a `submitBlock` call, with the roots its proof
+/// claims.
+#[derive(Clone, Debug)]
+pub struct DecodedProof {
+    pub pre_root: U256,
+    pub post_root: U256,
+    pub tx: DecodedTx,
+}
+
+#[derive(Clone, Debug)]
+pub enum Decoded {
+    Json(DecodedTx),
+    Raw(DecodedTx),
+    Batch {
+        proofs: Vec<DecodedProof>,
+        /// The root L1 moves to if it accepts the call.
+        post_root: U256,
+    },
+}
+
+/// Decodes `input`, guessing its kind. Hex may start with `0x`, and is
+/// preferred when the input is both valid hex and valid base64.
+pub fn decode(input: &str) -> Result<Decoded, String> {
+    let input = input.trim();
+    if input.starts_with('{') {
+        let signed_tx: SignedTx =
+            serde_json::from_str(input).map_err(|e| format!("Invalid transaction JSON: {e}"))?;
+        return Ok(Decoded::Json(DecodedTx::new(signed_tx)?));
+    }
+
+    let bytes = match hex::decode(input.strip_prefix("0x").unwrap_or(input)) {
+        Ok(bytes) => bytes,
+        Err(_) if input.starts_with("0x") => {
+            return Err("Invalid hex after `0x`".to_string());
+        }
+        Err(_) => STANDARD
+            .decode(input)
+            .map_err(|e| format!("Neither JSON, hex nor base64: {e}"))?,
+    };
+    let selector = fusion::SubmitBlockCall::selector();
+    if [RAW_TX_SIZE, PRE_TOKEN_RAW_TX_SIZE, LEGACY_RAW_TX_SIZE].contains(&bytes.len()) {
+        let signed_tx = decode_signed_tx(&bytes)?;
+        Ok(Decoded::Raw(DecodedTx::new(signed_tx)?))
+    } else if bytes.starts_with(&selector) {
+        decode_batch(&bytes)
+    } else if bytes.starts_with(&calldata::compressed_selector()) {
+        decode_proofs(&bytes)
+    } else {
+        Err(format!(
+            "{} bytes are neither a raw transaction, which has {RAW_TX_SIZE}, nor submitBlock calldata, which starts with 0x{}",
+            bytes.len(),
+            hex::encode(selector)
+        ))
+    }
+}
+
+/// Name of the `word`-th argument word of `submitBlock`.
+fn submission_word(word: usize) -> String {
+    match word {
+        0 => "the offset of l2Block".to_string(),
+        1 => "the offset of l2Block[0]".to_string(),
+        2..=3 => format!("proof.a[{}]", word - 2),
+        4..=7 => format!("proof.b[{}][{}]", (word - 4) / 2, (word - 4) % 2),
+        8..=9 => format!("proof.c[{}]", word - 8),
+        10 => "the offset of input".to_string(),
+        11 => "the length of input".to_string(),
+        _ => format!("input[{}]", word - 12),
+    }
+}
+
+fn decode_batch(calldata: &[u8]) -> Result<Decoded, String> {
+    let expected = 4 + 32 * SUBMISSION_WORDS;
+    if calldata.len() < expected {
+        let word = (calldata.len() - 4) / 32;
+        return Err(format!(
+            "submitBlock calldata has {} bytes instead of {expected}, truncated at byte {} in {}",
+            calldata.len(),
+            calldata.len(),
+            submission_word(word)
+        ));
+    }
+    if calldata.len() > expected {
+        return Err(format!(
+            "submitBlock calldata has {} bytes instead of {expected}, unexpected data from byte {expected}",
+            calldata.len()
+        ));
+    }
+    decode_proofs(calldata)
+}
+
+/// The proofs of `submitBlock` or `submitCompressedBlock` calldata.
+fn decode_proofs(calldata: &[u8]) -> Result<Decoded, String> {
+    let mut proofs = vec![];
+    for input in decode_submission(calldata)? {
+        if input.len() != PUBLIC_INPUTS {
+            return Err(format!(
+                "The proof has {} public inputs instead of {PUBLIC_INPUTS}",
+                input.len()
+            ));
+        }
+        let kind = match input[2] {
+            kind if kind < 3.into() => TxKind::from(kind),
+            kind => return Err(format!("Invalid transaction kind {kind} in input[2]")),
+        };
+        let bound = |i: usize| -> Result<Option<u64>, String> {
+            match input[i] {
+                bound if bound.is_zero() => Ok(None),
+                bound if bound <= u64::MAX.into() => Ok(Some(bound.as_u64())),
+                bound => Err(format!(
+                    "Timestamp {bound} in input[{i}] does not fit 64 bits"
+                )),
+            }
+        };
+        let tx = Tx {
+            kind,
+            sender: PublicKey::from_point(input[3], input[4]).to_u256(),
+            to: PublicKey::from_point(input[5], input[6]).to_u256(),
+            nonce: input[7],
+            value: input[8],
+            not_before: bound(9)?,
+            not_after: bound(10)?,
+            chain_id: match input[20] {
+                chain_id if chain_id.is_zero() => None,
+                chain_id if chain_id <= u64::MAX.into() => Some(chain_id.as_u64()),
+                chain_id => {
+                    return Err(format!(
+                        "Chain id {chain_id} in input[20] does not fit 64 bits"
+                    ))
+                }
+            },
+            token: match input[21] {
+                token if token.bits() <= 160 => {
+                    let mut word = [0u8; 32];
+                    token.to_big_endian(&mut word);
+                    Address::from_slice(&word[12..])
+                }
+                token => return Err(format!("Token {token:#x} in input[21] is not an address")),
+            },
+        };
+        let signature = fusion_wallet::signature_from_parts(input[11], input[12], input[13]);
+        proofs.push(DecodedProof {
+            pre_root: input[0],
+            post_root: input[1],
+            tx: DecodedTx::new(SignedTx { tx, signature })?,
+        });
+    }
+    let post_root = proofs.last().map_or(U256::zero(), |proof| proof.post_root);
+    Ok(Decoded::Batch { proofs, post_root })
+}
+
+fn bound(bound: Option<u64>) -> String {
+    bound.map_or("-".to_string(), |bound| bound.to_string())
+}
+
+impl fmt::Display for DecodedTx {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let tx = &self.signed_tx.tx;
+        writeln!(f, "kind        {:?}", tx.kind)?;
+        writeln!(f, "sender      {:#x}", tx.sender)?;
+        writeln!(f, "to          {:#x}", tx.to)?;
+        writeln!(f, "nonce       {}", tx.nonce)?;
+        writeln!(f, "value       {}", display_value(tx.value))?;
+        if tx.token != NATIVE_TOKEN {
+            writeln!(f, "token       {:#x}", tx.token)?;
+        }
+        writeln!(f, "not_before  {}", bound(tx.not_before))?;
+        writeln!(f, "not_after   {}", bound(tx.not_after))?;
+        writeln!(f, "hash        {:#x}", self.hash)?;
+        match &self.signature {
+            Ok(()) => write!(f, "signature   valid, signed by the sender"),
+            Err(e) => write!(f, "signature   INVALID: {e}"),
+        }
+    }
+}
+
+impl fmt::Display for Decoded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Decoded::Json(tx) => write!(f, "Signed transaction (JSON)\n{tx}"),
+            Decoded::Raw(tx) => write!(f, "Signed transaction (binary)\n{tx}"),
+            Decoded::Batch { proofs, post_root } => {
+                writeln!(f, "submitBlock call with {} transaction(s)", proofs.len())?;
+                for (i, proof) in proofs.iter().enumerate() {
+                    writeln!(f, "\nTransaction {i}")?;
+                    writeln!(f, "pre_root    {:#x}", proof.pre_root)?;
+                    writeln!(f, "post_root   {:#x}", proof.post_root)?;
+                    writeln!(f, "{}", proof.tx)?;
+                }
+                write!(f, "\nClaimed post-root {post_root:#x}")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ethers::abi::AbiEncode;
+    use fusion_api::encode_signed_tx;
+    use fusion_prover::CircuitInput;
+
+    use crate::dev;
+    use crate::transition::apply_tx;
+
+    fn signed_tx() -> SignedTx {
+        let tx = Tx {
+            value: U256::exp10(17),
+            not_after: Some(1_700_000_000),
+            ..dev::dev_transfer_tx(0, 1, 1, 0)
+        };
+        dev::sign_tx(tx, &dev::dev_private_key(0))
+    }
+
+    fn decoded_tx(decoded: Decoded) -> DecodedTx {
+        match decoded {
+            Decoded::Json(tx) | Decoded::Raw(tx) => tx,
+            Decoded::Batch { .. } => panic!("not a transaction"),
+        }
+    }
+
+    #[test]
+    fn json() {
+        let signed_tx = signed_tx();
+        let decoded = decode(&serde_json::to_string(&signed_tx).unwrap()).unwrap();
+        assert!(matches!(decoded, Decoded::Json(_)));
+        assert!(decoded.to_string().contains("0.1 eth"));
+        let decoded = decoded_tx(decoded);
+        assert_eq!(decoded.hash, hash_tx(&signed_tx.tx));
+        assert_eq!(decoded.signature, Ok(()));
+
+        let mut tampered = signed_tx;
+        tampered.tx.value += 1;
+        let decoded = decoded_tx(decode(&serde_json::to_string(&tampered).unwrap()).unwrap());
+        assert!(decoded.signature.is_err());
+        assert!(decode("{\"tx\": {}}").unwrap_err().contains("JSON"));
+        let mut invalid_sender = signed_tx();
+        invalid_sender.tx.sender = U256::MAX;
+        assert!(decode(&serde_json::to_string(&invalid_sender).unwrap())
+            .unwrap_err()
+            .contains("sender"));
+    }
+
+    #[test]
+    fn raw() {
+        let signed_tx = signed_tx();
+        let bytes = encode_signed_tx(&signed_tx).unwrap();
+        for input in [
+            format!("0x{}", hex::encode(&bytes)),
+            hex::encode(&bytes),
+            STANDARD.encode(&bytes),
+        ] {
+            let decoded = decode(&input).unwrap();
+            assert!(matches!(decoded, Decoded::Raw(_)));
+            let decoded = decoded_tx(decoded);
+            assert_eq!(decoded.hash, hash_tx(&signed_tx.tx));
+            assert_eq!(decoded.signature, Ok(()));
+        }
+
+        let mut invalid_kind = bytes.clone();
+        invalid_kind[0] = 7;
+        assert_eq!(
+            decode(&hex::encode(invalid_kind)).unwrap_err(),
+            "Invalid transaction kind 7"
+        );
+        assert!(decode(&hex::encode(&bytes[1..]))
+            .unwrap_err()
+            .contains("neither"));
+        assert!(decode("0xzz").unwrap_err().contains("hex"));
+    }
+
+    #[test]
+    fn batch() {
+        let signed_tx = signed_tx();
+        let state = dev::genesis_state(&fusion_config::Config {
+            dev_accounts: 2,
+            ..Default::default()
+        });
+        let states = vec![state.clone(), apply_tx(state, &signed_tx.tx).unwrap()];
+        let input = CircuitInput::batch(&[signed_tx.clone()], &states)
+            .remove(0)
+            .unwrap();
+        let proof = fusion::TxProof {
+            input: input.public_inputs(),
+            ..Default::default()
+        };
+        let compressed = hex::encode(calldata::compressed_call(&[proof.clone()]));
+        let calldata = hex::encode(fusion::SubmitBlockCall { l_2_block: [proof] }.encode());
+
+        for calldata in [&calldata, &compressed] {
+            let Decoded::Batch { proofs, post_root } = decode(calldata).unwrap() else {
+                panic!("not a batch");
+            };
+            assert_eq!(proofs.len(), 1);
+            assert_eq!(proofs[0].pre_root, states[0].root());
+            assert_eq!(post_root, states[1].root());
+            assert_eq!(proofs[0].tx.hash, hash_tx(&signed_tx.tx));
+            assert_eq!(proofs[0].tx.signature, Ok(()));
+        }
+
+        // Cut in the middle of the value, input[8].
+        let truncated = &calldata[..2 * (4 + 32 * 20 + 10)];
+        assert_eq!(
+            decode(truncated).unwrap_err(),
+            "submitBlock calldata has 654 bytes instead of 1092, truncated at byte 654 in input[8]"
+        );
+        assert!(decode(&format!("{calldata}00"))
+            .unwrap_err()
+            .contains("from byte 1092"));
+    }
+}
@@ -0,0 +1,130 @@
+//! Deployment of the L1 contract on local chains, for `--dev-l1`.
+
+use ethers::prelude::*;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use fusion_config::Config;
+use fusion_l1::fusion;
+
+use crate::metrics::Metrics;
+use crate::node::*;
+
+/// Chain ids of anvil and hardhat. Contracts are never deployed
+/// automatically anywhere else.
+const LOCAL_CHAIN_IDS: [u64; 2] = [31337, 1337];
+
+/// Where the address of the deployed contract is kept, so later runs on
+/// the same chain reuse it.
+fn address_path(config: &Config) -> PathBuf {
+    Path::new(&config.database_path).join("l1_contract")
+}
+
+/// `legacyTxsUntil` of the contract: 0 when transactions without a chain
+/// id are refused, the maximum when they are accepted forever.
+pub fn legacy_txs_until(config: &Config) -> U256 {
+    match (config.accept_legacy_txs, config.legacy_txs_until) {
+        (false, _) => U256::zero(),
+        (true, 0) => U256::MAX,
+        (true, until) => until.into(),
+    }
+}
+
+/// The contract to use with `--dev-l1`: the configured one if any, else
+/// the one deployed by a previous run if it is still on the chain, else
+/// a newly deployed one.
+pub async fn dev_l1_contract(config: &Config) -> anyhow::Result<Address> {
+    if !config.fusion_l1_contract.is_zero() {
+        return Ok(config.fusion_l1_contract);
+    }
+    let node = Node::from_config(config, Metrics::default()).await?;
+    let client = node.http_client;
+
+    let chain_id = client.get_chainid().await?.as_u64();
+    if !LOCAL_CHAIN_IDS.contains(&chain_id) {
+        anyhow::bail!(
+            "Refusing to deploy the contract on chain {chain_id}, --dev-l1 only deploys on local chains"
+        );
+    }
+
+    let path = address_path(config);
+    if let Ok(address) = std::fs::read_to_string(&path) {
+        let address: Address = address.trim().parse()?;
+        if !client.get_code(address, None).await?.is_empty() {
+            info!(?address, "Reusing the L1 contract");
+            return Ok(address);
+        }
+    }
+
+    let args = (U256::from(config.chain_id), legacy_txs_until(config));
+    let contract = fusion::Fusion::deploy(client, args)?
+        .gas(10000000)
+        .send()
+        .await?;
+    let address = contract.address();
+    std::fs::create_dir_all(&config.database_path)?;
+    std::fs::write(&path, format!("{address:?}"))?;
+    info!(?address, "Deployed the L1 contract");
+    Ok(address)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anvil::{spawn, NodeConfig};
+    use ethers::core::utils::hex;
+
+    #[test]
+    fn legacy_window() {
+        let mut config = Config::default();
+        assert_eq!(legacy_txs_until(&config), U256::MAX);
+        config.legacy_txs_until = 1_700_000_000;
+        assert_eq!(legacy_txs_until(&config), 1_700_000_000u64.into());
+        config.accept_legacy_txs = false;
+        assert_eq!(legacy_txs_until(&config), U256::zero());
+    }
+
+    #[tokio::test]
+    async fn configured_contract_is_never_deployed() {
+        let config = Config {
+            eth_rpc_url: "http://localhost:1".to_string(),
+            fusion_l1_contract: Address::repeat_byte(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            dev_l1_contract(&config).await.unwrap(),
+            Address::repeat_byte(1)
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn deployed_contract_is_reused() {
+        let anvil_config = NodeConfig::test();
+        let (_api, handle) = spawn(anvil_config.clone()).await;
+
+        let database_path =
+            std::env::temp_dir().join(format!("fusion-dev-l1-{}", rand::random::<u64>()));
+        let config = Config {
+            eth_rpc_url: handle.http_endpoint(),
+            eth_private_key: hex::encode(anvil_config.genesis_accounts[0].signer().to_bytes()),
+            database_path: database_path.display().to_string(),
+            ..Default::default()
+        };
+        let address = dev_l1_contract(&config).await.unwrap();
+        let provider = handle.http_provider();
+        assert!(!provider.get_code(address, None).await.unwrap().is_empty());
+        assert_eq!(dev_l1_contract(&config).await.unwrap(), address);
+
+        // A fresh chain does not have the contract anymore.
+        let (_api, handle) = spawn(anvil_config.clone()).await;
+        let config = Config {
+            eth_rpc_url: handle.http_endpoint(),
+            ..config
+        };
+        let address = dev_l1_contract(&config).await.unwrap();
+        let provider = handle.http_provider();
+        assert!(!provider.get_code(address, None).await.unwrap().is_empty());
+
+        std::fs::remove_dir_all(database_path).unwrap();
+    }
+}
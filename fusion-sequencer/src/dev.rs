@@ -0,0 +1,336 @@
+//! Helpers for dev mode. Nothing in here is secure,
+//! the keys are public and only meant for local testing.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use ethers::signers::{coins_bip39::English, MnemonicBuilder};
+use ethers::types::U256;
+use serde_json::Value;
+
+use fusion_api::{SignedTx, Tx, TxKind, NATIVE_TOKEN};
+use fusion_config::Config;
+use fusion_prover::state::{Account, State};
+use fusion_types::{PrivateKey, PublicKey, ToU256};
+use fusion_wallet::units::parse_value;
+
+/// The mnemonic anvil and hardhat derive their test accounts from.
+pub const DEV_MNEMONIC: &str = "test test test test test test test test test test test junk";
+
+/// Well-known private key of the dev faucet account.
+pub const FAUCET_PRIVATE_KEY: &str =
+    "4242424242424242424242424242424242424242424242424242424242424242424242424";
+
+/// Genesis balance of the faucet account, 10^9 * 10^18.
+pub fn faucet_balance() -> U256 {
+    U256::exp10(27)
+}
+
+pub fn faucet_private_key() -> PrivateKey {
+    FAUCET_PRIVATE_KEY.to_string().into()
+}
+
+pub fn faucet_public_key() -> PublicKey {
+    fusion_wallet::new_public_key(&faucet_private_key())
+}
+
+/// Derives the private key of the `index`-th dev account along the
+/// standard Ethereum path `m/44'/60'/0'/0/{index}`, so that the raw
+/// key is the same one anvil or MetaMask derive from `DEV_MNEMONIC`.
+pub fn dev_private_key(index: u32) -> PrivateKey {
+    let wallet = MnemonicBuilder::<English>::default()
+        .phrase(DEV_MNEMONIC)
+        .index(index)
+        .and_then(|builder| builder.build())
+        .expect("the dev mnemonic is valid");
+    U256::from_big_endian(wallet.signer().to_bytes().as_slice()).into()
+}
+
+pub fn dev_accounts(config: &Config) -> Vec<(PrivateKey, PublicKey)> {
+    (0..config.dev_accounts)
+        .map(|index| {
+            let sk = dev_private_key(index);
+            let pk = fusion_wallet::new_public_key(&sk);
+            (sk, pk)
+        })
+        .collect()
+}
+
+pub fn print_dev_accounts(config: &Config) {
+    println!("==================================================================");
+    println!("WARNING: dev accounts are derived from a public mnemonic.");
+    println!("Never use them outside of dev mode.");
+    println!("Mnemonic: {DEV_MNEMONIC}");
+    println!("Each account is funded with {}", config.dev_account_balance);
+    println!("==================================================================");
+    for (index, (sk, pk)) in dev_accounts(config).iter().enumerate() {
+        println!("({index}) Public key:  {}", pk.to_u256());
+        println!("    Address:     {}", pk.address());
+        println!("    Private key: {}", sk.to_string());
+    }
+    if let Some(tx) = quick_start_tx(config) {
+        println!("Send a first transfer, from (0) to (1), already signed:");
+        println!("{}", quick_start_command(&tx));
+    }
+    println!("==================================================================");
+}
+
+/// A transfer of 1 from the first dev account to the second, the first
+/// transaction of a fresh dev sequencer. `None` with fewer than two dev
+/// accounts.
+pub fn quick_start_tx(config: &Config) -> Option<SignedTx> {
+    let accounts = dev_accounts(config);
+    let [(sk, from), (_, to), ..] = accounts.as_slice() else {
+        return None;
+    };
+    let tx = Tx {
+        kind: TxKind::Transfer,
+        sender: from.to_u256(),
+        to: to.to_u256(),
+        nonce: 1.into(),
+        value: 1.into(),
+        not_before: None,
+        not_after: None,
+        chain_id: Some(config.chain_id),
+        token: NATIVE_TOKEN,
+    };
+    let signature = fusion_wallet::sign(&tx, sk.to_string())
+        .expect("dev keys sign")
+        .to_string();
+    Some(SignedTx { tx, signature })
+}
+
+/// A transfer of `value` from the `from`-th dev account to the `to`-th,
+/// not signed yet.
+#[cfg(test)]
+pub fn dev_transfer_tx(from: u32, to: u32, nonce: u64, value: u64) -> Tx {
+    Tx {
+        kind: TxKind::Transfer,
+        sender: fusion_wallet::new_public_key(&dev_private_key(from)).to_u256(),
+        to: fusion_wallet::new_public_key(&dev_private_key(to)).to_u256(),
+        nonce: nonce.into(),
+        value: value.into(),
+        not_before: None,
+        not_after: None,
+        chain_id: None,
+        token: NATIVE_TOKEN,
+    }
+}
+
+/// `tx` signed with `sk`.
+#[cfg(test)]
+pub fn sign_tx(tx: Tx, sk: &PrivateKey) -> SignedTx {
+    let signature = fusion_wallet::sign(&tx, sk.to_string())
+        .unwrap()
+        .to_string();
+    SignedTx { tx, signature }
+}
+
+/// `dev_transfer_tx` signed by its sender.
+#[cfg(test)]
+pub fn dev_transfer(from: u32, to: u32, nonce: u64, value: u64) -> SignedTx {
+    sign_tx(
+        dev_transfer_tx(from, to, nonce, value),
+        &dev_private_key(from),
+    )
+}
+
+/// Applies to `state` a `dev_transfer` with the next nonce of its sender,
+/// and returns the public inputs of its proof.
+#[cfg(test)]
+pub fn apply_dev_transfer(state: &mut State, from: u32, to: u32, value: u64) -> Vec<U256> {
+    let sender = fusion_wallet::new_public_key(&dev_private_key(from));
+    let nonce = state.get(&sender.address()).nonce + 1;
+    let tx = dev_transfer(from, to, nonce.as_u64(), value);
+    let post_state = crate::transition::apply_tx(state.clone(), &tx.tx).unwrap();
+    let inputs = fusion_prover::CircuitInput::new(&tx, state, &post_state)
+        .unwrap()
+        .public_inputs()
+        .to_vec();
+    *state = post_state;
+    inputs
+}
+
+/// The wallet command that submits `tx` as it is.
+pub fn quick_start_command(tx: &SignedTx) -> String {
+    format!(
+        "fusion-wallet send transfer -f {} -t {} -v {} -n {} --chain-id {} --signature {}",
+        tx.tx.sender,
+        tx.tx.to,
+        tx.tx.value,
+        tx.tx.nonce,
+        tx.tx.chain_id.unwrap_or_default(),
+        tx.signature
+    )
+}
+
+/// An account of a genesis file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GenesisAccount {
+    pub address: U256,
+    pub balance: U256,
+    pub nonce: U256,
+}
+
+/// Reads the genesis file at `path`, a JSON object from Fusion
+/// addresses, decimal or `0x` hex, to their accounts, e.g.
+/// `{"0x2a": {"balance": "1.5eth", "nonce": 3}}`. The nonce is 0 unless
+/// given.
+pub fn read_genesis(path: &Path) -> Result<Vec<GenesisAccount>, String> {
+    let json = std::fs::read_to_string(path)
+        .map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+    parse_genesis(&json).map_err(|e| format!("Invalid genesis file {}: {e}", path.display()))
+}
+
+fn parse_genesis(json: &str) -> Result<Vec<GenesisAccount>, String> {
+    let accounts: BTreeMap<String, Value> = serde_json::from_str(json)
+        .map_err(|e| format!("Expected an object from addresses to accounts: {e}"))?;
+    accounts
+        .iter()
+        .map(|(address, account)| {
+            let at = |e: String| format!("Account {address}: {e}");
+            let number = |key: &str, parse: fn(&str) -> Result<U256, String>| {
+                match account.get(key) {
+                    None | Some(Value::Null) => Ok(None),
+                    Some(Value::Number(n)) => parse(&n.to_string()).map(Some),
+                    Some(Value::String(s)) => parse(s).map(Some),
+                    Some(other) => Err(format!("Invalid {key} {other}")),
+                }
+                .map_err(at)
+            };
+            Ok(GenesisAccount {
+                address: parse_number(address).map_err(at)?,
+                balance: number("balance", parse_value)?
+                    .ok_or_else(|| at("`balance` is missing".to_string()))?,
+                nonce: number("nonce", parse_number)?.unwrap_or_default(),
+            })
+        })
+        .collect()
+}
+
+/// A number in decimal or `0x` hex.
+fn parse_number(s: &str) -> Result<U256, String> {
+    match s.strip_prefix("0x") {
+        Some(hex) => U256::from_str_radix(hex, 16),
+        None => U256::from_dec_str(s),
+    }
+    .map_err(|_| format!("Invalid number `{s}`"))
+}
+
+/// The genesis state of dev mode with the accounts of `genesis_path`
+/// on top, which take the place of a dev account at the same address.
+pub fn load_genesis(config: &Config) -> Result<State, String> {
+    let mut state = genesis_state(config);
+    if config.genesis_path.is_empty() {
+        return Ok(state);
+    }
+    for account in read_genesis(Path::new(&config.genesis_path))? {
+        state.update(
+            &account.address,
+            Account::new(account.address, account.balance, account.nonce),
+        );
+    }
+    Ok(state)
+}
+
+/// The genesis state of dev mode, where the faucet
+/// and the dev accounts are funded.
+pub fn genesis_state(config: &Config) -> State {
+    let mut state = State::default();
+    let faucet = faucet_public_key().address();
+    state.update(&faucet, Account::new(faucet, faucet_balance(), 0.into()));
+    for (_, pk) in dev_accounts(config) {
+        let address = pk.address();
+        state.update(
+            &address,
+            Account::new(address, config.dev_account_balance.into(), 0.into()),
+        );
+    }
+    state
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn first_dev_account_matches_anvil() {
+        let anvil_key = U256::from_str_radix(
+            "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80",
+            16,
+        )
+        .unwrap();
+        assert_eq!(dev_private_key(0).to_string(), anvil_key.to_string());
+    }
+
+    #[test]
+    fn dev_accounts_are_funded() {
+        let mut config = Config::default();
+        config.dev_accounts = 3;
+        config.dev_account_balance = 42;
+        let state = genesis_state(&config);
+        let accounts = dev_accounts(&config);
+        assert_eq!(accounts.len(), 3);
+        for (_, pk) in accounts {
+            assert_eq!(state.get(&pk.address()).balance, 42.into());
+        }
+    }
+
+    #[test]
+    fn genesis_file() {
+        let accounts = parse_genesis(
+            r#"{
+                "42": {"balance": "1.5eth", "nonce": 3},
+                "0x2b": {"balance": 7}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            accounts,
+            [
+                GenesisAccount {
+                    address: 42.into(),
+                    balance: U256::exp10(17) * 15,
+                    nonce: 3.into(),
+                },
+                GenesisAccount {
+                    address: 43.into(),
+                    balance: 7.into(),
+                    nonce: 0.into(),
+                },
+            ]
+        );
+        for (json, error) in [
+            ("[]", "Expected an object"),
+            (
+                r#"{"alice": {"balance": 1}}"#,
+                "Account alice: Invalid number",
+            ),
+            (r#"{"1": {"nonce": 1}}"#, "Account 1: `balance` is missing"),
+            (r#"{"1": {"balance": true}}"#, "Invalid balance"),
+        ] {
+            let e = parse_genesis(json).unwrap_err();
+            assert!(e.contains(error), "{json}: {e}");
+        }
+
+        let path =
+            std::env::temp_dir().join(format!("fusion-genesis-{}.json", rand::random::<u64>()));
+        std::fs::write(&path, r#"{"42": {"balance": 100, "nonce": 2}}"#).unwrap();
+        let mut config = Config::default();
+        config.genesis_path = path.display().to_string();
+        let state = load_genesis(&config).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            state.get(&42.into()),
+            Account::new(42.into(), 100.into(), 2.into())
+        );
+        let dev_account = dev_accounts(&config)[0].1.address();
+        assert_eq!(
+            state.get(&dev_account),
+            genesis_state(&config).get(&dev_account)
+        );
+        assert!(load_genesis(&config)
+            .unwrap_err()
+            .contains("Could not read"));
+    }
+}
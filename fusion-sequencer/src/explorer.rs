@@ -0,0 +1,407 @@
+//! Flat files a static block explorer can be served from.
+//!
+//! `blocks/<number>.json` holds a `Block` and `accounts/<public key>.json`
+//! an `AccountSummary`, in the JSON of the RPC responses, and
+//! `manifest.json` lists them. An incremental export only writes the
+//! blocks after the last one of the manifest and the accounts they touch.
+
+use ethers::types::U256;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    path::{Path, PathBuf},
+};
+
+use fusion_api::{AccountStats, Block, SignedTx, TxKind, MAX_BLOCKS_PER_REQUEST};
+use fusion_config::Config;
+use fusion_prover::state::State;
+use fusion_types::PublicKey;
+
+use crate::sequencer::{load_genesis_state, update_account_stats};
+use crate::server::{connect, request_context};
+use crate::transition::apply_tx;
+
+/// Number of transactions listed in `AccountSummary::recent_txs`.
+pub const RECENT_TXS: usize = 20;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountSummary {
+    pub public_key: U256,
+    pub address: U256,
+    pub balance: U256,
+    pub nonce: U256,
+    /// As `get_account_stats` returns them.
+    pub stats: AccountStats,
+    /// Hashes of its latest transactions, newest first.
+    pub recent_txs: Vec<U256>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestBlock {
+    pub number: u64,
+    pub hash: U256,
+    pub state_root: U256,
+    pub txs: usize,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Consecutive blocks, oldest first.
+    pub blocks: Vec<ManifestBlock>,
+    /// Public keys of the accounts, in increasing order.
+    pub accounts: Vec<U256>,
+}
+
+/// Number of documents an export wrote.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Written {
+    pub blocks: usize,
+    pub accounts: usize,
+}
+
+pub fn manifest_path(dir: &Path) -> PathBuf {
+    dir.join("manifest.json")
+}
+
+pub fn block_path(dir: &Path, number: u64) -> PathBuf {
+    dir.join("blocks").join(format!("{number}.json"))
+}
+
+pub fn account_path(dir: &Path, public_key: &U256) -> PathBuf {
+    dir.join("accounts").join(format!("{public_key:#x}.json"))
+}
+
+fn read_json<T: DeserializeOwned>(path: &Path) -> Result<T, String> {
+    let json =
+        std::fs::read(path).map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+    serde_json::from_slice(&json).map_err(|e| format!("Could not parse {}: {e}", path.display()))
+}
+
+fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(value).map_err(|e| e.to_string())? + "\n";
+    std::fs::create_dir_all(path.parent().unwrap())
+        .and_then(|()| std::fs::write(path, json))
+        .map_err(|e| format!("Could not write {}: {e}", path.display()))
+}
+
+/// The accounts whose summary a transaction changes, the same ones
+/// `update_account_stats` counts it for.
+fn parties(tx: &SignedTx) -> Vec<U256> {
+    match tx.tx.kind {
+        TxKind::Transfer => vec![tx.tx.sender, tx.tx.to],
+        TxKind::Deposit | TxKind::Withdraw => vec![tx.tx.sender],
+    }
+}
+
+/// Writes blocks `from` to the last of `blocks` to `dir`, with the
+/// accounts active in them. `blocks` must start at block 1, since the
+/// summaries replay them from `genesis`. With `incremental`, the export
+/// continues after the last block of the existing manifest instead.
+pub fn export(
+    dir: &Path,
+    genesis: State,
+    blocks: &[Block],
+    from: u64,
+    incremental: bool,
+) -> Result<Written, String> {
+    let mut manifest = match manifest_path(dir).exists() && incremental {
+        true => read_json(&manifest_path(dir))?,
+        false => Manifest::default(),
+    };
+    let from = match manifest.blocks.last() {
+        Some(last) => {
+            match blocks.get(last.number as usize - 1) {
+                Some(block) if block.header.hash() == last.hash => {}
+                _ => {
+                    return Err(format!(
+                        "Block {} of the manifest is not on the chain anymore, export again without incremental",
+                        last.number
+                    ))
+                }
+            }
+            last.number + 1
+        }
+        None => from,
+    };
+    if from == 0 {
+        return Err("Blocks start at 1".to_string());
+    }
+
+    let mut state = genesis;
+    let mut stats = HashMap::new();
+    let mut recent: HashMap<U256, VecDeque<U256>> = HashMap::new();
+    let mut touched = BTreeSet::new();
+    for block in blocks {
+        if block.is_pruned() {
+            return Err(format!(
+                "The transactions of block {} were pruned, export from a sequencer with block_retention = 0",
+                block.header.number
+            ));
+        }
+        state = block
+            .txs
+            .iter()
+            .try_fold(state, |state, tx| apply_tx(state, &tx.tx))
+            .map_err(|e| format!("Could not replay block {}: {e}", block.header.number))?;
+        update_account_stats(&mut stats, &block.txs, block.header.number);
+        for (tx, hash) in block.txs.iter().zip(&block.tx_hashes) {
+            for key in parties(tx) {
+                let txs = recent.entry(key).or_default();
+                txs.push_front(*hash);
+                txs.truncate(RECENT_TXS);
+                if block.header.number >= from {
+                    touched.insert(key);
+                }
+            }
+        }
+    }
+
+    let mut written = Written::default();
+    for block in blocks.iter().skip(from as usize - 1) {
+        write_json(&block_path(dir, block.header.number), block)?;
+        manifest.blocks.push(ManifestBlock {
+            number: block.header.number,
+            hash: block.header.hash(),
+            state_root: block.header.state_root,
+            txs: block.txs.len(),
+        });
+        written.blocks += 1;
+    }
+    for public_key in &touched {
+        let address = PublicKey::from(*public_key).address();
+        let account = state.get(&address);
+        let summary = AccountSummary {
+            public_key: *public_key,
+            address,
+            balance: account.balance,
+            nonce: account.nonce,
+            stats: stats.get(public_key).cloned().unwrap_or_default(),
+            recent_txs: recent.remove(public_key).unwrap_or_default().into(),
+        };
+        write_json(&account_path(dir, public_key), &summary)?;
+        written.accounts += 1;
+    }
+
+    if written != Written::default() {
+        manifest.accounts.extend(touched);
+        manifest.accounts.sort_unstable();
+        manifest.accounts.dedup();
+        write_json(&manifest_path(dir), &manifest)?;
+    }
+    Ok(written)
+}
+
+/// Checks that the manifest in `dir` lists a chain of blocks and that
+/// every document it lists is there and matches it.
+pub fn check_manifest(dir: &Path) -> Result<Manifest, String> {
+    let manifest: Manifest = read_json(&manifest_path(dir))?;
+    let mut parent: Option<&ManifestBlock> = None;
+    for entry in &manifest.blocks {
+        let block: Block = read_json(&block_path(dir, entry.number))?;
+        let header = &block.header;
+        if header.number != entry.number
+            || header.hash() != entry.hash
+            || header.state_root != entry.state_root
+            || block.txs.len() != entry.txs
+        {
+            return Err(format!(
+                "Block {} does not match the manifest",
+                entry.number
+            ));
+        }
+        if let Some(parent) = parent {
+            if entry.number != parent.number + 1 || header.parent_hash != parent.hash {
+                return Err(format!(
+                    "Block {} does not follow block {}",
+                    entry.number, parent.number
+                ));
+            }
+        }
+        parent = Some(entry);
+    }
+    if manifest.accounts.windows(2).any(|pair| pair[0] >= pair[1]) {
+        return Err("The accounts of the manifest are not sorted".to_string());
+    }
+    for public_key in &manifest.accounts {
+        let summary: AccountSummary = read_json(&account_path(dir, public_key))?;
+        if summary.public_key != *public_key {
+            return Err(format!(
+                "Account {public_key:#x} does not match the manifest"
+            ));
+        }
+    }
+    Ok(manifest)
+}
+
+/// Exports blocks `from` to `to`, the latest one by default, of the
+/// running sequencer.
+pub async fn export_range(
+    config: &Config,
+    dir: &Path,
+    from: u64,
+    to: Option<u64>,
+    incremental: bool,
+) -> Result<Written, String> {
+    let sequencer = connect(config).await?;
+    let to = match to {
+        Some(to) => to,
+        None => {
+            sequencer
+                .sequencer_health(request_context(config))
+                .await
+                .map_err(|e| e.to_string())?
+                .block_number
+        }
+    };
+
+    let mut blocks = vec![];
+    let mut page = 1;
+    while page <= to {
+        let page_end = to.min(page + MAX_BLOCKS_PER_REQUEST - 1);
+        blocks.extend(
+            sequencer
+                .get_blocks(request_context(config), page, page_end)
+                .await
+                .map_err(|e| e.to_string())??,
+        );
+        page = page_end + 1;
+    }
+    export(dir, load_genesis_state(config)?, &blocks, from, incremental)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fusion_types::ToU256;
+    use std::time::SystemTime;
+    use tokio::sync::oneshot;
+
+    use crate::dev;
+    use crate::sequencer::{genesis_state, Sequencer, Trigger};
+
+    fn dev_config() -> Config {
+        Config {
+            dev: true,
+            dev_accounts: 3,
+            min_tx_block: usize::MAX,
+            ..Default::default()
+        }
+    }
+
+    fn public_key(index: u32) -> U256 {
+        fusion_wallet::new_public_key(&dev::dev_private_key(index)).to_u256()
+    }
+
+    /// Builds a block of transfers `(from, to, nonce, value)` between
+    /// dev accounts.
+    async fn block(sequencer: &mut Sequencer, transfers: &[(u32, u32, u64, u64)]) {
+        for &(from, to, nonce, value) in transfers {
+            let tx = dev::dev_transfer(from, to, nonce, value);
+            sequencer.handle(Trigger::Tx(tx)).await;
+        }
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer.handle(Trigger::Flush(reply_sx)).await;
+        reply_rx.await.unwrap().unwrap();
+    }
+
+    fn blocks(sequencer: &Sequencer) -> Vec<Block> {
+        sequencer.shared().blocks.lock().unwrap().clone()
+    }
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("fusion-explorer-{}", rand::random::<u64>()))
+    }
+
+    fn modified(path: &Path) -> SystemTime {
+        std::fs::metadata(path).unwrap().modified().unwrap()
+    }
+
+    #[tokio::test]
+    async fn export_and_check() {
+        let config = dev_config();
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
+        block(&mut sequencer, &[(0, 1, 1, 10), (1, 2, 1, 3)]).await;
+        block(&mut sequencer, &[(0, 2, 2, 5)]).await;
+
+        let dir = temp_dir();
+        let blocks = blocks(&sequencer);
+        let written = export(&dir, genesis_state(&config), &blocks, 1, false).unwrap();
+        assert_eq!(
+            written,
+            Written {
+                blocks: 2,
+                accounts: 3
+            }
+        );
+        let manifest = check_manifest(&dir).unwrap();
+        assert_eq!(manifest.blocks.len(), 2);
+        assert_eq!(manifest.blocks[1].state_root, sequencer.state().root());
+
+        let summary: AccountSummary = read_json(&account_path(&dir, &public_key(0))).unwrap();
+        let balance = U256::from(config.dev_account_balance) - 15;
+        assert_eq!(summary.balance, balance);
+        assert_eq!(summary.nonce, 2.into());
+        assert_eq!(summary.stats.sent_count, 2);
+        assert_eq!(
+            summary.recent_txs,
+            vec![blocks[1].tx_hashes[0], blocks[0].tx_hashes[0]]
+        );
+
+        // A document that does not match the manifest is caught.
+        let mut tampered = blocks[0].clone();
+        tampered.header.timestamp += 1;
+        write_json(&block_path(&dir, 1), &tampered).unwrap();
+        assert!(check_manifest(&dir).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn incremental() {
+        let config = dev_config();
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
+        block(&mut sequencer, &[(0, 1, 1, 10)]).await;
+        block(&mut sequencer, &[(1, 2, 1, 3)]).await;
+
+        let dir = temp_dir();
+        export(&dir, genesis_state(&config), &blocks(&sequencer), 1, true).unwrap();
+        let untouched = [
+            block_path(&dir, 1),
+            block_path(&dir, 2),
+            account_path(&dir, &public_key(2)),
+        ];
+        let before: Vec<_> = untouched.iter().map(|path| modified(path)).collect();
+
+        block(&mut sequencer, &[(0, 1, 2, 1)]).await;
+        let written = export(&dir, genesis_state(&config), &blocks(&sequencer), 1, true).unwrap();
+        assert_eq!(
+            written,
+            Written {
+                blocks: 1,
+                accounts: 2
+            }
+        );
+        let after: Vec<_> = untouched.iter().map(|path| modified(path)).collect();
+        assert_eq!(before, after);
+        let manifest = check_manifest(&dir).unwrap();
+        assert_eq!(manifest.blocks.len(), 3);
+        assert_eq!(manifest.accounts.len(), 3);
+
+        // Nothing new, nothing written.
+        let manifest_modified = modified(&manifest_path(&dir));
+        let written = export(&dir, genesis_state(&config), &blocks(&sequencer), 1, true).unwrap();
+        assert_eq!(written, Written::default());
+        assert_eq!(modified(&manifest_path(&dir)), manifest_modified);
+
+        // The chain the manifest was written for is gone.
+        let mut config = dev_config();
+        config.dev_account_balance += 1;
+        let mut other = Sequencer::new(&config).await.unwrap();
+        for nonce in 1..=4 {
+            block(&mut other, &[(0, 1, nonce, 1)]).await;
+        }
+        assert!(export(&dir, genesis_state(&config), &blocks(&other), 1, true).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
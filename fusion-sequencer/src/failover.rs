@@ -0,0 +1,329 @@
+//! An L1 transport over several HTTP endpoints: `eth_rpc_url`, then
+//! `eth_rpc_fallback_urls`. Requests go to the active endpoint. When it
+//! cannot be reached, it is skipped for `l1_failover_backoff` seconds,
+//! doubled each time in a row it fails, and the request moves on to the
+//! next endpoint, which becomes the active one if it answers. Errors the
+//! node answers with, e.g. a reverted call, are returned as they are.
+//!
+//! A proof is never submitted twice on a switch: before sending a raw
+//! transaction again to another endpoint, it asks that endpoint whether
+//! it knows the transaction already, and the transaction sent again is
+//! the same signed one, with the same nonce.
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use ethers::providers::{Http, HttpClientError, JsonRpcClient, RpcError};
+use ethers::types::{Bytes, H256};
+use ethers::utils::keccak256;
+use futures::future::BoxFuture;
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::{info, warn};
+
+use crate::metrics::Metrics;
+use crate::node::{http_transport, ProviderSettings};
+
+/// Longest an endpoint is skipped.
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Default)]
+struct Health {
+    /// Failures in a row.
+    failures: u32,
+    skipped_until: Option<Instant>,
+}
+
+#[derive(Debug)]
+struct Endpoint {
+    url: String,
+    http: Http,
+    health: Mutex<Health>,
+}
+
+/// How long an endpoint is skipped after `failures` in a row.
+fn backoff(base: Duration, failures: u32) -> Duration {
+    let doubled = base.saturating_mul(1 << failures.saturating_sub(1).min(16));
+    doubled.min(MAX_BACKOFF)
+}
+
+/// The hash of the raw transaction of `eth_sendRawTransaction` `params`.
+fn raw_tx_hash(params: &impl Serialize) -> Option<H256> {
+    let (raw,): (Bytes,) = serde_json::to_value(params)
+        .ok()
+        .and_then(|params| serde_json::from_value(params).ok())?;
+    Some(keccak256(raw).into())
+}
+
+/// Transport over a list of endpoints. Its clones share which endpoint
+/// is active and which are skipped.
+#[derive(Clone)]
+pub struct FailoverHttp {
+    endpoints: Arc<[Endpoint]>,
+    active: Arc<AtomicUsize>,
+    base_backoff: Duration,
+    metrics: Metrics,
+}
+
+impl fmt::Debug for FailoverHttp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FailoverHttp")
+            .field("endpoints", &self.urls())
+            .field("active", &self.active())
+            .finish()
+    }
+}
+
+impl FailoverHttp {
+    pub fn new(
+        urls: &[String],
+        settings: &ProviderSettings,
+        metrics: Metrics,
+    ) -> anyhow::Result<Self> {
+        if urls.is_empty() {
+            anyhow::bail!("No L1 endpoint, eth_rpc_url is empty");
+        }
+        let endpoints = urls
+            .iter()
+            .map(|url| {
+                Ok(Endpoint {
+                    url: url.clone(),
+                    http: http_transport(url, settings)
+                        .map_err(|e| anyhow::anyhow!("Invalid L1 endpoint `{url}`: {e}"))?,
+                    health: Mutex::default(),
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Self {
+            endpoints: endpoints.into(),
+            active: Arc::new(AtomicUsize::new(0)),
+            base_backoff: settings.failover_backoff,
+            metrics,
+        })
+    }
+
+    pub fn urls(&self) -> Vec<&str> {
+        self.endpoints.iter().map(|e| e.url.as_str()).collect()
+    }
+
+    /// Index of the endpoint requests go to.
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    /// The endpoints to try, the active one first, then the others in
+    /// turn. Those being skipped come last.
+    fn candidates(&self, now: Instant) -> Vec<usize> {
+        let n = self.endpoints.len();
+        let (mut up, mut skipped): (Vec<_>, Vec<_>) =
+            (0..n).map(|k| (self.active() + k) % n).partition(|i| {
+                let health = self.endpoints[*i].health.lock().unwrap();
+                health.skipped_until.map_or(true, |until| until <= now)
+            });
+        up.append(&mut skipped);
+        up
+    }
+
+    fn succeeded(&self, i: usize) {
+        *self.endpoints[i].health.lock().unwrap() = Health::default();
+        if self.active.swap(i, Ordering::Relaxed) != i {
+            info!(endpoint = %self.endpoints[i].url, "Switched to another L1 endpoint");
+            self.metrics.l1_endpoint(i);
+        }
+    }
+
+    fn failed(&self, i: usize, method: &str, e: &HttpClientError) {
+        let endpoint = &self.endpoints[i];
+        let mut health = endpoint.health.lock().unwrap();
+        health.failures += 1;
+        let skipped_for = backoff(self.base_backoff, health.failures);
+        health.skipped_until = Some(Instant::now() + skipped_for);
+        warn!(
+            endpoint = %endpoint.url,
+            method,
+            error = %e,
+            failures = health.failures,
+            ?skipped_for,
+            "L1 endpoint unreachable"
+        );
+        self.metrics.l1_endpoint_failure();
+    }
+
+    async fn request_any<T, R>(&self, method: &str, params: T) -> Result<R, HttpClientError>
+    where
+        T: fmt::Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let raw_tx = match method {
+            "eth_sendRawTransaction" => raw_tx_hash(&params),
+            _ => None,
+        };
+        let mut last_error = None;
+        for (attempt, i) in self.candidates(Instant::now()).into_iter().enumerate() {
+            let http = &self.endpoints[i].http;
+            if let (Some(hash), true) = (raw_tx, attempt > 0) {
+                match http
+                    .request::<_, Option<serde_json::Value>>("eth_getTransactionByHash", [hash])
+                    .await
+                {
+                    Ok(Some(_)) => {
+                        let endpoint = &self.endpoints[i].url;
+                        info!(%endpoint, ?hash, "L1 transaction already sent");
+                        self.succeeded(i);
+                        return sent(hash);
+                    }
+                    Ok(None) => {}
+                    Err(e) if e.as_error_response().is_none() => {
+                        self.failed(i, method, &e);
+                        last_error = Some(e);
+                        continue;
+                    }
+                    Err(_) => {}
+                }
+            }
+            match http.request(method, &params).await {
+                Ok(result) => {
+                    self.succeeded(i);
+                    return Ok(result);
+                }
+                // The node answered: another endpoint would not do better.
+                Err(e) if e.as_error_response().is_some() => {
+                    self.succeeded(i);
+                    let known = e.to_string().contains("already known");
+                    return match raw_tx {
+                        Some(hash) if attempt > 0 && known => sent(hash),
+                        _ => Err(e),
+                    };
+                }
+                Err(e) => {
+                    self.failed(i, method, &e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.expect("There is at least one L1 endpoint"))
+    }
+}
+
+/// The answer of `eth_sendRawTransaction` for transaction `hash`.
+fn sent<R: DeserializeOwned>(hash: H256) -> Result<R, HttpClientError> {
+    serde_json::to_value(hash)
+        .and_then(serde_json::from_value)
+        .map_err(|err| HttpClientError::SerdeJson {
+            err,
+            text: format!("{hash:?}"),
+        })
+}
+
+// What `#[async_trait]` expands the method of `JsonRpcClient` to.
+impl JsonRpcClient for FailoverHttp {
+    type Error = HttpClientError;
+
+    fn request<'life0, 'life1, 'async_trait, T, R>(
+        &'life0 self,
+        method: &'life1 str,
+        params: T,
+    ) -> BoxFuture<'async_trait, Result<R, Self::Error>>
+    where
+        T: 'async_trait + fmt::Debug + Serialize + Send + Sync,
+        R: 'async_trait + DeserializeOwned + Send,
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        Self: 'async_trait,
+    {
+        Box::pin(self.request_any(method, params))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anvil::{spawn, NodeConfig};
+    use ethers::prelude::*;
+    use fusion_config::Config;
+
+    const UNREACHABLE: &str = "http://127.0.0.1:1";
+
+    fn failover(urls: &[&str], metrics: Metrics) -> FailoverHttp {
+        let urls: Vec<_> = urls.iter().map(|url| url.to_string()).collect();
+        let settings = ProviderSettings::from_config(&Config::default());
+        FailoverHttp::new(&urls, &settings, metrics).unwrap()
+    }
+
+    #[test]
+    fn backoff_doubles() {
+        let base = Duration::from_secs(5);
+        assert_eq!(backoff(base, 1), base);
+        assert_eq!(backoff(base, 3), Duration::from_secs(20));
+        assert_eq!(backoff(base, 40), MAX_BACKOFF);
+        let settings = ProviderSettings::from_config(&Config::default());
+        assert!(FailoverHttp::new(&[], &settings, Metrics::default()).is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn moves_on_to_a_reachable_endpoint() {
+        let anvil_config = NodeConfig::test();
+        let (_api, handle) = spawn(anvil_config.clone()).await;
+        let metrics = Metrics::default();
+        let transport = failover(&[UNREACHABLE, &handle.http_endpoint()], metrics.clone());
+        let provider = Provider::new(transport.clone());
+        provider.get_block_number().await.unwrap();
+        assert_eq!(transport.active(), 1);
+        assert_eq!(transport.candidates(Instant::now()), [1, 0]);
+        let rendered = metrics.render(0);
+        assert!(
+            rendered.contains("fusion_l1_active_endpoint 1"),
+            "{rendered}"
+        );
+        assert!(
+            rendered.contains("fusion_l1_endpoint_failures_total 1"),
+            "{rendered}"
+        );
+
+        // Errors the node answers with do not switch endpoints.
+        let unknown = provider
+            .request::<_, U256>("fusion_unknownMethod", ())
+            .await;
+        assert!(unknown.is_err());
+        assert_eq!(transport.active(), 1);
+
+        // A transaction the next endpoint knows is not sent again.
+        let chain_id = provider.get_chainid().await.unwrap().as_u64();
+        let wallet = anvil_config.genesis_accounts[0]
+            .clone()
+            .with_chain_id(chain_id);
+        let tx: TypedTransaction = TransactionRequest::new()
+            .from(wallet.address())
+            .to(Address::repeat_byte(1))
+            .value(1)
+            .nonce(0)
+            .gas(21000)
+            .gas_price(provider.get_gas_price().await.unwrap())
+            .chain_id(wallet.chain_id())
+            .into();
+        let raw = tx.rlp_signed(&wallet.sign_transaction(&tx).await.unwrap());
+        let hash = provider
+            .send_raw_transaction(raw.clone())
+            .await
+            .unwrap()
+            .tx_hash();
+        let transport = failover(&[UNREACHABLE, &handle.http_endpoint()], Metrics::default());
+        let resent = Provider::new(transport)
+            .send_raw_transaction(raw)
+            .await
+            .unwrap();
+        assert_eq!(resent.tx_hash(), hash);
+        assert_eq!(
+            provider
+                .get_transaction_count(wallet.address(), None)
+                .await
+                .unwrap(),
+            1.into()
+        );
+    }
+}
@@ -0,0 +1,252 @@
+//! A whole node as `fusion-sequencer` runs it: the sequencer and the
+//! tasks around it, i.e. the RPC and admin servers, the metrics
+//! endpoint, the L1 probes, the consistency sampler and maintenance.
+//! `start` returns once they are spawned, so that tests drive a node
+//! the way clients and L1 see it.
+
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use ethers::providers::Provider;
+use tokio::{sync::mpsc, task::JoinHandle};
+use tracing::error;
+
+use fusion_config::Config;
+
+use crate::consistency;
+use crate::maintenance;
+use crate::metrics;
+use crate::node::ProviderSettings;
+use crate::probes;
+use crate::rate_limit::RateLimiter;
+use crate::sequencer::{block_timestamp, genesis_state, Sequencer, Shared, Trigger};
+use crate::server::{run_admin_server, start_server, ServerHandle};
+
+/// A started node.
+pub struct RunningNode {
+    /// Reaches the sequencer, as the servers do.
+    pub sender: mpsc::Sender<Trigger>,
+    pub shared: Shared,
+    /// Where the RPC server listens, with the port the system picked
+    /// when `socket_port` is 0.
+    pub rpc_addr: SocketAddr,
+    /// Resolves when the sequencer stops.
+    pub sequencer: JoinHandle<anyhow::Result<()>>,
+    /// Without it nothing reaches the sequencer anymore.
+    pub server: ServerHandle,
+}
+
+impl RunningNode {
+    /// Stops the sequencer the way SIGTERM does, waits for it, then
+    /// shuts the RPC server down.
+    pub async fn stop(self) -> anyhow::Result<()> {
+        let _ = self.sender.send(Trigger::Shutdown).await;
+        let stopped = self.sequencer.await;
+        self.server.shutdown().await;
+        stopped?
+    }
+}
+
+/// Starts the node `config` describes, with the L1 private key already
+/// read and the contract deployed.
+pub async fn start(config: Config) -> anyhow::Result<RunningNode> {
+    let (sx, rx) = mpsc::channel(1024);
+    let sequencer = Sequencer::new(&config).await?;
+    let shared = sequencer.shared();
+
+    // The probes use the L1 endpoint the sequencer does.
+    let poll_interval = ProviderSettings::from_config(&config).poll_interval;
+    let l1_provider = sequencer
+        .l1_transport()
+        .map(|transport| Arc::new(Provider::new(transport).interval(poll_interval)));
+    if let Some(provider) = l1_provider
+        .clone()
+        .filter(|_| config.consistency_check_interval > 0)
+    {
+        tokio::spawn(consistency::run_sampler(
+            shared.clone(),
+            provider,
+            genesis_state(&config).root(),
+            Duration::from_secs(config.consistency_check_interval),
+        ));
+    }
+
+    if let Some(provider) = l1_provider {
+        tokio::spawn(probes::watch_l1(
+            shared.clone(),
+            provider,
+            config.fusion_l1_contract,
+        ));
+    }
+
+    if config.maintenance_interval > 0 {
+        tokio::spawn(maintenance::run_maintenance(
+            sx.clone(),
+            Duration::from_secs(config.maintenance_interval),
+        ));
+    }
+
+    let socket_addr = config.socket_addr().map_err(|e| anyhow::anyhow!(e))?;
+    if config.metrics_port > 0 {
+        let metrics_addr = SocketAddr::from((socket_addr.ip(), config.metrics_port));
+        let metrics = shared.metrics.clone();
+        let mempool_depth = sequencer.mempool_depth();
+        let (probed, max_block_lag) = (shared.clone(), config.ready_max_block_lag);
+        let status = move || probes::status(&probed, max_block_lag, block_timestamp());
+        tokio::spawn(async move {
+            if let Err(e) = metrics::serve(metrics_addr, metrics, mempool_depth, status).await {
+                error!(addr = %metrics_addr, error = %e, "The metrics endpoint stopped");
+            }
+        });
+    }
+    if config.admin_port > 0 {
+        let (admin_sx, admin_shared) = (sx.clone(), shared.clone());
+        let port = config.admin_port;
+        tokio::spawn(async move {
+            if let Err(e) = run_admin_server(admin_sx, admin_shared, port).await {
+                error!(port, error = %e, "The admin server stopped");
+            }
+        });
+    }
+    let server = start_server(
+        sx.clone(),
+        shared.clone(),
+        socket_addr,
+        RateLimiter::new(config.rpc_rate_limit, config.rpc_burst),
+        config.rpc_max_request_size,
+        config.ready_max_block_lag,
+    )
+    .await?;
+
+    Ok(RunningNode {
+        sender: sx,
+        shared,
+        rpc_addr: server.local_addr(),
+        sequencer: tokio::spawn(sequencer.run(rx)),
+        server,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anvil::{spawn, NodeConfig};
+    use ethers::types::U256;
+
+    use fusion_api::{SignedTx, Tx, TxKind, NATIVE_TOKEN};
+    use fusion_prover::state::State;
+    use fusion_types::{PrivateKey, ToU256};
+
+    use crate::deploy::dev_l1_contract;
+    use crate::dev;
+    use crate::server::{connect, request_context};
+    use crate::transition::apply_tx;
+
+    fn signed(kind: TxKind, sk: &PrivateKey, to: U256, nonce: u64, value: u64) -> SignedTx {
+        let tx = Tx {
+            kind,
+            sender: fusion_wallet::new_public_key(sk).to_u256(),
+            to,
+            nonce: nonce.into(),
+            value: value.into(),
+            not_before: None,
+            not_after: None,
+            chain_id: None,
+            token: NATIVE_TOKEN,
+        };
+        dev::sign_tx(tx, sk)
+    }
+
+    /// A deposit and two transfers submitted through the RPC client end
+    /// up in the root of the contract.
+    #[tokio::test(flavor = "multi_thread")]
+    async fn deposit_and_transfers_reach_l1() {
+        let anvil_config = NodeConfig::test();
+        let (_api, handle) = spawn(anvil_config.clone()).await;
+
+        let mut config = Config::default();
+        config.eth_rpc_url = handle.http_endpoint();
+        let wallet = &anvil_config.genesis_accounts[0];
+        config.eth_private_key = hex::encode(wallet.signer().to_bytes());
+        config.database_path = std::env::temp_dir()
+            .join(format!("fusion-launch-{}", rand::random::<u64>()))
+            .display()
+            .to_string();
+        config.dev_l1 = true;
+        config.fusion_l1_contract = dev_l1_contract(&config).await.unwrap();
+        config.min_tx_block = 1;
+        config.metrics_port = 0;
+        config.admin_port = 0;
+        config.socket_address = "127.0.0.1".to_string();
+        config.socket_port = 0;
+        let contract = fusion_l1::fusion::Fusion::new(
+            config.fusion_l1_contract,
+            handle.http_provider().into(),
+        );
+        assert_eq!(contract.root().call().await.unwrap(), U256::zero());
+
+        let node = start(config.clone()).await.unwrap();
+        config.socket_port = node.rpc_addr.port();
+        let client = connect(&config).await.unwrap();
+        let ctx = || request_context(&config);
+
+        let (sk_1, pk_1) = fusion_wallet::new_key_pair();
+        let (sk_2, pk_2) = fusion_wallet::new_key_pair();
+        contract
+            .deposit(pk_1.address())
+            .value(1000)
+            .gas(1000000)
+            .send()
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+
+        let txs = [
+            signed(TxKind::Deposit, &sk_1, U256::zero(), 1, 1000),
+            signed(TxKind::Transfer, &sk_1, pk_2.clone().to_u256(), 2, 300),
+            signed(TxKind::Transfer, &sk_2, pk_1.clone().to_u256(), 1, 100),
+        ];
+        for tx in &txs {
+            let submitted = client.submit_transaction(ctx(), tx.clone()).await.unwrap();
+            assert!(submitted.is_ok(), "{submitted:?}");
+            // Each is sent once the previous one is in a block.
+            let sender = tx.tx.sender;
+            for _ in 0..600 {
+                let nonces = client
+                    .get_nonce(ctx(), sender, None)
+                    .await
+                    .unwrap()
+                    .unwrap();
+                if nonces.committed == tx.tx.nonce {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+
+        let state = txs
+            .iter()
+            .map(|tx| &tx.tx)
+            .fold(State::default(), |state, tx| apply_tx(state, tx).unwrap());
+        let mut root = U256::zero();
+        for _ in 0..1200 {
+            root = contract.root().call().await.unwrap();
+            if root == state.root() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(root, state.root());
+
+        for (address, balance) in [(pk_1.address(), 800), (pk_2.address(), 200)] {
+            let found = client.get_balance(ctx(), address, None).await.unwrap();
+            assert_eq!(found.unwrap().committed, balance.into());
+        }
+        assert_eq!(
+            contract.deposits(pk_1.address()).call().await.unwrap(),
+            U256::zero()
+        );
+        node.stop().await.unwrap();
+        std::fs::remove_dir_all(&config.database_path).ok();
+    }
+}
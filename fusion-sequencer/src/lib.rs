@@ -1,3 +1,33 @@
+pub mod audit;
+pub mod batches;
+pub mod calldata;
+pub mod chaos;
+pub mod compatibility;
+pub mod consistency;
+pub mod decode;
+pub mod deploy;
+pub mod dev;
+pub mod explorer;
+pub mod failover;
+pub mod launch;
+pub mod logging;
+pub mod maintenance;
+pub mod mempool;
+pub mod metrics;
 pub mod node;
+pub mod ordering;
+pub mod preflight;
+pub mod probes;
+pub mod proving;
+pub mod rate_limit;
+pub mod reorg;
+pub mod scenario;
 pub mod sequencer;
 pub mod server;
+pub mod simulate;
+pub mod snapshot;
+pub mod submission;
+pub mod sync;
+pub mod transition;
+pub mod tuning;
+pub mod witness;
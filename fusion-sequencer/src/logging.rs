@@ -0,0 +1,22 @@
+//! Logs of the sequencer, written to stdout by `tracing` as text or JSON
+//! lines, filtered by `log_filter` or `RUST_LOG`.
+
+use tracing_subscriber::EnvFilter;
+
+use fusion_config::{Config, LogFormat};
+
+/// Installs the subscriber for the whole process.
+pub fn init(config: &Config) -> Result<(), String> {
+    let filter = match std::env::var(EnvFilter::DEFAULT_ENV) {
+        Ok(filter) => filter,
+        Err(_) => config.log_filter.clone(),
+    };
+    let filter =
+        EnvFilter::try_new(&filter).map_err(|e| format!("Invalid log filter `{filter}`: {e}"))?;
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+    match config.log_format {
+        LogFormat::Text => subscriber.try_init(),
+        LogFormat::Json => subscriber.json().try_init(),
+    }
+    .map_err(|e| format!("Could not set up logging: {e}"))
+}
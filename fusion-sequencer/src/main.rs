@@ -1,22 +1,605 @@
-use tokio::sync::mpsc;
+use clap::{Parser, Subcommand};
+use std::{future, path::Path};
+use tracing::{error, info, warn};
 
-use fusion_api::*;
-use fusion_config::Config;
+use fusion_config::{Config, KeySource};
+use fusion_prover::{offline, test_vectors};
 
+use fusion_sequencer::consistency;
+use fusion_sequencer::decode;
+use fusion_sequencer::deploy::dev_l1_contract;
+use fusion_sequencer::explorer;
+use fusion_sequencer::launch;
+use fusion_sequencer::logging;
+use fusion_sequencer::node::l1_private_key;
+use fusion_sequencer::preflight::preflight;
 use fusion_sequencer::sequencer::*;
 use fusion_sequencer::server::*;
+use fusion_sequencer::simulate::{simulate, SimulationParams};
+use fusion_sequencer::snapshot;
+use fusion_sequencer::sync::L1Sync;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let config = Config::from_file("../fusion.toml".to_string());
-    let (sx, rx): (mpsc::Sender<SignedTx>, mpsc::Receiver<SignedTx>) = mpsc::channel(1024);
+    let opts = Opts::parse();
+    if let Some(Command::GenVectors { output }) = &opts.command {
+        test_vectors::write(output).map_err(|e| anyhow::anyhow!(e))?;
+        println!("Wrote the conformance vectors to {output}");
+        return Ok(());
+    }
+    if let Some(Command::Decode { input }) = &opts.command {
+        let input = match input {
+            Some(input) => input.clone(),
+            None => std::io::read_to_string(std::io::stdin())?,
+        };
+        println!(
+            "{}",
+            decode::decode(&input).map_err(|e| anyhow::anyhow!(e))?
+        );
+        return Ok(());
+    }
+    if let Some(Command::VerifyProof { proof, vk, inputs }) = &opts.command {
+        offline::verify_file(
+            proof.as_ref(),
+            vk.as_ref(),
+            inputs.as_deref().map(Path::new),
+        )
+        .map_err(|e| anyhow::anyhow!(e))?;
+        println!("{proof} is valid");
+        return Ok(());
+    }
+    let resolved = Config::resolve(&opts.config, opts.profile.as_deref(), opts.flags()?)
+        .map_err(|e| anyhow::anyhow!(e))?;
+    logging::init(&resolved.config).map_err(|e| anyhow::anyhow!(e))?;
+    if let Some(profile) = &resolved.config.profile {
+        info!(profile, "Using profile");
+    }
+    info!("Configuration:\n{}", resolved.dump().trim_end());
+    let mut config = resolved.config;
 
-    let socket_address = config.socket_address.to_string();
-    tokio::spawn(async move {
-        run_server(sx, socket_address, config.socket_port)
+    if let Some(Command::Prove {
+        input,
+        proving_key,
+        output,
+    }) = &opts.command
+    {
+        let proving_key = proving_key.as_ref().unwrap_or(&config.proving_key_path);
+        let stats = offline::prove_file(&config, input.as_ref(), proving_key, output.as_ref())
+            .map_err(|e| anyhow::anyhow!(e))?;
+        println!("Wrote the proof to {output}");
+        println!(
+            "Building the witness took {:.2}s, proving {:.2}s",
+            stats.timings.witness.as_secs_f64(),
+            stats.timings.proof.as_secs_f64()
+        );
+        if let Some(peak_memory) = stats.peak_memory {
+            println!("Peak memory was {} MB", peak_memory / 1024);
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::CheckConsistency { from, to }) = &opts.command {
+        let found = consistency::check_range(&config, *from, *to)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))?;
+        for inconsistency in &found {
+            println!("{inconsistency}");
+        }
+        match found.len() {
+            0 => println!("Blocks {from} to {to} are consistent with L1"),
+            n => anyhow::bail!("{n} inconsistencies between L1 and the sequencer"),
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Export(ExportCommand::Explorer {
+        out,
+        from,
+        to,
+        incremental,
+    })) = &opts.command
+    {
+        let written = explorer::export_range(&config, Path::new(out), *from, *to, *incremental)
             .await
-            .unwrap();
+            .map_err(|e| anyhow::anyhow!(e))?;
+        let manifest = explorer::check_manifest(Path::new(out)).map_err(|e| anyhow::anyhow!(e))?;
+        println!(
+            "Wrote {} block(s) and {} account(s) to {out}, which has {} block(s) and {} account(s)",
+            written.blocks,
+            written.accounts,
+            manifest.blocks.len(),
+            manifest.accounts.len()
+        );
+        return Ok(());
+    }
+
+    if let Some(Command::Simulate {
+        accounts,
+        txs,
+        batch_size,
+        seed,
+        witness,
+        output,
+    }) = &opts.command
+    {
+        let params = SimulationParams {
+            accounts: *accounts,
+            txs: *txs,
+            batch_size: *batch_size,
+            seed: *seed,
+            witness: *witness,
+        };
+        let report = simulate(&config, &params).map_err(|e| anyhow::anyhow!(e))?;
+        let json = serde_json::to_string_pretty(&report)?;
+        match output {
+            Some(output) => {
+                std::fs::write(output, json + "\n")?;
+                println!(
+                    "{} transaction(s) in {} block(s), {:.0} tx/s, state root {}",
+                    report.included, report.blocks, report.throughput, report.state_root
+                );
+                println!("Wrote the report to {output}");
+            }
+            None => println!("{json}"),
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Snapshot(command)) = &opts.command {
+        let dir = Path::new(&config.database_path);
+        match command {
+            SnapshotCommand::Export { out } => {
+                let (newest, _, bytes) = snapshot::newest(dir)
+                    .map_err(|e| anyhow::anyhow!(e))?
+                    .ok_or_else(|| anyhow::anyhow!("No snapshot in {}", dir.display()))?;
+                std::fs::write(out, bytes)?;
+                println!(
+                    "Wrote the snapshot of L1 block {} to {out}",
+                    newest.l1_block
+                );
+            }
+            SnapshotCommand::Import { file } => {
+                let imported = L1Sync::import_snapshot(dir, &std::fs::read(file)?)
+                    .map_err(|e| anyhow::anyhow!(e))?;
+                println!(
+                    "Imported the snapshot of L1 block {}, {} account(s) at root {}",
+                    imported.l1_block,
+                    imported.accounts.len(),
+                    imported.root
+                );
+            }
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Admin(admin)) = &opts.command {
+        let client = connect_admin(&config)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))?;
+        let ctx = request_context(&config);
+        match admin {
+            AdminCommand::Pause => {
+                client
+                    .admin_pause_batching(ctx)
+                    .await?
+                    .map_err(|e| anyhow::anyhow!(e))?;
+                println!("Batching paused");
+            }
+            AdminCommand::Resume => {
+                client
+                    .admin_resume_batching(ctx)
+                    .await?
+                    .map_err(|e| anyhow::anyhow!(e))?;
+                println!("Batching resumed");
+            }
+            AdminCommand::Mempool => {
+                let content = client
+                    .admin_mempool_content(ctx)
+                    .await?
+                    .map_err(|e| anyhow::anyhow!(e))?;
+                println!("{}", serde_json::to_string_pretty(&content)?);
+            }
+            AdminCommand::DropAll => {
+                let dropped = client
+                    .admin_drop_all(ctx)
+                    .await?
+                    .map_err(|e| anyhow::anyhow!(e))?;
+                println!("Dropped {dropped} transaction(s)");
+            }
+            AdminCommand::Snapshot => {
+                let l1_block = client
+                    .admin_snapshot(ctx)
+                    .await?
+                    .map_err(|e| anyhow::anyhow!(e))?;
+                println!("Took a snapshot of the state at L1 block {l1_block}");
+            }
+            AdminCommand::Flush => {
+                let result = client
+                    .admin_flush_batch(ctx)
+                    .await?
+                    .map_err(|e| anyhow::anyhow!(e))?;
+                println!("{result:?}");
+            }
+            AdminCommand::DevReset => {
+                client
+                    .admin_dev_reset(ctx)
+                    .await?
+                    .map_err(|e| anyhow::anyhow!(e))?;
+                println!("Back to the genesis state");
+            }
+            AdminCommand::ProvingStats => {
+                let stats = client.admin_proving_stats(ctx).await?;
+                println!("{}", serde_json::to_string_pretty(&stats)?);
+            }
+            AdminCommand::CheckContract => {
+                let version = client
+                    .admin_check_contract(ctx)
+                    .await?
+                    .map_err(|e| anyhow::anyhow!(e))?;
+                println!("The contract matches, version {version}");
+            }
+            AdminCommand::SetChaos { file } => {
+                let json = match file.as_str() {
+                    "-" => std::io::read_to_string(std::io::stdin())?,
+                    path => std::fs::read_to_string(path)?,
+                };
+                let profile: fusion_api::ChaosProfile = serde_json::from_str(&json)?;
+                client
+                    .admin_set_chaos(ctx, profile)
+                    .await?
+                    .map_err(|e| anyhow::anyhow!(e))?;
+                println!("Chaos profile set");
+            }
+        }
+        return Ok(());
+    }
+
+    let preflight_only = matches!(opts.command, Some(Command::Preflight));
+    // Read once, so a keystore passphrase is asked for a single time. A
+    // hardware wallet is asked to sign each submission instead.
+    if (!config.dev || config.dev_l1) && !config.key_source.is_hardware() {
+        config.eth_private_key = l1_private_key(&config).map_err(|e| anyhow::anyhow!(e))?;
+        config.key_source = KeySource::Hex;
+    }
+    if config.dev_l1 && !config.dev && !preflight_only {
+        config.fusion_l1_contract = dev_l1_contract(&config).await?;
+    }
+    if preflight_only || !opts.skip_preflight {
+        let failures = preflight(&config).await;
+        if !failures.is_empty() {
+            eprintln!("Preflight failed:");
+            for failure in &failures {
+                eprintln!("  - {failure}");
+            }
+            anyhow::bail!("{} preflight check(s) failed", failures.len());
+        }
+        println!("Preflight passed");
+        if preflight_only {
+            return Ok(());
+        }
+    }
+
+    let node = launch::start(config).await?;
+    let stopper = node.sender.clone();
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        info!("Stopping, send the signal again to exit at once");
+        let _ = stopper.send(Trigger::Shutdown).await;
+        shutdown_signal().await;
+        std::process::exit(130);
     });
 
-    run_sequencer(&config, rx).await
+    let (sequencer, mut server) = (node.sequencer, node.server);
+    tokio::select! {
+        stopped = sequencer => {
+            server.shutdown().await;
+            stopped?
+        }
+        served = server.stopped() => {
+            // Without the RPC server nothing reaches the sequencer anymore.
+            let e = match served {
+                Ok(()) => "the listener closed".to_string(),
+                Err(e) => e.to_string(),
+            };
+            error!(error = %e, "ALERT: the RPC server stopped");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Resolves on SIGINT or SIGTERM.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                warn!(error = %e, "Could not listen for SIGTERM");
+                future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = future::pending::<()>();
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        () = terminate => {}
+    }
+}
+
+#[derive(Debug, Parser)]
+#[clap(name = "Fusion sequencer", version = env!("CARGO_PKG_VERSION"))]
+struct Opts {
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "The config file.",
+        default_value = "../fusion.toml"
+    )]
+    pub config: String,
+    #[clap(
+        long,
+        value_name = "NAME",
+        help = "The config profile to use, defaults to $FUSION_PROFILE."
+    )]
+    pub profile: Option<String>,
+    #[clap(
+        long,
+        value_name = "IP",
+        help = "The address the RPC server listens on."
+    )]
+    pub socket_address: Option<String>,
+    #[clap(
+        long,
+        value_name = "PORT",
+        help = "The port the RPC server listens on."
+    )]
+    pub socket_port: Option<u16>,
+    #[clap(long, value_name = "URL", help = "The L1 node endpoint.")]
+    pub eth_rpc_url: Option<String>,
+    #[clap(long, value_name = "ADDRESS", help = "The Fusion contract on L1.")]
+    pub fusion_l1_contract: Option<String>,
+    #[clap(long, value_name = "PATH", help = "The database directory.")]
+    pub database_path: Option<String>,
+    #[clap(long, value_name = "FORMAT", help = "Write logs as `text` or `json`.")]
+    pub log_format: Option<String>,
+    #[clap(
+        long,
+        help = "Commit blocks locally without proving them nor talking to L1."
+    )]
+    pub dev: bool,
+    #[clap(
+        long,
+        help = "Deploy the L1 contract on the local chain if fusion_l1_contract is zero."
+    )]
+    pub dev_l1: bool,
+    #[clap(
+        long,
+        value_name = "COUNT",
+        help = "Number of pre-funded accounts derived from the dev mnemonic."
+    )]
+    pub dev_accounts: Option<u32>,
+    #[clap(
+        long,
+        value_name = "BALANCE",
+        help = "Genesis balance of each dev account."
+    )]
+    pub dev_account_balance: Option<u64>,
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "Dev mode only: JSON file of the accounts funded at genesis."
+    )]
+    pub genesis: Option<String>,
+    #[clap(long, help = "Start without checking the configuration first.")]
+    pub skip_preflight: bool,
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    #[clap(about = "Check the configuration, the L1 node and the contract, then exit.")]
+    Preflight,
+    #[clap(about = "Prove a recorded circuit input, without L1 nor a running sequencer.")]
+    Prove {
+        #[clap(
+            long,
+            value_name = "PATH",
+            help = "Circuit input of one transaction, as the sequencer passes it to the circuit."
+        )]
+        input: String,
+        #[clap(
+            long,
+            value_name = "PATH",
+            help = "Proving key to use instead of proving_key_path."
+        )]
+        proving_key: Option<String>,
+        #[clap(long, value_name = "DIR", default_value = "proof")]
+        output: String,
+    },
+    #[clap(about = "Print the fields of a signed transaction or of submitBlock calldata.")]
+    Decode {
+        #[clap(
+            value_name = "INPUT",
+            help = "SignedTx JSON, or a raw transaction or calldata in hex or base64. Read from stdin when omitted."
+        )]
+        input: Option<String>,
+    },
+    #[clap(about = "Verify a proof locally, exiting with 1 if it is invalid.")]
+    VerifyProof {
+        #[clap(
+            long,
+            value_name = "PATH",
+            help = "proof.json from ZoKrates or `prove`, or the hex ABI encoding sent to the contract."
+        )]
+        proof: String,
+        #[clap(
+            long,
+            value_name = "PATH",
+            default_value = "../circuits/verification.key"
+        )]
+        vk: String,
+        #[clap(
+            long,
+            value_name = "PATH",
+            help = "Public inputs the proof must be for, as a JSON array."
+        )]
+        inputs: Option<String>,
+    },
+    #[clap(about = "Replay blocks from the proofs on L1 and report where they disagree.")]
+    CheckConsistency {
+        #[clap(long, default_value_t = 1)]
+        from: u64,
+        #[clap(long)]
+        to: u64,
+    },
+    #[clap(
+        subcommand,
+        about = "Export the chain of a running sequencer to files."
+    )]
+    Export(ExportCommand),
+    #[clap(about = "Simulate blocks of a synthetic workload locally and report their timings.")]
+    Simulate {
+        #[clap(long, default_value_t = 1000)]
+        accounts: usize,
+        #[clap(long, default_value_t = 10000)]
+        txs: usize,
+        #[clap(long, value_name = "TXS", default_value_t = 64)]
+        batch_size: usize,
+        #[clap(
+            long,
+            default_value_t = 0,
+            help = "Seed of the genesis and the workload."
+        )]
+        seed: u64,
+        #[clap(
+            long,
+            help = "Also build the witnesses, with circuit_path and circuit_abi_path."
+        )]
+        witness: bool,
+        #[clap(
+            long,
+            value_name = "PATH",
+            help = "Write the JSON report there instead of printing it."
+        )]
+        output: Option<String>,
+    },
+    #[clap(
+        subcommand,
+        about = "Operate a sequencer running on this host through its admin_port."
+    )]
+    Admin(AdminCommand),
+    #[clap(
+        subcommand,
+        about = "Export or import snapshots of the state replayed from L1."
+    )]
+    Snapshot(SnapshotCommand),
+    #[clap(about = "Regenerate the conformance vectors of the tx hash, leaves and roots.")]
+    GenVectors {
+        #[clap(long, value_name = "PATH", default_value = test_vectors::DEFAULT_PATH)]
+        output: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum AdminCommand {
+    #[clap(about = "Stop building blocks, transactions are still accepted.")]
+    Pause,
+    #[clap(about = "Build blocks again.")]
+    Resume,
+    #[clap(about = "Print the transactions waiting in the mempool.")]
+    Mempool,
+    #[clap(about = "Drop every transaction waiting in the mempool.")]
+    DropAll,
+    #[clap(about = "Catch up with L1 and snapshot the state replayed from it.")]
+    Snapshot,
+    #[clap(about = "Build a block right away with the queued transactions.")]
+    Flush,
+    #[clap(about = "Dev mode only: go back to the genesis state.")]
+    DevReset,
+    #[clap(about = "Print the time spent proving and submitting the latest blocks.")]
+    ProvingStats,
+    #[clap(about = "Check that the L1 contract still matches the sequencer.")]
+    CheckContract,
+    #[clap(about = "Replace the faults injected in dev mode or on a local chain.")]
+    SetChaos {
+        #[clap(long, value_name = "PATH", help = "JSON chaos profile, - for stdin")]
+        file: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum SnapshotCommand {
+    #[clap(about = "Copy the newest valid snapshot in database_path.")]
+    Export {
+        #[clap(long, value_name = "PATH")]
+        out: String,
+    },
+    #[clap(
+        about = "Start the next run from a snapshot, replacing the L1 progress in database_path."
+    )]
+    Import {
+        #[clap(long, value_name = "PATH")]
+        file: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum ExportCommand {
+    #[clap(about = "Write block, account and manifest JSON documents for a static explorer.")]
+    Explorer {
+        #[clap(long, value_name = "DIR")]
+        out: String,
+        #[clap(long, default_value_t = 1)]
+        from: u64,
+        #[clap(long, help = "Last block to export, defaults to the latest one.")]
+        to: Option<u64>,
+        #[clap(
+            long,
+            help = "Only write the blocks after the manifest in DIR and the accounts they touch."
+        )]
+        incremental: bool,
+    },
+}
+
+impl Opts {
+    /// The config keys set on the command line.
+    fn flags(&self) -> anyhow::Result<Vec<(&'static str, toml::Value)>> {
+        let mut flags = vec![];
+        let strings = [
+            ("socket_address", &self.socket_address),
+            ("eth_rpc_url", &self.eth_rpc_url),
+            ("fusion_l1_contract", &self.fusion_l1_contract),
+            ("database_path", &self.database_path),
+            ("log_format", &self.log_format),
+            ("genesis_path", &self.genesis),
+        ];
+        for (key, value) in strings {
+            if let Some(value) = value {
+                flags.push((key, value.clone().into()));
+            }
+        }
+        if let Some(socket_port) = self.socket_port {
+            flags.push(("socket_port", i64::from(socket_port).into()));
+        }
+        if self.dev {
+            flags.push(("dev", true.into()));
+        }
+        if self.dev_l1 {
+            flags.push(("dev_l1", true.into()));
+        }
+        if let Some(dev_accounts) = self.dev_accounts {
+            flags.push(("dev_accounts", dev_accounts.into()));
+        }
+        if let Some(dev_account_balance) = self.dev_account_balance {
+            flags.push((
+                "dev_account_balance",
+                i64::try_from(dev_account_balance)?.into(),
+            ));
+        }
+        Ok(flags)
+    }
 }
@@ -0,0 +1,139 @@
+//! Periodic pruning of what the sequencer accumulates.
+//!
+//! Blocks older than `block_retention` lose their transactions but keep
+//! their header and transaction hashes, so headers, inclusion proofs and
+//! consistency checks keep working. Blocks with a proof still waiting for
+//! L1 are never pruned.
+
+use std::{path::Path, time::Duration};
+use tokio::sync::{mpsc, oneshot};
+use tracing::info;
+
+use fusion_api::{Block, StorageStats};
+
+use crate::sequencer::Trigger;
+
+fn json_size<T: serde::Serialize>(value: &T) -> u64 {
+    serde_json::to_vec(value).map_or(0, |json| json.len() as u64)
+}
+
+/// Drops the transactions of the blocks before `keep_from`. Returns the
+/// size of what was dropped.
+pub fn prune_blocks(blocks: &mut [Block], keep_from: u64) -> u64 {
+    let mut reclaimed = 0;
+    for block in blocks
+        .iter_mut()
+        .take_while(|block| block.header.number < keep_from)
+        .filter(|block| !block.txs.is_empty())
+    {
+        reclaimed += json_size(&block.txs);
+        block.txs = vec![];
+    }
+    reclaimed
+}
+
+/// Size of the transactions `blocks` still keep.
+pub fn blocks_size(blocks: &[Block]) -> u64 {
+    blocks
+        .iter()
+        .filter(|block| !block.txs.is_empty())
+        .map(|block| json_size(&block.txs))
+        .sum()
+}
+
+/// Size of the files under `path`, zero if it does not exist.
+pub fn dir_size(path: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return 0;
+    };
+    entries
+        .filter_map(Result::ok)
+        .map(|entry| match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => dir_size(&entry.path()),
+            _ => entry.metadata().map_or(0, |metadata| metadata.len()),
+        })
+        .sum()
+}
+
+/// Asks the sequencer to run maintenance every `interval`.
+pub async fn run_maintenance(sequencer: mpsc::Sender<Trigger>, interval: Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+        let (reply_sx, reply_rx) = oneshot::channel();
+        if sequencer.send(Trigger::Maintain(reply_sx)).await.is_err() {
+            return;
+        }
+        if let Ok(stats) = reply_rx.await {
+            info!(
+                pruned_through = stats.pruned_through,
+                reclaimed_bytes = stats.reclaimed_bytes,
+                blocks_bytes = stats.blocks_bytes,
+                database_bytes = stats.database_bytes,
+                "Maintenance done"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fusion_api::{BlockHeader, NATIVE_TOKEN};
+
+    fn block(number: u64, txs: usize) -> Block {
+        let tx = fusion_api::SignedTx {
+            tx: fusion_api::Tx {
+                kind: fusion_api::TxKind::Deposit,
+                sender: number.into(),
+                to: 0.into(),
+                nonce: 1.into(),
+                value: 1.into(),
+                not_before: None,
+                not_after: None,
+                chain_id: None,
+                token: NATIVE_TOKEN,
+            },
+            signature: "1".to_string(),
+        };
+        Block {
+            header: BlockHeader {
+                number,
+                parent_hash: 0.into(),
+                state_root: 0.into(),
+                tx_root: 0.into(),
+                timestamp: 0,
+                l1_root: None,
+                l1_tx_hashes: vec![],
+            },
+            txs: vec![tx; txs],
+            tx_hashes: (0..txs as u64).map(Into::into).collect(),
+        }
+    }
+
+    #[test]
+    fn prune() {
+        let mut blocks: Vec<_> = (1..=5).map(|number| block(number, 2)).collect();
+        let size = blocks_size(&blocks);
+        let reclaimed = prune_blocks(&mut blocks, 3);
+        assert_eq!(reclaimed + blocks_size(&blocks), size);
+        assert!(reclaimed > 0);
+        let pruned: Vec<_> = blocks.iter().map(Block::is_pruned).collect();
+        assert_eq!(pruned, [true, true, false, false, false]);
+        assert_eq!(blocks[0].tx_hashes.len(), 2);
+
+        // Nothing left to reclaim below the same bound.
+        assert_eq!(prune_blocks(&mut blocks, 3), 0);
+    }
+
+    #[test]
+    fn disk_usage() {
+        let dir =
+            std::env::temp_dir().join(format!("fusion-maintenance-{}", rand::random::<u64>()));
+        assert_eq!(dir_size(&dir), 0);
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        std::fs::write(dir.join("a"), [0; 100]).unwrap();
+        std::fs::write(dir.join("nested").join("b"), [0; 20]).unwrap();
+        assert_eq!(dir_size(&dir), 120);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
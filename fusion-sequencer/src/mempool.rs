@@ -0,0 +1,588 @@
+//! Where the mempool is kept, behind `MempoolStore`: a plain list in
+//! memory, or `IndexedStore` on disk, selected by `mempool_backend`.
+//!
+//! On disk, so that a restart does not drop the transactions waiting for
+//! a block, each accepted transaction is appended to `mempool.jsonl` as a
+//! line of JSON, and the file is rewritten with the transactions left
+//! once the proofs of a block are in the submission queue, which then
+//! takes over.
+
+use ethers::types::U256;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+};
+use tracing::warn;
+
+use fusion_api::{hash_tx, SignedTx, NATIVE_TOKEN};
+
+/// What a store needs to know of a queued transaction.
+pub trait Keyed {
+    fn hash(&self) -> U256;
+    /// Sender and nonce. A sender has one pending transaction per nonce
+    /// and token, so the sender of a token transfer is its leaf.
+    fn slot(&self) -> (U256, U256);
+}
+
+impl Keyed for SignedTx {
+    fn hash(&self) -> U256 {
+        hash_tx(&self.tx)
+    }
+
+    fn slot(&self) -> (U256, U256) {
+        match self.tx.token == NATIVE_TOKEN {
+            true => (self.tx.sender, self.tx.nonce),
+            false => (self.tx.sender_account(), self.tx.nonce),
+        }
+    }
+}
+
+/// The transactions waiting for a block, in the order they are included.
+pub trait MempoolStore<T>: Send {
+    /// Queues `tx` last. It is queued even when it could not be written
+    /// to disk.
+    fn insert(&mut self, tx: T) -> Result<(), String>;
+    fn contains(&self, hash: &U256) -> bool;
+    fn get(&self, hash: &U256) -> Option<&T>;
+    /// The pending transaction of `sender` with `nonce`.
+    fn by_slot(&self, sender: U256, nonce: U256) -> Option<&T>;
+    /// The nonces of the pending transactions of `sender`, in order.
+    fn nonces_of(&self, sender: U256) -> Vec<U256>;
+    /// Replaces the transaction with hash `hash` by `tx`, in its place.
+    fn replace(&mut self, hash: &U256, tx: T) -> Option<T>;
+    fn remove(&mut self, hash: &U256) -> Option<T>;
+    /// Takes out the first `n` transactions that `take` accepts, in
+    /// order. The others stay queued as they were.
+    fn drain_batch(&mut self, n: usize, take: &mut dyn FnMut(&T) -> bool) -> Vec<T>;
+    /// Queues `txs` first, in their order.
+    fn requeue(&mut self, txs: Vec<T>);
+    fn len(&self) -> usize;
+    /// Number of transactions queued before the one with hash `hash`.
+    fn position(&self, hash: &U256) -> Option<usize>;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    fn iter_pending(&self) -> Box<dyn Iterator<Item = &T> + '_>;
+    /// Writes the transactions taken out since the last call to disk.
+    fn persist(&mut self) -> Result<(), String>;
+}
+
+/// A store for `backend`, with the transactions it holds in `dir`.
+pub fn open_store<T>(
+    backend: fusion_config::MempoolBackend,
+    dir: &Path,
+) -> Result<Box<dyn MempoolStore<T>>, String>
+where
+    T: Keyed + Serialize + DeserializeOwned + Send + 'static,
+{
+    match backend {
+        fusion_config::MempoolBackend::Memory => Ok(Box::<Vec<T>>::default()),
+        fusion_config::MempoolBackend::Log => {
+            let (log, txs) = MempoolLog::open(dir)?;
+            let mut store = IndexedStore::new(log);
+            store.requeue(txs);
+            // The log holds them in that order already.
+            store.stale = false;
+            Ok(Box::new(store))
+        }
+    }
+}
+
+/// Every lookup goes through the whole list.
+impl<T: Keyed + Send> MempoolStore<T> for Vec<T> {
+    fn insert(&mut self, tx: T) -> Result<(), String> {
+        self.push(tx);
+        Ok(())
+    }
+
+    fn contains(&self, hash: &U256) -> bool {
+        MempoolStore::get(self, hash).is_some()
+    }
+
+    fn get(&self, hash: &U256) -> Option<&T> {
+        self.iter().find(|tx| tx.hash() == *hash)
+    }
+
+    fn by_slot(&self, sender: U256, nonce: U256) -> Option<&T> {
+        self.iter().find(|tx| tx.slot() == (sender, nonce))
+    }
+
+    fn nonces_of(&self, sender: U256) -> Vec<U256> {
+        let mut nonces: Vec<_> = self
+            .iter()
+            .map(Keyed::slot)
+            .filter(|slot| slot.0 == sender)
+            .map(|(_, nonce)| nonce)
+            .collect();
+        nonces.sort_unstable();
+        nonces
+    }
+
+    fn replace(&mut self, hash: &U256, tx: T) -> Option<T> {
+        let i = self.iter().position(|pending| pending.hash() == *hash)?;
+        Some(std::mem::replace(&mut self[i], tx))
+    }
+
+    fn remove(&mut self, hash: &U256) -> Option<T> {
+        let i = self.iter().position(|pending| pending.hash() == *hash)?;
+        Some(Vec::remove(self, i))
+    }
+
+    fn drain_batch(&mut self, n: usize, take: &mut dyn FnMut(&T) -> bool) -> Vec<T> {
+        let (mut kept, mut taken) = (vec![], vec![]);
+        for tx in self.drain(..) {
+            if taken.len() < n && take(&tx) {
+                taken.push(tx);
+            } else {
+                kept.push(tx);
+            }
+        }
+        *self = kept;
+        taken
+    }
+
+    fn requeue(&mut self, txs: Vec<T>) {
+        self.splice(0..0, txs);
+    }
+
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+
+    fn position(&self, hash: &U256) -> Option<usize> {
+        self.iter().position(|tx| tx.hash() == *hash)
+    }
+
+    fn iter_pending(&self) -> Box<dyn Iterator<Item = &T> + '_> {
+        Box::new(self.iter())
+    }
+
+    fn persist(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Transactions by queue position, indexed by hash and by sender and
+/// nonce, and logged to disk by a `MempoolLog`.
+pub struct IndexedStore<T> {
+    /// With their hash, so that it is computed once.
+    queue: BTreeMap<i64, (U256, T)>,
+    by_hash: BTreeMap<U256, i64>,
+    by_slot: BTreeMap<(U256, U256), i64>,
+    /// Position of the next transaction queued first.
+    front: i64,
+    /// Position of the next transaction queued last.
+    back: i64,
+    log: MempoolLog,
+    /// Whether the log holds transactions that were taken out.
+    stale: bool,
+}
+
+impl<T: Keyed + Serialize> IndexedStore<T> {
+    pub fn new(log: MempoolLog) -> Self {
+        Self {
+            queue: BTreeMap::new(),
+            by_hash: BTreeMap::new(),
+            by_slot: BTreeMap::new(),
+            front: -1,
+            back: 0,
+            log,
+            stale: false,
+        }
+    }
+
+    fn put(&mut self, position: i64, hash: U256, tx: T) {
+        self.by_hash.insert(hash, position);
+        self.by_slot.insert(tx.slot(), position);
+        self.queue.insert(position, (hash, tx));
+    }
+
+    fn take(&mut self, position: i64) -> Option<T> {
+        let (hash, tx) = self.queue.remove(&position)?;
+        self.by_hash.remove(&hash);
+        self.by_slot.remove(&tx.slot());
+        self.stale = true;
+        Some(tx)
+    }
+}
+
+impl<T: Keyed + Serialize + Send> MempoolStore<T> for IndexedStore<T> {
+    fn insert(&mut self, tx: T) -> Result<(), String> {
+        let written = self.log.append(&tx);
+        let position = self.back;
+        self.back += 1;
+        self.put(position, tx.hash(), tx);
+        written
+    }
+
+    fn contains(&self, hash: &U256) -> bool {
+        self.by_hash.contains_key(hash)
+    }
+
+    fn get(&self, hash: &U256) -> Option<&T> {
+        let position = self.by_hash.get(hash)?;
+        self.queue.get(position).map(|(_, tx)| tx)
+    }
+
+    fn by_slot(&self, sender: U256, nonce: U256) -> Option<&T> {
+        let position = self.by_slot.get(&(sender, nonce))?;
+        self.queue.get(position).map(|(_, tx)| tx)
+    }
+
+    /// Walks the transactions of `sender` only.
+    fn nonces_of(&self, sender: U256) -> Vec<U256> {
+        self.by_slot
+            .range((sender, U256::zero())..=(sender, U256::MAX))
+            .map(|((_, nonce), _)| *nonce)
+            .collect()
+    }
+
+    fn replace(&mut self, hash: &U256, tx: T) -> Option<T> {
+        let position = *self.by_hash.get(hash)?;
+        let replaced = self.take(position);
+        self.put(position, tx.hash(), tx);
+        replaced
+    }
+
+    fn remove(&mut self, hash: &U256) -> Option<T> {
+        let position = *self.by_hash.get(hash)?;
+        self.take(position)
+    }
+
+    fn drain_batch(&mut self, n: usize, take: &mut dyn FnMut(&T) -> bool) -> Vec<T> {
+        let positions: Vec<_> = self
+            .queue
+            .iter()
+            .filter(|(_, (_, tx))| take(tx))
+            .map(|(position, _)| *position)
+            .take(n)
+            .collect();
+        positions
+            .into_iter()
+            .filter_map(|position| self.take(position))
+            .collect()
+    }
+
+    fn requeue(&mut self, txs: Vec<T>) {
+        for tx in txs.into_iter().rev() {
+            let position = self.front;
+            self.front -= 1;
+            self.put(position, tx.hash(), tx);
+        }
+        // Not in the log, or not in that order.
+        self.stale = true;
+    }
+
+    fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Walks the transactions before it only, not the whole queue.
+    fn position(&self, hash: &U256) -> Option<usize> {
+        let position = self.by_hash.get(hash)?;
+        Some(self.queue.range(..*position).count())
+    }
+
+    fn iter_pending(&self) -> Box<dyn Iterator<Item = &T> + '_> {
+        Box::new(self.queue.values().map(|(_, tx)| tx))
+    }
+
+    fn persist(&mut self) -> Result<(), String> {
+        if !self.stale {
+            return Ok(());
+        }
+        let txs: Vec<_> = self.iter_pending().collect();
+        self.log.compact(&txs)?;
+        self.stale = false;
+        Ok(())
+    }
+}
+
+pub struct MempoolLog {
+    /// `None` keeps the mempool in memory only.
+    path: Option<PathBuf>,
+}
+
+impl MempoolLog {
+    pub fn in_memory() -> Self {
+        Self { path: None }
+    }
+
+    /// Opens the log in `dir` and returns it with the transactions it
+    /// holds, in the order they were accepted. A last line cut short by a
+    /// crash is dropped.
+    pub fn open<T: DeserializeOwned + Serialize>(dir: &Path) -> Result<(Self, Vec<T>), String> {
+        let log = Self {
+            path: Some(dir.join("mempool.jsonl")),
+        };
+        let path = log.path.as_ref().unwrap();
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((log, vec![])),
+            Err(e) => return Err(format!("Could not read {}: {e}", path.display())),
+        };
+
+        let (complete, torn) = match contents.rfind('\n') {
+            Some(end) => contents.split_at(end + 1),
+            None => ("", contents.as_str()),
+        };
+        let txs = complete
+            .lines()
+            .enumerate()
+            .map(|(i, line)| {
+                serde_json::from_str(line).map_err(|e| {
+                    format!("Could not parse line {} of {}: {e}", i + 1, path.display())
+                })
+            })
+            .collect::<Result<Vec<T>, _>>()?;
+        if !torn.is_empty() {
+            warn!(
+                path = %path.display(),
+                "Dropping an incomplete transaction at the end of the mempool log"
+            );
+            log.compact(&txs)?;
+        }
+        Ok((log, txs))
+    }
+
+    /// Records a transaction accepted in the mempool.
+    pub fn append<T: Serialize>(&self, tx: &T) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut line = serde_json::to_vec(tx).map_err(|e| e.to_string())?;
+        line.push(b'\n');
+        std::fs::create_dir_all(path.parent().unwrap())
+            .and_then(|()| OpenOptions::new().create(true).append(true).open(path))
+            .and_then(|mut file| file.write_all(&line))
+            .map_err(|e| format!("Could not write {}: {e}", path.display()))
+    }
+
+    /// Replaces the log with `txs`, what is left in the mempool.
+    pub fn compact<T: Serialize>(&self, txs: &[T]) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut lines = vec![];
+        for tx in txs {
+            serde_json::to_writer(&mut lines, tx).map_err(|e| e.to_string())?;
+            lines.push(b'\n');
+        }
+        let tmp = path.with_extension("jsonl.tmp");
+        std::fs::create_dir_all(path.parent().unwrap())
+            .and_then(|()| std::fs::write(&tmp, lines))
+            .and_then(|()| std::fs::rename(&tmp, path))
+            .map_err(|e| format!("Could not write {}: {e}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fusion_api::{SignedTx, Tx, TxKind, NATIVE_TOKEN};
+
+    fn tx(nonce: u64) -> SignedTx {
+        SignedTx {
+            tx: Tx {
+                kind: TxKind::Transfer,
+                sender: 1.into(),
+                to: 2.into(),
+                nonce: nonce.into(),
+                value: 10.into(),
+                not_before: None,
+                not_after: Some(100),
+                chain_id: None,
+                token: NATIVE_TOKEN,
+            },
+            signature: format!("{nonce}"),
+        }
+    }
+
+    fn nonces(txs: &[SignedTx]) -> Vec<u64> {
+        txs.iter().map(|tx| tx.tx.nonce.as_u64()).collect()
+    }
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("fusion-mempool-{}", rand::random::<u64>()))
+    }
+
+    #[test]
+    fn restart_replays() {
+        let dir = temp_dir();
+        let (log, txs) = MempoolLog::open::<SignedTx>(&dir).unwrap();
+        assert!(txs.is_empty());
+        for nonce in 1..=3 {
+            log.append(&tx(nonce)).unwrap();
+        }
+        drop(log);
+
+        let (log, txs) = MempoolLog::open::<SignedTx>(&dir).unwrap();
+        assert_eq!(nonces(&txs), [1, 2, 3]);
+
+        // The first two made it into a block.
+        log.compact(&txs[2..]).unwrap();
+        log.append(&tx(4)).unwrap();
+        let (_, txs) = MempoolLog::open::<SignedTx>(&dir).unwrap();
+        assert_eq!(nonces(&txs), [3, 4]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn torn_write() {
+        let dir = temp_dir();
+        let (log, _) = MempoolLog::open::<SignedTx>(&dir).unwrap();
+        log.append(&tx(1)).unwrap();
+        let path = dir.join("mempool.jsonl");
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"tx":{"kind":"Tra"#).unwrap();
+
+        let (log, txs) = MempoolLog::open::<SignedTx>(&dir).unwrap();
+        assert_eq!(nonces(&txs), [1]);
+        // Appending after the torn line still gives valid lines.
+        log.append(&tx(2)).unwrap();
+        let (_, txs) = MempoolLog::open::<SignedTx>(&dir).unwrap();
+        assert_eq!(nonces(&txs), [1, 2]);
+
+        std::fs::write(&path, "{}\n").unwrap();
+        assert!(MempoolLog::open::<SignedTx>(&dir)
+            .unwrap_err()
+            .contains("line 1"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Cheaper to hash than a `SignedTx`.
+    #[derive(Clone, Debug, PartialEq, Eq, Serialize, serde::Deserialize)]
+    struct Queued {
+        id: u64,
+        sender: u64,
+        nonce: u64,
+    }
+
+    impl Keyed for Queued {
+        fn hash(&self) -> U256 {
+            self.id.into()
+        }
+
+        fn slot(&self) -> (U256, U256) {
+            (self.sender.into(), self.nonce.into())
+        }
+    }
+
+    fn queued(id: u64) -> Queued {
+        Queued {
+            id,
+            sender: id % 3,
+            nonce: id / 3,
+        }
+    }
+
+    fn ids(store: &dyn MempoolStore<Queued>) -> Vec<u64> {
+        store.iter_pending().map(|queued| queued.id).collect()
+    }
+
+    #[test]
+    fn stores_agree() {
+        let stores: [Box<dyn MempoolStore<Queued>>; 2] = [
+            Box::<Vec<Queued>>::default(),
+            Box::new(IndexedStore::new(MempoolLog::in_memory())),
+        ];
+        for mut store in stores {
+            for id in 1..=10 {
+                store.insert(queued(id)).unwrap();
+            }
+            assert_eq!(store.len(), 10);
+            assert!(store.contains(&4.into()));
+            assert!(!store.contains(&11.into()));
+            assert_eq!(store.by_slot(1.into(), 1.into()), Some(&queued(4)));
+            assert_eq!(store.get(&7.into()), Some(&queued(7)));
+            let nonces: Vec<U256> = (0..4).map(U256::from).collect();
+            assert_eq!(store.nonces_of(1.into()), nonces);
+            assert!(store.nonces_of(3.into()).is_empty());
+
+            let replacement = Queued {
+                id: 40,
+                ..queued(4)
+            };
+            assert_eq!(
+                store.replace(&4.into(), replacement.clone()),
+                Some(queued(4))
+            );
+            assert!(!store.contains(&4.into()));
+            assert_eq!(store.by_slot(1.into(), 1.into()), Some(&replacement));
+            assert_eq!(store.remove(&5.into()), Some(queued(5)));
+            assert_eq!(store.remove(&5.into()), None);
+            assert_eq!(ids(&*store), [1, 2, 3, 40, 6, 7, 8, 9, 10]);
+
+            let even = store.drain_batch(3, &mut |queued| queued.id % 2 == 0);
+            assert_eq!(
+                even.iter().map(|queued| queued.id).collect::<Vec<_>>(),
+                [2, 40, 6]
+            );
+            assert_eq!(ids(&*store), [1, 3, 7, 8, 9, 10]);
+            store.requeue(even);
+            assert_eq!(ids(&*store), [2, 40, 6, 1, 3, 7, 8, 9, 10]);
+            assert_eq!(store.position(&2.into()), Some(0));
+            assert_eq!(store.position(&1.into()), Some(3));
+            assert_eq!(store.position(&10.into()), Some(8));
+            assert_eq!(store.position(&5.into()), None);
+            assert_eq!(store.by_slot(0.into(), 2.into()), Some(&queued(6)));
+            assert_eq!(store.drain_batch(usize::MAX, &mut |_| true).len(), 9);
+            assert!(store.is_empty());
+        }
+    }
+
+    #[test]
+    fn indexed_store_persists() {
+        let dir = temp_dir();
+        let open = || open_store::<Queued>(fusion_config::MempoolBackend::Log, &dir).unwrap();
+        let mut store = open();
+        for id in 1..=5 {
+            store.insert(queued(id)).unwrap();
+        }
+        assert_eq!(ids(&*open()), [1, 2, 3, 4, 5]);
+
+        let taken = store.drain_batch(2, &mut |_| true);
+        store.replace(&4.into(), queued(40)).unwrap();
+        // Taken out, but still on disk until persisted.
+        assert_eq!(ids(&*open()).len(), 5);
+        store.persist().unwrap();
+        assert_eq!(ids(&*open()), [3, 40, 5]);
+        store.requeue(taken);
+        store.persist().unwrap();
+        let mut store = open();
+        assert_eq!(ids(&*store), [1, 2, 3, 40, 5]);
+        assert_eq!(store.by_slot(2.into(), 0.into()), Some(&queued(2)));
+        store.insert(queued(6)).unwrap();
+        assert_eq!(ids(&*open()), [1, 2, 3, 40, 5, 6]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn large_mempool() {
+        let mut store = IndexedStore::new(MempoolLog::in_memory());
+        for id in 0..50_000 {
+            store.insert(queued(id)).unwrap();
+        }
+        assert!(store.contains(&49_999.into()));
+        assert_eq!(
+            store.by_slot(1.into(), 16_666.into()),
+            Some(&queued(49_999))
+        );
+
+        // Batches come out full and in order until the store is empty.
+        let mut drained = 0;
+        while !store.is_empty() {
+            let batch = store.drain_batch(1000, &mut |_| true);
+            assert_eq!(batch.len(), 1000);
+            assert_eq!(batch[0].id, drained);
+            drained += batch.len() as u64;
+        }
+        assert_eq!(drained, 50_000);
+    }
+}
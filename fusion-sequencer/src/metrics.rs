@@ -0,0 +1,421 @@
+//! Prometheus metrics of the sequencer, served as text on `/metrics`.
+//!
+//! Counters only ever grow while the sequencer runs and restart from zero
+//! with it. The mempool depth is read when scraped rather than tracked.
+//!
+//! The same port answers the liveness probe `/health`, 200 as long as
+//! the process serves requests, and the readiness probe `/ready`, 503
+//! with the failed checks of `probes::status` when not ready.
+
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpListener,
+};
+use tracing::{debug, info};
+
+use fusion_api::{SequencerStatus, SubmitError};
+
+/// Upper bounds in seconds of the L1 submission latency buckets.
+const LATENCY_BUCKETS: [f64; 8] = [0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0];
+
+/// Why a transaction was turned down, as the `reason` label.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rejection {
+    Signature,
+    Balance,
+    Nonce,
+    RateLimited,
+    MempoolFull,
+    SenderLimit,
+    Other,
+}
+
+impl Rejection {
+    const ALL: [Rejection; 7] = [
+        Rejection::Signature,
+        Rejection::Balance,
+        Rejection::Nonce,
+        Rejection::RateLimited,
+        Rejection::MempoolFull,
+        Rejection::SenderLimit,
+        Rejection::Other,
+    ];
+
+    /// The rejection an error message of the sequencer stands for.
+    pub fn of(reason: &str) -> Self {
+        let reason = reason.to_lowercase();
+        if reason.starts_with("rate limited") {
+            Rejection::RateLimited
+        } else if reason.contains("mempool is full") {
+            Rejection::MempoolFull
+        } else if reason.contains("pending already") {
+            Rejection::SenderLimit
+        } else if reason.contains("signature") {
+            Rejection::Signature
+        } else if reason.contains("balance") {
+            Rejection::Balance
+        } else if reason.contains("nonce") {
+            Rejection::Nonce
+        } else {
+            Rejection::Other
+        }
+    }
+
+    /// The rejection of a refused submission.
+    pub fn of_submit(error: &SubmitError) -> Self {
+        match error {
+            SubmitError::InvalidSignature(_) => Rejection::Signature,
+            SubmitError::BadNonce { .. } | SubmitError::Duplicate { .. } => Rejection::Nonce,
+            SubmitError::MempoolFull { .. } => Rejection::MempoolFull,
+            SubmitError::SenderLimit { .. } => Rejection::SenderLimit,
+            SubmitError::RateLimited { .. } => Rejection::RateLimited,
+            SubmitError::CommitmentsFull { .. }
+            | SubmitError::Invalid(_)
+            | SubmitError::Internal(_) => Rejection::Other,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Rejection::Signature => "signature",
+            Rejection::Balance => "balance",
+            Rejection::Nonce => "nonce",
+            Rejection::RateLimited => "rate_limited",
+            Rejection::MempoolFull => "mempool_full",
+            Rejection::SenderLimit => "sender_limit",
+            Rejection::Other => "other",
+        }
+    }
+}
+
+#[derive(Default)]
+struct Histogram {
+    /// Observations in each of `LATENCY_BUCKETS`, not cumulated.
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+#[derive(Default)]
+struct Counters {
+    received: AtomicU64,
+    rejected: [AtomicU64; Rejection::ALL.len()],
+    batches_submitted: AtomicU64,
+    submission_failures: AtomicU64,
+    last_submitted_batch: AtomicU64,
+    calldata_saved: AtomicU64,
+    last_batch_calldata_saved: AtomicU64,
+    l1_endpoint: AtomicU64,
+    l1_endpoint_failures: AtomicU64,
+    submission_latency: Mutex<Histogram>,
+}
+
+/// Metrics updated by the RPC server and the sequencer.
+#[derive(Clone, Default)]
+pub struct Metrics(Arc<Counters>);
+
+impl Metrics {
+    pub fn received(&self) {
+        self.0.received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn rejected(&self, rejection: Rejection) {
+        self.0.rejected[rejection as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a proof submission to L1 that took `latency`.
+    pub fn submission(&self, latency: Duration, succeeded: bool) {
+        if !succeeded {
+            self.0.submission_failures.fetch_add(1, Ordering::Relaxed);
+        }
+        let seconds = latency.as_secs_f64();
+        let mut histogram = self.0.submission_latency.lock().unwrap();
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            histogram.buckets[bucket] += 1;
+        }
+        histogram.count += 1;
+        histogram.sum += seconds;
+    }
+
+    /// Records that every proof of `batch` was accepted by L1.
+    pub fn batch_submitted(&self, batch: u64) {
+        self.0.batches_submitted.fetch_add(1, Ordering::Relaxed);
+        self.0
+            .last_submitted_batch
+            .fetch_max(batch, Ordering::Relaxed);
+    }
+
+    /// Records the calldata bytes the compact encoding saves on the
+    /// proofs of a block, see `calldata`.
+    pub fn calldata_saved(&self, bytes: usize) {
+        self.0
+            .calldata_saved
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+        self.0
+            .last_batch_calldata_saved
+            .store(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Records that L1 requests now go to endpoint `index`, 0 being
+    /// `eth_rpc_url`, see `failover`.
+    pub fn l1_endpoint(&self, index: usize) {
+        self.0.l1_endpoint.store(index as u64, Ordering::Relaxed);
+    }
+
+    /// Records an L1 endpoint that could not be reached.
+    pub fn l1_endpoint_failure(&self) {
+        self.0.l1_endpoint_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The metrics in the Prometheus text format.
+    pub fn render(&self, mempool_depth: usize) -> String {
+        let counters = &self.0;
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed).to_string();
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: Vec<(String, String)>| {
+            out += &format!("# HELP fusion_{name} {help}\n# TYPE fusion_{name} {kind}\n");
+            for (suffix, value) in samples {
+                out += &format!("fusion_{name}{suffix} {value}\n");
+            }
+        };
+        let single = |value: String| vec![(String::new(), value)];
+        metric(
+            "transactions_received_total",
+            "counter",
+            "Transactions submitted to the RPC server.",
+            single(load(&counters.received)),
+        );
+        metric(
+            "transactions_rejected_total",
+            "counter",
+            "Transactions refused or dropped from the mempool, by reason.",
+            Rejection::ALL
+                .iter()
+                .map(|rejection| {
+                    (
+                        format!("{{reason=\"{}\"}}", rejection.label()),
+                        load(&counters.rejected[*rejection as usize]),
+                    )
+                })
+                .collect(),
+        );
+        metric(
+            "mempool_depth",
+            "gauge",
+            "Transactions waiting in the mempool.",
+            single(mempool_depth.to_string()),
+        );
+        metric(
+            "batches_submitted_total",
+            "counter",
+            "Blocks whose proofs were all accepted by L1.",
+            single(load(&counters.batches_submitted)),
+        );
+        metric(
+            "batch_submission_failures_total",
+            "counter",
+            "Proof submissions to L1 that failed and are retried.",
+            single(load(&counters.submission_failures)),
+        );
+        metric(
+            "last_submitted_batch",
+            "gauge",
+            "Latest block whose proofs were all accepted by L1.",
+            single(load(&counters.last_submitted_batch)),
+        );
+        metric(
+            "calldata_bytes_saved_total",
+            "counter",
+            "Calldata bytes the compact encoding saves with compressed_calldata, or would save without it.",
+            single(load(&counters.calldata_saved)),
+        );
+        metric(
+            "last_batch_calldata_bytes_saved",
+            "gauge",
+            "Calldata bytes the compact encoding saves on the latest proven block.",
+            single(load(&counters.last_batch_calldata_saved)),
+        );
+        metric(
+            "l1_active_endpoint",
+            "gauge",
+            "L1 endpoint requests go to, 0 for eth_rpc_url, then the eth_rpc_fallback_urls.",
+            single(load(&counters.l1_endpoint)),
+        );
+        metric(
+            "l1_endpoint_failures_total",
+            "counter",
+            "L1 requests that could not reach their endpoint and moved on to the next one.",
+            single(load(&counters.l1_endpoint_failures)),
+        );
+        let histogram = counters.submission_latency.lock().unwrap();
+        let mut cumulated = 0;
+        let mut samples: Vec<_> = LATENCY_BUCKETS
+            .iter()
+            .zip(histogram.buckets)
+            .map(|(bound, count)| {
+                cumulated += count;
+                (format!("_bucket{{le=\"{bound}\"}}"), cumulated.to_string())
+            })
+            .collect();
+        samples.extend([
+            (
+                "_bucket{le=\"+Inf\"}".to_string(),
+                histogram.count.to_string(),
+            ),
+            ("_sum".to_string(), histogram.sum.to_string()),
+            ("_count".to_string(), histogram.count.to_string()),
+        ]);
+        metric(
+            "l1_submission_seconds",
+            "histogram",
+            "Seconds to submit a proof to L1 and have it confirmed.",
+            samples,
+        );
+        out
+    }
+}
+
+fn response(status: &str, content_type: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+/// Answers `GET /metrics`, `/health` and `/ready` on `addr` until the
+/// process exits. The mempool depth is sampled with `mempool_depth` on
+/// every scrape, and the readiness with `status` on every probe.
+pub async fn serve(
+    addr: SocketAddr,
+    metrics: Metrics,
+    mempool_depth: impl Fn() -> usize + Send + Sync + 'static,
+    status: impl Fn() -> SequencerStatus + Send + Sync + 'static,
+) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!(addr = %listener.local_addr()?, "Serving metrics");
+    let mempool_depth = Arc::new(mempool_depth);
+    let status = Arc::new(status);
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let metrics = metrics.clone();
+        let mempool_depth = mempool_depth.clone();
+        let status = status.clone();
+        tokio::spawn(async move {
+            let (read, mut write) = stream.into_split();
+            let mut request_line = String::new();
+            if BufReader::new(read)
+                .read_line(&mut request_line)
+                .await
+                .is_err()
+            {
+                return;
+            }
+            let response = match request_line.split_whitespace().collect::<Vec<_>>()[..] {
+                ["GET", "/metrics", _] => response(
+                    "200 OK",
+                    "text/plain; version=0.0.4",
+                    &metrics.render(mempool_depth()),
+                ),
+                ["GET", "/health", _] => {
+                    let body = serde_json::json!({
+                        "alive": true,
+                        "block_number": status().block_number,
+                    });
+                    response("200 OK", "application/json", &body.to_string())
+                }
+                ["GET", "/ready", _] => {
+                    let status = status();
+                    let code = if status.ready {
+                        "200 OK"
+                    } else {
+                        "503 Service Unavailable"
+                    };
+                    let body = serde_json::to_string(&status).unwrap_or_default();
+                    response(code, "application/json", &body)
+                }
+                _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    .to_string(),
+            };
+            if let Err(e) = write.write_all(response.as_bytes()).await {
+                debug!(%peer, error = %e, "Could not answer a metrics scrape");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rejection_of() {
+        assert_eq!(Rejection::of("Invalid signature"), Rejection::Signature);
+        assert_eq!(Rejection::of("Insufficient balance"), Rejection::Balance);
+        assert_eq!(Rejection::of("Nonce too low"), Rejection::Nonce);
+        assert_eq!(
+            Rejection::of("Rate limited: more than 10 submissions per second"),
+            Rejection::RateLimited
+        );
+        assert_eq!(
+            Rejection::of("The mempool is full, with 10 transactions"),
+            Rejection::MempoolFull
+        );
+        assert_eq!(
+            Rejection::of("The sender has 16 transactions pending already"),
+            Rejection::SenderLimit
+        );
+        assert_eq!(Rejection::of("Expired"), Rejection::Other);
+
+        let duplicate = SubmitError::Duplicate { nonce: 1.into() };
+        assert_eq!(Rejection::of_submit(&duplicate), Rejection::Nonce);
+        let chain = SubmitError::Invalid("Signed for chain 2".to_string());
+        assert_eq!(Rejection::of_submit(&chain), Rejection::Other);
+    }
+
+    #[test]
+    fn render() {
+        let metrics = Metrics::default();
+        metrics.received();
+        metrics.received();
+        metrics.rejected(Rejection::Nonce);
+        metrics.submission(Duration::from_millis(800), false);
+        metrics.submission(Duration::from_secs(3), true);
+        metrics.batch_submitted(4);
+        metrics.batch_submitted(3);
+        metrics.calldata_saved(300);
+        metrics.calldata_saved(200);
+        metrics.l1_endpoint_failure();
+        metrics.l1_endpoint(1);
+        let rendered = metrics.render(5);
+        for line in [
+            "# TYPE fusion_transactions_received_total counter",
+            "fusion_transactions_received_total 2",
+            "fusion_transactions_rejected_total{reason=\"nonce\"} 1",
+            "fusion_transactions_rejected_total{reason=\"balance\"} 0",
+            "fusion_mempool_depth 5",
+            "fusion_batches_submitted_total 2",
+            "fusion_batch_submission_failures_total 1",
+            "fusion_last_submitted_batch 4",
+            "fusion_calldata_bytes_saved_total 500",
+            "fusion_last_batch_calldata_bytes_saved 200",
+            "fusion_l1_active_endpoint 1",
+            "fusion_l1_endpoint_failures_total 1",
+            "fusion_l1_submission_seconds_bucket{le=\"0.5\"} 0",
+            "fusion_l1_submission_seconds_bucket{le=\"1\"} 1",
+            "fusion_l1_submission_seconds_bucket{le=\"5\"} 2",
+            "fusion_l1_submission_seconds_bucket{le=\"+Inf\"} 2",
+            "fusion_l1_submission_seconds_count 2",
+        ] {
+            assert!(rendered.lines().any(|l| l == line), "missing {line}");
+        }
+    }
+}
@@ -1,37 +1,405 @@
 use ethers::core::utils::hex;
 use ethers::prelude::*;
+use ethers::types::transaction::{eip2718::TypedTransaction, eip712::Eip712};
+use fusion_config::{Config, KeySource};
+use fusion_wallet::keys;
+use futures::future::BoxFuture;
 use k256::SecretKey;
-use log::info;
-use std::{convert::TryFrom, sync::Arc, time::Duration};
+use std::{fmt, sync::Arc, time::Duration};
+use tracing::info;
+
+use crate::failover::FailoverHttp;
+use crate::metrics::Metrics;
 
 #[derive(Debug, Clone)]
 pub struct Node {
-    pub http_client: Arc<SignerMiddleware<Provider<Http>, LocalWallet>>,
-    pub http_endpoint: String,
+    pub http_client: Arc<SignerMiddleware<Provider<FailoverHttp>, L1Signer>>,
+}
+
+/// The key signing L1 transactions: in memory, or on a hardware wallet
+/// that asks to confirm each of them.
+#[derive(Debug)]
+pub enum L1Signer {
+    Local(LocalWallet),
+    #[cfg(feature = "ledger")]
+    Ledger(Ledger),
+    #[cfg(feature = "trezor")]
+    Trezor(Trezor),
+}
+
+#[derive(Debug)]
+pub struct L1SignerError(String);
+
+impl fmt::Display for L1SignerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for L1SignerError {}
+
+fn signer_error(e: impl fmt::Display) -> L1SignerError {
+    L1SignerError(e.to_string())
+}
+
+/// Evaluates `$body` with `$signer` bound to the signer of any variant.
+macro_rules! with_signer {
+    ($l1_signer:expr, $signer:ident => $body:expr) => {
+        match $l1_signer {
+            L1Signer::Local($signer) => $body,
+            #[cfg(feature = "ledger")]
+            L1Signer::Ledger($signer) => $body,
+            #[cfg(feature = "trezor")]
+            L1Signer::Trezor($signer) => $body,
+        }
+    };
+}
+
+impl L1Signer {
+    /// The hardware wallet `key_source` names, for L1 chain `chain_id`.
+    pub async fn hardware(config: &Config, chain_id: u64) -> anyhow::Result<Self> {
+        let path = config.eth_derivation_path.clone();
+        match config.key_source {
+            #[cfg(feature = "ledger")]
+            KeySource::Ledger => Ok(L1Signer::Ledger(
+                Ledger::new(HDPath::Other(path), chain_id)
+                    .await
+                    .map_err(|e| anyhow::anyhow!("Could not open the Ledger: {e}"))?,
+            )),
+            #[cfg(feature = "trezor")]
+            KeySource::Trezor => Ok(L1Signer::Trezor(
+                Trezor::new(TrezorHDPath::Other(path), chain_id, None)
+                    .await
+                    .map_err(|e| anyhow::anyhow!("Could not open the Trezor: {e}"))?,
+            )),
+            #[allow(unreachable_patterns)]
+            KeySource::Ledger | KeySource::Trezor => {
+                let _ = (path, chain_id);
+                anyhow::bail!(
+                    "key_source is {:?}, but the sequencer was built without the feature of that name",
+                    config.key_source
+                )
+            }
+            _ => anyhow::bail!(
+                "key_source {:?} is not a hardware wallet",
+                config.key_source
+            ),
+        }
+    }
+}
+
+// What `#[async_trait]` expands the methods of `Signer` to.
+impl Signer for L1Signer {
+    type Error = L1SignerError;
+
+    fn sign_message<'life0, 'async_trait, S>(
+        &'life0 self,
+        message: S,
+    ) -> BoxFuture<'async_trait, Result<Signature, Self::Error>>
+    where
+        S: 'async_trait + Send + Sync + AsRef<[u8]>,
+        'life0: 'async_trait,
+        Self: 'async_trait,
+    {
+        Box::pin(async move {
+            with_signer!(self, signer => {
+                signer.sign_message(message).await.map_err(signer_error)
+            })
+        })
+    }
+
+    fn sign_transaction<'life0, 'life1, 'async_trait>(
+        &'life0 self,
+        message: &'life1 TypedTransaction,
+    ) -> BoxFuture<'async_trait, Result<Signature, Self::Error>>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        Self: 'async_trait,
+    {
+        Box::pin(async move {
+            with_signer!(self, signer => {
+                signer.sign_transaction(message).await.map_err(signer_error)
+            })
+        })
+    }
+
+    fn sign_typed_data<'life0, 'life1, 'async_trait, T>(
+        &'life0 self,
+        payload: &'life1 T,
+    ) -> BoxFuture<'async_trait, Result<Signature, Self::Error>>
+    where
+        T: 'async_trait + Eip712 + Send + Sync,
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        Self: 'async_trait,
+    {
+        Box::pin(async move {
+            with_signer!(self, signer => {
+                signer.sign_typed_data(payload).await.map_err(signer_error)
+            })
+        })
+    }
+
+    fn address(&self) -> Address {
+        with_signer!(self, signer => signer.address())
+    }
+
+    fn chain_id(&self) -> u64 {
+        with_signer!(self, signer => signer.chain_id())
+    }
+
+    fn with_chain_id<T: Into<u64>>(self, chain_id: T) -> Self {
+        match self {
+            L1Signer::Local(wallet) => L1Signer::Local(wallet.with_chain_id(chain_id)),
+            #[cfg(feature = "ledger")]
+            L1Signer::Ledger(ledger) => L1Signer::Ledger(ledger.with_chain_id(chain_id)),
+            #[cfg(feature = "trezor")]
+            L1Signer::Trezor(trezor) => L1Signer::Trezor(trezor.with_chain_id(chain_id)),
+        }
+    }
+}
+
+/// How an L1 provider polls and when it gives up on the node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProviderSettings {
+    pub poll_interval: Duration,
+    pub request_timeout: Duration,
+    pub connect_timeout: Duration,
+    /// How long an unreachable endpoint is skipped at first.
+    pub failover_backoff: Duration,
+}
+
+impl ProviderSettings {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            poll_interval: Duration::from_millis(config.l1_poll_interval_ms),
+            request_timeout: Duration::from_secs(config.l1_request_timeout),
+            connect_timeout: Duration::from_secs(config.l1_connect_timeout),
+            failover_backoff: Duration::from_secs(config.l1_failover_backoff),
+        }
+    }
+}
+
+/// The HTTP transport to `http_endpoint`, with the timeouts of `settings`.
+pub fn http_transport(http_endpoint: &str, settings: &ProviderSettings) -> anyhow::Result<Http> {
+    let client = reqwest::Client::builder()
+        .timeout(settings.request_timeout)
+        .connect_timeout(settings.connect_timeout)
+        .build()?;
+    Ok(Http::new_with_client(http_endpoint.parse()?, client))
+}
+
+/// Builds an HTTP provider for `http_endpoint` that follows `settings`.
+pub fn http_provider(
+    http_endpoint: &str,
+    settings: &ProviderSettings,
+) -> anyhow::Result<Provider<Http>> {
+    let provider =
+        Provider::new(http_transport(http_endpoint, settings)?).interval(settings.poll_interval);
+    info!(
+        endpoint = http_endpoint,
+        poll_interval = ?settings.poll_interval,
+        request_timeout = ?settings.request_timeout,
+        connect_timeout = ?settings.connect_timeout,
+        "L1 provider"
+    );
+    Ok(provider)
+}
+
+/// `eth_rpc_url`, then `eth_rpc_fallback_urls`.
+pub fn l1_endpoints(config: &Config) -> Vec<String> {
+    std::iter::once(&config.eth_rpc_url)
+        .chain(&config.eth_rpc_fallback_urls)
+        .cloned()
+        .collect()
+}
+
+/// Builds a provider that follows `settings` and moves on to the next of
+/// `endpoints` when one cannot be reached, see `failover`.
+pub fn failover_provider(
+    endpoints: &[String],
+    settings: &ProviderSettings,
+    metrics: Metrics,
+) -> anyhow::Result<Provider<FailoverHttp>> {
+    let transport = FailoverHttp::new(endpoints, settings, metrics)?;
+    info!(
+        endpoints = ?transport.urls(),
+        poll_interval = ?settings.poll_interval,
+        request_timeout = ?settings.request_timeout,
+        connect_timeout = ?settings.connect_timeout,
+        failover_backoff = ?settings.failover_backoff,
+        "L1 provider"
+    );
+    Ok(Provider::new(transport).interval(settings.poll_interval))
+}
+
+/// The L1 private key in hex, read from where `key_source` says.
+pub fn l1_private_key(config: &Config) -> Result<String, String> {
+    let key = match config.key_source {
+        KeySource::Hex => return Ok(config.eth_private_key.clone()),
+        KeySource::Ledger | KeySource::Trezor => {
+            return Err("A hardware wallet does not reveal its private key".to_string())
+        }
+        KeySource::Keystore => {
+            let path = &config.eth_keystore_path;
+            keys::decrypt_keystore(path, &keys::keystore_password(path)?)?
+        }
+        KeySource::Mnemonic => {
+            keys::derive_from_mnemonic(&config.eth_mnemonic_path, &config.eth_derivation_path)?
+        }
+    };
+    Ok(hex::encode(key))
 }
 
 impl Node {
+    /// The node `config` connects to, over its `l1_endpoints`, signing
+    /// with the hardware wallet of `key_source` if any, else with
+    /// `eth_private_key`. Endpoint switches are recorded in `metrics`.
+    pub async fn from_config(config: &Config, metrics: Metrics) -> anyhow::Result<Self> {
+        let settings = ProviderSettings::from_config(config);
+        let provider = failover_provider(&l1_endpoints(config), &settings, metrics)?;
+        if !config.key_source.is_hardware() {
+            return Self::new_with_private_key(config.eth_private_key.clone(), provider).await;
+        }
+        let chain_id = provider.get_chainid().await?.as_u64();
+        let signer = L1Signer::hardware(config, chain_id).await?;
+        Self::new_with_signer(signer, provider).await
+    }
+
     pub async fn new_with_private_key(
         priv_key: String,
-        http_endpoint: String,
+        provider: Provider<FailoverHttp>,
     ) -> anyhow::Result<Self> {
-        let priv_key = hex::decode(priv_key)?;
-        let provider = Provider::<Http>::try_from(http_endpoint.clone())?
-            .interval(Duration::from_millis(10u64));
-        let chain_id: u64 = provider.get_chainid().await?.as_u64();
-
+        let invalid = || anyhow::anyhow!("eth_private_key is not a valid private key");
+        let priv_key = hex::decode(priv_key).map_err(|_| invalid())?;
         let wallet: LocalWallet = SecretKey::from_slice(&priv_key)
-            .expect("did not get private key")
+            .map_err(|_| invalid())?
             .into();
-        info!("Wallet with address: {:?}", wallet.address().clone());
-        let provider = provider.with_sender(wallet.address());
-        let wallet = wallet.with_chain_id(chain_id);
-        let http_client = SignerMiddleware::new(provider, wallet);
+        Self::new_with_signer(L1Signer::Local(wallet), provider).await
+    }
+
+    /// A node whose transactions `signer` signs, for the chain of
+    /// `provider`.
+    pub async fn new_with_signer(
+        signer: L1Signer,
+        provider: Provider<FailoverHttp>,
+    ) -> anyhow::Result<Self> {
+        let chain_id: u64 = provider.get_chainid().await?.as_u64();
+
+        info!(address = ?signer.address(), "L1 wallet");
+        let provider = provider.with_sender(signer.address());
+        let signer = signer.with_chain_id(chain_id);
+        let http_client = SignerMiddleware::new(provider, signer);
         let http_client = Arc::new(http_client);
 
-        Ok(Node {
-            http_client,
-            http_endpoint,
-        })
+        Ok(Node { http_client })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn provider_settings_follow_config() {
+        let config = Config {
+            l1_poll_interval_ms: 250,
+            l1_request_timeout: 7,
+            l1_connect_timeout: 3,
+            l1_failover_backoff: 2,
+            ..Default::default()
+        };
+        let settings = ProviderSettings::from_config(&config);
+        assert_eq!(
+            settings,
+            ProviderSettings {
+                poll_interval: Duration::from_millis(250),
+                request_timeout: Duration::from_secs(7),
+                connect_timeout: Duration::from_secs(3),
+                failover_backoff: Duration::from_secs(2),
+            }
+        );
+
+        let provider = http_provider("http://localhost:8545", &settings).unwrap();
+        assert_eq!(provider.get_interval(), Duration::from_millis(250));
+    }
+
+    #[test]
+    fn l1_private_key_sources() {
+        let anvil_key = "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+        let mut config = Config {
+            eth_private_key: anvil_key.to_string(),
+            ..Default::default()
+        };
+        assert_eq!(l1_private_key(&config), Ok(anvil_key.to_string()));
+
+        let path = std::env::temp_dir().join(format!("fusion-mnemonic-{}", rand::random::<u64>()));
+        std::fs::write(&path, crate::dev::DEV_MNEMONIC).unwrap();
+        config.key_source = KeySource::Mnemonic;
+        config.eth_private_key = String::new();
+        config.eth_mnemonic_path = path.to_str().unwrap().to_string();
+        assert_eq!(l1_private_key(&config), Ok(anvil_key.to_string()));
+
+        config.key_source = KeySource::Ledger;
+        assert!(l1_private_key(&config).is_err());
+    }
+
+    #[tokio::test]
+    async fn local_signer() {
+        let wallet: LocalWallet =
+            "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80"
+                .parse()
+                .unwrap();
+        let signer = L1Signer::Local(wallet.clone()).with_chain_id(5u64);
+        let wallet = wallet.with_chain_id(5u64);
+        assert_eq!(signer.address(), wallet.address());
+        assert_eq!(signer.chain_id(), 5);
+        assert_eq!(
+            signer.sign_message("block").await.unwrap(),
+            wallet.sign_message("block").await.unwrap()
+        );
+        let tx: TypedTransaction = TransactionRequest::new()
+            .to(Address::repeat_byte(1))
+            .nonce(3)
+            .chain_id(5)
+            .into();
+        assert_eq!(
+            signer.sign_transaction(&tx).await.unwrap(),
+            wallet.sign_transaction(&tx).await.unwrap()
+        );
+
+        let config = Config {
+            key_source: KeySource::Mnemonic,
+            ..Default::default()
+        };
+        assert!(L1Signer::hardware(&config, 5).await.is_err());
+    }
+
+    #[test]
+    fn provider_settings_defaults() {
+        let settings = ProviderSettings::from_config(&Config::default());
+        assert_eq!(settings.poll_interval, Duration::from_secs(1));
+        assert_eq!(settings.request_timeout, Duration::from_secs(30));
+        assert_eq!(settings.connect_timeout, Duration::from_secs(10));
+        assert_eq!(settings.failover_backoff, Duration::from_secs(5));
+    }
+
+    #[test]
+    fn invalid_endpoint() {
+        let settings = ProviderSettings::from_config(&Config::default());
+        assert!(http_provider("not a url", &settings).is_err());
+
+        let config = Config {
+            eth_rpc_fallback_urls: vec!["not a url".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            l1_endpoints(&config),
+            ["http://localhost:8545", "not a url"]
+        );
+        let endpoints = l1_endpoints(&config);
+        assert!(failover_provider(&endpoints, &settings, Metrics::default()).is_err());
+        assert!(failover_provider(&endpoints[..1], &settings, Metrics::default()).is_ok());
     }
 }
@@ -0,0 +1,140 @@
+//! Ordering of the transactions within a block.
+
+use std::collections::HashMap;
+
+use ethers::types::U256;
+use ethers::utils::keccak256;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+use fusion_api::{OrderingPolicy, SignedTx};
+
+/// Seed of the `Random` policy for block `number`.
+pub fn block_seed(parent_hash: U256, number: u64) -> [u8; 32] {
+    let mut preimage = [0u8; 40];
+    parent_hash.to_big_endian(&mut preimage[..32]);
+    preimage[32..].copy_from_slice(&number.to_be_bytes());
+    keccak256(preimage)
+}
+
+/// Orders `txs`, given in arrival order, according to `policy`.
+/// Revealed commit-reveal transactions come first, in the order given.
+pub fn order_txs(
+    policy: OrderingPolicy,
+    revealed: Vec<SignedTx>,
+    mut txs: Vec<SignedTx>,
+    seed: [u8; 32],
+) -> Vec<SignedTx> {
+    match policy {
+        OrderingPolicy::Fifo => {}
+        OrderingPolicy::Random => txs.shuffle(&mut ChaCha8Rng::from_seed(seed)),
+    }
+    sort_nonces_per_sender(revealed.into_iter().chain(txs).collect())
+}
+
+/// Keeps the positions each sender occupies, but fills them with the
+/// sender's transactions in increasing nonce order.
+fn sort_nonces_per_sender(txs: Vec<SignedTx>) -> Vec<SignedTx> {
+    let mut per_sender: HashMap<U256, Vec<SignedTx>> = HashMap::new();
+    let senders: Vec<U256> = txs.iter().map(|tx| tx.tx.sender).collect();
+    for tx in txs {
+        per_sender.entry(tx.tx.sender).or_default().push(tx);
+    }
+    for sender_txs in per_sender.values_mut() {
+        // Reversed so the lowest nonce is popped first.
+        sender_txs.sort_by(|a, b| b.tx.nonce.cmp(&a.tx.nonce));
+    }
+    senders
+        .iter()
+        .map(|sender| per_sender.get_mut(sender).unwrap().pop().unwrap())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fusion_api::{Tx, TxKind, NATIVE_TOKEN};
+
+    fn tx(sender: u64, nonce: u64) -> SignedTx {
+        SignedTx {
+            tx: Tx {
+                kind: TxKind::Transfer,
+                sender: sender.into(),
+                to: 0.into(),
+                nonce: nonce.into(),
+                value: 0.into(),
+                not_before: None,
+                not_after: None,
+                chain_id: None,
+                token: NATIVE_TOKEN,
+            },
+            signature: String::new(),
+        }
+    }
+
+    fn ids(txs: &[SignedTx]) -> Vec<(u64, u64)> {
+        txs.iter()
+            .map(|tx| (tx.tx.sender.as_u64(), tx.tx.nonce.as_u64()))
+            .collect()
+    }
+
+    fn arrivals() -> Vec<SignedTx> {
+        vec![tx(1, 1), tx(2, 1), tx(1, 3), tx(3, 1), tx(1, 2), tx(2, 2)]
+    }
+
+    fn nonces_increase_per_sender(txs: &[(u64, u64)]) -> bool {
+        let mut last = HashMap::new();
+        txs.iter()
+            .all(|(sender, nonce)| last.insert(sender, nonce).is_none_or(|prev| prev < nonce))
+    }
+
+    #[test]
+    fn fifo() {
+        let ordered = order_txs(OrderingPolicy::Fifo, vec![], arrivals(), [0; 32]);
+        // Sender 1's late nonce 2 takes the slot of its nonce 3.
+        assert_eq!(
+            ids(&ordered),
+            vec![(1, 1), (2, 1), (1, 2), (3, 1), (1, 3), (2, 2)]
+        );
+    }
+
+    #[test]
+    fn random() {
+        let seed = block_seed(U256::from(42), 7);
+        let ordered = ids(&order_txs(OrderingPolicy::Random, vec![], arrivals(), seed));
+        assert!(nonces_increase_per_sender(&ordered));
+
+        let mut sorted = ordered.clone();
+        sorted.sort();
+        let mut expected = ids(&arrivals());
+        expected.sort();
+        assert_eq!(sorted, expected);
+
+        // Anyone can reproduce the order from the seed.
+        assert_eq!(
+            ids(&order_txs(OrderingPolicy::Random, vec![], arrivals(), seed)),
+            ordered
+        );
+        // And it changes from block to block.
+        assert!((8..16).any(|number| {
+            let seed = block_seed(U256::from(42), number);
+            ids(&order_txs(OrderingPolicy::Random, vec![], arrivals(), seed)) != ordered
+        }));
+    }
+
+    #[test]
+    fn revealed_first() {
+        for policy in [OrderingPolicy::Fifo, OrderingPolicy::Random] {
+            let revealed = vec![tx(4, 1), tx(5, 1)];
+            let ordered = order_txs(policy, revealed, arrivals(), [1; 32]);
+            assert_eq!(ids(&ordered[..2]), vec![(4, 1), (5, 1)]);
+        }
+    }
+
+    #[test]
+    fn seeds_depend_on_parent_and_number() {
+        assert_ne!(block_seed(1.into(), 1), block_seed(2.into(), 1));
+        assert_ne!(block_seed(1.into(), 1), block_seed(1.into(), 2));
+    }
+}
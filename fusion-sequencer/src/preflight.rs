@@ -0,0 +1,350 @@
+//! Checks run before the sequencer starts, so that a misconfiguration is
+//! reported at once instead of failing the first block submission.
+//!
+//! The contract accepts blocks from anyone, so the sequencer key is only
+//! checked to be valid and funded.
+
+use ethers::core::utils::hex;
+use ethers::prelude::*;
+use k256::SecretKey;
+use std::{fmt, path::Path, sync::Arc};
+
+use fusion_config::Config;
+use fusion_l1::fusion;
+
+use crate::metrics::Metrics;
+use crate::node::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Check {
+    Listen,
+    Database,
+    L1Node,
+    ChainId,
+    Contract,
+    Key,
+    Balance,
+}
+
+#[derive(Clone, Debug)]
+pub struct Failure {
+    pub check: Check,
+    pub message: String,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}: {}", self.check, self.message)
+    }
+}
+
+fn failure(check: Check, message: String) -> Failure {
+    Failure { check, message }
+}
+
+/// Runs every check and returns all the failures. Only the listen
+/// address and the database are checked in dev mode.
+pub async fn preflight(config: &Config) -> Vec<Failure> {
+    let mut failures = vec![];
+    if let Err(e) = config.socket_addr() {
+        failures.push(failure(Check::Listen, e));
+    }
+    failures.extend(check_database(Path::new(&config.database_path)));
+    if config.dev {
+        return failures;
+    }
+    let settings = ProviderSettings::from_config(config);
+    match failover_provider(&l1_endpoints(config), &settings, Metrics::default()) {
+        Ok(provider) => failures.extend(check_l1(config, Arc::new(provider)).await),
+        Err(e) => failures.push(failure(Check::L1Node, e.to_string())),
+    }
+    failures
+}
+
+fn check_database(path: &Path) -> Vec<Failure> {
+    let probe = path.join(".preflight");
+    let writable = std::fs::create_dir_all(path)
+        .and_then(|()| std::fs::write(&probe, b""))
+        .and_then(|()| std::fs::remove_file(&probe));
+    match writable {
+        Ok(()) => vec![],
+        Err(e) => vec![failure(
+            Check::Database,
+            format!(
+                "database_path {} is not writable: {e}. Point it to a writable directory.",
+                path.display()
+            ),
+        )],
+    }
+}
+
+/// The address of the account signing block submissions, read from the
+/// device for a hardware wallet.
+async fn sequencer_address(config: &Config) -> Result<Address, String> {
+    if config.key_source.is_hardware() {
+        let signer = L1Signer::hardware(config, config.l1_chain_id)
+            .await
+            .map_err(|e| e.to_string())?;
+        return Ok(signer.address());
+    }
+    let invalid = || "eth_private_key is not a valid private key".to_string();
+    let bytes = hex::decode(&config.eth_private_key).map_err(|_| invalid())?;
+    let key = SecretKey::from_slice(&bytes).map_err(|_| invalid())?;
+    Ok(LocalWallet::from(key).address())
+}
+
+async fn check_l1<M: Middleware + 'static>(config: &Config, client: Arc<M>) -> Vec<Failure> {
+    let mut failures = vec![];
+    let sender = sequencer_address(config)
+        .await
+        .map_err(|message| failures.push(failure(Check::Key, message)))
+        .ok();
+
+    match client.get_chainid().await {
+        Err(e) => {
+            failures.push(failure(
+                Check::L1Node,
+                format!(
+                    "L1 node at {} does not respond: {e}. Check eth_rpc_url, eth_rpc_fallback_urls and that the node is running.",
+                    l1_endpoints(config).join(", ")
+                ),
+            ));
+            return failures;
+        }
+        Ok(chain_id) if config.l1_chain_id != 0 && chain_id != U256::from(config.l1_chain_id) => {
+            failures.push(failure(
+                Check::ChainId,
+                format!(
+                    "L1 node reports chain id {chain_id}, expected {}. Check eth_rpc_url and l1_chain_id.",
+                    config.l1_chain_id
+                ),
+            ))
+        }
+        Ok(_) => {}
+    }
+
+    let contract = config.fusion_l1_contract;
+    match client.get_code(contract, None).await {
+        Err(e) => failures.push(failure(
+            Check::L1Node,
+            format!("Cannot fetch the code of fusion_l1_contract {contract:?}: {e}"),
+        )),
+        Ok(code) if code.is_empty() => failures.push(failure(
+            Check::Contract,
+            format!(
+                "No contract deployed at fusion_l1_contract {contract:?}. Deploy it or fix the address."
+            ),
+        )),
+        Ok(_) => {
+            if let Err(e) = fusion::Fusion::new(contract, client.clone()).root().call().await {
+                failures.push(failure(
+                    Check::Contract,
+                    format!("Calling root() on {contract:?} failed: {e}. Is it a Fusion contract?"),
+                ));
+            }
+        }
+    }
+
+    if let Some(sender) = sender {
+        match client.get_balance(sender, None).await {
+            Err(e) => failures.push(failure(
+                Check::L1Node,
+                format!("Cannot fetch the balance of {sender:?}: {e}"),
+            )),
+            Ok(balance) if balance.is_zero() => failures.push(failure(
+                Check::Balance,
+                format!(
+                    "Sequencer account {sender:?} has no ether to pay for block submissions. Fund it or change eth_private_key."
+                ),
+            )),
+            Ok(_) => {}
+        }
+    }
+    failures
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// First anvil account.
+    const PRIVATE_KEY: &str = "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+
+    fn config() -> Config {
+        Config {
+            eth_private_key: PRIVATE_KEY.to_string(),
+            fusion_l1_contract: Address::repeat_byte(1),
+            l1_chain_id: 31337,
+            ..Default::default()
+        }
+    }
+
+    struct MockL1 {
+        chain_id: u64,
+        code: Bytes,
+        root_reverts: bool,
+        balance: U256,
+    }
+
+    impl Default for MockL1 {
+        fn default() -> Self {
+            Self {
+                chain_id: 31337,
+                code: Bytes::from(vec![0x60, 0x80]),
+                root_reverts: false,
+                balance: U256::exp10(18),
+            }
+        }
+    }
+
+    impl MockL1 {
+        /// Responses are popped from the back, so they are pushed in the
+        /// reverse order of the requests.
+        fn provider(&self) -> Arc<Provider<MockProvider>> {
+            let (provider, mock) = Provider::mocked();
+            mock.push::<U256, _>(self.balance).unwrap();
+            if !self.code.is_empty() {
+                match self.root_reverts {
+                    true => mock.push_response(MockResponse::Error(JsonRpcError {
+                        code: 3,
+                        message: "execution reverted".to_string(),
+                        data: None,
+                    })),
+                    false => mock.push::<Bytes, _>(Bytes::from(vec![0; 32])).unwrap(),
+                }
+            }
+            mock.push::<Bytes, _>(self.code.clone()).unwrap();
+            mock.push::<U256, _>(U256::from(self.chain_id)).unwrap();
+            Arc::new(provider)
+        }
+    }
+
+    fn checks(failures: &[Failure]) -> Vec<Check> {
+        failures.iter().map(|failure| failure.check).collect()
+    }
+
+    #[tokio::test]
+    async fn healthy() {
+        let failures = check_l1(&config(), MockL1::default().provider()).await;
+        assert!(failures.is_empty(), "{failures:?}");
+
+        // Any chain is accepted without an expected chain id.
+        let config = Config {
+            l1_chain_id: 0,
+            ..config()
+        };
+        let l1 = MockL1 {
+            chain_id: 1,
+            ..Default::default()
+        };
+        assert!(check_l1(&config, l1.provider()).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn unreachable_node() {
+        let (provider, _mock) = Provider::mocked();
+        let failures = check_l1(&config(), Arc::new(provider)).await;
+        assert_eq!(checks(&failures), vec![Check::L1Node]);
+        assert!(failures[0].message.contains("eth_rpc_url"));
+    }
+
+    #[tokio::test]
+    async fn wrong_chain_id() {
+        let l1 = MockL1 {
+            chain_id: 1,
+            ..Default::default()
+        };
+        let failures = check_l1(&config(), l1.provider()).await;
+        assert_eq!(checks(&failures), vec![Check::ChainId]);
+        assert!(failures[0].message.contains("chain id 1, expected 31337"));
+    }
+
+    #[tokio::test]
+    async fn missing_contract() {
+        let l1 = MockL1 {
+            code: Bytes::default(),
+            ..Default::default()
+        };
+        let failures = check_l1(&config(), l1.provider()).await;
+        assert_eq!(checks(&failures), vec![Check::Contract]);
+        assert!(failures[0].message.contains("No contract deployed"));
+
+        let l1 = MockL1 {
+            root_reverts: true,
+            ..Default::default()
+        };
+        let failures = check_l1(&config(), l1.provider()).await;
+        assert_eq!(checks(&failures), vec![Check::Contract]);
+        assert!(failures[0].message.contains("root()"));
+    }
+
+    #[tokio::test]
+    async fn sequencer_key() {
+        let l1 = MockL1 {
+            balance: U256::zero(),
+            ..Default::default()
+        };
+        let failures = check_l1(&config(), l1.provider()).await;
+        assert_eq!(checks(&failures), vec![Check::Balance]);
+
+        for private_key in ["", "not hex", "00"] {
+            let config = Config {
+                eth_private_key: private_key.to_string(),
+                ..config()
+            };
+            let failures = check_l1(&config, MockL1::default().provider()).await;
+            assert_eq!(checks(&failures), vec![Check::Key], "{private_key}");
+        }
+    }
+
+    #[tokio::test]
+    async fn database() {
+        let dir = std::env::temp_dir().join(format!("fusion-preflight-{}", std::process::id()));
+        assert!(check_database(&dir.join("db")).is_empty());
+
+        // A directory cannot be created under a file.
+        let file = dir.join("file");
+        std::fs::write(&file, b"").unwrap();
+        assert_eq!(
+            checks(&check_database(&file.join("db"))),
+            vec![Check::Database]
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn failures_are_reported_together() {
+        let dir = std::env::temp_dir().join(format!("fusion-preflight-all-{}", std::process::id()));
+        std::fs::write(&dir, b"").unwrap();
+        let config = Config {
+            eth_private_key: String::new(),
+            database_path: dir.join("db").display().to_string(),
+            dev: true,
+            ..config()
+        };
+        assert_eq!(checks(&preflight(&config).await), vec![Check::Database]);
+        let unresolved = Config {
+            socket_address: "sequencer.local".to_string(),
+            ..config.clone()
+        };
+        assert_eq!(
+            checks(&preflight(&unresolved).await),
+            vec![Check::Listen, Check::Database]
+        );
+
+        let l1 = MockL1 {
+            chain_id: 1,
+            code: Bytes::default(),
+            ..Default::default()
+        };
+        let mut failures = check_database(Path::new(&config.database_path));
+        failures.extend(check_l1(&config, l1.provider()).await);
+        assert_eq!(
+            checks(&failures),
+            vec![Check::Database, Check::Key, Check::ChainId, Check::Contract]
+        );
+
+        std::fs::remove_file(dir).unwrap();
+    }
+}
@@ -0,0 +1,253 @@
+//! Liveness and readiness of the sequencer, for orchestrators, served on
+//! `/health` and `/ready` next to `/metrics` and by `sequencer_status`.
+//!
+//! The L1 head and root are polled by `watch_l1` in a task of their own,
+//! so a probe only takes short locks on `Shared` and never waits for L1
+//! nor for the block being built.
+
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use ethers::{
+    providers::Middleware,
+    types::{Address, U256},
+};
+use tracing::debug;
+
+use fusion_api::{HealthStatus, SequencerStatus, StatusCheck};
+use fusion_l1::fusion;
+
+use crate::sequencer::{block_timestamp, Shared};
+
+/// Seconds between two reads of the L1 head and root.
+pub const PROBE_INTERVAL: u64 = 5;
+
+/// What `watch_l1` last read from L1.
+#[derive(Clone, Debug)]
+pub struct L1View {
+    pub block: u64,
+    pub root: U256,
+    /// When it was read, in seconds since the Unix epoch.
+    pub read_at: u64,
+}
+
+/// Reads the L1 head and the root of `contract` every `PROBE_INTERVAL`
+/// seconds into `shared.l1_view`, until the process exits.
+pub async fn watch_l1<M: Middleware + 'static>(shared: Shared, client: Arc<M>, contract: Address) {
+    let contract = fusion::Fusion::new(contract, client.clone());
+    let timeout = Duration::from_secs(PROBE_INTERVAL);
+    let mut interval = tokio::time::interval(timeout);
+    loop {
+        interval.tick().await;
+        let read = async {
+            let block = client
+                .get_block_number()
+                .await
+                .map_err(|e| format!("Could not read the latest L1 block: {e}"))?;
+            let root = contract
+                .root()
+                .call()
+                .await
+                .map_err(|e| format!("Could not read the L1 root: {e}"))?;
+            Ok(L1View {
+                block: block.as_u64(),
+                root,
+                read_at: block_timestamp(),
+            })
+        };
+        let view = tokio::time::timeout(timeout, read)
+            .await
+            .unwrap_or_else(|_| Err(format!("No answer from L1 within {PROBE_INTERVAL}s")));
+        if let Err(e) = &view {
+            debug!(error = %e, "L1 probe failed");
+        }
+        *shared.l1_view.lock().unwrap() = Some(view);
+    }
+}
+
+fn check(ok: bool, detail: impl Into<String>) -> StatusCheck {
+    StatusCheck {
+        ok,
+        detail: detail.into(),
+    }
+}
+
+/// The readiness checks at `now`. L1 may lag the sequencer by at most
+/// `max_block_lag` blocks, whose proofs are on their way.
+pub fn status(shared: &Shared, max_block_lag: u64, now: u64) -> SequencerStatus {
+    let dev = shared.info.lock().unwrap().dev;
+    let health = shared.health.lock().unwrap().clone();
+    let latest_batch = shared
+        .batches
+        .lock()
+        .unwrap()
+        .last()
+        .map(|batch| (batch.number, batch.state_root, batch.timestamp));
+    let mut checks = BTreeMap::new();
+
+    let (l1, sync) = match shared.l1_view.lock().unwrap().clone() {
+        _ if dev => (check(true, "dev mode"), check(true, "dev mode")),
+        None => {
+            let waiting = check(false, "L1 was not read yet");
+            (waiting.clone(), waiting)
+        }
+        Some(Err(e)) => (check(false, e), check(false, "L1 cannot be read")),
+        Some(Ok(view)) if now.saturating_sub(view.read_at) > 3 * PROBE_INTERVAL => {
+            let stale = format!("L1 was last read {}s ago", now - view.read_at);
+            (check(false, stale.clone()), check(false, stale))
+        }
+        Some(Ok(view)) => (
+            check(true, format!("L1 block {}", view.block)),
+            sync_check(
+                shared,
+                &view,
+                latest_batch.map(|(n, root, _)| (n, root)),
+                max_block_lag,
+            ),
+        ),
+    };
+    checks.insert("l1".to_string(), l1);
+    checks.insert("sync".to_string(), sync);
+
+    let submission = match (
+        &health.status,
+        shared.submission_error.lock().unwrap().clone(),
+    ) {
+        (HealthStatus::Halted, _) => check(
+            false,
+            format!("halted: {}", health.reason.unwrap_or_default()),
+        ),
+        (_, Some(e)) => check(false, e),
+        (_, None) => check(
+            true,
+            format!("{} proof(s) waiting", health.submission_queue),
+        ),
+    };
+    checks.insert("submission".to_string(), submission);
+
+    SequencerStatus {
+        ready: checks.values().all(|check| check.ok),
+        checks,
+        block_number: health.block_number,
+        last_batch: latest_batch.map(|(number, _, _)| number),
+        last_batch_time: latest_batch.map(|(_, _, timestamp)| timestamp),
+        stale_root_conflicts: *shared.stale_root_conflicts.lock().unwrap(),
+    }
+}
+
+/// Whether the L1 root is the one of a block at most `max_block_lag`
+/// blocks before the latest one.
+fn sync_check(
+    shared: &Shared,
+    view: &L1View,
+    latest_batch: Option<(u64, U256)>,
+    max_block_lag: u64,
+) -> StatusCheck {
+    let blocks = shared.blocks.lock().unwrap();
+    let Some(latest) = blocks.last().map(|block| block.header.number) else {
+        return match latest_batch {
+            Some((number, root)) if root != view.root => {
+                check(false, format!("L1 is not at the root of batch {number}"))
+            }
+            _ => check(true, "no block built since the start"),
+        };
+    };
+    let roots = blocks
+        .iter()
+        .map(|block| (block.header.number, block.header.state_root))
+        .chain(blocks.first().and_then(|first| {
+            first
+                .header
+                .l1_root
+                .map(|root| (first.header.number - 1, root))
+        }))
+        .chain(latest_batch);
+    match roots
+        .filter(|(_, root)| *root == view.root)
+        .map(|(number, _)| latest.saturating_sub(number))
+        .min()
+    {
+        Some(lag) if lag <= max_block_lag => check(true, format!("L1 is {lag} block(s) behind")),
+        Some(lag) => check(
+            false,
+            format!("L1 is {lag} block(s) behind, more than {max_block_lag}"),
+        ),
+        None => check(false, "L1 is at a root this sequencer did not build"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fusion_api::{Block, BlockHeader};
+
+    fn block(number: u64) -> Block {
+        Block {
+            header: BlockHeader {
+                number,
+                parent_hash: U256::zero(),
+                state_root: U256::from(number),
+                tx_root: U256::zero(),
+                timestamp: number,
+                l1_root: Some(U256::from(number - 1)),
+                l1_tx_hashes: vec![],
+            },
+            txs: vec![],
+            tx_hashes: vec![],
+        }
+    }
+
+    #[test]
+    fn readiness() {
+        let shared = Shared::default();
+        let now = 1000;
+        let ready = |shared: &Shared| {
+            let status = status(shared, 2, now);
+            let failed: Vec<_> = status
+                .checks
+                .iter()
+                .filter(|(_, check)| !check.ok)
+                .map(|(name, _)| name.clone())
+                .collect();
+            assert_eq!(status.ready, failed.is_empty());
+            failed
+        };
+        assert_eq!(ready(&shared), ["l1", "sync"]);
+
+        shared.blocks.lock().unwrap().extend((1..=5).map(block));
+        let view = |root: u64, read_at: u64| {
+            Some(Ok(L1View {
+                block: 100,
+                root: root.into(),
+                read_at,
+            }))
+        };
+        *shared.l1_view.lock().unwrap() = view(4, now);
+        assert!(ready(&shared).is_empty());
+        // Three blocks behind.
+        *shared.l1_view.lock().unwrap() = view(2, now);
+        assert_eq!(ready(&shared), ["sync"]);
+        // Before the first block.
+        *shared.l1_view.lock().unwrap() = view(0, now);
+        assert_eq!(ready(&shared), ["sync"]);
+        *shared.l1_view.lock().unwrap() = view(42, now);
+        assert_eq!(ready(&shared), ["sync"]);
+        *shared.l1_view.lock().unwrap() = view(5, now - 60);
+        assert_eq!(ready(&shared), ["l1", "sync"]);
+        *shared.l1_view.lock().unwrap() = Some(Err("connection refused".to_string()));
+        assert_eq!(ready(&shared), ["l1", "sync"]);
+
+        *shared.l1_view.lock().unwrap() = view(5, now);
+        *shared.submission_error.lock().unwrap() = Some("reverted".to_string());
+        assert_eq!(ready(&shared), ["submission"]);
+
+        shared.info.lock().unwrap().dev = true;
+        *shared.submission_error.lock().unwrap() = None;
+        *shared.l1_view.lock().unwrap() = None;
+        *shared.stale_root_conflicts.lock().unwrap() = 2;
+        let status = status(&shared, 2, now);
+        assert!(status.ready);
+        assert_eq!(status.block_number, 0);
+        assert_eq!(status.last_batch, None);
+        assert_eq!(status.stale_root_conflicts, 2);
+    }
+}
@@ -0,0 +1,196 @@
+//! Where the proofs of a block come from, selected by `prover`: the
+//! circuit run in this process, a proving service over HTTP, or a mock
+//! that proves nothing.
+//!
+//! The proving service receives the circuit input of a transaction as
+//! JSON, in the format `fusion-sequencer prove` reads, POSTed to
+//! `prover_url`. It answers with `{"proof": "0x..."}`, the ABI encoding
+//! of the `TxProof` the contract receives.
+
+use std::{sync::Arc, time::Duration};
+
+use ethers::{abi::AbiDecode, types::Bytes};
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+
+use fusion_config::{Config, ProverBackend};
+use fusion_l1::fusion;
+use fusion_prover::{CircuitInput, Prover, ProvingTimings};
+
+pub type ProofResult = Result<(fusion::TxProof, ProvingTimings), String>;
+
+pub trait ProofService: Send + Sync {
+    /// Proves `inputs` in the encoding the contract receives.
+    fn prove(&self, inputs: CircuitInput) -> BoxFuture<'static, ProofResult>;
+}
+
+/// The service `config` selects.
+pub fn proof_service(config: &Config) -> Result<Arc<dyn ProofService>, String> {
+    Ok(match config.prover {
+        ProverBackend::Local => Arc::new(LocalProver(config.clone())),
+        ProverBackend::Remote => Arc::new(RemoteProver::new(
+            &config.prover_url,
+            Duration::from_secs(config.prover_timeout),
+        )?),
+        ProverBackend::Mock => Arc::new(MockProver),
+    })
+}
+
+/// Runs the circuit on a blocking thread.
+pub struct LocalProver(pub Config);
+
+impl ProofService for LocalProver {
+    fn prove(&self, inputs: CircuitInput) -> BoxFuture<'static, ProofResult> {
+        let config = self.0.clone();
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || Prover::prove_for_l1(&config, &inputs))
+                .await
+                .map_err(|e| format!("The proving thread failed: {e}"))?
+        })
+    }
+}
+
+/// A proof of zeros with the right public inputs, which no verifier
+/// accepts. For running the pipeline without a circuit.
+pub struct MockProver;
+
+impl ProofService for MockProver {
+    fn prove(&self, inputs: CircuitInput) -> BoxFuture<'static, ProofResult> {
+        let proof = fusion::TxProof {
+            proof: Default::default(),
+            input: inputs.public_inputs().to_vec(),
+        };
+        Box::pin(async move { Ok((proof, ProvingTimings::default())) })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct RemoteProof {
+    proof: Bytes,
+}
+
+/// A proving service at `url`, given `timeout` per proof.
+pub struct RemoteProver {
+    client: reqwest::Client,
+    url: Arc<str>,
+}
+
+impl RemoteProver {
+    pub fn new(url: &str, timeout: Duration) -> Result<Self, String> {
+        if url.is_empty() {
+            return Err("prover is remote but prover_url is empty".to_string());
+        }
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .map_err(|e| e.to_string())?;
+        Ok(Self {
+            client,
+            url: url.into(),
+        })
+    }
+}
+
+impl ProofService for RemoteProver {
+    fn prove(&self, inputs: CircuitInput) -> BoxFuture<'static, ProofResult> {
+        let (client, url) = (self.client.clone(), self.url.clone());
+        Box::pin(async move {
+            let started = std::time::Instant::now();
+            let body = serde_json::to_vec(&inputs).map_err(|e| e.to_string())?;
+            let response = client
+                .post(&*url)
+                .header("Content-Type", "application/json")
+                .body(body)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .map_err(|e| format!("The proving service failed: {e}"))?;
+            let body = response
+                .bytes()
+                .await
+                .map_err(|e| format!("The proving service failed: {e}"))?;
+            let proof = decode_proof(&body, &inputs)?;
+            let timings = ProvingTimings {
+                proof: started.elapsed(),
+                ..Default::default()
+            };
+            Ok((proof, timings))
+        })
+    }
+}
+
+/// The proof in the answer `body` of the proving service, if it proves
+/// `inputs`.
+fn decode_proof(body: &[u8], inputs: &CircuitInput) -> Result<fusion::TxProof, String> {
+    let RemoteProof { proof } = serde_json::from_slice(body)
+        .map_err(|e| format!("Invalid answer from the proving service: {e}"))?;
+    let proof = fusion::TxProof::decode(&proof)
+        .map_err(|e| format!("Invalid proof from the proving service: {e}"))?;
+    if proof.input != inputs.public_inputs() {
+        return Err("The proving service proved other public inputs".to_string());
+    }
+    Ok(proof)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ethers::{abi::AbiEncode, types::U256};
+    use fusion_config::Config;
+    use fusion_types::ToU256;
+
+    use crate::dev;
+    use crate::sequencer::genesis_state;
+    use crate::transition::apply_tx;
+
+    fn inputs() -> CircuitInput {
+        let sk = dev::dev_private_key(0);
+        let tx = fusion_api::Tx {
+            kind: fusion_api::TxKind::Transfer,
+            sender: fusion_wallet::new_public_key(&sk).to_u256(),
+            to: fusion_wallet::new_public_key(&dev::dev_private_key(1)).to_u256(),
+            nonce: 1.into(),
+            value: 10.into(),
+            not_before: None,
+            not_after: None,
+            chain_id: None,
+            token: fusion_api::NATIVE_TOKEN,
+        };
+        let signature = fusion_wallet::sign(&tx, sk.to_string())
+            .unwrap()
+            .to_string();
+        let mut config = Config::default();
+        config.dev = true;
+        let state = genesis_state(&config);
+        let post_state = apply_tx(state.clone(), &tx).unwrap();
+        CircuitInput::new(&fusion_api::SignedTx { tx, signature }, &state, &post_state).unwrap()
+    }
+
+    /// The answer of a proving service with `proof`.
+    fn encode_answer(proof: fusion::TxProof) -> String {
+        serde_json::to_string(&RemoteProof {
+            proof: proof.encode().into(),
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn mock_and_remote_answers() {
+        let inputs = inputs();
+        let public_inputs = inputs.public_inputs();
+        let (proof, _) = MockProver.prove(inputs).await.unwrap();
+        assert_eq!(proof.input, public_inputs);
+        let inputs = self::inputs();
+
+        let answer = encode_answer(proof.clone());
+        assert_eq!(decode_proof(answer.as_bytes(), &inputs).unwrap(), proof);
+
+        let mut other = proof;
+        other.input[1] += U256::one();
+        let e = decode_proof(encode_answer(other).as_bytes(), &inputs).unwrap_err();
+        assert!(e.contains("other public inputs"), "{e}");
+        let e = decode_proof(b"{\"proof\": \"0x12\"}", &inputs).unwrap_err();
+        assert!(e.contains("Invalid proof"), "{e}");
+        assert!(RemoteProver::new("", Duration::from_secs(1)).is_err());
+    }
+}
@@ -0,0 +1,116 @@
+//! Per-IP token buckets limiting how fast a client submits transactions.
+//!
+//! A bucket holds up to `rpc_burst` tokens and refills at
+//! `rpc_rate_limit` tokens per second. Each submission takes one, and is
+//! refused while the bucket is empty.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use fusion_api::SubmitError;
+
+/// Buckets idle for that many seconds are full again and forgotten.
+const IDLE_SECS: f64 = 600.0;
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Shared by the connections of the RPC server.
+#[derive(Clone)]
+pub struct RateLimiter {
+    /// Tokens per second, unlimited when 0.
+    rate: f64,
+    burst: f64,
+    buckets: Arc<Mutex<HashMap<IpAddr, Bucket>>>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: u32, burst: u32) -> Self {
+        Self {
+            rate: rate.into(),
+            burst: burst.max(1).into(),
+            buckets: Arc::default(),
+        }
+    }
+
+    /// Takes a token from the bucket of `ip` at `now`, or tells when the
+    /// next one is available.
+    pub fn check(&self, ip: IpAddr, now: Instant) -> Result<(), SubmitError> {
+        if self.rate == 0.0 {
+            return Ok(());
+        }
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > 1000 {
+            buckets.retain(|_, bucket| {
+                now.saturating_duration_since(bucket.updated).as_secs_f64() < IDLE_SECS
+            });
+        }
+        let bucket = buckets.entry(ip).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            let wait = (1.0 - bucket.tokens) / self.rate;
+            return Err(SubmitError::RateLimited {
+                rate: self.rate as u32,
+                retry_in_ms: (wait * 1000.0).round() as u64,
+            });
+        }
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn token_bucket() {
+        let limiter = RateLimiter::new(10, 5);
+        let (client, other) = ([10, 0, 0, 1].into(), [10, 0, 0, 2].into());
+        let start = Instant::now();
+        let refused = (0..100)
+            .filter(|_| limiter.check(client, start).is_err())
+            .count();
+        // The burst goes through, the rest is refused.
+        assert_eq!(refused, 95);
+        let error = limiter.check(client, start).unwrap_err();
+        assert_eq!(
+            error,
+            SubmitError::RateLimited {
+                rate: 10,
+                retry_in_ms: 100
+            }
+        );
+        assert!(error.to_string().starts_with("Rate limited"), "{error}");
+        assert!(limiter.check(other, start).is_ok());
+
+        // A token every 100ms.
+        let later = start + Duration::from_millis(250);
+        assert!(limiter.check(client, later).is_ok());
+        assert!(limiter.check(client, later).is_ok());
+        assert!(limiter.check(client, later).is_err());
+        // Never more than the burst.
+        let idle = later + Duration::from_secs(60);
+        assert_eq!(
+            (0..10)
+                .filter(|_| limiter.check(client, idle).is_ok())
+                .count(),
+            5
+        );
+
+        let unlimited = RateLimiter::new(0, 0);
+        assert!((0..1000).all(|_| unlimited.check(client, start).is_ok()));
+    }
+}
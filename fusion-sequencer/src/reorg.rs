@@ -0,0 +1,190 @@
+//! The blocks confirmed on L1 recently, with the L1 block that confirmed
+//! them, so that an L1 reorg dropping that block is noticed before the
+//! next block is built on a root L1 no longer holds.
+//!
+//! A confirmation is checked until it is `reorg_depth` L1 blocks deep.
+//! Each entry keeps the transactions of its block and the root it built
+//! on, so the block can be requeued even once it is pruned from the
+//! block history.
+
+use std::collections::BTreeMap;
+
+use ethers::prelude::*;
+
+use fusion_api::SignedTx;
+
+/// An L1 block, as identified by a receipt.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct L1Block {
+    pub number: u64,
+    pub hash: H256,
+}
+
+impl L1Block {
+    /// The block `receipt` was mined in, unless it is still pending.
+    pub fn of(receipt: &TransactionReceipt) -> Option<Self> {
+        Some(Self {
+            number: receipt.block_number?.as_u64(),
+            hash: receipt.block_hash?,
+        })
+    }
+}
+
+/// A block whose proofs were all accepted by L1.
+#[derive(Clone, Debug)]
+pub struct ConfirmedBlock {
+    pub block: u64,
+    /// The root the block built on.
+    pub prior_root: U256,
+    pub txs: Vec<SignedTx>,
+    /// The L1 transaction of its last proof and where it was mined.
+    pub l1_tx_hash: H256,
+    pub l1_block: L1Block,
+}
+
+pub struct BatchHistory {
+    blocks: BTreeMap<u64, ConfirmedBlock>,
+    /// L1 blocks after which a confirmation is final, never tracked
+    /// when 0.
+    depth: u64,
+}
+
+impl BatchHistory {
+    pub fn new(depth: u64) -> Self {
+        Self {
+            blocks: BTreeMap::new(),
+            depth,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    pub fn record(&mut self, confirmed: ConfirmedBlock) {
+        if self.depth > 0 {
+            self.blocks.insert(confirmed.block, confirmed);
+        }
+    }
+
+    /// Removes the blocks from `block` on, oldest first.
+    pub fn remove_from(&mut self, block: u64) -> Vec<ConfirmedBlock> {
+        let removed = self.blocks.split_off(&block);
+        removed.into_values().collect()
+    }
+
+    /// Checks the confirmations against the canonical chain and returns
+    /// the oldest block whose L1 block was reorged out, if any. A
+    /// proof mined again in another L1 block is still confirmed and
+    /// only has its L1 block updated. The confirmations `depth` blocks
+    /// deep are dropped.
+    pub async fn first_reorged<M: Middleware>(
+        &mut self,
+        client: &M,
+    ) -> Result<Option<u64>, String> {
+        if self.blocks.is_empty() {
+            return Ok(None);
+        }
+        let latest = client
+            .get_block_number()
+            .await
+            .map_err(|e| format!("Could not read the latest L1 block: {e}"))?
+            .as_u64();
+        let depth = self.depth;
+        self.blocks
+            .retain(|_, confirmed| confirmed.l1_block.number + depth > latest);
+
+        for confirmed in self.blocks.values_mut() {
+            let l1_block = confirmed.l1_block;
+            let canonical = client
+                .get_block(l1_block.number)
+                .await
+                .map_err(|e| format!("Could not read L1 block {}: {e}", l1_block.number))?
+                .and_then(|canonical| canonical.hash);
+            if canonical == Some(l1_block.hash) {
+                continue;
+            }
+            let receipt = client
+                .get_transaction_receipt(confirmed.l1_tx_hash)
+                .await
+                .map_err(|e| {
+                    format!(
+                        "Could not read the receipt of {:?}: {e}",
+                        confirmed.l1_tx_hash
+                    )
+                })?;
+            match receipt
+                .filter(|receipt| receipt.status == Some(1u64.into()))
+                .and_then(|receipt| L1Block::of(&receipt))
+            {
+                Some(mined_again) => confirmed.l1_block = mined_again,
+                None => return Ok(Some(confirmed.block)),
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn confirmed(block: u64, l1_block: u64) -> ConfirmedBlock {
+        ConfirmedBlock {
+            block,
+            prior_root: U256::from(block - 1),
+            txs: vec![],
+            l1_tx_hash: H256::from_low_u64_be(block),
+            l1_block: L1Block {
+                number: l1_block,
+                hash: H256::from_low_u64_be(l1_block),
+            },
+        }
+    }
+
+    fn l1_block(number: u64, hash: u64) -> Block<H256> {
+        Block {
+            number: Some(number.into()),
+            hash: Some(H256::from_low_u64_be(hash)),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn reorged() {
+        let mut history = BatchHistory::new(10);
+        history.record(confirmed(1, 3));
+        history.record(confirmed(2, 5));
+        history.record(confirmed(3, 7));
+
+        // Responses are popped from the back, in the reverse order of
+        // the requests. Block 1 is deep enough to be final.
+        let (provider, mock) = Provider::mocked();
+        mock.push::<Option<TransactionReceipt>, _>(None).unwrap();
+        mock.push::<Block<H256>, _>(l1_block(7, 70)).unwrap();
+        mock.push::<Block<H256>, _>(l1_block(5, 5)).unwrap();
+        mock.push::<U64, _>(U64::from(13)).unwrap();
+        assert_eq!(history.first_reorged(&provider).await, Ok(Some(3)));
+        assert_eq!(history.blocks.keys().copied().collect::<Vec<_>>(), [2, 3]);
+
+        // Mined again after the reorg.
+        let receipt = TransactionReceipt {
+            status: Some(1u64.into()),
+            block_number: Some(8u64.into()),
+            block_hash: Some(H256::from_low_u64_be(80)),
+            ..Default::default()
+        };
+        let (provider, mock) = Provider::mocked();
+        mock.push::<TransactionReceipt, _>(receipt).unwrap();
+        mock.push::<Block<H256>, _>(l1_block(7, 70)).unwrap();
+        mock.push::<Block<H256>, _>(l1_block(5, 5)).unwrap();
+        mock.push::<U64, _>(U64::from(13)).unwrap();
+        assert_eq!(history.first_reorged(&provider).await, Ok(None));
+        assert_eq!(history.blocks[&3].l1_block.number, 8);
+
+        let removed = history.remove_from(3);
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].block, 3);
+        assert_eq!(history.blocks.keys().copied().collect::<Vec<_>>(), [2]);
+    }
+}
@@ -0,0 +1,295 @@
+//! End-to-end scenarios run against a dev sequencer.
+//!
+//! A scenario in `scenarios/<name>.json` describes the dev accounts and a
+//! sequence of steps, and its outcome is committed next to it in
+//! `scenarios/<name>.expected.json`. The outcome only depends on the
+//! steps, so any change in which transactions are included, in their
+//! order or in the resulting roots shows up as a diff.
+//!
+//! Run the scenarios with `cargo test scenarios`, and rewrite the
+//! expected outcomes with `FUSION_BLESS=1 cargo test scenarios` when a
+//! change is intended.
+
+use ethers::types::U256;
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::oneshot;
+
+use fusion_api::{hash_tx, SignedTx, Tx, TxKind, NATIVE_TOKEN};
+use fusion_config::Config;
+use fusion_types::ToU256;
+
+use crate::dev;
+use crate::sequencer::{Sequencer, Trigger};
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    pub description: String,
+    /// Funded dev accounts. Steps may use higher indices for accounts
+    /// that start empty.
+    pub accounts: u32,
+    pub balance: u64,
+    /// Queued transactions that build a block, flushes aside.
+    #[serde(default = "never")]
+    pub min_tx_block: usize,
+    pub steps: Vec<Step>,
+}
+
+fn never() -> usize {
+    usize::MAX
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum Step {
+    /// Submits a transaction signed by the dev account `from`.
+    Submit(ScenarioTx),
+    /// Builds a block with whatever is queued.
+    Flush,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScenarioTx {
+    #[serde(default = "transfer")]
+    pub kind: TxKind,
+    pub from: u32,
+    pub to: u32,
+    pub nonce: u64,
+    pub value: u64,
+    /// Seconds relative to when the step runs.
+    #[serde(default)]
+    pub not_before: Option<i64>,
+    #[serde(default)]
+    pub not_after: Option<i64>,
+}
+
+fn transfer() -> TxKind {
+    TxKind::Transfer
+}
+
+/// What a scenario led to. Transactions are named by the index of the
+/// step that submitted them.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Outcome {
+    pub blocks: Vec<BlockOutcome>,
+    /// Still in the mempool at the end.
+    pub pending: Vec<usize>,
+    /// Neither included nor pending.
+    pub dropped: Vec<usize>,
+    /// Every account the steps use, by index.
+    pub accounts: Vec<AccountOutcome>,
+    pub state_root: U256,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockOutcome {
+    pub number: u64,
+    pub txs: Vec<usize>,
+    pub state_root: U256,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountOutcome {
+    pub index: u32,
+    pub balance: U256,
+    pub nonce: U256,
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+fn signed_tx(tx: &ScenarioTx) -> SignedTx {
+    let key = dev::dev_private_key(tx.from);
+    let public_key = |index| fusion_wallet::new_public_key(&dev::dev_private_key(index)).to_u256();
+    let at = |offset: Option<i64>| offset.map(|offset| (now() + offset).max(0) as u64);
+    let tx = Tx {
+        kind: tx.kind.clone(),
+        sender: public_key(tx.from),
+        to: public_key(tx.to),
+        nonce: tx.nonce.into(),
+        value: tx.value.into(),
+        not_before: at(tx.not_before),
+        not_after: at(tx.not_after),
+        chain_id: None,
+        token: NATIVE_TOKEN,
+    };
+    let signature = fusion_wallet::sign(&tx, key.to_string())
+        .unwrap()
+        .to_string();
+    SignedTx { tx, signature }
+}
+
+pub async fn run(scenario: &Scenario) -> Outcome {
+    let config = Config {
+        dev: true,
+        dev_accounts: scenario.accounts,
+        dev_account_balance: scenario.balance,
+        min_tx_block: scenario.min_tx_block,
+        ..Default::default()
+    };
+    let mut sequencer = Sequencer::new(&config).await.unwrap();
+
+    let mut submitted = vec![];
+    let mut used = vec![];
+    for (index, step) in scenario.steps.iter().enumerate() {
+        match step {
+            Step::Submit(tx) => {
+                let signed_tx = signed_tx(tx);
+                submitted.push((index, hash_tx(&signed_tx.tx)));
+                used.extend([tx.from, tx.to]);
+                sequencer.handle(Trigger::Tx(signed_tx)).await;
+            }
+            Step::Flush => {
+                let (reply_sx, reply_rx) = oneshot::channel();
+                sequencer.handle(Trigger::Flush(reply_sx)).await;
+                reply_rx.await.unwrap().unwrap();
+            }
+        }
+    }
+
+    let step_of = |hash: &U256| {
+        submitted
+            .iter()
+            .find(|(_, submitted)| submitted == hash)
+            .map(|(index, _)| *index)
+            .unwrap()
+    };
+    let shared = sequencer.shared();
+    let blocks: Vec<_> = shared
+        .blocks
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|block| BlockOutcome {
+            number: block.header.number,
+            txs: block.tx_hashes.iter().map(step_of).collect(),
+            state_root: block.header.state_root,
+        })
+        .collect();
+    let pending: Vec<_> = sequencer.pending_tx_hashes().iter().map(step_of).collect();
+    let dropped = submitted
+        .iter()
+        .map(|(index, _)| *index)
+        .filter(|index| {
+            !pending.contains(index) && !blocks.iter().any(|block| block.txs.contains(index))
+        })
+        .collect();
+
+    used.sort_unstable();
+    used.dedup();
+    let accounts = used
+        .into_iter()
+        .map(|index| {
+            let address = fusion_wallet::new_public_key(&dev::dev_private_key(index)).address();
+            let account = sequencer.state().get(&address);
+            AccountOutcome {
+                index,
+                balance: account.balance,
+                nonce: account.nonce,
+            }
+        })
+        .collect();
+
+    Outcome {
+        blocks,
+        pending,
+        dropped,
+        accounts,
+        state_root: sequencer.state().root(),
+    }
+}
+
+/// The lines of `expected` and `actual` that differ, prefixed with `-`
+/// and `+`, with the common lines around them.
+pub fn diff(expected: &str, actual: &str) -> String {
+    let (old, new): (Vec<_>, Vec<_>) = (expected.lines().collect(), actual.lines().collect());
+    // Longest common subsequence, from the end.
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = match old[i] == new[j] {
+                true => lcs[i + 1][j + 1] + 1,
+                false => lcs[i + 1][j].max(lcs[i][j + 1]),
+            };
+        }
+    }
+    let mut lines = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push(format!("  {}", old[i]));
+            (i, j) = (i + 1, j + 1);
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            lines.push(format!("- {}", old[i]));
+            i += 1;
+        } else {
+            lines.push(format!("+ {}", new[j]));
+            j += 1;
+        }
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::{Path, PathBuf};
+
+    fn scenario_paths() -> Vec<PathBuf> {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios");
+        let mut paths: Vec<_> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| {
+                path.extension().is_some_and(|ext| ext == "json")
+                    && !path.to_string_lossy().ends_with(".expected.json")
+            })
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    #[tokio::test]
+    async fn scenarios() {
+        let bless = std::env::var("FUSION_BLESS").is_ok_and(|bless| bless == "1");
+        let mut failures = vec![];
+        for path in scenario_paths() {
+            let scenario: Scenario = serde_json::from_str(&std::fs::read_to_string(&path).unwrap())
+                .unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+            let actual = serde_json::to_string_pretty(&run(&scenario).await).unwrap() + "\n";
+            let expected_path = path.with_extension("expected.json");
+            if bless {
+                std::fs::write(&expected_path, &actual).unwrap();
+                continue;
+            }
+            let expected = std::fs::read_to_string(&expected_path).unwrap_or_default();
+            if expected != actual {
+                failures.push(format!(
+                    "{} ({}):\n{}",
+                    path.display(),
+                    scenario.description,
+                    diff(&expected, &actual)
+                ));
+            }
+        }
+        assert!(
+            failures.is_empty(),
+            "{}\n\nRun with FUSION_BLESS=1 if the changes are intended",
+            failures.join("\n\n")
+        );
+    }
+
+    #[test]
+    fn line_diff() {
+        assert_eq!(diff("a\nb\nc", "a\nb\nc"), "  a\n  b\n  c");
+        assert_eq!(diff("a\nb\nc", "a\nx\nc"), "  a\n- b\n+ x\n  c");
+        assert_eq!(diff("a", ""), "- a");
+        assert_eq!(diff("", "a\nb"), "+ a\n+ b");
+    }
+}
@@ -1,236 +1,5178 @@
 use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
     path::Path,
     sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use ethers::{
-    providers::{Http, Provider},
-    signers::LocalWallet,
+    providers::{Http, Middleware, Provider},
+    types::{Address, BlockNumber, H256, U256},
 };
 
-use tokio::sync::mpsc;
+use tokio::{
+    sync::{broadcast, mpsc, oneshot},
+    task::JoinHandle,
+};
+use tracing::{debug, error, info, warn};
 
 use fusion_api::*;
 use fusion_config::Config;
 use fusion_l1::fusion;
 use fusion_prover::state::{Account, State};
 use fusion_prover::*;
-use fusion_types::PublicKey;
+use fusion_types::{PublicKey, ToU256};
 
+use crate::audit::{self, AuditLog};
+use crate::batches::BatchLog;
+use crate::calldata;
+use crate::chaos::Chaos;
+use crate::compatibility::{check_circuit_params, check_compressed_calldata, contract_version};
+use crate::dev;
+use crate::failover::FailoverHttp;
+use crate::maintenance;
+use crate::mempool::{open_store, Keyed, MempoolStore};
+use crate::metrics::{Metrics, Rejection};
 use crate::node::*;
+use crate::ordering;
+use crate::probes::L1View;
+use crate::proving::{self, ProofResult, ProofService};
+use crate::reorg::{BatchHistory, ConfirmedBlock, L1Block};
+use crate::submission::{submit_proof, SubmissionQueue, SubmitSettings, Submitted};
+use crate::sync::L1Sync;
+use crate::transition::{
+    apply_tx, check_tx_account, check_tx_state, transition, validate_tx, verify_tx_signature,
+};
+use crate::tuning::BatchTuner;
+use crate::witness;
+
+/// A transaction waiting in the mempool.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+struct PendingTx {
+    tx: SignedTx,
+    /// Unix timestamp at which the sequencer received it.
+    received_at: u64,
+    /// Set for transactions submitted with commit-reveal.
+    commitment: Option<Commitment>,
+}
+
+impl Keyed for PendingTx {
+    fn hash(&self) -> U256 {
+        self.tx.hash()
+    }
+
+    fn slot(&self) -> (U256, U256) {
+        self.tx.slot()
+    }
+}
+
+/// A commitment to a transaction that is revealed later.
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+struct Commitment {
+    /// Position among all commitments. Revealed transactions are
+    /// included in this order.
+    sequence: u64,
+    /// The block the commitment appears in.
+    block: u64,
+}
+
+type MemPool = Arc<Mutex<Box<dyn MempoolStore<PendingTx>>>>;
+
+/// Times a transaction is proven before its block is given up on.
+const PROVE_ATTEMPTS: u32 = 3;
+
+/// Health reason while an operator paused batching.
+const PAUSED_REASON: &str = "Batching is paused by an operator";
+
+async fn request_proof(
+    prover: Arc<dyn ProofService>,
+    inputs: CircuitInput,
+    chaos: Chaos,
+) -> ProofResult {
+    let mut attempt = 1;
+    loop {
+        let proof = match chaos.inject(Fault::Prover) {
+            Some(e) => Err(e),
+            None => prover.prove(inputs.clone()).await,
+        };
+        match proof {
+            Err(e) if attempt < PROVE_ATTEMPTS => {
+                warn!(attempt, error = %e, "Proving failed, retrying");
+                attempt += 1;
+            }
+            proof => return proof,
+        }
+    }
+}
+
+/// A block whose proofs are being made. Its transactions stay in the
+/// mempool on disk until they are done.
+struct Proving {
+    block: u64,
+    txs: Vec<SignedTx>,
+    /// The state before the block, then after each of `txs`.
+    states: Vec<State>,
+    inputs_time: Duration,
+    started: Instant,
+    task: JoinHandle<Vec<ProofResult>>,
+}
+
+/// The proofs of the block being proven, never done if there is none.
+async fn proven(proving: &mut Option<Proving>) -> Vec<ProofResult> {
+    match proving {
+        Some(proving) => (&mut proving.task).await.unwrap_or_else(|e| {
+            vec![Err(format!("The proving task failed: {e}")); proving.txs.len()]
+        }),
+        None => std::future::pending().await,
+    }
+}
+
+pub(crate) type L1Contract =
+    fusion::Fusion<ethers::middleware::SignerMiddleware<Provider<FailoverHttp>, L1Signer>>;
+
+/// Messages that drive the sequencer loop.
+/// Everything that can start a block or touch the state goes through
+/// the same channel, so they are always handled one at a time.
+#[derive(Debug)]
+pub enum Trigger {
+    /// A new transaction for the mempool. Builds a block once
+    /// `min_tx_block` transactions are queued. Rejected transactions
+    /// are only logged.
+    Tx(SignedTx),
+    /// Same as `Tx`, replying whether the transaction was accepted and
+    /// where it stands in the mempool.
+    Submit {
+        tx: SignedTx,
+        reply: oneshot::Sender<Result<QueuePosition, SubmitError>>,
+    },
+    /// Builds a block right away with whatever is queued.
+    Flush(oneshot::Sender<Result<FlushResult, String>>),
+    /// Dev mode only: drops everything and goes back to genesis.
+    Reset(oneshot::Sender<Result<(), String>>),
+    /// Records a commitment to a transaction revealed later.
+    /// Replies with the block the commitment appears in.
+    Commit {
+        commitment: U256,
+        reply: oneshot::Sender<Result<u64, SubmitError>>,
+    },
+    /// Reveals a committed transaction and queues it.
+    Reveal {
+        tx: SignedTx,
+        salt: U256,
+        reply: oneshot::Sender<Result<(), SubmitError>>,
+    },
+    /// Drops the pending transaction with hash `hash`, see
+    /// `FusionRPC::drop_transaction`.
+    Drop {
+        hash: U256,
+        signature: String,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    /// Replies with statistics about the mempool.
+    MempoolStats(oneshot::Sender<MempoolStats>),
+    /// Replies with the next block as it would be built now.
+    PreviewBatch(oneshot::Sender<Result<BatchPreview, String>>),
+    /// Replies with the balance of `token` of the account with public
+    /// key `address`.
+    Balance {
+        address: U256,
+        token: Address,
+        reply: oneshot::Sender<Balance>,
+    },
+    /// Replies with the nonces of the sender with public key `address`
+    /// for `token`.
+    Nonce {
+        address: U256,
+        token: Address,
+        reply: oneshot::Sender<Nonces>,
+    },
+    /// Replies with what would become of `tx`, see `estimate`.
+    Estimate {
+        tx: Tx,
+        reply: oneshot::Sender<Estimate>,
+    },
+    /// Replies with the chain id transactions are signed for.
+    ChainInfo(oneshot::Sender<ChainInfo>),
+    /// Replies with the transaction with hash `hash` and its status, if
+    /// known.
+    Transaction {
+        hash: U256,
+        reply: oneshot::Sender<Option<TxInfo>>,
+    },
+    /// Replies with where the pending transaction with hash `hash`
+    /// stands in the mempool, if it is pending.
+    QueuePosition {
+        hash: U256,
+        reply: oneshot::Sender<Option<QueuePosition>>,
+    },
+    /// Replies with the witness of block `number`, if it was proven.
+    BatchWitness {
+        number: u64,
+        reply: oneshot::Sender<Result<Option<BatchWitness>, String>>,
+    },
+    /// Replies with the withdrawals of `sender` not paid out on L1 yet.
+    Withdrawals {
+        sender: U256,
+        reply: oneshot::Sender<Vec<TxInfo>>,
+    },
+    /// Stops the sequencer, see `Sequencer::shutdown`. The triggers
+    /// queued after it are dropped.
+    Shutdown,
+    /// Prunes old blocks and expired entries, and replies with the
+    /// storage usage.
+    Maintain(oneshot::Sender<StorageStats>),
+    /// Checks the L1 contract against the bindings and replies with its
+    /// version.
+    CheckContract(oneshot::Sender<Result<u64, String>>),
+    /// Dev mode only: sends `amount` from the faucet account to `to`.
+    /// Replies with the hash of the faucet transaction.
+    Faucet {
+        to: U256,
+        amount: U256,
+        reply: oneshot::Sender<Result<U256, String>>,
+    },
+    /// Dev mode or local chain only: replaces the injected faults.
+    SetChaos {
+        profile: ChaosProfile,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    /// Stops or resumes building blocks, see `AdminRPC`.
+    SetPaused {
+        paused: bool,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    /// Replies with every transaction in the mempool.
+    MempoolContent(oneshot::Sender<Vec<TxInfo>>),
+    /// Drops every transaction in the mempool and replies with how many.
+    DropAll(oneshot::Sender<usize>),
+    /// Snapshots the state replayed from L1 and replies with the L1
+    /// block of the snapshot.
+    Snapshot(oneshot::Sender<Result<u64, String>>),
+}
+
+/// What wakes up the RPC requests waiting for the sequencer.
+#[derive(Clone, Debug)]
+pub enum Event {
+    Block(BlockEvent),
+    /// A transaction was dropped while building a block.
+    Rejected(U256),
+}
+
+/// Events published to the waiting RPC requests. Nothing is kept when
+/// none is waiting.
+#[derive(Clone)]
+pub struct Events(broadcast::Sender<Event>);
+
+impl Default for Events {
+    fn default() -> Self {
+        Self(broadcast::channel(64).0)
+    }
+}
+
+impl Events {
+    pub fn publish(&self, event: Event) {
+        let _ = self.0.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.0.subscribe()
+    }
+}
+
+/// State the sequencer shares with the RPC server. Its locks are only
+/// held to copy data in or out, never across an `.await`. The chain state
+/// and the mempool rules belong to the sequencer task, which handles one
+/// `Trigger` at a time.
+#[derive(Clone, Default)]
+pub struct Shared {
+    pub info: Arc<Mutex<SequencerInfo>>,
+    pub health: Arc<Mutex<Health>>,
+    /// All blocks built so far, oldest first.
+    pub blocks: Arc<Mutex<Vec<Block>>>,
+    /// Attestations of the blocks confirmed on L1, oldest first.
+    pub attestations: Arc<Mutex<Vec<Attestation>>>,
+    /// The blocks confirmed on L1, oldest first, kept across restarts.
+    pub batches: Arc<Mutex<Vec<Batch>>>,
+    /// Statistics of every account active in `blocks`, by public key.
+    pub account_stats: Arc<Mutex<HashMap<U256, AccountStats>>>,
+    /// Timings of the latest `MAX_TIMED_BLOCKS` proven blocks.
+    pub timings: Arc<Mutex<VecDeque<BlockTimings>>>,
+    pub events: Events,
+    pub metrics: Metrics,
+    /// L1 transaction of the proof being submitted, until it is mined.
+    pub pending_l1_tx: Arc<Mutex<Option<H256>>>,
+    /// Why the last proof submission failed, `None` once one succeeds.
+    pub submission_error: Arc<Mutex<Option<String>>>,
+    /// The L1 head and root last read by `probes::watch_l1`.
+    pub l1_view: Arc<Mutex<Option<Result<L1View, String>>>>,
+    /// Times in a row the queued proofs built on a root L1 no longer
+    /// held, see `check_queue_front`.
+    pub stale_root_conflicts: Arc<Mutex<u64>>,
+    /// Records of the transactions in `blocks`, see `audit`. Held while
+    /// a history is read from it.
+    pub audit: Arc<Mutex<AuditLog>>,
+}
+
+impl Shared {
+    /// `view` of blocks `from` to `to`, both included, at most `max` of them.
+    fn block_range<T>(
+        &self,
+        (from, to): (u64, u64),
+        max: u64,
+        what: &str,
+        view: impl Fn(&Block) -> T,
+    ) -> Result<Vec<T>, String> {
+        if from == 0 || from > to {
+            return Err(format!("Invalid block range {from}..={to}"));
+        }
+        if to - from >= max {
+            return Err(format!("At most {max} {what} can be requested at once"));
+        }
+        let blocks = self.blocks.lock().unwrap();
+        if to > blocks.len() as u64 {
+            return Err(format!(
+                "Block {to} does not exist, the latest is {}",
+                blocks.len()
+            ));
+        }
+        Ok(blocks[from as usize - 1..to as usize]
+            .iter()
+            .map(view)
+            .collect())
+    }
+
+    /// Headers of blocks `from` to `to`, both included.
+    pub fn headers(&self, from: u64, to: u64) -> Result<Vec<BlockHeader>, String> {
+        self.block_range((from, to), MAX_HEADERS_PER_REQUEST, "headers", |block| {
+            block.header.clone()
+        })
+    }
+
+    /// Blocks `from` to `to` with their transactions, both included.
+    pub fn full_blocks(&self, from: u64, to: u64) -> Result<Vec<Block>, String> {
+        self.block_range((from, to), MAX_BLOCKS_PER_REQUEST, "blocks", Block::clone)
+    }
+
+    pub fn batch(&self, number: u64) -> Option<Batch> {
+        let batches = self.batches.lock().unwrap();
+        let i = batches
+            .binary_search_by_key(&number, |batch| batch.number)
+            .ok()?;
+        Some(batches[i].clone())
+    }
+
+    pub fn latest_batch(&self) -> Option<Batch> {
+        self.batches.lock().unwrap().last().cloned()
+    }
+
+    pub fn attestations(&self, offset: usize, limit: usize) -> Result<Vec<Attestation>, String> {
+        if limit > MAX_ATTESTATIONS_PER_REQUEST {
+            return Err(format!(
+                "At most {MAX_ATTESTATIONS_PER_REQUEST} attestations can be requested at once"
+            ));
+        }
+        Ok(self
+            .attestations
+            .lock()
+            .unwrap()
+            .iter()
+            .skip(offset)
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    /// See `FusionRPC::get_account_history`.
+    pub fn account_history(
+        &self,
+        address: U256,
+        (from_batch, to_batch): (u64, u64),
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<AuditRecord>, String> {
+        if limit > MAX_HISTORY_PER_REQUEST {
+            return Err(format!(
+                "At most {MAX_HISTORY_PER_REQUEST} records can be requested at once"
+            ));
+        }
+        self.audit
+            .lock()
+            .unwrap()
+            .account_history(address, (from_batch, to_batch), offset, limit)
+    }
+
+    pub fn proving_stats(&self) -> ProvingStats {
+        let blocks: Vec<_> = self.timings.lock().unwrap().iter().cloned().collect();
+        let stage = |ms: fn(&BlockTimings) -> u64| {
+            let mut values: Vec<_> = blocks.iter().map(ms).collect();
+            values.sort_unstable();
+            let percentile = |p: usize| match values.len() {
+                0 => 0,
+                n => values[(n * p).div_ceil(100).max(1) - 1],
+            };
+            StageStats {
+                p50: percentile(50),
+                p90: percentile(90),
+                max: values.last().copied().unwrap_or(0),
+            }
+        };
+        ProvingStats {
+            inputs: stage(|block| block.inputs_ms),
+            witness: stage(|block| block.witness_ms),
+            prove: stage(|block| block.prove_ms),
+            proving: stage(|block| block.proving_ms),
+            submit: stage(|block| block.submit_ms),
+            blocks,
+        }
+    }
+
+    fn record_timings(&self, timings: BlockTimings) {
+        let mut recorded = self.timings.lock().unwrap();
+        if recorded.len() == MAX_TIMED_BLOCKS {
+            recorded.pop_front();
+        }
+        recorded.push_back(timings);
+    }
+}
+
+pub struct Sequencer {
+    config: Config,
+    /// Kept on disk for restarts, unless `mempool_backend` is `memory`.
+    mempool: MemPool,
+    /// `Shared::batches` on disk, for restarts.
+    batch_log: BatchLog,
+    state: State,
+    block_number: u64,
+    /// `None` in dev mode, where blocks are committed locally
+    /// without proofs.
+    l1_contract: Option<L1Contract>,
+    /// Last time each recipient was sent funds by the faucet.
+    faucet_requests: HashMap<U256, Instant>,
+    /// Commitments not revealed yet, by commitment hash.
+    commitments: HashMap<U256, Commitment>,
+    next_commitment: u64,
+    /// Private key signing attestations and receipts.
+    attestation_key: String,
+    /// The receipts issued for the transactions not confirmed on L1 yet,
+    /// by transaction hash.
+    receipts: HashMap<U256, Receipt>,
+    /// Why the L1 contract does not match the bindings, if it stopped
+    /// matching. No block is built until a check succeeds again.
+    contract_error: Option<String>,
+    /// Identifier of the circuit proofs are made for, `None` in dev mode.
+    circuit_params: Option<H256>,
+    /// Proofs not accepted by L1 yet, oldest first.
+    submissions: SubmissionQueue,
+    /// The state replayed from L1, `None` in dev mode.
+    l1_sync: Option<L1Sync>,
+    /// The blocks confirmed on L1 less than `reorg_depth` L1 blocks ago.
+    history: BatchHistory,
+    /// Adjusts the batch size to the proving time, if enabled.
+    tuner: Option<BatchTuner>,
+    /// Faults injected on purpose, none unless enabled.
+    chaos: Chaos,
+    /// The system time, in seconds.
+    clock: fn() -> u64,
+    /// System time minus the timestamp of the latest L1 block, as
+    /// measured for the last block.
+    clock_drift: Option<i64>,
+    /// As of the latest maintenance run.
+    storage: StorageStats,
+    /// The latest `MAX_REJECTED_TXS` transactions dropped when building
+    /// a block, with the reason.
+    rejected: VecDeque<(SignedTx, String)>,
+    /// Set by an operator to stop building blocks.
+    paused: bool,
+    prover: Arc<dyn ProofService>,
+    /// The block being proven. No other block is built meanwhile.
+    proving: Option<Proving>,
+    /// Whether blocks are proven while transactions keep coming, as in
+    /// `run`, rather than before `build_block` returns.
+    pipelined: bool,
+    /// The state before the first block.
+    genesis: State,
+    shared: Shared,
+}
+
+impl Sequencer {
+    pub async fn new(config: &Config) -> anyhow::Result<Self> {
+        if config.chain_id == 0 {
+            anyhow::bail!("chain_id must not be 0, which the circuit reads as no chain id");
+        }
+        if !config.dev && !config.genesis_path.is_empty() {
+            anyhow::bail!(
+                "genesis_path is only read in dev mode, in L1 mode the state comes from the contract"
+            );
+        }
+        let genesis = load_genesis_state(config).map_err(|e| anyhow::anyhow!(e))?;
+        let db_path = Path::new(&config.database_path);
+        let metrics = Metrics::default();
+        let (l1_contract, contract_version, circuit_params) = match config.dev {
+            true => {
+                info!("Running in dev mode: blocks are not proven nor sent to L1");
+                if !config.genesis_path.is_empty() {
+                    let (path, root) = (&config.genesis_path, genesis.root());
+                    info!(%path, %root, "Loaded the genesis file");
+                }
+                dev::print_dev_accounts(config);
+                (None, None, None)
+            }
+            false => {
+                info!("Running in L1 mode: blocks are proven and submitted to L1");
+                let (l1_contract, version, circuit_params) =
+                    init_l1(config, metrics.clone()).await?;
+                info!(version, ?circuit_params, "Connected to the L1 contract");
+                (Some(l1_contract), Some(version), Some(circuit_params))
+            }
+        };
+        let l1_sync = match &l1_contract {
+            Some(l1_contract) => Some(init_sync(config, l1_contract).await?),
+            None => None,
+        };
+
+        let chaos = Chaos::default();
+        chaos
+            .set(config, config.chaos.clone())
+            .map_err(|e| anyhow::anyhow!(e))?;
+        let prover = proving::proof_service(config).map_err(|e| anyhow::anyhow!(e))?;
+        let mut submissions = match config.dev {
+            true => SubmissionQueue::in_memory(),
+            false => SubmissionQueue::open(db_path).map_err(|e| anyhow::anyhow!(e))?,
+        };
+        submissions.set_chaos(chaos.clone());
+        if !submissions.is_empty() {
+            info!(
+                proofs = submissions.len(),
+                "Resuming the submission of queued proofs"
+            );
+        }
+        let mempool: Box<dyn MempoolStore<PendingTx>> = match config.dev {
+            true => Box::<Vec<PendingTx>>::default(),
+            false => open_store(config.mempool_backend, db_path).map_err(|e| anyhow::anyhow!(e))?,
+        };
+        if !mempool.is_empty() {
+            info!(txs = mempool.len(), "Restored the mempool");
+        }
+        let (batch_log, batches) = match config.dev {
+            true => (BatchLog::in_memory(), vec![]),
+            false => BatchLog::open(db_path).map_err(|e| anyhow::anyhow!(e))?,
+        };
+        let (segment_bytes, max_bytes) =
+            (config.audit_log_segment_bytes, config.audit_log_max_bytes);
+        let audit = match config.dev {
+            true => AuditLog::in_memory(segment_bytes, max_bytes),
+            false => {
+                AuditLog::open(db_path, segment_bytes, max_bytes).map_err(|e| anyhow::anyhow!(e))?
+            }
+        };
+
+        let tuner = match config.auto_tune_batch {
+            true => Some(
+                BatchTuner::new(
+                    config.min_tx_block,
+                    config.min_batch_size,
+                    config.max_batch_size,
+                    Duration::from_secs(config.target_proving_time),
+                )
+                .map_err(|e| anyhow::anyhow!(e))?,
+            ),
+            false => None,
+        };
+        let batch_size = tuner
+            .as_ref()
+            .map_or(config.min_tx_block, BatchTuner::batch_size);
+
+        let attestation_key = match config.attestation_private_key.is_empty() {
+            true => {
+                warn!("No attestation_private_key configured, using a random one");
+                fusion_wallet::new_private_key().to_string()
+            }
+            false => config.attestation_private_key.clone(),
+        };
+        let attestation_public_key =
+            fusion_wallet::new_public_key(&attestation_key.clone().into()).to_u256();
+        info!(%attestation_public_key, "Attesting blocks");
+
+        let mut sequencer = Self {
+            config: config.clone(),
+            mempool: Arc::new(Mutex::new(mempool)),
+            batch_log,
+            state: l1_sync
+                .as_ref()
+                .map_or_else(|| genesis.clone(), |sync| sync.state().clone()),
+            genesis,
+            block_number: 0,
+            l1_contract,
+            faucet_requests: HashMap::new(),
+            commitments: HashMap::new(),
+            next_commitment: 0,
+            attestation_key,
+            receipts: HashMap::new(),
+            contract_error: None,
+            circuit_params,
+            submissions,
+            l1_sync,
+            history: BatchHistory::new(config.reorg_depth),
+            tuner,
+            chaos,
+            clock: block_timestamp,
+            clock_drift: None,
+            storage: StorageStats::default(),
+            rejected: VecDeque::new(),
+            paused: false,
+            prover,
+            proving: None,
+            pipelined: false,
+            shared: Shared {
+                info: Arc::new(Mutex::new(SequencerInfo {
+                    dev: config.dev,
+                    ordering: config.ordering,
+                    attestation_key: attestation_public_key,
+                    profile: config.profile.clone(),
+                    contract_version,
+                    circuit_params,
+                    batch_size,
+                })),
+                health: Arc::new(Mutex::new(Health::default())),
+                blocks: Arc::new(Mutex::new(vec![])),
+                attestations: Arc::new(Mutex::new(vec![])),
+                batches: Arc::new(Mutex::new(batches)),
+                account_stats: Arc::new(Mutex::new(HashMap::new())),
+                timings: Arc::new(Mutex::new(VecDeque::new())),
+                events: Events::default(),
+                metrics,
+                pending_l1_tx: Arc::new(Mutex::new(None)),
+                audit: Arc::new(Mutex::new(audit)),
+                ..Default::default()
+            },
+        };
+        sequencer.snapshot_if_due().await;
+        Ok(sequencer)
+    }
+
+    /// The transport to the L1 contract, `None` in dev mode. Its clones
+    /// move on to another endpoint together with it.
+    pub fn l1_transport(&self) -> Option<FailoverHttp> {
+        let contract = self.l1_contract.as_ref()?;
+        Some(AsRef::<FailoverHttp>::as_ref(contract.client().inner()).clone())
+    }
+
+    pub fn shared(&self) -> Shared {
+        self.shared.clone()
+    }
+
+    pub async fn run(mut self, mut rx: mpsc::Receiver<Trigger>) -> anyhow::Result<()> {
+        self.pipelined = true;
+        self.submit_queued().await;
+        loop {
+            let submission = self
+                .submissions
+                .next_attempt()
+                .map(|at| Duration::from_secs(at.saturating_sub(block_timestamp())));
+            // Confirmations are checked for reorgs while L1 polls.
+            let reorg_check = (!self.history.is_empty())
+                .then(|| Duration::from_millis(self.config.l1_poll_interval_ms));
+            let delay = submission.into_iter().chain(reorg_check).min();
+            let trigger = tokio::select! {
+                trigger = rx.recv() => trigger,
+                () = async {
+                    match delay {
+                        Some(delay) => tokio::time::sleep(delay).await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    // Requeued transactions are batched again at once.
+                    if self.check_reorgs().await
+                        && self.mempool.lock().unwrap().len() >= self.batch_size()
+                    {
+                        self.build_block().await;
+                    }
+                    if submission.is_some() && submission == delay {
+                        self.submit_queued().await;
+                    }
+                    continue;
+                }
+                proofs = proven(&mut self.proving) => {
+                    let proving = self.proving.take().unwrap();
+                    self.finish_proving(proving, proofs).await;
+                    // What queued up meanwhile makes the next block.
+                    if self.mempool.lock().unwrap().len() >= self.batch_size() {
+                        self.build_block().await;
+                    }
+                    continue;
+                }
+            };
+            let Some(trigger) = trigger else {
+                self.await_proving().await;
+                break;
+            };
+            let stop = matches!(trigger, Trigger::Shutdown);
+            self.handle(trigger).await;
+            if stop {
+                break;
+            }
+        }
+
+        self.set_health(
+            HealthStatus::Halted,
+            Some("Sequencer stopped receiving transactions".to_string()),
+        );
+        Ok(())
+    }
+
+    /// Sets the health, degraded if it would otherwise be `Ok` while
+    /// batching is paused or the clock drifted from L1 by more than
+    /// `clock_drift_warning`.
+    fn set_health(&self, status: HealthStatus, reason: Option<String>) {
+        let warning = self.config.clock_drift_warning;
+        let (status, reason) = match self.clock_drift {
+            _ if status == HealthStatus::Ok && self.paused => {
+                (HealthStatus::Degraded, Some(PAUSED_REASON.to_string()))
+            }
+            Some(drift)
+                if status == HealthStatus::Ok && warning > 0 && drift.unsigned_abs() > warning =>
+            {
+                (
+                    HealthStatus::Degraded,
+                    Some(format!(
+                        "The system clock is {drift}s away from the latest L1 block"
+                    )),
+                )
+            }
+            _ => (status, reason),
+        };
+        *self.shared.health.lock().unwrap() = Health {
+            status,
+            reason,
+            block_number: self.block_number,
+            mempool_size: self.mempool.lock().unwrap().len(),
+            submission_queue: self.submissions.len(),
+            oldest_submission_age: self.submissions.oldest_age(block_timestamp()),
+            clock_drift: self.clock_drift,
+            storage: self.storage.clone(),
+            pending_l1_tx: *self.shared.pending_l1_tx.lock().unwrap(),
+        };
+    }
+
+    /// Submits the queued proofs that are due, and attests the latest
+    /// block once all of its proofs are accepted.
+    async fn submit_queued(&mut self) -> Vec<Submitted> {
+        let Some(l1_contract) = &self.l1_contract else {
+            return vec![];
+        };
+        let shared = &self.shared;
+        let chaos = &self.chaos;
+        let settings = SubmitSettings::from_config(&self.config);
+        let now = block_timestamp();
+        let mut submitted = self
+            .submissions
+            .drain(now, |block, proof| {
+                let submission = submit_proof(
+                    l1_contract.clone(),
+                    shared.clone(),
+                    block,
+                    proof,
+                    chaos.clone(),
+                    settings,
+                );
+                async move {
+                    let started = Instant::now();
+                    let result = submission.await;
+                    shared.metrics.submission(started.elapsed(), result.is_ok());
+                    *shared.submission_error.lock().unwrap() = result.as_ref().err().cloned();
+                    result
+                }
+            })
+            .await;
+        if self.submissions.next_attempt() > Some(now) {
+            submitted.extend(self.check_queue_front().await);
+        }
+        if !submitted.is_empty() {
+            *self.shared.stale_root_conflicts.lock().unwrap() = 0;
+        }
+        for (i, confirmed) in submitted.iter().enumerate() {
+            let last_of_block = submitted[i + 1..]
+                .iter()
+                .all(|next| next.block != confirmed.block);
+            if last_of_block && !self.submissions.has_block(confirmed.block) {
+                self.shared.metrics.batch_submitted(confirmed.block);
+                if let Some(l1_block) = confirmed.l1_block {
+                    self.record_confirmation(confirmed.block, confirmed.l1_tx_hash, l1_block);
+                }
+                self.publish_block(confirmed.block, Some(confirmed.l1_tx_hash));
+            }
+        }
+        if self.contract_error.is_none() {
+            match self.submissions.len() {
+                0 => self.set_health(HealthStatus::Ok, None),
+                n => self.set_health(
+                    HealthStatus::Degraded,
+                    Some(format!("{n} proof(s) waiting to be submitted to L1")),
+                ),
+            }
+        }
+        submitted
+    }
+
+    /// After a failed submission, compares the root L1 holds with the
+    /// roots of the first queued proof. When L1 already holds its
+    /// post-root, the proof landed anyway, e.g. through a replaced
+    /// transaction, and is returned. When L1 holds neither root, every
+    /// queued proof builds on a stale root: their blocks are dropped and
+    /// their transactions go back to the mempool, to be revalidated
+    /// against the state L1 holds, and the next block replays L1 first,
+    /// see `follow_l1`.
+    async fn check_queue_front(&mut self) -> Vec<Submitted> {
+        let (Some(l1_contract), Some((block, proof))) =
+            (&self.l1_contract, self.submissions.front())
+        else {
+            return vec![];
+        };
+        let l1_root = match l1_contract.root().call().await {
+            Ok(l1_root) => l1_root,
+            Err(e) => {
+                warn!(error = %e, "Could not read the L1 root");
+                return vec![];
+            }
+        };
+        if l1_root == proof.input[0] {
+            return vec![];
+        }
+        if l1_root == proof.input[1] {
+            info!(block, "The proof was already accepted by L1");
+            let l1_tx_hash = self
+                .shared
+                .blocks
+                .lock()
+                .unwrap()
+                .iter()
+                .rev()
+                .find(|recorded| recorded.header.number == block)
+                .and_then(|recorded| recorded.header.l1_tx_hashes.last().copied())
+                .unwrap_or_default();
+            let l1_block = match l1_contract
+                .client()
+                .get_transaction_receipt(l1_tx_hash)
+                .await
+            {
+                Ok(Some(receipt)) => L1Block::of(&receipt),
+                _ => None,
+            };
+            if let Err(e) = self.submissions.pop_front() {
+                error!("{e}");
+            }
+            return vec![Submitted {
+                block,
+                l1_tx_hash,
+                l1_block,
+            }];
+        }
+
+        self.stale_root_conflict(block, l1_root);
+        self.requeue_from(block, vec![]);
+        vec![]
+    }
+
+    /// Counts a conflict of the proofs of `block` with `l1_root`. Several
+    /// in a row mean another submitter keeps moving L1, most likely a
+    /// second sequencer deployed against the same contract.
+    fn stale_root_conflict(&self, block: u64, l1_root: U256) {
+        let conflicts = {
+            let mut conflicts = self.shared.stale_root_conflicts.lock().unwrap();
+            *conflicts += 1;
+            *conflicts
+        };
+        warn!(
+            block,
+            %l1_root,
+            conflicts,
+            "Queued proofs build on a stale root, requeuing their transactions"
+        );
+        if conflicts > 1 {
+            error!(
+                conflicts,
+                "ALERT: L1 keeps moving without this sequencer, is another one submitting to the same contract?"
+            );
+        }
+    }
+
+    /// Requeues the blocks from the oldest one whose L1 confirmation was
+    /// reorged out, see `BatchHistory::first_reorged`. The replay of L1
+    /// is rewound to before its L1 block, so the next block builds on
+    /// the root L1 now holds. Returns whether blocks were requeued.
+    async fn check_reorgs(&mut self) -> bool {
+        let Some(l1_contract) = &self.l1_contract else {
+            return false;
+        };
+        let client = l1_contract.client();
+        let block = match self.history.first_reorged(client.as_ref()).await {
+            Ok(Some(block)) => block,
+            Ok(None) => return false,
+            Err(e) => {
+                warn!(error = %e, "Could not check the L1 confirmations for reorgs");
+                return false;
+            }
+        };
+        let confirmed = self.history.remove_from(block);
+        let (l1_block, prior_root) = (confirmed[0].l1_block.number, confirmed[0].prior_root);
+        warn!(
+            block,
+            l1_block,
+            %prior_root,
+            "An L1 reorg dropped a confirmed block, requeuing its transactions"
+        );
+        if let Some(sync) = &mut self.l1_sync {
+            if let Err(e) = sync.rewind(l1_block, genesis_state(&self.config)) {
+                error!("{e}");
+            }
+        }
+        self.requeue_from(block, confirmed);
+        true
+    }
+
+    /// Drops the blocks from `block` on with their queued proofs, and
+    /// puts their transactions back at the front of the mempool, to be
+    /// revalidated against the state L1 holds. The transactions of
+    /// `confirmed` blocks are taken from there, as the block history may
+    /// only keep their hashes.
+    fn requeue_from(&mut self, block: u64, confirmed: Vec<ConfirmedBlock>) {
+        if let Err(e) = self.submissions.clear() {
+            error!("{e}");
+        }
+        let mut txs: BTreeMap<u64, Vec<SignedTx>> = confirmed
+            .into_iter()
+            .map(|confirmed| (confirmed.block, confirmed.txs))
+            .collect();
+        {
+            let mut blocks = self.shared.blocks.lock().unwrap();
+            let kept = blocks
+                .iter()
+                .position(|recorded| recorded.header.number >= block)
+                .unwrap_or(blocks.len());
+            for recorded in blocks.drain(kept..) {
+                txs.entry(recorded.header.number).or_insert(recorded.txs);
+            }
+        }
+        let received_at = block_timestamp();
+        let requeued: Vec<_> = txs
+            .into_values()
+            .flatten()
+            .map(|tx| PendingTx {
+                tx,
+                received_at,
+                commitment: None,
+            })
+            .collect();
+        info!(txs = requeued.len(), "Transactions back in the mempool");
+        self.mempool.lock().unwrap().requeue(requeued);
+        self.persist_mempool();
+        self.block_number = block - 1;
+
+        let mut batches = self.shared.batches.lock().unwrap();
+        let kept = batches.partition_point(|batch| batch.number < block);
+        for batch in batches.drain(kept..) {
+            self.receipts.extend(
+                batch
+                    .receipts
+                    .into_iter()
+                    .map(|receipt| (receipt.tx_hash, receipt)),
+            );
+        }
+        if let Err(e) = self.batch_log.rewrite(&batches) {
+            error!("{e}");
+        }
+        if let Err(e) = self.shared.audit.lock().unwrap().truncate_from(block) {
+            error!("{e}");
+        }
+    }
+
+    /// Logs the batch of `block`, and remembers the L1 block that
+    /// confirmed it to notice a reorg dropping it.
+    fn record_confirmation(&mut self, block: u64, l1_tx_hash: H256, l1_block: L1Block) {
+        let blocks = self.shared.blocks.lock().unwrap();
+        let Some(i) = blocks
+            .iter()
+            .rposition(|recorded| recorded.header.number == block)
+        else {
+            return;
+        };
+        let prior_root = match i {
+            0 => blocks[0].header.l1_root.unwrap_or_default(),
+            i => blocks[i - 1].header.state_root,
+        };
+        let batch = Batch {
+            number: block,
+            prior_root,
+            state_root: blocks[i].header.state_root,
+            tx_hashes: blocks[i].tx_hashes.clone(),
+            txs: blocks[i].txs.clone(),
+            l1_tx_hash,
+            l1_block_number: l1_block.number,
+            timestamp: block_timestamp(),
+            receipts: blocks[i]
+                .tx_hashes
+                .iter()
+                .filter_map(|tx_hash| self.receipts.remove(tx_hash))
+                .collect(),
+        };
+        // The promises of rejected and replaced transactions are void,
+        // their receipts are dropped once the promised block is confirmed.
+        {
+            let mempool = self.mempool.lock().unwrap();
+            self.receipts.retain(|tx_hash, receipt| {
+                receipt.promised_block > block || mempool.get(tx_hash).is_some()
+            });
+        }
+        if let Err(e) = self.batch_log.append(&batch) {
+            error!("{e}");
+        }
+        self.shared.batches.lock().unwrap().push(batch);
+        self.history.record(ConfirmedBlock {
+            block,
+            prior_root,
+            txs: blocks[i].txs.clone(),
+            l1_tx_hash,
+            l1_block,
+        });
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    pub fn block_number(&self) -> u64 {
+        self.block_number
+    }
+
+    /// Hashes of the transactions in the mempool, in the order they
+    /// were queued.
+    pub fn pending_tx_hashes(&self) -> Vec<U256> {
+        self.mempool
+            .lock()
+            .unwrap()
+            .iter_pending()
+            .map(|pending| hash_tx(&pending.tx.tx))
+            .collect()
+    }
+
+    /// Samples the number of transactions in the mempool, for the metrics.
+    pub fn mempool_depth(&self) -> impl Fn() -> usize + Send + Sync + 'static {
+        let mempool = self.mempool.clone();
+        move || mempool.lock().unwrap().len()
+    }
+
+    /// Number of queued transactions that builds a block.
+    pub fn batch_size(&self) -> usize {
+        self.tuner
+            .as_ref()
+            .map_or(self.config.min_tx_block, BatchTuner::batch_size)
+    }
+
+    /// Most transactions in a block, see `max_block_txs`.
+    fn max_block_txs(&self) -> usize {
+        match self.config.max_block_txs {
+            0 => usize::MAX,
+            max => max,
+        }
+    }
+
+    /// Feeds the proving time of a block to the tuner, if enabled.
+    fn tune(&mut self, txs: usize, proving: Duration) {
+        let Some(tuner) = &mut self.tuner else {
+            return;
+        };
+        let previous = tuner.batch_size();
+        if let Some(batch_size) = tuner.record(txs, proving) {
+            info!(
+                txs,
+                proving_s = proving.as_secs_f64(),
+                previous,
+                batch_size,
+                "Tuned the batch size"
+            );
+            self.shared.info.lock().unwrap().batch_size = batch_size;
+        }
+    }
+
+    pub async fn handle(&mut self, trigger: Trigger) {
+        match trigger {
+            Trigger::Tx(tx) => {
+                let tx_hash = hash_tx(&tx.tx);
+                if let Err(e) = self.queue(tx, None).await {
+                    info!(%tx_hash, reason = %e, "Rejecting transaction");
+                }
+            }
+            Trigger::Submit { tx, reply } => {
+                self.queue_and_reply(tx, None, |queued| {
+                    let _ = reply.send(queued);
+                })
+                .await
+            }
+            Trigger::Commit { commitment, reply } => {
+                let _ = reply.send(self.commit(commitment));
+            }
+            Trigger::Reveal { tx, salt, reply } => {
+                match self
+                    .check_nonce(&tx.tx)
+                    .and_then(|()| self.reveal(&tx, salt).map_err(SubmitError::Invalid))
+                {
+                    Ok(commitment) => {
+                        self.queue_and_reply(tx, Some(commitment), |queued| {
+                            let _ = reply.send(queued.map(|_| ()));
+                        })
+                        .await
+                    }
+                    Err(e) => {
+                        let _ = reply.send(Err(e));
+                    }
+                }
+            }
+            Trigger::Drop {
+                hash,
+                signature,
+                reply,
+            } => {
+                let _ = reply.send(self.drop_tx(hash, &signature));
+            }
+            Trigger::QueuePosition { hash, reply } => {
+                let _ = reply.send(self.queue_position(&hash));
+            }
+            Trigger::Flush(reply) if self.paused => {
+                let _ = reply.send(Err("Batching is paused".to_string()));
+            }
+            Trigger::Flush(reply) => {
+                self.await_proving().await;
+                let result = match self.build_block().await {
+                    Some(block_number) => FlushResult::Block(block_number),
+                    None => FlushResult::NothingToBatch,
+                };
+                self.await_proving().await;
+                let _ = reply.send(Ok(result));
+            }
+            Trigger::Reset(reply) => {
+                let _ = reply.send(self.reset());
+            }
+            Trigger::MempoolStats(reply) => {
+                let pending = self.pending();
+                let _ = reply.send(mempool_stats(&self.state, &pending, block_timestamp()));
+            }
+            Trigger::PreviewBatch(reply) => {
+                let _ = reply.send(self.preview_batch());
+            }
+            Trigger::Balance {
+                address,
+                token,
+                reply,
+            } => {
+                let _ = reply.send(self.balance(address, token));
+            }
+            Trigger::Nonce {
+                address,
+                token,
+                reply,
+            } => {
+                let (nonces, _) = self.nonces(address, token);
+                let _ = reply.send(nonces);
+            }
+            Trigger::Estimate { tx, reply } => {
+                let _ = reply.send(self.estimate(&tx));
+            }
+            Trigger::ChainInfo(reply) => {
+                let _ = reply.send(self.chain_info());
+            }
+            Trigger::Transaction { hash, reply } => {
+                let _ = reply.send(self.transaction(hash));
+            }
+            Trigger::BatchWitness { number, reply } => {
+                let witness = match self.config.dev {
+                    true => Ok(None),
+                    false => witness::read(Path::new(&self.config.database_path), number),
+                };
+                let _ = reply.send(witness);
+            }
+            Trigger::Withdrawals { sender, reply } => {
+                let _ = reply.send(self.pending_withdrawals(sender));
+            }
+            Trigger::Shutdown => self.shutdown().await,
+            Trigger::Maintain(reply) => {
+                self.snapshot_if_due().await;
+                let _ = reply.send(self.maintain());
+            }
+            Trigger::CheckContract(reply) => {
+                let _ = reply.send(self.check_contract().await);
+            }
+            Trigger::Faucet { to, amount, reply } => match self.faucet_tx(to, amount) {
+                Ok(tx) => {
+                    let tx_hash = hash_tx(&tx.tx);
+                    let _ = reply.send(Ok(tx_hash));
+                    if let Err(e) = self.queue(tx, None).await {
+                        warn!(%tx_hash, reason = %e, "Rejecting faucet transaction");
+                    }
+                }
+                Err(e) => {
+                    let _ = reply.send(Err(e));
+                }
+            },
+            Trigger::SetChaos { profile, reply } => {
+                let _ = reply.send(self.chaos.set(&self.config, profile));
+            }
+            Trigger::SetPaused { paused, reply } => {
+                let _ = reply.send(self.set_paused(paused).await);
+            }
+            Trigger::MempoolContent(reply) => {
+                let content = self
+                    .mempool
+                    .lock()
+                    .unwrap()
+                    .iter_pending()
+                    .map(|pending| TxInfo {
+                        hash: hash_tx(&pending.tx.tx),
+                        tx: Some(pending.tx.clone()),
+                        status: TxStatus::Pending,
+                    })
+                    .collect();
+                let _ = reply.send(content);
+            }
+            Trigger::DropAll(reply) => {
+                let dropped = self
+                    .mempool
+                    .lock()
+                    .unwrap()
+                    .drain_batch(usize::MAX, &mut |_| true);
+                warn!(txs = dropped.len(), "Dropping the mempool");
+                self.persist_mempool();
+                let count = dropped.len();
+                for pending in dropped {
+                    self.reject(pending.tx, "Dropped by an operator".to_string());
+                }
+                let _ = reply.send(count);
+            }
+            Trigger::Snapshot(reply) => {
+                let _ = reply.send(self.snapshot(true).await.map(Option::unwrap_or_default));
+            }
+        }
+    }
 
-type MemPool = Arc<Mutex<Vec<SignedTx>>>;
+    /// Stops or resumes building blocks. On resuming, a block is built
+    /// at once if enough transactions are waiting.
+    async fn set_paused(&mut self, paused: bool) -> Result<(), String> {
+        if self.paused == paused {
+            return Err(match paused {
+                true => "Batching is already paused".to_string(),
+                false => "Batching is not paused".to_string(),
+            });
+        }
+        self.paused = paused;
+        warn!(paused, "Batching paused or resumed by an operator");
+        let health = self.shared.health.lock().unwrap().clone();
+        let unpaused = !paused && health.reason.as_deref() == Some(PAUSED_REASON);
+        if (paused && health.status == HealthStatus::Ok) || unpaused {
+            self.set_health(HealthStatus::Ok, None);
+        }
+        if !paused && self.mempool.lock().unwrap().len() >= self.batch_size() {
+            self.build_block().await;
+        }
+        Ok(())
+    }
+
+    /// Adds a transaction to the mempool and builds a block
+    /// once there are enough of them.
+    async fn queue(
+        &mut self,
+        tx: SignedTx,
+        commitment: Option<Commitment>,
+    ) -> Result<(), SubmitError> {
+        if self.admit(tx, commitment)? {
+            self.build_block().await;
+        }
+        Ok(())
+    }
+
+    /// Same as `queue`, replying whether the transaction was accepted,
+    /// and where it stands, before building a block.
+    async fn queue_and_reply(
+        &mut self,
+        tx: SignedTx,
+        commitment: Option<Commitment>,
+        reply: impl FnOnce(Result<QueuePosition, SubmitError>),
+    ) {
+        let tx_hash = hash_tx(&tx.tx);
+        let admitted = self.admit(tx, commitment);
+        let ready = admitted == Ok(true);
+        reply(admitted.and_then(|_| {
+            let mut queued = self
+                .queue_position(&tx_hash)
+                .ok_or_else(|| SubmitError::Internal(format!("{tx_hash} left the mempool")))?;
+            queued.receipt = Some(self.receipt(&queued)?);
+            Ok(queued)
+        }));
+        if ready {
+            self.build_block().await;
+        }
+    }
+
+    /// Where the pending transaction with hash `hash` stands. Blocks take
+    /// the mempool in order, at most `max_block_txs` at a time.
+    fn queue_position(&self, hash: &U256) -> Option<QueuePosition> {
+        let (position, queued) = {
+            let mempool = self.mempool.lock().unwrap();
+            (mempool.position(hash)?, mempool.len())
+        };
+        let max_block_txs = self.config.max_block_txs;
+        Some(QueuePosition {
+            tx_hash: *hash,
+            position,
+            queued,
+            batch_size: self.batch_size(),
+            max_block_txs,
+            blocks_ahead: position.checked_div(max_block_txs).unwrap_or(0),
+            receipt: None,
+        })
+    }
+
+    /// The receipt of the transaction `queued`, signed the first time it
+    /// is queued and returned again when it is resubmitted.
+    fn receipt(&mut self, queued: &QueuePosition) -> Result<Receipt, SubmitError> {
+        if let Some(receipt) = self.receipts.get(&queued.tx_hash) {
+            return Ok(receipt.clone());
+        }
+        let mut receipt = Receipt {
+            tx_hash: queued.tx_hash,
+            accepted_at: block_timestamp(),
+            promised_block: self.block_number + 1 + queued.blocks_ahead as u64,
+            signature: String::new(),
+        };
+        receipt.signature = fusion_wallet::sign_receipt(&receipt, self.attestation_key.clone())
+            .map_err(|e| SubmitError::Internal(format!("Could not sign the receipt: {e}")))?
+            .to_string();
+        self.receipts.insert(receipt.tx_hash, receipt.clone());
+        Ok(receipt)
+    }
+
+    /// Records that `tx` left the mempool without being included, for
+    /// `get_transaction` and the waiting RPC requests.
+    fn reject(&mut self, tx: SignedTx, reason: String) {
+        let tx_hash = hash_tx(&tx.tx);
+        info!(%tx_hash, sender = %tx.tx.sender, %reason, "Dropping transaction");
+        self.shared.metrics.rejected(Rejection::of(&reason));
+        if self.rejected.len() == MAX_REJECTED_TXS {
+            self.rejected.pop_front();
+        }
+        self.rejected.push_back((tx, reason));
+        self.shared.events.publish(Event::Rejected(tx_hash));
+    }
+
+    /// Drops the transactions that waited in the mempool for more than
+    /// `mempool_ttl` seconds.
+    fn prune_expired(&mut self) {
+        let ttl = self.config.mempool_ttl;
+        if ttl == 0 {
+            return;
+        }
+        let now = block_timestamp();
+        let expired = self
+            .mempool
+            .lock()
+            .unwrap()
+            .drain_batch(usize::MAX, &mut |pending| {
+                pending.received_at.saturating_add(ttl) <= now
+            });
+        if expired.is_empty() {
+            return;
+        }
+        self.persist_mempool();
+        for pending in expired {
+            self.reject(pending.tx, format!("Expired after {ttl}s in the mempool"));
+        }
+    }
+
+    /// Drops the pending transaction with hash `hash` at the request of
+    /// its sender, who signed `drop_message(hash)`.
+    fn drop_tx(&mut self, hash: U256, signature: &str) -> Result<(), String> {
+        let pending = {
+            let mut mempool = self.mempool.lock().unwrap();
+            let sender = mempool
+                .get(&hash)
+                .ok_or_else(|| format!("Transaction {hash} is not pending"))?
+                .tx
+                .tx
+                .sender;
+            fusion_wallet::verify_drop(hash, signature, &sender)
+                .map_err(|e| format!("Not dropping {hash}: {e}"))?;
+            mempool.remove(&hash).unwrap()
+        };
+        self.persist_mempool();
+        self.reject(pending.tx, "Dropped by its sender".to_string());
+        Ok(())
+    }
+
+    /// Adds a transaction to the mempool. Returns whether there are
+    /// enough of them for a block.
+    fn admit(&self, tx: SignedTx, commitment: Option<Commitment>) -> Result<bool, SubmitError> {
+        fusion_wallet::check_chain_id(&tx.tx, self.config.chain_id, self.accepts_legacy())
+            .map_err(SubmitError::Invalid)?;
+        // Checked before anything else, so that a forged transaction can
+        // neither take the sender's next nonce nor evict one it signed.
+        verify_tx_signature(&tx).map_err(|e| {
+            SubmitError::InvalidSignature(format!("Not queuing {}: {e}", hash_tx(&tx.tx)))
+        })?;
+        if self.resubmit(&tx, commitment.is_some())? {
+            return Ok(false);
+        }
+        self.check_nonce(&tx.tx)?;
+        let max = self.config.max_mempool_size;
+        if max > 0 && self.mempool.lock().unwrap().len() >= max {
+            return Err(SubmitError::MempoolFull { max });
+        }
+        let pending = PendingTx {
+            tx,
+            received_at: block_timestamp(),
+            commitment,
+        };
+        let mut unlocked_mempool = self.mempool.lock().unwrap();
+        if let Err(e) = unlocked_mempool.insert(pending) {
+            error!("{e}");
+        }
+        Ok(unlocked_mempool.len() >= self.batch_size())
+    }
+
+    /// Handles a transaction whose sender already has one pending with
+    /// its nonce, once its signature is checked. The same signed
+    /// transaction is accepted again without being queued twice, so that
+    /// clients can retry. A different one takes the place of the pending
+    /// one if `replace_pending_txs`, and is refused otherwise. Returns
+    /// whether there was one pending.
+    fn resubmit(&self, tx: &SignedTx, committed: bool) -> Result<bool, SubmitError> {
+        let mut mempool = self.mempool.lock().unwrap();
+        let (sender, nonce) = tx.slot();
+        let Some(pending) = mempool.by_slot(sender, nonce) else {
+            return Ok(false);
+        };
+        let (pending_hash, tx_hash) = (hash_tx(&pending.tx.tx), hash_tx(&tx.tx));
+        if pending_hash == tx_hash && pending.tx.signature == tx.signature {
+            return Ok(true);
+        }
+        // Commitments are revealed in their order, a replacement would
+        // skip it.
+        if !self.config.replace_pending_txs || committed || pending.commitment.is_some() {
+            return Err(SubmitError::Duplicate { nonce: tx.tx.nonce });
+        }
+        info!(%pending_hash, %tx_hash, "Replacing a pending transaction");
+        let replacement = PendingTx {
+            tx: tx.clone(),
+            ..pending.clone()
+        };
+        mempool.replace(&pending_hash, replacement);
+        drop(mempool);
+        self.persist_mempool();
+        Ok(true)
+    }
+
+    /// Nonces of the sender with public key `sender` for `token`,
+    /// counting its transactions in the mempool, whose nonces are
+    /// returned as well.
+    fn nonces(&self, sender: U256, token: Address) -> (Nonces, Vec<U256>) {
+        let address = PublicKey::from(sender).address();
+        let committed = self.state.get(&account_key(address, token)).nonce;
+        // Slots are keyed as in `Keyed::slot`.
+        let slot = match token == NATIVE_TOKEN {
+            true => sender,
+            false => account_key(address, token),
+        };
+        let pending = self.mempool.lock().unwrap().nonces_of(slot);
+        let last = pending.iter().copied().fold(committed, U256::max);
+        let nonces = Nonces {
+            committed,
+            pending: last,
+            next: last + 1,
+        };
+        (nonces, pending)
+    }
+
+    /// Checks that `tx` has the next nonce of its sender, counting the
+    /// sender's transactions already in the mempool, and that the sender
+    /// has fewer than `max_pending_per_sender` of them.
+    fn check_nonce(&self, tx: &Tx) -> Result<(), SubmitError> {
+        let (nonces, pending) = self.nonces(tx.sender, tx.token);
+        let max = self.config.max_pending_per_sender;
+        if pending.contains(&tx.nonce) {
+            Err(SubmitError::Duplicate { nonce: tx.nonce })
+        } else if tx.nonce <= nonces.committed || tx.nonce != nonces.next {
+            Err(SubmitError::BadNonce {
+                nonce: tx.nonce,
+                expected: nonces.next,
+            })
+        } else if max > 0 && pending.len() >= max {
+            Err(SubmitError::SenderLimit { max })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Rewrites the mempool on disk with what is left in memory, unless
+    /// a block is being proven, whose transactions stay on disk until
+    /// its proofs are queued.
+    fn persist_mempool(&self) {
+        if self.proving.is_some() {
+            return;
+        }
+        if let Err(e) = self.mempool.lock().unwrap().persist() {
+            error!("{e}");
+        }
+    }
+
+    /// A copy of the transactions in the mempool, in order.
+    fn pending(&self) -> Vec<PendingTx> {
+        self.mempool
+            .lock()
+            .unwrap()
+            .iter_pending()
+            .cloned()
+            .collect()
+    }
+
+    /// Builds a last block if `flush_on_shutdown`, then submits the
+    /// queued proofs for at most `shutdown_timeout` seconds. Whatever is
+    /// left is on disk and picked up at the next start.
+    async fn shutdown(&mut self) {
+        info!("Shutting down");
+        self.set_health(HealthStatus::Halted, Some("Shutting down".to_string()));
+        let timeout = Duration::from_secs(self.config.shutdown_timeout);
+        let finished = tokio::time::timeout(timeout, async {
+            self.await_proving().await;
+            if self.config.flush_on_shutdown && !self.mempool.lock().unwrap().is_empty() {
+                self.build_block().await;
+                self.await_proving().await;
+            }
+            while let Some(at) = self.submissions.next_attempt() {
+                tokio::time::sleep(Duration::from_secs(at.saturating_sub(block_timestamp()))).await;
+                self.submit_queued().await;
+            }
+        })
+        .await;
+        if finished.is_err() {
+            warn!(
+                timeout_s = timeout.as_secs(),
+                proofs = self.submissions.len(),
+                "Stopping with proofs still queued"
+            );
+        }
+        self.persist_mempool();
+    }
+
+    /// Drops the transactions of the blocks older than `block_retention`,
+    /// except for the ones still waiting for L1, the faucet requests past
+    /// their cooldown and the mempool entries past `mempool_ttl`.
+    fn maintain(&mut self) -> StorageStats {
+        self.prune_expired();
+        let keep_from = match self.config.block_retention {
+            0 => 0,
+            retention => (self.block_number + 1).saturating_sub(retention),
+        };
+        let keep_from = keep_from.min(self.submissions.oldest_block().unwrap_or(u64::MAX));
+        let (reclaimed_bytes, blocks_bytes) = {
+            let mut blocks = self.shared.blocks.lock().unwrap();
+            let reclaimed = maintenance::prune_blocks(&mut blocks, keep_from);
+            (reclaimed, maintenance::blocks_size(&blocks))
+        };
+
+        let cooldown = Duration::from_secs(self.config.faucet_cooldown);
+        self.faucet_requests
+            .retain(|_, requested| requested.elapsed() < cooldown);
+
+        self.storage = StorageStats {
+            pruned_through: self.storage.pruned_through.max(keep_from.saturating_sub(1)),
+            reclaimed_bytes,
+            blocks_bytes,
+            database_bytes: match self.config.dev {
+                true => 0,
+                false => maintenance::dir_size(Path::new(&self.config.database_path)),
+            },
+        };
+        self.shared.health.lock().unwrap().storage = self.storage.clone();
+        self.storage.clone()
+    }
+
+    /// Balance of `token` of the account with public key `address` as of
+    /// the latest block, and once the mempool is included.
+    fn balance(&self, address: U256, token: Address) -> Balance {
+        let address = account_key(PublicKey::from(address).address(), token);
+        let pending = self.pending();
+        Balance {
+            committed: self.state.get(&address).balance,
+            pending: project_accounts(&self.state, &pending, &[address])[0].balance,
+        }
+    }
+
+    /// Whether `tx`, once signed, would be admitted and then included
+    /// after the transactions in the mempool. Changes nothing.
+    fn estimate(&self, tx: &Tx) -> Estimate {
+        if let Err(e) =
+            fusion_wallet::check_chain_id(tx, self.config.chain_id, self.accepts_legacy())
+        {
+            return Estimate::Invalid(e);
+        }
+        let max = self.config.max_mempool_size;
+        if max > 0 && self.mempool.lock().unwrap().len() >= max {
+            return Estimate::Invalid(format!("The mempool is full, with {max} transactions"));
+        }
+        let (nonces, pending) = self.nonces(tx.sender, tx.token);
+        if tx.nonce != nonces.next {
+            return Estimate::BadNonce {
+                expected: nonces.next,
+            };
+        }
+        let max = self.config.max_pending_per_sender;
+        if max > 0 && pending.len() >= max {
+            return Estimate::Invalid(SubmitError::SenderLimit { max }.to_string());
+        }
+        // Only a transfer credits its recipient, whose key a withdrawal's
+        // L1 address need not be.
+        let mut addresses = vec![tx.sender_account()];
+        if matches!(tx.kind, TxKind::Transfer) {
+            addresses.push(tx.to_account());
+        }
+        let pending = self.pending();
+        let accounts = project_accounts(&self.state, &pending, &addresses);
+        let sender = accounts[0].clone();
+        let to = accounts.get(1).cloned().unwrap_or_default();
+        if matches!(tx.kind, TxKind::Transfer | TxKind::Withdraw) && sender.balance < tx.value {
+            return Estimate::InsufficientBalance {
+                balance: sender.balance,
+                shortfall: tx.value - sender.balance,
+            };
+        }
+        let estimate = check_tx_account(&sender, tx, false)
+            .and_then(|()| transition(&tx.kind, sender, to, tx.nonce, tx.value));
+        match estimate {
+            Ok((sender, _)) => Estimate::Success {
+                post_balance: sender.balance,
+                fee: U256::zero(),
+            },
+            Err(e) => Estimate::Invalid(e.to_string()),
+        }
+    }
+
+    /// Whether transactions signed without a chain id are still admitted.
+    fn accepts_legacy(&self) -> bool {
+        self.config.accept_legacy_txs
+            && (self.config.legacy_txs_until == 0 || (self.clock)() <= self.config.legacy_txs_until)
+    }
+
+    fn chain_info(&self) -> ChainInfo {
+        ChainInfo {
+            chain_id: self.config.chain_id,
+            l1_chain_id: self.config.l1_chain_id,
+            l1_contract: self.config.fusion_l1_contract,
+            tx_format: TX_FORMAT,
+            accepts_legacy: self.accepts_legacy(),
+        }
+    }
+
+    /// The transaction with hash `hash`, looked up in the mempool, then
+    /// the blocks, then the rejected transactions.
+    fn transaction(&self, hash: U256) -> Option<TxInfo> {
+        let pending = self
+            .mempool
+            .lock()
+            .unwrap()
+            .get(&hash)
+            .map(|pending| pending.tx.clone());
+        if let Some(tx) = pending {
+            return Some(TxInfo {
+                hash,
+                tx: Some(tx),
+                status: TxStatus::Pending,
+            });
+        }
+        let blocks = self.shared.blocks.lock().unwrap();
+        for block in blocks.iter().rev() {
+            if let Some(i) = block.tx_hashes.iter().position(|tx_hash| *tx_hash == hash) {
+                return Some(TxInfo {
+                    hash,
+                    tx: block.txs.get(i).cloned(),
+                    status: TxStatus::Included {
+                        block: block.header.number,
+                        l1_tx_hashes: block.header.l1_tx_hashes.clone(),
+                    },
+                });
+            }
+        }
+        self.rejected
+            .iter()
+            .rev()
+            .find(|(tx, _)| hash_tx(&tx.tx) == hash)
+            .map(|(tx, reason)| TxInfo {
+                hash,
+                tx: Some(tx.clone()),
+                status: TxStatus::Rejected {
+                    reason: reason.clone(),
+                },
+            })
+    }
+
+    /// The withdrawals of `sender` in blocks whose proofs are still
+    /// queued, then in the mempool. Without L1 nothing is paid out, only
+    /// the mempool is listed.
+    fn pending_withdrawals(&self, sender: U256) -> Vec<TxInfo> {
+        let is_withdrawal =
+            |tx: &SignedTx| matches!(tx.tx.kind, TxKind::Withdraw) && tx.tx.sender == sender;
+        let mut withdrawals = vec![];
+        for block in self.shared.blocks.lock().unwrap().iter() {
+            if !self.submissions.has_block(block.header.number) {
+                continue;
+            }
+            for (tx, hash) in block.txs.iter().zip(&block.tx_hashes) {
+                if is_withdrawal(tx) {
+                    withdrawals.push(TxInfo {
+                        hash: *hash,
+                        tx: Some(tx.clone()),
+                        status: TxStatus::Included {
+                            block: block.header.number,
+                            l1_tx_hashes: block.header.l1_tx_hashes.clone(),
+                        },
+                    });
+                }
+            }
+        }
+        for pending in self.mempool.lock().unwrap().iter_pending() {
+            if is_withdrawal(&pending.tx) {
+                withdrawals.push(TxInfo {
+                    hash: hash_tx(&pending.tx.tx),
+                    tx: Some(pending.tx.clone()),
+                    status: TxStatus::Pending,
+                });
+            }
+        }
+        withdrawals
+    }
+
+    /// Hash of the latest block, zero before the first one.
+    fn parent_hash(&self) -> U256 {
+        self.shared
+            .blocks
+            .lock()
+            .unwrap()
+            .last()
+            .map_or(U256::zero(), |block| block.header.hash())
+    }
+
+    /// Timestamp of the latest block, if any.
+    fn parent_timestamp(&self) -> Option<u64> {
+        self.shared
+            .blocks
+            .lock()
+            .unwrap()
+            .last()
+            .map(|block| block.header.timestamp)
+    }
+
+    /// What `build_block` would do with the mempool now, leaving it and
+    /// the state as they are.
+    fn preview_batch(&self) -> Result<BatchPreview, String> {
+        if !self.config.batch_preview {
+            return Err("Batch previews are disabled, see batch_preview in the config".to_string());
+        }
+        if let Some(e) = &self.contract_error {
+            return Err(format!(
+                "No block would be built, the L1 contract does not match: {e}"
+            ));
+        }
+        let timestamp = assign_timestamp((self.clock)(), self.parent_timestamp(), None)?;
+        let block = self.block_number + 1;
+        let pending = self.pending();
+        let (waiting, eligible) = split_eligible(pending, timestamp, block, self.max_block_txs());
+        let mut excluded: Vec<_> = waiting
+            .iter()
+            .map(|pending| {
+                let exclusion = match pending.tx.tx.validity_at(timestamp) {
+                    Validity::Premature => Exclusion::Premature,
+                    _ if is_waiting(pending, timestamp, block) => Exclusion::Committed,
+                    _ => Exclusion::Full,
+                };
+                (hash_tx(&pending.tx.tx), exclusion)
+            })
+            .collect();
+
+        // Deposit claims are not checked against L1, that needs a call.
+        let selection = select_txs(
+            &self.state,
+            eligible,
+            timestamp,
+            self.config.ordering,
+            self.parent_hash(),
+            block,
+            None,
+        );
+        excluded.extend(
+            selection
+                .excluded
+                .into_iter()
+                .map(|(tx, exclusion)| (hash_tx(&tx.tx), exclusion)),
+        );
+        Ok(BatchPreview {
+            block,
+            timestamp,
+            tx_hashes: selection.txs.iter().map(|tx| hash_tx(&tx.tx)).collect(),
+            state_root: selection.states.last().unwrap().root(),
+            excluded,
+        })
+    }
+
+    /// Builds a block with the eligible transactions in the mempool and
+    /// starts proving it. Returns the number of the new block, if any.
+    async fn build_block(&mut self) -> Option<u64> {
+        self.prune_expired();
+        if let Some(proving) = &self.proving {
+            debug!(
+                block = proving.block,
+                "Not building a block, one is being proven"
+            );
+            return None;
+        }
+        if self.paused {
+            debug!("Not building a block, batching is paused");
+            return None;
+        }
+        if self.l1_contract.is_some() {
+            let _ = self.check_contract().await;
+        }
+        if let Some(e) = &self.contract_error {
+            warn!(reason = %e, "Not building a block, the L1 contract does not match");
+            return None;
+        }
+        self.check_reorgs().await;
+        let (l1_root, l1_time) = match &self.l1_contract {
+            Some(l1_contract) => {
+                // Nothing has left the mempool yet, the next tick retries.
+                let current_root = match l1_contract.root().call().await {
+                    Ok(current_root) => current_root,
+                    Err(e) => {
+                        let e = format!("Could not read the L1 root: {e}");
+                        warn!(reason = %e, "Not building a block");
+                        self.set_health(HealthStatus::Degraded, Some(e));
+                        return None;
+                    }
+                };
+                debug!(root = %current_root, "Read the L1 root");
+                let l1_time = l1_timestamp(l1_contract).await;
+                if let Err(e) = &l1_time {
+                    warn!(error = %e, "Could not read the L1 time");
+                }
+                (Some(current_root), l1_time.ok())
+            }
+            None => (None, None),
+        };
+        // With proofs still queued, L1 is behind this sequencer rather
+        // than ahead of it.
+        if let Some(l1_root) = l1_root.filter(|root| *root != self.state.root()) {
+            if self.submissions.is_empty() {
+                if let Err(e) = self.follow_l1(l1_root).await {
+                    warn!(reason = %e, "Not building a block");
+                    self.set_health(HealthStatus::Degraded, Some(e));
+                    return None;
+                }
+            }
+        }
+
+        let now = (self.clock)();
+        self.clock_drift = l1_time.map(|l1_time| now as i64 - l1_time as i64);
+        let max_timestamp = l1_time
+            .filter(|_| self.config.max_l1_drift > 0)
+            .map(|l1_time| l1_time + self.config.max_l1_drift);
+        let timestamp = match assign_timestamp(now, self.parent_timestamp(), max_timestamp) {
+            Ok(timestamp) => timestamp,
+            Err(e) => {
+                warn!(reason = %e, "Not building a block");
+                self.set_health(HealthStatus::Degraded, Some(e));
+                return None;
+            }
+        };
+        let deposits = match &self.l1_contract {
+            Some(l1_contract) => {
+                let recipients: Vec<_> = self
+                    .mempool
+                    .lock()
+                    .unwrap()
+                    .iter_pending()
+                    .filter(|pending| matches!(pending.tx.tx.kind, TxKind::Deposit))
+                    .map(|pending| (pending.tx.tx.token, pending.tx.tx.sender_account()))
+                    .collect();
+                match l1_deposits(l1_contract, recipients, self.submissions.deposit_claims()).await
+                {
+                    Ok(deposits) => Some(deposits),
+                    Err(e) => {
+                        let e = format!("Could not read the L1 deposits: {e}");
+                        warn!(reason = %e, "Not building a block");
+                        self.set_health(HealthStatus::Degraded, Some(e));
+                        return None;
+                    }
+                }
+            }
+            None => None,
+        };
+        let eligible = take_eligible(
+            &self.mempool,
+            timestamp,
+            self.block_number + 1,
+            self.max_block_txs(),
+        );
+        let drained = eligible.clone();
+        let Selection {
+            txs,
+            states,
+            excluded,
+        } = select_txs(
+            &self.state,
+            eligible,
+            timestamp,
+            self.config.ordering,
+            self.parent_hash(),
+            self.block_number + 1,
+            deposits.as_ref(),
+        );
+        for (tx, exclusion) in excluded {
+            let reason = match exclusion {
+                Exclusion::Expired => "Expired".to_string(),
+                Exclusion::Invalid(e) | Exclusion::Revalidation(e) => e,
+                Exclusion::Premature | Exclusion::Committed | Exclusion::Full => continue,
+            };
+            self.reject(tx, reason);
+        }
+
+        if txs.is_empty() {
+            return None;
+        }
+
+        // Checked before anything moves forward: a block without its
+        // witness cannot be proven, so its transactions go back to the
+        // front of the mempool, and the next block tries them again.
+        if self.l1_contract.is_some() {
+            let block_number = self.block_number + 1;
+            let witness = witness::batch_witness(block_number, &txs, &states);
+            if let Err(e) = witness::verify(&witness)
+                .and_then(|()| witness::write(Path::new(&self.config.database_path), &witness))
+            {
+                error!(block = block_number, error = %e, "No witness, not building the block");
+                let selected: HashSet<U256> = txs.iter().map(|tx| hash_tx(&tx.tx)).collect();
+                let requeued = drained
+                    .into_iter()
+                    .filter(|pending| selected.contains(&hash_tx(&pending.tx.tx)))
+                    .collect();
+                self.mempool.lock().unwrap().requeue(requeued);
+                self.persist_mempool();
+                self.set_health(
+                    HealthStatus::Degraded,
+                    Some(format!(
+                        "Could not build the witness of block {block_number}: {e}"
+                    )),
+                );
+                return None;
+            }
+        }
+
+        self.block_number += 1;
+        info!(block = self.block_number, txs = txs.len(), "Building block");
+        let block_number = self.block_number;
+        let reveal_window = self.config.reveal_window;
+        self.commitments
+            .retain(|_, commitment| commitment.block + reveal_window > block_number);
+
+        self.state = states.last().unwrap().clone();
+        info!(block = block_number, root = %self.state.root(), "Computed the state root");
+        let records = audit::records(block_number, &txs, &states);
+        if let Err(e) = self.shared.audit.lock().unwrap().append(&records) {
+            error!(block = block_number, error = %e, "Could not write the audit log");
+        }
+        self.record_block(&txs, timestamp, l1_root);
+        self.publish_block(block_number, None);
+
+        if self.l1_contract.is_none() {
+            self.persist_mempool();
+            info!(block = block_number, "Block committed locally");
+            self.set_health(HealthStatus::Ok, None);
+            return Some(self.block_number);
+        }
+
+        let inputs_start = Instant::now();
+        let inputs = CircuitInput::batch(&txs, &states);
+        let inputs_time = inputs_start.elapsed();
+
+        info!(
+            block = block_number,
+            proofs = inputs.len(),
+            "Requesting proofs"
+        );
+        let started = Instant::now();
+        // A transaction whose inputs cannot be built fails like a proof would.
+        let tasks: Vec<_> = inputs
+            .into_iter()
+            .map(|inputs| match inputs {
+                Ok(inputs) => Ok(tokio::spawn(request_proof(
+                    self.prover.clone(),
+                    inputs,
+                    self.chaos.clone(),
+                ))),
+                Err(e) => Err(format!("Could not build the circuit inputs: {e}")),
+            })
+            .collect();
+        let task = tokio::spawn(async move {
+            let mut proofs = vec![];
+            for task in tasks {
+                proofs.push(match task {
+                    Ok(task) => task
+                        .await
+                        .unwrap_or_else(|e| Err(format!("The proving task failed: {e}"))),
+                    Err(e) => Err(e),
+                });
+            }
+            proofs
+        });
+        self.proving = Some(Proving {
+            block: block_number,
+            txs,
+            states,
+            inputs_time,
+            started,
+            task,
+        });
+        if !self.pipelined {
+            self.await_proving().await;
+        }
+        Some(block_number)
+    }
+
+    /// Waits for the block being proven, if any, and queues its proofs.
+    async fn await_proving(&mut self) {
+        if self.proving.is_some() {
+            let proofs = proven(&mut self.proving).await;
+            let proving = self.proving.take().unwrap();
+            self.finish_proving(proving, proofs).await;
+        }
+    }
+
+    /// Queues the proofs of the block `proving` made and submits them.
+    async fn finish_proving(&mut self, proving: Proving, proofs: Vec<ProofResult>) {
+        let Proving {
+            block: block_number,
+            txs,
+            states,
+            inputs_time,
+            started,
+            ..
+        } = proving;
+        let proving_time = started.elapsed();
+        let mut timings = BlockTimings {
+            block: block_number,
+            txs: txs.len(),
+            inputs_ms: inputs_time.as_millis() as u64,
+            proving_ms: proving_time.as_millis() as u64,
+            ..Default::default()
+        };
+        let mut proven = 0;
+        let mut persisted = true;
+        let mut calldata_saved = 0;
+        // Each proof builds on the root the previous one leaves, so none
+        // after a failed one can land.
+        for proof in proofs {
+            let (proof, proof_timings) = match proof {
+                Ok(proof) => proof,
+                Err(e) => {
+                    error!(block = block_number, error = %e, "Could not generate a proof");
+                    break;
+                }
+            };
+            timings.witness_ms += proof_timings.witness.as_millis() as u64;
+            timings.prove_ms += proof_timings.proof.as_millis() as u64;
+            calldata_saved += calldata::saved_bytes(&fusion::SubmitBlockCall {
+                l_2_block: [proof.clone()],
+            });
+            if let Err(e) = self
+                .submissions
+                .push(block_number, proof, block_timestamp())
+            {
+                error!(block = block_number, error = %e, "Could not persist a proof");
+                persisted = false;
+            }
+            proven += 1;
+        }
+        self.shared.metrics.calldata_saved(calldata_saved);
+        let unproven = txs.len() - proven;
+        if unproven > 0 {
+            self.requeue_unproven(block_number, &txs, &states, proven);
+        }
+        // Until its proofs are persisted, the transactions of the block
+        // are replayed from the mempool after a restart.
+        if persisted {
+            self.persist_mempool();
+        }
+
+        let submit_start = Instant::now();
+        let submitted = self.submit_queued().await;
+        timings.submit_ms = submit_start.elapsed().as_millis() as u64;
+        info!(
+            block = block_number,
+            inputs_ms = timings.inputs_ms,
+            witness_ms = timings.witness_ms,
+            prove_ms = timings.prove_ms,
+            proving_ms = timings.proving_ms,
+            submit_ms = timings.submit_ms,
+            "Block done"
+        );
+        self.shared.record_timings(timings);
+        if unproven == 0 {
+            self.tune(txs.len(), proving_time);
+        }
+        if unproven == 0 && self.submissions.is_empty() {
+            if let Some(last) = submitted.last().filter(|last| last.block == block_number) {
+                self.attest(last.l1_tx_hash);
+            }
+        }
+
+        if unproven > 0 {
+            self.set_health(
+                HealthStatus::Degraded,
+                Some(format!(
+                    "Could not prove the transaction at index {proven} of block {block_number}, \
+                     {unproven} transaction(s) went back to the mempool"
+                )),
+            );
+        }
+    }
+
+    /// Puts the transactions of `block` from `proven` on, which have no
+    /// proof, back at the front of the mempool. The block keeps the
+    /// others, or is dropped without any, and the next block builds on
+    /// the state L1 holds once their proofs land.
+    fn requeue_unproven(&mut self, block: u64, txs: &[SignedTx], states: &[State], proven: usize) {
+        let received_at = block_timestamp();
+        let requeued: Vec<_> = txs[proven..]
+            .iter()
+            .map(|tx| PendingTx {
+                tx: tx.clone(),
+                received_at,
+                commitment: None,
+            })
+            .collect();
+        warn!(
+            block,
+            txs = requeued.len(),
+            "Unproven transactions back in the mempool"
+        );
+        self.mempool.lock().unwrap().requeue(requeued);
+        self.state = states[proven].clone();
+        {
+            let mut blocks = self.shared.blocks.lock().unwrap();
+            if let Some(i) = blocks
+                .iter()
+                .rposition(|recorded| recorded.header.number == block)
+            {
+                if proven == 0 {
+                    blocks.remove(i);
+                } else {
+                    let recorded = &mut blocks[i];
+                    recorded.txs.truncate(proven);
+                    recorded.tx_hashes.truncate(proven);
+                    recorded.header.tx_root = tx_root(&recorded.tx_hashes);
+                    recorded.header.state_root = self.state.root();
+                }
+            }
+        }
+        if proven == 0 {
+            self.block_number = block - 1;
+        }
+
+        let (txs, states) = (&txs[..proven], &states[..=proven]);
+        let mut audit = self.shared.audit.lock().unwrap();
+        if let Err(e) = audit
+            .truncate_from(block)
+            .and_then(|()| audit.append(&audit::records(block, txs, states)))
+        {
+            error!(block, error = %e, "Could not write the audit log");
+        }
+        if self.l1_contract.is_some() && proven > 0 {
+            let witness = witness::batch_witness(block, txs, states);
+            if let Err(e) = witness::write(Path::new(&self.config.database_path), &witness) {
+                error!(block, error = %e, "Could not write the witness");
+            }
+        }
+    }
+
+    /// Signs and publishes an attestation of the latest block, once the
+    /// L1 transaction `l1_tx_hash` moved the L1 root to its state root.
+    fn attest(&self, l1_tx_hash: H256) {
+        let Some(header) = self
+            .shared
+            .blocks
+            .lock()
+            .unwrap()
+            .last()
+            .map(|block| block.header.clone())
+        else {
+            return;
+        };
+        let mut attestation = Attestation {
+            block_number: header.number,
+            block_hash: header.hash(),
+            state_root: header.state_root,
+            l1_tx_hash,
+            signature: String::new(),
+        };
+        match fusion_wallet::sign_attestation(&attestation, self.attestation_key.clone()) {
+            Ok(signature) => {
+                attestation.signature = signature.to_string();
+                self.shared.attestations.lock().unwrap().push(attestation);
+            }
+            Err(e) => error!(block = header.number, error = %e, "Could not sign the attestation"),
+        }
+    }
+
+    /// Catches up with L1 and snapshots the state replayed, see
+    /// `snapshot`. Unless `forced`, only once `snapshot_interval` L1
+    /// blocks passed since the last snapshot.
+    async fn snapshot(&mut self, forced: bool) -> Result<Option<u64>, String> {
+        let (Some(l1_contract), Some(sync)) = (&self.l1_contract, &mut self.l1_sync) else {
+            return Err("Dev mode does not replay L1, there is no state to snapshot".to_string());
+        };
+        let client = l1_contract.client();
+        let latest = client
+            .get_block_number()
+            .await
+            .map_err(|e| format!("Could not read the latest L1 block: {e}"))?
+            .as_u64();
+        if !forced && !sync.snapshot_due(latest, self.config.snapshot_interval) {
+            return Ok(None);
+        }
+        sync.catch_up(client.as_ref(), l1_contract.address(), latest)
+            .await?;
+        let last_batch = self.shared.latest_batch().map(|batch| batch.number);
+        let l1_block = sync.snapshot(last_batch)?;
+        info!(l1_block, ?last_batch, "Took a snapshot of the state");
+        Ok(Some(l1_block))
+    }
+
+    async fn snapshot_if_due(&mut self) {
+        if self.l1_sync.is_none() || self.config.snapshot_interval == 0 {
+            return;
+        }
+        if let Err(e) = self.snapshot(false).await {
+            warn!(error = %e, "Could not take a snapshot of the state");
+        }
+    }
+
+    /// Replays the blocks L1 accepted from another submitter, and
+    /// continues from the root L1 holds.
+    async fn follow_l1(&mut self, l1_root: U256) -> Result<(), String> {
+        let (Some(l1_contract), Some(sync)) = (&self.l1_contract, &mut self.l1_sync) else {
+            return Ok(());
+        };
+        let client = l1_contract.client();
+        let latest = client
+            .get_block_number()
+            .await
+            .map_err(|e| format!("Could not read the latest L1 block: {e}"))?;
+        sync.catch_up(client.as_ref(), l1_contract.address(), latest.as_u64())
+            .await?;
+        if sync.state().root() != l1_root {
+            return Err(format!(
+                "L1 is at root {l1_root}, replaying it reached {}",
+                sync.state().root()
+            ));
+        }
+        warn!(root = %l1_root, "L1 moved without this sequencer, continuing from there");
+        self.state = sync.state().clone();
+        Ok(())
+    }
+
+    /// Tells the waiting RPC requests that `block` was built, or
+    /// confirmed in `l1_tx_hash`.
+    fn publish_block(&self, block: u64, l1_tx_hash: Option<H256>) {
+        let state_root = self
+            .shared
+            .blocks
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|recorded| recorded.header.number == block)
+            .map(|recorded| recorded.header.state_root);
+        if let Some(state_root) = state_root {
+            self.shared.events.publish(Event::Block(BlockEvent {
+                block,
+                state_root,
+                l1_tx_hash,
+            }));
+        }
+    }
+
+    fn record_block(&self, txs: &[SignedTx], timestamp: u64, l1_root: Option<U256>) {
+        let mut blocks = self.shared.blocks.lock().unwrap();
+        let tx_hashes: Vec<_> = txs.iter().map(|tx| hash_tx(&tx.tx)).collect();
+        let header = BlockHeader {
+            number: self.block_number,
+            parent_hash: blocks
+                .last()
+                .map_or(U256::zero(), |block| block.header.hash()),
+            state_root: self.state.root(),
+            tx_root: tx_root(&tx_hashes),
+            timestamp,
+            l1_root,
+            l1_tx_hashes: vec![],
+        };
+        blocks.push(Block {
+            header,
+            txs: txs.to_vec(),
+            tx_hashes,
+        });
+        update_account_stats(
+            &mut self.shared.account_stats.lock().unwrap(),
+            txs,
+            self.block_number,
+        );
+    }
+
+    /// Drops the mempool and all blocks, and goes back to the genesis
+    /// state. Only allowed in dev mode, where nothing was sent to L1.
+    fn reset(&mut self) -> Result<(), String> {
+        if !self.config.dev {
+            return Err("admin_dev_reset is only available in dev mode".to_string());
+        }
+
+        info!("Resetting the chain back to genesis");
+        self.mempool
+            .lock()
+            .unwrap()
+            .drain_batch(usize::MAX, &mut |_| true);
+        self.persist_mempool();
+        self.shared.blocks.lock().unwrap().clear();
+        self.shared.account_stats.lock().unwrap().clear();
+        self.shared.timings.lock().unwrap().clear();
+        self.shared.audit.lock().unwrap().truncate_from(0)?;
+        self.state = self.genesis.clone();
+        self.block_number = 0;
+        self.faucet_requests.clear();
+        self.commitments.clear();
+        self.next_commitment = 0;
+        self.rejected.clear();
+        self.receipts.clear();
+
+        Ok(())
+    }
+
+    /// Checks the L1 contract against the bindings and the circuit. Block
+    /// building stops while it does not match, and resumes once a check
+    /// succeeds.
+    async fn check_contract(&mut self) -> Result<u64, String> {
+        let Some(l1_contract) = &self.l1_contract else {
+            return Err("There is no L1 contract in dev mode".to_string());
+        };
+        let client = l1_contract.client();
+        let address = l1_contract.address();
+        let checked = match contract_version(client.as_ref(), address).await {
+            Ok(version) => match self.circuit_params {
+                Some(params) => check_circuit_params(client.as_ref(), address, params)
+                    .await
+                    .map(|()| version),
+                None => Ok(version),
+            },
+            Err(e) => Err(e),
+        };
+        let checked = match checked {
+            Ok(version) if self.config.compressed_calldata => {
+                check_compressed_calldata(client.as_ref(), address)
+                    .await
+                    .map(|()| version)
+            }
+            checked => checked,
+        };
+        match checked {
+            Ok(version) => {
+                self.shared.info.lock().unwrap().contract_version = Some(version);
+                if self.contract_error.take().is_some() {
+                    info!("L1 contract matches again, resuming");
+                    self.set_health(HealthStatus::Ok, None);
+                }
+                Ok(version)
+            }
+            Err(e) => {
+                error!(reason = %e, "ALERT: the L1 contract does not match, stopping block production");
+                self.contract_error = Some(e.clone());
+                self.set_health(HealthStatus::Halted, Some(e.clone()));
+                Err(e)
+            }
+        }
+    }
+
+    fn commit(&mut self, commitment: U256) -> Result<u64, SubmitError> {
+        if self.commitments.contains_key(&commitment) {
+            return Err(SubmitError::Invalid(format!(
+                "Commitment {commitment} is already pending"
+            )));
+        }
+        let max = self.config.max_pending_commitments;
+        if max > 0 && self.commitments.len() >= max {
+            return Err(SubmitError::CommitmentsFull { max });
+        }
+        let block = self.block_number + 1;
+        self.commitments.insert(
+            commitment,
+            Commitment {
+                sequence: self.next_commitment,
+                block,
+            },
+        );
+        self.next_commitment += 1;
+        Ok(block)
+    }
+
+    /// Consumes the pending commitment that `tx` and `salt` open.
+    fn reveal(&mut self, tx: &SignedTx, salt: U256) -> Result<Commitment, String> {
+        self.commitments
+            .remove(&tx_commitment(&tx.tx, salt))
+            .ok_or_else(|| {
+                format!(
+                    "Transaction does not match any pending commitment, they expire after {} blocks",
+                    self.config.reveal_window
+                )
+            })
+    }
+
+    /// Builds and signs a transfer from the dev faucet account.
+    /// Its nonce and available balance take into account the faucet
+    /// transactions still in the mempool.
+    fn faucet_tx(&mut self, to: U256, amount: U256) -> Result<SignedTx, String> {
+        if !self.config.dev {
+            return Err("dev_faucet is only available in dev mode".to_string());
+        }
+
+        let cooldown = Duration::from_secs(self.config.faucet_cooldown);
+        if let Some(last) = self.faucet_requests.get(&to) {
+            if last.elapsed() < cooldown {
+                return Err(format!(
+                    "Faucet already sent funds to {to}, try again in {}s",
+                    (cooldown - last.elapsed()).as_secs()
+                ));
+            }
+        }
+
+        let faucet_pk = dev::faucet_public_key();
+        let faucet_sender = faucet_pk.to_u256();
+        let faucet = self.state.get(&faucet_pk.address());
+        let (pending_count, pending_value) = self
+            .mempool
+            .lock()
+            .unwrap()
+            .iter_pending()
+            .filter(|pending| pending.tx.tx.sender == faucet_sender)
+            .fold((0u64, U256::zero()), |(count, value), pending| {
+                (count + 1, value + pending.tx.tx.value)
+            });
+
+        let available = faucet.balance.saturating_sub(pending_value);
+        if available < amount {
+            return Err(format!(
+                "Faucet is drained: {available} available, {amount} requested"
+            ));
+        }
+
+        let tx = Tx {
+            kind: TxKind::Transfer,
+            sender: faucet_sender,
+            to,
+            nonce: faucet.nonce + pending_count + 1,
+            value: amount,
+            not_before: None,
+            not_after: None,
+            chain_id: Some(self.config.chain_id),
+            token: NATIVE_TOKEN,
+        };
+        let signature = fusion_wallet::sign(&tx, dev::FAUCET_PRIVATE_KEY.to_string())
+            .map_err(|e| e.to_string())?;
+        self.faucet_requests.insert(to, Instant::now());
+
+        info!(%amount, %to, "Faucet sending funds");
+        Ok(SignedTx {
+            tx,
+            signature: signature.to_string(),
+        })
+    }
+}
+
+pub async fn run_sequencer(config: &Config, rx: mpsc::Receiver<Trigger>) -> anyhow::Result<()> {
+    Sequencer::new(config).await?.run(rx).await
+}
+
+/// The state the chain starts from.
+pub fn genesis_state(config: &Config) -> State {
+    match config.dev {
+        true => dev::genesis_state(config),
+        false => State::default(),
+    }
+}
+
+/// `genesis_state` with the accounts of `genesis_path` in dev mode.
+pub fn load_genesis_state(config: &Config) -> Result<State, String> {
+    match config.dev {
+        true => dev::load_genesis(config),
+        false => Ok(genesis_state(config)),
+    }
+}
+
+/// The system time, in seconds.
+pub fn block_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock is before the unix epoch")
+        .as_secs()
+}
+
+/// The timestamp of a block built at system time `now`, at least one
+/// second after its parent so that timestamps never go back, and at
+/// most `max`. This is the only clock the sequencer uses to validate
+/// transactions.
+fn assign_timestamp(now: u64, parent: Option<u64>, max: Option<u64>) -> Result<u64, String> {
+    let min = parent.map_or(0, |parent| parent + 1);
+    match max {
+        Some(max) if min > max => Err(format!(
+            "Block timestamp {min} would be after {max}, max_l1_drift seconds past the latest L1 block"
+        )),
+        Some(max) => Ok(now.clamp(min, max)),
+        None => Ok(now.max(min)),
+    }
+}
+
+/// Timestamp of the latest L1 block.
+async fn l1_timestamp(l1_contract: &L1Contract) -> Result<u64, String> {
+    let block = l1_contract
+        .client()
+        .get_block(BlockNumber::Latest)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("The L1 node has no latest block")?;
+    Ok(block.timestamp.as_u64())
+}
+
+/// What each of `recipients`, a token and the leaf it credits, can
+/// still claim of its deposits on L1, minus the `claims` of proofs that
+/// have not landed yet. Leaves are distinct per token, so the result is
+/// keyed by leaf.
+async fn l1_deposits(
+    l1_contract: &L1Contract,
+    recipients: Vec<(Address, U256)>,
+    claims: HashMap<U256, U256>,
+) -> Result<HashMap<U256, U256>, String> {
+    let mut deposits = HashMap::new();
+    for (token, recipient) in recipients {
+        if deposits.contains_key(&recipient) {
+            continue;
+        }
+        let deposited = match token == NATIVE_TOKEN {
+            true => l1_contract.deposits(recipient).call().await,
+            false => l1_contract.token_deposits(token, recipient).call().await,
+        }
+        .map_err(|e| e.to_string())?;
+        let claimed = claims.get(&recipient).copied().unwrap_or_default();
+        deposits.insert(recipient, deposited.saturating_sub(claimed));
+    }
+    Ok(deposits)
+}
+
+/// Drains the mempool for block `block`, except for transactions whose
+/// validity window has not started yet or whose commitment appears in
+/// that same block, and those past the first `max` eligible ones, which
+/// stay queued in order for a later block.
+fn take_eligible(mempool: &MemPool, timestamp: u64, block: u64, max: usize) -> Vec<PendingTx> {
+    mempool
+        .lock()
+        .unwrap()
+        .drain_batch(max, &mut |pending| !is_waiting(pending, timestamp, block))
+}
+
+/// Splits `pending` into the transactions that stay queued and the
+/// first `max` eligible for block `block`, keeping their order.
+fn split_eligible(
+    pending: impl IntoIterator<Item = PendingTx>,
+    timestamp: u64,
+    block: u64,
+    max: usize,
+) -> (Vec<PendingTx>, Vec<PendingTx>) {
+    let (mut waiting, mut eligible) = (vec![], vec![]);
+    for pending in pending {
+        if eligible.len() < max && !is_waiting(&pending, timestamp, block) {
+            eligible.push(pending);
+        } else {
+            waiting.push(pending);
+        }
+    }
+    (waiting, eligible)
+}
+
+/// Whether a transaction stays queued rather than being considered for
+/// block `block`.
+fn is_waiting(pending: &PendingTx, timestamp: u64, block: u64) -> bool {
+    pending.tx.tx.validity_at(timestamp) == Validity::Premature
+        || pending
+            .commitment
+            .is_some_and(|commitment| commitment.block >= block)
+}
+
+/// The transactions of a block, in order.
+struct Selection {
+    txs: Vec<SignedTx>,
+    /// The state before the block, then after each transaction.
+    states: Vec<State>,
+    /// Eligible transactions left out, which are dropped.
+    excluded: Vec<(SignedTx, Exclusion)>,
+}
+
+/// Picks the transactions of block `block` among `eligible` and orders
+/// them. Revealed transactions come first, in the order of their
+/// commitments. Deposit claims must fit in the L1 `deposits` left to
+/// each recipient, when given.
+fn select_txs(
+    state: &State,
+    eligible: Vec<PendingTx>,
+    timestamp: u64,
+    policy: OrderingPolicy,
+    parent_hash: U256,
+    block: u64,
+    deposits: Option<&HashMap<U256, U256>>,
+) -> Selection {
+    let mut excluded = vec![];
+    let (mut revealed, txs): (Vec<_>, Vec<_>) = eligible
+        .into_iter()
+        .filter(|pending| {
+            let exclusion = match pending.tx.tx.validity_at(timestamp) {
+                Validity::Expired => Exclusion::Expired,
+                _ => match validate_tx(state, &pending.tx) {
+                    Ok(()) => return true,
+                    Err(e) => Exclusion::Invalid(e.to_string()),
+                },
+            };
+            excluded.push((pending.tx.clone(), exclusion));
+            false
+        })
+        .partition(|pending| pending.commitment.is_some());
+    revealed.sort_by_key(|pending| pending.commitment.unwrap().sequence);
+    let txs = ordering::order_txs(
+        policy,
+        revealed.into_iter().map(|pending| pending.tx).collect(),
+        txs.into_iter().map(|pending| pending.tx).collect(),
+        ordering::block_seed(parent_hash, block),
+    );
+
+    // Transactions were checked against the state before the block,
+    // an earlier one of the same block may have spent the funds or
+    // used the nonce since.
+    let mut states = vec![state.clone()];
+    let mut claimed = HashMap::new();
+    let txs = txs
+        .into_iter()
+        .filter(|tx| {
+            let latest = states.last().unwrap();
+            match check_tx_state(latest, &tx.tx)
+                .and_then(|()| apply_tx(latest.clone(), &tx.tx))
+                .and_then(|next| claim_deposit(deposits, &mut claimed, &tx.tx).map(|()| next))
+            {
+                Ok(next) => {
+                    states.push(next);
+                    true
+                }
+                Err(e) => {
+                    excluded.push((tx.clone(), Exclusion::Revalidation(e.to_string())));
+                    false
+                }
+            }
+        })
+        .collect();
+    Selection {
+        txs,
+        states,
+        excluded,
+    }
+}
+
+/// Counts a deposit claim against what its recipient deposited on L1,
+/// so that the claims of a block never add up to more than the contract
+/// lets through.
+fn claim_deposit(
+    deposits: Option<&HashMap<U256, U256>>,
+    claimed: &mut HashMap<U256, U256>,
+    tx: &Tx,
+) -> anyhow::Result<()> {
+    let Some(deposits) = deposits.filter(|_| matches!(tx.kind, TxKind::Deposit)) else {
+        return Ok(());
+    };
+    let recipient = tx.sender_account();
+    let claimed = claimed.entry(recipient).or_default();
+    let available = deposits
+        .get(&recipient)
+        .copied()
+        .unwrap_or_default()
+        .saturating_sub(*claimed);
+    if tx.value > available {
+        return Err(anyhow::anyhow!(
+            "Deposit not available on L1, {available} left to claim"
+        ));
+    }
+    *claimed += tx.value;
+    Ok(())
+}
+
+/// Number of senders listed in `MempoolStats::top_senders`.
+const TOP_SENDERS: usize = 5;
+
+/// Computes mempool statistics from a snapshot, so the mempool lock is
+/// only held while copying it. Statuses are checked against `state`
+/// the same way `build_block` does, minus the signature.
+fn mempool_stats(state: &State, pending: &[PendingTx], now: u64) -> MempoolStats {
+    let mut stats = MempoolStats {
+        pending: pending.len(),
+        ..Default::default()
+    };
+    let mut per_sender: HashMap<U256, usize> = HashMap::new();
+    for PendingTx { tx, .. } in pending {
+        let tx = &tx.tx;
+        *per_sender.entry(tx.sender).or_default() += 1;
+        stats.total_value += tx.value;
+
+        let account = state.get(&tx.sender_account());
+        match tx.validity_at(now) {
+            Validity::Premature => stats.premature += 1,
+            Validity::Expired => stats.expired += 1,
+            Validity::Valid if account.nonce >= tx.nonce => stats.stale_nonce += 1,
+            Validity::Valid
+                if matches!(tx.kind, TxKind::Transfer | TxKind::Withdraw)
+                    && account.balance < tx.value =>
+            {
+                stats.unfundable += 1
+            }
+            Validity::Valid => stats.ready += 1,
+        }
+    }
+
+    stats.distinct_senders = per_sender.len();
+    let mut top_senders: Vec<_> = per_sender.into_iter().collect();
+    top_senders.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    top_senders.truncate(TOP_SENDERS);
+    stats.top_senders = top_senders;
+
+    let mut ages: Vec<u64> = pending
+        .iter()
+        .map(|pending| now.saturating_sub(pending.received_at))
+        .collect();
+    ages.sort_unstable();
+    let percentile = |p: usize| match ages.len() {
+        0 => 0,
+        n => ages[(n * p).div_ceil(100).max(1) - 1],
+    };
+    stats.age_p50 = percentile(50);
+    stats.age_p90 = percentile(90);
+    stats.oldest_age = ages.last().copied().unwrap_or(0);
+
+    stats
+}
+
+/// The accounts of `addresses` after the transactions of `pending`
+/// that are valid in the order they arrived, applied on top of `state`.
+/// Signatures were checked by `admit` when they were queued, and validity
+/// windows are ignored.
+fn project_accounts(state: &State, pending: &[PendingTx], addresses: &[U256]) -> Vec<Account> {
+    let mut accounts: HashMap<U256, Account> = HashMap::new();
+    let get = |accounts: &HashMap<U256, Account>, address: &U256| {
+        accounts
+            .get(address)
+            .cloned()
+            .unwrap_or_else(|| state.get(address))
+    };
+    for pending in pending {
+        let tx = &pending.tx.tx;
+        let (sender, to) = (tx.sender_account(), tx.to_account());
+        let account_sender = get(&accounts, &sender);
+        if check_tx_account(&account_sender, tx, true).is_err() {
+            continue;
+        }
+        let Ok((account_sender, account_to)) = transition(
+            &tx.kind,
+            account_sender,
+            get(&accounts, &to),
+            tx.nonce,
+            tx.value,
+        ) else {
+            continue;
+        };
+        accounts.insert(sender, account_sender);
+        accounts.insert(to, account_to);
+    }
+    addresses
+        .iter()
+        .map(|address| get(&accounts, address))
+        .collect()
+}
+
+/// Marks the account `key` active in block `block_number` and returns
+/// its statistics.
+fn active_account(
+    stats: &mut HashMap<U256, AccountStats>,
+    key: U256,
+    block_number: u64,
+) -> &mut AccountStats {
+    let account = stats.entry(key).or_default();
+    account.first_active_block.get_or_insert(block_number);
+    account.last_active_block = Some(block_number);
+    account
+}
+
+pub(crate) fn update_account_stats(
+    stats: &mut HashMap<U256, AccountStats>,
+    txs: &[SignedTx],
+    block_number: u64,
+) {
+    for SignedTx { tx, .. } in txs {
+        let sender = active_account(stats, tx.sender, block_number);
+        match tx.kind {
+            TxKind::Transfer | TxKind::Withdraw => {
+                sender.sent_count += 1;
+                sender.total_sent = sender.total_sent.saturating_add(tx.value);
+            }
+            TxKind::Deposit => {
+                sender.received_count += 1;
+                sender.total_received = sender.total_received.saturating_add(tx.value);
+            }
+        }
+        if matches!(tx.kind, TxKind::Transfer) {
+            let to = active_account(stats, tx.to, block_number);
+            to.received_count += 1;
+            to.total_received = to.total_received.saturating_add(tx.value);
+        }
+    }
+}
+
+/// Replays the blocks L1 accepted since the last run, and refuses to
+/// start unless the replay reaches the root the contract holds.
+async fn init_sync(config: &Config, l1_contract: &L1Contract) -> anyhow::Result<L1Sync> {
+    let mut sync = L1Sync::open(
+        Path::new(&config.database_path),
+        genesis_state(config),
+        config.l1_sync_start_block,
+        config.l1_log_range,
+    )
+    .map_err(|e| anyhow::anyhow!(e))?;
+    let client = l1_contract.client();
+    let latest = client.get_block_number().await?.as_u64();
+    sync.catch_up(client.as_ref(), l1_contract.address(), latest)
+        .await
+        .map_err(|e| anyhow::anyhow!(e))?;
+    let root = l1_contract.root().block(latest).call().await?;
+    if sync.state().root() != root {
+        anyhow::bail!(
+            "Refusing to sequence: replaying L1 up to block {latest} reached root {}, the contract holds {root}",
+            sync.state().root()
+        );
+    }
+    info!(l1_block = latest, %root, "Replayed L1");
+    Ok(sync)
+}
+
+/// Connects to the L1 contract, refusing to if it does not match the
+/// bindings or the circuit. Returns it with its version and the circuit
+/// params.
+async fn init_l1(config: &Config, metrics: Metrics) -> anyhow::Result<(L1Contract, u64, H256)> {
+    let node = Arc::new(Node::from_config(config, metrics).await?);
+
+    let version = contract_version(node.http_client.as_ref(), config.fusion_l1_contract)
+        .await
+        .map_err(|e| anyhow::anyhow!("Refusing to sequence: {e}"))?;
+    let circuit_params = params::CircuitParams::from_proving_key(&config.proving_key_path)
+        .map_err(|e| anyhow::anyhow!(e))?
+        .id();
+    check_circuit_params(
+        node.http_client.as_ref(),
+        config.fusion_l1_contract,
+        circuit_params,
+    )
+    .await
+    .map_err(|e| anyhow::anyhow!("Refusing to sequence: {e}"))?;
+    if config.compressed_calldata {
+        check_compressed_calldata(node.http_client.as_ref(), config.fusion_l1_contract)
+            .await
+            .map_err(|e| anyhow::anyhow!("Refusing to sequence: {e}"))?;
+    }
+    let l1_contract = fusion::Fusion::new(config.fusion_l1_contract, node.http_client.clone());
+
+    Ok((l1_contract, version, circuit_params))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashSet;
+
+    use anvil::{spawn, NodeConfig, NodeHandle};
+    use ethers::abi::AbiDecode;
+    use ethers::prelude::*;
+    use ethers::providers::Middleware;
+    use ethers::types;
+
+    use tokio::sync::{mpsc, oneshot};
+
+    use fusion_types::ToU256;
+    use fusion_wallet;
+
+    use crate::deploy::dev_l1_contract;
+
+    #[test]
+    fn deposit_claims_fit_l1_deposits() {
+        let (sk_1, pk_1) = fusion_wallet::new_key_pair();
+        let (sk_2, pk_2) = fusion_wallet::new_key_pair();
+        let claim = |sk: &str, pk: &PublicKey, nonce: u64, value: u64| {
+            let tx = fusion_api::Tx {
+                kind: TxKind::Deposit,
+                sender: pk.clone().to_u256(),
+                to: 0.into(),
+                nonce: nonce.into(),
+                value: value.into(),
+                not_before: None,
+                not_after: None,
+                chain_id: None,
+                token: NATIVE_TOKEN,
+            };
+            let signature = fusion_wallet::sign(&tx, sk.to_string()).unwrap();
+            PendingTx {
+                tx: SignedTx {
+                    tx,
+                    signature: signature.to_string(),
+                },
+                received_at: 0,
+                commitment: None,
+            }
+        };
+        let eligible = vec![
+            claim(&sk_1.to_string(), &pk_1, 1, 600),
+            claim(&sk_1.to_string(), &pk_1, 2, 600),
+            claim(&sk_2.to_string(), &pk_2, 1, 1),
+        ];
+        // 1000 deposited for the first account, none for the second.
+        let deposits = HashMap::from([(pk_1.address(), U256::from(1000))]);
+
+        let selection = select_txs(
+            &State::default(),
+            eligible.clone(),
+            0,
+            OrderingPolicy::Fifo,
+            0.into(),
+            1,
+            Some(&deposits),
+        );
+        assert_eq!(selection.txs.len(), 1);
+        assert_eq!(selection.txs[0].tx.nonce, 1.into());
+        let state = selection.states.last().unwrap();
+        assert_eq!(state.get(&pk_1.address()).balance, 600.into());
+        assert_eq!(selection.excluded.len(), 2);
+        assert!(selection.excluded.iter().all(|(_, exclusion)| matches!(
+            exclusion,
+            Exclusion::Revalidation(e) if e.starts_with("Deposit not available on L1")
+        )));
+
+        // Without L1 the claims are not checked.
+        let selection = select_txs(
+            &State::default(),
+            eligible,
+            0,
+            OrderingPolicy::Fifo,
+            0.into(),
+            1,
+            None,
+        );
+        assert_eq!(selection.txs.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn dev_reset() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.min_tx_block = 2;
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
+
+        let (sk_1, pk_1) = fusion_wallet::new_key_pair();
+        let tx = fusion_api::Tx {
+            kind: TxKind::Deposit,
+            sender: pk_1.clone().to_u256(),
+            to: 0.into(),
+            nonce: 1.into(),
+            value: 1000.into(),
+            not_before: None,
+            not_after: None,
+            chain_id: None,
+            token: NATIVE_TOKEN,
+        };
+        let sig = fusion_wallet::sign(&tx, sk_1.to_string()).unwrap();
+        sequencer
+            .handle(Trigger::Tx(SignedTx {
+                tx,
+                signature: sig.to_string(),
+            }))
+            .await;
+
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer.handle(Trigger::Flush(reply_sx)).await;
+        assert_eq!(reply_rx.await.unwrap(), Ok(FlushResult::Block(1)));
+        assert_eq!(sequencer.state().get(&pk_1.address()).balance, 1000.into());
+
+        // Stays in the mempool since `min_tx_block` is not reached.
+        let tx = fusion_api::Tx {
+            kind: TxKind::Deposit,
+            sender: pk_1.clone().to_u256(),
+            to: 0.into(),
+            nonce: 2.into(),
+            value: 1000.into(),
+            not_before: None,
+            not_after: None,
+            chain_id: None,
+            token: NATIVE_TOKEN,
+        };
+        let sig = fusion_wallet::sign(&tx, sk_1.to_string()).unwrap();
+        sequencer
+            .handle(Trigger::Tx(SignedTx {
+                tx,
+                signature: sig.to_string(),
+            }))
+            .await;
+
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer.handle(Trigger::Reset(reply_sx)).await;
+        assert_eq!(reply_rx.await.unwrap(), Ok(()));
+
+        assert_eq!(sequencer.block_number(), 0);
+        assert_eq!(sequencer.state().root(), genesis_state(&config).root());
+        assert_eq!(sequencer.state().get(&pk_1.address()).balance, 0.into());
+
+        // The pending transaction is gone.
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer.handle(Trigger::Flush(reply_sx)).await;
+        assert_eq!(reply_rx.await.unwrap(), Ok(FlushResult::NothingToBatch));
+    }
+
+    #[tokio::test]
+    async fn faucet_then_transfer() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.min_tx_block = 1;
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
+
+        let (sk_1, pk_1) = fusion_wallet::new_key_pair();
+        let (_sk_2, pk_2) = fusion_wallet::new_key_pair();
+
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer
+            .handle(Trigger::Faucet {
+                to: pk_1.to_u256(),
+                amount: 1000.into(),
+                reply: reply_sx,
+            })
+            .await;
+        assert!(reply_rx.await.unwrap().is_ok());
+        assert_eq!(sequencer.block_number(), 1);
+
+        // Rate limited.
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer
+            .handle(Trigger::Faucet {
+                to: pk_1.to_u256(),
+                amount: 1000.into(),
+                reply: reply_sx,
+            })
+            .await;
+        assert!(reply_rx.await.unwrap().is_err());
+
+        let tx = fusion_api::Tx {
+            kind: TxKind::Transfer,
+            sender: pk_1.to_u256(),
+            to: pk_2.to_u256(),
+            nonce: 1.into(),
+            value: 400.into(),
+            not_before: None,
+            not_after: None,
+            chain_id: None,
+            token: NATIVE_TOKEN,
+        };
+        let sig = fusion_wallet::sign(&tx, sk_1.to_string()).unwrap();
+        sequencer
+            .handle(Trigger::Tx(SignedTx {
+                tx,
+                signature: sig.to_string(),
+            }))
+            .await;
+        assert_eq!(sequencer.block_number(), 2);
+
+        assert_eq!(sequencer.state().get(&pk_1.address()).balance, 600.into());
+        assert_eq!(sequencer.state().get(&pk_2.address()).balance, 400.into());
+        assert_eq!(
+            sequencer
+                .state()
+                .get(&dev::faucet_public_key().address())
+                .balance,
+            dev::faucet_balance() - U256::from(1000)
+        );
+    }
+
+    #[tokio::test]
+    async fn blocks_commit_to_their_transactions() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.min_tx_block = 2;
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
+
+        let mut tx_hashes = vec![];
+        for _ in 0..3 {
+            let (_sk, pk) = fusion_wallet::new_key_pair();
+            let (reply_sx, reply_rx) = oneshot::channel();
+            sequencer
+                .handle(Trigger::Faucet {
+                    to: pk.to_u256(),
+                    amount: 10.into(),
+                    reply: reply_sx,
+                })
+                .await;
+            tx_hashes.push(reply_rx.await.unwrap().unwrap());
+        }
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer.handle(Trigger::Flush(reply_sx)).await;
+        assert_eq!(reply_rx.await.unwrap(), Ok(FlushResult::Block(2)));
+
+        let blocks = sequencer.shared().blocks.lock().unwrap().clone();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].header.parent_hash, U256::zero());
+        assert_eq!(blocks[1].header.parent_hash, blocks[0].header.hash());
+        assert_eq!(blocks[1].header.state_root, sequencer.state().root());
+
+        let proof = blocks[1].inclusion_proof(&tx_hashes[2]).unwrap();
+        assert_eq!(proof.header.number, 2);
+        assert!(verify_inclusion(&proof, &blocks[1].header.hash()));
+        assert!(!verify_inclusion(&proof, &blocks[0].header.hash()));
+        assert!(blocks[0].inclusion_proof(&tx_hashes[2]).is_none());
+    }
+
+    #[tokio::test]
+    async fn sync_headers() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.min_tx_block = 1;
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
+
+        for _ in 0..5 {
+            let (_sk, pk) = fusion_wallet::new_key_pair();
+            let (reply_sx, reply_rx) = oneshot::channel();
+            sequencer
+                .handle(Trigger::Faucet {
+                    to: pk.to_u256(),
+                    amount: 10.into(),
+                    reply: reply_sx,
+                })
+                .await;
+            assert!(reply_rx.await.unwrap().is_ok());
+        }
+
+        let shared = sequencer.shared();
+        let headers = shared.headers(1, 5).unwrap();
+        assert_eq!(headers.len(), 5);
+        assert!(verify_header_chain(&headers));
+        assert!(headers.iter().all(|header| header.l1_root.is_none()));
+        assert_eq!(headers[4].state_root, sequencer.state().root());
+
+        let compact = encode_headers(&headers);
+        assert_eq!(decode_headers(&compact).unwrap(), headers);
+
+        // Following the chain from a known header.
+        let tail = shared.headers(3, 5).unwrap();
+        assert_eq!(tail[0].parent_hash, headers[1].hash());
+
+        assert!(shared.headers(0, 2).is_err());
+        assert!(shared.headers(4, 3).is_err());
+        assert!(shared.headers(5, 6).is_err());
+        assert!(shared
+            .headers(1, MAX_HEADERS_PER_REQUEST + 1)
+            .unwrap_err()
+            .starts_with("At most"));
+    }
+
+    #[tokio::test]
+    async fn contract_mismatch_stops_blocks() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.min_tx_block = 10;
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
+        assert_eq!(
+            sequencer.shared().info.lock().unwrap().contract_version,
+            None
+        );
+
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer.handle(Trigger::CheckContract(reply_sx)).await;
+        assert!(reply_rx.await.unwrap().is_err());
+
+        let (_sk, pk) = fusion_wallet::new_key_pair();
+        let (reply_sx, _reply_rx) = oneshot::channel();
+        sequencer
+            .handle(Trigger::Faucet {
+                to: pk.to_u256(),
+                amount: 10.into(),
+                reply: reply_sx,
+            })
+            .await;
+        sequencer.contract_error = Some("submitBlock is missing".to_string());
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer.handle(Trigger::Flush(reply_sx)).await;
+        assert_eq!(reply_rx.await.unwrap(), Ok(FlushResult::NothingToBatch));
+        assert_eq!(sequencer.mempool.lock().unwrap().len(), 1);
+
+        sequencer.contract_error = None;
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer.handle(Trigger::Flush(reply_sx)).await;
+        assert_eq!(reply_rx.await.unwrap(), Ok(FlushResult::Block(1)));
+    }
+
+    #[tokio::test]
+    async fn l1_failure_keeps_the_mempool() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.min_tx_block = 10;
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
+        faucet_block(&mut sequencer).await;
+
+        // Nothing listens on port 1, so every L1 call fails.
+        let provider = failover_provider(
+            &["http://127.0.0.1:1".to_string()],
+            &ProviderSettings::from_config(&config),
+            Metrics::default(),
+        )
+        .unwrap();
+        let wallet: LocalWallet =
+            "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80"
+                .parse()
+                .unwrap();
+        sequencer.l1_contract = Some(fusion::Fusion::new(
+            Address::zero(),
+            Arc::new(SignerMiddleware::new(provider, L1Signer::Local(wallet))),
+        ));
+
+        for nonce in 1..=2 {
+            submit(&mut sequencer, signed_dev_transfer(0, nonce, 10, None))
+                .await
+                .unwrap();
+        }
+        let pending = sequencer.pending_tx_hashes();
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer.handle(Trigger::Flush(reply_sx)).await;
+        assert_eq!(reply_rx.await.unwrap(), Ok(FlushResult::NothingToBatch));
+        assert_eq!(sequencer.pending_tx_hashes(), pending);
+        assert_eq!(sequencer.block_number(), 1);
+        assert_ne!(
+            sequencer.shared().health.lock().unwrap().status,
+            HealthStatus::Ok
+        );
+
+        // Once L1 is back the same transactions make it into a block.
+        sequencer.l1_contract = None;
+        sequencer.contract_error = None;
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer.handle(Trigger::Flush(reply_sx)).await;
+        assert_eq!(reply_rx.await.unwrap(), Ok(FlushResult::Block(2)));
+        assert!(sequencer.pending_tx_hashes().is_empty());
+        assert_eq!(
+            sequencer.shared().blocks.lock().unwrap()[1].tx_hashes,
+            pending
+        );
+    }
+
+    #[tokio::test]
+    async fn faucet_drained() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.min_tx_block = 100;
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
+
+        let (_sk_1, pk_1) = fusion_wallet::new_key_pair();
+        let (_sk_2, pk_2) = fusion_wallet::new_key_pair();
+
+        // The first request is still pending in the mempool
+        // but already counts against the faucet balance.
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer
+            .handle(Trigger::Faucet {
+                to: pk_1.to_u256(),
+                amount: dev::faucet_balance() - U256::from(1),
+                reply: reply_sx,
+            })
+            .await;
+        assert!(reply_rx.await.unwrap().is_ok());
+
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer
+            .handle(Trigger::Faucet {
+                to: pk_2.to_u256(),
+                amount: 2.into(),
+                reply: reply_sx,
+            })
+            .await;
+        assert!(reply_rx
+            .await
+            .unwrap()
+            .unwrap_err()
+            .starts_with("Faucet is drained"));
+    }
+
+    #[tokio::test]
+    async fn dev_account_can_transfer() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.min_tx_block = 1;
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
+        assert!(sequencer.shared().info.lock().unwrap().dev);
+
+        let sk_0 = dev::dev_private_key(0);
+        let pk_0 = fusion_wallet::new_public_key(&sk_0);
+        let (_sk_1, pk_1) = fusion_wallet::new_key_pair();
+
+        let tx = fusion_api::Tx {
+            kind: TxKind::Transfer,
+            sender: pk_0.to_u256(),
+            to: pk_1.to_u256(),
+            nonce: 1.into(),
+            value: 1000.into(),
+            not_before: None,
+            not_after: None,
+            chain_id: None,
+            token: NATIVE_TOKEN,
+        };
+        let sig = fusion_wallet::sign(&tx, sk_0.to_string()).unwrap();
+        sequencer
+            .handle(Trigger::Tx(SignedTx {
+                tx,
+                signature: sig.to_string(),
+            }))
+            .await;
+
+        assert_eq!(sequencer.block_number(), 1);
+        assert_eq!(sequencer.state().get(&pk_1.address()).balance, 1000.into());
+        assert_eq!(
+            sequencer.state().get(&pk_0.address()).balance,
+            U256::from(config.dev_account_balance) - U256::from(1000)
+        );
+    }
+
+    #[test]
+    fn mempool_stats_breakdown() {
+        let config = Config {
+            dev: true,
+            dev_accounts: 3,
+            ..Default::default()
+        };
+        let state = genesis_state(&config);
+        let dev: Vec<U256> = dev::dev_accounts(&config)
+            .iter()
+            .map(|(_, pk)| pk.to_u256())
+            .collect();
+        let (_sk, outsider) = fusion_wallet::new_key_pair();
+        let balance = U256::from(config.dev_account_balance);
+
+        type Window = Option<(Option<u64>, Option<u64>)>;
+        let pending =
+            |kind: TxKind, sender: U256, nonce: u64, value: U256, window: Window, age: u64| {
+                PendingTx {
+                    tx: SignedTx {
+                        tx: Tx {
+                            kind,
+                            sender,
+                            to: dev[0],
+                            nonce: nonce.into(),
+                            value,
+                            not_before: window.and_then(|(not_before, _)| not_before),
+                            not_after: window.and_then(|(_, not_after)| not_after),
+                            chain_id: None,
+                            token: NATIVE_TOKEN,
+                        },
+                        signature: String::new(),
+                    },
+                    received_at: 1000 - age,
+                    commitment: None,
+                }
+            };
+        let population = vec![
+            pending(TxKind::Transfer, dev[0], 1, 10.into(), None, 10),
+            pending(TxKind::Transfer, dev[0], 2, 10.into(), None, 20),
+            pending(TxKind::Transfer, dev[1], 0, 10.into(), None, 0),
+            pending(TxKind::Withdraw, dev[1], 1, balance + U256::one(), None, 5),
+            pending(
+                TxKind::Transfer,
+                dev[2],
+                1,
+                10.into(),
+                Some((Some(2000), None)),
+                100,
+            ),
+            pending(
+                TxKind::Transfer,
+                dev[2],
+                2,
+                10.into(),
+                Some((None, Some(500))),
+                30,
+            ),
+            pending(TxKind::Deposit, outsider.to_u256(), 1, 7.into(), None, 40),
+        ];
+
+        let stats = mempool_stats(&state, &population, 1000);
+        assert_eq!(stats.pending, 7);
+        assert_eq!(stats.ready, 3);
+        assert_eq!(stats.stale_nonce, 1);
+        assert_eq!(stats.unfundable, 1);
+        assert_eq!(stats.premature, 1);
+        assert_eq!(stats.expired, 1);
+        assert_eq!(stats.distinct_senders, 4);
+        assert_eq!(stats.top_senders.len(), 4);
+        assert!(stats.top_senders[..3].iter().all(|(_, count)| *count == 2));
+        assert_eq!(stats.top_senders[3], (outsider.to_u256(), 1));
+        assert_eq!(stats.total_value, balance + U256::from(58));
+        assert_eq!(stats.age_p50, 20);
+        assert_eq!(stats.age_p90, 100);
+        assert_eq!(stats.oldest_age, 100);
+
+        assert_eq!(mempool_stats(&state, &[], 1000), MempoolStats::default());
+    }
+
+    /// Builds one block with a faucet transfer to a fresh account.
+    async fn faucet_block(sequencer: &mut Sequencer) {
+        let (_sk, pk) = fusion_wallet::new_key_pair();
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer
+            .handle(Trigger::Faucet {
+                to: pk.to_u256(),
+                amount: 10.into(),
+                reply: reply_sx,
+            })
+            .await;
+        assert!(reply_rx.await.unwrap().is_ok());
+    }
+
+    fn dev_transfer(nonce: u64, value: u64) -> (SignedTx, U256) {
+        let sk_0 = dev::dev_private_key(0);
+        let pk_0 = fusion_wallet::new_public_key(&sk_0);
+        let (_sk, pk) = fusion_wallet::new_key_pair();
+        let tx = fusion_api::Tx {
+            kind: TxKind::Transfer,
+            sender: pk_0.to_u256(),
+            to: pk.to_u256(),
+            nonce: nonce.into(),
+            value: value.into(),
+            not_before: None,
+            not_after: None,
+            chain_id: None,
+            token: NATIVE_TOKEN,
+        };
+        let sig = fusion_wallet::sign(&tx, sk_0.to_string()).unwrap();
+        (
+            SignedTx {
+                tx,
+                signature: sig.to_string(),
+            },
+            pk.to_u256(),
+        )
+    }
+
+    async fn commit(sequencer: &mut Sequencer, commitment: U256) -> Result<u64, SubmitError> {
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer
+            .handle(Trigger::Commit {
+                commitment,
+                reply: reply_sx,
+            })
+            .await;
+        reply_rx.await.unwrap()
+    }
+
+    async fn reveal(sequencer: &mut Sequencer, tx: SignedTx, salt: U256) -> Result<(), String> {
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer
+            .handle(Trigger::Reveal {
+                tx,
+                salt,
+                reply: reply_sx,
+            })
+            .await;
+        reply_rx.await.unwrap().map_err(|e| e.to_string())
+    }
+
+    #[tokio::test]
+    async fn commit_reveal() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.min_tx_block = 1;
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
+
+        let (tx, to) = dev_transfer(1, 500);
+        let salt = U256::from(7);
+        let commitment = tx_commitment(&tx.tx, salt);
+        assert_eq!(commit(&mut sequencer, commitment).await, Ok(1));
+        assert!(commit(&mut sequencer, commitment).await.is_err());
+
+        // Mismatched reveals leave the commitment pending.
+        assert!(reveal(&mut sequencer, tx.clone(), 8.into()).await.is_err());
+        let mut other = tx.clone();
+        other.tx.value = 600.into();
+        assert!(reveal(&mut sequencer, other, salt).await.is_err());
+
+        // Revealed in the block its commitment appears in, so it waits.
+        assert_eq!(reveal(&mut sequencer, tx.clone(), salt).await, Ok(()));
+        assert_eq!(sequencer.block_number(), 0);
+        assert!(reveal(&mut sequencer, tx.clone(), salt).await.is_err());
+
+        let to: PublicKey = to.into();
+        faucet_block(&mut sequencer).await;
+        assert_eq!(sequencer.block_number(), 1);
+        assert_eq!(sequencer.state().get(&to.address()).balance, U256::zero());
+
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer.handle(Trigger::Flush(reply_sx)).await;
+        assert_eq!(reply_rx.await.unwrap(), Ok(FlushResult::Block(2)));
+        assert_eq!(sequencer.state().get(&to.address()).balance, 500.into());
+    }
+
+    async fn submit(sequencer: &mut Sequencer, tx: SignedTx) -> Result<(), String> {
+        try_submit(sequencer, tx).await.map_err(|e| e.to_string())
+    }
+
+    async fn try_submit(sequencer: &mut Sequencer, tx: SignedTx) -> Result<(), SubmitError> {
+        enqueue(sequencer, tx).await.map(|_| ())
+    }
+
+    async fn enqueue(
+        sequencer: &mut Sequencer,
+        tx: SignedTx,
+    ) -> Result<QueuePosition, SubmitError> {
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer
+            .handle(Trigger::Submit {
+                tx,
+                reply: reply_sx,
+            })
+            .await;
+        reply_rx.await.unwrap()
+    }
+
+    async fn queue_position(sequencer: &mut Sequencer, hash: U256) -> Option<QueuePosition> {
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer
+            .handle(Trigger::QueuePosition {
+                hash,
+                reply: reply_sx,
+            })
+            .await;
+        reply_rx.await.unwrap()
+    }
+
+    async fn preview(sequencer: &mut Sequencer) -> Result<BatchPreview, String> {
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer.handle(Trigger::PreviewBatch(reply_sx)).await;
+        reply_rx.await.unwrap()
+    }
+
+    /// A transfer from dev account `from` to dev account `from + 1`.
+    fn signed_dev_transfer(from: u32, nonce: u64, value: u64, not_before: Option<u64>) -> SignedTx {
+        let tx = fusion_api::Tx {
+            not_before,
+            ..dev::dev_transfer_tx(from, from + 1, nonce, value)
+        };
+        dev::sign_tx(tx, &dev::dev_private_key(from))
+    }
+
+    #[tokio::test]
+    async fn shutdown_flushes_the_mempool() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.min_tx_block = usize::MAX;
+        config.flush_on_shutdown = true;
+        let sequencer = Sequencer::new(&config).await.unwrap();
+        let shared = sequencer.shared();
+
+        let (sx, rx) = mpsc::channel(8);
+        sx.send(Trigger::Tx(signed_dev_transfer(0, 1, 1, None)))
+            .await
+            .unwrap();
+        sx.send(Trigger::Shutdown).await.unwrap();
+        // Too late, the sequencer is stopping.
+        sx.send(Trigger::Tx(signed_dev_transfer(0, 2, 1, None)))
+            .await
+            .unwrap();
+        sequencer.run(rx).await.unwrap();
+
+        let blocks = shared.blocks.lock().unwrap();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].txs.len(), 1);
+        assert_eq!(shared.health.lock().unwrap().status, HealthStatus::Halted);
+    }
+
+    #[tokio::test]
+    async fn reorged_blocks_are_requeued() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.min_tx_block = usize::MAX;
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
+        let txs: Vec<_> = [0, 2, 4]
+            .into_iter()
+            .map(|from| signed_dev_transfer(from, 1, 1, None))
+            .collect();
+        for (i, tx) in txs[..2].iter().enumerate() {
+            submit(&mut sequencer, tx.clone()).await.unwrap();
+            let (reply_sx, reply_rx) = oneshot::channel();
+            sequencer.handle(Trigger::Flush(reply_sx)).await;
+            assert_eq!(
+                reply_rx.await.unwrap(),
+                Ok(FlushResult::Block(i as u64 + 1))
+            );
+        }
+        for block in 1..=2 {
+            let l1_block = L1Block {
+                number: block + 10,
+                hash: H256::from_low_u64_be(block),
+            };
+            sequencer.record_confirmation(block, H256::zero(), l1_block);
+        }
+        let root_after_1 = sequencer.shared().blocks.lock().unwrap()[0]
+            .header
+            .state_root;
+        let batch = sequencer.shared().batch(2).unwrap();
+        assert_eq!(
+            (batch.prior_root, batch.l1_block_number),
+            (root_after_1, 12)
+        );
+        assert_eq!(batch.tx_hashes, [hash_tx(&txs[1].tx)]);
+        assert_eq!(batch.receipts[0].tx_hash, hash_tx(&txs[1].tx));
+        assert!(sequencer.shared().batch(3).is_none());
+        // Block 1 was pruned since.
+        sequencer.shared().blocks.lock().unwrap()[0].txs.clear();
+        submit(&mut sequencer, txs[2].clone()).await.unwrap();
+
+        // The L1 block confirming block 2 was reorged out.
+        let confirmed = sequencer.history.remove_from(2);
+        assert_eq!(confirmed[0].prior_root, root_after_1);
+        assert_eq!(hash_tx(&confirmed[0].txs[0].tx), hash_tx(&txs[1].tx));
+        sequencer.requeue_from(2, confirmed);
+        assert_eq!(sequencer.block_number(), 1);
+        assert!(sequencer.shared().batch(2).is_none());
+        assert_eq!(sequencer.shared().latest_batch().unwrap().number, 1);
+        assert_eq!(sequencer.shared().blocks.lock().unwrap().len(), 1);
+        let hashes: Vec<_> = txs[1..].iter().map(|tx| hash_tx(&tx.tx)).collect();
+        assert_eq!(sequencer.pending_tx_hashes(), hashes);
+        // Its receipts are owed again.
+        assert!(sequencer.receipts.contains_key(&hashes[0]));
+
+        // Block 1 is requeued from the history.
+        let confirmed = sequencer.history.remove_from(1);
+        sequencer.requeue_from(1, confirmed);
+        assert_eq!(sequencer.block_number(), 0);
+        let hashes: Vec<_> = txs.iter().map(|tx| hash_tx(&tx.tx)).collect();
+        assert_eq!(sequencer.pending_tx_hashes(), hashes);
+    }
+
+    /// Only the proofs before a failed one are queued, and the block
+    /// gives the transactions from the failed one on back to the mempool.
+    #[tokio::test]
+    async fn failed_proof() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.min_tx_block = usize::MAX;
+        config.dev_account_balance = 1000;
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
+        let txs: Vec<_> = (1..=3)
+            .map(|nonce| signed_dev_transfer(0, nonce, 100, None))
+            .collect();
+        let mut states = vec![sequencer.state.clone()];
+        for tx in &txs {
+            submit(&mut sequencer, tx.clone()).await.unwrap();
+            states.push(apply_tx(states.last().unwrap().clone(), &tx.tx).unwrap());
+        }
+        assert_eq!(sequencer.build_block().await, Some(1));
+
+        let mut proofs = vec![];
+        for inputs in CircuitInput::batch(&txs, &states) {
+            proofs.push(proving::MockProver.prove(inputs.unwrap()).await);
+        }
+        proofs[1] = Err("The prover failed".to_string());
+        let proving = Proving {
+            block: 1,
+            txs: txs.clone(),
+            states: states.clone(),
+            inputs_time: Duration::ZERO,
+            started: Instant::now(),
+            task: tokio::spawn(async { vec![] }),
+        };
+        sequencer.finish_proving(proving, proofs).await;
+
+        assert_eq!(sequencer.submissions.len(), 1);
+        let (block, proof) = sequencer.submissions.front().unwrap();
+        assert_eq!((block, proof.input[1]), (1, states[1].root()));
+        assert_eq!(sequencer.block_number(), 1);
+        assert_eq!(sequencer.state.root(), states[1].root());
+        {
+            let shared = sequencer.shared();
+            let blocks = shared.blocks.lock().unwrap();
+            assert_eq!(blocks.len(), 1);
+            assert_eq!(blocks[0].tx_hashes, [hash_tx(&txs[0].tx)]);
+            assert_eq!(blocks[0].header.state_root, states[1].root());
+        }
+        let hashes: Vec<_> = txs[1..].iter().map(|tx| hash_tx(&tx.tx)).collect();
+        assert_eq!(sequencer.pending_tx_hashes(), hashes);
+        assert_eq!(
+            sequencer.shared().health.lock().unwrap().status,
+            HealthStatus::Degraded
+        );
+
+        // The next block builds on the proven transaction.
+        assert_eq!(sequencer.build_block().await, Some(2));
+        assert_eq!(sequencer.state.root(), states[3].root());
+        assert!(sequencer.pending_tx_hashes().is_empty());
+    }
+
+    #[tokio::test]
+    async fn replace_pending_txs() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.min_tx_block = usize::MAX;
+        config.dev_account_balance = 1000;
+        config.replace_pending_txs = true;
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
+        for nonce in 1..=2 {
+            let tx = signed_dev_transfer(0, nonce, 100, None);
+            assert_eq!(submit(&mut sequencer, tx).await, Ok(()));
+        }
+
+        // The replacement keeps the place of the pending transaction.
+        let replacement = signed_dev_transfer(0, 1, 5, None);
+        assert_eq!(submit(&mut sequencer, replacement.clone()).await, Ok(()));
+        let expected = vec![
+            hash_tx(&replacement.tx),
+            hash_tx(&signed_dev_transfer(0, 2, 100, None).tx),
+        ];
+        assert_eq!(sequencer.pending_tx_hashes(), expected);
+
+        // Signed by someone else.
+        let mut forged = signed_dev_transfer(0, 2, 7, None);
+        forged.signature = replacement.signature.clone();
+        let error = try_submit(&mut sequencer, forged).await.unwrap_err();
+        assert!(matches!(error, SubmitError::InvalidSignature(_)), "{error}");
+        assert_eq!(sequencer.pending_tx_hashes(), expected);
+
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer.handle(Trigger::Flush(reply_sx)).await;
+        assert_eq!(reply_rx.await.unwrap(), Ok(FlushResult::Block(1)));
+        let sender = fusion_wallet::new_public_key(&dev::dev_private_key(0));
+        let account = sequencer.state().get(&sender.address());
+        assert_eq!((account.balance, account.nonce), (895.into(), 2.into()));
+    }
+
+    #[tokio::test]
+    async fn forged_txs_reserve_no_nonce() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.min_tx_block = usize::MAX;
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
+        let tx = signed_dev_transfer(0, 1, 100, None);
+
+        // Signed by the recipient rather than the sender.
+        let mut forged = tx.clone();
+        forged.signature = fusion_wallet::sign(&tx.tx, dev::dev_private_key(1).to_string())
+            .unwrap()
+            .to_string();
+        let error = try_submit(&mut sequencer, forged).await.unwrap_err();
+        assert!(matches!(error, SubmitError::InvalidSignature(_)), "{error}");
+        assert!(sequencer.pending_tx_hashes().is_empty());
+        let (nonces, pending) = sequencer.nonces(tx.tx.sender, NATIVE_TOKEN);
+        assert_eq!((nonces.next, pending.len()), (1.into(), 0));
+
+        // The sender's own transaction still takes the nonce.
+        assert_eq!(submit(&mut sequencer, tx.clone()).await, Ok(()));
+        assert_eq!(sequencer.pending_tx_hashes(), [hash_tx(&tx.tx)]);
+    }
+
+    #[tokio::test]
+    async fn metrics_count_dropped_transactions() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.min_tx_block = usize::MAX;
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
+        let mempool_depth = sequencer.mempool_depth();
+        submit(&mut sequencer, signed_dev_transfer(0, 1, 1, None))
+            .await
+            .unwrap();
+        submit(&mut sequencer, signed_dev_transfer(2, 1, u64::MAX, None))
+            .await
+            .unwrap();
+        assert_eq!(mempool_depth(), 2);
+
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer.handle(Trigger::Flush(reply_sx)).await;
+        assert_eq!(reply_rx.await.unwrap(), Ok(FlushResult::Block(1)));
+        assert_eq!(mempool_depth(), 0);
+        let rendered = sequencer.shared().metrics.render(mempool_depth());
+        assert!(rendered
+            .lines()
+            .any(|line| line == "fusion_transactions_rejected_total{reason=\"balance\"} 1"));
+    }
+
+    #[tokio::test]
+    async fn full_blocks_keep_the_order() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.min_tx_block = usize::MAX;
+        config.max_block_txs = 2;
+        config.batch_preview = true;
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
+        for nonce in 1..=6 {
+            submit(&mut sequencer, signed_dev_transfer(0, nonce, 1, None))
+                .await
+                .unwrap();
+        }
+
+        let preview = preview(&mut sequencer).await.unwrap();
+        assert_eq!(preview.tx_hashes.len(), 2);
+        assert_eq!(preview.excluded.len(), 4);
+        assert!(preview
+            .excluded
+            .iter()
+            .all(|(_, exclusion)| *exclusion == Exclusion::Full));
+
+        for block in 1..=3 {
+            let (reply_sx, reply_rx) = oneshot::channel();
+            sequencer.handle(Trigger::Flush(reply_sx)).await;
+            assert_eq!(reply_rx.await.unwrap(), Ok(FlushResult::Block(block)));
+        }
+        let nonces: Vec<Vec<u64>> = sequencer
+            .shared()
+            .blocks
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|block| block.txs.iter().map(|tx| tx.tx.nonce.as_u64()).collect())
+            .collect();
+        assert_eq!(nonces, [[1, 2], [3, 4], [5, 6]]);
+
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer.handle(Trigger::Flush(reply_sx)).await;
+        assert_eq!(reply_rx.await.unwrap(), Ok(FlushResult::NothingToBatch));
+    }
+
+    #[tokio::test]
+    async fn queue_positions() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.min_tx_block = 10;
+        config.max_block_txs = 2;
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
+        let attestation_key = sequencer.shared().info.lock().unwrap().attestation_key;
+        let mut hashes = vec![];
+        for nonce in 1..=5 {
+            let tx = signed_dev_transfer(0, nonce, 1, None);
+            hashes.push(hash_tx(&tx.tx));
+            let queued = enqueue(&mut sequencer, tx.clone()).await.unwrap();
+            assert_eq!(queued.tx_hash, *hashes.last().unwrap());
+            assert_eq!(queued.position, nonce as usize - 1);
+            assert_eq!(queued.queued, nonce as usize);
+            assert_eq!(queued.batch_size, 10);
+            assert_eq!(queued.blocks_ahead, (nonce as usize - 1) / 2);
+
+            let receipt = queued.receipt.unwrap();
+            assert_eq!(receipt.tx_hash, queued.tx_hash);
+            assert_eq!(receipt.promised_block, 1 + queued.blocks_ahead as u64);
+            fusion_wallet::verify_receipt(&receipt, &attestation_key).unwrap();
+            // A retry gets the same receipt.
+            let retried = enqueue(&mut sequencer, tx).await.unwrap();
+            assert_eq!(retried.receipt, Some(receipt));
+        }
+
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer.handle(Trigger::Flush(reply_sx)).await;
+        assert_eq!(reply_rx.await.unwrap(), Ok(FlushResult::Block(1)));
+        assert_eq!(queue_position(&mut sequencer, hashes[0]).await, None);
+        let queued = queue_position(&mut sequencer, hashes[4]).await.unwrap();
+        assert_eq!((queued.position, queued.queued), (2, 3));
+        assert_eq!(queued.blocks_ahead, 1);
+        assert_eq!(queued.receipt, None);
+
+        // The receipts of the block go into its batch.
+        let l1_block = L1Block {
+            number: 11,
+            hash: H256::zero(),
+        };
+        sequencer.record_confirmation(1, H256::zero(), l1_block);
+        let batch = sequencer.shared().batch(1).unwrap();
+        let receipts: Vec<_> = batch.receipts.iter().map(|r| r.tx_hash).collect();
+        assert_eq!(receipts, hashes[..2]);
+        assert_eq!(sequencer.receipts.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn pending_withdrawals() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.dev_account_balance = 1000;
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
+
+        let sk = dev::dev_private_key(0);
+        let sender = fusion_wallet::new_public_key(&sk).to_u256();
+        let tx = fusion_api::Tx {
+            kind: TxKind::Withdraw,
+            sender,
+            to: 2.into(),
+            nonce: 1.into(),
+            value: 10.into(),
+            not_before: None,
+            not_after: None,
+            chain_id: None,
+            token: NATIVE_TOKEN,
+        };
+        let signature = fusion_wallet::sign(&tx, sk.to_string())
+            .unwrap()
+            .to_string();
+        let hash = hash_tx(&tx);
+        submit(&mut sequencer, SignedTx { tx, signature })
+            .await
+            .unwrap();
+        submit(&mut sequencer, signed_dev_transfer(0, 2, 10, None))
+            .await
+            .unwrap();
+
+        let pending = sequencer.pending_withdrawals(sender);
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].hash, hash);
+        assert_eq!(pending[0].status, TxStatus::Pending);
+        assert!(sequencer.pending_withdrawals(U256::one()).is_empty());
+
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer.handle(Trigger::Flush(reply_sx)).await;
+        assert_eq!(reply_rx.await.unwrap(), Ok(FlushResult::Block(1)));
+        // Nothing waits for L1 in dev mode.
+        assert!(sequencer.pending_withdrawals(sender).is_empty());
+
+        // Until its proof lands, the withdrawal is not paid out.
+        sequencer
+            .submissions
+            .push(1, fusion::TxProof::default(), 0)
+            .unwrap();
+        let pending = sequencer.pending_withdrawals(sender);
+        assert_eq!(pending.len(), 1);
+        assert_eq!(
+            pending[0].status,
+            TxStatus::Included {
+                block: 1,
+                l1_tx_hashes: vec![]
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn batch_preview() {
+        let mut config = Config::default();
+        config.dev = true;
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
+        assert!(preview(&mut sequencer).await.is_err());
+
+        for ordering in [OrderingPolicy::Fifo, OrderingPolicy::Random] {
+            config.batch_preview = true;
+            config.min_tx_block = usize::MAX;
+            config.dev_account_balance = 1000;
+            config.ordering = ordering;
+            let mut sequencer = Sequencer::new(&config).await.unwrap();
+
+            let overspend = signed_dev_transfer(3, 1, 2000, None);
+            let overdraft = signed_dev_transfer(0, 3, 200, None);
+            let premature = signed_dev_transfer(0, 4, 1, Some(u64::MAX));
+            for tx in [
+                overspend.clone(),
+                signed_dev_transfer(1, 1, 10, None),
+                signed_dev_transfer(0, 1, 600, None),
+                signed_dev_transfer(2, 1, 20, None),
+                signed_dev_transfer(0, 2, 300, None),
+                overdraft.clone(),
+                premature.clone(),
+            ] {
+                assert_eq!(submit(&mut sequencer, tx).await, Ok(()));
+            }
+
+            let pending = sequencer.pending_tx_hashes();
+            let preview = preview(&mut sequencer).await.unwrap();
+            assert_eq!(sequencer.pending_tx_hashes(), pending);
+            assert_eq!(preview.block, 1);
+            assert_eq!(preview.tx_hashes.len(), 4);
+            assert_eq!(
+                preview.excluded,
+                vec![
+                    (hash_tx(&premature.tx), Exclusion::Premature),
+                    (
+                        hash_tx(&overspend.tx),
+                        Exclusion::Invalid("Insufficient balance".to_string())
+                    ),
+                    (
+                        hash_tx(&overdraft.tx),
+                        Exclusion::Revalidation("Insufficient balance".to_string())
+                    ),
+                ]
+            );
+
+            let (reply_sx, reply_rx) = oneshot::channel();
+            sequencer.handle(Trigger::Flush(reply_sx)).await;
+            assert_eq!(reply_rx.await.unwrap(), Ok(FlushResult::Block(1)));
+            let block = sequencer.shared().blocks.lock().unwrap()[0].clone();
+            assert_eq!(block.tx_hashes, preview.tx_hashes);
+            assert_eq!(block.header.state_root, preview.state_root);
+            assert_eq!(sequencer.pending_tx_hashes(), vec![hash_tx(&premature.tx)]);
+        }
+    }
+
+    async fn nonce(sequencer: &mut Sequencer, address: U256) -> (u64, u64, u64) {
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer
+            .handle(Trigger::Nonce {
+                address,
+                token: NATIVE_TOKEN,
+                reply: reply_sx,
+            })
+            .await;
+        let Nonces {
+            committed,
+            pending,
+            next,
+        } = reply_rx.await.unwrap();
+        (committed.as_u64(), pending.as_u64(), next.as_u64())
+    }
+
+    #[tokio::test]
+    async fn nonces() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.min_tx_block = usize::MAX;
+        config.dev_account_balance = 1000;
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
+        let sender = fusion_wallet::new_public_key(&dev::dev_private_key(0));
+        assert_eq!(nonce(&mut sequencer, sender.to_u256()).await, (0, 0, 1));
+
+        let error = submit(&mut sequencer, signed_dev_transfer(0, 2, 1, None))
+            .await
+            .unwrap_err();
+        assert!(error.contains("the next one is 1"), "{error}");
+        for nonce in 1..=3 {
+            let tx = signed_dev_transfer(0, nonce, 100, None);
+            assert_eq!(submit(&mut sequencer, tx).await, Ok(()));
+        }
+        assert_eq!(nonce(&mut sequencer, sender.to_u256()).await, (0, 3, 4));
+        // The same transaction twice is only queued once, another one
+        // with its nonce is refused.
+        assert_eq!(
+            submit(&mut sequencer, signed_dev_transfer(0, 3, 100, None)).await,
+            Ok(())
+        );
+        assert_eq!(sequencer.pending_tx_hashes().len(), 3);
+        let error = submit(&mut sequencer, signed_dev_transfer(0, 2, 5, None))
+            .await
+            .unwrap_err();
+        assert!(error.contains("already pending"), "{error}");
+        let error = submit(&mut sequencer, signed_dev_transfer(0, 5, 1, None))
+            .await
+            .unwrap_err();
+        assert!(error.contains("the next one is 4"), "{error}");
+
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer.handle(Trigger::Flush(reply_sx)).await;
+        assert_eq!(reply_rx.await.unwrap(), Ok(FlushResult::Block(1)));
+        let block = sequencer.shared().blocks.lock().unwrap()[0].clone();
+        let nonces: Vec<_> = block.txs.iter().map(|tx| tx.tx.nonce.as_u64()).collect();
+        assert_eq!(nonces, [1, 2, 3]);
+        assert_eq!(nonce(&mut sequencer, sender.to_u256()).await, (3, 3, 4));
+        let account = sequencer.state().get(&sender.address());
+        assert_eq!((account.balance, account.nonce), (700.into(), 3.into()));
+
+        // Replaying an included transaction is rejected.
+        let error = submit(&mut sequencer, signed_dev_transfer(0, 1, 100, None))
+            .await
+            .unwrap_err();
+        assert!(error.contains("already used"), "{error}");
+        assert!(sequencer.pending_tx_hashes().is_empty());
+
+        // Without the one before it, a transaction is dropped.
+        let mut state = sequencer.state().clone();
+        let gap = signed_dev_transfer(0, 5, 1, None);
+        assert!(check_tx_state(&state, &gap.tx).is_err());
+        state = apply_tx(state, &signed_dev_transfer(0, 4, 1, None).tx).unwrap();
+        assert!(check_tx_state(&state, &gap.tx).is_ok());
+    }
+
+    async fn balance(sequencer: &mut Sequencer, address: U256) -> (u64, u64) {
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer
+            .handle(Trigger::Balance {
+                address,
+                token: NATIVE_TOKEN,
+                reply: reply_sx,
+            })
+            .await;
+        let Balance { committed, pending } = reply_rx.await.unwrap();
+        (committed.as_u64(), pending.as_u64())
+    }
+
+    /// A transaction of `token` from dev account `from` to the next one.
+    fn signed_token_tx(
+        kind: TxKind,
+        from: u32,
+        nonce: u64,
+        value: u64,
+        token: Address,
+    ) -> SignedTx {
+        let mut tx = signed_dev_transfer(from, nonce, value, None).tx;
+        tx.kind = kind;
+        tx.token = token;
+        dev::sign_tx(tx, &dev::dev_private_key(from))
+    }
+
+    #[tokio::test]
+    async fn tokens() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.min_tx_block = usize::MAX;
+        config.dev_account_balance = 1000;
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
+        let token = Address::repeat_byte(0xaa);
+        let [a, b] = [0, 1].map(|i| fusion_wallet::new_public_key(&dev::dev_private_key(i)));
+
+        // Each token has its own nonces, so the native transfer and the
+        // token deposit both have nonce 1.
+        submit(&mut sequencer, signed_dev_transfer(0, 1, 10, None))
+            .await
+            .unwrap();
+        submit(
+            &mut sequencer,
+            signed_token_tx(TxKind::Deposit, 0, 1, 500, token),
+        )
+        .await
+        .unwrap();
+        submit(
+            &mut sequencer,
+            signed_token_tx(TxKind::Transfer, 0, 2, 200, token),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            submit(
+                &mut sequencer,
+                signed_token_tx(TxKind::Transfer, 0, 2, 1, token)
+            )
+            .await
+            .unwrap_err(),
+            "A transaction with nonce 2 is already pending"
+        );
+        // More than the token balance, though less than the native one.
+        submit(
+            &mut sequencer,
+            signed_token_tx(TxKind::Transfer, 0, 3, 400, token),
+        )
+        .await
+        .unwrap();
+
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer.handle(Trigger::Flush(reply_sx)).await;
+        assert_eq!(reply_rx.await.unwrap(), Ok(FlushResult::Block(1)));
+
+        let state = sequencer.state();
+        let native = |pk: &PublicKey| state.get(&pk.address());
+        let of_token = |pk: &PublicKey| state.get(&account_key(pk.address(), token));
+        assert_eq!(native(&a).balance + native(&b).balance, 2000.into());
+        assert_eq!(native(&a).balance, 990.into());
+        assert_eq!(of_token(&a).balance, 300.into());
+        assert_eq!(of_token(&b).balance, 200.into());
+        assert_eq!((native(&a).nonce, of_token(&a).nonce), (1.into(), 2.into()));
+
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer
+            .handle(Trigger::Balance {
+                address: b.to_u256(),
+                token,
+                reply: reply_sx,
+            })
+            .await;
+        assert_eq!(reply_rx.await.unwrap().committed, 200.into());
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer
+            .handle(Trigger::Nonce {
+                address: a.to_u256(),
+                token,
+                reply: reply_sx,
+            })
+            .await;
+        assert_eq!(reply_rx.await.unwrap().next, 3.into());
+        assert_eq!(balance(&mut sequencer, b.to_u256()).await, (1010, 1010));
+    }
+
+    async fn estimate(sequencer: &mut Sequencer, tx: SignedTx) -> Estimate {
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer
+            .handle(Trigger::Estimate {
+                tx: tx.tx,
+                reply: reply_sx,
+            })
+            .await;
+        reply_rx.await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn estimates() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.min_tx_block = usize::MAX;
+        config.dev_account_balance = 1000;
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
+        let sender = fusion_wallet::new_public_key(&dev::dev_private_key(0)).to_u256();
+        let tx = signed_dev_transfer(0, 1, 300, None);
+        assert_eq!(submit(&mut sequencer, tx).await, Ok(()));
+
+        let next = signed_dev_transfer(0, 2, 200, None);
+        let success = Estimate::Success {
+            post_balance: 500.into(),
+            fee: 0.into(),
+        };
+        assert_eq!(estimate(&mut sequencer, next.clone()).await, success);
+        // Nothing was queued nor reserved.
+        assert_eq!(estimate(&mut sequencer, next.clone()).await, success);
+        assert_eq!(nonce(&mut sequencer, sender).await, (0, 1, 2));
+        assert_eq!(sequencer.pending().len(), 1);
+
+        for nonce in [1, 3] {
+            assert_eq!(
+                estimate(&mut sequencer, signed_dev_transfer(0, nonce, 1, None)).await,
+                Estimate::BadNonce { expected: 2.into() }
+            );
+        }
+        assert_eq!(
+            estimate(&mut sequencer, signed_dev_transfer(0, 2, 1000, None)).await,
+            Estimate::InsufficientBalance {
+                balance: 700.into(),
+                shortfall: 300.into(),
+            }
+        );
+        let mut to_self = next;
+        to_self.tx.to = sender;
+        assert_eq!(
+            estimate(&mut sequencer, to_self).await,
+            Estimate::Invalid("Tx to self".to_string())
+        );
+        assert_eq!(
+            submit(&mut sequencer, signed_dev_transfer(0, 2, 200, None)).await,
+            Ok(())
+        );
+
+        // An L1 address that is not a Fusion public key.
+        let l1_address = (1u64..)
+            .map(U256::from)
+            .find(|address| !fusion_wallet::is_public_key(address))
+            .unwrap();
+        let mut withdrawal = signed_dev_transfer(0, 3, 100, None);
+        withdrawal.tx.kind = TxKind::Withdraw;
+        withdrawal.tx.to = l1_address;
+        assert_eq!(
+            estimate(&mut sequencer, withdrawal).await,
+            Estimate::Success {
+                post_balance: 400.into(),
+                fee: 0.into(),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn chain_ids() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.min_tx_block = usize::MAX;
+        config.chain_id = 7;
+        config.accept_legacy_txs = false;
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
+        let signed_for = |chain_id: Option<u64>| {
+            let mut tx = signed_dev_transfer(0, 1, 10, None).tx;
+            tx.chain_id = chain_id;
+            let sk = dev::dev_private_key(0).to_string();
+            let signature = fusion_wallet::sign(&tx, sk).unwrap().to_string();
+            SignedTx { tx, signature }
+        };
+
+        let e = submit(&mut sequencer, signed_for(Some(8)))
+            .await
+            .unwrap_err();
+        assert!(e.contains("this is chain 7"), "{e}");
+        let e = submit(&mut sequencer, signed_for(None)).await.unwrap_err();
+        assert!(e.contains("no longer accepted"), "{e}");
+        assert!(matches!(
+            estimate(&mut sequencer, signed_for(None)).await,
+            Estimate::Invalid(_)
+        ));
+        // Signed for another chain, a transaction is not valid here either.
+        let mut replayed = signed_for(Some(8));
+        replayed.tx.chain_id = Some(7);
+        assert!(submit(&mut sequencer, replayed).await.is_err());
+        assert_eq!(submit(&mut sequencer, signed_for(Some(7))).await, Ok(()));
+
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer.handle(Trigger::ChainInfo(reply_sx)).await;
+        let info = reply_rx.await.unwrap();
+        assert_eq!((info.chain_id, info.tx_format), (7, TX_FORMAT));
+        assert!(!info.accepts_legacy);
+
+        config.chain_id = 0;
+        assert!(Sequencer::new(&config).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn submit_errors() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.min_tx_block = usize::MAX;
+        config.max_mempool_size = 2;
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
+        let tx = signed_dev_transfer(0, 1, 10, None);
+        assert_eq!(try_submit(&mut sequencer, tx).await, Ok(()));
+
+        let e = try_submit(&mut sequencer, signed_dev_transfer(0, 3, 10, None))
+            .await
+            .unwrap_err();
+        assert_eq!(e.code(), -32003);
+        assert_eq!(
+            e,
+            SubmitError::BadNonce {
+                nonce: 3.into(),
+                expected: 2.into()
+            }
+        );
+        let e = try_submit(&mut sequencer, signed_dev_transfer(0, 1, 20, None))
+            .await
+            .unwrap_err();
+        assert_eq!(e.code(), -32005);
+        assert_eq!(e, SubmitError::Duplicate { nonce: 1.into() });
+
+        let mut other_chain = signed_dev_transfer(1, 1, 10, None);
+        other_chain.tx.chain_id = Some(config.chain_id + 1);
+        let e = try_submit(&mut sequencer, other_chain).await.unwrap_err();
+        assert_eq!(e.code(), -32602);
+
+        let tx = signed_dev_transfer(1, 1, 10, None);
+        assert_eq!(try_submit(&mut sequencer, tx).await, Ok(()));
+        let e = try_submit(&mut sequencer, signed_dev_transfer(2, 1, 10, None))
+            .await
+            .unwrap_err();
+        assert_eq!(e.code(), -32004);
+        assert_eq!(e, SubmitError::MempoolFull { max: 2 });
+    }
+
+    #[tokio::test]
+    async fn balances() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.min_tx_block = usize::MAX;
+        config.dev_account_balance = 1000;
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
+        let key = |index| fusion_wallet::new_public_key(&dev::dev_private_key(index)).to_u256();
+
+        let (_sk, unknown) = fusion_wallet::new_key_pair();
+        assert_eq!(balance(&mut sequencer, unknown.to_u256()).await, (0, 0));
+
+        for tx in [
+            signed_dev_transfer(0, 1, 300, None),
+            signed_dev_transfer(1, 1, 50, None),
+            signed_dev_transfer(0, 2, 200, None),
+            // More than is left once the first two are included.
+            signed_dev_transfer(0, 3, 600, None),
+        ] {
+            assert_eq!(submit(&mut sequencer, tx).await, Ok(()));
+        }
+        assert_eq!(balance(&mut sequencer, key(0)).await, (1000, 500));
+        assert_eq!(balance(&mut sequencer, key(1)).await, (1000, 1450));
+        assert_eq!(balance(&mut sequencer, key(2)).await, (1000, 1050));
+
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer.handle(Trigger::Flush(reply_sx)).await;
+        assert_eq!(reply_rx.await.unwrap(), Ok(FlushResult::Block(1)));
+        assert_eq!(balance(&mut sequencer, key(0)).await, (500, 500));
+        assert_eq!(balance(&mut sequencer, key(1)).await, (1450, 1450));
+    }
+
+    /// Submissions and balance reads from many clients at once, while
+    /// blocks are built, all go through the sequencer task in turn.
+    #[tokio::test(flavor = "multi_thread")]
+    async fn concurrent_submissions() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.dev_accounts = 101;
+        config.min_tx_block = 16;
+        let sequencer = Sequencer::new(&config).await.unwrap();
+        let shared = sequencer.shared();
+        let (sx, rx) = mpsc::channel(1024);
+
+        let clients = async move {
+            let clients: Vec<_> = (0..100)
+                .map(|from| {
+                    let sx = sx.clone();
+                    tokio::spawn(async move {
+                        let mut hashes = vec![];
+                        for nonce in 1..=3 {
+                            let tx = signed_dev_transfer(from, nonce, 1, None);
+                            let (reply_sx, reply_rx) = oneshot::channel();
+                            sx.send(Trigger::Submit {
+                                tx: tx.clone(),
+                                reply: reply_sx,
+                            })
+                            .await
+                            .unwrap();
+                            reply_rx.await.unwrap().unwrap();
+                            hashes.push(hash_tx(&tx.tx));
+
+                            let (reply_sx, reply_rx) = oneshot::channel();
+                            sx.send(Trigger::Balance {
+                                address: tx.tx.sender,
+                                token: NATIVE_TOKEN,
+                                reply: reply_sx,
+                            })
+                            .await
+                            .unwrap();
+                            reply_rx.await.unwrap();
+                        }
+                        hashes
+                    })
+                })
+                .collect();
+            let mut submitted = HashSet::new();
+            for client in clients {
+                submitted.extend(client.await.unwrap());
+            }
+            let (reply_sx, reply_rx) = oneshot::channel();
+            sx.send(Trigger::Flush(reply_sx)).await.unwrap();
+            let _ = reply_rx.await.unwrap();
+            sx.send(Trigger::Shutdown).await.unwrap();
+            submitted
+        };
+        let (stopped, submitted) = tokio::join!(sequencer.run(rx), clients);
+        stopped.unwrap();
+        assert_eq!(submitted.len(), 300);
+
+        // Each one is in exactly one block.
+        let included: Vec<_> = shared
+            .blocks
+            .lock()
+            .unwrap()
+            .iter()
+            .flat_map(|block| block.tx_hashes.clone())
+            .collect();
+        assert_eq!(included.len(), 300);
+        assert_eq!(included.into_iter().collect::<HashSet<_>>(), submitted);
+        assert_eq!(shared.health.lock().unwrap().mempool_size, 0);
+    }
+
+    async fn transaction(sequencer: &mut Sequencer, hash: U256) -> Option<TxInfo> {
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer
+            .handle(Trigger::Transaction {
+                hash,
+                reply: reply_sx,
+            })
+            .await;
+        reply_rx.await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn expired_and_dropped_transactions() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.min_tx_block = usize::MAX;
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
+        let txs: Vec<_> = [0, 2, 4]
+            .into_iter()
+            .map(|from| signed_dev_transfer(from, 1, 1, None))
+            .collect();
+        let hashes: Vec<_> = txs.iter().map(|tx| hash_tx(&tx.tx)).collect();
+        for tx in &txs {
+            submit(&mut sequencer, tx.clone()).await.unwrap();
+        }
+
+        // The first one is older than `mempool_ttl`.
+        let mut mempool = sequencer.mempool.lock().unwrap();
+        let mut oldest = mempool.remove(&hashes[0]).unwrap();
+        oldest.received_at = 0;
+        mempool.requeue(vec![oldest]);
+        drop(mempool);
+        let mut events = sequencer.shared().events.subscribe();
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer.handle(Trigger::Maintain(reply_sx)).await;
+        reply_rx.await.unwrap();
+        assert!(matches!(events.try_recv(), Ok(Event::Rejected(hash)) if hash == hashes[0]));
+        assert_eq!(sequencer.pending_tx_hashes(), hashes[1..]);
+        assert_eq!(
+            transaction(&mut sequencer, hashes[0]).await.unwrap().status,
+            TxStatus::Rejected {
+                reason: "Expired after 3600s in the mempool".to_string()
+            }
+        );
+
+        async fn drop_tx(
+            sequencer: &mut Sequencer,
+            hash: U256,
+            signature: String,
+        ) -> Result<(), String> {
+            let (reply_sx, reply_rx) = oneshot::channel();
+            sequencer
+                .handle(Trigger::Drop {
+                    hash,
+                    signature,
+                    reply: reply_sx,
+                })
+                .await;
+            reply_rx.await.unwrap()
+        }
+        let sign_drop = |from: u32, hash: U256| {
+            fusion_wallet::sign_drop(hash, dev::dev_private_key(from).to_string())
+                .unwrap()
+                .to_string()
+        };
+        // Only the sender can drop it, and not with the signature of the
+        // transaction itself.
+        let error = drop_tx(&mut sequencer, hashes[1], sign_drop(0, hashes[1]))
+            .await
+            .unwrap_err();
+        assert!(error.starts_with("Not dropping"), "{error}");
+        let error = drop_tx(&mut sequencer, hashes[1], txs[1].signature.clone())
+            .await
+            .unwrap_err();
+        assert!(error.starts_with("Not dropping"), "{error}");
+        let error = drop_tx(&mut sequencer, hashes[0], sign_drop(0, hashes[0]))
+            .await
+            .unwrap_err();
+        assert!(error.contains("is not pending"), "{error}");
+        assert_eq!(sequencer.pending_tx_hashes(), hashes[1..]);
+
+        assert_eq!(
+            drop_tx(&mut sequencer, hashes[1], sign_drop(2, hashes[1])).await,
+            Ok(())
+        );
+        assert_eq!(sequencer.pending_tx_hashes(), hashes[2..]);
+        assert_eq!(
+            transaction(&mut sequencer, hashes[1]).await.unwrap().status,
+            TxStatus::Rejected {
+                reason: "Dropped by its sender".to_string()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn mempool_full() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.min_tx_block = usize::MAX;
+        config.max_mempool_size = 2;
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
+        let txs: Vec<_> = [0, 2, 4]
+            .into_iter()
+            .map(|from| signed_dev_transfer(from, 1, 1, None))
+            .collect();
+        for tx in &txs[..2] {
+            submit(&mut sequencer, tx.clone()).await.unwrap();
+        }
+        assert_eq!(
+            submit(&mut sequencer, txs[2].clone()).await,
+            Err("The mempool is full, with 2 transactions".to_string())
+        );
+        // A retry of a pending transaction is still answered.
+        assert_eq!(submit(&mut sequencer, txs[0].clone()).await, Ok(()));
+        assert_eq!(sequencer.pending_tx_hashes().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn sender_limit() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.min_tx_block = usize::MAX;
+        config.max_pending_per_sender = 2;
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
+        for nonce in 1..=2 {
+            let tx = signed_dev_transfer(0, nonce, 1, None);
+            assert_eq!(try_submit(&mut sequencer, tx).await, Ok(()));
+        }
+        let e = try_submit(&mut sequencer, signed_dev_transfer(0, 3, 1, None))
+            .await
+            .unwrap_err();
+        assert_eq!(e.code(), -32007);
+        assert_eq!(e, SubmitError::SenderLimit { max: 2 });
+        // Other senders are not held back, and a retry is still answered.
+        let tx = signed_dev_transfer(1, 1, 1, None);
+        assert_eq!(try_submit(&mut sequencer, tx).await, Ok(()));
+        let tx = signed_dev_transfer(0, 2, 1, None);
+        assert_eq!(try_submit(&mut sequencer, tx).await, Ok(()));
+        assert_eq!(sequencer.pending_tx_hashes().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn paused_batching() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.min_tx_block = 1;
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
+        async fn set_paused(sequencer: &mut Sequencer, paused: bool) -> Result<(), String> {
+            let (reply_sx, reply_rx) = oneshot::channel();
+            sequencer
+                .handle(Trigger::SetPaused {
+                    paused,
+                    reply: reply_sx,
+                })
+                .await;
+            reply_rx.await.unwrap()
+        }
+        assert_eq!(
+            set_paused(&mut sequencer, false).await,
+            Err("Batching is not paused".to_string())
+        );
+        assert_eq!(set_paused(&mut sequencer, true).await, Ok(()));
+        assert_eq!(
+            set_paused(&mut sequencer, true).await,
+            Err("Batching is already paused".to_string())
+        );
+        let health = sequencer.shared().health.lock().unwrap().clone();
+        assert_eq!(health.status, HealthStatus::Degraded);
+
+        // Still accepted, but left in the mempool.
+        let txs: Vec<_> = [0, 2, 4]
+            .into_iter()
+            .map(|from| signed_dev_transfer(from, 1, 1, None))
+            .collect();
+        let hashes: Vec<_> = txs.iter().map(|tx| hash_tx(&tx.tx)).collect();
+        for tx in &txs[..2] {
+            submit(&mut sequencer, tx.clone()).await.unwrap();
+        }
+        assert_eq!(sequencer.pending_tx_hashes(), hashes[..2]);
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer.handle(Trigger::Flush(reply_sx)).await;
+        assert_eq!(
+            reply_rx.await.unwrap(),
+            Err("Batching is paused".to_string())
+        );
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer.handle(Trigger::MempoolContent(reply_sx)).await;
+        let content = reply_rx.await.unwrap();
+        assert_eq!(
+            content.iter().map(|info| info.hash).collect::<Vec<_>>(),
+            hashes[..2]
+        );
+        assert!(content.iter().all(|info| info.status == TxStatus::Pending));
+
+        assert_eq!(set_paused(&mut sequencer, false).await, Ok(()));
+        assert!(sequencer.pending_tx_hashes().is_empty());
+        assert!(matches!(
+            transaction(&mut sequencer, hashes[0]).await.unwrap().status,
+            TxStatus::Included { block: 1, .. }
+        ));
+        let health = sequencer.shared().health.lock().unwrap().clone();
+        assert_eq!(health.status, HealthStatus::Ok);
+
+        set_paused(&mut sequencer, true).await.unwrap();
+        submit(&mut sequencer, txs[2].clone()).await.unwrap();
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer.handle(Trigger::DropAll(reply_sx)).await;
+        assert_eq!(reply_rx.await.unwrap(), 1);
+        assert!(sequencer.pending_tx_hashes().is_empty());
+        assert_eq!(
+            transaction(&mut sequencer, hashes[2]).await.unwrap().status,
+            TxStatus::Rejected {
+                reason: "Dropped by an operator".to_string()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn transaction_status() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.min_tx_block = usize::MAX;
+        config.dev_account_balance = 1000;
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
+
+        let transfer = signed_dev_transfer(0, 1, 10, None);
+        let overspend = signed_dev_transfer(3, 1, 2000, None);
+        let (transfer_hash, overspend_hash) = (hash_tx(&transfer.tx), hash_tx(&overspend.tx));
+        assert!(transaction(&mut sequencer, transfer_hash).await.is_none());
+        for tx in [transfer, overspend] {
+            submit(&mut sequencer, tx).await.unwrap();
+        }
+        let info = transaction(&mut sequencer, transfer_hash).await.unwrap();
+        assert_eq!(info.hash, transfer_hash);
+        assert_eq!(info.status, TxStatus::Pending);
+
+        let mut events = sequencer.shared().events.subscribe();
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer.handle(Trigger::Flush(reply_sx)).await;
+        assert_eq!(reply_rx.await.unwrap(), Ok(FlushResult::Block(1)));
+        assert!(matches!(
+            events.try_recv(),
+            Ok(Event::Rejected(hash)) if hash == overspend_hash
+        ));
+        match events.try_recv() {
+            Ok(Event::Block(event)) => assert_eq!(
+                event,
+                BlockEvent {
+                    block: 1,
+                    state_root: sequencer.state().root(),
+                    l1_tx_hash: None
+                }
+            ),
+            event => panic!("expected the block, got {event:?}"),
+        }
+        let info = transaction(&mut sequencer, transfer_hash).await.unwrap();
+        assert_eq!(
+            info.status,
+            TxStatus::Included {
+                block: 1,
+                l1_tx_hashes: vec![]
+            }
+        );
+        assert_eq!(hash_tx(&info.tx.unwrap().tx), transfer_hash);
+        let info = transaction(&mut sequencer, overspend_hash).await.unwrap();
+        assert_eq!(
+            info.status,
+            TxStatus::Rejected {
+                reason: "Insufficient balance".to_string()
+            }
+        );
+
+        // Pruned blocks keep the status but not the transaction.
+        sequencer.shared().blocks.lock().unwrap()[0].txs.clear();
+        let info = transaction(&mut sequencer, transfer_hash).await.unwrap();
+        assert!(info.tx.is_none());
+    }
+
+    #[tokio::test]
+    async fn maintenance() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.min_tx_block = 1;
+        config.block_retention = 2;
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
+        for _ in 0..5 {
+            faucet_block(&mut sequencer).await;
+        }
+        let blocks = sequencer.shared().blocks.lock().unwrap().clone();
+        // The proof of block 2 is still waiting for L1.
+        sequencer
+            .submissions
+            .push(2, fusion::TxProof::default(), 0)
+            .unwrap();
+
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer.handle(Trigger::Maintain(reply_sx)).await;
+        let stats = reply_rx.await.unwrap();
+        assert_eq!(stats.pruned_through, 1);
+        assert!(stats.reclaimed_bytes > 0);
+        assert_eq!(sequencer.shared().health.lock().unwrap().storage, stats);
+
+        let pruned = sequencer.shared().blocks.lock().unwrap().clone();
+        assert_eq!(pruned.len(), 5);
+        assert!(pruned[0].is_pruned());
+        assert!(pruned[1..].iter().all(|block| !block.is_pruned()));
+        // Headers and inclusion proofs survive the pruning.
+        assert_eq!(pruned[0].header, blocks[0].header);
+        assert!(pruned[0].inclusion_proof(&blocks[0].tx_hashes[0]).is_some());
+
+        // Once L1 accepted it, block 2 goes as well, the last two stay.
+        sequencer.submissions = SubmissionQueue::in_memory();
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer.handle(Trigger::Maintain(reply_sx)).await;
+        let stats = reply_rx.await.unwrap();
+        assert_eq!(stats.pruned_through, 3);
+        let pruned = sequencer.shared().blocks.lock().unwrap().clone();
+        let kept: Vec<_> = pruned.iter().map(|block| !block.is_pruned()).collect();
+        assert_eq!(kept, [false, false, false, true, true]);
+        assert_eq!(stats.blocks_bytes, maintenance::blocks_size(&pruned));
+    }
 
-async fn request_proof(
-    config: Config,
-    tx: SignedTx,
-    pre_state: State,
-    post_state: State,
-) -> anyhow::Result<fusion::TxProof, String> {
-    Prover::prove(&config, &tx, &pre_state, &post_state)
-}
+    #[test]
+    fn timestamp_bounds() {
+        assert_eq!(assign_timestamp(100, None, None), Ok(100));
+        assert_eq!(assign_timestamp(100, Some(99), None), Ok(100));
+        assert_eq!(assign_timestamp(100, Some(100), None), Ok(101));
+        assert_eq!(assign_timestamp(50, Some(100), None), Ok(101));
+        assert_eq!(assign_timestamp(200, Some(100), Some(150)), Ok(150));
+        assert_eq!(assign_timestamp(50, Some(100), Some(150)), Ok(101));
+        assert!(assign_timestamp(200, Some(150), Some(150)).is_err());
+    }
 
-pub async fn run_sequencer(
-    config: &Config,
-    mut rx: mpsc::Receiver<SignedTx>,
-) -> anyhow::Result<()> {
-    let db_path = Path::new(&config.database_path);
-    let mempool = init_mempool(db_path);
+    static CLOCK: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
 
-    let mut state = State::default();
-    let l1_contract = init_l1(config).await.unwrap();
+    fn test_clock() -> u64 {
+        CLOCK.load(std::sync::atomic::Ordering::SeqCst)
+    }
 
-    while let Some(tx) = rx.recv().await {
-        let current_root = l1_contract.root().call().await.unwrap();
-        println!("Current root is {current_root}");
+    #[tokio::test]
+    async fn monotonic_timestamps() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.min_tx_block = 1;
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
+        sequencer.clock = test_clock;
 
-        {
-            let mut unlocked_mempool = mempool.lock().unwrap();
-            unlocked_mempool.push(tx);
-            if unlocked_mempool.len() < config.min_tx_block {
-                continue;
-            }
+        // The clock goes back by an hour after the first block, then
+        // catches up.
+        for now in [1_000_000, 996_400, 996_400, 1_000_001, 1_000_100] {
+            CLOCK.store(now, std::sync::atomic::Ordering::SeqCst);
+            faucet_block(&mut sequencer).await;
         }
-
-        let txs: Vec<_> = mempool
+        let timestamps: Vec<_> = sequencer
+            .shared()
+            .blocks
             .lock()
             .unwrap()
-            .drain(..)
-            .filter(|tx| validate_tx(&state, tx).is_ok())
+            .iter()
+            .map(|block| block.header.timestamp)
             .collect();
+        assert_eq!(
+            timestamps,
+            vec![1_000_000, 1_000_001, 1_000_002, 1_000_003, 1_000_100]
+        );
 
-        let mut states = vec![state.clone()];
-        for tx in &txs {
-            states.push(apply_tx(states.last().unwrap().clone(), &tx.tx));
-        }
+        // Blocks are still built when the clock drifts from L1, but the
+        // sequencer is reported degraded.
+        sequencer.clock_drift = Some(-120);
+        sequencer.set_health(HealthStatus::Ok, None);
+        let health = sequencer.shared().health.lock().unwrap().clone();
+        assert_eq!(health.status, HealthStatus::Degraded);
+        assert_eq!(health.clock_drift, Some(-120));
+        sequencer.clock_drift = Some(30);
+        sequencer.set_health(HealthStatus::Ok, None);
+        assert_eq!(
+            sequencer.shared().health.lock().unwrap().status,
+            HealthStatus::Ok
+        );
+    }
 
-        state = states.last().unwrap().clone();
-        println!("Computed L2 state root is {:?}", state.root());
+    #[tokio::test]
+    async fn expired_commitment() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.min_tx_block = 1;
+        config.reveal_window = 2;
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
 
-        println!("Requesting {} proof(s)...", states.len() - 1);
-        let mut tasks = vec![];
-        states.windows(2).zip(txs.iter()).for_each(|(states, tx)| {
-            tasks.push(tokio::spawn(request_proof(
-                config.clone(),
-                tx.clone(),
-                states[0].clone(),
-                states[1].clone(),
-            )))
-        });
+        let (tx, _) = dev_transfer(1, 500);
+        let salt = U256::from(7);
+        assert_eq!(
+            commit(&mut sequencer, tx_commitment(&tx.tx, salt)).await,
+            Ok(1)
+        );
+        let (late_tx, _) = dev_transfer(2, 500);
+        assert_eq!(
+            commit(&mut sequencer, tx_commitment(&late_tx.tx, salt)).await,
+            Ok(1)
+        );
 
-        let mut proofs = vec![];
-        for task in tasks {
-            proofs.push(task.await.unwrap());
-        }
+        faucet_block(&mut sequencer).await;
+        faucet_block(&mut sequencer).await;
+        // Still within the window, can be included in block 3.
+        assert_eq!(reveal(&mut sequencer, tx, salt).await, Ok(()));
+        assert_eq!(sequencer.block_number(), 3);
 
-        println!("Received all proofs.");
-        for proof in proofs {
-            match proof {
-                Err(e) => println!("Could not generate proof: {e}"),
-                Ok(proof) => {
-                    println!("Submiting block");
-                    l1_contract
-                        .submit_block([proof])
-                        .gas(1000000)
-                        .send()
-                        .await
-                        .unwrap();
-                    println!("Block sent!");
-                }
-            };
-        }
+        assert!(reveal(&mut sequencer, late_tx, salt)
+            .await
+            .unwrap_err()
+            .contains("expire after 2 blocks"));
     }
 
-    Ok(())
-}
+    #[tokio::test]
+    async fn commitments_full() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.min_tx_block = 1;
+        config.reveal_window = 1;
+        config.max_pending_commitments = 2;
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
 
-fn validate_tx(state: &State, tx: &SignedTx) -> anyhow::Result<()> {
-    verify_tx_signature(tx)?;
+        assert_eq!(commit(&mut sequencer, 1.into()).await, Ok(1));
+        assert_eq!(commit(&mut sequencer, 2.into()).await, Ok(1));
+        let e = commit(&mut sequencer, 3.into()).await.unwrap_err();
+        assert_eq!(e.code(), -32008);
+        assert_eq!(e, SubmitError::CommitmentsFull { max: 2 });
 
-    let sender_pk: PublicKey = tx.tx.sender.into();
-    let sender_addr = sender_pk.address();
-
-    let account = state.get(&sender_addr);
-    if matches!(tx.tx.kind, TxKind::Transfer) && tx.tx.sender == tx.tx.to {
-        Err(anyhow::anyhow!("Tx to self"))
-    } else if matches!(tx.tx.kind, TxKind::Transfer | TxKind::Withdraw)
-        && account.balance < tx.tx.value
-    {
-        Err(anyhow::anyhow!("Insufficient balance"))
-    } else if account.nonce >= tx.tx.nonce {
-        Err(anyhow::anyhow!("Nonce too low"))
-    } else {
-        Ok(())
+        // Room again once the unrevealed ones expire.
+        faucet_block(&mut sequencer).await;
+        faucet_block(&mut sequencer).await;
+        assert_eq!(commit(&mut sequencer, 3.into()).await, Ok(3));
     }
-}
 
-fn apply_tx(mut state: State, tx: &Tx) -> State {
-    let sender_pk: PublicKey = tx.sender.into();
-    let sender_addr = sender_pk.address();
+    #[test]
+    fn attestation_pages() {
+        let shared = Shared::default();
+        for block_number in 1..=3 {
+            shared.attestations.lock().unwrap().push(Attestation {
+                block_number,
+                block_hash: U256::zero(),
+                state_root: U256::zero(),
+                l1_tx_hash: H256::zero(),
+                signature: String::new(),
+            });
+        }
+        let numbers = |page: Vec<Attestation>| -> Vec<u64> {
+            page.iter()
+                .map(|attestation| attestation.block_number)
+                .collect()
+        };
+        assert_eq!(numbers(shared.attestations(0, 2).unwrap()), vec![1, 2]);
+        assert_eq!(numbers(shared.attestations(2, 2).unwrap()), vec![3]);
+        assert!(shared.attestations(5, 2).unwrap().is_empty());
+        assert!(shared
+            .attestations(0, MAX_ATTESTATIONS_PER_REQUEST + 1)
+            .is_err());
+    }
 
-    let to_pk: PublicKey = tx.to.into();
-    let to_addr = to_pk.address();
+    #[test]
+    fn proving_stats_window() {
+        let shared = Shared::default();
+        assert_eq!(shared.proving_stats(), ProvingStats::default());
+        for block in 1..=MAX_TIMED_BLOCKS as u64 + 10 {
+            shared.record_timings(BlockTimings {
+                block,
+                txs: 1,
+                inputs_ms: 1,
+                witness_ms: block,
+                prove_ms: 10 * block,
+                proving_ms: 10 * block,
+                submit_ms: 5,
+            });
+        }
+        let stats = shared.proving_stats();
+        assert_eq!(stats.blocks.len(), MAX_TIMED_BLOCKS);
+        assert_eq!(stats.blocks[0].block, 11);
+        assert_eq!(stats.witness.p50, 60);
+        assert_eq!(stats.witness.p90, 100);
+        assert_eq!(stats.prove.max, 1100);
+        assert_eq!(
+            stats.submit,
+            StageStats {
+                p50: 5,
+                p90: 5,
+                max: 5
+            }
+        );
+    }
 
-    let account_sender = state.get(&sender_addr);
-    let account_to = state.get(&to_addr);
+    #[tokio::test]
+    async fn tuned_batch_size() {
+        let config = Config {
+            dev: true,
+            min_tx_block: 100,
+            auto_tune_batch: true,
+            min_batch_size: 2,
+            max_batch_size: 8,
+            ..Default::default()
+        };
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
+        let shared = sequencer.shared();
+        assert_eq!(sequencer.batch_size(), 8);
+        assert_eq!(shared.info.lock().unwrap().batch_size, 8);
 
-    let new_account_sender = match tx.kind {
-        TxKind::Deposit => Account::new(sender_addr, account_sender.balance + tx.value, tx.nonce),
-        TxKind::Transfer | TxKind::Withdraw => {
-            Account::new(sender_addr, account_sender.balance - tx.value, tx.nonce)
-        }
-    };
-    let new_account_to = match tx.kind {
-        TxKind::Transfer => Account::new(to_addr, account_to.balance + tx.value, account_to.nonce),
-        TxKind::Withdraw | TxKind::Deposit => account_to,
-    };
+        sequencer.tune(8, Duration::from_secs(2 * config.target_proving_time));
+        assert_eq!(sequencer.batch_size(), 4);
+        assert_eq!(shared.info.lock().unwrap().batch_size, 4);
 
-    state.update(&sender_addr, new_account_sender);
-    state.update(&to_addr, new_account_to);
+        let config = Config {
+            auto_tune_batch: false,
+            ..config
+        };
+        assert_eq!(Sequencer::new(&config).await.unwrap().batch_size(), 100);
+    }
 
-    state
-}
+    #[tokio::test]
+    async fn account_stats() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.min_tx_block = 1;
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
+        let shared = sequencer.shared();
+        let stats = |key: &U256| shared.account_stats.lock().unwrap().get(key).cloned();
+        let dev_0 = fusion_wallet::new_public_key(&dev::dev_private_key(0)).to_u256();
 
-fn verify_tx_signature(signed_tx: &SignedTx) -> anyhow::Result<()> {
-    fusion_wallet::verify_tx_signature(signed_tx)
-}
+        let (tx, first_recipient) = dev_transfer(1, 500);
+        sequencer.handle(Trigger::Tx(tx)).await;
+        let (tx, second_recipient) = dev_transfer(2, 300);
+        sequencer.handle(Trigger::Tx(tx)).await;
+        assert_eq!(sequencer.block_number(), 2);
 
-fn init_mempool(_path: &Path) -> MemPool {
-    Arc::new(Mutex::new(vec![]))
-}
+        assert_eq!(
+            stats(&dev_0),
+            Some(AccountStats {
+                sent_count: 2,
+                received_count: 0,
+                total_sent: 800.into(),
+                total_received: U256::zero(),
+                first_active_block: Some(1),
+                last_active_block: Some(2),
+            })
+        );
+        let first = stats(&first_recipient).unwrap();
+        assert_eq!(
+            (first.received_count, first.total_received),
+            (1, 500.into())
+        );
+        assert_eq!(
+            (first.first_active_block, first.last_active_block),
+            (Some(1), Some(1))
+        );
+        assert_eq!(
+            stats(&second_recipient).unwrap().first_active_block,
+            Some(2)
+        );
 
-async fn init_l1(
-    config: &Config,
-) -> anyhow::Result<fusion::Fusion<ethers::middleware::SignerMiddleware<Provider<Http>, LocalWallet>>>
-{
-    let node = Arc::new(
-        Node::new_with_private_key(config.eth_private_key.clone(), config.eth_rpc_url.clone())
-            .await?,
-    );
+        // Rolling the blocks back drops what they counted.
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer.handle(Trigger::Reset(reply_sx)).await;
+        assert_eq!(reply_rx.await.unwrap(), Ok(()));
+        assert!(stats(&dev_0).is_none());
 
-    let l1_contract = fusion::Fusion::new(config.fusion_l1_contract, node.http_client.clone());
+        let (tx, _) = dev_transfer(1, 200);
+        sequencer.handle(Trigger::Tx(tx)).await;
+        let dev_0_stats = stats(&dev_0).unwrap();
+        assert_eq!(
+            (dev_0_stats.sent_count, dev_0_stats.total_sent),
+            (1, 200.into())
+        );
+        assert_eq!(dev_0_stats.first_active_block, Some(1));
+        assert!(stats(&first_recipient).is_none());
+    }
 
-    Ok(l1_contract)
-}
+    #[tokio::test]
+    async fn account_history() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.min_tx_block = 1;
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
+        let shared = sequencer.shared();
+        let dev_0 = fusion_wallet::new_public_key(&dev::dev_private_key(0)).to_u256();
+        let history = |address: U256, offset: usize| {
+            shared.account_history(address, (1, u64::MAX), offset, MAX_HISTORY_PER_REQUEST)
+        };
 
-#[cfg(test)]
-mod test {
-    use super::*;
+        let (tx, recipient) = dev_transfer(1, 500);
+        sequencer.handle(Trigger::Tx(tx)).await;
+        let (tx, _) = dev_transfer(2, 300);
+        sequencer.handle(Trigger::Tx(tx)).await;
 
-    use anvil::{spawn, NodeConfig};
-    use ethers::abi::AbiDecode;
-    use ethers::prelude::*;
-    use ethers::providers::Middleware;
-    use ethers::types;
+        let records = history(dev_0, 0).unwrap();
+        let batches: Vec<_> = records.iter().map(|record| record.batch).collect();
+        assert_eq!(batches, [1, 2]);
+        let first = &records[0];
+        assert_eq!((first.to, first.value), (recipient, 500.into()));
+        assert_eq!(
+            first.from_balance_before - first.from_balance_after,
+            500.into()
+        );
+        assert_eq!(
+            (first.to_balance_before, first.to_balance_after),
+            (U256::zero(), 500.into())
+        );
+        assert_eq!(records[1].from_balance_before, first.from_balance_after);
+        assert_eq!(history(dev_0, 1).unwrap(), records[1..]);
+        assert_eq!(history(recipient, 0).unwrap(), records[..1]);
+        assert!(shared
+            .account_history(dev_0, (1, 1), 0, MAX_HISTORY_PER_REQUEST + 1)
+            .is_err());
 
-    use tokio::sync::mpsc;
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer.handle(Trigger::Reset(reply_sx)).await;
+        assert_eq!(reply_rx.await.unwrap(), Ok(()));
+        assert!(history(dev_0, 0).unwrap().is_empty());
+    }
 
-    use fusion_types::ToU256;
-    use fusion_wallet;
+    #[tokio::test]
+    async fn reset_outside_dev_mode() {
+        let mut config = Config::default();
+        config.dev = true;
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
+        sequencer.config.dev = false;
 
-    #[test]
-    fn state_update_test() {
-        let state = State::default();
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer.handle(Trigger::Reset(reply_sx)).await;
+        assert!(reply_rx.await.unwrap().is_err());
+    }
 
-        let (_sk_1, pk_1) = fusion_wallet::new_key_pair();
-        let (_sk_2, pk_2) = fusion_wallet::new_key_pair();
+    #[tokio::test]
+    async fn genesis_file() {
+        let (sk, pk) = fusion_wallet::new_key_pair();
+        let path =
+            std::env::temp_dir().join(format!("fusion-genesis-{}.json", rand::random::<u64>()));
+        let genesis = format!(r#"{{"{}": {{"balance": 500, "nonce": 4}}}}"#, pk.address());
+        std::fs::write(&path, genesis).unwrap();
+        let mut config = Config::default();
+        config.genesis_path = path.display().to_string();
+        let e = Sequencer::new(&config).await.err().unwrap().to_string();
+        assert!(e.contains("only read in dev mode"), "{e}");
 
-        let tx_1 = fusion_api::Tx {
-            kind: TxKind::Deposit,
-            sender: pk_1.clone().to_u256(),
-            to: 0.into(),
-            nonce: 1.into(),
-            value: 1000.into(),
-        };
+        config.dev = true;
+        config.min_tx_block = usize::MAX;
+        let mut sequencer = Sequencer::new(&config).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let genesis_root = sequencer.state().root();
+        assert_ne!(genesis_root, genesis_state(&config).root());
+        assert_eq!(balance(&mut sequencer, pk.to_u256()).await, (500, 500));
+        assert_eq!(nonce(&mut sequencer, pk.to_u256()).await, (4, 4, 5));
 
-        let tx_2 = fusion_api::Tx {
+        let tx = fusion_api::Tx {
             kind: TxKind::Transfer,
-            sender: pk_1.clone().to_u256(),
-            to: pk_2.clone().to_u256(),
-            nonce: 2.into(),
-            value: 500.into(),
-        };
-
-        let tx_3 = fusion_api::Tx {
-            kind: TxKind::Withdraw,
-            sender: pk_2.clone().to_u256(),
-            to: 0.into(),
-            nonce: 3.into(),
+            sender: pk.to_u256(),
+            to: fusion_wallet::new_public_key(&dev::dev_private_key(0)).to_u256(),
+            nonce: 5.into(),
             value: 200.into(),
+            not_before: None,
+            not_after: None,
+            chain_id: None,
+            token: NATIVE_TOKEN,
         };
+        let signature = fusion_wallet::sign(&tx, sk.to_string())
+            .unwrap()
+            .to_string();
+        submit(&mut sequencer, SignedTx { tx, signature })
+            .await
+            .unwrap();
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer.handle(Trigger::Flush(reply_sx)).await;
+        assert_eq!(reply_rx.await.unwrap(), Ok(FlushResult::Block(1)));
+        assert_eq!(balance(&mut sequencer, pk.to_u256()).await, (300, 300));
+        assert_eq!(nonce(&mut sequencer, pk.to_u256()).await, (5, 5, 6));
 
-        let state = apply_tx(state, &tx_1);
-        let state = apply_tx(state, &tx_2);
-        let state = apply_tx(state, &tx_3);
-
-        let acc_1 = state.get(&pk_1.address());
-        let acc_2 = state.get(&pk_2.address());
+        // Back to the genesis of the file, read when the sequencer started.
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer.handle(Trigger::Reset(reply_sx)).await;
+        assert_eq!(reply_rx.await.unwrap(), Ok(()));
+        assert_eq!(sequencer.state().root(), genesis_root);
+    }
 
-        assert_eq!(acc_1.balance, 500.into());
-        assert_eq!(acc_2.balance, 300.into());
+    /// Config of a sequencer using the anvil node behind `handle`, with
+    /// the contract deployed the way `--dev-l1` does.
+    async fn dev_l1_config(
+        anvil_config: &NodeConfig,
+        handle: &NodeHandle,
+    ) -> (Config, fusion::Fusion<Provider<Http>>) {
+        let mut fusion_config = Config::default();
+        fusion_config.eth_rpc_url = handle.http_endpoint();
+        let wallet = &anvil_config.genesis_accounts[0];
+        fusion_config.eth_private_key = hex::encode(wallet.signer().to_bytes());
+        fusion_config.database_path = std::env::temp_dir()
+            .join(format!("fusion-test-{}", rand::random::<u64>()))
+            .display()
+            .to_string();
+        fusion_config.dev_l1 = true;
+        fusion_config.fusion_l1_contract = dev_l1_contract(&fusion_config).await.unwrap();
 
-        assert_eq!(acc_1.nonce, 2.into());
-        assert_eq!(acc_2.nonce, 3.into());
+        let contract = fusion::Fusion::new(
+            fusion_config.fusion_l1_contract,
+            handle.http_provider().into(),
+        );
+        (fusion_config, contract)
     }
 
     #[tokio::test(flavor = "multi_thread")]
@@ -240,23 +5182,12 @@ mod test {
         let (_api, handle) = spawn(anvil_config.clone()).await;
         let provider = handle.http_provider();
 
-        let contract = fusion::Fusion::deploy(provider.clone().into(), ())
-            .unwrap()
-            .gas(10000000)
-            .send()
-            .await
-            .unwrap();
-
-        let mut fusion_config = Config::default();
-        fusion_config.eth_rpc_url = handle.http_endpoint();
-        fusion_config.fusion_l1_contract = contract.address();
-        let wallet = &anvil_config.genesis_accounts[0];
-        fusion_config.eth_private_key = hex::encode(wallet.signer().to_bytes());
+        let (mut fusion_config, contract) = dev_l1_config(&anvil_config, &handle).await;
         fusion_config.min_tx_block = 1;
 
         assert_eq!(contract.root().call().await.unwrap(), 0.into());
 
-        let (sx, rx): (mpsc::Sender<SignedTx>, mpsc::Receiver<SignedTx>) = mpsc::channel(1024);
+        let (sx, rx): (mpsc::Sender<Trigger>, mpsc::Receiver<Trigger>) = mpsc::channel(1024);
 
         tokio::spawn(async move {
             run_sequencer(&fusion_config, rx).await.unwrap();
@@ -275,6 +5206,10 @@ mod test {
                     to: pk_2.clone().to_u256(),
                     nonce: (i + 1).into(),
                     value: 0.into(),
+                    not_before: None,
+                    not_after: None,
+                    chain_id: None,
+                    token: NATIVE_TOKEN,
                 };
                 let sig = fusion_wallet::sign(&tx, sk_1.to_string()).unwrap();
                 let signed_tx = fusion_api::SignedTx {
@@ -283,7 +5218,7 @@ mod test {
                 };
                 // TODO: fix this hack somehow
                 // Wait until rx starts listening.
-                while sx.send(signed_tx.clone()).await.is_err() {}
+                while sx.send(Trigger::Tx(signed_tx.clone())).await.is_err() {}
             }
         });
 
@@ -303,23 +5238,12 @@ mod test {
         let (_api, handle) = spawn(anvil_config.clone()).await;
         let provider = handle.http_provider();
 
-        let contract = fusion::Fusion::deploy(provider.clone().into(), ())
-            .unwrap()
-            .gas(10000000)
-            .send()
-            .await
-            .unwrap();
-
-        let mut fusion_config = Config::default();
-        fusion_config.eth_rpc_url = handle.http_endpoint();
-        fusion_config.fusion_l1_contract = contract.address();
-        let wallet = &anvil_config.genesis_accounts[0];
-        fusion_config.eth_private_key = hex::encode(wallet.signer().to_bytes());
+        let (mut fusion_config, contract) = dev_l1_config(&anvil_config, &handle).await;
         fusion_config.min_tx_block = 1;
 
         assert_eq!(contract.root().call().await.unwrap(), 0.into());
 
-        let (sx, rx): (mpsc::Sender<SignedTx>, mpsc::Receiver<SignedTx>) = mpsc::channel(1024);
+        let (sx, rx): (mpsc::Sender<Trigger>, mpsc::Receiver<Trigger>) = mpsc::channel(1024);
 
         tokio::spawn(async move {
             run_sequencer(&fusion_config, rx).await.unwrap();
@@ -353,6 +5277,10 @@ mod test {
                 to: 0.into(),
                 nonce: 1.into(),
                 value: deposit_amt.into(),
+                not_before: None,
+                not_after: None,
+                chain_id: None,
+                token: NATIVE_TOKEN,
             };
             let sig = fusion_wallet::sign(&tx, sk_1.to_string()).unwrap();
             let signed_tx = fusion_api::SignedTx {
@@ -361,7 +5289,7 @@ mod test {
             };
             // TODO: fix this hack somehow
             // Wait until rx starts listening.
-            while sx.send(signed_tx.clone()).await.is_err() {}
+            while sx.send(Trigger::Tx(signed_tx.clone())).await.is_err() {}
         });
 
         let mut state = State::default();
@@ -389,23 +5317,12 @@ mod test {
         let (_api, handle) = spawn(anvil_config.clone()).await;
         let provider = handle.http_provider();
 
-        let contract = fusion::Fusion::deploy(provider.clone().into(), ())
-            .unwrap()
-            .gas(10000000)
-            .send()
-            .await
-            .unwrap();
-
-        let mut fusion_config = Config::default();
-        fusion_config.eth_rpc_url = handle.http_endpoint();
-        fusion_config.fusion_l1_contract = contract.address();
-        let wallet = &anvil_config.genesis_accounts[0];
-        fusion_config.eth_private_key = hex::encode(wallet.signer().to_bytes());
+        let (mut fusion_config, contract) = dev_l1_config(&anvil_config, &handle).await;
         fusion_config.min_tx_block = 1;
 
         assert_eq!(contract.root().call().await.unwrap(), 0.into());
 
-        let (sx, rx): (mpsc::Sender<SignedTx>, mpsc::Receiver<SignedTx>) = mpsc::channel(1024);
+        let (sx, rx): (mpsc::Sender<Trigger>, mpsc::Receiver<Trigger>) = mpsc::channel(1024);
 
         tokio::spawn(async move {
             run_sequencer(&fusion_config, rx).await.unwrap();
@@ -440,6 +5357,10 @@ mod test {
                     to: 0.into(),
                     nonce: i.into(),
                     value: 300.into(),
+                    not_before: None,
+                    not_after: None,
+                    chain_id: None,
+                    token: NATIVE_TOKEN,
                 };
                 let sig = fusion_wallet::sign(&tx, sk_1.to_string()).unwrap();
                 let signed_tx = fusion_api::SignedTx {
@@ -448,7 +5369,7 @@ mod test {
                 };
                 // TODO: fix this hack somehow
                 // Wait until rx starts listening.
-                while sx.send(signed_tx.clone()).await.is_err() {}
+                while sx.send(Trigger::Tx(signed_tx.clone())).await.is_err() {}
             }
         });
 
@@ -477,23 +5398,12 @@ mod test {
         let (_api, handle) = spawn(anvil_config.clone()).await;
         let provider = handle.http_provider();
 
-        let contract = fusion::Fusion::deploy(provider.clone().into(), ())
-            .unwrap()
-            .gas(10000000)
-            .send()
-            .await
-            .unwrap();
-
-        let mut fusion_config = Config::default();
-        fusion_config.eth_rpc_url = handle.http_endpoint();
-        fusion_config.fusion_l1_contract = contract.address();
-        let wallet = &anvil_config.genesis_accounts[0];
-        fusion_config.eth_private_key = hex::encode(wallet.signer().to_bytes());
+        let (mut fusion_config, contract) = dev_l1_config(&anvil_config, &handle).await;
         fusion_config.min_tx_block = 1;
 
         assert_eq!(contract.root().call().await.unwrap(), 0.into());
 
-        let (sx, rx): (mpsc::Sender<SignedTx>, mpsc::Receiver<SignedTx>) = mpsc::channel(1024);
+        let (sx, rx): (mpsc::Sender<Trigger>, mpsc::Receiver<Trigger>) = mpsc::channel(1024);
 
         tokio::spawn(async move {
             run_sequencer(&fusion_config, rx).await.unwrap();
@@ -543,6 +5453,10 @@ mod test {
                 to: 0.into(),
                 nonce: 1.into(),
                 value: 1000.into(),
+                not_before: None,
+                not_after: None,
+                chain_id: None,
+                token: NATIVE_TOKEN,
             };
             let sig = fusion_wallet::sign(&tx, sk_1.to_string()).unwrap();
             let signed_tx = fusion_api::SignedTx {
@@ -551,7 +5465,7 @@ mod test {
             };
             // TODO: fix this hack somehow
             // Wait until rx starts listening.
-            while sx.send(signed_tx.clone()).await.is_err() {}
+            while sx.send(Trigger::Tx(signed_tx.clone())).await.is_err() {}
 
             let tx = fusion_api::Tx {
                 kind: TxKind::Transfer,
@@ -559,6 +5473,10 @@ mod test {
                 to: pk_2.clone().to_u256(),
                 nonce: 2.into(),
                 value: 600.into(),
+                not_before: None,
+                not_after: None,
+                chain_id: None,
+                token: NATIVE_TOKEN,
             };
             let sig = fusion_wallet::sign(&tx, sk_1.to_string()).unwrap();
             let signed_tx = fusion_api::SignedTx {
@@ -567,7 +5485,7 @@ mod test {
             };
             // TODO: fix this hack somehow
             // Wait until rx starts listening.
-            while sx.send(signed_tx.clone()).await.is_err() {}
+            while sx.send(Trigger::Tx(signed_tx.clone())).await.is_err() {}
 
             let tx = fusion_api::Tx {
                 kind: TxKind::Withdraw,
@@ -575,6 +5493,10 @@ mod test {
                 to: l1_recipient_addr.to_low_u64_be().into(),
                 nonce: 3.into(),
                 value: 100.into(),
+                not_before: None,
+                not_after: None,
+                chain_id: None,
+                token: NATIVE_TOKEN,
             };
             let sig = fusion_wallet::sign(&tx, sk_2.to_string()).unwrap();
             let signed_tx = fusion_api::SignedTx {
@@ -583,7 +5505,7 @@ mod test {
             };
             // TODO: fix this hack somehow
             // Wait until rx starts listening.
-            while sx.send(signed_tx.clone()).await.is_err() {}
+            while sx.send(Trigger::Tx(signed_tx.clone())).await.is_err() {}
         });
 
         let mut state = State::default();
@@ -615,6 +5537,235 @@ mod test {
         assert_eq!(contract.root().call().await.unwrap(), state.root());
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn chaos_keeps_every_transaction() {
+        let anvil_config = NodeConfig::test();
+
+        let (_api, handle) = spawn(anvil_config.clone()).await;
+
+        let (mut fusion_config, contract) = dev_l1_config(&anvil_config, &handle).await;
+        fusion_config.min_tx_block = 1;
+        // The standard chaos profile, after one fault of each kind.
+        fusion_config.chaos = ChaosProfile {
+            enabled: true,
+            seed: 42,
+            script: vec![Fault::Submission, Fault::Prover, Fault::Storage],
+            ..Default::default()
+        };
+
+        let (sx, rx): (mpsc::Sender<Trigger>, mpsc::Receiver<Trigger>) = mpsc::channel(1024);
+
+        tokio::spawn(async move {
+            run_sequencer(&fusion_config, rx).await.unwrap();
+        });
+
+        let (sk_1, pk_1) = fusion_wallet::new_key_pair();
+        let (_sk_2, pk_2) = fusion_wallet::new_key_pair();
+        let deposit_amt = 1000;
+        contract
+            .deposit(pk_1.address())
+            .value(deposit_amt)
+            .gas(1000000)
+            .send()
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+
+        let deposit = fusion_api::Tx {
+            kind: TxKind::Deposit,
+            sender: pk_1.to_u256(),
+            to: 0.into(),
+            nonce: 1.into(),
+            value: deposit_amt.into(),
+            not_before: None,
+            not_after: None,
+            chain_id: None,
+            token: NATIVE_TOKEN,
+        };
+        let transfers = (2..6).map(|nonce| fusion_api::Tx {
+            kind: TxKind::Transfer,
+            sender: pk_1.to_u256(),
+            to: pk_2.to_u256(),
+            nonce: nonce.into(),
+            value: 100.into(),
+            not_before: None,
+            not_after: None,
+            chain_id: None,
+            token: NATIVE_TOKEN,
+        });
+        let txs: Vec<_> = std::iter::once(deposit).chain(transfers).collect();
+        for tx in &txs {
+            let signed_tx = fusion_api::SignedTx {
+                tx: tx.clone(),
+                signature: fusion_wallet::sign(tx, sk_1.to_string())
+                    .unwrap()
+                    .to_string(),
+            };
+            // Wait until rx starts listening.
+            while sx.send(Trigger::Tx(signed_tx.clone())).await.is_err() {}
+        }
+
+        // No value was created or lost on L2.
+        let expected = txs.iter().fold(State::default(), apply_tx);
+        let balances = [&pk_1, &pk_2].map(|pk| expected.get(&pk.address()).balance);
+        assert_eq!(balances[0] + balances[1], deposit_amt.into());
+
+        // L1 ends up with every transaction despite the faults.
+        tokio::time::timeout(Duration::from_secs(600), async {
+            while contract.root().call().await.unwrap() != expected.root() {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        })
+        .await
+        .expect("L1 never reached the state with every transaction");
+        assert_eq!(
+            contract.deposits(pk_1.address()).call().await.unwrap(),
+            0.into()
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn flush_while_idle() {
+        let anvil_config = NodeConfig::test();
+
+        let (_api, handle) = spawn(anvil_config.clone()).await;
+        let (mut fusion_config, contract) = dev_l1_config(&anvil_config, &handle).await;
+        // Never reached, blocks are only built by flushing.
+        fusion_config.min_tx_block = 100;
+
+        let (sx, rx): (mpsc::Sender<Trigger>, mpsc::Receiver<Trigger>) = mpsc::channel(1024);
+
+        tokio::spawn(async move {
+            run_sequencer(&fusion_config, rx).await.unwrap();
+        });
+
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sx.send(Trigger::Flush(reply_sx)).await.unwrap();
+        assert_eq!(reply_rx.await.unwrap(), Ok(FlushResult::NothingToBatch));
+
+        let (sk_1, pk_1) = fusion_wallet::new_key_pair();
+        let (_sk_2, pk_2) = fusion_wallet::new_key_pair();
+        let tx = fusion_api::Tx {
+            kind: TxKind::Transfer,
+            sender: pk_1.clone().to_u256(),
+            to: pk_2.clone().to_u256(),
+            nonce: 1.into(),
+            value: 0.into(),
+            not_before: None,
+            not_after: None,
+            chain_id: None,
+            token: NATIVE_TOKEN,
+        };
+        let sig = fusion_wallet::sign(&tx, sk_1.to_string()).unwrap();
+        let signed_tx = fusion_api::SignedTx {
+            tx: tx.clone(),
+            signature: sig.to_string(),
+        };
+        sx.send(Trigger::Tx(signed_tx)).await.unwrap();
+
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sx.send(Trigger::Flush(reply_sx)).await.unwrap();
+        assert_eq!(reply_rx.await.unwrap(), Ok(FlushResult::Block(1)));
+
+        let state = apply_tx(State::default(), &tx).unwrap();
+        assert_eq!(contract.root().call().await.unwrap(), state.root());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn flush_during_batch() {
+        let anvil_config = NodeConfig::test();
+
+        let (_api, handle) = spawn(anvil_config.clone()).await;
+        let (mut fusion_config, contract) = dev_l1_config(&anvil_config, &handle).await;
+        fusion_config.min_tx_block = 1;
+
+        let (sx, rx): (mpsc::Sender<Trigger>, mpsc::Receiver<Trigger>) = mpsc::channel(1024);
+
+        tokio::spawn(async move {
+            run_sequencer(&fusion_config, rx).await.unwrap();
+        });
+
+        let (sk_1, pk_1) = fusion_wallet::new_key_pair();
+        let (_sk_2, pk_2) = fusion_wallet::new_key_pair();
+        let tx = fusion_api::Tx {
+            kind: TxKind::Transfer,
+            sender: pk_1.clone().to_u256(),
+            to: pk_2.clone().to_u256(),
+            nonce: 1.into(),
+            value: 0.into(),
+            not_before: None,
+            not_after: None,
+            chain_id: None,
+            token: NATIVE_TOKEN,
+        };
+        let sig = fusion_wallet::sign(&tx, sk_1.to_string()).unwrap();
+        let signed_tx = fusion_api::SignedTx {
+            tx: tx.clone(),
+            signature: sig.to_string(),
+        };
+
+        // The transaction starts a block on its own, the flush is queued
+        // behind it and only runs once that block has been submitted.
+        sx.send(Trigger::Tx(signed_tx)).await.unwrap();
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sx.send(Trigger::Flush(reply_sx)).await.unwrap();
+        assert_eq!(reply_rx.await.unwrap(), Ok(FlushResult::NothingToBatch));
+
+        let state = apply_tx(State::default(), &tx).unwrap();
+        assert_eq!(contract.root().call().await.unwrap(), state.root());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn witness_failure_keeps_txs() {
+        let anvil_config = NodeConfig::test();
+
+        let (_api, handle) = spawn(anvil_config.clone()).await;
+        let (mut fusion_config, contract) = dev_l1_config(&anvil_config, &handle).await;
+        fusion_config.min_tx_block = 100;
+        let mut sequencer = Sequencer::new(&fusion_config).await.unwrap();
+        // A file where the witnesses directory goes, so writing one fails.
+        let witnesses = Path::new(&fusion_config.database_path).join("witnesses");
+        std::fs::write(&witnesses, b"").unwrap();
+
+        let (sk_1, pk_1) = fusion_wallet::new_key_pair();
+        let (_sk_2, pk_2) = fusion_wallet::new_key_pair();
+        let tx = fusion_api::Tx {
+            kind: TxKind::Transfer,
+            sender: pk_1.clone().to_u256(),
+            to: pk_2.clone().to_u256(),
+            nonce: 1.into(),
+            value: 0.into(),
+            not_before: None,
+            not_after: None,
+            chain_id: None,
+            token: NATIVE_TOKEN,
+        };
+        let sig = fusion_wallet::sign(&tx, sk_1.to_string()).unwrap();
+        let signed_tx = fusion_api::SignedTx {
+            tx: tx.clone(),
+            signature: sig.to_string(),
+        };
+        try_submit(&mut sequencer, signed_tx).await.unwrap();
+
+        let root = sequencer.state().root();
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer.handle(Trigger::Flush(reply_sx)).await;
+        assert_eq!(reply_rx.await.unwrap(), Ok(FlushResult::NothingToBatch));
+        assert_eq!(sequencer.pending_tx_hashes(), vec![hash_tx(&tx)]);
+        assert_eq!(sequencer.block_number(), 0);
+        assert_eq!(sequencer.state().root(), root);
+        assert_eq!(contract.root().call().await.unwrap(), root);
+
+        // Built once the witness can be written.
+        std::fs::remove_file(&witnesses).unwrap();
+        let (reply_sx, reply_rx) = oneshot::channel();
+        sequencer.handle(Trigger::Flush(reply_sx)).await;
+        assert_eq!(reply_rx.await.unwrap(), Ok(FlushResult::Block(1)));
+        assert!(sequencer.pending_tx_hashes().is_empty());
+        std::fs::remove_dir_all(&fusion_config.database_path).unwrap();
+    }
+
     async fn next_fusion_txs(contract: types::Address, provider: &Provider<Http>) -> Vec<Tx> {
         let mut stream = provider.watch_blocks().await.unwrap();
         loop {
@@ -654,6 +5805,21 @@ mod test {
             to: PublicKey::from_point(tx_proof.input[5], tx_proof.input[6]).to_u256(),
             nonce: tx_proof.input[7],
             value: tx_proof.input[8],
+            not_before: timestamp_input(tx_proof.input[9]),
+            not_after: timestamp_input(tx_proof.input[10]),
+            chain_id: timestamp_input(tx_proof.input[20]),
+            token: {
+                let mut word = [0u8; 32];
+                tx_proof.input[21].to_big_endian(&mut word);
+                Address::from_slice(&word[12..])
+            },
+        }
+    }
+
+    fn timestamp_input(input: U256) -> Option<u64> {
+        match input.is_zero() {
+            true => None,
+            false => Some(input.as_u64()),
         }
     }
 }
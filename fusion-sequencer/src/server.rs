@@ -1,18 +1,175 @@
+use ethers::types::{Address, Bytes, U256};
 use fusion_api::*;
 
 use futures::{future, prelude::*};
 use tarpc::{
-    context,
+    client, context,
     server::{self, incoming::Incoming, Channel},
     tokio_serde::formats::Json,
 };
 
-use tokio::sync::mpsc;
+use tokio::sync::{broadcast::error::RecvError, mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tracing::{debug, info};
 
-use std::net::{IpAddr, SocketAddr};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant, SystemTime};
+
+use fusion_config::Config;
+use fusion_types::ToBabyJubjubSignature;
+
+use crate::metrics::Rejection;
+use crate::probes;
+use crate::rate_limit::RateLimiter;
+use crate::sequencer::{block_timestamp, Event, Shared, Trigger};
+
+/// A client of the sequencer listening at `socket_address` and
+/// `socket_port`, for the subcommands that read from a running one.
+pub async fn connect(config: &Config) -> Result<FusionRPCClient, String> {
+    let server_addr = config.socket_addr()?;
+    let transport = tarpc::serde_transport::tcp::connect(server_addr, Json::default)
+        .await
+        .map_err(|e| format!("Could not connect to the sequencer: {e}"))?;
+    Ok(FusionRPCClient::new(client::Config::default(), transport).spawn())
+}
+
+/// A client of the admin server of a sequencer running on this host.
+pub async fn connect_admin(config: &Config) -> Result<AdminRPCClient, String> {
+    if config.admin_port == 0 {
+        return Err("The admin server is off, admin_port is 0".to_string());
+    }
+    let server_addr = SocketAddr::from(([127, 0, 0, 1], config.admin_port));
+    let transport = tarpc::serde_transport::tcp::connect(server_addr, Json::default)
+        .await
+        .map_err(|e| format!("Could not connect to the admin server: {e}"))?;
+    Ok(AdminRPCClient::new(client::Config::default(), transport).spawn())
+}
+
+/// Context of a request abandoned after `rpc_request_timeout`.
+pub fn request_context(config: &Config) -> context::Context {
+    let mut ctx = context::current();
+    ctx.deadline = SystemTime::now() + Duration::from_secs(config.rpc_request_timeout);
+    ctx
+}
+
+/// Checks shared by every way of submitting a transaction, before it
+/// reaches the sequencer. That the signature is the sender's is checked
+/// by the sequencer when it admits the transaction.
+fn check_submission(tx: &SignedTx) -> Result<(), SubmitError> {
+    check_tx_fields(&tx.tx)?;
+    parse_signature(&tx.signature).map_err(SubmitError::InvalidSignature)?;
+    // Refused here rather than when its block is proven.
+    tx.signature
+        .to_babyjubjub_signature()
+        .map_err(SubmitError::InvalidSignature)?;
+    Ok(())
+}
+
+/// The checks of `check_submission` that do not need a signature, which
+/// the sequencer relies on to hash `tx` and find its accounts.
+fn check_tx_fields(tx: &Tx) -> Result<(), SubmitError> {
+    for (name, key) in [("sender", tx.sender), ("recipient", tx.to)] {
+        if !fusion_wallet::is_public_key(&key) {
+            return Err(SubmitError::Invalid(format!(
+                "The {name} {key} is not a Fusion public key"
+            )));
+        }
+    }
+    // Hashing the transaction would panic on them.
+    for (name, value) in [("nonce", tx.nonce), ("value", tx.value)] {
+        if !fusion_types::is_field_element(&value) {
+            return Err(SubmitError::Invalid(format!(
+                "The {name} {value} does not fit in a field element"
+            )));
+        }
+    }
+    // The contract pays a withdrawal to its recipient as an L1 address.
+    if matches!(tx.kind, TxKind::Withdraw) && (tx.to.is_zero() || tx.to.bits() > 160) {
+        return Err(SubmitError::Invalid(format!(
+            "The recipient {} is not an L1 address",
+            tx.to
+        )));
+    }
+    Ok(())
+}
 
 #[derive(Clone)]
-struct FusionServer(SocketAddr, mpsc::Sender<SignedTx>);
+struct FusionServer {
+    peer: SocketAddr,
+    sequencer: mpsc::Sender<Trigger>,
+    shared: Shared,
+    limiter: RateLimiter,
+    /// Blocks L1 may lag behind while the sequencer is ready.
+    max_block_lag: u64,
+}
+
+impl FusionServer {
+    async fn transaction(&self, tx_hash: U256) -> Result<TxInfo, String> {
+        let (reply_sx, reply_rx) = oneshot::channel();
+        self.sequencer
+            .send(Trigger::Transaction {
+                hash: tx_hash,
+                reply: reply_sx,
+            })
+            .await
+            .map_err(|_| "Sequencer is not running".to_string())?;
+        reply_rx
+            .await
+            .map_err(|_| "Sequencer stopped before answering".to_string())?
+            .ok_or_else(|| format!("Unknown transaction {tx_hash}"))
+    }
+
+    /// Checks and queues a submitted transaction, counting it and its
+    /// rejection in the metrics.
+    async fn submit(
+        &self,
+        tx: Result<SignedTx, SubmitError>,
+    ) -> Result<QueuePosition, SubmitError> {
+        self.shared.metrics.received();
+        let result = match self
+            .limiter
+            .check(self.peer.ip(), Instant::now())
+            .and(tx)
+            .and_then(|tx| check_submission(&tx).map(|()| tx))
+        {
+            Ok(tx) => self.queue(tx).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = &result {
+            self.shared.metrics.rejected(Rejection::of_submit(e));
+        }
+        result
+    }
+
+    /// Queues `tx` once the sequencer accepts its nonce, and returns its
+    /// hash and where it stands.
+    async fn queue(&self, tx: SignedTx) -> Result<QueuePosition, SubmitError> {
+        let hash = hash_tx(&tx.tx);
+        let (reply_sx, reply_rx) = oneshot::channel();
+        self.sequencer
+            .send(Trigger::Submit {
+                tx,
+                reply: reply_sx,
+            })
+            .await
+            .map_err(|_| SubmitError::Internal("Sequencer is not running".to_string()))?;
+        let result = reply_rx.await.map_err(|_| {
+            SubmitError::Internal("Sequencer stopped before queuing the transaction".to_string())
+        })?;
+        match &result {
+            Ok(queued) => debug!(
+                peer = %self.peer,
+                tx_hash = %hash,
+                position = queued.position,
+                "Transaction accepted"
+            ),
+            Err(e) => {
+                debug!(peer = %self.peer, tx_hash = %hash, reason = %e, "Transaction refused")
+            }
+        }
+        result
+    }
+}
 
 #[tarpc::server]
 impl FusionRPC for FusionServer {
@@ -20,34 +177,942 @@ impl FusionRPC for FusionServer {
         self,
         _: context::Context,
         tx: fusion_api::SignedTx,
+    ) -> Result<QueuePosition, SubmitError> {
+        self.submit(Ok(tx)).await
+    }
+
+    async fn submit_transaction_raw(
+        self,
+        _: context::Context,
+        tx: Bytes,
+    ) -> Result<QueuePosition, SubmitError> {
+        self.submit(decode_signed_tx(&tx).map_err(SubmitError::Invalid))
+            .await
+    }
+
+    async fn get_queue_position(
+        self,
+        _: context::Context,
+        tx_hash: U256,
+    ) -> Result<QueuePosition, String> {
+        let (reply_sx, reply_rx) = oneshot::channel();
+        self.sequencer
+            .send(Trigger::QueuePosition {
+                hash: tx_hash,
+                reply: reply_sx,
+            })
+            .await
+            .map_err(|_| "Sequencer is not running".to_string())?;
+        reply_rx
+            .await
+            .map_err(|_| "Sequencer stopped before answering".to_string())?
+            .ok_or_else(|| format!("Transaction {tx_hash} is not pending"))
+    }
+
+    async fn submit_commitment(
+        self,
+        _: context::Context,
+        commitment: U256,
+    ) -> Result<u64, SubmitError> {
+        self.limiter.check(self.peer.ip(), Instant::now())?;
+        let (reply_sx, reply_rx) = oneshot::channel();
+        self.sequencer
+            .send(Trigger::Commit {
+                commitment,
+                reply: reply_sx,
+            })
+            .await
+            .map_err(|_| SubmitError::Internal("Sequencer is not running".to_string()))?;
+        reply_rx.await.map_err(|_| {
+            SubmitError::Internal("Sequencer stopped before recording the commitment".to_string())
+        })?
+    }
+
+    async fn reveal_transaction(
+        self,
+        _: context::Context,
+        tx: SignedTx,
+        salt: U256,
+    ) -> Result<(), SubmitError> {
+        // The sequencer reads the nonce and the commitment of `tx` before
+        // admitting it, so it must be well formed by then.
+        self.limiter.check(self.peer.ip(), Instant::now())?;
+        check_submission(&tx)?;
+        let (reply_sx, reply_rx) = oneshot::channel();
+        self.sequencer
+            .send(Trigger::Reveal {
+                tx,
+                salt,
+                reply: reply_sx,
+            })
+            .await
+            .map_err(|_| SubmitError::Internal("Sequencer is not running".to_string()))?;
+        reply_rx
+            .await
+            .map_err(|_| SubmitError::Internal("Sequencer stopped before the reveal".to_string()))?
+    }
+
+    async fn drop_transaction(
+        self,
+        _: context::Context,
+        tx_hash: U256,
+        signature: String,
     ) -> Result<(), String> {
-        self.1.send(tx.clone()).await.unwrap();
-        Ok(())
+        let (reply_sx, reply_rx) = oneshot::channel();
+        self.sequencer
+            .send(Trigger::Drop {
+                hash: tx_hash,
+                signature,
+                reply: reply_sx,
+            })
+            .await
+            .map_err(|_| "Sequencer is not running".to_string())?;
+        reply_rx
+            .await
+            .map_err(|_| "Sequencer stopped before dropping the transaction".to_string())?
+    }
+
+    async fn dev_faucet(self, _: context::Context, to: U256, amount: U256) -> Result<U256, String> {
+        // The sequencer signs the transfer, which hashes both.
+        if !fusion_wallet::is_public_key(&to) {
+            return Err(format!("{to} is not a Fusion public key"));
+        }
+        if !fusion_types::is_field_element(&amount) {
+            return Err(format!(
+                "The amount {amount} does not fit in a field element"
+            ));
+        }
+        let (reply_sx, reply_rx) = oneshot::channel();
+        self.sequencer
+            .send(Trigger::Faucet {
+                to,
+                amount,
+                reply: reply_sx,
+            })
+            .await
+            .map_err(|_| "Sequencer is not running".to_string())?;
+        reply_rx
+            .await
+            .map_err(|_| "Sequencer stopped before using the faucet".to_string())?
+    }
+
+    async fn sequencer_info(self, _: context::Context) -> SequencerInfo {
+        self.shared.info.lock().unwrap().clone()
+    }
+
+    async fn chain_info(self, _: context::Context) -> Result<ChainInfo, String> {
+        let (reply_sx, reply_rx) = oneshot::channel();
+        self.sequencer
+            .send(Trigger::ChainInfo(reply_sx))
+            .await
+            .map_err(|_| "Sequencer is not running".to_string())?;
+        reply_rx
+            .await
+            .map_err(|_| "Sequencer stopped before answering".to_string())
+    }
+
+    async fn sequencer_health(self, _: context::Context) -> Health {
+        if self.sequencer.is_closed() {
+            return Health {
+                status: HealthStatus::Halted,
+                reason: Some("Sequencer is not running".to_string()),
+                ..self.shared.health.lock().unwrap().clone()
+            };
+        }
+        Health {
+            pending_l1_tx: *self.shared.pending_l1_tx.lock().unwrap(),
+            ..self.shared.health.lock().unwrap().clone()
+        }
+    }
+
+    async fn sequencer_status(self, _: context::Context) -> SequencerStatus {
+        let mut status = probes::status(&self.shared, self.max_block_lag, block_timestamp());
+        if self.sequencer.is_closed() {
+            status.ready = false;
+            status.checks.insert(
+                "sequencer".to_string(),
+                StatusCheck {
+                    ok: false,
+                    detail: "Sequencer is not running".to_string(),
+                },
+            );
+        }
+        status
+    }
+
+    async fn get_mempool_stats(self, _: context::Context) -> Result<MempoolStats, String> {
+        let (reply_sx, reply_rx) = oneshot::channel();
+        self.sequencer
+            .send(Trigger::MempoolStats(reply_sx))
+            .await
+            .map_err(|_| "Sequencer is not running".to_string())?;
+        reply_rx
+            .await
+            .map_err(|_| "Sequencer stopped before answering".to_string())
+    }
+
+    async fn get_next_batch_preview(self, _: context::Context) -> Result<BatchPreview, String> {
+        let (reply_sx, reply_rx) = oneshot::channel();
+        self.sequencer
+            .send(Trigger::PreviewBatch(reply_sx))
+            .await
+            .map_err(|_| "Sequencer is not running".to_string())?;
+        reply_rx
+            .await
+            .map_err(|_| "Sequencer stopped before answering".to_string())?
+    }
+
+    async fn get_inclusion_proof(
+        self,
+        _: context::Context,
+        tx_hash: U256,
+    ) -> Result<InclusionProof, String> {
+        self.shared
+            .blocks
+            .lock()
+            .unwrap()
+            .iter()
+            .find_map(|block| block.inclusion_proof(&tx_hash))
+            .ok_or_else(|| format!("Transaction {tx_hash} is not in any block"))
+    }
+
+    async fn get_headers(
+        self,
+        _: context::Context,
+        from: u64,
+        to: u64,
+    ) -> Result<Vec<BlockHeader>, String> {
+        self.shared.headers(from, to)
+    }
+
+    async fn get_compact_headers(
+        self,
+        _: context::Context,
+        from: u64,
+        to: u64,
+    ) -> Result<Vec<u8>, String> {
+        Ok(encode_headers(&self.shared.headers(from, to)?))
+    }
+
+    async fn get_blocks(
+        self,
+        _: context::Context,
+        from: u64,
+        to: u64,
+    ) -> Result<Vec<Block>, String> {
+        self.shared.full_blocks(from, to)
+    }
+
+    async fn get_batch(self, _: context::Context, number: u64) -> Option<Batch> {
+        self.shared.batch(number)
+    }
+
+    async fn get_latest_batch(self, _: context::Context) -> Option<Batch> {
+        self.shared.latest_batch()
+    }
+
+    async fn get_batch_witness(
+        self,
+        _: context::Context,
+        number: u64,
+    ) -> Result<Option<BatchWitness>, String> {
+        let (reply_sx, reply_rx) = oneshot::channel();
+        self.sequencer
+            .send(Trigger::BatchWitness {
+                number,
+                reply: reply_sx,
+            })
+            .await
+            .map_err(|_| "Sequencer is not running".to_string())?;
+        reply_rx
+            .await
+            .map_err(|_| "Sequencer stopped before answering".to_string())?
+    }
+
+    async fn get_attestations(
+        self,
+        _: context::Context,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<Attestation>, String> {
+        self.shared.attestations(offset, limit)
+    }
+
+    async fn get_account_stats(
+        self,
+        _: context::Context,
+        address: U256,
+    ) -> Result<AccountStats, String> {
+        if !fusion_wallet::is_public_key(&address) {
+            return Err(format!("{address} is not a Fusion public key"));
+        }
+        Ok(self
+            .shared
+            .account_stats
+            .lock()
+            .unwrap()
+            .get(&address)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn get_account_history(
+        self,
+        _: context::Context,
+        address: U256,
+        from_batch: u64,
+        to_batch: u64,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<AuditRecord>, String> {
+        if !fusion_wallet::is_public_key(&address) {
+            return Err(format!("{address} is not a Fusion public key"));
+        }
+        self.shared
+            .account_history(address, (from_batch, to_batch), offset, limit)
+    }
+
+    async fn get_nonce(
+        self,
+        _: context::Context,
+        address: U256,
+        token: Option<Address>,
+    ) -> Result<Nonces, String> {
+        if !fusion_wallet::is_public_key(&address) {
+            return Err(format!("{address} is not a Fusion public key"));
+        }
+        let (reply_sx, reply_rx) = oneshot::channel();
+        self.sequencer
+            .send(Trigger::Nonce {
+                address,
+                token: token.unwrap_or(NATIVE_TOKEN),
+                reply: reply_sx,
+            })
+            .await
+            .map_err(|_| "Sequencer is not running".to_string())?;
+        reply_rx
+            .await
+            .map_err(|_| "Sequencer stopped before answering".to_string())
+    }
+
+    async fn estimate_transaction(self, _: context::Context, tx: Tx) -> Result<Estimate, String> {
+        check_tx_fields(&tx).map_err(|e| e.to_string())?;
+        let (reply_sx, reply_rx) = oneshot::channel();
+        self.sequencer
+            .send(Trigger::Estimate {
+                tx,
+                reply: reply_sx,
+            })
+            .await
+            .map_err(|_| "Sequencer is not running".to_string())?;
+        reply_rx
+            .await
+            .map_err(|_| "Sequencer stopped before answering".to_string())
+    }
+
+    async fn get_balance(
+        self,
+        _: context::Context,
+        address: U256,
+        token: Option<Address>,
+    ) -> Result<Balance, String> {
+        if !fusion_wallet::is_public_key(&address) {
+            return Err(format!("{address} is not a Fusion public key"));
+        }
+        let (reply_sx, reply_rx) = oneshot::channel();
+        self.sequencer
+            .send(Trigger::Balance {
+                address,
+                token: token.unwrap_or(NATIVE_TOKEN),
+                reply: reply_sx,
+            })
+            .await
+            .map_err(|_| "Sequencer is not running".to_string())?;
+        reply_rx
+            .await
+            .map_err(|_| "Sequencer stopped before answering".to_string())
+    }
+
+    async fn get_transaction(self, _: context::Context, tx_hash: U256) -> Result<TxInfo, String> {
+        self.transaction(tx_hash).await
+    }
+
+    async fn get_pending_withdrawals(
+        self,
+        _: context::Context,
+        sender: U256,
+    ) -> Result<Vec<TxInfo>, String> {
+        if !fusion_wallet::is_public_key(&sender) {
+            return Err(format!("{sender} is not a Fusion public key"));
+        }
+        let (reply_sx, reply_rx) = oneshot::channel();
+        self.sequencer
+            .send(Trigger::Withdrawals {
+                sender,
+                reply: reply_sx,
+            })
+            .await
+            .map_err(|_| "Sequencer is not running".to_string())?;
+        reply_rx
+            .await
+            .map_err(|_| "Sequencer stopped before answering".to_string())
+    }
+
+    async fn wait_for_block(self, _: context::Context) -> Result<BlockEvent, String> {
+        let mut events = self.shared.events.subscribe();
+        wait(async {
+            loop {
+                match events.recv().await {
+                    Ok(Event::Block(event)) => return Ok(event),
+                    Ok(Event::Rejected(_)) | Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => return Err("Sequencer stopped".to_string()),
+                }
+            }
+        })
+        .await
+    }
+
+    async fn wait_for_transaction(
+        self,
+        _: context::Context,
+        tx_hash: U256,
+        known: Option<TxStatus>,
+    ) -> Result<TxInfo, String> {
+        // Subscribed before the first lookup, so no change is missed.
+        let mut events = self.shared.events.subscribe();
+        wait(async {
+            loop {
+                let info = self.transaction(tx_hash).await?;
+                if known.as_ref() != Some(&info.status) {
+                    return Ok(info);
+                }
+                if let Err(RecvError::Closed) = events.recv().await {
+                    return Err("Sequencer stopped".to_string());
+                }
+            }
+        })
+        .await
     }
 }
 
-pub async fn run_server(sx: mpsc::Sender<SignedTx>, addr: String, port: u16) -> anyhow::Result<()> {
-    let mut listener = tarpc::serde_transport::tcp::listen(
-        &(IpAddr::V4(addr.parse().unwrap()), port),
-        Json::default,
-    )
-    .await?;
-    println!("Listening on port {}", listener.local_addr().port());
-    listener.config_mut().max_frame_length(usize::MAX);
-    listener
+#[derive(Clone)]
+struct AdminServer {
+    peer: SocketAddr,
+    sequencer: mpsc::Sender<Trigger>,
+    shared: Shared,
+}
+
+impl AdminServer {
+    async fn request<T>(
+        &self,
+        trigger: impl FnOnce(oneshot::Sender<T>) -> Trigger,
+        action: &str,
+    ) -> Result<T, String> {
+        let (reply_sx, reply_rx) = oneshot::channel();
+        self.sequencer
+            .send(trigger(reply_sx))
+            .await
+            .map_err(|_| "Sequencer is not running".to_string())?;
+        reply_rx
+            .await
+            .map_err(|_| format!("Sequencer stopped before {action}"))
+    }
+
+    async fn set_paused(self, paused: bool) -> Result<(), String> {
+        info!(peer = %self.peer, paused, "Pausing or resuming batching");
+        self.request(|reply| Trigger::SetPaused { paused, reply }, "pausing")
+            .await?
+    }
+}
+
+#[tarpc::server]
+impl AdminRPC for AdminServer {
+    async fn admin_pause_batching(self, _: context::Context) -> Result<(), String> {
+        self.set_paused(true).await
+    }
+
+    async fn admin_resume_batching(self, _: context::Context) -> Result<(), String> {
+        self.set_paused(false).await
+    }
+
+    async fn admin_mempool_content(self, _: context::Context) -> Result<Vec<TxInfo>, String> {
+        self.request(Trigger::MempoolContent, "listing the mempool")
+            .await
+    }
+
+    async fn admin_drop_all(self, _: context::Context) -> Result<usize, String> {
+        info!(peer = %self.peer, "admin_drop_all requested");
+        self.request(Trigger::DropAll, "dropping the mempool").await
+    }
+
+    async fn admin_snapshot(self, _: context::Context) -> Result<u64, String> {
+        info!(peer = %self.peer, "admin_snapshot requested");
+        self.request(Trigger::Snapshot, "taking a snapshot").await?
+    }
+
+    async fn admin_flush_batch(self, _: context::Context) -> Result<FlushResult, String> {
+        info!(peer = %self.peer, "admin_flush_batch requested");
+        let result = self.request(Trigger::Flush, "flushing").await?;
+        info!(peer = %self.peer, ?result, "admin_flush_batch done");
+        result
+    }
+
+    async fn admin_dev_reset(self, _: context::Context) -> Result<(), String> {
+        info!(peer = %self.peer, "admin_dev_reset requested");
+        self.request(Trigger::Reset, "resetting").await?
+    }
+
+    async fn admin_proving_stats(self, _: context::Context) -> ProvingStats {
+        self.shared.proving_stats()
+    }
+
+    async fn admin_check_contract(self, _: context::Context) -> Result<u64, String> {
+        info!(peer = %self.peer, "admin_check_contract requested");
+        self.request(Trigger::CheckContract, "checking the contract")
+            .await?
+    }
+
+    async fn admin_set_chaos(
+        self,
+        _: context::Context,
+        profile: ChaosProfile,
+    ) -> Result<(), String> {
+        info!(peer = %self.peer, ?profile, "admin_set_chaos requested");
+        self.request(
+            |reply| Trigger::SetChaos { profile, reply },
+            "setting chaos",
+        )
+        .await?
+    }
+}
+
+/// Runs `waiting` for at most `MAX_WAIT` seconds. The request is dropped,
+/// and with it its subscription, when the client goes away or its
+/// deadline passes.
+async fn wait<T>(waiting: impl Future<Output = Result<T, String>>) -> Result<T, String> {
+    tokio::time::timeout(Duration::from_secs(MAX_WAIT), waiting)
+        .await
+        .map_err(|_| format!("Nothing happened within {MAX_WAIT}s"))?
+}
+
+/// The RPC server `start_server` spawned.
+pub struct ServerHandle {
+    local_addr: SocketAddr,
+    stop: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl ServerHandle {
+    /// Where it listens, with the port the system picked for port 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Resolves if the server stops on its own.
+    pub async fn stopped(&mut self) -> anyhow::Result<()> {
+        (&mut self.task)
+            .await
+            .map_err(|e| anyhow::anyhow!("The RPC server failed: {e}"))
+    }
+
+    /// Closes the listener and the open connections, and waits until the
+    /// address can be bound again.
+    pub async fn shutdown(self) {
+        let _ = self.stop.send(());
+        let _ = self.task.await;
+        info!(addr = %self.local_addr, "Stopped listening");
+    }
+}
+
+/// Serves `FusionRPC` on `addr`, whose port may be 0. Submissions are
+/// limited per IP by `limiter`, and requests larger than
+/// `max_request_size` bytes close their connection.
+pub async fn start_server(
+    sx: mpsc::Sender<Trigger>,
+    shared: Shared,
+    addr: SocketAddr,
+    limiter: RateLimiter,
+    max_request_size: usize,
+    max_block_lag: u64,
+) -> anyhow::Result<ServerHandle> {
+    let mut listener = tarpc::serde_transport::tcp::listen(&addr, Json::default)
+        .await
+        .map_err(|e| anyhow::anyhow!("Could not listen on {addr}: {e}"))?;
+    let local_addr = listener.local_addr();
+    info!(addr = %local_addr, "Listening");
+    listener.config_mut().max_frame_length(max_request_size);
+    let serving = listener
         // Ignore accept errors.
         .filter_map(|r| future::ready(r.ok()))
         .map(server::BaseChannel::with_defaults)
         // Limit channels to 1 per IP.
         .max_channels_per_key(1, |t| t.transport().peer_addr().unwrap().ip())
         .map(|channel| {
-            let server = FusionServer(channel.transport().peer_addr().unwrap(), sx.clone());
+            let server = FusionServer {
+                peer: channel.transport().peer_addr().unwrap(),
+                sequencer: sx.clone(),
+                shared: shared.clone(),
+                limiter: limiter.clone(),
+                max_block_lag,
+            };
             channel.execute(server.serve())
         })
         // Max 10 channels.
         .buffer_unordered(10)
+        .for_each(|_| async {});
+
+    let (stop, stopping) = oneshot::channel();
+    // Dropping `serving` drops the listener and every channel.
+    let task = tokio::spawn(async move {
+        tokio::select! {
+            () = serving => {}
+            _ = stopping => {}
+        }
+    });
+    Ok(ServerHandle {
+        local_addr,
+        stop,
+        task,
+    })
+}
+
+/// Serves `AdminRPC` on the loopback interface at `port`, apart from the
+/// public server so that its methods are never reachable from outside.
+pub async fn run_admin_server(
+    sx: mpsc::Sender<Trigger>,
+    shared: Shared,
+    port: u16,
+) -> anyhow::Result<()> {
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    let listener = tarpc::serde_transport::tcp::listen(&addr, Json::default)
+        .await
+        .map_err(|e| anyhow::anyhow!("Could not listen on {addr}: {e}"))?;
+    info!(addr = %listener.local_addr(), "Admin server listening");
+    listener
+        .filter_map(|r| future::ready(r.ok()))
+        .map(server::BaseChannel::with_defaults)
+        .map(|channel| {
+            let server = AdminServer {
+                peer: channel.transport().peer_addr().unwrap(),
+                sequencer: sx.clone(),
+                shared: shared.clone(),
+            };
+            channel.execute(server.serve())
+        })
+        .buffer_unordered(4)
         .for_each(|_| async {})
         .await;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fusion_types::ToU256;
+
+    use crate::dev;
+
+    fn signed_tx(value: u64) -> SignedTx {
+        let (sk, pk) = fusion_wallet::new_key_pair();
+        let (_sk, to) = fusion_wallet::new_key_pair();
+        let tx = Tx {
+            sender: pk.to_u256(),
+            to: to.to_u256(),
+            not_before: Some(1),
+            ..dev::dev_transfer_tx(0, 1, 1, value)
+        };
+        dev::sign_tx(tx, &sk)
+    }
+
+    /// What the binary path does with a transaction a client encoded.
+    fn submit_raw(tx: &SignedTx) -> Result<(), SubmitError> {
+        let bytes = encode_signed_tx(tx).map_err(SubmitError::InvalidSignature)?;
+        check_submission(&decode_signed_tx(&bytes).map_err(SubmitError::Invalid)?)
+    }
+
+    #[test]
+    fn json_and_binary_agree() {
+        let valid = signed_tx(10);
+        let mut invalid_sender = signed_tx(10);
+        invalid_sender.tx.sender = U256::MAX;
+        let mut invalid_recipient = signed_tx(10);
+        invalid_recipient.tx.to = U256::MAX;
+        let mut padded_signature = signed_tx(10);
+        padded_signature.signature = format!("0{}", padded_signature.signature);
+        let mut invalid_signature = signed_tx(10);
+        invalid_signature.signature = "0xdeadbeef".to_string();
+
+        for tx in [
+            valid,
+            invalid_sender,
+            invalid_recipient,
+            padded_signature,
+            invalid_signature,
+        ] {
+            assert_eq!(check_submission(&tx), submit_raw(&tx), "{tx:?}");
+        }
+        assert!(check_submission(&signed_tx(10)).is_ok());
+    }
+
+    #[test]
+    fn field_range() {
+        let modulus = U256::from_dec_str(
+            "21888242871839275222246405745257275088548364400416034343698204186575808495617",
+        )
+        .unwrap();
+        let mut tx = signed_tx(10);
+        tx.tx.value = modulus - 1;
+        assert!(check_submission(&tx).is_ok());
+        tx.tx.value = modulus;
+        let e = check_submission(&tx).unwrap_err();
+        assert_eq!(e.code(), -32602);
+        assert!(e.to_string().contains("does not fit"), "{e}");
+        let mut tx = signed_tx(10);
+        tx.tx.nonce = U256::MAX;
+        assert!(check_submission(&tx)
+            .unwrap_err()
+            .to_string()
+            .starts_with("The nonce"));
+    }
+
+    #[test]
+    fn withdrawal_recipient() {
+        let withdrawal = |to: U256| {
+            let mut tx = signed_tx(10);
+            tx.tx.kind = TxKind::Withdraw;
+            tx.tx.to = to;
+            check_submission(&tx)
+        };
+        // A small L1 address that is also a compressed point.
+        assert!(withdrawal(2.into()).is_ok());
+        assert!(withdrawal(0.into())
+            .unwrap_err()
+            .to_string()
+            .contains("not an L1 address"));
+        let (_sk, key) = fusion_wallet::new_key_pair();
+        let e = withdrawal(key.to_u256()).unwrap_err();
+        assert_eq!(e.code(), -32602);
+        assert!(e.to_string().contains("not an L1 address"));
+    }
+
+    #[test]
+    fn malformed_signatures() {
+        let with_signature = |signature: &str| {
+            let mut tx = signed_tx(10);
+            tx.signature = signature.to_string();
+            tx
+        };
+        let valid = "122241928682229286598976029249532022025637739860654613779160404391018488754905842538161678835085790288668324736777854019032918292839284526282556835964629";
+        assert!(check_submission(&with_signature(valid)).is_ok());
+        // Each is refused here instead of failing the proof of its block.
+        for signature in [&valid[..valid.len() - 1], "", "2"] {
+            let tx = with_signature(signature);
+            let e = check_submission(&tx).unwrap_err();
+            assert_eq!(e.code(), -32001);
+            assert!(e.to_string().starts_with("Invalid signature"));
+            assert_eq!(check_submission(&tx), submit_raw(&tx), "{tx:?}");
+        }
+    }
+
+    #[test]
+    fn binary_only_errors() {
+        let bytes = encode_signed_tx(&signed_tx(10)).unwrap();
+        assert!(decode_signed_tx(&bytes[..RAW_TX_SIZE - 1])
+            .unwrap_err()
+            .contains("bytes"));
+        let mut invalid_kind = bytes;
+        invalid_kind[0] = 9;
+        assert!(decode_signed_tx(&invalid_kind).is_err());
+    }
+
+    /// The transfer dev mode prints at startup goes through the RPC
+    /// server of a fresh sequencer as printed.
+    #[tokio::test]
+    async fn quick_start() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.socket_address = "127.0.0.1".to_string();
+        config.socket_port = 0;
+        let tx = crate::dev::quick_start_tx(&config).unwrap();
+        let command = crate::dev::quick_start_command(&tx);
+        assert!(command.contains(&format!("--signature {}", tx.signature)));
+
+        let (sx, shared) = dev_sequencer(&config).await;
+        let server = start(&config, sx, shared).await;
+        config.socket_port = server.local_addr().port();
+        let queued = connect(&config)
+            .await
+            .unwrap()
+            .submit_transaction(request_context(&config), tx.clone())
+            .await
+            .unwrap();
+        assert_eq!(queued.map(|queued| queued.tx_hash), Ok(hash_tx(&tx.tx)));
+        server.shutdown().await;
+    }
+
+    async fn dev_sequencer(config: &Config) -> (mpsc::Sender<Trigger>, Shared) {
+        let sequencer = crate::sequencer::Sequencer::new(config).await.unwrap();
+        let shared = sequencer.shared();
+        let (sx, rx) = mpsc::channel(8);
+        tokio::spawn(sequencer.run(rx));
+        (sx, shared)
+    }
+
+    async fn start(config: &Config, sx: mpsc::Sender<Trigger>, shared: Shared) -> ServerHandle {
+        start_server(
+            sx,
+            shared,
+            config.socket_addr().unwrap(),
+            RateLimiter::new(0, 0),
+            config.rpc_max_request_size,
+            config.ready_max_block_lag,
+        )
+        .await
+        .unwrap()
+    }
+
+    /// A server on port 0 answers on the port it reports, and once shut
+    /// down its port is free for the next one.
+    #[tokio::test]
+    async fn shutdown_and_restart() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.socket_address = "127.0.0.1".to_string();
+        config.socket_port = 0;
+        let (sx, shared) = dev_sequencer(&config).await;
+        let server = start(&config, sx.clone(), shared.clone()).await;
+        assert_ne!(server.local_addr().port(), 0);
+        config.socket_port = server.local_addr().port();
+
+        let address = U256::from(7);
+        let client = connect(&config).await.unwrap();
+        let nonces = client
+            .get_nonce(request_context(&config), address, None)
+            .await
+            .unwrap();
+        assert_eq!(nonces.unwrap().committed, U256::zero());
+
+        server.shutdown().await;
+        assert!(client
+            .get_nonce(request_context(&config), address, None)
+            .await
+            .is_err());
+        assert!(connect(&config).await.is_err());
+
+        let server = start(&config, sx, shared).await;
+        assert_eq!(server.local_addr().port(), config.socket_port);
+        let nonces = connect(&config)
+            .await
+            .unwrap()
+            .get_nonce(request_context(&config), address, None)
+            .await
+            .unwrap();
+        assert!(nonces.is_ok());
+        server.shutdown().await;
+    }
+
+    /// Estimates of transactions the sequencer could not hash are refused
+    /// by the server, the others are answered.
+    #[tokio::test]
+    async fn checked_estimates() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.socket_address = "127.0.0.1".to_string();
+        config.socket_port = 0;
+        let (sx, shared) = dev_sequencer(&config).await;
+        let server = start(&config, sx, shared).await;
+        config.socket_port = server.local_addr().port();
+        let client = connect(&config).await.unwrap();
+
+        let mut invalid_recipient = signed_tx(10).tx;
+        invalid_recipient.to = U256::MAX;
+        let e = client
+            .estimate_transaction(request_context(&config), invalid_recipient)
+            .await
+            .unwrap()
+            .unwrap_err();
+        assert!(e.contains("not a Fusion public key"), "{e}");
+
+        // A fresh sender cannot afford it, which takes reading its account.
+        let mut withdrawal = signed_tx(10).tx;
+        withdrawal.kind = TxKind::Withdraw;
+        withdrawal.to = 2.into();
+        let estimate = client
+            .estimate_transaction(request_context(&config), withdrawal)
+            .await
+            .unwrap();
+        assert_eq!(
+            estimate,
+            Ok(Estimate::InsufficientBalance {
+                balance: 0.into(),
+                shortfall: 10.into(),
+            })
+        );
+        server.shutdown().await;
+    }
+
+    /// The faucet refuses recipients and amounts the sequencer could not
+    /// sign a transfer for, and keeps paying the others.
+    #[tokio::test]
+    async fn faucet_checks() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.socket_address = "127.0.0.1".to_string();
+        config.socket_port = 0;
+        let (sx, shared) = dev_sequencer(&config).await;
+        let server = start(&config, sx, shared).await;
+        config.socket_port = server.local_addr().port();
+        let client = connect(&config).await.unwrap();
+
+        let to = signed_tx(10).tx.to;
+        for (to, amount) in [(U256::MAX, 10.into()), (to, U256::MAX)] {
+            let result = client
+                .dev_faucet(request_context(&config), to, amount)
+                .await
+                .unwrap();
+            assert!(result.is_err(), "{result:?}");
+        }
+        let result = client
+            .dev_faucet(request_context(&config), to, 10.into())
+            .await
+            .unwrap();
+        assert!(result.is_ok(), "{result:?}");
+        server.shutdown().await;
+    }
+
+    /// Reveals the sequencer could not hash are refused by the server,
+    /// and the sequencer keeps answering.
+    #[tokio::test]
+    async fn malformed_reveal() {
+        let mut config = Config::default();
+        config.dev = true;
+        config.socket_address = "127.0.0.1".to_string();
+        config.socket_port = 0;
+        let (sx, shared) = dev_sequencer(&config).await;
+        let server = start(&config, sx, shared).await;
+        config.socket_port = server.local_addr().port();
+        let client = connect(&config).await.unwrap();
+
+        let mut invalid_sender = signed_tx(10);
+        invalid_sender.tx.sender = U256::MAX;
+        let mut invalid_value = signed_tx(10);
+        invalid_value.tx.value = U256::MAX;
+        for tx in [invalid_sender, invalid_value] {
+            let e = client
+                .reveal_transaction(request_context(&config), tx, 7.into())
+                .await
+                .unwrap()
+                .unwrap_err();
+            assert_eq!(e.code(), -32602);
+        }
+
+        let sender = signed_tx(10).tx.sender;
+        let nonces = client
+            .get_nonce(request_context(&config), sender, None)
+            .await
+            .unwrap();
+        assert_eq!(nonces.unwrap().next, U256::one());
+        server.shutdown().await;
+    }
+}
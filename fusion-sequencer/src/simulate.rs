@@ -0,0 +1,274 @@
+//! Local simulation of the block pipeline, for capacity planning.
+//!
+//! A synthetic genesis and workload are drawn from a seed, then cut into
+//! blocks of `batch_size` transactions that go through the same stages as
+//! in `Sequencer::build_block`: signature checks, state checks and
+//! application, roots and circuit inputs, and optionally witnesses. There
+//! is no network, L1 nor proof involved.
+
+use ethers::types::U256;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+use fusion_api::{SignedTx, Tx, TxKind, NATIVE_TOKEN};
+use fusion_config::Config;
+use fusion_prover::offline::peak_memory;
+use fusion_prover::state::{Account, State};
+use fusion_prover::{CircuitInput, Prover};
+use fusion_types::{PrivateKey, PublicKey, ToU256};
+
+use crate::transition::{apply_tx, check_tx_state};
+
+/// Genesis balance of every simulated account.
+const GENESIS_BALANCE: u64 = 1_000_000;
+/// Largest value of a simulated transfer.
+const MAX_VALUE: u64 = 100;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SimulationParams {
+    pub accounts: usize,
+    pub txs: usize,
+    pub batch_size: usize,
+    pub seed: u64,
+    /// Also build the witness of every transaction, which needs the
+    /// compiled circuit.
+    pub witness: bool,
+}
+
+/// Milliseconds spent in each stage, summed over the blocks.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SimulationStages {
+    pub genesis_ms: u64,
+    /// Generating and signing the transactions, not part of the pipeline.
+    pub workload_ms: u64,
+    pub signatures_ms: u64,
+    pub apply_ms: u64,
+    pub roots_ms: u64,
+    pub inputs_ms: u64,
+    pub witness_ms: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SimulationReport {
+    pub params: SimulationParams,
+    pub blocks: usize,
+    pub included: usize,
+    pub dropped: usize,
+    pub state_root: U256,
+    pub stages: SimulationStages,
+    /// Milliseconds spent in the pipeline, genesis and workload aside.
+    pub pipeline_ms: u64,
+    /// Included transactions per second of pipeline.
+    pub throughput: f64,
+    /// Peak resident memory of the process in kB, where available.
+    pub peak_memory_kb: Option<u64>,
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis() as u64
+}
+
+/// Keys of `accounts` funded accounts and the genesis state holding them,
+/// drawn from `rng`.
+pub fn genesis(accounts: usize, rng: &mut ChaCha8Rng) -> (Vec<(PrivateKey, PublicKey)>, State) {
+    let seeds: Vec<[u8; 32]> = (0..accounts).map(|_| rng.gen()).collect();
+    let keys: Vec<_> = seeds
+        .into_par_iter()
+        .map(|seed| {
+            let sk: PrivateKey = U256::from_big_endian(&seed).into();
+            let pk = fusion_wallet::new_public_key(&sk);
+            (sk, pk)
+        })
+        .collect();
+    let mut state = State::default();
+    for (_, pk) in &keys {
+        let address = pk.address();
+        state.update(
+            &address,
+            Account::new(address, GENESIS_BALANCE.into(), 0.into()),
+        );
+    }
+    (keys, state)
+}
+
+/// `txs` signed transfers between random pairs of `keys`, which their
+/// senders can all afford when applied in order.
+pub fn workload(
+    keys: &[(PrivateKey, PublicKey)],
+    txs: usize,
+    rng: &mut ChaCha8Rng,
+) -> Vec<SignedTx> {
+    let mut balances = vec![GENESIS_BALANCE; keys.len()];
+    let mut nonces = vec![0u64; keys.len()];
+    let unsigned: Vec<_> = (0..txs)
+        .map(|_| {
+            let from = rng.gen_range(0..keys.len());
+            let to = (from + rng.gen_range(1..keys.len())) % keys.len();
+            let value = rng.gen_range(0..=MAX_VALUE.min(balances[from]));
+            balances[from] -= value;
+            balances[to] += value;
+            nonces[from] += 1;
+            let tx = Tx {
+                kind: TxKind::Transfer,
+                sender: keys[from].1.to_u256(),
+                to: keys[to].1.to_u256(),
+                nonce: nonces[from].into(),
+                value: value.into(),
+                not_before: None,
+                not_after: None,
+                chain_id: None,
+                token: NATIVE_TOKEN,
+            };
+            (from, tx)
+        })
+        .collect();
+    unsigned
+        .into_par_iter()
+        .map(|(from, tx)| {
+            let signature = fusion_wallet::sign(&tx, keys[from].0.to_string())
+                .expect("simulated transactions can be signed")
+                .to_string();
+            SignedTx { tx, signature }
+        })
+        .collect()
+}
+
+pub fn simulate(config: &Config, params: &SimulationParams) -> Result<SimulationReport, String> {
+    if params.accounts < 2 {
+        return Err("At least 2 accounts are needed to transfer between".to_string());
+    }
+    if params.batch_size == 0 {
+        return Err("The batch size must be positive".to_string());
+    }
+    let mut rng = ChaCha8Rng::seed_from_u64(params.seed);
+    let mut stages = SimulationStages::default();
+
+    let start = Instant::now();
+    let (keys, mut state) = genesis(params.accounts, &mut rng);
+    stages.genesis_ms = millis(start.elapsed());
+
+    let start = Instant::now();
+    let txs = workload(&keys, params.txs, &mut rng);
+    stages.workload_ms = millis(start.elapsed());
+
+    let mut report = SimulationReport {
+        params: params.clone(),
+        blocks: 0,
+        included: 0,
+        dropped: 0,
+        state_root: U256::zero(),
+        stages,
+        pipeline_ms: 0,
+        throughput: 0.0,
+        peak_memory_kb: None,
+    };
+    let mut witness = params.witness.then_some(Duration::ZERO);
+    let pipeline_start = Instant::now();
+    for batch in txs.chunks(params.batch_size) {
+        let start = Instant::now();
+        let batch: Vec<_> = batch
+            .iter()
+            .filter(|tx| fusion_wallet::verify_tx_signature(tx).is_ok())
+            .cloned()
+            .collect();
+        report.stages.signatures_ms += millis(start.elapsed());
+
+        let start = Instant::now();
+        let mut states = vec![state.clone()];
+        let block: Vec<_> = batch
+            .into_iter()
+            .filter(|tx| {
+                let latest = states.last().unwrap();
+                match check_tx_state(latest, &tx.tx).and_then(|()| apply_tx(latest.clone(), &tx.tx))
+                {
+                    Ok(next) => {
+                        states.push(next);
+                        true
+                    }
+                    Err(_) => false,
+                }
+            })
+            .collect();
+        report.stages.apply_ms += millis(start.elapsed());
+
+        let start = Instant::now();
+        report.state_root = states.last().unwrap().root();
+        report.stages.roots_ms += millis(start.elapsed());
+
+        let start = Instant::now();
+        let inputs = CircuitInput::batch(&block, &states)
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?;
+        report.stages.inputs_ms += millis(start.elapsed());
+
+        if let Some(witness) = &mut witness {
+            *witness += Prover::compute_witnesses(config, &inputs)?;
+        }
+
+        report.blocks += 1;
+        report.included += block.len();
+        state = states.pop().unwrap();
+    }
+    let pipeline = pipeline_start.elapsed();
+
+    report.dropped = txs.len() - report.included;
+    report.stages.witness_ms = witness.map(millis);
+    report.pipeline_ms = millis(pipeline);
+    report.throughput = match pipeline.as_secs_f64() {
+        secs if secs > 0.0 => report.included as f64 / secs,
+        _ => 0.0,
+    };
+    report.peak_memory_kb = peak_memory();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tiny(seed: u64) -> SimulationParams {
+        SimulationParams {
+            accounts: 4,
+            txs: 10,
+            batch_size: 4,
+            seed,
+            witness: false,
+        }
+    }
+
+    #[test]
+    fn smoke() {
+        let report = simulate(&Config::default(), &tiny(1)).unwrap();
+        assert_eq!(report.blocks, 3);
+        assert_eq!(report.included, 10);
+        assert_eq!(report.dropped, 0);
+        assert_eq!(report.stages.witness_ms, None);
+
+        // The same seed gives the same chain, another one does not.
+        let again = simulate(&Config::default(), &tiny(1)).unwrap();
+        assert_eq!(again.state_root, report.state_root);
+        let other = simulate(&Config::default(), &tiny(2)).unwrap();
+        assert_ne!(other.state_root, report.state_root);
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["params"]["batch_size"], 4);
+        assert!(json["stages"]["apply_ms"].is_u64());
+    }
+
+    #[test]
+    fn invalid_params() {
+        let params = SimulationParams {
+            accounts: 1,
+            ..tiny(1)
+        };
+        assert!(simulate(&Config::default(), &params).is_err());
+        let params = SimulationParams {
+            batch_size: 0,
+            ..tiny(1)
+        };
+        assert!(simulate(&Config::default(), &params).is_err());
+    }
+}
@@ -0,0 +1,236 @@
+//! Snapshots of the state replayed from L1, so that a restart replays
+//! only the proofs after the newest one instead of `l1_sync.jsonl` from
+//! genesis. A snapshot holds every account, nonces included, the root
+//! they hash to, the last L1 block replayed and the last block built.
+//!
+//! Snapshots are written to `snapshots/<l1_block>.json` in
+//! `database_path`, to a temporary file first then renamed, with the
+//! keccak256 of their contents. The `KEPT` newest are kept. One whose
+//! checksum or root does not match is skipped for the one before it.
+
+use std::path::{Path, PathBuf};
+
+use ethers::types::{H256, U256};
+use ethers::utils::keccak256;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use fusion_prover::state::{Account, State};
+
+/// Snapshots kept in `database_path`.
+pub const KEPT: usize = 3;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// The proofs of every L1 block up to this one are in the state.
+    pub l1_block: u64,
+    /// The last block the sequencer built when it was taken.
+    pub last_batch: Option<u64>,
+    pub root: U256,
+    /// Every account set, by key.
+    pub accounts: Vec<(U256, Account)>,
+}
+
+/// What is written to a snapshot file.
+#[derive(Serialize, Deserialize)]
+struct SnapshotFile {
+    /// keccak256 of the JSON encoding of `snapshot`.
+    checksum: H256,
+    snapshot: Snapshot,
+}
+
+impl Snapshot {
+    pub fn new(state: &State, l1_block: u64, last_batch: Option<u64>) -> Self {
+        Self {
+            l1_block,
+            last_batch,
+            root: state.root(),
+            accounts: state
+                .accounts()
+                .map(|(key, account)| (*key, account.clone()))
+                .collect(),
+        }
+    }
+
+    /// The state of the accounts, if they hash to `root`.
+    pub fn state(&self) -> Result<State, String> {
+        let mut state = State::default();
+        for (key, account) in &self.accounts {
+            state.update(key, account.clone());
+        }
+        if state.root() != self.root {
+            return Err(format!(
+                "The accounts of the snapshot of L1 block {} hash to {}, not {}",
+                self.l1_block,
+                state.root(),
+                self.root
+            ));
+        }
+        Ok(state)
+    }
+}
+
+fn checksum(snapshot: &Snapshot) -> Result<H256, String> {
+    let json = serde_json::to_vec(snapshot).map_err(|e| e.to_string())?;
+    Ok(keccak256(json).into())
+}
+
+/// The contents of a snapshot file.
+pub fn encode(snapshot: &Snapshot) -> Result<Vec<u8>, String> {
+    let file = SnapshotFile {
+        checksum: checksum(snapshot)?,
+        snapshot: snapshot.clone(),
+    };
+    serde_json::to_vec(&file).map_err(|e| e.to_string())
+}
+
+/// The snapshot in the contents of a snapshot file, with its state, if
+/// its checksum and root match.
+pub fn decode(bytes: &[u8]) -> Result<(Snapshot, State), String> {
+    let file: SnapshotFile =
+        serde_json::from_slice(bytes).map_err(|e| format!("Invalid snapshot: {e}"))?;
+    if checksum(&file.snapshot)? != file.checksum {
+        return Err(format!(
+            "The snapshot of L1 block {} does not match its checksum",
+            file.snapshot.l1_block
+        ));
+    }
+    let state = file.snapshot.state()?;
+    Ok((file.snapshot, state))
+}
+
+fn snapshots_dir(dir: &Path) -> PathBuf {
+    dir.join("snapshots")
+}
+
+fn path(dir: &Path, l1_block: u64) -> PathBuf {
+    snapshots_dir(dir).join(format!("{l1_block}.json"))
+}
+
+/// The L1 blocks of the snapshots in `dir`, the newest first.
+pub fn list(dir: &Path) -> Result<Vec<u64>, String> {
+    let entries = match std::fs::read_dir(snapshots_dir(dir)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => {
+            return Err(format!(
+                "Could not read {}: {e}",
+                snapshots_dir(dir).display()
+            ))
+        }
+    };
+    let mut blocks: Vec<u64> = entries
+        .filter_map(|entry| {
+            let name = entry.ok()?.file_name();
+            name.to_str()?.strip_suffix(".json")?.parse().ok()
+        })
+        .collect();
+    blocks.sort_unstable_by(|a, b| b.cmp(a));
+    Ok(blocks)
+}
+
+/// Writes `snapshot` in `dir`, then removes all but the `KEPT` newest.
+pub fn write(dir: &Path, snapshot: &Snapshot) -> Result<(), String> {
+    let path = path(dir, snapshot.l1_block);
+    let bytes = encode(snapshot)?;
+    let tmp = path.with_extension("json.tmp");
+    std::fs::create_dir_all(path.parent().unwrap())
+        .and_then(|()| std::fs::write(&tmp, bytes))
+        .and_then(|()| std::fs::rename(&tmp, &path))
+        .map_err(|e| format!("Could not write {}: {e}", path.display()))?;
+    for l1_block in list(dir)?.into_iter().skip(KEPT) {
+        remove(dir, l1_block)?;
+    }
+    Ok(())
+}
+
+fn remove(dir: &Path, l1_block: u64) -> Result<(), String> {
+    let path = path(dir, l1_block);
+    std::fs::remove_file(&path).map_err(|e| format!("Could not remove {}: {e}", path.display()))
+}
+
+/// Removes the snapshots of L1 block `l1_block` and after, once a reorg
+/// dropped it.
+pub fn remove_from(dir: &Path, l1_block: u64) -> Result<(), String> {
+    for b in list(dir)?.into_iter().filter(|b| *b >= l1_block) {
+        remove(dir, b)?;
+    }
+    Ok(())
+}
+
+/// The newest snapshot in `dir` that reads back, with its state and the
+/// contents of its file.
+pub fn newest(dir: &Path) -> Result<Option<(Snapshot, State, Vec<u8>)>, String> {
+    for l1_block in list(dir)? {
+        let path = path(dir, l1_block);
+        let read = std::fs::read(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| decode(&bytes).map(|(snapshot, state)| (snapshot, state, bytes)));
+        match read {
+            Ok((snapshot, ..)) if snapshot.l1_block != l1_block => warn!(
+                path = %path.display(),
+                "Skipping a snapshot written for another L1 block"
+            ),
+            Ok(read) => return Ok(Some(read)),
+            Err(e) => warn!(path = %path.display(), error = %e, "Skipping a corrupt snapshot"),
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fusion_config::Config;
+
+    use crate::dev;
+
+    fn state() -> State {
+        let config = Config {
+            dev: true,
+            dev_accounts: 3,
+            ..Default::default()
+        };
+        dev::genesis_state(&config)
+    }
+
+    #[test]
+    fn write_and_read_back() {
+        let state = state();
+        let dir = std::env::temp_dir().join(format!("fusion-snapshot-{}", rand::random::<u64>()));
+        assert!(newest(&dir).unwrap().is_none());
+
+        for l1_block in [10, 20, 30, 40] {
+            write(&dir, &Snapshot::new(&state, l1_block, Some(7))).unwrap();
+        }
+        assert_eq!(list(&dir).unwrap(), [40, 30, 20]);
+        let (snapshot, restored, bytes) = newest(&dir).unwrap().unwrap();
+        assert_eq!(snapshot.l1_block, 40);
+        assert_eq!(snapshot.last_batch, Some(7));
+        assert_eq!(snapshot.accounts.len(), 3);
+        assert_eq!(restored.root(), state.root());
+        assert_eq!(decode(&bytes).unwrap().0, snapshot);
+
+        // A corrupt snapshot falls back to the one before it.
+        let mut corrupt = snapshot.clone();
+        corrupt.accounts[0].1.balance += U256::one();
+        std::fs::write(
+            path(&dir, 40),
+            serde_json::to_vec(&SnapshotFile {
+                checksum: checksum(&snapshot).unwrap(),
+                snapshot: corrupt.clone(),
+            })
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(newest(&dir).unwrap().unwrap().0.l1_block, 30);
+        let e = decode(&encode(&corrupt).unwrap()).unwrap_err();
+        assert!(e.contains("hash to"), "{e}");
+        std::fs::write(path(&dir, 30), b"{\"checks").unwrap();
+        assert_eq!(newest(&dir).unwrap().unwrap().0.l1_block, 20);
+
+        remove_from(&dir, 30).unwrap();
+        assert_eq!(list(&dir).unwrap(), [20]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
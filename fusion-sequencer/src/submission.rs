@@ -0,0 +1,642 @@
+//! Proofs waiting to be submitted to L1, and their submission. They are
+//! persisted as soon as they are computed, so a congested L1 or a restart
+//! delays their submission instead of requiring the blocks to be proven
+//! again.
+
+use ethers::abi::{AbiDecode, AbiEncode};
+use ethers::providers::{JsonRpcError, Middleware, MiddlewareError};
+use ethers::types::{BlockNumber, Bytes, TransactionReceipt, H256, U256};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tracing::{debug, error, info, warn};
+
+use fusion_api::Fault;
+use fusion_config::Config;
+use fusion_l1::fusion;
+use fusion_prover::PUBLIC_INPUTS;
+
+use crate::calldata;
+use crate::chaos::{Chaos, INJECTED};
+use crate::reorg::L1Block;
+use crate::sequencer::{L1Contract, Shared};
+
+/// Longest delay between two attempts to submit the same proof.
+pub const MAX_BACKOFF: u64 = 300;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QueuedProof {
+    pub block: u64,
+    /// ABI encoding of the `fusion::TxProof`.
+    pub proof: Bytes,
+    /// Unix timestamp at which the proof was queued.
+    pub queued_at: u64,
+    /// Failed attempts so far.
+    pub attempts: u32,
+    /// Unix timestamp before which it is not submitted again.
+    pub next_attempt: u64,
+}
+
+/// A proof the L1 contract accepted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Submitted {
+    pub block: u64,
+    pub l1_tx_hash: H256,
+    /// Where the L1 transaction was mined, if known.
+    pub l1_block: Option<L1Block>,
+}
+
+pub struct SubmissionQueue {
+    /// `None` keeps the queue in memory only.
+    path: Option<PathBuf>,
+    items: VecDeque<QueuedProof>,
+    chaos: Chaos,
+}
+
+impl SubmissionQueue {
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            items: VecDeque::new(),
+            chaos: Chaos::default(),
+        }
+    }
+
+    /// Opens the queue persisted in `dir`, empty if there is none yet.
+    pub fn open(dir: &Path) -> Result<Self, String> {
+        let path = dir.join("submissions.json");
+        let items = match std::fs::read(&path) {
+            Ok(json) => serde_json::from_slice(&json)
+                .map_err(|e| format!("Could not parse {}: {e}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => VecDeque::new(),
+            Err(e) => return Err(format!("Could not read {}: {e}", path.display())),
+        };
+        Ok(Self {
+            path: Some(path),
+            items,
+            chaos: Chaos::default(),
+        })
+    }
+
+    /// Makes writes fail when `chaos` injects storage faults.
+    pub fn set_chaos(&mut self, chaos: Chaos) {
+        self.chaos = chaos;
+    }
+
+    fn persist(&self) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(e) = self.chaos.inject(Fault::Storage) {
+            return Err(format!("Could not write {}: {e}", path.display()));
+        }
+        let json = serde_json::to_vec(&self.items).map_err(|e| e.to_string())?;
+        let tmp = path.with_extension("json.tmp");
+        std::fs::create_dir_all(path.parent().unwrap())
+            .and_then(|()| std::fs::write(&tmp, json))
+            .and_then(|()| std::fs::rename(&tmp, path))
+            .map_err(|e| format!("Could not write {}: {e}", path.display()))
+    }
+
+    /// Queues the proof of a transaction of `block`.
+    pub fn push(&mut self, block: u64, proof: fusion::TxProof, now: u64) -> Result<(), String> {
+        self.items.push_back(QueuedProof {
+            block,
+            proof: proof.encode().into(),
+            queued_at: now,
+            attempts: 0,
+            next_attempt: now,
+        });
+        self.persist()
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// How long the oldest proof has been waiting, zero if there is none.
+    pub fn oldest_age(&self, now: u64) -> u64 {
+        self.items
+            .front()
+            .map_or(0, |item| now.saturating_sub(item.queued_at))
+    }
+
+    /// Whether proofs of `block` are still waiting.
+    pub fn has_block(&self, block: u64) -> bool {
+        self.items.iter().any(|item| item.block == block)
+    }
+
+    /// The first proof waiting and its block, if any.
+    pub fn front(&self) -> Option<(u64, fusion::TxProof)> {
+        let item = self.items.front()?;
+        let proof = fusion::TxProof::decode(&item.proof).ok()?;
+        (proof.input.len() == PUBLIC_INPUTS).then_some((item.block, proof))
+    }
+
+    /// Drops the first proof, which L1 accepted without it being known.
+    pub fn pop_front(&mut self) -> Result<(), String> {
+        self.items.pop_front();
+        self.persist()
+    }
+
+    /// Drops every proof, once they no longer build on the L1 root.
+    pub fn clear(&mut self) -> Result<(), String> {
+        self.items.clear();
+        self.persist()
+    }
+
+    /// The oldest block with a proof still waiting, if any.
+    pub fn oldest_block(&self) -> Option<u64> {
+        self.items.front().map(|item| item.block)
+    }
+
+    /// The L1 deposits claimed by the queued proofs, by L2 recipient.
+    /// The contract only deducts them once the proofs land.
+    pub fn deposit_claims(&self) -> HashMap<U256, U256> {
+        let mut claims: HashMap<U256, U256> = HashMap::new();
+        for item in &self.items {
+            let Ok(proof) = fusion::TxProof::decode(&item.proof) else {
+                continue;
+            };
+            if proof.input.len() == PUBLIC_INPUTS && proof.input[2] == U256::one() {
+                *claims.entry(proof.input[14]).or_default() += proof.input[8];
+            }
+        }
+        claims
+    }
+
+    /// When the next submission is due, if any proof is queued.
+    pub fn next_attempt(&self) -> Option<u64> {
+        self.items.front().map(|item| item.next_attempt)
+    }
+
+    /// Submits the queued proofs in order with `submit` until the queue
+    /// is empty or a submission fails. A failed proof is retried after a
+    /// backoff doubling with each attempt, and the ones behind it wait.
+    /// `submit` returns the L1 transaction accepting the proof and the
+    /// block it was mined in. Returns the proofs accepted by L1.
+    pub async fn drain<F, Fut>(&mut self, now: u64, mut submit: F) -> Vec<Submitted>
+    where
+        F: FnMut(u64, fusion::TxProof) -> Fut,
+        Fut: Future<Output = Result<(H256, Option<L1Block>), String>>,
+    {
+        let mut submitted = vec![];
+        while let Some(item) = self.items.front_mut() {
+            if item.next_attempt > now {
+                break;
+            }
+            let result = match fusion::TxProof::decode(&item.proof) {
+                Ok(proof) => submit(item.block, proof).await,
+                Err(e) => Err(format!("Invalid queued proof: {e}")),
+            };
+            match result {
+                Ok((l1_tx_hash, l1_block)) => {
+                    submitted.push(Submitted {
+                        block: item.block,
+                        l1_tx_hash,
+                        l1_block,
+                    });
+                    self.items.pop_front();
+                }
+                Err(e) => {
+                    item.attempts += 1;
+                    let backoff = 1u64
+                        .checked_shl(item.attempts)
+                        .unwrap_or(MAX_BACKOFF)
+                        .min(MAX_BACKOFF);
+                    item.next_attempt = now + backoff;
+                    warn!(
+                        block = item.block,
+                        attempt = item.attempts,
+                        backoff_s = backoff,
+                        error = %e,
+                        "Could not submit a proof, retrying"
+                    );
+                    break;
+                }
+            }
+        }
+        if let Err(e) = self.persist() {
+            error!("{e}");
+        }
+        submitted
+    }
+}
+
+/// How proofs are sent to L1.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct SubmitSettings {
+    confirmations: usize,
+    /// How long a transaction may stay unmined before it is replaced.
+    stuck_after: Duration,
+    /// How long signing may take, apart from `stuck_after`, since a
+    /// hardware wallet waits for a confirmation on the device.
+    signing_timeout: Duration,
+    /// Left to the node to estimate when 0.
+    gas_limit: u64,
+    gas_bump_percent: u64,
+    max_gas_bumps: u32,
+    /// Whether proofs go to `submitCompressedBlock`, see `calldata`.
+    compressed: bool,
+}
+
+impl SubmitSettings {
+    pub(crate) fn from_config(config: &Config) -> Self {
+        Self {
+            confirmations: config.l1_confirmations,
+            stuck_after: Duration::from_secs(config.l1_tx_timeout),
+            signing_timeout: Duration::from_secs(config.l1_signing_timeout),
+            gas_limit: config.l1_gas_limit,
+            gas_bump_percent: config.gas_bump_percent,
+            max_gas_bumps: config.max_gas_bumps,
+            compressed: config.compressed_calldata,
+        }
+    }
+
+    /// `gas_price` raised by `gas_bump_percent`, by at least 1 wei.
+    fn bump(&self, gas_price: U256) -> U256 {
+        (gas_price * (100 + self.gas_bump_percent) / 100).max(gas_price + 1)
+    }
+}
+
+/// Whether the node refused a transaction with the error code it gives an
+/// underpriced one: -32000 for geth, anvil and most clients, -32003
+/// ("transaction rejected") in EIP-1474. Other refusals share these codes,
+/// so they are retried too, at most `max_gas_bumps` times.
+fn underpriced(error: Option<&JsonRpcError>) -> bool {
+    matches!(error, Some(error) if matches!(error.code, -32000 | -32003))
+}
+
+/// The receipt of the first of `l1_tx_hashes` that was mined.
+async fn mined(l1_contract: &L1Contract, l1_tx_hashes: &[H256]) -> Option<TransactionReceipt> {
+    for l1_tx_hash in l1_tx_hashes {
+        if let Ok(Some(receipt)) = l1_contract
+            .client()
+            .get_transaction_receipt(*l1_tx_hash)
+            .await
+        {
+            return Some(receipt);
+        }
+    }
+    None
+}
+
+/// The L1 transaction of `receipt` and the block it was mined in,
+/// unless it reverted.
+fn accepted(receipt: TransactionReceipt) -> Result<(H256, Option<L1Block>), String> {
+    match receipt.status == Some(1u64.into()) {
+        true => Ok((receipt.transaction_hash, L1Block::of(&receipt))),
+        false => Err(format!(
+            "L1 transaction {:?} reverted",
+            receipt.transaction_hash
+        )),
+    }
+}
+
+/// Submits the proof of a transaction of `block` and waits for it to be
+/// confirmed. A transaction still unmined after `stuck_after` is replaced
+/// by the same one with a higher gas price, reusing its nonce. Returns
+/// the L1 transaction that was mined and its block.
+#[tracing::instrument(skip(l1_contract, shared, proof, chaos, settings))]
+pub(crate) async fn submit_proof(
+    l1_contract: L1Contract,
+    shared: Shared,
+    block: u64,
+    proof: fusion::TxProof,
+    chaos: Chaos,
+    settings: SubmitSettings,
+) -> Result<(H256, Option<L1Block>), String> {
+    debug!("Submitting a proof");
+    if let Some(e) = chaos.inject(Fault::Submission) {
+        return Err(e);
+    }
+    let client = l1_contract.client();
+    // Mined rather than pending, so an attempt stuck since a previous
+    // run is replaced instead of queued behind.
+    let nonce = client
+        .get_transaction_count(client.address(), Some(BlockNumber::Latest.into()))
+        .await
+        .map_err(|e| format!("Could not read the L1 nonce: {e}"))?;
+    let mut gas_price = client
+        .get_gas_price()
+        .await
+        .map_err(|e| format!("Could not read the L1 gas price: {e}"))?;
+    let mut sent = vec![];
+    let mut bumps = 0;
+    let compressed = settings
+        .compressed
+        .then(|| Bytes::from(calldata::compressed_call(&[proof.clone()])));
+    let result = loop {
+        let mut call = l1_contract
+            .submit_block([proof.clone()])
+            .nonce(nonce)
+            .gas_price(gas_price);
+        if settings.gas_limit > 0 {
+            call = call.gas(settings.gas_limit);
+        }
+        if let Some(compressed) = &compressed {
+            call.tx.set_data(compressed.clone());
+        }
+        let Ok(signed) = tokio::time::timeout(settings.signing_timeout, call.send()).await else {
+            break match mined(&l1_contract, &sent).await {
+                Some(receipt) => accepted(receipt),
+                None => Err(format!(
+                    "The submission was not signed within {}s",
+                    settings.signing_timeout.as_secs()
+                )),
+            };
+        };
+        let l1_tx = match signed {
+            Ok(l1_tx) => l1_tx,
+            Err(e)
+                if underpriced(e.as_middleware_error().and_then(|e| e.as_error_response()))
+                    && bumps < settings.max_gas_bumps =>
+            {
+                bumps += 1;
+                gas_price = settings.bump(gas_price);
+                warn!(error = %e, %gas_price, "Raising the gas price");
+                continue;
+            }
+            // An earlier attempt may have been mined meanwhile.
+            Err(e) => match mined(&l1_contract, &sent).await {
+                Some(receipt) => break accepted(receipt),
+                None => break Err(e.to_string()),
+            },
+        };
+        let l1_tx_hash = l1_tx.tx_hash();
+        info!(l1_tx_hash = ?l1_tx_hash, %gas_price, "Proof sent to L1");
+        sent.push(l1_tx_hash);
+        *shared.pending_l1_tx.lock().unwrap() = Some(l1_tx_hash);
+        if let Some(recorded) = shared
+            .blocks
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .rev()
+            .find(|recorded| recorded.header.number == block)
+        {
+            recorded.header.l1_tx_hashes.push(l1_tx_hash);
+        }
+        if let Some(delay) = chaos.receipt_delay() {
+            warn!(
+                delay_ms = delay.as_millis() as u64,
+                "{INJECTED} delaying the receipt"
+            );
+            tokio::time::sleep(delay).await;
+        }
+        let receipt = tokio::time::timeout(
+            settings.stuck_after,
+            l1_tx.confirmations(settings.confirmations),
+        )
+        .await;
+        match receipt {
+            Ok(Ok(Some(receipt))) => break accepted(receipt),
+            // Dropped, possibly because a replaced attempt was mined.
+            Ok(Ok(None)) => match mined(&l1_contract, &sent).await {
+                Some(receipt) => break accepted(receipt),
+                None => break Err(format!("L1 transaction {l1_tx_hash:?} was dropped")),
+            },
+            Ok(Err(e)) => {
+                break Err(format!(
+                    "L1 transaction {l1_tx_hash:?} was not confirmed: {e}"
+                ))
+            }
+            Err(_) if bumps < settings.max_gas_bumps => {
+                bumps += 1;
+                gas_price = settings.bump(gas_price);
+                warn!(l1_tx_hash = ?l1_tx_hash, %gas_price, "L1 transaction stuck, replacing it");
+            }
+            Err(_) => {
+                break Err(format!(
+                    "L1 transaction {l1_tx_hash:?} still unmined after {} gas price raises",
+                    settings.max_gas_bumps
+                ))
+            }
+        }
+    };
+    *shared.pending_l1_tx.lock().unwrap() = None;
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+
+    fn proof(tag: u64) -> fusion::TxProof {
+        fusion::TxProof {
+            input: vec![tag.into()],
+            ..Default::default()
+        }
+    }
+
+    fn tag(proof: &fusion::TxProof) -> u64 {
+        proof.input[0].as_u64()
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("fusion-{name}-{}", rand::random::<u64>()))
+    }
+
+    #[tokio::test]
+    async fn drains_in_order() {
+        let mut queue = SubmissionQueue::in_memory();
+        for block in 1..=3 {
+            queue.push(block, proof(block), 10).unwrap();
+        }
+        assert_eq!(queue.oldest_age(15), 5);
+
+        let calls = RefCell::new(vec![]);
+        let submitted = queue
+            .drain(10, |block, proof| {
+                calls.borrow_mut().push(tag(&proof));
+                async move { Ok((H256::from_low_u64_be(block), None)) }
+            })
+            .await;
+        assert_eq!(calls.into_inner(), vec![1, 2, 3]);
+        assert_eq!(submitted.len(), 3);
+        assert_eq!(submitted[2].l1_tx_hash, H256::from_low_u64_be(3));
+        assert!(queue.is_empty());
+        assert_eq!(queue.oldest_age(15), 0);
+    }
+
+    #[tokio::test]
+    async fn failures_back_off() {
+        let mut queue = SubmissionQueue::in_memory();
+        queue.push(1, proof(1), 0).unwrap();
+        queue.push(2, proof(2), 0).unwrap();
+
+        let calls = RefCell::new(0);
+        let failing = |_: u64, _: fusion::TxProof| {
+            *calls.borrow_mut() += 1;
+            async { Err::<(H256, _), _>("execution reverted".to_string()) }
+        };
+        assert!(queue.drain(0, failing).await.is_empty());
+        // The proofs behind the failed one wait for it.
+        assert_eq!(*calls.borrow(), 1);
+        assert_eq!(queue.next_attempt(), Some(2));
+
+        // Not retried before the backoff ends, then with a longer one.
+        assert!(queue.drain(1, failing).await.is_empty());
+        assert_eq!(*calls.borrow(), 1);
+        assert!(queue.drain(2, failing).await.is_empty());
+        assert_eq!(*calls.borrow(), 2);
+        assert_eq!(queue.next_attempt(), Some(6));
+
+        // The backoff is capped.
+        for _ in 0..20 {
+            let now = queue.next_attempt().unwrap();
+            queue.drain(now, failing).await;
+        }
+        let now = queue.next_attempt().unwrap();
+        queue.drain(now, failing).await;
+        assert_eq!(queue.next_attempt(), Some(now + MAX_BACKOFF));
+        assert_eq!(queue.len(), 2);
+    }
+
+    #[test]
+    fn deposit_claims() {
+        let claim = |kind: u64, recipient: u64, value: u64| {
+            let mut input = vec![U256::zero(); PUBLIC_INPUTS];
+            input[2] = kind.into();
+            input[8] = value.into();
+            input[14] = recipient.into();
+            fusion::TxProof {
+                input,
+                ..Default::default()
+            }
+        };
+        let mut queue = SubmissionQueue::in_memory();
+        queue.push(1, claim(1, 7, 100), 0).unwrap();
+        queue.push(1, claim(0, 7, 50), 0).unwrap();
+        queue.push(2, claim(1, 7, 30), 0).unwrap();
+        queue.push(2, claim(1, 8, 5), 0).unwrap();
+        queue.push(2, proof(1), 0).unwrap();
+
+        let claims = queue.deposit_claims();
+        assert_eq!(claims.len(), 2);
+        assert_eq!(claims[&7.into()], 130.into());
+        assert_eq!(claims[&8.into()], 5.into());
+    }
+
+    #[test]
+    fn stale_proofs_are_dropped() {
+        let dir = temp_dir("stale");
+        let mut queue = SubmissionQueue::open(&dir).unwrap();
+        let full = |tag: u64| fusion::TxProof {
+            input: vec![tag.into(); PUBLIC_INPUTS],
+            ..Default::default()
+        };
+        queue.push(1, proof(1), 0).unwrap();
+        // Not a proof of the circuit.
+        assert!(queue.front().is_none());
+        queue.pop_front().unwrap();
+        queue.push(2, full(2), 0).unwrap();
+        queue.push(3, full(3), 0).unwrap();
+        assert_eq!(queue.front(), Some((2, full(2))));
+
+        queue.clear().unwrap();
+        assert!(queue.front().is_none());
+        assert!(SubmissionQueue::open(&dir).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn restart_resumes_without_proving_again() {
+        let dir = temp_dir("submissions");
+        let mut queue = SubmissionQueue::open(&dir).unwrap();
+        for block in 1..=3 {
+            queue.push(block, proof(block), 0).unwrap();
+        }
+
+        // Block 1 is submitted, then L1 rejects block 2 and the
+        // sequencer stops.
+        let submitted = queue
+            .drain(0, |block, _| async move {
+                match block {
+                    1 => Ok((H256::repeat_byte(1), None)),
+                    _ => Err("L1 is congested".to_string()),
+                }
+            })
+            .await;
+        assert_eq!(submitted.len(), 1);
+        drop(queue);
+
+        let mut queue = SubmissionQueue::open(&dir).unwrap();
+        assert_eq!(queue.len(), 2);
+        let now = queue.next_attempt().unwrap();
+        let calls = RefCell::new(vec![]);
+        queue
+            .drain(now, |_, proof| {
+                calls.borrow_mut().push(tag(&proof));
+                async { Ok((H256::zero(), None)) }
+            })
+            .await;
+        // The queued proofs are submitted as they were computed.
+        assert_eq!(calls.into_inner(), vec![2, 3]);
+        assert!(SubmissionQueue::open(&dir).unwrap().is_empty());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn failed_writes_keep_the_queue() {
+        let dir = temp_dir("chaos");
+        let mut queue = SubmissionQueue::open(&dir).unwrap();
+        let chaos = Chaos::default();
+        let config = fusion_config::Config {
+            dev_l1: true,
+            ..Default::default()
+        };
+        let profile = fusion_api::ChaosProfile {
+            enabled: true,
+            storage_failure_rate: 0.0,
+            script: vec![Fault::Storage],
+            ..Default::default()
+        };
+        chaos.set(&config, profile).unwrap();
+        queue.set_chaos(chaos);
+
+        let error = queue.push(1, proof(1), 0).unwrap_err();
+        assert!(error.contains(crate::chaos::INJECTED), "{error}");
+        assert_eq!(queue.len(), 1);
+        assert!(SubmissionQueue::open(&dir).unwrap().is_empty());
+
+        // The next write persists both proofs.
+        queue.push(2, proof(2), 0).unwrap();
+        assert_eq!(SubmissionQueue::open(&dir).unwrap().len(), 2);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn gas_price_bumps() {
+        let mut settings = SubmitSettings::from_config(&Config::default());
+        assert_eq!(settings.bump(100.into()), 120.into());
+        // A replacement always pays more.
+        assert_eq!(settings.bump(1.into()), 2.into());
+        settings.gas_bump_percent = 0;
+        assert_eq!(settings.bump(100.into()), 101.into());
+    }
+
+    #[test]
+    fn underpriced_codes() {
+        let error = |code| JsonRpcError {
+            code,
+            message: "replacement transaction underpriced".to_string(),
+            data: None,
+        };
+        assert!(underpriced(Some(&error(-32000))));
+        assert!(underpriced(Some(&error(-32003))));
+        // Same message, but not a refusal of the transaction.
+        assert!(!underpriced(Some(&error(-32602))));
+        assert!(!underpriced(None));
+    }
+}
@@ -0,0 +1,533 @@
+//! Rebuilds the L2 state from the blocks L1 accepted, so that a restarted
+//! sequencer, or one sharing the contract with another submitter, builds
+//! on the root L1 holds.
+//!
+//! Fusion.sol emits `BlockSubmitted` for every block it accepts. The logs
+//! are fetched from `l1_sync_start_block` on, at most `l1_log_range` L1
+//! blocks at a time, fewer once the node refuses a range, and the proofs
+//! in the calldata of the logged transactions are replayed. What was
+//! replayed is appended to `l1_sync.jsonl` in `database_path`, so a
+//! restart only fetches the blocks after it. It replays the log from the
+//! newest snapshot on, see `snapshot`.
+
+use ethers::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+};
+use tracing::{info, warn};
+
+use fusion_api::TxKind;
+use fusion_prover::state::State;
+use fusion_prover::PUBLIC_INPUTS;
+
+use crate::consistency::decode_submission;
+use crate::snapshot::{self, Snapshot};
+use crate::transition::apply_transition;
+
+/// Topic of `BlockSubmitted(uint256,uint256)`.
+fn block_submitted_topic() -> H256 {
+    H256::from(ethers::utils::keccak256("BlockSubmitted(uint256,uint256)"))
+}
+
+/// A line of `l1_sync.jsonl`.
+#[derive(Debug, Serialize, Deserialize)]
+enum Record {
+    /// The public inputs of a proof accepted in L1 block `l1_block`.
+    Proof { l1_block: u64, input: Vec<U256> },
+    /// The proofs of every L1 block up to this one were replayed.
+    Through(u64),
+    /// The log starts from the snapshot of this L1 block, imported
+    /// instead of replayed.
+    Snapshot(u64),
+}
+
+pub struct L1Sync {
+    path: PathBuf,
+    state: State,
+    /// The L1 block the contract was deployed at.
+    start_block: u64,
+    /// The next L1 block to fetch the logs of.
+    next_block: u64,
+    /// L1 blocks per `eth_getLogs` call.
+    log_range: u64,
+    /// The L1 block of the last snapshot.
+    last_snapshot: Option<u64>,
+}
+
+impl L1Sync {
+    /// Opens the progress in `dir` and replays the proofs it holds on top
+    /// of the newest snapshot, or of `genesis` without one. Proofs
+    /// written after the last complete range, e.g. by a crash, are
+    /// dropped and fetched again.
+    pub fn open(
+        dir: &Path,
+        genesis: State,
+        start_block: u64,
+        log_range: u64,
+    ) -> Result<Self, String> {
+        let mut sync = Self {
+            path: dir.join("l1_sync.jsonl"),
+            state: genesis,
+            start_block,
+            next_block: start_block,
+            log_range: log_range.max(1),
+            last_snapshot: None,
+        };
+        if let Some((snapshot, state, _)) = snapshot::newest(dir)? {
+            info!(
+                l1_block = snapshot.l1_block,
+                accounts = snapshot.accounts.len(),
+                "Replaying L1 from a snapshot"
+            );
+            sync.state = state;
+            sync.next_block = snapshot.l1_block + 1;
+            sync.last_snapshot = Some(snapshot.l1_block);
+        }
+        let contents = match std::fs::read_to_string(&sync.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                if let Some(l1_block) = sync.last_snapshot {
+                    sync.append(&[Record::Snapshot(l1_block)])?;
+                }
+                return Ok(sync);
+            }
+            Err(e) => return Err(format!("Could not read {}: {e}", sync.path.display())),
+        };
+
+        let base = sync.last_snapshot;
+        let replayed = |l1_block| base.is_some_and(|base| l1_block <= base);
+        let mut pending = vec![];
+        let mut complete = 0;
+        let mut offset = 0;
+        for (i, line) in contents.split_inclusive('\n').enumerate() {
+            offset += line.len();
+            let record = match serde_json::from_str(line) {
+                Ok(record) => record,
+                Err(_) if offset == contents.len() => break,
+                Err(e) => {
+                    return Err(format!(
+                        "Could not parse line {} of {}: {e}",
+                        i + 1,
+                        sync.path.display()
+                    ))
+                }
+            };
+            match record {
+                Record::Proof { l1_block, .. } if replayed(l1_block) => {}
+                Record::Proof { input, .. } => pending.push(input),
+                Record::Through(l1_block) => {
+                    for input in pending.drain(..) {
+                        sync.state = replay_proof(sync.state, &input)?;
+                    }
+                    sync.next_block = sync.next_block.max(l1_block + 1);
+                    complete = offset;
+                }
+                Record::Snapshot(l1_block) if replayed(l1_block) => {}
+                Record::Snapshot(l1_block) => {
+                    return Err(format!(
+                        "{} starts from the snapshot of L1 block {l1_block}, which is missing or corrupt",
+                        sync.path.display()
+                    ))
+                }
+            }
+        }
+        if complete < contents.len() {
+            std::fs::write(&sync.path, &contents[..complete])
+                .map_err(|e| format!("Could not write {}: {e}", sync.path.display()))?;
+        }
+        Ok(sync)
+    }
+
+    /// The state after the replayed blocks.
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    pub fn next_block(&self) -> u64 {
+        self.next_block
+    }
+
+    /// Whether `interval` L1 blocks passed since the last snapshot, or
+    /// since the contract was deployed, at L1 block `latest`.
+    pub fn snapshot_due(&self, latest: u64, interval: u64) -> bool {
+        let since = self.last_snapshot.unwrap_or(self.start_block);
+        interval > 0 && latest >= since.saturating_add(interval)
+    }
+
+    /// Writes a snapshot of the replayed state, with `last_batch` the
+    /// last block built, and returns its L1 block.
+    pub fn snapshot(&mut self, last_batch: Option<u64>) -> Result<u64, String> {
+        let l1_block = match self.next_block.checked_sub(1) {
+            Some(l1_block) if l1_block >= self.start_block => l1_block,
+            _ => return Err("Nothing was replayed from L1 yet".to_string()),
+        };
+        let snapshot = Snapshot::new(&self.state, l1_block, last_batch);
+        snapshot::write(self.path.parent().unwrap(), &snapshot)?;
+        self.last_snapshot = Some(l1_block);
+        Ok(l1_block)
+    }
+
+    /// Installs the snapshot file `bytes` in `dir`, in place of the
+    /// snapshots and the log there, so that the next start replays L1
+    /// from it. The sequencer must be stopped.
+    pub fn import_snapshot(dir: &Path, bytes: &[u8]) -> Result<Snapshot, String> {
+        let (snapshot, _) = snapshot::decode(bytes)?;
+        snapshot::remove_from(dir, 0)?;
+        snapshot::write(dir, &snapshot)?;
+        let path = dir.join("l1_sync.jsonl");
+        match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(format!("Could not remove {}: {e}", path.display()))
+            }
+            _ => Ok(snapshot),
+        }
+    }
+
+    /// Forgets what was replayed from L1 block `l1_block` on, once a
+    /// reorg dropped it, with the snapshots taken since, and replays the
+    /// rest again on top of the snapshot before or of `genesis`. The next
+    /// `catch_up` fetches the logs from `l1_block` again.
+    pub fn rewind(&mut self, l1_block: u64, genesis: State) -> Result<(), String> {
+        if l1_block >= self.next_block {
+            return Ok(());
+        }
+        let dir = self.path.parent().unwrap().to_path_buf();
+        snapshot::remove_from(&dir, l1_block)?;
+        let contents = match std::fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(format!("Could not read {}: {e}", self.path.display())),
+        };
+        let mut kept: Vec<Record> = vec![];
+        // Then the log starts after `l1_block`, there is nothing to keep.
+        let mut from_dropped_snapshot = false;
+        for line in contents.lines() {
+            let record = serde_json::from_str(line)
+                .map_err(|e| format!("Could not parse {}: {e}", self.path.display()))?;
+            match record {
+                Record::Snapshot(b) if b >= l1_block => from_dropped_snapshot = true,
+                Record::Proof { l1_block: b, .. } | Record::Through(b) if b >= l1_block => {}
+                record => kept.push(record),
+            }
+        }
+        if l1_block > self.start_block && !from_dropped_snapshot {
+            kept.push(Record::Through(l1_block - 1));
+        }
+        std::fs::write(&self.path, "")
+            .map_err(|e| format!("Could not write {}: {e}", self.path.display()))?;
+        self.append(&kept)?;
+        *self = Self::open(&dir, genesis, self.start_block, self.log_range)?;
+        Ok(())
+    }
+
+    /// Replays the blocks L1 accepted from `next_block` to `to`, both
+    /// included.
+    pub async fn catch_up<M: Middleware>(
+        &mut self,
+        client: &M,
+        contract: Address,
+        to: u64,
+    ) -> Result<(), String> {
+        while self.next_block <= to {
+            let from = self.next_block;
+            let end = to.min(from.saturating_add(self.log_range - 1));
+            let filter = Filter::new()
+                .address(contract)
+                .topic0(block_submitted_topic())
+                .from_block(from)
+                .to_block(end);
+            let logs = match client.get_logs(&filter).await {
+                Ok(logs) => logs,
+                Err(e) if self.log_range > 1 => {
+                    self.log_range /= 2;
+                    warn!(
+                        from,
+                        end,
+                        log_range = self.log_range,
+                        error = %e,
+                        "Could not fetch the logs, asking for fewer blocks at a time"
+                    );
+                    continue;
+                }
+                Err(e) => return Err(format!("Could not fetch the logs of L1 block {from}: {e}")),
+            };
+
+            let mut state = self.state.clone();
+            let mut records = vec![];
+            for log in logs {
+                let (Some(l1_tx_hash), Some(l1_block)) = (log.transaction_hash, log.block_number)
+                else {
+                    return Err(format!("A log of L1 block {from} to {end} is pending"));
+                };
+                let l1_tx = client
+                    .get_transaction(l1_tx_hash)
+                    .await
+                    .map_err(|e| e.to_string())?
+                    .ok_or_else(|| format!("L1 transaction {l1_tx_hash:?} not found"))?;
+                for input in decode_submission(&l1_tx.input)
+                    .map_err(|e| format!("L1 transaction {l1_tx_hash:?}: {e}"))?
+                {
+                    state = replay_proof(state, &input)
+                        .map_err(|e| format!("L1 transaction {l1_tx_hash:?}: {e}"))?;
+                    records.push(Record::Proof {
+                        l1_block: l1_block.as_u64(),
+                        input,
+                    });
+                }
+            }
+            records.push(Record::Through(end));
+            self.append(&records)?;
+            self.state = state;
+            self.next_block = end + 1;
+        }
+        Ok(())
+    }
+
+    fn append(&self, records: &[Record]) -> Result<(), String> {
+        let mut lines = vec![];
+        for record in records {
+            serde_json::to_writer(&mut lines, record).map_err(|e| e.to_string())?;
+            lines.push(b'\n');
+        }
+        std::fs::create_dir_all(self.path.parent().unwrap())
+            .and_then(|()| {
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)
+            })
+            .and_then(|mut file| file.write_all(&lines))
+            .map_err(|e| format!("Could not write {}: {e}", self.path.display()))
+    }
+}
+
+/// Applies a proof L1 accepted to `state`, which must be at the root the
+/// proof starts from.
+pub fn replay_proof(state: State, input: &[U256]) -> Result<State, String> {
+    if input.len() != PUBLIC_INPUTS {
+        return Err(format!(
+            "{} public inputs, expected {PUBLIC_INPUTS}",
+            input.len()
+        ));
+    }
+    if input[2] > U256::from(2) {
+        return Err(format!("invalid transaction kind {}", input[2]));
+    }
+    if state.root() != input[0] {
+        return Err(format!(
+            "proof starts from root {}, the replay is at {}",
+            input[0],
+            state.root()
+        ));
+    }
+    let (sender, to, nonce, value) = (input[14], input[17], input[7], input[8]);
+    let state = apply_transition(state, &TxKind::from(input[2]), sender, to, nonce, value)
+        .map_err(|e| format!("the transaction cannot be replayed: {e}"))?;
+    if state.root() != input[1] {
+        return Err(format!(
+            "proof ends at root {}, the replay reached {}",
+            input[1],
+            state.root()
+        ));
+    }
+    Ok(state)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ethers::abi::AbiEncode;
+    use fusion_config::Config;
+    use fusion_l1::fusion;
+
+    use crate::dev;
+
+    /// Public inputs of two transfers between dev accounts, with the
+    /// state they start from.
+    fn proofs() -> (State, Vec<Vec<U256>>) {
+        let config = Config {
+            dev: true,
+            dev_accounts: 2,
+            ..Default::default()
+        };
+        let genesis = dev::genesis_state(&config);
+        let mut state = genesis.clone();
+        let inputs = (0..2)
+            .map(|i| dev::apply_dev_transfer(&mut state, i, 1 - i, 10))
+            .collect();
+        (genesis, inputs)
+    }
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("fusion-sync-{}", rand::random::<u64>()))
+    }
+
+    #[test]
+    fn replay() {
+        let (genesis, inputs) = proofs();
+        let state = replay_proof(genesis.clone(), &inputs[0]).unwrap();
+        let state = replay_proof(state, &inputs[1]).unwrap();
+        assert_eq!(state.root(), inputs[1][1]);
+
+        // Out of order.
+        assert!(replay_proof(genesis.clone(), &inputs[1])
+            .unwrap_err()
+            .starts_with("proof starts from root"));
+        let mut forged = inputs[0].clone();
+        forged[8] += 1.into();
+        assert!(replay_proof(genesis, &forged)
+            .unwrap_err()
+            .starts_with("proof ends at root"));
+    }
+
+    #[test]
+    fn resume() {
+        let (genesis, inputs) = proofs();
+        let dir = temp_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("l1_sync.jsonl");
+        let record = |record: Record| serde_json::to_string(&record).unwrap() + "\n";
+        let complete = record(Record::Proof {
+            l1_block: 3,
+            input: inputs[0].clone(),
+        }) + &record(Record::Through(4));
+        // The second range was cut short.
+        let torn = record(Record::Proof {
+            l1_block: 6,
+            input: inputs[1].clone(),
+        }) + r#"{"Thro"#;
+        std::fs::write(&path, complete.clone() + &torn).unwrap();
+
+        let sync = L1Sync::open(&dir, genesis.clone(), 0, 100).unwrap();
+        assert_eq!(sync.next_block(), 5);
+        assert_eq!(sync.state().root(), inputs[0][1]);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), complete);
+
+        std::fs::write(&path, "{}\n".to_string() + &complete).unwrap();
+        assert!(L1Sync::open(&dir, genesis, 0, 100)
+            .unwrap_err()
+            .contains("line 1"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn snapshots() {
+        let (genesis, inputs) = proofs();
+        let dir = temp_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        let record = |record: Record| serde_json::to_string(&record).unwrap() + "\n";
+        let log = record(Record::Proof {
+            l1_block: 3,
+            input: inputs[0].clone(),
+        }) + &record(Record::Through(4))
+            + &record(Record::Proof {
+                l1_block: 6,
+                input: inputs[1].clone(),
+            })
+            + &record(Record::Through(7));
+        std::fs::write(dir.join("l1_sync.jsonl"), log).unwrap();
+
+        let mut sync = L1Sync::open(&dir, genesis.clone(), 0, 100).unwrap();
+        assert!(sync.snapshot_due(7, 5));
+        assert_eq!(sync.snapshot(Some(2)).unwrap(), 7);
+        assert!(!sync.snapshot_due(11, 5));
+        assert!(sync.snapshot_due(12, 5));
+        assert_eq!(snapshot::list(&dir).unwrap(), [7]);
+
+        // Imported elsewhere, the log starts from the snapshot.
+        let (_, _, bytes) = snapshot::newest(&dir).unwrap().unwrap();
+        let imported = temp_dir();
+        let snapshot = L1Sync::import_snapshot(&imported, &bytes).unwrap();
+        assert_eq!(snapshot.last_batch, Some(2));
+        let mut restored = L1Sync::open(&imported, genesis.clone(), 0, 100).unwrap();
+        assert_eq!(restored.next_block(), 8);
+        assert_eq!(restored.state().root(), inputs[1][1]);
+        assert_eq!(
+            std::fs::read_to_string(imported.join("l1_sync.jsonl")).unwrap(),
+            record(Record::Snapshot(7))
+        );
+
+        // A reorg drops the snapshots it reaches.
+        sync.rewind(6, genesis.clone()).unwrap();
+        assert_eq!(sync.next_block(), 6);
+        assert_eq!(sync.state().root(), inputs[0][1]);
+        assert!(snapshot::list(&dir).unwrap().is_empty());
+        restored.rewind(5, genesis.clone()).unwrap();
+        assert_eq!(restored.next_block(), 0);
+        assert_eq!(restored.state().root(), genesis.root());
+
+        // The log cannot be replayed without the snapshot it starts from.
+        L1Sync::import_snapshot(&imported, &bytes).unwrap();
+        L1Sync::open(&imported, genesis.clone(), 0, 100).unwrap();
+        std::fs::write(imported.join("snapshots").join("7.json"), b"{}").unwrap();
+        assert!(L1Sync::open(&imported, genesis, 0, 100)
+            .unwrap_err()
+            .contains("missing or corrupt"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+        std::fs::remove_dir_all(imported).unwrap();
+    }
+
+    #[tokio::test]
+    async fn catch_up() {
+        let (genesis, inputs) = proofs();
+        let contract = Address::repeat_byte(1);
+        let submission = |input: &Vec<U256>| Transaction {
+            input: fusion::SubmitBlockCall {
+                l_2_block: [fusion::TxProof {
+                    input: input.clone(),
+                    ..Default::default()
+                }],
+            }
+            .encode()
+            .into(),
+            ..Default::default()
+        };
+        let log = |l1_block: u64, l1_tx_hash: u64| Log {
+            address: contract,
+            block_number: Some(l1_block.into()),
+            transaction_hash: Some(H256::from_low_u64_be(l1_tx_hash)),
+            ..Default::default()
+        };
+
+        // Responses are popped from the back, in the reverse order of
+        // the requests.
+        let (provider, mock) = Provider::mocked();
+        mock.push::<Transaction, _>(submission(&inputs[1])).unwrap();
+        mock.push::<Vec<Log>, _>(vec![log(5, 2)]).unwrap();
+        mock.push::<Vec<Log>, _>(vec![]).unwrap();
+        mock.push::<Transaction, _>(submission(&inputs[0])).unwrap();
+        mock.push::<Vec<Log>, _>(vec![log(1, 1)]).unwrap();
+        // The node refuses 4 blocks at once.
+        mock.push_response(MockResponse::Error(JsonRpcError {
+            code: -32005,
+            message: "query returned more than 10000 results".to_string(),
+            data: None,
+        }));
+
+        let dir = temp_dir();
+        let mut sync = L1Sync::open(&dir, genesis.clone(), 0, 4).unwrap();
+        sync.catch_up(&provider, contract, 5).await.unwrap();
+        assert_eq!(sync.next_block(), 6);
+        assert_eq!(sync.state().root(), inputs[1][1]);
+
+        // A restart replays from the file without asking L1.
+        let mut sync = L1Sync::open(&dir, genesis.clone(), 0, 4).unwrap();
+        assert_eq!(sync.next_block(), 6);
+        assert_eq!(sync.state().root(), inputs[1][1]);
+
+        // L1 block 5 was reorged out.
+        sync.rewind(5, genesis.clone()).unwrap();
+        assert_eq!(sync.next_block(), 5);
+        assert_eq!(sync.state().root(), inputs[0][1]);
+        let sync = L1Sync::open(&dir, genesis, 0, 4).unwrap();
+        assert_eq!(sync.next_block(), 5);
+        assert_eq!(sync.state().root(), inputs[0][1]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
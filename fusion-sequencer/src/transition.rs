@@ -0,0 +1,318 @@
+//! The state transition of a transaction: the checks it must pass
+//! against the state and the accounts it changes. Blocks, the replay of
+//! L1 and the tools that re-execute blocks all go through it.
+
+use ethers::types::U256;
+
+use fusion_api::{SignedTx, Tx, TxKind};
+use fusion_prover::state::{Account, State};
+
+/// Checks `tx` against the state before its block, where the earlier
+/// transactions of its sender in the block are not applied yet, so its
+/// nonce only has to be unused.
+pub(crate) fn validate_tx(state: &State, tx: &SignedTx) -> anyhow::Result<()> {
+    verify_tx_signature(tx)?;
+    check_tx(state, &tx.tx, false)
+}
+
+/// The checks of `validate_tx` that depend on the state, against the
+/// state right before `tx`: its nonce must be the next one.
+pub(crate) fn check_tx_state(state: &State, tx: &Tx) -> anyhow::Result<()> {
+    check_tx(state, tx, true)
+}
+
+fn check_tx(state: &State, tx: &Tx, next_nonce: bool) -> anyhow::Result<()> {
+    let sender = state.get(&tx.sender_account());
+    check_tx_account(&sender, tx, next_nonce)?;
+    transition(
+        &tx.kind,
+        sender,
+        state.get(&tx.to_account()),
+        tx.nonce,
+        tx.value,
+    )
+    .map(|_| ())
+}
+
+/// Checks `tx` against the `account` of its sender.
+pub(crate) fn check_tx_account(account: &Account, tx: &Tx, next_nonce: bool) -> anyhow::Result<()> {
+    if matches!(tx.kind, TxKind::Transfer) && tx.sender == tx.to {
+        Err(anyhow::anyhow!("Tx to self"))
+    } else if matches!(tx.kind, TxKind::Transfer | TxKind::Withdraw) && account.balance < tx.value {
+        Err(anyhow::anyhow!("Insufficient balance"))
+    } else if account.nonce >= tx.nonce {
+        Err(anyhow::anyhow!("Nonce too low"))
+    } else if next_nonce && tx.nonce != account.nonce + 1 {
+        Err(anyhow::anyhow!(
+            "Nonce too high, expected {}",
+            account.nonce + 1
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Applies `tx` to `state`, or fails if a balance would go below zero or
+/// overflow. Other checks are left to `check_tx_state`.
+pub(crate) fn apply_tx(state: State, tx: &Tx) -> anyhow::Result<State> {
+    apply_transition(
+        state,
+        &tx.kind,
+        tx.sender_account(),
+        tx.to_account(),
+        tx.nonce,
+        tx.value,
+    )
+}
+
+/// Applies a transaction between the accounts at `sender_addr` and
+/// `to_addr`, which is all the proofs posted to L1 reveal of it.
+pub(crate) fn apply_transition(
+    mut state: State,
+    kind: &TxKind,
+    sender_addr: U256,
+    to_addr: U256,
+    nonce: U256,
+    value: U256,
+) -> anyhow::Result<State> {
+    let (new_account_sender, new_account_to) = transition(
+        kind,
+        state.get(&sender_addr),
+        state.get(&to_addr),
+        nonce,
+        value,
+    )?;
+
+    state.update(&sender_addr, new_account_sender);
+    state.update(&to_addr, new_account_to);
+
+    Ok(state)
+}
+
+/// The sender and recipient accounts after a transaction, unless a
+/// balance would go below zero or overflow.
+pub(crate) fn transition(
+    kind: &TxKind,
+    account_sender: Account,
+    account_to: Account,
+    nonce: U256,
+    value: U256,
+) -> anyhow::Result<(Account, Account)> {
+    let sender_balance = match kind {
+        TxKind::Deposit => account_sender.balance.checked_add(value),
+        TxKind::Transfer | TxKind::Withdraw => account_sender.balance.checked_sub(value),
+    }
+    .ok_or_else(|| match kind {
+        TxKind::Deposit => anyhow::anyhow!("Balance overflow"),
+        TxKind::Transfer | TxKind::Withdraw => anyhow::anyhow!("Insufficient balance"),
+    })?;
+    let new_account_sender = Account::new(account_sender.id, sender_balance, nonce);
+    let new_account_to = match kind {
+        TxKind::Transfer => {
+            let balance = account_to
+                .balance
+                .checked_add(value)
+                .ok_or_else(|| anyhow::anyhow!("Recipient balance overflow"))?;
+            Account::new(account_to.id, balance, account_to.nonce)
+        }
+        TxKind::Withdraw | TxKind::Deposit => account_to,
+    };
+    Ok((new_account_sender, new_account_to))
+}
+
+pub(crate) fn verify_tx_signature(signed_tx: &SignedTx) -> anyhow::Result<()> {
+    fusion_wallet::verify_tx_signature(signed_tx)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use fusion_api::NATIVE_TOKEN;
+    use fusion_config::Config;
+    use fusion_types::{PublicKey, ToU256};
+
+    use crate::dev;
+
+    #[test]
+    fn state_update_test() {
+        let state = State::default();
+
+        let (_sk_1, pk_1) = fusion_wallet::new_key_pair();
+        let (_sk_2, pk_2) = fusion_wallet::new_key_pair();
+
+        let tx_1 = fusion_api::Tx {
+            kind: TxKind::Deposit,
+            sender: pk_1.clone().to_u256(),
+            to: 0.into(),
+            nonce: 1.into(),
+            value: 1000.into(),
+            not_before: None,
+            not_after: None,
+            chain_id: None,
+            token: NATIVE_TOKEN,
+        };
+
+        let tx_2 = fusion_api::Tx {
+            kind: TxKind::Transfer,
+            sender: pk_1.clone().to_u256(),
+            to: pk_2.clone().to_u256(),
+            nonce: 2.into(),
+            value: 500.into(),
+            not_before: None,
+            not_after: None,
+            chain_id: None,
+            token: NATIVE_TOKEN,
+        };
+
+        let tx_3 = fusion_api::Tx {
+            kind: TxKind::Withdraw,
+            sender: pk_2.clone().to_u256(),
+            to: 0.into(),
+            nonce: 3.into(),
+            value: 200.into(),
+            not_before: None,
+            not_after: None,
+            chain_id: None,
+            token: NATIVE_TOKEN,
+        };
+
+        let state = apply_tx(state, &tx_1).unwrap();
+        let state = apply_tx(state, &tx_2).unwrap();
+        let state = apply_tx(state, &tx_3).unwrap();
+
+        let acc_1 = state.get(&pk_1.address());
+        let acc_2 = state.get(&pk_2.address());
+
+        assert_eq!(acc_1.balance, 500.into());
+        assert_eq!(acc_2.balance, 300.into());
+
+        assert_eq!(acc_1.nonce, 2.into());
+        assert_eq!(acc_2.nonce, 3.into());
+    }
+
+    fn plain_tx(kind: TxKind, from: &PublicKey, to: &PublicKey, nonce: u64, value: U256) -> Tx {
+        Tx {
+            kind,
+            sender: from.to_u256(),
+            to: to.to_u256(),
+            nonce: nonce.into(),
+            value,
+            not_before: None,
+            not_after: None,
+            chain_id: None,
+            token: NATIVE_TOKEN,
+        }
+    }
+
+    /// Checks `tx` like a block does and applies it.
+    fn checked_apply(state: &State, tx: &Tx) -> anyhow::Result<State> {
+        check_tx_state(state, tx).and_then(|()| apply_tx(state.clone(), tx))
+    }
+
+    #[test]
+    fn state_transitions() {
+        let config = Config {
+            dev_accounts: 2,
+            dev_account_balance: 1000,
+            ..Default::default()
+        };
+        let state = dev::genesis_state(&config);
+        let accounts = dev::dev_accounts(&config);
+        let (a, b) = (&accounts[0].1, &accounts[1].1);
+        let (_, fresh) = fusion_wallet::new_key_pair();
+        let balance = |state: &State, pk: &PublicKey| state.get(&pk.address()).balance;
+        let error = |state: &State, tx: &Tx| checked_apply(state, tx).unwrap_err().to_string();
+
+        let to_self = plain_tx(TxKind::Transfer, a, a, 1, 10.into());
+        assert_eq!(error(&state, &to_self), "Tx to self");
+
+        let to_fresh = plain_tx(TxKind::Transfer, a, &fresh, 1, 10.into());
+        let after = checked_apply(&state, &to_fresh).unwrap();
+        assert_eq!(balance(&after, a), 990.into());
+        assert_eq!(balance(&after, &fresh), 10.into());
+        assert_eq!(after.get(&fresh.address()).nonce, 0.into());
+
+        let zero = plain_tx(TxKind::Transfer, a, b, 1, 0.into());
+        let after = checked_apply(&state, &zero).unwrap();
+        assert_eq!(
+            (balance(&after, a), balance(&after, b)),
+            (1000.into(), 1000.into())
+        );
+        assert_eq!(after.get(&a.address()).nonce, 1.into());
+
+        let exact = plain_tx(TxKind::Transfer, a, b, 1, 1000.into());
+        let after = checked_apply(&state, &exact).unwrap();
+        assert_eq!(
+            (balance(&after, a), balance(&after, b)),
+            (0.into(), 2000.into())
+        );
+
+        let over = plain_tx(TxKind::Transfer, a, b, 1, 1001.into());
+        assert_eq!(error(&state, &over), "Insufficient balance");
+        assert_eq!(
+            apply_tx(state.clone(), &over).unwrap_err().to_string(),
+            "Insufficient balance"
+        );
+
+        // Credits never wrap around.
+        let max = plain_tx(TxKind::Deposit, &fresh, a, 1, U256::MAX);
+        let after = checked_apply(&state, &max).unwrap();
+        let again = plain_tx(TxKind::Deposit, &fresh, a, 2, U256::MAX);
+        assert_eq!(error(&after, &again), "Balance overflow");
+        let to_max = plain_tx(TxKind::Transfer, a, &fresh, 1, 1.into());
+        assert_eq!(error(&after, &to_max), "Recipient balance overflow");
+        assert_eq!(
+            apply_tx(after.clone(), &to_max).unwrap_err().to_string(),
+            "Recipient balance overflow"
+        );
+    }
+
+    #[test]
+    fn transfers_conserve_supply_and_cannot_be_replayed() {
+        use rand::{Rng, SeedableRng};
+
+        let config = Config {
+            dev_accounts: 4,
+            dev_account_balance: 1000,
+            ..Default::default()
+        };
+        let genesis = dev::genesis_state(&config);
+        let accounts: Vec<_> = dev::dev_accounts(&config)
+            .into_iter()
+            .map(|(_, pk)| pk)
+            .collect();
+        let supply = |state: &State| {
+            accounts.iter().fold(U256::zero(), |sum, pk| {
+                sum + state.get(&pk.address()).balance
+            })
+        };
+        for seed in 0..32 {
+            let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(seed);
+            let mut state = genesis.clone();
+            let mut batch = vec![];
+            for _ in 0..64 {
+                // Mostly the next nonce, so that most transfers are valid.
+                let from = &accounts[rng.gen_range(0..accounts.len())];
+                let to = &accounts[rng.gen_range(0..accounts.len())];
+                let nonce = match rng.gen_bool(0.8) {
+                    true => state.get(&from.address()).nonce.as_u64() + 1,
+                    false => rng.gen_range(0..64),
+                };
+                let value = rng.gen_range(0..=600).into();
+                let tx = plain_tx(TxKind::Transfer, from, to, nonce, value);
+                if let Ok(next) = checked_apply(&state, &tx) {
+                    state = next;
+                    batch.push(tx);
+                }
+            }
+            assert_eq!(supply(&state), supply(&genesis), "seed {seed}");
+            assert!(!batch.is_empty());
+            // Every transaction of the batch reuses a nonce the second time.
+            for tx in &batch {
+                assert!(state.get(&PublicKey::from(tx.sender).address()).nonce >= tx.nonce);
+                assert!(checked_apply(&state, tx).is_err(), "seed {seed}");
+            }
+        }
+    }
+}
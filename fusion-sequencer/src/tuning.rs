@@ -0,0 +1,111 @@
+//! Tuning of the number of transactions that trigger a block, so that
+//! proving a block keeps taking about `target_proving_time`.
+//!
+//! A block proven too slowly shrinks the batch in proportion to how
+//! much it overshot, and a full block proven well within the target
+//! grows it by one transaction. Blocks built before the batch was full,
+//! e.g. by `admin_flush_batch`, only ever shrink it.
+
+use std::time::Duration;
+
+/// Proving time under which a full block grows the batch, in percent
+/// of the target.
+const GROW_BELOW: u32 = 75;
+
+#[derive(Clone, Debug)]
+pub struct BatchTuner {
+    min: usize,
+    max: usize,
+    target: Duration,
+    batch_size: usize,
+}
+
+impl BatchTuner {
+    /// Starts from `initial`, brought within `min..=max`.
+    pub fn new(initial: usize, min: usize, max: usize, target: Duration) -> Result<Self, String> {
+        if min == 0 || min > max {
+            return Err(format!("Invalid batch size bounds {min}..={max}"));
+        }
+        if target.is_zero() {
+            return Err("target_proving_time must be positive".to_string());
+        }
+        Ok(Self {
+            min,
+            max,
+            target,
+            batch_size: initial.clamp(min, max),
+        })
+    }
+
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    /// Takes into account that a block of `txs` transactions took
+    /// `proving` to prove. Returns the new batch size if it changed.
+    pub fn record(&mut self, txs: usize, proving: Duration) -> Option<usize> {
+        let previous = self.batch_size;
+        if proving > self.target {
+            let fitting = (txs as u128 * self.target.as_millis() / proving.as_millis()) as usize;
+            self.batch_size = fitting.min(previous.saturating_sub(1)).max(self.min);
+        } else if txs >= previous && proving < self.target * GROW_BELOW / 100 {
+            self.batch_size = (previous + 1).min(self.max);
+        }
+        (self.batch_size != previous).then_some(self.batch_size)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn bounds() {
+        assert!(BatchTuner::new(1, 0, 4, secs(60)).is_err());
+        assert!(BatchTuner::new(1, 5, 4, secs(60)).is_err());
+        assert!(BatchTuner::new(1, 1, 4, secs(0)).is_err());
+        assert_eq!(BatchTuner::new(10, 2, 4, secs(60)).unwrap().batch_size(), 4);
+        assert_eq!(BatchTuner::new(1, 2, 4, secs(60)).unwrap().batch_size(), 2);
+    }
+
+    #[test]
+    fn shrinks_in_proportion() {
+        let mut tuner = BatchTuner::new(20, 1, 64, secs(60)).unwrap();
+        // Twice too slow: half the transactions fit.
+        assert_eq!(tuner.record(20, secs(120)), Some(10));
+        // Barely too slow: at least one transaction less.
+        assert_eq!(tuner.record(10, secs(61)), Some(9));
+        // Never below the minimum.
+        assert_eq!(tuner.record(9, secs(6000)), Some(1));
+        assert_eq!(tuner.record(1, secs(6000)), None);
+    }
+
+    #[test]
+    fn grows_with_full_fast_blocks() {
+        let mut tuner = BatchTuner::new(4, 1, 6, secs(60)).unwrap();
+        assert_eq!(tuner.record(4, secs(10)), Some(5));
+        // Within the target but not well within: kept.
+        assert_eq!(tuner.record(5, secs(50)), None);
+        // A flushed block says nothing about a full one.
+        assert_eq!(tuner.record(2, secs(1)), None);
+        assert_eq!(tuner.record(5, secs(10)), Some(6));
+        // Never above the maximum.
+        assert_eq!(tuner.record(6, secs(1)), None);
+        assert_eq!(tuner.batch_size(), 6);
+    }
+
+    #[test]
+    fn converges() {
+        // Proving takes 5s per transaction, so 12 of them fit in 60s.
+        let mut tuner = BatchTuner::new(1, 1, 64, secs(60)).unwrap();
+        for _ in 0..100 {
+            let txs = tuner.batch_size();
+            tuner.record(txs, secs(5 * txs as u64));
+        }
+        assert!((9..=12).contains(&tuner.batch_size()), "{tuner:?}");
+    }
+}
@@ -0,0 +1,214 @@
+//! Witnesses of the proven blocks: for each transaction, the leaves of
+//! its sender and recipient before and after it, with their Merkle
+//! paths. The witness of a block is checked with `verify` and written to
+//! `witnesses/<number>.json` in `database_path` before its proofs are
+//! requested, and `get_batch_witness` reads it back.
+
+use std::path::{Path, PathBuf};
+
+use ethers::types::U256;
+use rayon::prelude::*;
+
+use fusion_api::{BatchWitness, LeafWitness, SignedTx, TxWitness};
+use fusion_prover::state::{Account, State};
+
+use crate::transition::transition;
+
+fn leaf(state: &State, address: U256) -> LeafWitness {
+    let account = state.get(&address);
+    LeafWitness {
+        address,
+        id: account.id,
+        balance: account.balance,
+        nonce: account.nonce,
+        path: state.proof(&address),
+    }
+}
+
+fn account(leaf: &LeafWitness) -> Account {
+    Account::new(leaf.id, leaf.balance, leaf.nonce)
+}
+
+/// The witness of block `number`, where `states` are the state before
+/// the block followed by the state after each of its `txs`.
+pub fn batch_witness(number: u64, txs: &[SignedTx], states: &[State]) -> BatchWitness {
+    assert_eq!(states.len(), txs.len() + 1);
+    let txs = txs
+        .par_iter()
+        .zip(states.par_windows(2))
+        .map(|(tx, states)| {
+            let (sender, to) = (tx.tx.sender_account(), tx.tx.to_account());
+            TxWitness {
+                tx: tx.clone(),
+                pre_root: states[0].root(),
+                post_root: states[1].root(),
+                pre_sender: leaf(&states[0], sender),
+                pre_to: leaf(&states[0], to),
+                post_sender: leaf(&states[1], sender),
+                post_to: leaf(&states[1], to),
+            }
+        })
+        .collect();
+    BatchWitness {
+        number,
+        pre_root: states[0].root(),
+        post_root: states.last().unwrap().root(),
+        txs,
+    }
+}
+
+/// Re-executes the transactions of `witness` from the witness alone and
+/// checks that they lead from its pre-state root to its post-state root.
+pub fn verify(witness: &BatchWitness) -> Result<(), String> {
+    let mut root = witness.pre_root;
+    for (i, tx) in witness.txs.iter().enumerate() {
+        verify_tx(tx, root).map_err(|e| format!("Transaction {}: {e}", i + 1))?;
+        root = tx.post_root;
+    }
+    if root != witness.post_root {
+        return Err("The transactions do not end at the post-state root".to_string());
+    }
+    Ok(())
+}
+
+fn verify_tx(witness: &TxWitness, pre_root: U256) -> Result<(), String> {
+    let tx = &witness.tx.tx;
+    if witness.pre_root != pre_root {
+        return Err("Does not start at the root the previous one ended at".to_string());
+    }
+    fusion_wallet::verify_tx_signature(&witness.tx).map_err(|e| e.to_string())?;
+    let (sender, to) = (tx.sender_account(), tx.to_account());
+    for (leaf, address) in [
+        (&witness.pre_sender, sender),
+        (&witness.pre_to, to),
+        (&witness.post_sender, sender),
+        (&witness.post_to, to),
+    ] {
+        if leaf.address != address {
+            return Err(format!("Unexpected leaf {}", leaf.address));
+        }
+    }
+    let root_with = |leaf: &LeafWitness, account: &Account, path: &[U256]| {
+        State::root_from_proof(&leaf.address, account, path)
+            .ok_or_else(|| format!("Invalid path for leaf {}", leaf.address))
+    };
+    let (pre_sender, pre_to) = (account(&witness.pre_sender), account(&witness.pre_to));
+    if root_with(&witness.pre_sender, &pre_sender, &witness.pre_sender.path)? != pre_root
+        || root_with(&witness.pre_to, &pre_to, &witness.pre_to.path)? != pre_root
+    {
+        return Err("The accounts are not in the pre-state".to_string());
+    }
+    if tx.nonce <= pre_sender.nonce {
+        return Err("Nonce too low".to_string());
+    }
+
+    let (post_sender, post_to) =
+        transition(&tx.kind, pre_sender, pre_to, tx.nonce, tx.value).map_err(|e| e.to_string())?;
+    if account(&witness.post_sender) != post_sender || account(&witness.post_to) != post_to {
+        return Err("The accounts after it do not follow from the ones before".to_string());
+    }
+    // The sender is updated first, then the recipient, whose path after
+    // both updates is its path after the first.
+    let between = root_with(&witness.pre_sender, &post_sender, &witness.pre_sender.path)?;
+    if root_with(&witness.pre_to, &pre_to, &witness.post_to.path)? != between
+        || root_with(&witness.post_to, &post_to, &witness.post_to.path)? != witness.post_root
+        || root_with(
+            &witness.post_sender,
+            &post_sender,
+            &witness.post_sender.path,
+        )? != witness.post_root
+    {
+        return Err("The accounts after it are not in the post-state".to_string());
+    }
+    Ok(())
+}
+
+fn path(dir: &Path, number: u64) -> PathBuf {
+    dir.join("witnesses").join(format!("{number}.json"))
+}
+
+/// Writes `witness` in `dir`, replacing the one of a block with the same
+/// number, which an L1 reorg dropped.
+pub fn write(dir: &Path, witness: &BatchWitness) -> Result<(), String> {
+    let path = path(dir, witness.number);
+    let json = serde_json::to_vec(witness).map_err(|e| e.to_string())?;
+    let tmp = path.with_extension("json.tmp");
+    std::fs::create_dir_all(path.parent().unwrap())
+        .and_then(|()| std::fs::write(&tmp, json))
+        .and_then(|()| std::fs::rename(&tmp, &path))
+        .map_err(|e| format!("Could not write {}: {e}", path.display()))
+}
+
+/// The witness of block `number` in `dir`, if it was proven.
+pub fn read(dir: &Path, number: u64) -> Result<Option<BatchWitness>, String> {
+    let path = path(dir, number);
+    match std::fs::read(&path) {
+        Ok(json) => serde_json::from_slice(&json)
+            .map(Some)
+            .map_err(|e| format!("Could not parse {}: {e}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("Could not read {}: {e}", path.display())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fusion_config::Config;
+
+    use crate::dev::dev_transfer;
+    use crate::sequencer::genesis_state;
+    use crate::transition::apply_tx;
+
+    fn block(txs: &[SignedTx]) -> BatchWitness {
+        let mut config = Config::default();
+        config.dev = true;
+        let mut states = vec![genesis_state(&config)];
+        for tx in txs {
+            let state = apply_tx(states.last().unwrap().clone(), &tx.tx).unwrap();
+            states.push(state);
+        }
+        batch_witness(7, txs, &states)
+    }
+
+    #[test]
+    fn verify_witness() {
+        let txs = [
+            dev_transfer(0, 1, 1, 10),
+            dev_transfer(1, 2, 1, 5),
+            dev_transfer(0, 2, 2, 1),
+        ];
+        let witness = block(&txs);
+        assert_eq!(witness.txs.len(), 3);
+        assert_eq!(witness.txs[0].pre_root, witness.pre_root);
+        verify(&witness).unwrap();
+
+        let tampered = |change: fn(&mut BatchWitness), error: &str| {
+            let mut witness = witness.clone();
+            change(&mut witness);
+            let e = verify(&witness).unwrap_err();
+            assert!(e.contains(error), "{e}");
+        };
+        tampered(|w| w.post_root += U256::one(), "post-state root");
+        tampered(|w| w.txs[1].post_to.balance += U256::one(), "do not follow");
+        tampered(|w| w.txs[1].pre_sender.balance += U256::one(), "pre-state");
+        tampered(|w| w.txs[2].pre_to.path[0] += U256::one(), "Transaction 3");
+        tampered(|w| w.txs.swap(1, 2), "Transaction 2");
+        tampered(|w| w.txs[0].tx.tx.value += U256::one(), "Transaction 1");
+        // A replay of the first transaction.
+        tampered(
+            |w| {
+                let mut replay = w.txs[0].clone();
+                replay.pre_root = w.txs[2].post_root;
+                w.txs.push(replay);
+            },
+            "Transaction 4",
+        );
+
+        let dir = std::env::temp_dir().join(format!("fusion-witness-{}", rand::random::<u64>()));
+        assert_eq!(read(&dir, 7).unwrap(), None);
+        write(&dir, &witness).unwrap();
+        assert_eq!(read(&dir, 7).unwrap(), Some(witness));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
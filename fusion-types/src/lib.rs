@@ -70,8 +70,8 @@ impl From<U256> for PublicKey {
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Point {
-    x: U256,
-    y: U256,
+    pub x: U256,
+    pub y: U256,
 }
 
 pub trait FromBabyJubjubPoint {
@@ -155,13 +155,19 @@ impl ToFr for PublicKey {
 }
 
 /// Converts a U256 into a field element.
-/// Panics if it does not fit.
+/// Panics if it does not fit, see `is_field_element`.
 impl ToFr for U256 {
     fn to_fr(&self) -> Fr {
         Fr::from_str(&self.to_string()).unwrap()
     }
 }
 
+/// Whether `value` is below the BN254 scalar field modulus, so that
+/// `to_fr` accepts it.
+pub fn is_field_element(value: &U256) -> bool {
+    Fr::from_str(&value.to_string()).is_some()
+}
+
 pub trait ToBigInt {
     fn to_big_int(&self) -> BigInt;
 }
@@ -175,22 +181,24 @@ impl ToBigInt for U256 {
 }
 
 pub trait ToBabyJubjubSignature {
-    fn to_babyjubjub_signature(&self) -> babyjubjub_rs::Signature;
+    fn to_babyjubjub_signature(&self) -> Result<babyjubjub_rs::Signature, String>;
 }
 
 /// Parses a String into a Baby Jubjub signature.
 impl ToBabyJubjubSignature for String {
-    fn to_babyjubjub_signature(&self) -> babyjubjub_rs::Signature {
-        U512::from_dec_str(self).unwrap().to_babyjubjub_signature()
+    fn to_babyjubjub_signature(&self) -> Result<babyjubjub_rs::Signature, String> {
+        U512::from_dec_str(self)
+            .map_err(|_| format!("Invalid signature `{self}`, expected a decimal number"))?
+            .to_babyjubjub_signature()
     }
 }
 
 /// Decompresses a Baby Jubjub signature from a U512.
 impl ToBabyJubjubSignature for U512 {
-    fn to_babyjubjub_signature(&self) -> babyjubjub_rs::Signature {
-        let mut bytes = vec![0; 64];
+    fn to_babyjubjub_signature(&self) -> Result<babyjubjub_rs::Signature, String> {
+        let mut bytes = [0; 64];
         self.to_little_endian(&mut bytes);
-        babyjubjub_rs::decompress_signature(bytes.as_slice().try_into().unwrap()).unwrap()
+        babyjubjub_rs::decompress_signature(&bytes).map_err(|e| format!("Invalid signature: {e}"))
     }
 }
 
@@ -258,11 +266,44 @@ mod test {
         assert_eq!(x, x.to_fr().to_u256());
     }
 
+    #[test]
+    fn field_elements() {
+        let modulus = U256::from_dec_str(
+            "21888242871839275222246405745257275088548364400416034343698204186575808495617",
+        )
+        .unwrap();
+        assert!(is_field_element(&U256::zero()));
+        assert!(is_field_element(&(modulus - 1)));
+        assert!(!is_field_element(&modulus));
+        assert!(!is_field_element(&U256::MAX));
+    }
+
     #[test]
     fn signature_compress_decompress() {
         let sig = "122241928682229286598976029249532022025637739860654613779160404391018488754905842538161678835085790288668324736777854019032918292839284526282556835964629";
-        let bjj_sig = sig.to_string().to_babyjubjub_signature();
-        let u512_sig = U512::from_dec_str(sig).unwrap().to_babyjubjub_signature();
+        let bjj_sig = sig.to_string().to_babyjubjub_signature().unwrap();
+        let u512_sig = U512::from_dec_str(sig)
+            .unwrap()
+            .to_babyjubjub_signature()
+            .unwrap();
         assert_eq!(bjj_sig.s, u512_sig.s);
     }
+
+    #[test]
+    fn malformed_signatures() {
+        let sig = "122241928682229286598976029249532022025637739860654613779160404391018488754905842538161678835085790288668324736777854019032918292839284526282556835964629";
+        // Truncated, the r point is no longer on the curve.
+        assert!(sig[..sig.len() - 1]
+            .to_string()
+            .to_babyjubjub_signature()
+            .unwrap_err()
+            .starts_with("Invalid signature:"));
+        assert!(String::new()
+            .to_babyjubjub_signature()
+            .unwrap_err()
+            .contains("expected a decimal number"));
+        assert!("0x12".to_string().to_babyjubjub_signature().is_err());
+        // A decimal number, but its r is not a point of the curve.
+        assert!("2".to_string().to_babyjubjub_signature().is_err());
+    }
 }
@@ -0,0 +1,147 @@
+//! Transfers read from a JSON file for `send-batch`, e.g.
+//! `[{"to": "alice", "value": "1.5eth"}, {"to": "123...", "value": 10, "nonce": 7}]`.
+
+use ethers_core::types::U256;
+use serde_json::Value;
+
+use crate::units::parse_value;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BatchEntry {
+    /// A public key or a contact name.
+    pub to: String,
+    pub value: U256,
+    /// The sender's next nonces are used when `None`.
+    pub nonce: Option<U256>,
+    /// Refused until transactions carry a fee.
+    pub fee: Option<U256>,
+}
+
+/// The number at `key`, given as a JSON number or string, read with
+/// `parse`.
+fn number(
+    entry: &Value,
+    key: &str,
+    parse: fn(&str) -> Result<U256, String>,
+) -> Result<Option<U256>, String> {
+    match entry.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::Number(n)) => parse(&n.to_string()).map(Some),
+        Some(Value::String(s)) => parse(s).map(Some),
+        Some(other) => Err(format!("Invalid {key} {other}")),
+    }
+}
+
+fn decimal(s: &str) -> Result<U256, String> {
+    U256::from_dec_str(s).map_err(|_| format!("Invalid number `{s}`"))
+}
+
+/// The entries of the JSON array `json`.
+pub fn parse_entries(json: &str) -> Result<Vec<BatchEntry>, String> {
+    let entries: Vec<Value> =
+        serde_json::from_str(json).map_err(|e| format!("Expected an array of transfers: {e}"))?;
+    entries
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            let at = |e: String| format!("Transfer {}: {e}", i + 1);
+            let to = match entry.get("to") {
+                Some(Value::String(to)) => to.clone(),
+                Some(Value::Number(to)) => to.to_string(),
+                _ => return Err(at("`to` is missing".to_string())),
+            };
+            Ok(BatchEntry {
+                to,
+                value: number(entry, "value", parse_value)
+                    .map_err(at)?
+                    .ok_or_else(|| at("`value` is missing".to_string()))?,
+                nonce: number(entry, "nonce", decimal).map_err(at)?,
+                fee: number(entry, "fee", parse_value).map_err(at)?,
+            })
+        })
+        .collect()
+}
+
+/// The nonce of each entry: its own, or the next one from `next` on
+/// that no other entry uses.
+pub fn assign_nonces(entries: &[BatchEntry], mut next: U256) -> Vec<U256> {
+    let taken: Vec<_> = entries.iter().filter_map(|entry| entry.nonce).collect();
+    entries
+        .iter()
+        .map(|entry| {
+            entry.nonce.unwrap_or_else(|| {
+                while taken.contains(&next) {
+                    next += U256::one();
+                }
+                next += U256::one();
+                next - 1
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn entries() {
+        let entries = parse_entries(
+            r#"[
+                {"to": "alice", "value": "1.5eth"},
+                {"to": 1234, "value": 10, "nonce": 7},
+                {"to": "bob", "value": "2500gwei", "nonce": "5", "fee": 1}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            entries[0],
+            BatchEntry {
+                to: "alice".to_string(),
+                value: U256::exp10(17) * 15,
+                nonce: None,
+                fee: None,
+            }
+        );
+        assert_eq!(entries[1].to, "1234");
+        assert_eq!(entries[1].nonce, Some(7.into()));
+        assert_eq!(entries[2].value, U256::exp10(9) * 2500);
+        assert_eq!(entries[2].fee, Some(1.into()));
+
+        for (json, error) in [
+            ("{}", "Expected an array"),
+            (r#"[{"value": 1}]"#, "Transfer 1: `to` is missing"),
+            (r#"[{"to": "a"}]"#, "Transfer 1: `value` is missing"),
+            (
+                r#"[{"to": "a", "value": 1}, {"to": "b", "value": 1, "nonce": -1}]"#,
+                "Transfer 2",
+            ),
+            (r#"[{"to": "a", "value": true}]"#, "Invalid value"),
+        ] {
+            let e = parse_entries(json).unwrap_err();
+            assert!(e.contains(error), "{json}: {e}");
+        }
+    }
+
+    #[test]
+    fn nonces() {
+        let entry = |nonce: Option<u64>| BatchEntry {
+            to: "alice".to_string(),
+            value: 1.into(),
+            nonce: nonce.map(U256::from),
+            fee: None,
+        };
+        let entries = [
+            entry(None),
+            entry(Some(5)),
+            entry(None),
+            entry(None),
+            entry(None),
+        ];
+        let nonces: Vec<_> = assign_nonces(&entries, 3.into())
+            .into_iter()
+            .map(|nonce| nonce.as_u64())
+            .collect();
+        assert_eq!(nonces, [3, 5, 4, 6, 7]);
+    }
+}
@@ -0,0 +1,123 @@
+//! Local address book, so recipients can be given by name.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use ethers_core::types::U256;
+
+pub struct AddressBook {
+    path: PathBuf,
+    contacts: BTreeMap<String, U256>,
+}
+
+impl AddressBook {
+    /// Loads the address book stored at `path`, empty if the file does
+    /// not exist yet.
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let contacts = match std::fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self { path, contacts })
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        std::fs::write(&self.path, serde_json::to_string_pretty(&self.contacts)?)?;
+        Ok(())
+    }
+
+    /// Adds `name` for the public key `address`. Names cannot be numbers,
+    /// so they are never mistaken for an address.
+    pub fn add(&mut self, name: &str, address: U256) -> Result<(), String> {
+        if name.is_empty() || name.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(format!("Invalid contact name `{name}`"));
+        }
+        if !crate::is_public_key(&address) {
+            return Err(format!("{address} is not a Fusion public key"));
+        }
+        if let Some(existing) = self.contacts.get(name) {
+            return Err(format!("Contact `{name}` already exists: {existing}"));
+        }
+        self.contacts.insert(name.to_string(), address);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<U256, String> {
+        self.contacts
+            .remove(name)
+            .ok_or_else(|| format!("Unknown contact `{name}`"))
+    }
+
+    pub fn list(&self) -> impl Iterator<Item = (&String, &U256)> {
+        self.contacts.iter()
+    }
+
+    /// Resolves a recipient given either as a public key or a contact name.
+    pub fn resolve(&self, recipient: &str) -> Result<U256, String> {
+        if let Ok(address) = U256::from_dec_str(recipient) {
+            return Ok(address);
+        }
+        self.contacts.get(recipient).copied().ok_or_else(|| {
+            format!("`{recipient}` is neither a public key nor a contact, refusing to use it")
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::new_key_pair;
+    use fusion_types::ToU256;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "fusion-contacts-{}-{name}.json",
+            std::process::id()
+        ))
+    }
+
+    #[test]
+    fn resolution() {
+        let (_sk, alice) = new_key_pair();
+        let mut book = AddressBook::load(temp_path("resolution")).unwrap();
+        book.add("alice", alice.to_u256()).unwrap();
+
+        assert_eq!(book.resolve("alice"), Ok(alice.to_u256()));
+        // Numbers are always addresses.
+        assert_eq!(book.resolve("42"), Ok(42.into()));
+        assert!(book.add("42", alice.to_u256()).is_err());
+        // Names are case sensitive.
+        assert!(book.resolve("Alice").is_err());
+        assert!(book
+            .resolve("bob")
+            .unwrap_err()
+            .contains("refusing to use it"));
+    }
+
+    #[test]
+    fn add_and_remove() {
+        let (_sk, alice) = new_key_pair();
+        let (_sk, bob) = new_key_pair();
+        let path = temp_path("add_and_remove");
+        let mut book = AddressBook::load(&path).unwrap();
+        book.add("alice", alice.to_u256()).unwrap();
+        assert!(book.add("alice", bob.to_u256()).is_err());
+        book.add("bob", bob.to_u256()).unwrap();
+        book.save().unwrap();
+
+        let mut book = AddressBook::load(&path).unwrap();
+        assert_eq!(
+            book.list()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["alice", "bob"]
+        );
+        assert_eq!(book.remove("alice"), Ok(alice.to_u256()));
+        assert!(book.remove("alice").is_err());
+        assert!(book.resolve("alice").is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,105 @@
+//! Private keys read from an encrypted JSON keystore or derived from a
+//! BIP-39 mnemonic, so that they stay out of shell history and
+//! environment dumps.
+
+use std::path::Path;
+
+use ethers_signers::{coins_bip39::English, MnemonicBuilder};
+
+/// Environment variable holding the keystore passphrase. The passphrase
+/// is asked on the terminal when it is not set.
+pub const KEYSTORE_PASSWORD_ENV: &str = "ETH_KEYSTORE_PASSWORD";
+
+/// The first account of the standard Ethereum path.
+pub const DEFAULT_DERIVATION_PATH: &str = "m/44'/60'/0'/0/0";
+
+/// The passphrase of the keystore at `path`.
+pub fn keystore_password(path: &str) -> Result<String, String> {
+    if let Ok(password) = std::env::var(KEYSTORE_PASSWORD_ENV) {
+        return Ok(password);
+    }
+    dialoguer::Password::new()
+        .with_prompt(format!("Passphrase of {path}"))
+        .interact()
+        .map_err(|e| format!("Could not read the passphrase of {path}: {e}"))
+}
+
+/// The key kept in the keystore at `path`.
+pub fn decrypt_keystore(path: &str, password: &str) -> Result<[u8; 32], String> {
+    let key = eth_keystore::decrypt_key(Path::new(path), password).map_err(|e| match e {
+        eth_keystore::KeystoreError::MacMismatch => format!("Wrong passphrase for {path}"),
+        e => format!("Could not decrypt {path}: {e}"),
+    })?;
+    key.try_into()
+        .map_err(|key: Vec<u8>| format!("{path} holds a {}-byte key, expected 32", key.len()))
+}
+
+/// The key derived along `derivation_path` from the mnemonic in the file
+/// at `path`. The same as the one MetaMask or anvil derive.
+pub fn derive_from_mnemonic(path: &str, derivation_path: &str) -> Result<[u8; 32], String> {
+    let phrase = std::fs::read_to_string(path)
+        .map_err(|e| format!("Could not read the mnemonic in {path}: {e}"))?;
+    let wallet = MnemonicBuilder::<English>::default()
+        .phrase(phrase.trim())
+        .derivation_path(derivation_path)
+        .and_then(|builder| builder.build())
+        .map_err(|e| format!("Could not derive {derivation_path} from {path}: {e}"))?;
+    let mut key = [0; 32];
+    key.copy_from_slice(&wallet.signer().to_bytes());
+    Ok(key)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MNEMONIC: &str = "test test test test test test test test test test test junk";
+
+    fn temp_dir() -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("fusion-keys-{}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn keystore() {
+        let dir = temp_dir();
+        let key = [7u8; 32];
+        eth_keystore::encrypt_key(&dir, &mut rand::thread_rng(), key, "secret", Some("key"))
+            .unwrap();
+        let path = dir.join("key");
+        let path = path.to_str().unwrap();
+
+        assert_eq!(decrypt_keystore(path, "secret"), Ok(key));
+        assert_eq!(
+            decrypt_keystore(path, "guess"),
+            Err(format!("Wrong passphrase for {path}"))
+        );
+        assert!(
+            decrypt_keystore(dir.join("missing").to_str().unwrap(), "secret")
+                .unwrap_err()
+                .starts_with("Could not decrypt")
+        );
+    }
+
+    #[test]
+    fn mnemonic() {
+        let dir = temp_dir();
+        let path = dir.join("mnemonic");
+        std::fs::write(&path, format!("{MNEMONIC}\n")).unwrap();
+        let path = path.to_str().unwrap();
+
+        // The first anvil account.
+        assert_eq!(
+            hex::encode(derive_from_mnemonic(path, DEFAULT_DERIVATION_PATH).unwrap()),
+            "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80"
+        );
+        assert_eq!(
+            hex::encode(derive_from_mnemonic(path, "m/44'/60'/0'/0/1").unwrap()),
+            "59c6995e998f97a5a0044966f0945389dc9e86dae88c7a8412f4603b6b78690d"
+        );
+        assert!(derive_from_mnemonic(path, "m/not/a/path").is_err());
+        std::fs::write(path, "not a mnemonic").unwrap();
+        assert!(derive_from_mnemonic(path, DEFAULT_DERIVATION_PATH).is_err());
+    }
+}
@@ -1,31 +1,238 @@
-use ethers_core::types::U512;
+use ethers_core::types::{U256, U512};
 
-use fusion_api::{hash_tx, SignedTx, Tx};
-use fusion_types::{
-    FromBabyJubjubPoint, PrivateKey, PublicKey, ToBabyJubjubPoint, ToBabyJubjubSignature, ToBigInt,
+pub mod batch;
+pub mod contacts;
+pub mod keys;
+pub mod units;
+
+use fusion_api::{
+    drop_message, hash_tx, Attestation, Health, HealthStatus, Receipt, SignedTx, Tx, TxStatus,
+    NATIVE_TOKEN,
 };
+use fusion_types::{FromBabyJubjubPoint, PrivateKey, PublicKey, ToBabyJubjubPoint, ToBigInt};
 
-pub fn sign(tx: &Tx, private_key: String) -> anyhow::Result<U512> {
+fn sign_message(msg: U256, private_key: String) -> anyhow::Result<U512> {
     let wallet: PrivateKey = private_key.into();
-    let msg = hash_tx(tx).to_big_int();
 
-    match wallet.0.sign(msg) {
+    match wallet.0.sign(msg.to_big_int()) {
         Ok(sig) => Ok(U512::from_little_endian(sig.compress().as_slice())),
         Err(e) => Err(anyhow::anyhow!(e)),
     }
 }
 
-pub fn verify_tx_signature(tx: &SignedTx) -> anyhow::Result<()> {
-    let pk = tx.tx.sender.to_babyjubjub_point();
-    let sig = tx.signature.to_babyjubjub_signature();
-    let msg = hash_tx(&tx.tx).to_big_int();
+fn verify_signature(msg: U256, signature: &str, public_key: &U256) -> anyhow::Result<()> {
+    if !is_public_key(public_key) {
+        return Err(anyhow::anyhow!("{public_key} is not a Fusion public key"));
+    }
+    let pk = public_key.to_babyjubjub_point();
+    let mut bytes = [0; 64];
+    U512::from_dec_str(signature)
+        .map_err(|_| anyhow::anyhow!("Invalid signature `{signature}`, expected a decimal number"))?
+        .to_little_endian(&mut bytes);
+    let sig = babyjubjub_rs::decompress_signature(&bytes)
+        .map_err(|e| anyhow::anyhow!("Invalid signature: {e}"))?;
 
-    match babyjubjub_rs::verify(pk, sig, msg) {
+    match babyjubjub_rs::verify(pk, sig, msg.to_big_int()) {
         true => Ok(()),
         false => Err(anyhow::anyhow!("Invalid signature.")),
     }
 }
 
+pub fn sign(tx: &Tx, private_key: String) -> anyhow::Result<U512> {
+    sign_message(hash_tx(tx), private_key)
+}
+
+pub fn verify_tx_signature(tx: &SignedTx) -> anyhow::Result<()> {
+    verify_signature(hash_tx(&tx.tx), &tx.signature, &tx.tx.sender)
+}
+
+/// `tx` signed with `private_key`, as `submit_transaction` takes it.
+pub fn sign_tx(tx: Tx, private_key: String) -> anyhow::Result<SignedTx> {
+    let signature = sign(&tx, private_key)?.to_string();
+    Ok(SignedTx { tx, signature })
+}
+
+/// The contents of `path`, or stdin when it is `-`.
+fn read_input(path: &str) -> anyhow::Result<String> {
+    match path {
+        "-" => Ok(std::io::read_to_string(std::io::stdin())?),
+        _ => {
+            std::fs::read_to_string(path).map_err(|e| anyhow::anyhow!("Could not read {path}: {e}"))
+        }
+    }
+}
+
+/// The `Tx` JSON of `path`, or of stdin when it is `-`, in the format
+/// of the RPC.
+pub fn read_tx(path: &str) -> anyhow::Result<Tx> {
+    serde_json::from_str(&read_input(path)?)
+        .map_err(|e| anyhow::anyhow!("Invalid transaction JSON in {path}: {e}"))
+}
+
+/// The `SignedTx` JSON of `path`, or of stdin when it is `-`.
+pub fn read_signed_tx(path: &str) -> anyhow::Result<SignedTx> {
+    serde_json::from_str(&read_input(path)?)
+        .map_err(|e| anyhow::anyhow!("Invalid transaction JSON in {path}: {e}"))
+}
+
+/// Checks that `tx` is signed for the chain `chain_id`, or without a
+/// chain id if `accept_legacy`.
+pub fn check_chain_id(tx: &Tx, chain_id: u64, accept_legacy: bool) -> Result<(), String> {
+    match tx.chain_id {
+        Some(id) if id == chain_id => Ok(()),
+        Some(id) => Err(format!("Signed for chain {id}, this is chain {chain_id}")),
+        None if accept_legacy => Ok(()),
+        None => Err(format!(
+            "Signed without a chain id, which is no longer accepted, sign for chain {chain_id}"
+        )),
+    }
+}
+
+/// Signs the request to drop the pending transaction with hash `tx_hash`.
+pub fn sign_drop(tx_hash: U256, private_key: String) -> anyhow::Result<U512> {
+    sign_message(drop_message(tx_hash), private_key)
+}
+
+/// Checks that `sender` signed the request to drop `tx_hash`.
+pub fn verify_drop(tx_hash: U256, signature: &str, sender: &U256) -> anyhow::Result<()> {
+    verify_signature(drop_message(tx_hash), signature, sender)
+}
+
+/// The signature a proof carries as the `r` point and `s` scalar of
+/// its public inputs, in the encoding of `SignedTx::signature`.
+pub fn signature_from_parts(r_x: U256, r_y: U256, s: U256) -> String {
+    let signature = babyjubjub_rs::Signature {
+        r_b8: PublicKey::from_point(r_x, r_y).to_babyjubjub_point(),
+        s: s.to_big_int(),
+    };
+    U512::from_little_endian(signature.compress().as_slice()).to_string()
+}
+
+pub fn sign_attestation(attestation: &Attestation, private_key: String) -> anyhow::Result<U512> {
+    sign_message(attestation.message(), private_key)
+}
+
+/// Checks an attestation against the sequencer's `attestation_key`.
+pub fn verify_attestation(attestation: &Attestation, attestation_key: &U256) -> anyhow::Result<()> {
+    verify_signature(
+        attestation.message(),
+        &attestation.signature,
+        attestation_key,
+    )
+}
+
+pub fn sign_receipt(receipt: &Receipt, private_key: String) -> anyhow::Result<U512> {
+    sign_message(receipt.message(), private_key)
+}
+
+/// Checks a receipt against the sequencer's `attestation_key`.
+pub fn verify_receipt(receipt: &Receipt, attestation_key: &U256) -> anyhow::Result<()> {
+    verify_signature(receipt.message(), &receipt.signature, attestation_key)
+}
+
+/// The `Receipt` JSON of `path`, or of stdin when it is `-`.
+pub fn read_receipt(path: &str) -> anyhow::Result<Receipt> {
+    serde_json::from_str(&read_input(path)?)
+        .map_err(|e| anyhow::anyhow!("Invalid receipt JSON in {path}: {e}"))
+}
+
+/// Whether the sequencer kept the promise of `receipt`, given the status
+/// of its transaction, `None` if unknown, and the last block built.
+/// `Ok(false)` while the promised block is not built yet.
+pub fn receipt_kept(
+    receipt: &Receipt,
+    status: Option<&TxStatus>,
+    block_number: u64,
+) -> Result<bool, String> {
+    let promised = receipt.promised_block;
+    match status {
+        Some(TxStatus::Included { block, .. }) if *block <= promised => Ok(true),
+        Some(TxStatus::Included { block, .. }) => {
+            Err(format!("Included in block {block}, after block {promised}"))
+        }
+        Some(TxStatus::Rejected { reason }) => Err(format!("Rejected: {reason}")),
+        _ if block_number < promised => Ok(false),
+        Some(_) => Err(format!("Still pending after block {promised}")),
+        None => Err(format!("Unknown to the sequencer after block {promised}")),
+    }
+}
+
+/// Parses a timestamp given either as unix seconds or as an RFC3339 date.
+pub fn parse_timestamp(s: &str) -> Result<u64, String> {
+    if let Ok(secs) = s.parse::<u64>() {
+        return Ok(secs);
+    }
+    let date = chrono::DateTime::parse_from_rfc3339(s)
+        .map_err(|e| format!("Invalid timestamp `{s}`, expected unix seconds or RFC3339: {e}"))?;
+    u64::try_from(date.timestamp()).map_err(|_| format!("Timestamp `{s}` is before the unix epoch"))
+}
+
+/// Result of pinging a sequencer.
+#[derive(Debug)]
+pub enum PingOutcome {
+    Reachable(Health),
+    Unreachable(String),
+}
+
+impl PingOutcome {
+    /// 0 if healthy, 1 if degraded, 2 if halted or unreachable.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            PingOutcome::Reachable(health) => match health.status {
+                HealthStatus::Ok => 0,
+                HealthStatus::Degraded => 1,
+                HealthStatus::Halted => 2,
+            },
+            PingOutcome::Unreachable(_) => 2,
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        match self {
+            PingOutcome::Reachable(health) => {
+                let status = match health.status {
+                    HealthStatus::Ok => "ok",
+                    HealthStatus::Degraded => "degraded",
+                    HealthStatus::Halted => "halted",
+                };
+                let mut summary = format!(
+                    "{status}: block {}, {} pending transaction(s)",
+                    health.block_number, health.mempool_size
+                );
+                if let Some(reason) = &health.reason {
+                    summary.push_str(&format!(" ({reason})"));
+                }
+                summary
+            }
+            PingOutcome::Unreachable(e) => format!("unreachable: {e}"),
+        }
+    }
+}
+
+/// One line describing where a transaction is, for the `tx` subcommand.
+pub fn describe_status(status: &TxStatus) -> String {
+    match status {
+        TxStatus::Pending => "pending in the mempool".to_string(),
+        TxStatus::Included {
+            block,
+            l1_tx_hashes,
+        } => match l1_tx_hashes.last() {
+            Some(l1_tx_hash) => {
+                format!("included in batch {block}, proven on L1 in {l1_tx_hash:?}")
+            }
+            None => format!("included in batch {block}, not on L1 yet"),
+        },
+        TxStatus::Rejected { reason } => format!("rejected: {reason}"),
+    }
+}
+
+/// Whether `key` is a compressed Baby Jubjub point.
+pub fn is_public_key(key: &U256) -> bool {
+    let mut bytes = [0u8; 32];
+    key.to_big_endian(&mut bytes);
+    babyjubjub_rs::decompress_point(bytes).is_ok()
+}
+
 pub fn new_private_key() -> PrivateKey {
     PrivateKey(babyjubjub_rs::new_key())
 }
@@ -44,7 +251,7 @@ pub fn new_key_pair() -> (PrivateKey, PublicKey) {
 mod test {
     use super::*;
     use fusion_api::TxKind;
-    use fusion_types::ToU256;
+    use fusion_types::{ToBabyJubjubSignature, ToU256};
 
     #[test]
     fn signatures() {
@@ -56,6 +263,10 @@ mod test {
             to: pk_2.to_u256(),
             nonce: 1.into(),
             value: 0.into(),
+            not_before: None,
+            not_after: None,
+            chain_id: None,
+            token: NATIVE_TOKEN,
         };
         let sig = sign(&tx, sk_1.to_string());
         let mut signed_tx = SignedTx {
@@ -65,5 +276,286 @@ mod test {
         assert!(verify_tx_signature(&signed_tx).is_ok());
         signed_tx.tx.nonce = 3.into();
         assert!(verify_tx_signature(&signed_tx).is_err());
+
+        // Malformed signatures and keys are errors, not panics.
+        signed_tx.signature = "12".to_string();
+        assert!(verify_tx_signature(&signed_tx).is_err());
+        signed_tx.signature = "0x12".to_string();
+        assert!(verify_tx_signature(&signed_tx).is_err());
+        signed_tx.tx.sender = U256::MAX;
+        assert!(verify_tx_signature(&signed_tx).is_err());
+    }
+
+    #[test]
+    fn json_signatures() {
+        let (sk, pk) = new_key_pair();
+        let json = format!(
+            r#"{{"kind": "Transfer", "sender": "{:#x}", "to": "{:#x}", "nonce": "0x2", "value": "0x64", "chain_id": 7}}"#,
+            pk.to_u256(),
+            new_key_pair().1.to_u256()
+        );
+        let path = std::env::temp_dir().join(format!("fusion-tx-{}.json", rand::random::<u64>()));
+        std::fs::write(&path, json).unwrap();
+        let tx = read_tx(path.to_str().unwrap()).unwrap();
+        assert_eq!(
+            (tx.nonce, tx.chain_id, tx.token),
+            (2.into(), Some(7), NATIVE_TOKEN)
+        );
+
+        // The signature of the flags, over the same hash.
+        let signed = sign_tx(tx.clone(), sk.to_string()).unwrap();
+        assert_eq!(
+            signed.signature,
+            sign(&tx, sk.to_string()).unwrap().to_string()
+        );
+        std::fs::write(&path, serde_json::to_string(&signed).unwrap()).unwrap();
+        let mut read = read_signed_tx(path.to_str().unwrap()).unwrap();
+        assert!(verify_tx_signature(&read).is_ok());
+        read.tx.value += U256::one();
+        assert!(verify_tx_signature(&read).is_err());
+
+        std::fs::write(&path, "{}").unwrap();
+        let e = read_signed_tx(path.to_str().unwrap()).unwrap_err();
+        assert!(e.to_string().starts_with("Invalid transaction JSON"), "{e}");
+        std::fs::remove_file(&path).unwrap();
+        assert!(read_tx(path.to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn drop_signatures() {
+        let (sk_1, pk_1) = new_key_pair();
+        let (_sk_2, pk_2) = new_key_pair();
+        let tx = Tx {
+            kind: TxKind::Transfer,
+            sender: pk_1.to_u256(),
+            to: pk_2.to_u256(),
+            nonce: 1.into(),
+            value: 0.into(),
+            not_before: None,
+            not_after: None,
+            chain_id: None,
+            token: NATIVE_TOKEN,
+        };
+        let tx_hash = hash_tx(&tx);
+        let signature = sign_drop(tx_hash, sk_1.to_string()).unwrap().to_string();
+        assert!(verify_drop(tx_hash, &signature, &pk_1.to_u256()).is_ok());
+        assert!(verify_drop(tx_hash, &signature, &pk_2.to_u256()).is_err());
+        // The signature of the transaction does not drop it.
+        let tx_signature = sign(&tx, sk_1.to_string()).unwrap().to_string();
+        assert!(verify_drop(tx_hash, &tx_signature, &pk_1.to_u256()).is_err());
+    }
+
+    #[test]
+    fn signature_parts() {
+        let (sk, pk) = new_key_pair();
+        let tx = Tx {
+            kind: TxKind::Deposit,
+            sender: pk.to_u256(),
+            to: 0.into(),
+            nonce: 1.into(),
+            value: 10.into(),
+            not_before: None,
+            not_after: None,
+            chain_id: None,
+            token: NATIVE_TOKEN,
+        };
+        let signature = sign(&tx, sk.to_string()).unwrap().to_string();
+        let parts = signature.to_babyjubjub_signature().unwrap();
+        let r = PublicKey::from_babyjubjub_point(&parts.r_b8).0;
+        assert_eq!(signature_from_parts(r.x, r.y, parts.s.to_u256()), signature);
+    }
+
+    #[test]
+    fn signature_commits_to_validity_window() {
+        let (sk_1, pk_1) = new_key_pair();
+        let (_sk_2, pk_2) = new_key_pair();
+        let tx = Tx {
+            kind: TxKind::Transfer,
+            sender: pk_1.to_u256(),
+            to: pk_2.to_u256(),
+            nonce: 1.into(),
+            value: 0.into(),
+            not_before: Some(1_700_000_000),
+            not_after: None,
+            chain_id: None,
+            token: NATIVE_TOKEN,
+        };
+        let sig = sign(&tx, sk_1.to_string());
+        let mut signed_tx = SignedTx {
+            tx,
+            signature: sig.unwrap().to_string(),
+        };
+        assert!(verify_tx_signature(&signed_tx).is_ok());
+        signed_tx.tx.not_before = None;
+        assert!(verify_tx_signature(&signed_tx).is_err());
+    }
+
+    #[test]
+    fn chain_ids() {
+        let (sk_1, pk_1) = new_key_pair();
+        let (_sk_2, pk_2) = new_key_pair();
+        let tx = Tx {
+            kind: TxKind::Transfer,
+            sender: pk_1.to_u256(),
+            to: pk_2.to_u256(),
+            nonce: 1.into(),
+            value: 0.into(),
+            not_before: None,
+            not_after: None,
+            chain_id: Some(7),
+            token: NATIVE_TOKEN,
+        };
+        let signature = sign(&tx, sk_1.to_string()).unwrap().to_string();
+        let mut signed_tx = SignedTx { tx, signature };
+        assert!(verify_tx_signature(&signed_tx).is_ok());
+        assert_eq!(check_chain_id(&signed_tx.tx, 7, false), Ok(()));
+        assert!(check_chain_id(&signed_tx.tx, 8, true).is_err());
+        // Replayed on another deployment.
+        signed_tx.tx.chain_id = Some(8);
+        assert!(verify_tx_signature(&signed_tx).is_err());
+
+        signed_tx.tx.chain_id = None;
+        assert_eq!(check_chain_id(&signed_tx.tx, 7, true), Ok(()));
+        assert!(check_chain_id(&signed_tx.tx, 7, false).is_err());
+    }
+
+    #[test]
+    fn attestations() {
+        let (sk, pk) = new_key_pair();
+        let mut attestation = Attestation {
+            block_number: 3,
+            block_hash: 1234.into(),
+            state_root: 5678.into(),
+            l1_tx_hash: ethers_core::types::H256::repeat_byte(0xab),
+            signature: String::new(),
+        };
+        attestation.signature = sign_attestation(&attestation, sk.to_string())
+            .unwrap()
+            .to_string();
+        assert!(verify_attestation(&attestation, &pk.to_u256()).is_ok());
+
+        let (_sk, other_pk) = new_key_pair();
+        assert!(verify_attestation(&attestation, &other_pk.to_u256()).is_err());
+
+        let mut tampered = attestation.clone();
+        tampered.state_root = 5679.into();
+        assert!(verify_attestation(&tampered, &pk.to_u256()).is_err());
+
+        let mut tampered = attestation;
+        tampered.l1_tx_hash = ethers_core::types::H256::repeat_byte(0xac);
+        assert!(verify_attestation(&tampered, &pk.to_u256()).is_err());
+    }
+
+    #[test]
+    fn receipts() {
+        let (sk, pk) = new_key_pair();
+        let mut receipt = Receipt {
+            tx_hash: 1234.into(),
+            accepted_at: 1_700_000_000,
+            promised_block: 12,
+            signature: String::new(),
+        };
+        receipt.signature = sign_receipt(&receipt, sk.to_string()).unwrap().to_string();
+        assert!(verify_receipt(&receipt, &pk.to_u256()).is_ok());
+        assert!(verify_receipt(&receipt, &new_key_pair().1.to_u256()).is_err());
+
+        let mut tampered = receipt.clone();
+        tampered.promised_block = 13;
+        assert!(verify_receipt(&tampered, &pk.to_u256()).is_err());
+
+        let path =
+            std::env::temp_dir().join(format!("fusion-receipt-{}.json", rand::random::<u64>()));
+        std::fs::write(&path, serde_json::to_string(&receipt).unwrap()).unwrap();
+        assert_eq!(read_receipt(path.to_str().unwrap()).unwrap(), receipt);
+        std::fs::remove_file(&path).unwrap();
+
+        let included = |block| TxStatus::Included {
+            block,
+            l1_tx_hashes: vec![],
+        };
+        assert_eq!(receipt_kept(&receipt, Some(&included(12)), 12), Ok(true));
+        assert!(receipt_kept(&receipt, Some(&included(13)), 13).is_err());
+        assert_eq!(
+            receipt_kept(&receipt, Some(&TxStatus::Pending), 11),
+            Ok(false)
+        );
+        assert!(receipt_kept(&receipt, Some(&TxStatus::Pending), 12).is_err());
+        assert_eq!(receipt_kept(&receipt, None, 11), Ok(false));
+        assert!(receipt_kept(&receipt, None, 12).is_err());
+        let rejected = TxStatus::Rejected {
+            reason: "Nonce too low".to_string(),
+        };
+        assert!(receipt_kept(&receipt, Some(&rejected), 5).is_err());
+    }
+
+    #[test]
+    fn ping_exit_codes() {
+        let reachable = |status| {
+            PingOutcome::Reachable(Health {
+                status,
+                ..Default::default()
+            })
+        };
+        assert_eq!(reachable(HealthStatus::Ok).exit_code(), 0);
+        assert_eq!(reachable(HealthStatus::Degraded).exit_code(), 1);
+        assert_eq!(reachable(HealthStatus::Halted).exit_code(), 2);
+        assert_eq!(
+            PingOutcome::Unreachable("timed out".to_string()).exit_code(),
+            2
+        );
+    }
+
+    #[test]
+    fn ping_summaries() {
+        let halted = PingOutcome::Reachable(Health {
+            status: HealthStatus::Halted,
+            reason: Some("Sequencer is not running".to_string()),
+            block_number: 3,
+            mempool_size: 1,
+            ..Default::default()
+        });
+        assert_eq!(
+            halted.summary(),
+            "halted: block 3, 1 pending transaction(s) (Sequencer is not running)"
+        );
+        let unreachable = PingOutcome::Unreachable("connection refused".to_string());
+        assert_eq!(unreachable.summary(), "unreachable: connection refused");
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(parse_timestamp("0"), Ok(0));
+        assert_eq!(parse_timestamp("1700000000"), Ok(1700000000));
+        assert_eq!(parse_timestamp("2023-11-14T22:13:20Z"), Ok(1700000000));
+        assert_eq!(parse_timestamp("2023-11-14T23:13:20+01:00"), Ok(1700000000));
+        assert!(parse_timestamp("1969-12-31T23:59:59Z").is_err());
+        assert!(parse_timestamp("tomorrow").is_err());
+        assert!(parse_timestamp("-1").is_err());
+    }
+
+    #[test]
+    fn statuses() {
+        use ethers_core::types::H256;
+
+        assert_eq!(
+            describe_status(&TxStatus::Pending),
+            "pending in the mempool"
+        );
+        let included = |l1_tx_hashes| TxStatus::Included {
+            block: 4,
+            l1_tx_hashes,
+        };
+        assert_eq!(
+            describe_status(&included(vec![])),
+            "included in batch 4, not on L1 yet"
+        );
+        assert_eq!(
+            describe_status(&included(vec![H256::zero(), H256::repeat_byte(1)])),
+            format!("included in batch 4, proven on L1 in 0x{}", "01".repeat(32))
+        );
+        let rejected = TxStatus::Rejected {
+            reason: "Nonce too low".to_string(),
+        };
+        assert_eq!(describe_status(&rejected), "rejected: Nonce too low");
     }
 }
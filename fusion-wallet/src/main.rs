@@ -1,19 +1,41 @@
 use tarpc::{client, context, tokio_serde::formats::Json};
 
 use clap::{Parser, Subcommand};
+use ethers_core::types::{Address, U256};
 use num_bigint::BigInt;
-use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::{sync::Semaphore, task::JoinSet};
 
 use fusion_api::*;
 use fusion_config::Config;
 use fusion_types::ToU256;
+use fusion_wallet::batch;
+use fusion_wallet::contacts::AddressBook;
+use fusion_wallet::keys;
+use fusion_wallet::units::{display_value, parse_value};
+use fusion_wallet::PingOutcome;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let config = Config::from_file("../fusion.toml".to_string());
-
     let opts = Opts::parse();
-    match opts.sub {
+    let mut config = Config::resolve(&opts.config, opts.profile.as_deref(), vec![])
+        .map_err(|e| anyhow::anyhow!(e))?
+        .config;
+    if let Some(url) = &opts.sequencer_url {
+        let addr: SocketAddr = url
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid --sequencer-url `{url}`: {e}"))?;
+        config.socket_address = addr.ip().to_string();
+        config.socket_port = addr.port();
+    }
+    if let Some(profile) = &config.profile {
+        eprintln!("[profile: {profile}]");
+    }
+    let mut contacts = AddressBook::load(&config.contacts_path)?;
+    opts.sub.check_tx_json()?;
+    match opts.sub.resolve_recipients(&contacts)? {
         Subcommands::New => {
             let k = fusion_wallet::new_private_key();
             println!("{}", k.to_string());
@@ -25,57 +47,686 @@ async fn main() -> anyhow::Result<()> {
             Ok(())
         }
         Subcommands::Sign(cli_tx) => {
-            let signature =
-                fusion_wallet::sign(&cli_tx.clone().into(), cli_tx.private_key.unwrap()).unwrap();
-            println!("{signature}");
+            let private_key = cli_tx.signing_key()?.ok_or_else(|| {
+                anyhow::anyhow!("Signing needs --private-key, --keystore or --mnemonic-file")
+            })?;
+            match &cli_tx.tx_json {
+                Some(path) => {
+                    let tx = fusion_wallet::read_tx(path)?;
+                    let signed = fusion_wallet::sign_tx(tx, private_key)?;
+                    println!("{}", serde_json::to_string_pretty(&signed)?);
+                }
+                None => println!("{}", fusion_wallet::sign(&cli_tx.into(), private_key)?),
+            }
             Ok(())
         }
         Subcommands::Send {
-            send_sub: SendSubcommands::Transfer(cli_tx),
+            binary,
+            auto_nonce,
+            dry_run,
+            receipt,
+            send_sub: SendSubcommands::Transfer(mut cli_tx),
         } => {
-            let tx: TransferTx = cli_tx.clone().into();
-            let signed_tx = SignedTx {
-                tx: tx.clone().0,
-                signature: match cli_tx.signature {
-                    Some(sig) => sig,
-                    None => fusion_wallet::sign(&tx.0, cli_tx.private_key.unwrap())
-                        .unwrap()
-                        .to_string(),
-                },
+            if auto_nonce {
+                cli_tx.nonce = next_nonce(&cli_tx, &config).await?;
+            }
+            let options = SendOptions {
+                binary,
+                dry_run,
+                receipt,
             };
-            send(signed_tx, &config).await
+            send_or_estimate(cli_tx, TxKind::Transfer, options, &config).await
         }
         Subcommands::Send {
-            send_sub: SendSubcommands::Deposit(_cli_tx),
-        } => Ok(()),
+            binary,
+            auto_nonce,
+            dry_run,
+            receipt,
+            send_sub: SendSubcommands::Deposit(mut cli_tx),
+        } => {
+            if auto_nonce {
+                cli_tx.nonce = next_nonce(&cli_tx, &config).await?;
+            }
+            let options = SendOptions {
+                binary,
+                dry_run,
+                receipt,
+            };
+            send_or_estimate(cli_tx, TxKind::Deposit, options, &config).await
+        }
         Subcommands::Send {
-            send_sub: SendSubcommands::Withdraw(_cli_tx),
-        } => Ok(()),
-        Subcommands::Verify(args) => {
-            fusion_wallet::verify_tx_signature(&args.into()).unwrap();
+            binary,
+            auto_nonce,
+            dry_run,
+            receipt,
+            send_sub: SendSubcommands::Withdraw(mut cli_tx),
+        } => {
+            cli_tx.to = l1_recipient(&cli_tx.to)?.to_string();
+            if auto_nonce {
+                cli_tx.nonce = next_nonce(&cli_tx, &config).await?;
+            }
+            let options = SendOptions {
+                binary,
+                dry_run,
+                receipt,
+            };
+            send_or_estimate(cli_tx, TxKind::Withdraw, options, &config).await
+        }
+        Subcommands::Balance(args) => {
+            let client = connect(&config).await?;
+            let balance = client
+                .get_balance(request_context(&config), args.address.to_u256(), args.token)
+                .await?
+                .map_err(|e| anyhow::anyhow!(e))?;
+            match args.json {
+                true => println!("{}", serde_json::to_string_pretty(&balance)?),
+                false => {
+                    println!("committed\t{}", display_value(balance.committed));
+                    println!("pending\t{}", display_value(balance.pending));
+                }
+            }
+            Ok(())
+        }
+        Subcommands::Nonce(args) => {
+            let client = connect(&config).await?;
+            let nonces = client
+                .get_nonce(request_context(&config), args.address.to_u256(), args.token)
+                .await?
+                .map_err(|e| anyhow::anyhow!(e))?;
+            match args.json {
+                true => println!("{}", serde_json::to_string_pretty(&nonces)?),
+                false => {
+                    println!("committed\t{}", nonces.committed);
+                    println!("pending\t{}", nonces.pending);
+                    println!("next\t{}", nonces.next);
+                }
+            }
+            Ok(())
+        }
+        Subcommands::Tx(args) => {
+            let client = connect(&config).await?;
+            let info = client
+                .get_transaction(request_context(&config), args.hash.to_u256())
+                .await?
+                .map_err(|e| anyhow::anyhow!(e))?;
+            if args.json {
+                println!("{}", serde_json::to_string_pretty(&info)?);
+                return Ok(());
+            }
+            println!("hash\t{}", info.hash);
+            println!("status\t{}", fusion_wallet::describe_status(&info.status));
+            if info.status == TxStatus::Pending {
+                // It may have left the mempool in the meantime.
+                if let Ok(queued) = client
+                    .get_queue_position(request_context(&config), info.hash)
+                    .await?
+                {
+                    println!("position\t{}", queued.position);
+                    println!("blocks_ahead\t{}", queued.blocks_ahead);
+                }
+            }
+            if let Some(SignedTx { tx, .. }) = &info.tx {
+                println!("kind\t{:?}", tx.kind);
+                println!("from\t{}", tx.sender);
+                println!("to\t{}", tx.to);
+                println!("nonce\t{}", tx.nonce);
+                println!("value\t{}", display_value(tx.value));
+            }
+            Ok(())
+        }
+        Subcommands::Batch(args) => {
+            let client = connect(&config).await?;
+            let batch = match args.number {
+                Some(number) => client.get_batch(request_context(&config), number).await?,
+                None => client.get_latest_batch(request_context(&config)).await?,
+            };
+            let Some(batch) = batch else {
+                anyhow::bail!(match args.number {
+                    Some(number) => format!("Batch {number} is not confirmed on L1"),
+                    None => "No batch is confirmed on L1 yet".to_string(),
+                });
+            };
+            if args.json {
+                println!("{}", serde_json::to_string_pretty(&batch)?);
+                return Ok(());
+            }
+            println!("batch\t{}", batch.number);
+            println!("prior root\t{}", batch.prior_root);
+            println!("state root\t{}", batch.state_root);
+            println!("L1 tx\t{:?}", batch.l1_tx_hash);
+            println!("L1 block\t{}", batch.l1_block_number);
+            println!("time\t{}", batch.timestamp);
+            println!("transactions\t{}", batch.tx_hashes.len());
+            for (hash, tx) in batch.tx_hashes.iter().zip(&batch.txs) {
+                println!("\t{hash}\t{:?}\t{}", tx.tx.kind, display_value(tx.tx.value));
+            }
+            Ok(())
+        }
+        Subcommands::SendBatch(args) => send_batch(args, &contacts, &config).await,
+        Subcommands::Withdrawals(args) => {
+            let client = connect(&config).await?;
+            let withdrawals = client
+                .get_pending_withdrawals(request_context(&config), args.sender.to_u256())
+                .await?
+                .map_err(|e| anyhow::anyhow!(e))?;
+            for withdrawal in withdrawals {
+                let value = withdrawal
+                    .tx
+                    .map_or("?".to_string(), |tx| display_value(tx.tx.value));
+                let status = match withdrawal.status {
+                    TxStatus::Included { block, .. } => {
+                        format!("in block {block}, waiting for L1")
+                    }
+                    _ => "in the mempool".to_string(),
+                };
+                println!("{}\t{value}\t{status}", withdrawal.hash);
+            }
+            Ok(())
+        }
+        Subcommands::Verify(cli_tx) => {
+            let signed = match &cli_tx.tx_json {
+                Some(path) => fusion_wallet::read_signed_tx(path)?,
+                None if cli_tx.signature.is_none() => {
+                    anyhow::bail!("Verifying needs --signature or --tx-json")
+                }
+                None => cli_tx.into(),
+            };
+            // EdDSA signatures do not recover their signer, the one
+            // checked is the sender.
+            println!("hash\t{}", hash_tx(&signed.tx));
+            println!("signer\t{}", signed.tx.sender);
+            match fusion_wallet::verify_tx_signature(&signed) {
+                Ok(()) => {
+                    println!("valid\ttrue");
+                    Ok(())
+                }
+                Err(e) => {
+                    println!("valid\tfalse ({e})");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Subcommands::VerifyReceipt(args) => {
+            let receipt = fusion_wallet::read_receipt(&args.file)?;
+            let client = connect(&config).await?;
+            let attestation_key = match &args.key {
+                Some(key) => key.to_u256(),
+                None => {
+                    client
+                        .sequencer_info(request_context(&config))
+                        .await?
+                        .attestation_key
+                }
+            };
+            println!("hash\t{}", receipt.tx_hash);
+            println!("promised_block\t{}", receipt.promised_block);
+            if let Err(e) = fusion_wallet::verify_receipt(&receipt, &attestation_key) {
+                println!("valid\tfalse ({e})");
+                std::process::exit(1);
+            }
+            println!("valid\ttrue");
+            let status = client
+                .get_transaction(request_context(&config), receipt.tx_hash)
+                .await?
+                .ok()
+                .map(|info| info.status);
+            let health = client.sequencer_health(request_context(&config)).await?;
+            match fusion_wallet::receipt_kept(&receipt, status.as_ref(), health.block_number) {
+                Ok(true) => println!("kept\ttrue"),
+                Ok(false) => println!("kept\tnot yet, at block {}", health.block_number),
+                Err(e) => {
+                    println!("kept\tfalse ({e})");
+                    std::process::exit(1);
+                }
+            }
+            Ok(())
+        }
+        Subcommands::Drop(args) => {
+            let tx_hash = args.hash.to_u256();
+            let signature = fusion_wallet::sign_drop(tx_hash, args.private_key)?;
+            let client = connect(&config).await?;
+            client
+                .drop_transaction(request_context(&config), tx_hash, signature.to_string())
+                .await?
+                .map_err(|e| anyhow::anyhow!(e))?;
+            println!("Dropped {tx_hash}");
+            Ok(())
+        }
+        Subcommands::Faucet(args) => {
+            let client = connect(&config).await?;
+            let hash = client
+                .dev_faucet(
+                    request_context(&config),
+                    U256::from_dec_str(&args.to).unwrap(),
+                    args.value,
+                )
+                .await?
+                .map_err(|e| anyhow::anyhow!(e))?;
+            println!("Requested {}: {hash}", display_value(args.value));
+            Ok(())
+        }
+        Subcommands::Commit(args) => {
+            let signed_tx = signed_tx(with_chain_id(args.tx, &config).await?, TxKind::Transfer)?;
+            fusion_wallet::verify_tx_signature(&signed_tx)?;
+            let commitment = tx_commitment(&signed_tx.tx, args.salt.to_u256());
+            let client = connect(&config).await?;
+            let block = client
+                .submit_commitment(request_context(&config), commitment)
+                .await?
+                .map_err(|e| anyhow::anyhow!(e))?;
+            println!("Commitment {commitment} appears in block {block}");
+            Ok(())
+        }
+        Subcommands::Reveal(args) => {
+            let signed_tx = signed_tx(with_chain_id(args.tx, &config).await?, TxKind::Transfer)?;
+            let client = connect(&config).await?;
+            client
+                .reveal_transaction(request_context(&config), signed_tx, args.salt.to_u256())
+                .await?
+                .map_err(|e| anyhow::anyhow!(e))?;
             Ok(())
         }
+        Subcommands::Contacts {
+            contacts_sub: ContactsSubcommands::Add(args),
+        } => {
+            contacts
+                .add(&args.name, args.address.to_u256())
+                .map_err(|e| anyhow::anyhow!(e))?;
+            contacts.save()
+        }
+        Subcommands::Contacts {
+            contacts_sub: ContactsSubcommands::List,
+        } => {
+            for (name, address) in contacts.list() {
+                println!("{name}\t{address}");
+            }
+            Ok(())
+        }
+        Subcommands::Contacts {
+            contacts_sub: ContactsSubcommands::Remove { name },
+        } => {
+            let address = contacts.remove(&name).map_err(|e| anyhow::anyhow!(e))?;
+            println!("Removed {name}: {address}");
+            contacts.save()
+        }
+        Subcommands::Ping(args) => {
+            let outcome = ping(&args, &config).await;
+            if args.json {
+                let json = match &outcome {
+                    PingOutcome::Reachable(health) => serde_json::json!({
+                        "reachable": true,
+                        "health": health,
+                    }),
+                    PingOutcome::Unreachable(e) => serde_json::json!({
+                        "reachable": false,
+                        "error": e,
+                    }),
+                };
+                println!("{json}");
+            } else {
+                println!("{}", outcome.summary());
+            }
+            std::process::exit(outcome.exit_code());
+        }
     }
 }
 
-async fn send(tx: SignedTx, config: &Config) -> anyhow::Result<()> {
-    fusion_wallet::verify_tx_signature(&tx)?;
+async fn ping(args: &CLIPing, config: &Config) -> PingOutcome {
+    let server_addr: SocketAddr = match &args.rpc_url {
+        Some(url) => match url.parse() {
+            Ok(addr) => addr,
+            Err(e) => return PingOutcome::Unreachable(format!("Invalid address `{url}`: {e}")),
+        },
+        None => match config.socket_addr() {
+            Ok(addr) => addr,
+            Err(e) => return PingOutcome::Unreachable(e),
+        },
+    };
+    let timeout = Duration::from_secs(args.timeout);
+
+    let health = tokio::time::timeout(timeout, async {
+        let transport = tarpc::serde_transport::tcp::connect(server_addr, Json::default).await?;
+        let client = FusionRPCClient::new(client::Config::default(), transport).spawn();
+        let mut ctx = context::current();
+        ctx.deadline = SystemTime::now() + timeout;
+        anyhow::Ok(client.sequencer_health(ctx).await?)
+    })
+    .await;
+
+    match health {
+        Ok(Ok(health)) => PingOutcome::Reachable(health),
+        Ok(Err(e)) => PingOutcome::Unreachable(e.to_string()),
+        Err(_) => PingOutcome::Unreachable(format!("No answer within {}s", args.timeout)),
+    }
+}
+
+async fn connect(config: &Config) -> anyhow::Result<FusionRPCClient> {
+    let server_addr = config.socket_addr().map_err(|e| anyhow::anyhow!(e))?;
+    let connect_timeout = Duration::from_secs(config.rpc_connect_timeout);
+    let transport = tokio::time::timeout(
+        connect_timeout,
+        tarpc::serde_transport::tcp::connect(server_addr, Json::default),
+    )
+    .await
+    .map_err(|_| {
+        anyhow::anyhow!("Could not connect to the sequencer within {connect_timeout:?}")
+    })??;
+    Ok(FusionRPCClient::new(client::Config::default(), transport).spawn())
+}
 
-    let server_addr = (
-        IpAddr::V4(config.socket_address.parse().unwrap()),
-        config.socket_port,
-    );
-    let transport = tarpc::serde_transport::tcp::connect(server_addr, Json::default);
-    let client = FusionRPCClient::new(client::Config::default(), transport.await?).spawn();
-    client
-        .submit_transaction(context::current(), tx)
-        .await
-        .unwrap()
-        .unwrap();
+/// Context for a request to the sequencer, bounded by `rpc_request_timeout`.
+fn request_context(config: &Config) -> context::Context {
+    let mut ctx = context::current();
+    ctx.deadline = SystemTime::now() + Duration::from_secs(config.rpc_request_timeout);
+    ctx
+}
 
+/// The transaction of kind `kind` described by `cli_tx`, signed with
+/// its private key unless a signature is given.
+fn unsigned_tx(cli_tx: &CLITx, kind: TxKind) -> Tx {
+    let TransferTx(tx) = cli_tx.clone().into();
+    Tx { kind, ..tx }
+}
+
+fn signed_tx(cli_tx: CLITx, kind: TxKind) -> anyhow::Result<SignedTx> {
+    let tx = unsigned_tx(&cli_tx, kind);
+    let signature = match (&cli_tx.signature, cli_tx.signing_key()?) {
+        (Some(sig), _) => sig.clone(),
+        (None, Some(private_key)) => fusion_wallet::sign(&tx, private_key)?.to_string(),
+        (None, None) => {
+            anyhow::bail!("Sending needs --private-key, --keystore, --mnemonic-file or --signature")
+        }
+    };
+    Ok(SignedTx { tx, signature })
+}
+
+/// The L1 address a withdrawal pays, given in hex or decimal.
+fn l1_recipient(to: &str) -> anyhow::Result<U256> {
+    let address = match to.strip_prefix("0x") {
+        Some(_) => U256::from_big_endian(
+            to.parse::<ethers_core::types::Address>()
+                .map_err(|e| anyhow::anyhow!("Invalid L1 address `{to}`: {e}"))?
+                .as_bytes(),
+        ),
+        None => {
+            U256::from_dec_str(to).map_err(|e| anyhow::anyhow!("Invalid L1 address `{to}`: {e}"))?
+        }
+    };
+    if address.is_zero() || address.bits() > 160 {
+        anyhow::bail!("Invalid L1 address `{to}`");
+    }
+    // The transaction hash decompresses the recipient like a public key.
+    if !fusion_wallet::is_public_key(&address) {
+        anyhow::bail!("The L1 address `{to}` cannot receive withdrawals yet, use another one");
+    }
+    Ok(address)
+}
+
+/// The next nonce of the sender of `cli_tx`, from the sequencer.
+async fn next_nonce(cli_tx: &CLITx, config: &Config) -> anyhow::Result<BigInt> {
+    if cli_tx.signature.is_some() {
+        anyhow::bail!(
+            "--auto-nonce needs the private key, a given signature already covers a nonce"
+        );
+    }
+    let client = connect(config).await?;
+    let nonces = client
+        .get_nonce(
+            request_context(config),
+            cli_tx.sender.to_u256(),
+            cli_tx.token,
+        )
+        .await?
+        .map_err(|e| anyhow::anyhow!(e))?;
+    if nonces.pending > nonces.committed {
+        eprintln!(
+            "{} transaction(s) of this sender are waiting for a block",
+            nonces.pending - nonces.committed
+        );
+    }
+    Ok(nonces.next.to_string().parse()?)
+}
+
+/// `cli_tx` for the chain of the sequencer, unless it names a chain or
+/// is already signed.
+async fn with_chain_id(mut cli_tx: CLITx, config: &Config) -> anyhow::Result<CLITx> {
+    if cli_tx.chain_id.is_none() && cli_tx.signature.is_none() {
+        let client = connect(config).await?;
+        let info = client
+            .chain_info(request_context(config))
+            .await?
+            .map_err(|e| anyhow::anyhow!(e))?;
+        cli_tx.chain_id = Some(info.chain_id);
+    }
+    Ok(cli_tx)
+}
+
+/// Signs and sends the transaction, or with `dry_run` prints what the
+/// sequencer makes of it unsigned.
+/// The flags of `send`.
+struct SendOptions {
+    binary: bool,
+    dry_run: bool,
+    receipt: Option<String>,
+}
+
+async fn send_or_estimate(
+    cli_tx: CLITx,
+    kind: TxKind,
+    options: SendOptions,
+    config: &Config,
+) -> anyhow::Result<()> {
+    let cli_tx = with_chain_id(cli_tx, config).await?;
+    if !options.dry_run {
+        return send(signed_tx(cli_tx, kind)?, options, config).await;
+    }
+    let client = connect(config).await?;
+    let estimate = client
+        .estimate_transaction(request_context(config), unsigned_tx(&cli_tx, kind))
+        .await?
+        .map_err(|e| anyhow::anyhow!(e))?;
+    match estimate {
+        Estimate::Success { post_balance, fee } => {
+            println!("post_balance\t{}", display_value(post_balance));
+            println!("fee\t{}", display_value(fee));
+            Ok(())
+        }
+        Estimate::InsufficientBalance { balance, shortfall } => anyhow::bail!(
+            "Insufficient balance: {} pending, {} short",
+            display_value(balance),
+            display_value(shortfall)
+        ),
+        Estimate::BadNonce { expected } => anyhow::bail!("Bad nonce, expected {expected}"),
+        Estimate::Invalid(e) => anyhow::bail!(e),
+    }
+}
+
+async fn send(tx: SignedTx, options: SendOptions, config: &Config) -> anyhow::Result<()> {
+    fusion_wallet::verify_tx_signature(&tx)?;
+
+    let client = connect(config).await?;
+    let queued = match options.binary {
+        true => {
+            let raw = encode_signed_tx(&tx).map_err(|e| anyhow::anyhow!(e))?;
+            client
+                .submit_transaction_raw(request_context(config), raw.into())
+                .await?
+        }
+        false => {
+            client
+                .submit_transaction(request_context(config), tx)
+                .await?
+        }
+    }
+    .map_err(|e| anyhow::anyhow!(e))?;
+    println!("{}", queued.tx_hash);
+    eprintln!("{}", describe_position(&queued));
+    match (&queued.receipt, options.receipt) {
+        (Some(receipt), Some(path)) => {
+            std::fs::write(&path, serde_json::to_string_pretty(receipt)?)
+                .map_err(|e| anyhow::anyhow!("Could not write {path}: {e}"))?;
+            eprintln!(
+                "Promised in block {}, receipt in {path}",
+                receipt.promised_block
+            );
+        }
+        (None, Some(_)) => eprintln!("The sequencer did not sign a receipt"),
+        (_, None) => {}
+    }
     Ok(())
 }
 
+/// Where a pending transaction stands, in words.
+fn describe_position(queued: &QueuePosition) -> String {
+    format!(
+        "Queued behind {} transaction(s), {} of {} needed for a block, \
+         expected {} block(s) after the next",
+        queued.position, queued.queued, queued.batch_size, queued.blocks_ahead
+    )
+}
+
+/// The private key given with --private-key, --keystore or
+/// --mnemonic-file, if any.
+fn signing_key(
+    private_key: &Option<String>,
+    keystore: &Option<String>,
+    mnemonic_file: &Option<String>,
+    derivation_path: &str,
+) -> anyhow::Result<Option<String>> {
+    let key = match (private_key, keystore, mnemonic_file) {
+        (Some(private_key), _, _) => return Ok(Some(private_key.clone())),
+        (None, Some(path), _) => keys::keystore_password(path)
+            .and_then(|password| keys::decrypt_keystore(path, &password)),
+        (None, None, Some(path)) => keys::derive_from_mnemonic(path, derivation_path),
+        (None, None, None) => return Ok(None),
+    }
+    .map_err(|e| anyhow::anyhow!(e))?;
+    Ok(Some(U256::from_big_endian(&key).to_string()))
+}
+
+impl CLITx {
+    fn signing_key(&self) -> anyhow::Result<Option<String>> {
+        signing_key(
+            &self.private_key,
+            &self.keystore,
+            &self.mnemonic_file,
+            &self.derivation_path,
+        )
+    }
+}
+
+/// Signs and submits the transfers of `args.file`, at most
+/// `args.parallelism` at once, and prints how each went. Fails if any
+/// did.
+async fn send_batch(
+    args: CLISendBatch,
+    contacts: &AddressBook,
+    config: &Config,
+) -> anyhow::Result<()> {
+    let private_key = signing_key(
+        &args.private_key,
+        &args.keystore,
+        &args.mnemonic_file,
+        &args.derivation_path,
+    )?
+    .ok_or_else(|| {
+        anyhow::anyhow!("send-batch needs --private-key, --keystore or --mnemonic-file")
+    })?;
+    let sender = fusion_wallet::new_public_key(&private_key.clone().into()).to_u256();
+    let json = std::fs::read_to_string(&args.file)
+        .map_err(|e| anyhow::anyhow!("Could not read {}: {e}", args.file))?;
+    let entries = batch::parse_entries(&json).map_err(|e| anyhow::anyhow!(e))?;
+
+    let client = connect(config).await?;
+    let chain_id = client
+        .chain_info(request_context(config))
+        .await?
+        .map_err(|e| anyhow::anyhow!(e))?
+        .chain_id;
+    let nonces = match entries.iter().any(|entry| entry.nonce.is_none()) {
+        true => {
+            let nonces = client
+                .get_nonce(request_context(config), sender, None)
+                .await?
+                .map_err(|e| anyhow::anyhow!(e))?;
+            batch::assign_nonces(&entries, nonces.next)
+        }
+        false => batch::assign_nonces(&entries, U256::zero()),
+    };
+
+    let semaphore = Arc::new(Semaphore::new(args.parallelism.max(1)));
+    let mut sending = JoinSet::new();
+    for (i, (entry, nonce)) in entries.into_iter().zip(nonces).enumerate() {
+        let signed = match (entry.fee, contacts.resolve(&entry.to)) {
+            (Some(fee), _) if !fee.is_zero() => {
+                Err("Transaction fees are not supported yet".to_string())
+            }
+            (_, Err(e)) => Err(e),
+            (_, Ok(to)) => {
+                let tx = Tx {
+                    kind: TxKind::Transfer,
+                    sender,
+                    to,
+                    nonce,
+                    value: entry.value,
+                    not_before: None,
+                    not_after: None,
+                    chain_id: Some(chain_id),
+                    token: NATIVE_TOKEN,
+                };
+                fusion_wallet::sign(&tx, private_key.clone())
+                    .map(|signature| SignedTx {
+                        tx,
+                        signature: signature.to_string(),
+                    })
+                    .map_err(|e| e.to_string())
+            }
+        };
+        let (client, ctx, semaphore) = (client.clone(), request_context(config), semaphore.clone());
+        sending.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            let result = match signed {
+                Ok(tx) => client
+                    .submit_transaction(ctx, tx)
+                    .await
+                    .map_err(|e| e.to_string())
+                    .and_then(|result| {
+                        result
+                            .map(|queued| queued.tx_hash)
+                            .map_err(|e| e.to_string())
+                    }),
+                Err(e) => Err(e),
+            };
+            (i, nonce, result)
+        });
+    }
+    let mut results = vec![];
+    while let Some(result) = sending.join_next().await {
+        results.push(result?);
+    }
+    results.sort_by_key(|(i, _, _)| *i);
+
+    println!("#\tnonce\ttx hash\tstatus");
+    for (i, nonce, result) in &results {
+        match result {
+            Ok(hash) => println!("{}\t{nonce}\t{hash}\taccepted", i + 1),
+            Err(_) => println!("{}\t{nonce}\t-\tfailed", i + 1),
+        }
+    }
+    let failed: Vec<_> = results
+        .iter()
+        .filter_map(|(i, _, result)| result.as_ref().err().map(|e| (i, e)))
+        .collect();
+    if failed.is_empty() {
+        return Ok(());
+    }
+    eprintln!();
+    for (i, e) in &failed {
+        eprintln!("Transfer {}: {e}", *i + 1);
+    }
+    anyhow::bail!("{} of {} transfer(s) failed", failed.len(), results.len())
+}
+
 #[derive(Debug, Clone)]
 pub struct TransferTx(pub Tx);
 
@@ -83,10 +734,14 @@ impl From<CLITx> for TransferTx {
     fn from(cli_tx: CLITx) -> Self {
         let tx = Tx {
             sender: cli_tx.sender.to_u256(),
-            to: cli_tx.to.to_u256(),
+            to: U256::from_dec_str(&cli_tx.to).unwrap(),
             nonce: cli_tx.nonce.to_u256(),
-            value: cli_tx.value.to_u256(),
+            value: cli_tx.value,
             kind: TxKind::Transfer,
+            not_before: cli_tx.not_before,
+            not_after: cli_tx.not_after,
+            chain_id: cli_tx.chain_id,
+            token: cli_tx.token.unwrap_or(NATIVE_TOKEN),
         };
         Self(tx)
     }
@@ -96,10 +751,14 @@ impl From<CLITx> for Tx {
     fn from(cli_tx: CLITx) -> Self {
         Self {
             sender: cli_tx.sender.to_u256(),
-            to: cli_tx.to.to_u256(),
+            to: U256::from_dec_str(&cli_tx.to).unwrap(),
             nonce: cli_tx.nonce.to_u256(),
-            value: cli_tx.value.to_u256(),
+            value: cli_tx.value,
             kind: cli_tx.kind.unwrap().into(),
+            not_before: cli_tx.not_before,
+            not_after: cli_tx.not_after,
+            chain_id: cli_tx.chain_id,
+            token: cli_tx.token.unwrap_or(NATIVE_TOKEN),
         }
     }
 }
@@ -116,6 +775,28 @@ impl From<CLITx> for SignedTx {
 #[derive(Debug, Parser)]
 #[clap(name = "Fusion transaction signer and sender", version = env!("CARGO_PKG_VERSION"))]
 struct Opts {
+    #[clap(
+        long,
+        global = true,
+        value_name = "PATH",
+        help = "The config file.",
+        default_value = "../fusion.toml"
+    )]
+    pub config: String,
+    #[clap(
+        long,
+        global = true,
+        value_name = "NAME",
+        help = "The config profile to use, defaults to $FUSION_PROFILE."
+    )]
+    pub profile: Option<String>,
+    #[clap(
+        long,
+        global = true,
+        value_name = "HOST:PORT",
+        help = "The sequencer RPC address, defaults to the one in the config."
+    )]
+    pub sequencer_url: Option<String>,
     #[clap(subcommand)]
     pub sub: Subcommands,
 }
@@ -126,15 +807,68 @@ pub enum Subcommands {
     New,
     #[clap(about = "Generate a public key from a private key.")]
     Public(CLIPublic),
-    #[clap(about = "Sign a fusion transaction.")]
+    #[clap(about = "Sign a fusion transaction, or the Tx JSON of --tx-json into a SignedTx JSON.")]
     Sign(CLITx),
     #[clap(about = "Send fusion transaction, optionally sign it before.")]
     Send {
+        #[clap(long, help = "Submit the transaction in the binary encoding.")]
+        binary: bool,
+        #[clap(
+            long,
+            help = "Use the sender's next nonce, from the sequencer, instead of --nonce."
+        )]
+        auto_nonce: bool,
+        #[clap(
+            long,
+            help = "Only ask the sequencer whether it would include the transaction, unsigned."
+        )]
+        dry_run: bool,
+        #[clap(
+            long,
+            value_name = "PATH",
+            help = "Write the receipt the sequencer signs to PATH, see `verify-receipt`."
+        )]
+        receipt: Option<String>,
         #[clap(subcommand)]
         send_sub: SendSubcommands,
     },
-    #[clap(about = "Verify transaction signature.")]
+    #[clap(about = "Sign and send the transfers of a JSON file, see `batch`.")]
+    SendBatch(CLISendBatch),
+    #[clap(
+        about = "Verify transaction signature, of the flags or a SignedTx JSON. Exits with 1 if invalid."
+    )]
     Verify(CLITx),
+    #[clap(
+        about = "Check a receipt of `send --receipt` and whether its promise was kept. Exits with 1 if invalid or broken."
+    )]
+    VerifyReceipt(CLIVerifyReceipt),
+    #[clap(about = "Commit to a transfer without disclosing it, to reveal it later.")]
+    Commit(CLICommit),
+    #[clap(about = "Reveal a transfer committed to with `commit`, using the same salt.")]
+    Reveal(CLICommit),
+    #[clap(about = "Show the balance of an account.")]
+    Balance(CLIAccount),
+    #[clap(about = "Show the nonces of an account.")]
+    Nonce(CLIAccount),
+    #[clap(about = "Show a transaction and its status.")]
+    Tx(CLIQueryTx),
+    #[clap(about = "Show a batch confirmed on L1, the latest one by default.")]
+    Batch(CLIBatch),
+    #[clap(about = "List the withdrawals of a sender not paid out on L1 yet.")]
+    Withdrawals(CLIWithdrawals),
+    #[clap(about = "Drop a pending transaction of yours from the mempool.")]
+    Drop(CLIDrop),
+    #[clap(about = "Get funds from the sequencer's faucet (dev mode only).")]
+    Faucet(CLIFaucet),
+    #[clap(about = "Manage the address book of recipients.")]
+    Contacts {
+        #[clap(subcommand)]
+        contacts_sub: ContactsSubcommands,
+    },
+    #[clap(
+        about = "Check the sequencer's health. Exits with 0 if healthy, 1 if degraded, 2 if halted or unreachable."
+    )]
+    Ping(CLIPing),
 }
 
 #[derive(Debug, Subcommand)]
@@ -143,10 +877,104 @@ pub enum SendSubcommands {
     Transfer(CLITx),
     #[clap(about = "Claim an L1 deposit into the L2.")]
     Deposit(CLITx),
-    #[clap(about = "Withdraw from the L2 into Ethereum L1.")]
+    #[clap(about = "Withdraw from the L2 into Ethereum L1, --to is the L1 address.")]
     Withdraw(CLITx),
 }
 
+#[derive(Debug, Subcommand)]
+pub enum ContactsSubcommands {
+    #[clap(about = "Add a contact.")]
+    Add(CLIContact),
+    #[clap(about = "List all contacts.")]
+    List,
+    #[clap(about = "Remove a contact.")]
+    Remove {
+        #[clap(value_name = "NAME")]
+        name: String,
+    },
+}
+
+impl Subcommands {
+    /// Fails if `--tx-json` is given to a command that does not read it.
+    fn check_tx_json(&self) -> anyhow::Result<()> {
+        match self {
+            Subcommands::Send {
+                send_sub:
+                    SendSubcommands::Transfer(cli_tx)
+                    | SendSubcommands::Deposit(cli_tx)
+                    | SendSubcommands::Withdraw(cli_tx),
+                ..
+            }
+            | Subcommands::Commit(CLICommit { tx: cli_tx, .. })
+            | Subcommands::Reveal(CLICommit { tx: cli_tx, .. })
+                if cli_tx.tx_json.is_some() =>
+            {
+                anyhow::bail!("--tx-json is only read by `sign` and `verify`")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Replaces the recipients given as contact names by their public
+    /// keys, failing if a name is unknown.
+    fn resolve_recipients(self, contacts: &AddressBook) -> anyhow::Result<Self> {
+        let resolve = |to: &mut String| -> anyhow::Result<()> {
+            *to = contacts
+                .resolve(to)
+                .map_err(|e| anyhow::anyhow!(e))?
+                .to_string();
+            Ok(())
+        };
+        Ok(match self {
+            Subcommands::Sign(mut cli_tx) if cli_tx.tx_json.is_none() => {
+                resolve(&mut cli_tx.to)?;
+                Subcommands::Sign(cli_tx)
+            }
+            Subcommands::Verify(mut cli_tx) if cli_tx.tx_json.is_none() => {
+                resolve(&mut cli_tx.to)?;
+                Subcommands::Verify(cli_tx)
+            }
+            Subcommands::Send {
+                binary,
+                auto_nonce,
+                dry_run,
+                receipt,
+                send_sub: SendSubcommands::Transfer(mut cli_tx),
+            } => {
+                resolve(&mut cli_tx.to)?;
+                Subcommands::Send {
+                    binary,
+                    auto_nonce,
+                    dry_run,
+                    receipt,
+                    send_sub: SendSubcommands::Transfer(cli_tx),
+                }
+            }
+            Subcommands::Commit(mut args) => {
+                resolve(&mut args.tx.to)?;
+                Subcommands::Commit(args)
+            }
+            Subcommands::Reveal(mut args) => {
+                resolve(&mut args.tx.to)?;
+                Subcommands::Reveal(args)
+            }
+            Subcommands::Faucet(mut args) => {
+                resolve(&mut args.to)?;
+                Subcommands::Faucet(args)
+            }
+            other => other,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Parser, Default)]
+pub struct CLIContact {
+    #[clap(value_name = "NAME")]
+    pub name: String,
+    #[clap(value_name = "PUBLIC_KEY")]
+    pub address: BigInt,
+}
+
 #[derive(Debug, Clone, Parser, Default)]
 pub struct CLIPublic {
     #[clap(
@@ -158,6 +986,172 @@ pub struct CLIPublic {
     pub private_key: String,
 }
 
+#[derive(Debug, Clone, Parser, Default)]
+pub struct CLIFaucet {
+    #[clap(
+        long,
+        short = 't',
+        value_name = "DEST_ADDRESS",
+        help = "The address or contact name that receives the funds."
+    )]
+    pub to: String,
+    #[clap(
+        long,
+        short = 'v',
+        value_name = "VALUE",
+        value_parser = parse_value,
+        help = "The amount requested, raw or with a unit (1.5eth, 2500gwei)."
+    )]
+    pub value: U256,
+}
+
+#[derive(Debug, Clone, Parser, Default)]
+pub struct CLIWithdrawals {
+    #[clap(
+        long,
+        short = 'f',
+        value_name = "SENDER_ADDRESS",
+        help = "The address of the sender."
+    )]
+    pub sender: BigInt,
+}
+
+#[derive(Debug, Clone, Parser, Default)]
+pub struct CLISendBatch {
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "JSON array of {to, value, nonce?} transfers, nonces are filled in when missing."
+    )]
+    pub file: String,
+    #[clap(
+        long,
+        short = 'p',
+        value_name = "PRIVATE_KEY",
+        help = "The private key of the sender"
+    )]
+    pub private_key: Option<String>,
+    #[clap(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["private_key", "mnemonic_file"],
+        help = "Sign with the key of this JSON keystore, unlocked with $ETH_KEYSTORE_PASSWORD or a prompt."
+    )]
+    pub keystore: Option<String>,
+    #[clap(
+        long,
+        value_name = "PATH",
+        conflicts_with = "private_key",
+        help = "Sign with the key derived from the BIP-39 mnemonic in this file."
+    )]
+    pub mnemonic_file: Option<String>,
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "The derivation path used with --mnemonic-file.",
+        default_value = keys::DEFAULT_DERIVATION_PATH
+    )]
+    pub derivation_path: String,
+    #[clap(
+        long,
+        value_name = "N",
+        help = "Transfers submitted at once.",
+        default_value = "8"
+    )]
+    pub parallelism: usize,
+}
+
+#[derive(Debug, Clone, Parser, Default)]
+pub struct CLIAccount {
+    #[clap(
+        long,
+        short = 'a',
+        value_name = "ADDRESS",
+        help = "The public key of the account."
+    )]
+    pub address: BigInt,
+    #[clap(
+        long,
+        value_name = "TOKEN",
+        help = "The L1 address of an ERC-20 token, ether by default."
+    )]
+    pub token: Option<Address>,
+    #[clap(long, help = "Print the result as JSON.")]
+    pub json: bool,
+}
+
+#[derive(Debug, Clone, Parser, Default)]
+pub struct CLIQueryTx {
+    #[clap(long, value_name = "TX_HASH", help = "The hash of the transaction.")]
+    pub hash: BigInt,
+    #[clap(long, help = "Print the result as JSON.")]
+    pub json: bool,
+}
+
+#[derive(Debug, Clone, Parser, Default)]
+pub struct CLIVerifyReceipt {
+    #[clap(long, value_name = "PATH", help = "The receipt JSON, `-` for stdin.")]
+    pub file: String,
+    #[clap(
+        long,
+        value_name = "PUBLIC_KEY",
+        help = "The sequencer's attestation key, asked to the sequencer by default."
+    )]
+    pub key: Option<BigInt>,
+}
+
+#[derive(Debug, Clone, Parser, Default)]
+pub struct CLIBatch {
+    #[clap(long, short = 'n', value_name = "NUMBER", help = "The batch number.")]
+    pub number: Option<u64>,
+    #[clap(long, help = "Print the result as JSON.")]
+    pub json: bool,
+}
+
+#[derive(Debug, Clone, Parser, Default)]
+pub struct CLIDrop {
+    #[clap(
+        long,
+        short = 'p',
+        value_name = "PRIVATE_KEY",
+        help = "The private key of the transaction's sender"
+    )]
+    pub private_key: String,
+    #[clap(long, value_name = "TX_HASH", help = "The hash of the transaction.")]
+    pub hash: BigInt,
+}
+
+#[derive(Debug, Clone, Parser, Default)]
+pub struct CLICommit {
+    #[clap(flatten)]
+    pub tx: CLITx,
+    #[clap(
+        long,
+        value_name = "SALT",
+        help = "Secret salt of the commitment, needed again to reveal."
+    )]
+    pub salt: BigInt,
+}
+
+#[derive(Debug, Clone, Parser, Default)]
+pub struct CLIPing {
+    #[clap(
+        long,
+        value_name = "HOST:PORT",
+        help = "The sequencer RPC address, defaults to --sequencer-url or the config."
+    )]
+    pub rpc_url: Option<String>,
+    #[clap(
+        long,
+        value_name = "SECONDS",
+        help = "Give up after this many seconds.",
+        default_value = "5"
+    )]
+    pub timeout: u64,
+    #[clap(long, help = "Print the result as JSON.")]
+    pub json: bool,
+}
+
 #[derive(Debug, Clone, Parser, Default)]
 pub struct CLITx {
     #[clap(
@@ -167,10 +1161,44 @@ pub struct CLITx {
         help = "The private key that signs the message"
     )]
     pub private_key: Option<String>,
+    #[clap(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["private_key", "mnemonic_file"],
+        help = "Sign with the key of this JSON keystore, unlocked with $ETH_KEYSTORE_PASSWORD or a prompt."
+    )]
+    pub keystore: Option<String>,
+    #[clap(
+        long,
+        value_name = "PATH",
+        conflicts_with = "private_key",
+        help = "Sign with the key derived from the BIP-39 mnemonic in this file."
+    )]
+    pub mnemonic_file: Option<String>,
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "The derivation path used with --mnemonic-file.",
+        default_value = keys::DEFAULT_DERIVATION_PATH
+    )]
+    pub derivation_path: String,
+    #[clap(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = [
+            "sender", "to", "value", "nonce", "kind", "not_before", "not_after", "chain_id",
+            "token", "signature",
+        ],
+        help = "For `sign`, the Tx JSON in this file, `-` for stdin; for `verify`, the SignedTx JSON."
+    )]
+    pub tx_json: Option<String>,
     #[clap(
         long,
         short = 'f',
         value_name = "SENDER_ADDRESS",
+        required_unless_present = "tx_json",
+        default_value = "0",
+        hide_default_value = true,
         help = "The address of the sender address."
     )]
     pub sender: BigInt,
@@ -178,17 +1206,21 @@ pub struct CLITx {
         long,
         short = 't',
         value_name = "DEST_ADDRESS",
-        help = "The address of the destination address."
+        required_unless_present = "tx_json",
+        default_value = "",
+        hide_default_value = true,
+        help = "The address or contact name of the destination."
     )]
-    pub to: BigInt,
+    pub to: String,
     #[clap(
         long,
         short = 'v',
         value_name = "VALUE",
-        help = "The value of the transaction.",
+        value_parser = parse_value,
+        help = "The value of the transaction, raw or with a unit (1.5eth, 2500gwei).",
         default_value = "0"
     )]
-    pub value: BigInt,
+    pub value: U256,
     #[clap(
         long,
         short = 'n',
@@ -199,6 +1231,32 @@ pub struct CLITx {
     pub nonce: BigInt,
     #[clap(long, short = 'k', value_name = "KIND", help = "The transaction kind.")]
     pub kind: Option<u8>,
+    #[clap(
+        long,
+        value_name = "TIMESTAMP",
+        value_parser = fusion_wallet::parse_timestamp,
+        help = "Do not include the transaction before this time (unix seconds or RFC3339)."
+    )]
+    pub not_before: Option<u64>,
+    #[clap(
+        long,
+        value_name = "TIMESTAMP",
+        value_parser = fusion_wallet::parse_timestamp,
+        help = "Expire the transaction after this time (unix seconds or RFC3339)."
+    )]
+    pub not_after: Option<u64>,
+    #[clap(
+        long,
+        value_name = "CHAIN_ID",
+        help = "Sign for this Fusion chain, the sequencer's by default. `sign` signs without one."
+    )]
+    pub chain_id: Option<u64>,
+    #[clap(
+        long,
+        value_name = "TOKEN",
+        help = "The L1 address of the ERC-20 token moved, ether by default."
+    )]
+    pub token: Option<Address>,
     #[clap(
         long,
         short = 's',
@@ -0,0 +1,177 @@
+//! Parsing and display of values with units.
+
+use ethers_core::types::U256;
+
+/// Units accepted as value suffixes, with their number of decimals.
+/// Longest suffixes first so `gwei` is not read as `wei`.
+const UNITS: [(&str, u32); 4] = [("ether", 18), ("gwei", 9), ("eth", 18), ("wei", 0)];
+
+/// Decimals of the unit values are displayed in.
+const DISPLAY_DECIMALS: u32 = 18;
+const DISPLAY_UNIT: &str = "eth";
+
+/// Parses a value given either as a raw integer (`1500`), in scientific
+/// notation (`1.5e18`), or with a unit (`1.5eth`, `2500gwei`, `7wei`).
+/// Values that do not land on a whole raw unit are rejected.
+pub fn parse_value(s: &str) -> Result<U256, String> {
+    let input = s.trim().to_lowercase();
+    let unit = UNITS.iter().find_map(|(unit, decimals)| {
+        input
+            .strip_suffix(unit)
+            .map(|number| (number.trim_end(), *decimals))
+    });
+    let (number, unit_decimals) = unit.unwrap_or((input.as_str(), 0));
+
+    let (mantissa, exponent) = match number.split_once('e') {
+        Some((mantissa, exponent)) => (
+            mantissa,
+            exponent
+                .parse::<u32>()
+                .map_err(|_| format!("Invalid exponent in `{s}`"))?,
+        ),
+        None => (number, 0),
+    };
+    let (integer, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let is_digits = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+    if !is_digits(integer) || (mantissa.contains('.') && !is_digits(fraction)) {
+        return Err(format!(
+            "Invalid value `{s}`, expected e.g. `1500`, `1.5e18`, `1.5eth` or `2500gwei`"
+        ));
+    }
+
+    let fraction = fraction.trim_end_matches('0');
+    let shift = unit_decimals
+        .checked_add(exponent)
+        .ok_or_else(|| format!("Value `{s}` is too large"))?;
+    let Some(padding) = shift.checked_sub(fraction.len() as u32) else {
+        return Err(match (unit, exponent) {
+            (None, 0) => format!("Fractional value `{s}` needs a unit, e.g. `{s}eth`"),
+            _ => format!("Value `{s}` has more decimal places than supported"),
+        });
+    };
+
+    let too_large = || format!("Value `{s}` is too large");
+    let digits = U256::from_dec_str(&format!("{integer}{fraction}")).map_err(|_| too_large())?;
+    let scale = U256::from(10)
+        .checked_pow(padding.into())
+        .ok_or_else(too_large)?;
+    digits.checked_mul(scale).ok_or_else(too_large)
+}
+
+/// Formats a raw value in eth, without trailing zeros.
+pub fn format_value(value: U256) -> String {
+    let unit = U256::exp10(DISPLAY_DECIMALS as usize);
+    let integer = value / unit;
+    let fraction = value % unit;
+    if fraction.is_zero() {
+        return format!("{integer} {DISPLAY_UNIT}");
+    }
+    let fraction = format!(
+        "{:0>width$}",
+        fraction.to_string(),
+        width = DISPLAY_DECIMALS as usize
+    );
+    format!(
+        "{integer}.{} {DISPLAY_UNIT}",
+        fraction.trim_end_matches('0')
+    )
+}
+
+/// Formats a value in both raw and human form, e.g.
+/// `1500000000000000000 (1.5 eth)`.
+pub fn display_value(value: U256) -> String {
+    format!("{value} ({})", format_value(value))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn exp10(n: usize) -> U256 {
+        U256::exp10(n)
+    }
+
+    #[test]
+    fn parse() {
+        let cases = [
+            ("0", U256::zero()),
+            ("1500", 1500.into()),
+            (" 1500 ", 1500.into()),
+            ("7wei", 7.into()),
+            ("1.5e18", exp10(17) * 15),
+            ("1.5E3", 1500.into()),
+            ("1e0", 1.into()),
+            ("1.5eth", exp10(17) * 15),
+            ("1.5 ETH", exp10(17) * 15),
+            ("2ether", exp10(18) * 2),
+            ("2500gwei", exp10(9) * 2500),
+            ("0.000000001eth", exp10(9)),
+            ("1.000000000000000000eth", exp10(18)),
+            ("1.10gwei", 1_100_000_000.into()),
+            ("1e77", exp10(77)),
+            (
+                "115792089237316195423570985008687907853269984665640564039457584007913129639935",
+                U256::MAX,
+            ),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse_value(input), Ok(expected), "{input}");
+        }
+    }
+
+    #[test]
+    fn rejected() {
+        let cases = [
+            ("", "Invalid value"),
+            ("eth", "Invalid value"),
+            ("-1", "Invalid value"),
+            ("1,000", "Invalid value"),
+            (".5eth", "Invalid value"),
+            ("1.eth", "Invalid value"),
+            ("1.5.1eth", "Invalid value"),
+            ("0x10", "Invalid value"),
+            ("1e-3", "Invalid exponent"),
+            ("1.5", "needs a unit"),
+            ("1.5wei", "more decimal places"),
+            ("1.55e1", "more decimal places"),
+            ("0.0000000000000000001eth", "more decimal places"),
+            ("1.0000000001gwei", "more decimal places"),
+            ("1e78", "too large"),
+            ("1e4294967295eth", "too large"),
+            (
+                "115792089237316195423570985008687907853269984665640564039457584007913129639936",
+                "too large",
+            ),
+            (
+                "200000000000000000000000000000000000000000000000000000000000eth",
+                "too large",
+            ),
+        ];
+        for (input, error) in cases {
+            let result = parse_value(input);
+            assert!(
+                result.as_ref().is_err_and(|e| e.contains(error)),
+                "{input}: {result:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn format() {
+        let cases = [
+            (U256::zero(), "0 eth"),
+            (1.into(), "0.000000000000000001 eth"),
+            (exp10(17) * 15, "1.5 eth"),
+            (exp10(18) * 2, "2 eth"),
+            (exp10(9) * 2500, "0.0000025 eth"),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(format_value(value), expected);
+            assert_eq!(parse_value(&expected.replace(' ', "")), Ok(value));
+        }
+        assert_eq!(
+            display_value(exp10(17) * 15),
+            "1500000000000000000 (1.5 eth)"
+        );
+    }
+}
@@ -9,9 +9,18 @@ contract VerifierScript is Script {
     function setUp() public {}
 
     function run() public returns (Fusion) {
+        // The same keys as the sequencer reads, see fusion.toml.
+        uint256 chainId = vm.envOr("FUSION_CHAIN_ID", uint256(1));
+        uint256 legacyTxsUntil = vm.envOr("FUSION_LEGACY_TXS_UNTIL", uint256(0));
+        if (!vm.envOr("FUSION_ACCEPT_LEGACY_TXS", true)) {
+            legacyTxsUntil = 0;
+        } else if (legacyTxsUntil == 0) {
+            legacyTxsUntil = type(uint256).max;
+        }
+
         vm.startBroadcast();
 
-        Fusion t = new Fusion();
+        Fusion t = new Fusion(chainId, legacyTxsUntil);
 
         vm.stopBroadcast();
 
@@ -4,9 +4,23 @@ import {Verifier} from "src/Verifier.sol";
 
 uint8 constant BLOCK_SIZE = 1;
 
+interface IERC20 {
+    function transfer(address to, uint256 amount) external returns (bool);
+    function transferFrom(address from, address to, uint256 amount) external returns (bool);
+}
+
 contract Fusion is Verifier {
     uint256 public root;
     mapping(uint256 => uint256) public deposits;
+    // The ERC-20 deposits, by token and then by the L2 leaf they credit.
+    mapping(address => mapping(uint256 => uint256)) public tokenDeposits;
+    // The chain id transactions must be signed for, `chain_id` in
+    // fusion.toml.
+    uint256 public immutable chainId;
+    // Until when transactions signed without a chain id (input 20 is 0)
+    // are accepted, compared with the timestamp of the block that submits
+    // their proof: never when 0, always when type(uint256).max.
+    uint256 public immutable legacyTxsUntil;
 
     error InvalidPreRoot();
     error InvalidInputLength();
@@ -15,6 +29,13 @@ contract Fusion is Verifier {
     error InvalidL1Address();
     error DepositAmountTooLow();
     error DepositAmountNotAvailable();
+    error InvalidToken();
+    error TokenTransferFailed();
+    error InvalidChainId();
+
+    // Lets the sequencer find the accepted blocks with eth_getLogs and
+    // replay their proofs from the calldata.
+    event BlockSubmitted(uint256 preRoot, uint256 postRoot);
 
     // Proof: zk proof (see Verifier.sol)
     // Inputs:
@@ -27,20 +48,31 @@ contract Fusion is Verifier {
     //  6: tx.to.y
     //  7: tx.nonce
     //  8: tx.value
-    //  8: tx.sig.r.x
-    // 10: tx.sig.r.y
-    // 11: tx.sig.s
-    // 12: sender.id
-    // 13: sender.balance
-    // 14: sender.nonce
-    // 15: to.id
-    // 16: to.balance
-    // 17: to.nonce
+    //  9: tx.not_before
+    // 10: tx.not_after
+    // 11: tx.sig.r.x
+    // 12: tx.sig.r.y
+    // 13: tx.sig.s
+    // 14: sender.id
+    // 15: sender.balance
+    // 16: sender.nonce
+    // 17: to.id
+    // 18: to.balance
+    // 19: to.nonce
+    // 20: chain id the transaction is signed for, 0 for the first
+    //     message format, without one
+    // 21: address of the ERC-20 token moved, 0 for ether
     struct TxProof {
         Proof proof;
         uint256[] input;
     }
 
+    constructor(uint256 _chainId, uint256 _legacyTxsUntil) {
+        if (_chainId == 0) revert InvalidChainId();
+        chainId = _chainId;
+        legacyTxsUntil = _legacyTxsUntil;
+    }
+
     function submitBlock(TxProof[BLOCK_SIZE] memory l2Block) external {
         // The proof's pre-state's root must be the current root in this contract.
         if (root != l2Block[0].input[0]) {
@@ -52,7 +84,9 @@ contract Fusion is Verifier {
         }
 
         // Update the canonical root with the proof's post-state's root.
+        uint256 preRoot = root;
         root = l2Block[l2Block.length - 1].input[1];
+        emit BlockSubmitted(preRoot, root);
     }
 
     function deposit(uint256 l2Recipient) external payable {
@@ -60,11 +94,28 @@ contract Fusion is Verifier {
         deposits[l2Recipient] += msg.value;
     }
 
+    // `l2Account` is the key of the leaf holding `token` for the L2
+    // account, poseidon(address, token), as `fusion_api::account_key`
+    // computes it. Requires an allowance of `amount`.
+    function depositToken(address token, uint256 l2Account, uint256 amount) external {
+        if (token == address(0)) revert InvalidToken();
+        if (amount == 0) revert DepositAmountTooLow();
+        if (!IERC20(token).transferFrom(msg.sender, address(this), amount)) {
+            revert TokenTransferFailed();
+        }
+        tokenDeposits[token][l2Account] += amount;
+    }
+
     function verifyTx(TxProof memory l2Tx) internal {
-        if (l2Tx.input.length != 18) {
+        if (l2Tx.input.length != 22) {
             revert InvalidInputLength();
         }
 
+        uint256 txChainId = l2Tx.input[20];
+        if (txChainId != chainId && (txChainId != 0 || block.timestamp > legacyTxsUntil)) {
+            revert InvalidChainId();
+        }
+
         verifyAndProcessDeposit(l2Tx);
 
         // A valid SNARK returns 0.
@@ -75,25 +126,34 @@ contract Fusion is Verifier {
 
     function verifyAndProcessDeposit(TxProof memory l2Tx) internal {
         uint256 txKind = l2Tx.input[2];
+        address token = address(uint160(l2Tx.input[21]));
+        if (l2Tx.input[21] != uint160(token)) revert InvalidToken();
+
         if (txKind == 0) {
             // L2 transfer, do nothing
         } else if (txKind == 1) {
             uint256 l2Value = l2Tx.input[8];
-            uint256 l2Recipient = l2Tx.input[12];
+            uint256 l2Recipient = l2Tx.input[14];
 
-            if (deposits[l2Recipient] < l2Value) revert DepositAmountNotAvailable();
+            mapping(uint256 => uint256) storage available =
+                token == address(0) ? deposits : tokenDeposits[token];
+            if (available[l2Recipient] < l2Value) revert DepositAmountNotAvailable();
 
             unchecked {
-                deposits[l2Recipient] -= l2Value;
+                available[l2Recipient] -= l2Value;
             }
         } else if (txKind == 2) {
             uint256 l2Value = l2Tx.input[8];
-            uint256 l1Recipient = l2Tx.input[15];
+            uint256 l1Recipient = l2Tx.input[17];
 
             address l1Address = address(uint160(l1Recipient));
             if (l1Recipient != uint160(l1Address)) revert InvalidL1Address();
 
-            payable(l1Address).transfer(l2Value);
+            if (token == address(0)) {
+                payable(l1Address).transfer(l2Value);
+            } else if (!IERC20(token).transfer(l1Address, l2Value)) {
+                revert TokenTransferFailed();
+            }
         } else {
             revert InvalidTransactionType();
         }
@@ -9,7 +9,34 @@ contract FusionTest is Test {
     Fusion fusion;
 
     function setUp() public {
-        fusion = new Fusion();
+        fusion = new Fusion(1, 0);
+    }
+
+    function chainIdInput(uint256 chainId) internal pure returns (uint256[] memory input) {
+        input = new uint256[](22);
+        input[20] = chainId;
+    }
+
+    function testChainId() public {
+        Fusion.Proof memory proof;
+        vm.expectRevert(Fusion.InvalidChainId.selector);
+        fusion.submitBlock([Fusion.TxProof(proof, chainIdInput(2))]);
+        // Legacy transactions are refused by this deployment.
+        vm.expectRevert(Fusion.InvalidChainId.selector);
+        fusion.submitBlock([Fusion.TxProof(proof, chainIdInput(0))]);
+        // The chain id passes, the proof does not.
+        vm.expectRevert(Fusion.InvalidSNARK.selector);
+        fusion.submitBlock([Fusion.TxProof(proof, chainIdInput(1))]);
+
+        Fusion legacy = new Fusion(1, block.timestamp);
+        vm.expectRevert(Fusion.InvalidSNARK.selector);
+        legacy.submitBlock([Fusion.TxProof(proof, chainIdInput(0))]);
+        vm.warp(block.timestamp + 1);
+        vm.expectRevert(Fusion.InvalidChainId.selector);
+        legacy.submitBlock([Fusion.TxProof(proof, chainIdInput(0))]);
+
+        vm.expectRevert(Fusion.InvalidChainId.selector);
+        new Fusion(0, 0);
     }
 
     function testProof1() public {